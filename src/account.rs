@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fmt;
+use quik_rs::transaction::{Side, Transaction};
+use crate::config::InstrumentSettings;
+use crate::psql::{Db, DepoPosition, MarginRequirement, MoneyLimit};
+
+
+/// Account of the bot, a table `[accounts.<name>]` of the configuration, or `[account]`
+/// for a single account named `default`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountSettings {
+    pub name: String,
+    pub client_code: String,
+    /// Trade account of the orders, the ACCOUNT of the transactions, e.g. `NL0011100043`
+    /// for the stock market or `SPBFUT00ABC` for the derivatives. `None` leaves it to the terminal.
+    pub trade_account: Option<String>,
+    /// Classes of the instruments traded through the account, empty for any class not
+    /// listed by the other accounts.
+    pub class_codes: Vec<String>,
+    /// Currency of the money limit, `SUR` by default.
+    pub currency: String,
+    /// Loss of an exchange day net of the commissions the account is allowed, shown with
+    /// its utilization on the risk panel of the dashboard.
+    pub max_daily_loss: Option<f64>,
+}
+
+
+impl AccountSettings {
+    /// Sets the trade account and the client code of the account in the transaction.
+    pub fn apply(&self, transaction: Transaction) -> Transaction {
+        let transaction = transaction.with_client_code(&self.client_code);
+        match &self.trade_account {
+            Some(trade_account) => transaction.with_account(trade_account),
+            None => transaction,
+        }
+    }
+}
+
+
+/// Account the orders of an instrument of the class are sent through: the account named by
+/// the instrument, otherwise the first account listing the class, otherwise the first account
+/// without classes. `None` without the accounts or a matching one.
+///
+/// # Example of use
+/// ```ignore
+/// let account = account::route(&config.accounts, "SPBFUT", None);
+/// let order = account.map_or(order.clone(), |account| account.apply(order));
+/// ```
+pub fn route<'a>(accounts: &'a [AccountSettings], class_code: &str, name: Option<&str>) -> Option<&'a AccountSettings> {
+    if let Some(name) = name {
+        return accounts.iter().find(|account| account.name == name);
+    }
+    accounts
+        .iter()
+        .find(|account| account.class_codes.iter().any(|code| code == class_code))
+        .or_else(|| accounts.iter().find(|account| account.class_codes.is_empty()))
+}
+
+
+/// Account of a position: the account of its instrument in the watchlist, otherwise the
+/// account routed by its class.
+pub fn of_position<'a>(
+    accounts: &'a [AccountSettings],
+    instruments: &'a [InstrumentSettings],
+    class_code: &str,
+    sec_code: &str,
+) -> Option<&'a AccountSettings> {
+    instruments
+        .iter()
+        .find(|instrument| instrument.sec_code == sec_code)
+        .and_then(|instrument| instrument.account.as_ref())
+        .or_else(|| route(accounts, class_code, None))
+}
+
+
+/// Order which the funds or the holdings of the account don't cover.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountBreach {
+    pub sec_code: String,
+    pub side: Side,
+    pub lots: i64,
+    /// Lots the account can afford.
+    pub affordable: i64,
+}
+
+
+impl fmt::Display for AccountBreach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:?} of {} lots exceeds the {} lots covered by the account",
+            self.sec_code, self.side, self.lots, self.affordable
+        )
+    }
+}
+
+
+impl std::error::Error for AccountBreach {}
+
+
+/// Snapshot of the account in QUIK: the money limit, the holdings and the margin requirements
+/// of the instruments, exported by the terminal into `money_limits`, `depo_limits` and
+/// `current_trades`. The risk checks and the position sizing consult it before an order is sent.
+///
+/// The snapshot covers nothing until the export fills the tables: without a money limit every
+/// order is allowed, as before the tables existed.
+///
+/// # Example of use
+/// ```ignore
+/// let account = Account::load(&db, &settings, &["SBER".to_string()]).await?;
+/// let lots = sizer.lots(&SizingInput {
+///     equity: account.available_funds().unwrap_or(capital),
+///     price,
+///     lot_size,
+///     atr,
+///     affordable_lots: account.affordable_lots("SBER", Side::Buy, price, lot_size),
+/// });
+/// account.check_order("SBER", Side::Buy, lots, price, lot_size)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Account {
+    pub money: Option<MoneyLimit>,
+    pub holdings: HashMap<String, DepoPosition>,
+    pub margin: HashMap<String, MarginRequirement>,
+}
+
+
+impl Account {
+    pub async fn load(db: &Db, settings: &AccountSettings, sec_codes: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let money = db.get_money_limit(&settings.client_code, &settings.currency).await?;
+
+        let mut holdings = HashMap::new();
+        for sec_code in sec_codes {
+            if let Some(position) = db.get_depo_position(&settings.client_code, sec_code).await? {
+                holdings.insert(sec_code.clone(), position);
+            }
+        }
+
+        let margin = db
+            .get_margin_requirements(sec_codes)
+            .await?
+            .into_iter()
+            .map(|requirement| (requirement.instrument_code.clone(), requirement))
+            .collect();
+
+        Ok(Account { money, holdings, margin })
+    }
+
+
+    /// Funds available for new orders, `None` without the money limit.
+    pub fn available_funds(&self) -> Option<f64> {
+        self.money.as_ref().map(MoneyLimit::available)
+    }
+
+
+    /// Units of the instrument available for sale.
+    pub fn available_units(&self, sec_code: &str) -> i64 {
+        self.holdings.get(sec_code).map_or(0, |position| position.available().max(0))
+    }
+
+
+    /// Lots of an order the account can afford: a sale is covered by the holdings first,
+    /// the rest of the order by the funds at the margin requirement of the instrument or,
+    /// without it, at the full price. `None` without the money limit.
+    pub fn affordable_lots(&self, sec_code: &str, side: Side, price: f64, lot_size: i64) -> Option<i64> {
+        let funds = self.available_funds()?.max(0.0);
+        let lot_size = lot_size.max(1);
+        let requirement = self.margin.get(sec_code);
+        let deposit = match side {
+            Side::Buy => requirement.and_then(|requirement| requirement.buy_deposit),
+            Side::Sell => requirement.and_then(|requirement| requirement.sell_deposit),
+        };
+        let cost = deposit.filter(|deposit| *deposit > 0.0).unwrap_or(price * lot_size as f64);
+
+        let covered = if side == Side::Sell { self.available_units(sec_code) / lot_size } else { 0 };
+        let funded = if cost > 0.0 { (funds / cost).floor() as i64 } else { i64::MAX - covered };
+
+        Some(covered + funded)
+    }
+
+
+    /// Allows an order covered by the account or returns the breach.
+    pub fn check_order(&self, sec_code: &str, side: Side, lots: i64, price: f64, lot_size: i64) -> Result<(), AccountBreach> {
+        match self.affordable_lots(sec_code, side, price, lot_size) {
+            Some(affordable) if affordable < lots => Err(AccountBreach {
+                sec_code: sec_code.to_string(),
+                side,
+                lots,
+                affordable,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.money {
+            Some(money) => write!(
+                f,
+                "Account {}: available {:.2} {} (T{}), balance {:.2}, locked {:.2}",
+                money.client_code,
+                money.available(),
+                money.currency,
+                money.limit_kind,
+                money.current_balance,
+                money.locked
+            )?,
+            None => write!(f, "Account: no money limit in money_limits, the orders are not checked against the funds")?,
+        }
+
+        let mut holdings: Vec<&DepoPosition> = self.holdings.values().filter(|position| position.current_balance != 0).collect();
+        holdings.sort_by(|a, b| a.sec_code.cmp(&b.sec_code));
+        for position in holdings {
+            write!(f, "\n  {}: {} units, {} available for sale", position.sec_code, position.current_balance, position.available())?;
+        }
+
+        Ok(())
+    }
+}
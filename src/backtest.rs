@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+use chrono::{DateTime, Datelike, NaiveDate, Utc, Weekday};
+use serde_json::json;
+use ta::indicators::AverageTrueRange;
+use ta::{DataItem, Next};
+use quik_rs::transaction::Side;
+use crate::churn::ChurnGuard;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::latency::LatencyStats;
+use crate::portfolio::InstrumentPosition;
+use crate::psql::{BacktestRecord, DataForEma, Db, Fill};
+use crate::sizing::{LotResiduals, PositionSizer, SizingInput};
+use crate::strategy::{self, Action, Strategy};
+use crate::tearsheet::TearSheet;
+
+
+/// Trading days in a year for the annualized Sharpe ratio.
+const TRADING_DAYS: f64 = 252.0;
+
+
+/// Execution model of the backtest, the `[backtest]` section of the configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BacktestSettings {
+    /// Capital the returns are calculated on.
+    pub capital: f64,
+    /// Lots of an entry.
+    pub entry_lots: i64,
+    /// Commission in percent of the traded value.
+    pub commission_percent: f64,
+    /// Price shift of the fills against the order in percent of the price.
+    pub slippage_percent: f64,
+}
+
+
+impl Default for BacktestSettings {
+    fn default() -> Self {
+        BacktestSettings {
+            capital: 1_000_000.0,
+            entry_lots: 1,
+            commission_percent: 0.0,
+            slippage_percent: 0.0,
+        }
+    }
+}
+
+
+/// Candles of an instrument replayed by the backtest.
+#[derive(Debug, Clone)]
+pub struct InstrumentCandles {
+    pub sec_code: String,
+    /// Units of the instrument in a lot.
+    pub lot: i64,
+    pub timeframe: Duration,
+    pub candles: Vec<DataForEma>,
+    /// Candles of the confirmation timeframes of the strategy.
+    pub confirmations: Vec<(Duration, Vec<DataForEma>)>,
+}
+
+
+/// Result of a backtest: the tear sheet of the simulated fills with the equity curve, the drawdown
+/// and the trade statistics, and the Sharpe ratio of the daily returns.
+#[derive(Debug, Clone)]
+pub struct BacktestResult {
+    pub sheet: TearSheet,
+    /// Annualized Sharpe ratio of the daily realized returns, `None` without variation.
+    pub sharpe: Option<f64>,
+    /// Positions in lots left open at the end of the period.
+    pub open_positions: Vec<(String, i64)>,
+}
+
+
+impl BacktestResult {
+    /// Record of the `backtests` table with the parameters of the strategy.
+    pub fn record(&self, parameters: String, created_at: DateTime<Utc>) -> BacktestRecord {
+        let sheet = &self.sheet;
+        BacktestRecord {
+            strategy: sheet.strategy.clone(),
+            parameters,
+            period_from: sheet.from.and_time(Default::default()).and_utc(),
+            period_to: (sheet.to + chrono::Duration::days(1)).and_time(Default::default()).and_utc(),
+            capital: sheet.capital,
+            fills: sheet.stats.fills as i64,
+            closed_trades: sheet.stats.closed_trades as i64,
+            net_pnl: sheet.stats.net_pnl,
+            max_drawdown: sheet.stats.max_drawdown,
+            sharpe: self.sharpe,
+            win_rate: sheet.stats.win_rate(),
+            created_at,
+        }
+    }
+}
+
+
+impl fmt::Display for BacktestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sheet = &self.sheet;
+        let show = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.2}", value));
+
+        write!(
+            f,
+            "{} {} - {}: {} fills, {} closed trades, net profit {:.2}, max drawdown {:.2}, Sharpe {}, win rate {}%",
+            sheet.strategy,
+            sheet.from,
+            sheet.to,
+            sheet.stats.fills,
+            sheet.stats.closed_trades,
+            sheet.stats.net_pnl,
+            sheet.stats.max_drawdown,
+            show(self.sharpe),
+            show(sheet.stats.win_rate()),
+        )?;
+        for (sec_code, lots) in &self.open_positions {
+            write!(f, "\n  open at the end: {} {} lots", sec_code, lots)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Close of a candle of an instrument, of its main series or of a confirmation timeframe.
+struct Event {
+    closed_at: DateTime<Utc>,
+    timeframe: Duration,
+    instrument: usize,
+    /// Index of the confirmation series, `None` for the main one.
+    confirmation: Option<usize>,
+    candle: usize,
+}
+
+
+/// Order decided on the close of a candle, filled at the open of the next candle.
+struct PendingOrder {
+    side: Side,
+    lots: i64,
+    expected_price: f64,
+}
+
+
+/// Backtesting engine: replays the candles of the instruments through a `Strategy` in the order
+/// of their closes, as the candle scheduler feeds them live, and simulates the fills of the
+/// decisions at the open of the next candle with the commission and the slippage of the settings.
+///
+/// The entries are of `entry_lots` unless a position sizer is set, which sizes them on the capital
+/// with the realized profit net of the commissions. With a churn guard the decisions pass through
+/// the cooldown and anti-churn rules of the instruments as they do live.
+///
+/// # Example of use
+/// ```ignore
+/// let strategy = strategy::build("ema_cross", &config, &LatencyStats::default())?;
+/// let result = Backtest::new(strategy, BacktestSettings::default()).run(from, to, candles);
+/// println!("{}", result);
+/// ```
+pub struct Backtest {
+    strategy: Box<dyn Strategy>,
+    settings: BacktestSettings,
+    sizer: PositionSizer,
+    churn: Option<ChurnGuard>,
+}
+
+
+impl Backtest {
+    pub fn new(strategy: Box<dyn Strategy>, settings: BacktestSettings) -> Self {
+        Backtest {
+            strategy,
+            settings,
+            sizer: PositionSizer::fixed(settings.entry_lots),
+            churn: None,
+        }
+    }
+
+
+    pub fn with_churn_guard(mut self, churn: ChurnGuard) -> Self {
+        self.churn = Some(churn);
+        self
+    }
+
+
+    pub fn with_sizer(mut self, sizer: PositionSizer) -> Self {
+        self.sizer = sizer;
+        self
+    }
+
+
+    pub fn run(mut self, from: NaiveDate, to: NaiveDate, instruments: Vec<InstrumentCandles>) -> BacktestResult {
+        let name = self.strategy.name();
+
+        // Candles of all instruments in the order of their closes, the longer timeframes first
+        // at the same close as the candle scheduler passes them
+        let mut events: Vec<Event> = Vec::new();
+        for (index, instrument) in instruments.iter().enumerate() {
+            let series = std::iter::once((instrument.timeframe, &instrument.candles, None))
+                .chain(instrument.confirmations.iter().enumerate().map(|(number, (timeframe, candles))| (*timeframe, candles, Some(number))));
+            for (timeframe, candles, confirmation) in series {
+                let length = chrono::Duration::from_std(timeframe).unwrap_or_default();
+                for (number, candle) in candles.iter().enumerate() {
+                    events.push(Event {
+                        closed_at: candle.period_start + length,
+                        timeframe,
+                        instrument: index,
+                        confirmation,
+                        candle: number,
+                    });
+                }
+            }
+        }
+        events.sort_by(|a, b| {
+            (a.closed_at, std::cmp::Reverse(a.timeframe), &instruments[a.instrument].sec_code)
+                .cmp(&(b.closed_at, std::cmp::Reverse(b.timeframe), &instruments[b.instrument].sec_code))
+        });
+
+        let mut positions: HashMap<&str, i64> = HashMap::new();
+        let mut pending: HashMap<&str, PendingOrder> = HashMap::new();
+        let mut fills = Vec::new();
+        // Positions in units for the realized profit, and the ATR with the number of its candles
+        let mut books: HashMap<&str, InstrumentPosition> = HashMap::new();
+        let mut atrs: HashMap<&str, (AverageTrueRange, usize, f64)> = HashMap::new();
+        let mut equity = self.settings.capital;
+        let mut residuals = LotResiduals::default();
+
+        for event in events {
+            let instrument = &instruments[event.instrument];
+            let sec_code = instrument.sec_code.as_str();
+            if let Some(confirmation) = event.confirmation {
+                let candle = &instrument.confirmations[confirmation].1[event.candle];
+                self.strategy.on_confirmation_candle(sec_code, event.timeframe, candle);
+                continue;
+            }
+            let candle = &instrument.candles[event.candle];
+
+            if let Some(order) = pending.remove(sec_code) {
+                let fill = self.fill(name, instrument, candle, &order, fills.len() as i64 + 1);
+                *positions.entry(sec_code).or_default() += if order.side == Side::Sell { -order.lots } else { order.lots };
+                let quantity = if fill.is_sell { -fill.quantity } else { fill.quantity };
+                if let Some(churn) = &mut self.churn {
+                    churn.on_fill(sec_code, fill.is_sell, order.lots, fill.executed_at);
+                }
+                equity += books.entry(sec_code).or_default().apply(quantity, fill.price, fill.point_value).unwrap_or(0.0) - fill.commission;
+                fills.push(fill);
+            }
+
+            let atr = if self.sizer.needs_atr() {
+                let (indicator, count, value) = atrs
+                    .entry(sec_code)
+                    .or_insert_with(|| (AverageTrueRange::new(self.sizer.atr_period).expect("validated ATR period"), 0, 0.0));
+                if let Ok(item) = DataItem::builder().open(candle.open).high(candle.high).low(candle.low).close(candle.close).volume(candle.volume).build() {
+                    *value = indicator.next(&item);
+                    *count += 1;
+                }
+                (*count >= self.sizer.atr_period).then_some(*value)
+            } else {
+                None
+            };
+            let input = SizingInput {
+                equity,
+                price: candle.close,
+                lot_size: instrument.lot,
+                atr,
+                affordable_lots: None,
+            };
+
+            if let Some(churn) = &mut self.churn {
+                churn.on_candle(sec_code, event.timeframe);
+            }
+
+            for decision in self.strategy.on_candle(sec_code, candle) {
+                let decision = match &self.churn {
+                    Some(churn) => churn.filter(decision, event.closed_at),
+                    None => decision,
+                };
+                let position = positions.get(sec_code).copied().unwrap_or_default();
+                // Only the entries are sized, the exits close what is open
+                let entry_lots = if matches!(decision.action, Action::Buy | Action::Sell) {
+                    self.sizer.lots_tracked(sec_code, &input, &mut residuals)
+                } else {
+                    self.sizer.min_lots
+                };
+                if let Some((side, lots)) = decision.action.to_order(position, entry_lots) {
+                    pending.insert(sec_code, PendingOrder { side, lots, expected_price: candle.close });
+                }
+            }
+        }
+
+        let sheet = TearSheet::build(name, from, to, self.settings.capital, &fills);
+        let sharpe = sharpe(&sheet, from, to);
+        let mut open_positions: Vec<(String, i64)> = positions
+            .into_iter()
+            .filter(|(_, lots)| *lots != 0)
+            .map(|(sec_code, lots)| (sec_code.to_string(), lots))
+            .collect();
+        open_positions.sort();
+
+        BacktestResult {
+            sheet,
+            sharpe,
+            open_positions,
+        }
+    }
+
+
+    fn fill(&self, strategy: &str, instrument: &InstrumentCandles, candle: &DataForEma, order: &PendingOrder, trade_num: i64) -> Fill {
+        let shift = candle.open * self.settings.slippage_percent / 100.0;
+        let price = match order.side {
+            Side::Buy => candle.open + shift,
+            Side::Sell => candle.open - shift,
+        };
+        let quantity = order.lots * instrument.lot;
+
+        Fill {
+            strategy: strategy.to_string(),
+            instrument_code: instrument.sec_code.clone(),
+            trade_num,
+            is_sell: order.side == Side::Sell,
+            quantity,
+            price,
+            commission: price * quantity as f64 * self.settings.commission_percent / 100.0,
+            expected_price: Some(order.expected_price),
+            executed_at: candle.period_start,
+            // The quantity is in the units of the lots, so a move of the price by one is worth one per unit
+            point_value: 1.0,
+        }
+    }
+}
+
+
+/// Annualized Sharpe ratio of the daily changes of the realized equity over the weekdays of the period.
+fn sharpe(sheet: &TearSheet, from: NaiveDate, to: NaiveDate) -> Option<f64> {
+    let closing: BTreeMap<NaiveDate, f64> = sheet.equity.iter().map(|(time, equity)| (time.date_naive(), *equity)).collect();
+
+    let mut returns = Vec::new();
+    let mut previous = 0.0;
+    for day in from.iter_days().take_while(|day| *day <= to) {
+        if matches!(day.weekday(), Weekday::Sat | Weekday::Sun) {
+            continue;
+        }
+        let equity = closing.get(&day).copied().unwrap_or(previous);
+        returns.push((equity - previous) / sheet.capital);
+        previous = equity;
+    }
+    if returns.len() < 2 {
+        return None;
+    }
+
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    (variance > 0.0).then(|| mean / variance.sqrt() * TRADING_DAYS.sqrt())
+}
+
+
+/// Runs the strategy over the candles built from `historical_trades` of the instruments it wants
+/// from `from` until the end of `to`, adjusted for the splits of `corporate_actions`, and stores
+/// the result in the `backtests` table.
+/// Returns the result with the id of the record.
+pub async fn run(
+    db: &Db,
+    config: &Config,
+    strategy_name: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    settings: BacktestSettings,
+    clock: &dyn Clock,
+) -> Result<(BacktestResult, i32), Box<dyn std::error::Error>> {
+    if to < from {
+        return Err(format!("the period {} - {} is empty", from, to).into());
+    }
+    // The latency of a replay is not reported
+    let strategy = strategy::build(strategy_name, config, &LatencyStats::default())?;
+    let instrument_settings = config.instrument_settings()?;
+    let sec_codes = strategy.wants_instruments();
+    let lots: HashMap<String, i64> = db
+        .get_instrument_rows(&sec_codes)
+        .await?
+        .into_iter()
+        .filter_map(|row| Some((row.instrument_code, row.lot? as i64)))
+        .collect();
+
+    let start = from.and_time(Default::default()).and_utc();
+    let end = (to + chrono::Duration::days(1)).and_time(Default::default()).and_utc();
+    let calendar = config.corporate_action_calendar()?;
+    let mut instruments = Vec::new();
+    let mut parameters = serde_json::Map::new();
+    for sec_code in &sec_codes {
+        let settings = instrument_settings.iter().find(|settings| &settings.sec_code == sec_code);
+        let timeframe = strategy
+            .timeframe(sec_code)
+            .or_else(|| settings.map(|settings| settings.timeframe()))
+            .unwrap_or(Duration::from_secs(15 * 60));
+        if let Some(settings) = settings {
+            parameters.insert(
+                sec_code.clone(),
+                json!({
+                    "timeframe_seconds": timeframe.as_secs(),
+                    "ema_pairs": settings.ema_pairs.iter().map(|pair| pair.to_string()).collect::<Vec<_>>(),
+                    "ema_trend_filter": settings.ema_trend_filter,
+                    "ema_hysteresis": settings.ema_hysteresis,
+                    "ema_price_filter": settings.ema_price_filter,
+                    "ema_min_slope": settings.ema_min_slope,
+                    "ema_confirm_candles": settings.ema_confirm_candles,
+                    "confirmation": settings.confirmation().map(|(timeframe, pair)| format!("{}m {}", timeframe.as_secs() / 60, pair)),
+                    "cooldown_candles": settings.cooldown_candles,
+                    "max_round_trips_per_day": settings.max_round_trips_per_day,
+                }),
+            );
+        }
+
+        // The prices before a split are made comparable with the prices after it
+        let mut confirmations = Vec::new();
+        for confirmation in strategy.confirmation_timeframes(sec_code) {
+            let mut candles = db.get_candles(sec_code, start, end, confirmation.as_secs_f64()).await?;
+            calendar.adjust_for_splits(sec_code, &mut candles);
+            confirmations.push((confirmation, candles));
+        }
+        let mut candles = db.get_candles(sec_code, start, end, timeframe.as_secs_f64()).await?;
+        calendar.adjust_for_splits(sec_code, &mut candles);
+
+        instruments.push(InstrumentCandles {
+            sec_code: sec_code.clone(),
+            lot: lots.get(sec_code).copied().filter(|lot| *lot > 0).unwrap_or(1),
+            timeframe,
+            candles,
+            confirmations,
+        });
+    }
+
+    let mut backtest = Backtest::new(strategy, settings);
+    if let Some(sizer) = config.sizing {
+        backtest = backtest.with_sizer(sizer);
+    }
+    let churn = ChurnGuard::new(&instrument_settings, config.exchange_timezone);
+    backtest = backtest.with_churn_guard(churn);
+    let result = backtest.run(from, to, instruments);
+    let parameters = json!({
+        "instruments": parameters,
+        "entry_lots": settings.entry_lots,
+        "sizing": config.sizing.map(|sizer| sizer.to_string()),
+        "commission_percent": settings.commission_percent,
+        "slippage_percent": settings.slippage_percent,
+    });
+    let id = db.insert_backtest(&result.record(parameters.to_string(), clock.now())).await?;
+
+    Ok((result, id))
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+use crate::psql::Db;
+
+
+/// Prefix of the codes of the synthetic instruments, so their data can be removed after the run.
+const SYNTHETIC_PREFIX: &str = "BENCH";
+
+/// Class code of the synthetic instruments.
+const SYNTHETIC_CLASS: &str = "BENCH";
+
+/// Interval between the aggregation and the query probes.
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum 99th percentile of the tick insert latency of a setup able to keep up.
+const MAX_INSERT_P99: Duration = Duration::from_millis(100);
+
+/// Maximum 95th percentile of the candle aggregation latency, which delays every evaluation.
+const MAX_AGGREGATION_P95: Duration = Duration::from_secs(1);
+
+
+/// Parameters of a load run.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadProfile {
+    /// Number of synthetic instruments, normally the size of the watchlist.
+    pub instruments: usize,
+
+    /// Ticks per second of every instrument.
+    pub ticks_per_second: f64,
+
+    pub duration: Duration,
+}
+
+
+/// Latency percentiles of one kind of operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Latency {
+    pub count: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+
+impl Latency {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Latency::default();
+        }
+
+        samples.sort();
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+
+        Latency {
+            count: samples.len(),
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "n={} p50={:?} p95={:?} p99={:?} max={:?}", self.count, self.p50, self.p95, self.p99, self.max)
+    }
+}
+
+
+/// Results of a load run.
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub profile: LoadProfile,
+    pub target_rate: f64,
+    pub achieved_rate: f64,
+    pub failed: usize,
+    pub stored: i64,
+    pub insert: Latency,
+    pub aggregation: Latency,
+    pub query: Latency,
+}
+
+
+impl LoadReport {
+    /// The database keeps up with the profile: at least 95% of the target rate is written,
+    /// no write fails and the latencies stay within the limits.
+    pub fn is_sustainable(&self) -> bool {
+        self.achieved_rate >= self.target_rate * 0.95
+            && self.failed == 0
+            && self.insert.p99 <= MAX_INSERT_P99
+            && self.aggregation.p95 <= MAX_AGGREGATION_P95
+    }
+}
+
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Database load test")?;
+        writeln!(
+            f,
+            "  profile: {} instruments x {} ticks/s for {:?}",
+            self.profile.instruments, self.profile.ticks_per_second, self.profile.duration
+        )?;
+        writeln!(f, "  rate: {:.1} ticks/s of {:.1} target, {} failed, {} stored", self.achieved_rate, self.target_rate, self.failed, self.stored)?;
+        writeln!(f, "  insert: {}", self.insert)?;
+        writeln!(f, "  aggregation: {}", self.aggregation)?;
+        writeln!(f, "  query: {}", self.query)?;
+
+        if self.is_sustainable() {
+            write!(f, "  verdict: the database sustains the configured watchlist")
+        } else {
+            write!(
+                f,
+                "  verdict: NOT sustainable, required insert p99 <= {:?} and aggregation p95 <= {:?} at 95% of the target rate",
+                MAX_INSERT_P99, MAX_AGGREGATION_P95
+            )
+        }
+    }
+}
+
+
+/// Writes synthetic ticks at the rate of the profile and measures the latency of the tick
+/// inserts, of the candle aggregation used by the indicators and of a query over the ticks.
+/// Run by `quik-rs db-benchmark` before going live; the synthetic data is removed afterwards.
+pub async fn run(db: Arc<Db>, profile: LoadProfile) -> Result<LoadReport, Box<dyn std::error::Error>> {
+    if profile.instruments == 0 || profile.ticks_per_second <= 0.0 {
+        return Err("the load test requires instruments and a positive tick rate".into());
+    }
+
+    db.init().await?;
+    db.delete_instrument_data(SYNTHETIC_PREFIX).await?;
+
+    let codes: Vec<String> = (0..profile.instruments).map(|n| format!("{}{:04}", SYNTHETIC_PREFIX, n)).collect();
+    let target_rate = profile.instruments as f64 * profile.ticks_per_second;
+    info!("Writing {:.1} ticks/s for {:?}", target_rate, profile.duration);
+
+    let inserts = Arc::new(Mutex::new(Vec::new()));
+    let failures = Arc::new(Mutex::new(0));
+    let mut aggregations = Vec::new();
+    let mut queries = Vec::new();
+
+    let started_at = Utc::now();
+    let started = Instant::now();
+    let mut ticker = interval(Duration::from_secs_f64(1.0 / target_rate));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let mut probe = interval(PROBE_INTERVAL);
+    let mut tasks = Vec::new();
+    let mut tick: u64 = 0;
+
+    while started.elapsed() < profile.duration {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let code = codes[tick as usize % codes.len()].clone();
+                // A deterministic random walk is enough for the load
+                let price = 100.0 + ((tick / codes.len() as u64) % 200) as f64 * 0.01;
+                let (db, inserts, failures) = (db.clone(), inserts.clone(), failures.clone());
+                tick += 1;
+
+                tasks.push(tokio::spawn(async move {
+                    let sent = Instant::now();
+                    match db.write_tick(SYNTHETIC_CLASS, &code, price, 1.0).await {
+                        Ok(()) => inserts.lock().unwrap_or_else(|e| e.into_inner()).push(sent.elapsed()),
+                        Err(e) => {
+                            warn!("Tick of {} is not written: {}", code, e);
+                            *failures.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+                        }
+                    }
+                }));
+            }
+            _ = probe.tick() => {
+                let sent = Instant::now();
+                db.get_data_for_ema(&codes[0], 24.0 * 3600.0, 15.0 * 60.0).await?;
+                aggregations.push(sent.elapsed());
+
+                let sent = Instant::now();
+                db.count_ticks(SYNTHETIC_PREFIX, started_at).await?;
+                queries.push(sent.elapsed());
+            }
+        }
+    }
+
+    for task in tasks {
+        task.await?;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let stored = db.count_ticks(SYNTHETIC_PREFIX, started_at).await?;
+
+    let inserts = std::mem::take(&mut *inserts.lock().unwrap_or_else(|e| e.into_inner()));
+    let failed = *failures.lock().unwrap_or_else(|e| e.into_inner());
+    let report = LoadReport {
+        profile,
+        target_rate,
+        achieved_rate: inserts.len() as f64 / elapsed,
+        failed,
+        stored,
+        insert: Latency::from_samples(inserts),
+        aggregation: Latency::from_samples(aggregations),
+        query: Latency::from_samples(queries),
+    };
+
+    let deleted = db.delete_instrument_data(SYNTHETIC_PREFIX).await?;
+    info!("Synthetic data is removed, {} rows", deleted);
+
+    Ok(report)
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+use crate::instrument::InstrumentStates;
+use crate::psql::{BotStateRecord, Db};
+use crate::strategy::StrategySet;
+
+
+/// Saves the signal states of the instruments in `bot_state`, e.g. after every candle close,
+/// so a restart mid-session continues the crossovers, their hysteresis and the time in the
+/// current state instead of starting them over and entering the same positions again.
+///
+/// The positions and the working orders are not part of the snapshot: they are already
+/// persisted in `positions` and the order journal, and restored by `Portfolio::load`
+/// and `order_recovery::reconcile`.
+///
+/// # Example of use
+/// ```ignore
+/// bot_state::restore(&db, &mut strategies, &metrics.instruments, clock.now()).await?;
+/// let decisions = strategies.on_candle("SBER", timeframe, &candle);
+/// bot_state::save(&db, &strategies, &["SBER".to_string()], Utc::now()).await?;
+/// ```
+pub async fn save(db: &Db, strategies: &StrategySet, sec_codes: &[String], now: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>> {
+    let records: Vec<BotStateRecord> = sec_codes
+        .iter()
+        .flat_map(|sec_code| {
+            strategies.states(sec_code).into_iter().map(move |(strategy, state)| BotStateRecord {
+                strategy: strategy.to_string(),
+                sec_code: sec_code.clone(),
+                state: state.to_string(),
+                updated_at: now,
+            })
+        })
+        .collect();
+
+    db.upsert_bot_state(&records).await?;
+    Ok(())
+}
+
+
+/// Restores the signal states saved by `save` into the strategies. The states of the strategies
+/// or the instruments not run anymore are skipped, a malformed state leaves the instrument
+/// with a fresh one. A state saved a timeframe of its strategy or more before `now` missed
+/// a candle, e.g. the bot was down overnight, and is skipped as well. The restored instruments
+/// are recorded as warmed up in `instruments`. Returns the number of the restored states.
+pub async fn restore(
+    db: &Db,
+    strategies: &mut StrategySet,
+    instruments: &InstrumentStates,
+    now: DateTime<Utc>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut restored = 0;
+    for record in db.get_bot_state().await? {
+        let timeframe = strategies.timeframe(&record.strategy, &record.sec_code);
+        if !is_fresh(record.updated_at, timeframe, now) {
+            info!("State of {} {} saved at {} is stale and not restored", record.strategy, record.sec_code, record.updated_at);
+            continue;
+        }
+        let state = match serde_json::from_str(&record.state) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("State of {} {} is not restored: {}", record.strategy, record.sec_code, e);
+                continue;
+            }
+        };
+
+        match strategies.restore(&record.strategy, &record.sec_code, &state) {
+            Ok(true) => {
+                instruments.record_restored(&record.sec_code);
+                restored += 1;
+            }
+            Ok(false) => {}
+            Err(e) => warn!("State of {} is not restored: {}", record.strategy, e),
+        }
+    }
+
+    info!("Restored {} signal states saved before the restart", restored);
+    Ok(restored)
+}
+
+
+/// A state saved at a candle close is fresh until the next candle of the timeframe closes.
+/// A state of a strategy without a timeframe is left to `StrategySet::restore`.
+fn is_fresh(updated_at: DateTime<Utc>, timeframe: Option<Duration>, now: DateTime<Utc>) -> bool {
+    let Some(timeframe) = timeframe.and_then(|timeframe| chrono::Duration::from_std(timeframe).ok()) else {
+        return true;
+    };
+    now - updated_at < timeframe
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn state_older_than_the_timeframe_is_stale() {
+        let saved: DateTime<Utc> = "2026-06-03T07:15:00Z".parse().unwrap();
+        let timeframe = Some(Duration::from_secs(900));
+
+        assert!(is_fresh(saved, timeframe, saved + chrono::Duration::minutes(14)));
+        // The candle closed at 07:30 was missed
+        assert!(!is_fresh(saved, timeframe, saved + chrono::Duration::minutes(15)));
+        assert!(!is_fresh(saved, timeframe, saved + chrono::Duration::hours(16)));
+        assert!(is_fresh(saved, None, saved + chrono::Duration::hours(16)));
+    }
+}
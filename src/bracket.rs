@@ -0,0 +1,107 @@
+use std::fmt;
+use quik_rs::transaction::Side;
+
+
+/// Distance of a stop or a target from the entry price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Offset {
+    /// Percent of the entry price.
+    Percent(f64),
+    /// Multiple of the ATR at the moment of the signal.
+    Atr(f64),
+}
+
+
+impl Offset {
+    /// Parses an offset like `1.5%` or `2atr`.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let value = value.trim().to_lowercase();
+
+        let (number, offset): (&str, fn(f64) -> Offset) = if let Some(number) = value.strip_suffix('%') {
+            (number, Offset::Percent)
+        } else if let Some(number) = value.strip_suffix("atr") {
+            (number, Offset::Atr)
+        } else {
+            return Err(format!("offset '{}' must end with '%' or 'atr'", value).into());
+        };
+
+        let number: f64 = number.trim().parse().map_err(|e| format!("offset '{}': {}", value, e))?;
+        if number <= 0.0 {
+            return Err(format!("offset '{}' must be positive", value).into());
+        }
+
+        Ok(offset(number))
+    }
+
+
+    /// Distance in price units.
+    pub fn distance(&self, entry_price: f64, atr: Option<f64>) -> Result<f64, Box<dyn std::error::Error>> {
+        match self {
+            Offset::Percent(percent) => Ok(entry_price * percent / 100.0),
+            Offset::Atr(multiple) => atr
+                .map(|atr| atr * multiple)
+                .ok_or_else(|| "the ATR offset requires the ATR value".into()),
+        }
+    }
+}
+
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Offset::Percent(percent) => write!(f, "{}%", percent),
+            Offset::Atr(multiple) => write!(f, "{}atr", multiple),
+        }
+    }
+}
+
+
+/// Bracket template of a strategy: the offsets of the stop and the target from the entry.
+///
+/// # Example of use
+/// ```ignore
+/// let template = config.brackets.get("ema_cross").ok_or("no bracket template")?;
+/// let bracket = template.apply(Side::Buy, position.average_price, Some(atr))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BracketTemplate {
+    pub stop: Offset,
+    pub target: Offset,
+}
+
+
+/// Entry with the stop and the target prices, forming one OCO structure: the exit manager
+/// closes the position at whichever of them is reached first, see `exits::ExitManager`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bracket {
+    pub side: Side,
+    pub entry: f64,
+    pub stop: f64,
+    pub target: f64,
+}
+
+
+impl BracketTemplate {
+    /// Calculates the bracket of an entry at `entry_price`. `atr` is required by the ATR offsets.
+    pub fn apply(&self, side: Side, entry_price: f64, atr: Option<f64>) -> Result<Bracket, Box<dyn std::error::Error>> {
+        let stop = self.stop.distance(entry_price, atr)?;
+        let target = self.target.distance(entry_price, atr)?;
+
+        let (stop, target) = match side {
+            Side::Buy => (entry_price - stop, entry_price + target),
+            Side::Sell => (entry_price + stop, entry_price - target),
+        };
+
+        if stop <= 0.0 || target <= 0.0 {
+            return Err(format!("bracket of {:?} at {} has a non-positive price: stop {}, target {}", side, entry_price, stop, target).into());
+        }
+
+        Ok(Bracket { side, entry: entry_price, stop, target })
+    }
+
+
+    /// Either offset is in ATRs.
+    pub fn uses_atr(&self) -> bool {
+        matches!(self.stop, Offset::Atr(_)) || matches!(self.target, Offset::Atr(_))
+    }
+}
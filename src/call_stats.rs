@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::time::interval;
+use tracing::{error, info, warn};
+use quik_rs::quik::{FunctionStats, QuikApi};
+use crate::clock::Clock;
+use crate::dashboard::Dashboard;
+use crate::psql::{CallStatsRecord, Db};
+
+
+/// Converts the statistics of a function for a period to a database record.
+pub fn record(function: &str, stats: &FunctionStats, period_start: DateTime<Utc>, period_end: DateTime<Utc>) -> CallStatsRecord {
+    let results: BTreeMap<String, u64> = stats
+        .results
+        .iter()
+        .map(|(result, count)| (format!("{:?}", result), *count))
+        .collect();
+
+    CallStatsRecord {
+        period_start,
+        period_end,
+        function: function.to_string(),
+        calls: stats.calls as i64,
+        failures: stats.failures() as i64,
+        results: serde_json::to_string(&results).unwrap_or_else(|_| "{}".to_string()),
+        avg_latency_ms: stats.average_latency().as_secs_f64() * 1000.0,
+        max_latency_ms: stats.max_latency.as_secs_f64() * 1000.0,
+    }
+}
+
+
+/// Every `period` takes the statistics of the Trans2QUIK.dll calls of the terminal, writes
+/// them to the `trans2quik_call_stats` table and shows them on the dashboard. A function
+/// whose failures grew compared to the previous period is reported with a warning.
+///
+/// # Example of use
+/// ```ignore
+/// tokio::spawn(call_stats::run(db.clone(), terminal.clone(), dashboard.clone(), Duration::from_secs(60), clock.clone()));
+/// ```
+pub async fn run(db: Arc<Db>, terminal: Arc<dyn QuikApi>, dashboard: Dashboard, period: Duration, clock: Arc<dyn Clock>) {
+    let mut ticker = interval(period);
+    // The first tick completes immediately
+    ticker.tick().await;
+
+    let mut period_start = clock.now();
+    let mut previous_failures: HashMap<&'static str, u64> = HashMap::new();
+
+    loop {
+        ticker.tick().await;
+        let period_end = clock.now();
+        let functions = terminal.call_stats().take();
+
+        for (function, stats) in &functions {
+            let failures = stats.failures();
+            let previous = previous_failures.insert(function, failures).unwrap_or(0);
+            if failures > previous {
+                warn!(
+                    "Failures of {} are rising: {} of {} calls, {} in the previous period, results {:?}",
+                    function, failures, stats.calls, previous, stats.results
+                );
+            }
+
+            if let Err(e) = db.insert_call_stats(&record(function, stats, period_start, period_end)).await {
+                error!("Error recording the call statistics of {}: {}", function, e);
+            }
+        }
+        // Functions without calls in the period had no failures
+        previous_failures.retain(|function, _| functions.contains_key(function));
+
+        let calls: u64 = functions.values().map(|stats| stats.calls).sum();
+        info!("Trans2QUIK calls in the period: {} of {} functions", calls, functions.len());
+
+        dashboard.set_call_stats(functions);
+        period_start = period_end;
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::json;
+use ta::indicators::{MovingAverageConvergenceDivergence, RelativeStrengthIndex};
+use ta::Next;
+use crate::psql::{DataForEma, Db};
+
+
+/// Width of the chart in SVG units.
+const WIDTH: f64 = 1000.0;
+
+/// Gap between the panes.
+const GAP: f64 = 12.0;
+
+
+/// Pane of the chart with its height. The panes are stacked under the price pane and share
+/// its x-axis: a candle has the same x in every pane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pane {
+    Price,
+    Volume,
+    /// RSI 14 with the 30 and 70 levels.
+    Rsi,
+    /// Histogram of MACD 12/26/9.
+    MacdHistogram,
+}
+
+
+impl Pane {
+    pub const ALL: [Pane; 4] = [Pane::Price, Pane::Volume, Pane::Rsi, Pane::MacdHistogram];
+
+
+    fn height(self) -> f64 {
+        match self {
+            Pane::Price => 320.0,
+            Pane::Volume => 80.0,
+            Pane::Rsi | Pane::MacdHistogram => 110.0,
+        }
+    }
+
+
+    fn title(self) -> &'static str {
+        match self {
+            Pane::Price => "Price",
+            Pane::Volume => "Volume",
+            Pane::Rsi => "RSI 14",
+            Pane::MacdHistogram => "MACD 12/26/9 histogram",
+        }
+    }
+}
+
+
+/// Chart of an instrument: candles in the price pane and the oscillators in stacked sub-panes
+/// beneath it, instead of everything overlaid on one price axis.
+///
+/// # Example of use
+/// ```ignore
+/// let chart = Chart::load(&db, "SBER", Duration::from_secs(900), from, to).await?;
+/// std::fs::write("chart-SBER.html", chart.render_html(SystemClock.now()))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Chart {
+    pub sec_code: String,
+    pub timeframe: Duration,
+    pub candles: Vec<DataForEma>,
+    pub rsi: Vec<f64>,
+    pub macd_histogram: Vec<f64>,
+}
+
+
+impl Chart {
+    /// Loads the candles of `sec_code` from `from` until the end of `to`.
+    pub async fn load(db: &Db, sec_code: &str, timeframe: Duration, from: NaiveDate, to: NaiveDate) -> Result<Self, Box<dyn std::error::Error>> {
+        if to < from {
+            return Err(format!("the period {} - {} is empty", from, to).into());
+        }
+
+        let start = from.and_time(Default::default()).and_utc();
+        let end = (to + chrono::Duration::days(1)).and_time(Default::default()).and_utc();
+        let candles = db.get_candles(sec_code, start, end, timeframe.as_secs_f64()).await?;
+
+        Ok(Self::build(sec_code, timeframe, candles))
+    }
+
+
+    /// Calculates the oscillators over the candles.
+    pub fn build(sec_code: &str, timeframe: Duration, candles: Vec<DataForEma>) -> Self {
+        let mut rsi = RelativeStrengthIndex::new(14).expect("valid RSI period");
+        let mut macd = MovingAverageConvergenceDivergence::new(12, 26, 9).expect("valid MACD periods");
+
+        Chart {
+            sec_code: sec_code.to_string(),
+            timeframe,
+            rsi: candles.iter().map(|candle| rsi.next(candle.close)).collect(),
+            macd_histogram: candles.iter().map(|candle| macd.next(candle.close).histogram).collect(),
+            candles,
+        }
+    }
+
+
+    /// Renders the chart generated at `generated_at` as a standalone HTML page. The crosshair
+    /// follows the mouse across all panes and shows the values of the candle under it.
+    pub fn render_html(&self, generated_at: DateTime<Utc>) -> String {
+        let mut page = String::new();
+        let _ = write!(
+            page,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{sec_code}</title>\
+             <style>body{{font-family:sans-serif;margin:2em}}svg{{display:block;max-width:{width}px}}\
+             text{{font-size:11px;fill:#666}}#readout{{font-family:monospace;min-height:1.5em}}</style></head><body>\
+             <h1>{sec_code}</h1><p>{minutes}-minute candles, {count} candles</p>",
+            sec_code = escape(&self.sec_code),
+            width = WIDTH,
+            minutes = self.timeframe.as_secs() / 60,
+            count = self.candles.len(),
+        );
+
+        if self.candles.is_empty() {
+            page.push_str("<p>No data</p></body></html>");
+            return page;
+        }
+
+        let height: f64 = Pane::ALL.iter().map(|pane| pane.height() + GAP).sum();
+        let _ = write!(
+            page,
+            "<div id=\"readout\"></div><svg id=\"chart\" viewBox=\"0 0 {w} {h}\" width=\"100%\">",
+            w = WIDTH,
+            h = height
+        );
+        let mut top = 0.0;
+        for pane in Pane::ALL {
+            page.push_str(&self.pane(pane, top));
+            top += pane.height() + GAP;
+        }
+        let _ = write!(
+            page,
+            "<line id=\"cross-x\" y1=\"0\" y2=\"{h}\" stroke=\"#555\" stroke-dasharray=\"3\" visibility=\"hidden\"/>\
+             <line id=\"cross-y\" x1=\"0\" x2=\"{w}\" stroke=\"#555\" stroke-dasharray=\"3\" visibility=\"hidden\"/></svg>",
+            w = WIDTH,
+            h = height
+        );
+
+        let data: Vec<_> = self
+            .candles
+            .iter()
+            .zip(&self.rsi)
+            .zip(&self.macd_histogram)
+            .map(|((candle, rsi), histogram)| {
+                json!([
+                    candle.period_start.format("%Y-%m-%d %H:%M").to_string(),
+                    candle.open,
+                    candle.high,
+                    candle.low,
+                    candle.close,
+                    candle.volume,
+                    rsi,
+                    histogram,
+                ])
+            })
+            .collect();
+        let _ = write!(
+            page,
+            "<script>\
+             const data = {data};\
+             const svg = document.getElementById('chart');\
+             const crossX = document.getElementById('cross-x'), crossY = document.getElementById('cross-y');\
+             const readout = document.getElementById('readout');\
+             svg.addEventListener('mousemove', e => {{\
+               const p = new DOMPoint(e.clientX, e.clientY).matrixTransform(svg.getScreenCTM().inverse());\
+               const i = Math.min(data.length - 1, Math.max(0, Math.floor(p.x / {w} * data.length)));\
+               const x = (i + 0.5) * {w} / data.length;\
+               crossX.setAttribute('x1', x); crossX.setAttribute('x2', x);\
+               crossY.setAttribute('y1', p.y); crossY.setAttribute('y2', p.y);\
+               crossX.setAttribute('visibility', 'visible'); crossY.setAttribute('visibility', 'visible');\
+               const d = data[i];\
+               readout.textContent = `${{d[0]}} O ${{d[1]}} H ${{d[2]}} L ${{d[3]}} C ${{d[4]}} V ${{d[5]}} RSI ${{d[6].toFixed(2)}} MACD hist ${{d[7].toFixed(4)}}`;\
+             }});\
+             svg.addEventListener('mouseleave', () => {{\
+               crossX.setAttribute('visibility', 'hidden'); crossY.setAttribute('visibility', 'hidden');\
+             }});\
+             </script><p><small>Generated {generated}</small></p></body></html>",
+            data = serde_json::Value::from(data),
+            w = WIDTH,
+            generated = generated_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+
+        page
+    }
+
+
+    /// Renders a pane at `top` as SVG elements.
+    fn pane(&self, pane: Pane, top: f64) -> String {
+        let height = pane.height();
+        let step = WIDTH / self.candles.len() as f64;
+        let x = |index: usize| (index as f64 + 0.5) * step;
+        let body = (step * 0.7).max(1.0);
+
+        let (min, max) = match pane {
+            Pane::Price => self
+                .candles
+                .iter()
+                .fold((f64::MAX, f64::MIN), |(min, max), candle| (min.min(candle.low), max.max(candle.high))),
+            Pane::Volume => (0.0, self.candles.iter().map(|candle| candle.volume).fold(0.0, f64::max)),
+            Pane::Rsi => (0.0, 100.0),
+            Pane::MacdHistogram => {
+                let extent = self.macd_histogram.iter().map(|value| value.abs()).fold(0.0, f64::max);
+                (-extent, extent)
+            }
+        };
+        let range = if max > min { max - min } else { 1.0 };
+        let y = |value: f64| top + height - (value - min) / range * height;
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            "<rect x=\"0\" y=\"{top}\" width=\"{w}\" height=\"{h}\" fill=\"none\" stroke=\"#ddd\"/>\
+             <text x=\"4\" y=\"{label:.1}\">{title}</text>",
+            top = top,
+            w = WIDTH,
+            h = height,
+            label = top + 12.0,
+            title = pane.title(),
+        );
+
+        match pane {
+            Pane::Price => {
+                for (index, candle) in self.candles.iter().enumerate() {
+                    let color = if candle.close < candle.open { "#c62828" } else { "#2e7d32" };
+                    let (upper, lower) = (candle.open.max(candle.close), candle.open.min(candle.close));
+                    let _ = write!(
+                        svg,
+                        "<line x1=\"{x:.1}\" y1=\"{high:.1}\" x2=\"{x:.1}\" y2=\"{low:.1}\" stroke=\"{color}\"/>\
+                         <rect x=\"{left:.1}\" y=\"{upper:.1}\" width=\"{body:.1}\" height=\"{size:.1}\" fill=\"{color}\"/>",
+                        x = x(index),
+                        high = y(candle.high),
+                        low = y(candle.low),
+                        left = x(index) - body / 2.0,
+                        upper = y(upper),
+                        size = (y(lower) - y(upper)).max(1.0),
+                        body = body,
+                        color = color,
+                    );
+                }
+                let _ = write!(
+                    svg,
+                    "<text x=\"{w}\" y=\"{top:.1}\" text-anchor=\"end\">{max:.2}</text>\
+                     <text x=\"{w}\" y=\"{bottom:.1}\" text-anchor=\"end\">{min:.2}</text>",
+                    w = WIDTH - 4.0,
+                    top = top + 12.0,
+                    bottom = top + height - 4.0,
+                    max = max,
+                    min = min,
+                );
+            }
+            Pane::Volume => {
+                for (index, candle) in self.candles.iter().enumerate() {
+                    let _ = write!(
+                        svg,
+                        "<rect x=\"{left:.1}\" y=\"{y:.1}\" width=\"{body:.1}\" height=\"{size:.1}\" fill=\"#90a4ae\"/>",
+                        left = x(index) - body / 2.0,
+                        y = y(candle.volume),
+                        body = body,
+                        size = top + height - y(candle.volume),
+                    );
+                }
+            }
+            Pane::Rsi => {
+                for level in [30.0, 70.0] {
+                    let _ = write!(
+                        svg,
+                        "<line x1=\"0\" y1=\"{y:.1}\" x2=\"{w}\" y2=\"{y:.1}\" stroke=\"#999\" stroke-dasharray=\"4\"/>",
+                        y = y(level),
+                        w = WIDTH,
+                    );
+                }
+                let points: Vec<String> = self.rsi.iter().enumerate().map(|(index, value)| format!("{:.1},{:.1}", x(index), y(*value))).collect();
+                let _ = write!(svg, "<polyline fill=\"none\" stroke=\"#6a1b9a\" stroke-width=\"1.5\" points=\"{}\"/>", points.join(" "));
+            }
+            Pane::MacdHistogram => {
+                let zero = y(0.0);
+                for (index, value) in self.macd_histogram.iter().enumerate() {
+                    let _ = write!(
+                        svg,
+                        "<rect x=\"{left:.1}\" y=\"{y:.1}\" width=\"{body:.1}\" height=\"{size:.1}\" fill=\"{color}\"/>",
+                        left = x(index) - body / 2.0,
+                        y = y(*value).min(zero),
+                        body = body,
+                        size = (y(*value) - zero).abs(),
+                        color = if *value < 0.0 { "#c62828" } else { "#2e7d32" },
+                    );
+                }
+            }
+        }
+
+        svg
+    }
+}
+
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use crate::config::InstrumentSettings;
+use crate::strategy::{Action, Decision};
+
+
+/// Anti-churn rules of an instrument, `cooldown_candles` and `max_round_trips_per_day`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChurnRules {
+    /// Candles after a fill during which the signals of the opposite side are suppressed.
+    pub cooldown_candles: Option<u32>,
+    /// Round trips of an exchange day after which no new position is opened.
+    pub max_round_trips: Option<u32>,
+}
+
+
+impl ChurnRules {
+    pub fn is_enabled(&self) -> bool {
+        self.cooldown_candles.is_some() || self.max_round_trips.is_some()
+    }
+}
+
+
+/// Signal suppressed by the anti-churn rules.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChurnSkip {
+    /// The opposite side was filled less than `cooldown_candles` candles ago.
+    Cooldown { remaining: u32 },
+    /// The instrument made `max_round_trips_per_day` round trips today.
+    MaxRoundTrips { round_trips: u32 },
+}
+
+
+impl ChurnSkip {
+    /// Reason code of the suppressed decision in the evaluation audit.
+    pub fn reason_code(&self) -> &'static str {
+        match self {
+            ChurnSkip::Cooldown { .. } => "cooldown",
+            ChurnSkip::MaxRoundTrips { .. } => "max_round_trips",
+        }
+    }
+}
+
+
+impl fmt::Display for ChurnSkip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChurnSkip::Cooldown { remaining } => write!(f, "cooldown after the last fill, {} candles left", remaining),
+            ChurnSkip::MaxRoundTrips { round_trips } => write!(f, "{} round trips today, no new positions until tomorrow", round_trips),
+        }
+    }
+}
+
+
+/// Trading state of an instrument followed by the guard.
+#[derive(Debug, Clone, Default)]
+struct InstrumentChurn {
+    /// Position built by the fills, positive for long.
+    position: i64,
+    /// Side of the last fill, `true` for a sale.
+    last_sell: Option<bool>,
+    /// Candles closed since the last fill.
+    candles: u32,
+    day: Option<NaiveDate>,
+    round_trips: u32,
+}
+
+
+/// Cooldown and anti-churn protection: with the EMA hysteresis alone the crossovers still flip
+/// back and forth in a choppy market and every flip pays the commissions. After a fill the signals
+/// of the opposite side are suppressed for `cooldown_candles` candles of the instrument, and after
+/// `max_round_trips_per_day` positions closed in an exchange day no new position is opened until
+/// the next day. The exits which are not reversals, e.g. `ClosePosition`, are never suppressed.
+///
+/// The trading loop reports the fills and the closed candles and passes the decisions through
+/// `filter`.
+///
+/// # Example of use
+/// ```ignore
+/// let mut guard = ChurnGuard::new(&config.instrument_settings()?, config.exchange_timezone);
+/// guard.on_fill("SBER", false, 1, Utc::now());
+/// guard.on_candle("SBER", Duration::from_secs(900));
+/// let decision = guard.filter(decision, Utc::now());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChurnGuard {
+    /// Rules with the timeframe of the instrument whose candles count the cooldown.
+    rules: HashMap<String, (ChurnRules, Duration)>,
+    timezone: FixedOffset,
+    instruments: HashMap<String, InstrumentChurn>,
+}
+
+
+impl ChurnGuard {
+    pub fn new(settings: &[InstrumentSettings], timezone: FixedOffset) -> Self {
+        ChurnGuard {
+            rules: settings
+                .iter()
+                .map(|settings| (settings.sec_code.clone(), (settings.churn_rules(), settings.timeframe())))
+                .filter(|(_, (rules, _))| rules.is_enabled())
+                .collect(),
+            timezone,
+            instruments: HashMap::new(),
+        }
+    }
+
+
+    /// Starts following an instrument added to the universe.
+    pub fn add_instrument(&mut self, settings: &InstrumentSettings) {
+        let rules = settings.churn_rules();
+        if rules.is_enabled() {
+            self.rules.insert(settings.sec_code.clone(), (rules, settings.timeframe()));
+        }
+    }
+
+
+    pub fn remove_instrument(&mut self, sec_code: &str) {
+        self.rules.remove(sec_code);
+        self.instruments.remove(sec_code);
+    }
+
+
+    /// Records a fill of `quantity` of the instrument. A position brought back to flat
+    /// or reversed completes a round trip.
+    pub fn on_fill(&mut self, sec_code: &str, is_sell: bool, quantity: i64, time: DateTime<Utc>) {
+        if !self.rules.contains_key(sec_code) {
+            return;
+        }
+        let day = time.with_timezone(&self.timezone).date_naive();
+        let instrument = self.instruments.entry(sec_code.to_string()).or_default();
+        if instrument.day != Some(day) {
+            instrument.day = Some(day);
+            instrument.round_trips = 0;
+        }
+
+        let before = instrument.position;
+        instrument.position += if is_sell { -quantity } else { quantity };
+        if before != 0 && (instrument.position == 0 || instrument.position.signum() != before.signum()) {
+            instrument.round_trips += 1;
+        }
+        instrument.last_sell = Some(is_sell);
+        instrument.candles = 0;
+    }
+
+
+    /// Counts a closed candle of the instrument, only the candles of its own timeframe count.
+    pub fn on_candle(&mut self, sec_code: &str, timeframe: Duration) {
+        if self.rules.get(sec_code).is_none_or(|(_, own)| *own != timeframe) {
+            return;
+        }
+        if let Some(instrument) = self.instruments.get_mut(sec_code) {
+            instrument.candles = instrument.candles.saturating_add(1);
+        }
+    }
+
+
+    /// Allows the action or returns the rule suppressing it.
+    pub fn check(&self, sec_code: &str, action: Action, now: DateTime<Utc>) -> Result<(), ChurnSkip> {
+        let is_sell = match action {
+            Action::Buy => false,
+            Action::Sell => true,
+            _ => return Ok(()),
+        };
+        let (Some((rules, _)), Some(instrument)) = (self.rules.get(sec_code), self.instruments.get(sec_code)) else {
+            return Ok(());
+        };
+
+        if let (Some(cooldown), Some(last_sell)) = (rules.cooldown_candles, instrument.last_sell) {
+            if last_sell != is_sell && instrument.candles < cooldown {
+                return Err(ChurnSkip::Cooldown { remaining: cooldown - instrument.candles });
+            }
+        }
+
+        // Increasing an open position is not a new round trip
+        let opens = instrument.position == 0 || (instrument.position < 0) != is_sell;
+        let today = now.with_timezone(&self.timezone).date_naive();
+        if let Some(max) = rules.max_round_trips {
+            if opens && instrument.day == Some(today) && instrument.round_trips >= max {
+                return Err(ChurnSkip::MaxRoundTrips { round_trips: instrument.round_trips });
+            }
+        }
+
+        Ok(())
+    }
+
+
+    /// Replaces a suppressed decision with `DoNothingExplicit` with the reason code of the rule,
+    /// so the suppression is recorded in the evaluation audit.
+    pub fn filter(&self, decision: Decision, now: DateTime<Utc>) -> Decision {
+        match self.check(&decision.sec_code, decision.action, now) {
+            Ok(()) => decision,
+            Err(skip) => Decision {
+                action: Action::DoNothingExplicit,
+                reason_code: skip.reason_code().to_string(),
+                ..decision
+            },
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::Mutex;
+use chrono::{DateTime, Duration, Utc};
+
+
+/// Source of the current time. The time-dependent logic takes a clock instead of calling
+/// `Utc::now()`, so session boundaries, cooldowns and schedules can be driven deterministically.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+
+/// The system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+
+/// Clock which only moves when told to, e.g. to replay a session or to check a boundary.
+///
+/// # Example of use
+/// ```
+/// # use std::sync::Arc;
+/// # use quik_rs::clock::{Clock, TestClock};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let clock = Arc::new(TestClock::new("2024-06-03T06:59:59Z".parse()?));
+/// // Given to the time-dependent logic, e.g. `CandleScheduler::with_clock(clock.clone())`
+/// clock.advance(chrono::Duration::seconds(1));
+/// assert_eq!(clock.now().to_rfc3339(), "2024-06-03T07:00:00+00:00");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TestClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+
+impl TestClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        TestClock { now: Mutex::new(now) }
+    }
+
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+}
+
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
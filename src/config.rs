@@ -0,0 +1,1598 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+use chrono::{Datelike, FixedOffset, NaiveDateTime, NaiveTime, Weekday};
+use quik_rs::transaction::Transaction;
+use toml_edit::{DocumentMut, Item, TableLike};
+use tracing::error;
+use crate::account::{self, AccountSettings};
+use crate::backtest::BacktestSettings;
+use crate::bracket::{BracketTemplate, Offset};
+use crate::churn::ChurnRules;
+use crate::crossover::{CrossoverFilters, EmaCross, EmaPair};
+use crate::eod::{self, EodSettings, EodStep, OnFailure};
+use crate::execution::ExecutionPolicy;
+use crate::expression::CustomIndicator;
+use crate::hedge::PairSettings;
+use crate::corporate_actions::CorporateActionCalendar;
+use crate::holding::HoldingRules;
+use crate::instrument_info::InstrumentInfo;
+use crate::notifier::NotificationChannel;
+use crate::orders::ChaseSettings;
+use crate::psql::DataForEma;
+use crate::retry::RetryPolicy;
+use crate::secrets::{self, Secret};
+use crate::spread::MaxSpread;
+use crate::strategy;
+use crate::sizing::{PositionSizer, Rounding, SizingMethod};
+use crate::tick_filter::{AnomalyAction, TickFilterSettings};
+use crate::watchdog::WatchdogSettings;
+
+
+/// Application settings loaded from a TOML file.
+///
+/// # Example of use
+/// ```ignore
+/// let config = config::Config::load("config.toml")?;
+/// let terminal = quik::Terminal::new(&config.path_to_lib, &config.path_to_quik)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path to the library `Trans2QUIK.dll`.
+    pub path_to_lib: String,
+
+    /// Path to the directory of the QUIK terminal.
+    pub path_to_quik: String,
+
+    /// Demo or live environment.
+    pub mode: RunMode,
+
+    /// Implementation of the QUIK terminal.
+    pub backend: Backend,
+
+    /// Explicit confirmation required to trade real classes and accounts.
+    pub confirm_live: bool,
+
+    /// Connection string for PostgreSQL, decrypted if it is stored encrypted.
+    pub connection_str: Secret,
+
+    /// Watchlist groups by name, e.g. "blue_chips" or "futures".
+    pub groups: HashMap<String, GroupConfig>,
+
+    /// Instruments of the watchlist.
+    pub instruments: Vec<InstrumentConfig>,
+
+    /// Number of days the per-candle evaluation audit is kept.
+    pub audit_retention_days: i64,
+
+    /// File persisting the last reserved TRANS_ID.
+    pub trans_id_file: String,
+
+    /// Write-ahead log of the transactions sent by the bot.
+    pub intent_log_file: String,
+
+    /// Interval between the refreshes of the instrument universe, `None` disables them.
+    pub universe_refresh: Option<Duration>,
+
+    /// Sustained limit of the transactions sent to the terminal.
+    pub max_transactions_per_second: f64,
+
+    /// Number of transactions that may be sent at once after a quiet period.
+    pub transaction_burst: u32,
+
+    /// Path to the CSV calendar of dividends and splits.
+    pub corporate_actions: Option<String>,
+
+    /// Custom indicators as pairs of the name and the expression, e.g. `(ema9 - ema21) / atr14`.
+    pub custom_indicators: Vec<(String, String)>,
+
+    /// Session schedules by class code, e.g. "TQBR" or "SPBFUT".
+    pub sessions: HashMap<String, ClassSession>,
+
+    /// Time zone of the exchange, UTC+3 (Moscow) by default: the session windows are in this
+    /// time, and the candles of the candle source are matched against them in it.
+    pub exchange_timezone: FixedOffset,
+
+    /// Bracket templates by strategy name, applied to the positions of the strategy by `exits::run`.
+    pub brackets: HashMap<String, BracketTemplate>,
+
+    /// Time-based exit rules by strategy name.
+    pub holding: HashMap<String, HoldingRules>,
+
+    /// Filter of erroneous prices in the incoming ticks.
+    pub tick_filter: TickFilterSettings,
+
+    /// Simulated execution of the `mock` backend.
+    pub paper: PaperSettings,
+
+    /// Stop of the trading on a shutdown request.
+    pub shutdown: ShutdownSettings,
+
+    /// End-of-day maintenance pipeline.
+    pub eod: EodSettings,
+
+    /// Restarts of the long-running tasks which panicked or exited.
+    pub watchdog: WatchdogSettings,
+
+    /// Chasing of the limit orders not filled in time, `None` leaves them working at their price.
+    pub chase: Option<ChaseSettings>,
+
+    /// Execution model of the backtests.
+    pub backtest: BacktestSettings,
+
+    /// Accounts the orders are routed to and checked against, empty skips the checks.
+    pub accounts: Vec<AccountSettings>,
+
+    /// Spot instruments hedged with their futures by the `spread_hedge` strategy.
+    pub pairs: Vec<PairSettings>,
+
+    /// Position sizing of the entries, `None` enters with `entry_lots` of `[backtest]`.
+    pub sizing: Option<PositionSizer>,
+
+    /// Retries of the database connection, the reconnection to QUIK and the notifications.
+    pub retry: RetryPolicy,
+
+    /// Age after which an active order without fills not sent by the bot is cancelled.
+    /// `None` disables the cleanup.
+    pub stale_order_minutes: Option<i64>,
+
+    /// Cancels the active orders the bot doesn't recognize after every reconnection to the
+    /// terminal, see `ConnectionSupervisor::with_dead_man_switch`. Off by default.
+    pub dead_man_switch: bool,
+
+    /// Address of the read-only web dashboard, e.g. `127.0.0.1:8080`, a bare port is served on
+    /// `127.0.0.1`. `None` disables it.
+    pub dashboard_addr: Option<String>,
+
+    /// Names of the strategies run by the bot, `["ema_cross"]` by default.
+    pub strategies: Vec<String>,
+
+    /// Notification channels by name, e.g. a Telegram chat or a webhook.
+    pub channels: HashMap<String, NotificationChannel>,
+
+    /// Names of the notification channels by strategy name, overriding the channels of the instruments.
+    pub notifications: HashMap<String, Vec<String>>,
+}
+
+
+/// Class codes of the demo trading system of QUIK Junior.
+const DEMO_CLASS_CODES: [&str; 1] = ["QJSIM"];
+
+
+/// Environment in which the bot runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    /// Demo environment (QJSIM/QUIK Junior), no real money at risk.
+    Demo,
+    /// Live environment with real classes and accounts.
+    Live,
+}
+
+
+impl RunMode {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value {
+            "demo" => Ok(RunMode::Demo),
+            "live" => Ok(RunMode::Live),
+            _ => Err(format!("unknown mode '{}', expected 'demo' or 'live'", value).into()),
+        }
+    }
+
+
+    /// Prefix of the notification messages, so demo and live messages can't be confused.
+    pub fn message_prefix(&self) -> &'static str {
+        match self {
+            RunMode::Demo => "[DEMO]",
+            RunMode::Live => "[LIVE]",
+        }
+    }
+
+
+    /// Banner shown at startup.
+    pub fn banner(&self) -> &'static str {
+        match self {
+            RunMode::Demo => "DEMO MODE: trading on the demo environment, no real money at risk",
+            RunMode::Live => "LIVE MODE: trading real accounts with real money",
+        }
+    }
+}
+
+
+/// Implementation of the QUIK terminal used by the bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The real terminal driven through Trans2QUIK.dll.
+    Trans2quik,
+    /// The simulated terminal `mock::MockTerminal`, e.g. for running on Linux.
+    Mock,
+}
+
+
+impl Backend {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value {
+            "trans2quik" => Ok(Backend::Trans2quik),
+            "mock" => Ok(Backend::Mock),
+            _ => Err(format!("unknown backend '{}', expected 'trans2quik' or 'mock'", value).into()),
+        }
+    }
+}
+
+
+/// What the bot does with a position of an instrument found at startup, e.g. after a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingPositions {
+    /// The position is managed by the strategy as if the bot had opened it.
+    Adopt,
+    /// The position is closed with a limit order at the price limit.
+    Close,
+    /// The position is left to the user and the bot doesn't trade the instrument while it is open.
+    Ignore,
+}
+
+
+impl ExistingPositions {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value {
+            "adopt" => Ok(ExistingPositions::Adopt),
+            "close" => Ok(ExistingPositions::Close),
+            "ignore" => Ok(ExistingPositions::Ignore),
+            _ => Err(format!("unknown existing_positions '{}', expected 'adopt', 'close' or 'ignore'", value).into()),
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExistingPositions::Adopt => "adopt",
+            ExistingPositions::Close => "close",
+            ExistingPositions::Ignore => "ignore",
+        }
+    }
+}
+
+
+/// Simulated execution of the `mock` backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PaperSettings {
+    /// Delay of the acknowledgement of a transaction.
+    pub ack_latency: Duration,
+    /// Delay between the acknowledgement of an order and its fill.
+    pub fill_latency: Duration,
+    /// Maximum random delay added to both latencies.
+    pub jitter: Duration,
+    /// Price shift of the fills against the order.
+    pub slippage: f64,
+}
+
+
+/// Stop of the trading on a shutdown request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShutdownSettings {
+    /// The working orders of the bot are cancelled before the disconnection.
+    pub cancel_orders: bool,
+    /// Maximum wait for the replies of the transactions in flight.
+    pub reply_timeout: Duration,
+    /// Names of the notification channels receiving the final state.
+    pub notification_channels: Vec<String>,
+}
+
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        ShutdownSettings {
+            cancel_orders: true,
+            reply_timeout: Duration::from_secs(10),
+            notification_channels: Vec::new(),
+        }
+    }
+}
+
+
+/// A time interval during which trading is allowed, e.g. `10:00-18:40`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradingWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+
+impl TradingWindow {
+    /// Parses a window from the `HH:MM-HH:MM` format.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| format!("invalid trading window '{}', expected HH:MM-HH:MM", value))?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M")?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M")?;
+
+        Ok(TradingWindow { start, end })
+    }
+
+
+    /// Checks whether the time falls into the window. A window whose end is earlier
+    /// than its start is treated as crossing midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+
+/// Group-level settings shared by all instruments of the group.
+#[derive(Debug, Clone)]
+pub struct GroupConfig {
+    /// Trading is allowed for the instruments of the group.
+    pub enabled: bool,
+
+    /// Candles, indicators, signals and alerts are produced, but no orders are sent.
+    pub watch_only: bool,
+
+    /// Intervals during which the instruments of the group are traded.
+    /// An empty list means no restriction.
+    pub trading_windows: Vec<TradingWindow>,
+
+    /// Maximum capital allocated to the instruments of the group.
+    pub risk_budget: Option<f64>,
+
+    /// Names of the notification channels, e.g. "telegram".
+    pub notification_channels: Vec<String>,
+
+    /// Number of days before an ex-dividend date of `corporate_actions` during which no new
+    /// positions are opened. `None` allows opening positions before ex-dividend dates.
+    pub ex_dividend_blackout_days: Option<i64>,
+
+    /// EMA crossovers evaluated simultaneously, e.g. 9/21 and 50/200.
+    pub ema_pairs: Vec<EmaPair>,
+
+    /// The faster pairs only trade in the direction of the trend of the slowest pair.
+    pub ema_trend_filter: bool,
+
+    /// Length of the candles evaluated by the strategies, 15 minutes by default.
+    pub timeframe_minutes: i64,
+
+    /// Distance between the EMAs in percent of the slow EMA the fast EMA must cross by
+    /// to change its side, so a crossover doesn't flip back and forth on noise. 0 by default.
+    pub ema_hysteresis: f64,
+
+    /// A crossover is only taken with the close beyond both EMAs in its direction.
+    pub ema_price_filter: bool,
+
+    /// Minimum slope of the slow EMA in the direction of a crossover, in percent of it per
+    /// candle. `None` takes the crossovers of a slow EMA of any slope.
+    pub ema_min_slope: Option<f64>,
+
+    /// Closed candles the fast EMA must stay on its new side before a crossover is taken,
+    /// 1 (the candle of the cross) by default.
+    pub ema_confirm_candles: u32,
+
+    /// Policy for the positions found at startup.
+    pub existing_positions: ExistingPositions,
+
+    /// Maximum spread for market entries, `None` allows any spread.
+    pub max_spread: Option<MaxSpread>,
+
+    /// Order book imbalance in `(0, 1]` confirming the entries, see `orderbook::Imbalance`.
+    /// `None` enters without a confirmation.
+    pub min_imbalance: Option<f64>,
+
+    /// Hours a position may stay open before an alert prompts a manual review.
+    /// `None` disables the alert.
+    pub max_position_age_hours: Option<f64>,
+
+    /// Realized profit of a session net of the commissions after which no new entries are made
+    /// in the instrument until the next session. `None` trades the whole session.
+    pub daily_profit_target: Option<f64>,
+
+    /// Candles after a fill during which the signals of the opposite side are suppressed.
+    pub cooldown_candles: Option<u32>,
+
+    /// Positions closed in an exchange day after which no new position is opened until the next day.
+    pub max_round_trips_per_day: Option<u32>,
+
+    /// Days before the expiry of a futures contract from which no new positions are opened
+    /// in it. `None` trades the contract until its expiry.
+    pub expiry_guard_days: Option<u32>,
+
+    /// Positions in a contract inside `expiry_guard_days` are rolled into the next series.
+    pub roll_on_expiry: bool,
+
+    /// Higher timeframe whose EMA trend must agree with a crossover before it is taken,
+    /// e.g. 60 to take the 5-minute crosses only in the direction of the 1-hour trend.
+    /// `None` takes every crossover.
+    pub confirmation_timeframe_minutes: Option<i64>,
+
+    /// EMA pair of the confirmation timeframe, the first of `ema_pairs` by default.
+    pub confirmation_ema_pair: Option<EmaPair>,
+
+    /// How the signals are turned into orders, a single market order by default.
+    pub execution: ExecutionPolicy,
+
+    /// Name of the account of `[accounts]` the orders are sent through, by default the account
+    /// routed by the class of the instrument.
+    pub account: Option<String>,
+}
+
+
+impl Default for GroupConfig {
+    fn default() -> Self {
+        GroupConfig {
+            enabled: true,
+            watch_only: false,
+            trading_windows: Vec::new(),
+            risk_budget: None,
+            notification_channels: Vec::new(),
+            ex_dividend_blackout_days: None,
+            ema_pairs: vec![EmaPair { fast: 9, slow: 21 }],
+            ema_trend_filter: false,
+            timeframe_minutes: 15,
+            ema_hysteresis: 0.0,
+            ema_price_filter: false,
+            ema_min_slope: None,
+            ema_confirm_candles: 1,
+            existing_positions: ExistingPositions::Ignore,
+            max_spread: None,
+            min_imbalance: None,
+            max_position_age_hours: None,
+            daily_profit_target: None,
+            cooldown_candles: None,
+            max_round_trips_per_day: None,
+            expiry_guard_days: None,
+            roll_on_expiry: false,
+            confirmation_timeframe_minutes: None,
+            confirmation_ema_pair: None,
+            execution: ExecutionPolicy::Market,
+            account: None,
+        }
+    }
+}
+
+
+/// Settings of a watchlist instrument. Every optional field overrides
+/// the corresponding setting of the group.
+#[derive(Debug, Clone)]
+pub struct InstrumentConfig {
+    pub class_code: String,
+    pub sec_code: String,
+    pub group: Option<String>,
+    pub enabled: Option<bool>,
+    pub watch_only: Option<bool>,
+    pub trading_windows: Option<Vec<TradingWindow>>,
+    pub risk_budget: Option<f64>,
+    pub notification_channels: Option<Vec<String>>,
+    pub ex_dividend_blackout_days: Option<i64>,
+    pub ema_pairs: Option<Vec<EmaPair>>,
+    pub ema_trend_filter: Option<bool>,
+    pub timeframe_minutes: Option<i64>,
+    pub ema_hysteresis: Option<f64>,
+    pub ema_price_filter: Option<bool>,
+    pub ema_min_slope: Option<f64>,
+    pub ema_confirm_candles: Option<u32>,
+    pub existing_positions: Option<ExistingPositions>,
+    pub max_spread: Option<MaxSpread>,
+    pub min_imbalance: Option<f64>,
+    pub max_position_age_hours: Option<f64>,
+    pub daily_profit_target: Option<f64>,
+    pub cooldown_candles: Option<u32>,
+    pub max_round_trips_per_day: Option<u32>,
+    pub expiry_guard_days: Option<u32>,
+    pub roll_on_expiry: Option<bool>,
+    pub confirmation_timeframe_minutes: Option<i64>,
+    pub confirmation_ema_pair: Option<EmaPair>,
+    pub execution: Option<ExecutionPolicy>,
+    pub account: Option<String>,
+}
+
+
+/// Effective settings of an instrument after merging it with its group.
+#[derive(Debug, Clone)]
+pub struct InstrumentSettings {
+    pub class_code: String,
+    /// Session schedule of the class of the instrument.
+    pub session: Option<ClassSession>,
+    pub sec_code: String,
+    pub group: Option<String>,
+    pub enabled: bool,
+    /// The instrument is evaluated, but no orders are generated for it.
+    pub watch_only: bool,
+    pub trading_windows: Vec<TradingWindow>,
+    pub risk_budget: Option<f64>,
+    pub notification_channels: Vec<String>,
+    pub ex_dividend_blackout_days: Option<i64>,
+    pub ema_pairs: Vec<EmaPair>,
+    pub ema_trend_filter: bool,
+    pub timeframe_minutes: i64,
+    pub ema_hysteresis: f64,
+    pub ema_price_filter: bool,
+    pub ema_min_slope: Option<f64>,
+    pub ema_confirm_candles: u32,
+    pub existing_positions: ExistingPositions,
+    pub max_spread: Option<MaxSpread>,
+    pub min_imbalance: Option<f64>,
+    pub max_position_age_hours: Option<f64>,
+    pub daily_profit_target: Option<f64>,
+    pub cooldown_candles: Option<u32>,
+    pub max_round_trips_per_day: Option<u32>,
+    pub expiry_guard_days: Option<u32>,
+    pub roll_on_expiry: bool,
+    pub confirmation_timeframe_minutes: Option<i64>,
+    pub confirmation_ema_pair: Option<EmaPair>,
+    pub execution: ExecutionPolicy,
+    /// Account the orders of the instrument are sent through, `None` without the accounts.
+    pub account: Option<AccountSettings>,
+}
+
+
+impl InstrumentSettings {
+    /// Checks whether orders may be generated for the instrument. Watch-only instruments
+    /// produce signals and alerts, but never orders.
+    pub fn allows_orders(&self) -> bool {
+        self.enabled && !self.watch_only
+    }
+
+
+    /// Sends the order through the account of the instrument, as is without the accounts, and
+    /// rounds and formats its prices with the price grid of the instrument from `info`.
+    /// Without the parameters of the instrument the prices are written as they are.
+    pub fn route(&self, transaction: Transaction, info: Option<&InstrumentInfo>) -> Transaction {
+        let transaction = match info {
+            Some(info) => transaction.with_price_format(info.price_format()),
+            None => transaction,
+        };
+        match &self.account {
+            Some(account) => account.apply(transaction),
+            None => transaction,
+        }
+    }
+
+
+    /// Length of the candles of the instrument.
+    pub fn timeframe(&self) -> Duration {
+        Duration::from_secs(self.timeframe_minutes as u64 * 60)
+    }
+
+
+    /// Higher timeframe and EMA pair whose trend must confirm the crossovers, if any.
+    pub fn confirmation(&self) -> Option<(Duration, EmaPair)> {
+        let minutes = self.confirmation_timeframe_minutes?;
+        let pair = self.confirmation_ema_pair.or_else(|| self.ema_pairs.first().copied())?;
+        Some((Duration::from_secs(minutes as u64 * 60), pair))
+    }
+
+
+    /// Conditions the crossovers of the instrument must meet to be taken.
+    pub fn crossover_filters(&self) -> CrossoverFilters {
+        CrossoverFilters {
+            price_beyond_emas: self.ema_price_filter,
+            min_slope: self.ema_min_slope,
+            persist_candles: self.ema_confirm_candles,
+        }
+    }
+
+
+    /// Cooldown and anti-churn rules of the instrument.
+    pub fn churn_rules(&self) -> ChurnRules {
+        ChurnRules {
+            cooldown_candles: self.cooldown_candles,
+            max_round_trips: self.max_round_trips_per_day,
+        }
+    }
+
+
+    /// Age of a position after which it is reported for a manual review.
+    pub fn max_position_age(&self) -> Option<Duration> {
+        self.max_position_age_hours.map(|hours| Duration::from_secs_f64(hours * 3600.0))
+    }
+
+
+    /// Checks whether the instrument may be traded at the given exchange time: the session
+    /// of its class is open and the time falls into the trading windows of the instrument.
+    pub fn is_trading_time(&self, time: NaiveDateTime) -> bool {
+        self.enabled
+            && self.session.as_ref().is_none_or(|session| session.is_open(time))
+            && (self.trading_windows.is_empty()
+                || self.trading_windows.iter().any(|window| window.contains(time.time())))
+    }
+}
+
+
+/// Phase of a session at a moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionPhase {
+    Closed,
+    /// Opening or closing auction, the prices are not formed by continuous trading.
+    Auction,
+    /// Break inside the session, e.g. the clearing of the derivatives market.
+    Break,
+    Trading,
+}
+
+
+/// Session schedule of a class, e.g. the stock market TQBR, the derivatives market SPBFUT
+/// or the currency market CETS. Days without windows have no session.
+///
+/// A schedule is defined once, directly in `[sessions.<class>]` or as a shared
+/// `[session_templates.<name>]`, and the same schedule decides what is inside the session
+/// for the trading windows of the instruments and for the candles of the class.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClassSession {
+    pub weekdays: Vec<TradingWindow>,
+    pub weekends: Vec<TradingWindow>,
+    /// Breaks of every session day.
+    pub breaks: Vec<TradingWindow>,
+    /// Auctions of every session day, inside or next to the windows.
+    pub auctions: Vec<TradingWindow>,
+}
+
+
+impl ClassSession {
+    /// Phase of the session at the given exchange time.
+    pub fn phase(&self, time: NaiveDateTime) -> SessionPhase {
+        let windows = match time.weekday() {
+            Weekday::Sat | Weekday::Sun => &self.weekends,
+            _ => &self.weekdays,
+        };
+        let contains = |windows: &[TradingWindow]| windows.iter().any(|window| window.contains(time.time()));
+
+        if windows.is_empty() {
+            SessionPhase::Closed
+        } else if contains(&self.auctions) {
+            SessionPhase::Auction
+        } else if !contains(windows) {
+            SessionPhase::Closed
+        } else if contains(&self.breaks) {
+            SessionPhase::Break
+        } else {
+            SessionPhase::Trading
+        }
+    }
+
+
+    /// Checks whether the continuous trading of the session is open at the given exchange time.
+    pub fn is_open(&self, time: NaiveDateTime) -> bool {
+        self.phase(time) == SessionPhase::Trading
+    }
+
+
+    /// Keeps the candles starting in the continuous trading, dropping the prints of the
+    /// auctions, the breaks and the off-session trades. `timezone` is the exchange time.
+    pub fn in_session(&self, candles: Vec<DataForEma>, timezone: FixedOffset) -> Vec<DataForEma> {
+        candles
+            .into_iter()
+            .filter(|candle| self.is_open(candle.period_start.with_timezone(&timezone).naive_local()))
+            .collect()
+    }
+}
+
+
+impl Config {
+    /// Reads and parses the configuration file.
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            error!("Error reading config file {}: {}", path, e);
+            e
+        })?;
+
+        Self::parse(&content).map_err(|e| {
+            error!("Error parsing config file {}: {}", path, e);
+            e
+        })
+    }
+
+
+    /// Parses the configuration from a TOML string.
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let document = content.parse::<DocumentMut>()?;
+
+        let mut groups = HashMap::new();
+        if let Some(table) = document.get("groups").and_then(Item::as_table_like) {
+            for (name, item) in table.iter() {
+                let group = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("group '{}' must be a table", name))?;
+                let group = parse_group(group).map_err(|e| format!("group '{}': {}", name, e))?;
+                groups.insert(name.to_string(), group);
+            }
+        }
+
+        let mut instruments = Vec::new();
+        if let Some(array) = document.get("instruments").and_then(Item::as_array_of_tables) {
+            for table in array.iter() {
+                instruments.push(parse_instrument(table)?);
+            }
+        }
+
+        let mut custom_indicators = Vec::new();
+        if let Some(table) = document.get("indicators").and_then(Item::as_table_like) {
+            for (name, item) in table.iter() {
+                let source = item
+                    .as_str()
+                    .ok_or_else(|| format!("indicator '{}' must be a string expression", name))?;
+                // Parse once to report errors in the expression at startup
+                CustomIndicator::new(name, source)?;
+                custom_indicators.push((name.to_string(), source.to_string()));
+            }
+        }
+
+        let mut session_templates = HashMap::new();
+        if let Some(table) = document.get("session_templates").and_then(Item::as_table_like) {
+            for (name, item) in table.iter() {
+                let template = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("session template '{}' must be a table", name))?;
+                let template = parse_session(template, &HashMap::new()).map_err(|e| format!("session template '{}': {}", name, e))?;
+                session_templates.insert(name.to_string(), template);
+            }
+        }
+
+        let mut sessions = HashMap::new();
+        if let Some(table) = document.get("sessions").and_then(Item::as_table_like) {
+            for (class_code, item) in table.iter() {
+                let session = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("session '{}' must be a table", class_code))?;
+                let session = parse_session(session, &session_templates).map_err(|e| format!("session '{}': {}", class_code, e))?;
+                sessions.insert(class_code.to_string(), session);
+            }
+        }
+
+        let mut brackets = HashMap::new();
+        if let Some(table) = document.get("brackets").and_then(Item::as_table_like) {
+            for (strategy, item) in table.iter() {
+                let bracket = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("bracket '{}' must be a table", strategy))?;
+                let bracket = parse_bracket(bracket).map_err(|e| format!("bracket '{}': {}", strategy, e))?;
+                brackets.insert(strategy.to_string(), bracket);
+            }
+        }
+
+        let mut holding = HashMap::new();
+        if let Some(table) = document.get("holding").and_then(Item::as_table_like) {
+            for (strategy, item) in table.iter() {
+                let rules = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("holding '{}' must be a table", strategy))?;
+                let rules = parse_holding(rules).map_err(|e| format!("holding '{}': {}", strategy, e))?;
+                holding.insert(strategy.to_string(), rules);
+            }
+        }
+
+        let mut channels = HashMap::new();
+        if let Some(table) = document.get("channels").and_then(Item::as_table_like) {
+            for (name, item) in table.iter() {
+                let channel = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("channel '{}' must be a table", name))?;
+                let channel = parse_channel(channel).map_err(|e| format!("channel '{}': {}", name, e))?;
+                channels.insert(name.to_string(), channel);
+            }
+        }
+
+        let mut notifications = HashMap::new();
+        if let Some(table) = document.get("notifications").and_then(Item::as_table_like) {
+            for (strategy, item) in table.iter() {
+                let profile = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("notifications '{}' must be a table", strategy))?;
+                let names = get_str_array(profile, "channels")?.ok_or_else(|| format!("notifications '{}': missing 'channels'", strategy))?;
+                if let Some(name) = names.iter().find(|name| !channels.contains_key(*name)) {
+                    return Err(format!("notifications '{}': unknown channel '{}'", strategy, name).into());
+                }
+                notifications.insert(strategy.to_string(), names);
+            }
+        }
+
+        let mut tick_filter = TickFilterSettings::default();
+        if let Some(table) = document.get("tick_filter").and_then(Item::as_table_like) {
+            if let Some(max_jump_percent) = get_float(table, "max_jump_percent")? {
+                if max_jump_percent <= 0.0 {
+                    return Err("tick_filter: 'max_jump_percent' must be positive".into());
+                }
+                tick_filter.max_jump_percent = max_jump_percent;
+            }
+            if let Some(action) = get_str(table, "action")? {
+                tick_filter.action = AnomalyAction::parse(&action).map_err(|e| format!("tick_filter: {}", e))?;
+            }
+            if let Some(confirm_ticks) = get_int(table, "confirm_ticks")? {
+                tick_filter.confirm_ticks = usize::try_from(confirm_ticks)
+                    .map_err(|_| "tick_filter: 'confirm_ticks' must be a non-negative integer")?;
+            }
+        }
+
+        let mut paper = PaperSettings::default();
+        if let Some(table) = document.get("paper").and_then(Item::as_table_like) {
+            let milliseconds = |key: &str| -> Result<Duration, Box<dyn std::error::Error>> {
+                match get_int(table, key)? {
+                    Some(value) if value >= 0 => Ok(Duration::from_millis(value as u64)),
+                    Some(_) => Err(format!("paper: '{}' must be a non-negative integer", key).into()),
+                    None => Ok(Duration::ZERO),
+                }
+            };
+            paper.ack_latency = milliseconds("ack_latency_ms")?;
+            paper.fill_latency = milliseconds("fill_latency_ms")?;
+            paper.jitter = milliseconds("jitter_ms")?;
+            paper.slippage = get_float(table, "slippage")?.unwrap_or(0.0);
+        }
+
+        let mut shutdown = ShutdownSettings::default();
+        if let Some(table) = document.get("shutdown").and_then(Item::as_table_like) {
+            shutdown.cancel_orders = get_bool(table, "cancel_orders")?.unwrap_or(shutdown.cancel_orders);
+            if let Some(seconds) = get_float(table, "reply_timeout_seconds")? {
+                if seconds < 0.0 {
+                    return Err("shutdown: 'reply_timeout_seconds' must not be negative".into());
+                }
+                shutdown.reply_timeout = Duration::from_secs_f64(seconds);
+            }
+            shutdown.notification_channels = get_str_array(table, "notification_channels")?.unwrap_or_default();
+        }
+
+        let eod = document
+            .get("eod")
+            .and_then(Item::as_table_like)
+            .map(parse_eod)
+            .transpose()
+            .map_err(|e| format!("eod: {}", e))?
+            .unwrap_or_default();
+        if let Some(name) = eod.notification_channels.iter().find(|name| !channels.contains_key(*name)) {
+            return Err(format!("eod: unknown channel '{}'", name).into());
+        }
+
+        let mut watchdog = WatchdogSettings::default();
+        if let Some(table) = document.get("watchdog").and_then(Item::as_table_like) {
+            if let Some(max_restarts) = get_int(table, "max_restarts")? {
+                watchdog.max_restarts = u32::try_from(max_restarts).map_err(|_| "watchdog: 'max_restarts' must be a non-negative integer")?;
+            }
+            if let Some(minutes) = get_int(table, "window_minutes")? {
+                if minutes <= 0 {
+                    return Err("watchdog: 'window_minutes' must be positive".into());
+                }
+                watchdog.window = Duration::from_secs(minutes as u64 * 60);
+            }
+            watchdog.notification_channels = get_str_array(table, "notification_channels")?.unwrap_or_default();
+            if let Some(name) = watchdog.notification_channels.iter().find(|name| !channels.contains_key(*name)) {
+                return Err(format!("watchdog: unknown channel '{}'", name).into());
+            }
+        }
+
+        let mut chase = None;
+        if let Some(table) = document.get("chase").and_then(Item::as_table_like) {
+            let mut settings = ChaseSettings::default();
+            if let Some(seconds) = get_float(table, "timeout_seconds")? {
+                if seconds <= 0.0 {
+                    return Err("chase: 'timeout_seconds' must be positive".into());
+                }
+                settings.timeout = Duration::from_secs_f64(seconds);
+            }
+            if let Some(ticks) = get_int(table, "ticks")? {
+                settings.ticks = u32::try_from(ticks).map_err(|_| "chase: 'ticks' must be a non-negative integer")?;
+            }
+            if let Some(max_chases) = get_int(table, "max_chases")? {
+                settings.max_chases = u32::try_from(max_chases).map_err(|_| "chase: 'max_chases' must be a non-negative integer")?;
+            }
+            if get_bool(table, "enabled")?.unwrap_or(true) {
+                chase = Some(settings);
+            }
+        }
+
+        let mut backtest = BacktestSettings::default();
+        if let Some(table) = document.get("backtest").and_then(Item::as_table_like) {
+            backtest.capital = get_float(table, "capital")?.unwrap_or(backtest.capital);
+            backtest.entry_lots = get_int(table, "entry_lots")?.unwrap_or(backtest.entry_lots);
+            backtest.commission_percent = get_float(table, "commission_percent")?.unwrap_or(backtest.commission_percent);
+            backtest.slippage_percent = get_float(table, "slippage_percent")?.unwrap_or(backtest.slippage_percent);
+            if backtest.capital <= 0.0 || backtest.entry_lots <= 0 {
+                return Err("backtest: 'capital' and 'entry_lots' must be positive".into());
+            }
+            if backtest.commission_percent < 0.0 || backtest.slippage_percent < 0.0 {
+                return Err("backtest: 'commission_percent' and 'slippage_percent' must not be negative".into());
+            }
+        }
+
+        let mut accounts = Vec::new();
+        if let Some(table) = document.get("account").and_then(Item::as_table_like) {
+            accounts.push(parse_account("default", table).map_err(|e| format!("account: {}", e))?);
+        }
+        if let Some(table) = document.get("accounts").and_then(Item::as_table_like) {
+            if !accounts.is_empty() {
+                return Err("[account] and [accounts] are exclusive, move [account] to [accounts.<name>]".into());
+            }
+            for (name, item) in table.iter() {
+                let account = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("account '{}' must be a table", name))?;
+                accounts.push(parse_account(name, account).map_err(|e| format!("account '{}': {}", name, e))?);
+            }
+        }
+        for account in &accounts {
+            let other = accounts
+                .iter()
+                .filter(|other| other.name != account.name)
+                .find(|other| other.class_codes.iter().any(|code| account.class_codes.contains(code)));
+            if let Some(other) = other {
+                return Err(format!("accounts '{}' and '{}' share a class in 'class_codes'", account.name, other.name).into());
+            }
+        }
+
+        let mut pairs = Vec::new();
+        if let Some(array) = document.get("pairs").and_then(Item::as_array_of_tables) {
+            for table in array.iter() {
+                let pair = parse_pair(table).map_err(|e| format!("pair {}: {}", pairs.len() + 1, e))?;
+                pairs.push(pair);
+            }
+        }
+
+        let sizing = document
+            .get("sizing")
+            .and_then(Item::as_table_like)
+            .map(parse_sizing)
+            .transpose()
+            .map_err(|e| format!("sizing: {}", e))?;
+
+        let retry = document
+            .get("retry")
+            .and_then(Item::as_table_like)
+            .map(parse_retry)
+            .transpose()
+            .map_err(|e| format!("retry: {}", e))?
+            .unwrap_or_default();
+
+        let config = Config {
+            path_to_lib: get_str(document.as_table(), "path_to_lib")?.unwrap_or_default(),
+            path_to_quik: get_str(document.as_table(), "path_to_quik")?.unwrap_or_default(),
+            mode: get_str(document.as_table(), "mode")?
+                .map(|mode| RunMode::parse(&mode))
+                .transpose()?
+                .unwrap_or(RunMode::Demo),
+            backend: get_str(document.as_table(), "backend")?
+                .map(|backend| Backend::parse(&backend))
+                .transpose()?
+                .unwrap_or(Backend::Trans2quik),
+            confirm_live: get_bool(document.as_table(), "confirm_live")?.unwrap_or(false),
+            connection_str: secrets::reveal(&get_str(document.as_table(), "connection_str")?.unwrap_or_default())?,
+            audit_retention_days: get_int(document.as_table(), "audit_retention_days")?.unwrap_or(30),
+            trans_id_file: get_str(document.as_table(), "trans_id_file")?.unwrap_or_else(|| "trans_id.txt".to_string()),
+            intent_log_file: get_str(document.as_table(), "intent_log_file")?.unwrap_or_else(|| "intents.jsonl".to_string()),
+            universe_refresh: match get_int(document.as_table(), "universe_refresh_minutes")?.unwrap_or(60) {
+                0 => None,
+                minutes if minutes < 0 => return Err("'universe_refresh_minutes' must not be negative".into()),
+                minutes => Some(Duration::from_secs(minutes as u64 * 60)),
+            },
+            max_transactions_per_second: get_float(document.as_table(), "max_transactions_per_second")?.unwrap_or(5.0),
+            transaction_burst: get_int(document.as_table(), "transaction_burst")?
+                .map(u32::try_from)
+                .transpose()
+                .map_err(|_| "'transaction_burst' must be a non-negative integer")?
+                .unwrap_or(5),
+            corporate_actions: get_str(document.as_table(), "corporate_actions")?,
+            dashboard_addr: get_str(document.as_table(), "dashboard_addr")?,
+            exchange_timezone: get_str(document.as_table(), "exchange_timezone")?
+                .map(|timezone| {
+                    timezone
+                        .parse::<FixedOffset>()
+                        .map_err(|_| format!("invalid exchange_timezone '{}', expected e.g. +03:00", timezone))
+                })
+                .transpose()?
+                .unwrap_or_else(|| FixedOffset::east_opt(3 * 3600).expect("valid offset")),
+            strategies: get_str_array(document.as_table(), "strategies")?
+                .unwrap_or_else(|| vec![EmaCross::NAME.to_string()]),
+            stale_order_minutes: get_int(document.as_table(), "stale_order_minutes")?
+                .map(|minutes| if minutes > 0 { Ok(minutes) } else { Err("'stale_order_minutes' must be positive") })
+                .transpose()?,
+            dead_man_switch: get_bool(document.as_table(), "dead_man_switch")?.unwrap_or(false),
+            groups,
+            instruments,
+            custom_indicators,
+            sessions,
+            brackets,
+            holding,
+            tick_filter,
+            paper,
+            shutdown,
+            eod,
+            watchdog,
+            chase,
+            backtest,
+            accounts,
+            pairs,
+            sizing,
+            retry,
+            channels,
+            notifications,
+        };
+
+        // Validate the group references once, so the errors surface at startup
+        let instruments = config.instrument_settings()?;
+        for (number, pair) in config.pairs.iter().enumerate() {
+            for leg in [&pair.spot, &pair.futures] {
+                if !instruments.iter().any(|instrument| &instrument.sec_code == leg) {
+                    return Err(format!("pair {}: '{}' is not in [[instruments]]", number + 1, leg).into());
+                }
+                if config.pairs[..number].iter().any(|other| &other.spot == leg || &other.futures == leg) {
+                    return Err(format!("pair {}: '{}' is already a leg of another pair", number + 1, leg).into());
+                }
+            }
+        }
+        for name in &config.strategies {
+            if !strategy::STRATEGIES.contains(&name.as_str()) {
+                return Err(format!("unknown strategy '{}', expected one of: {}", name, strategy::STRATEGIES.join(", ")).into());
+            }
+        }
+        config.check_environment()?;
+
+        Ok(config)
+    }
+
+
+    /// Refuses to trade real classes unless the live mode is confirmed with `confirm_live = true`.
+    /// In the demo mode only the classes of the demo environment may be traded.
+    pub fn check_environment(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let real_classes: Vec<&str> = self
+            .instruments
+            .iter()
+            .map(|instrument| instrument.class_code.as_str())
+            .filter(|class_code| !DEMO_CLASS_CODES.contains(class_code))
+            .collect();
+
+        match self.mode {
+            RunMode::Live if !self.confirm_live => {
+                Err("mode = \"live\" requires confirm_live = true".into())
+            }
+            RunMode::Demo if !real_classes.is_empty() => Err(format!(
+                "classes {:?} are not demo classes; set mode = \"live\" and confirm_live = true to trade them",
+                real_classes
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+
+    /// Calendar of the dividends and the splits of `corporate_actions`, empty without the file.
+    pub fn corporate_action_calendar(&self) -> Result<CorporateActionCalendar, Box<dyn std::error::Error>> {
+        match &self.corporate_actions {
+            Some(path) => CorporateActionCalendar::load_csv(path),
+            None => Ok(CorporateActionCalendar::default()),
+        }
+    }
+
+
+    /// Returns the effective settings of every instrument of the watchlist.
+    pub fn instrument_settings(&self) -> Result<Vec<InstrumentSettings>, Box<dyn std::error::Error>> {
+        self.instruments
+            .iter()
+            .map(|instrument| self.resolve(instrument))
+            .collect()
+    }
+
+
+    /// Merges the instrument settings with the settings of its group.
+    fn resolve(&self, instrument: &InstrumentConfig) -> Result<InstrumentSettings, Box<dyn std::error::Error>> {
+        let group = match &instrument.group {
+            Some(name) => self.groups.get(name).cloned().ok_or_else(|| {
+                format!("instrument {} refers to unknown group '{}'", instrument.sec_code, name)
+            })?,
+            None => GroupConfig::default(),
+        };
+
+        let timeframe_minutes = instrument.timeframe_minutes.unwrap_or(group.timeframe_minutes);
+        let confirmation_timeframe_minutes = instrument.confirmation_timeframe_minutes.or(group.confirmation_timeframe_minutes);
+        if confirmation_timeframe_minutes.is_some_and(|minutes| minutes <= timeframe_minutes) {
+            return Err(format!(
+                "instrument {}: confirmation_timeframe_minutes must exceed timeframe_minutes {}",
+                instrument.sec_code, timeframe_minutes
+            )
+            .into());
+        }
+
+        let name = instrument.account.as_deref().or(group.account.as_deref());
+        let account = account::route(&self.accounts, &instrument.class_code, name).cloned();
+        if let (Some(name), None) = (name, &account) {
+            return Err(format!("instrument {} refers to unknown account '{}'", instrument.sec_code, name).into());
+        }
+
+        Ok(InstrumentSettings {
+            class_code: instrument.class_code.clone(),
+            session: self.sessions.get(&instrument.class_code).cloned(),
+            sec_code: instrument.sec_code.clone(),
+            group: instrument.group.clone(),
+            // A disabled group switches off all of its instruments
+            enabled: group.enabled && instrument.enabled.unwrap_or(true),
+            watch_only: instrument.watch_only.unwrap_or(group.watch_only),
+            trading_windows: instrument.trading_windows.clone().unwrap_or(group.trading_windows),
+            risk_budget: instrument.risk_budget.or(group.risk_budget),
+            notification_channels: instrument
+                .notification_channels
+                .clone()
+                .unwrap_or(group.notification_channels),
+            ex_dividend_blackout_days: instrument.ex_dividend_blackout_days.or(group.ex_dividend_blackout_days),
+            ema_pairs: instrument.ema_pairs.clone().unwrap_or(group.ema_pairs),
+            ema_trend_filter: instrument.ema_trend_filter.unwrap_or(group.ema_trend_filter),
+            timeframe_minutes,
+            ema_hysteresis: instrument.ema_hysteresis.unwrap_or(group.ema_hysteresis),
+            ema_price_filter: instrument.ema_price_filter.unwrap_or(group.ema_price_filter),
+            ema_min_slope: instrument.ema_min_slope.or(group.ema_min_slope),
+            ema_confirm_candles: instrument.ema_confirm_candles.unwrap_or(group.ema_confirm_candles),
+            existing_positions: instrument.existing_positions.unwrap_or(group.existing_positions),
+            max_spread: instrument.max_spread.or(group.max_spread),
+            min_imbalance: instrument.min_imbalance.or(group.min_imbalance),
+            max_position_age_hours: instrument.max_position_age_hours.or(group.max_position_age_hours),
+            daily_profit_target: instrument.daily_profit_target.or(group.daily_profit_target),
+            cooldown_candles: instrument.cooldown_candles.or(group.cooldown_candles),
+            max_round_trips_per_day: instrument.max_round_trips_per_day.or(group.max_round_trips_per_day),
+            expiry_guard_days: instrument.expiry_guard_days.or(group.expiry_guard_days),
+            roll_on_expiry: instrument.roll_on_expiry.unwrap_or(group.roll_on_expiry),
+            confirmation_timeframe_minutes,
+            confirmation_ema_pair: instrument.confirmation_ema_pair.or(group.confirmation_ema_pair),
+            execution: instrument.execution.unwrap_or(group.execution),
+            account,
+        })
+    }
+}
+
+
+fn parse_group(table: &dyn TableLike) -> Result<GroupConfig, Box<dyn std::error::Error>> {
+    let defaults = GroupConfig::default();
+
+    Ok(GroupConfig {
+        enabled: get_bool(table, "enabled")?.unwrap_or(defaults.enabled),
+        watch_only: get_bool(table, "watch_only")?.unwrap_or(defaults.watch_only),
+        trading_windows: get_windows(table, "trading_windows")?.unwrap_or_default(),
+        risk_budget: get_float(table, "risk_budget")?,
+        notification_channels: get_str_array(table, "notification_channels")?.unwrap_or_default(),
+        ex_dividend_blackout_days: get_int(table, "ex_dividend_blackout_days")?,
+        ema_pairs: get_ema_pairs(table, "ema_pairs")?.unwrap_or(defaults.ema_pairs),
+        ema_trend_filter: get_bool(table, "ema_trend_filter")?.unwrap_or(defaults.ema_trend_filter),
+        timeframe_minutes: get_timeframe(table)?.unwrap_or(defaults.timeframe_minutes),
+        ema_hysteresis: get_hysteresis(table)?.unwrap_or(defaults.ema_hysteresis),
+        ema_price_filter: get_bool(table, "ema_price_filter")?.unwrap_or(defaults.ema_price_filter),
+        ema_min_slope: get_float(table, "ema_min_slope")?,
+        ema_confirm_candles: get_count(table, "ema_confirm_candles")?.unwrap_or(defaults.ema_confirm_candles),
+        existing_positions: get_existing_positions(table)?.unwrap_or(defaults.existing_positions),
+        max_spread: get_max_spread(table)?,
+        min_imbalance: get_min_imbalance(table)?,
+        max_position_age_hours: get_position_age(table)?,
+        daily_profit_target: get_profit_target(table)?,
+        cooldown_candles: get_count(table, "cooldown_candles")?,
+        max_round_trips_per_day: get_count(table, "max_round_trips_per_day")?,
+        expiry_guard_days: get_count(table, "expiry_guard_days")?,
+        roll_on_expiry: get_bool(table, "roll_on_expiry")?.unwrap_or(defaults.roll_on_expiry),
+        confirmation_timeframe_minutes: get_confirmation_timeframe(table)?,
+        confirmation_ema_pair: get_str(table, "confirmation_ema_pair")?.map(|value| EmaPair::parse(&value)).transpose()?,
+        execution: get_execution(table)?.unwrap_or(defaults.execution),
+        account: get_str(table, "account")?,
+    })
+}
+
+
+/// Parses a session schedule. With `template` the windows of the template are taken
+/// unless the session sets its own.
+fn parse_session(table: &dyn TableLike, templates: &HashMap<String, ClassSession>) -> Result<ClassSession, Box<dyn std::error::Error>> {
+    let template = match get_str(table, "template")? {
+        Some(name) => templates
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| format!("unknown session template '{}'", name))?,
+        None => ClassSession::default(),
+    };
+
+    Ok(ClassSession {
+        weekdays: get_windows(table, "weekdays")?.unwrap_or(template.weekdays),
+        weekends: get_windows(table, "weekends")?.unwrap_or(template.weekends),
+        breaks: get_windows(table, "breaks")?.unwrap_or(template.breaks),
+        auctions: get_windows(table, "auctions")?.unwrap_or(template.auctions),
+    })
+}
+
+
+fn parse_bracket(table: &dyn TableLike) -> Result<BracketTemplate, Box<dyn std::error::Error>> {
+    let stop = get_str(table, "stop")?.ok_or("missing 'stop'")?;
+    let target = get_str(table, "target")?.ok_or("missing 'target'")?;
+
+    Ok(BracketTemplate {
+        stop: Offset::parse(&stop)?,
+        target: Offset::parse(&target)?,
+    })
+}
+
+
+fn parse_holding(table: &dyn TableLike) -> Result<HoldingRules, Box<dyn std::error::Error>> {
+    let candles = |key: &str| -> Result<Option<u32>, Box<dyn std::error::Error>> {
+        get_int(table, key)?
+            .map(|value| u32::try_from(value).ok().filter(|value| *value > 0).ok_or(format!("'{}' must be positive", key)))
+            .transpose()
+            .map_err(Into::into)
+    };
+    let duration = |key: &str, seconds: f64| -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+        get_float(table, key)?
+            .map(|value| {
+                if value > 0.0 {
+                    Ok(Duration::from_secs_f64(value * seconds))
+                } else {
+                    Err(format!("'{}' must be positive", key))
+                }
+            })
+            .transpose()
+            .map_err(Into::into)
+    };
+
+    let rules = HoldingRules {
+        max_candles: candles("max_candles")?,
+        max_duration: duration("max_hours", 3600.0)?,
+        min_candles: candles("min_candles")?,
+        min_duration: duration("min_minutes", 60.0)?,
+    };
+    rules.validate()?;
+
+    Ok(rules)
+}
+
+
+fn parse_eod(table: &dyn TableLike) -> Result<EodSettings, Box<dyn std::error::Error>> {
+    let mut eod = EodSettings::default();
+    eod.run_after_close = get_bool(table, "run_after_close")?.unwrap_or(eod.run_after_close);
+    if let Some(minutes) = get_int(table, "delay_minutes")? {
+        eod.delay_minutes = u32::try_from(minutes).map_err(|_| "'delay_minutes' must be a non-negative integer")?;
+    }
+    eod.run_at = get_str(table, "run_at")?
+        .map(|time| NaiveTime::parse_from_str(&time, "%H:%M").map_err(|_| format!("invalid 'run_at' '{}', expected HH:MM", time)))
+        .transpose()?;
+    eod.notification_channels = get_str_array(table, "notification_channels")?.unwrap_or_default();
+
+    for step in EodStep::ALL {
+        let Some(item) = table.get(step.as_str()) else {
+            continue;
+        };
+        let step_table = item.as_table_like().ok_or_else(|| format!("'{}' must be a table", step.as_str()))?;
+        let settings = eod.steps.entry(step).or_insert_with(|| eod::StepSettings { enabled: true, on_failure: OnFailure::Continue });
+        settings.enabled = get_bool(step_table, "enabled")?.unwrap_or(settings.enabled);
+        if let Some(on_failure) = get_str(step_table, "on_failure")? {
+            settings.on_failure = OnFailure::parse(&on_failure).map_err(|e| format!("{}: {}", step.as_str(), e))?;
+        }
+    }
+
+    Ok(eod)
+}
+
+
+fn parse_account(name: &str, table: &dyn TableLike) -> Result<AccountSettings, Box<dyn std::error::Error>> {
+    Ok(AccountSettings {
+        name: name.to_string(),
+        client_code: get_str(table, "client_code")?.ok_or("missing 'client_code'")?,
+        trade_account: get_str(table, "trade_account")?,
+        class_codes: get_str_array(table, "class_codes")?.unwrap_or_default(),
+        currency: get_str(table, "currency")?.unwrap_or_else(|| "SUR".to_string()),
+        max_daily_loss: match get_float(table, "max_daily_loss")? {
+            Some(loss) if loss <= 0.0 => return Err("'max_daily_loss' must be positive".into()),
+            loss => loss,
+        },
+    })
+}
+
+
+fn parse_pair(table: &dyn TableLike) -> Result<PairSettings, Box<dyn std::error::Error>> {
+    let pair = PairSettings {
+        spot: get_str(table, "spot")?.ok_or("missing 'spot'")?,
+        futures: get_str(table, "futures")?.ok_or("missing 'futures'")?,
+        futures_units: get_int(table, "futures_units")?.ok_or("missing 'futures_units'")?,
+        spot_lots: get_int(table, "spot_lots")?.unwrap_or(1),
+        timeframe_minutes: get_timeframe(table)?.unwrap_or(15),
+        lookback: get_count(table, "lookback")?.unwrap_or(20) as usize,
+        entry_z: get_float(table, "entry_z")?.unwrap_or(2.0),
+        exit_z: get_float(table, "exit_z")?.unwrap_or(0.5),
+        reduce_z: get_float(table, "reduce_z")?,
+    };
+    if pair.spot == pair.futures {
+        return Err("'spot' and 'futures' are the same instrument".into());
+    }
+    if pair.futures_units <= 0 || pair.spot_lots <= 0 {
+        return Err("'futures_units' and 'spot_lots' must be positive".into());
+    }
+    if pair.lookback < 2 {
+        return Err("'lookback' must be at least 2".into());
+    }
+    if pair.exit_z < 0.0 || pair.entry_z <= pair.exit_z {
+        return Err("'entry_z' must be greater than 'exit_z', which must not be negative".into());
+    }
+    if pair.reduce_z.is_some_and(|reduce_z| reduce_z <= pair.exit_z || reduce_z >= pair.entry_z) {
+        return Err("'reduce_z' must be between 'exit_z' and 'entry_z'".into());
+    }
+
+    Ok(pair)
+}
+
+
+fn parse_retry(table: &dyn TableLike) -> Result<RetryPolicy, Box<dyn std::error::Error>> {
+    let mut retry = RetryPolicy::default();
+    let milliseconds = |key: &str| -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+        match get_int(table, key)? {
+            Some(value) if value > 0 => Ok(Some(Duration::from_millis(value as u64))),
+            Some(_) => Err(format!("'{}' must be positive", key).into()),
+            None => Ok(None),
+        }
+    };
+
+    if let Some(max_attempts) = get_int(table, "max_attempts")? {
+        retry.max_attempts = match max_attempts {
+            0 => None,
+            attempts => Some(u32::try_from(attempts).map_err(|_| "'max_attempts' must be a non-negative integer")?),
+        };
+    }
+    retry.base_delay = milliseconds("base_delay_ms")?.unwrap_or(retry.base_delay);
+    retry.max_delay = milliseconds("max_delay_ms")?.unwrap_or(retry.max_delay);
+    if retry.max_delay < retry.base_delay {
+        return Err("'max_delay_ms' is less than 'base_delay_ms'".into());
+    }
+    if let Some(jitter) = get_float(table, "jitter")? {
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err("'jitter' must be between 0 and 1".into());
+        }
+        retry.jitter = jitter;
+    }
+
+    Ok(retry)
+}
+
+
+fn parse_sizing(table: &dyn TableLike) -> Result<PositionSizer, Box<dyn std::error::Error>> {
+    let method = match get_str(table, "method")?.as_deref().unwrap_or("fixed") {
+        "fixed" => SizingMethod::Fixed {
+            lots: get_int(table, "lots")?.unwrap_or(1),
+        },
+        "fixed_fraction" => SizingMethod::FixedFraction {
+            risk_percent: get_float(table, "risk_percent")?.ok_or("missing 'risk_percent'")?,
+            stop: Offset::parse(&get_str(table, "stop")?.ok_or("missing 'stop'")?)?,
+        },
+        "volatility_target" => SizingMethod::VolatilityTarget {
+            volatility_percent: get_float(table, "volatility_percent")?.ok_or("missing 'volatility_percent'")?,
+        },
+        method => {
+            return Err(format!("unknown method '{}', expected 'fixed', 'fixed_fraction' or 'volatility_target'", method).into())
+        }
+    };
+    let defaults = PositionSizer::fixed(1);
+    let sizer = PositionSizer {
+        method,
+        atr_period: match get_int(table, "atr_period")? {
+            Some(period) => usize::try_from(period).map_err(|_| "'atr_period' must be positive")?,
+            None => defaults.atr_period,
+        },
+        min_lots: get_int(table, "min_lots")?.unwrap_or(defaults.min_lots),
+        max_lots: get_int(table, "max_lots")?,
+        rounding: get_str(table, "rounding")?
+            .map(|rounding| Rounding::parse(&rounding))
+            .transpose()?
+            .unwrap_or(defaults.rounding),
+    };
+    sizer.validate()?;
+
+    Ok(sizer)
+}
+
+
+fn parse_channel(table: &dyn TableLike) -> Result<NotificationChannel, Box<dyn std::error::Error>> {
+    match get_str(table, "kind")?.as_deref() {
+        Some("telegram") => Ok(NotificationChannel::Telegram {
+            bot_token: secrets::reveal(&get_str(table, "bot_token")?.ok_or("missing 'bot_token'")?)?,
+            chat_id: get_str(table, "chat_id")?.ok_or("missing 'chat_id'")?,
+            api_url: get_str(table, "api_url")?.unwrap_or_else(|| NotificationChannel::TELEGRAM_API_URL.to_string()),
+        }),
+        Some("webhook") => Ok(NotificationChannel::Webhook {
+            url: secrets::reveal(&get_str(table, "url")?.ok_or("missing 'url'")?)?,
+        }),
+        Some(kind) => Err(format!("unknown kind '{}', expected 'telegram' or 'webhook'", kind).into()),
+        None => Err("missing 'kind'".into()),
+    }
+}
+
+
+fn parse_instrument(table: &dyn TableLike) -> Result<InstrumentConfig, Box<dyn std::error::Error>> {
+    let class_code = get_str(table, "class_code")?.ok_or("instrument without class_code")?;
+    let sec_code = get_str(table, "sec_code")?.ok_or("instrument without sec_code")?;
+
+    Ok(InstrumentConfig {
+        class_code,
+        sec_code,
+        group: get_str(table, "group")?,
+        enabled: get_bool(table, "enabled")?,
+        watch_only: get_bool(table, "watch_only")?,
+        trading_windows: get_windows(table, "trading_windows")?,
+        risk_budget: get_float(table, "risk_budget")?,
+        notification_channels: get_str_array(table, "notification_channels")?,
+        ex_dividend_blackout_days: get_int(table, "ex_dividend_blackout_days")?,
+        ema_pairs: get_ema_pairs(table, "ema_pairs")?,
+        ema_trend_filter: get_bool(table, "ema_trend_filter")?,
+        timeframe_minutes: get_timeframe(table)?,
+        ema_hysteresis: get_hysteresis(table)?,
+        ema_price_filter: get_bool(table, "ema_price_filter")?,
+        ema_min_slope: get_float(table, "ema_min_slope")?,
+        ema_confirm_candles: get_count(table, "ema_confirm_candles")?,
+        existing_positions: get_existing_positions(table)?,
+        max_spread: get_max_spread(table)?,
+        min_imbalance: get_min_imbalance(table)?,
+        max_position_age_hours: get_position_age(table)?,
+        daily_profit_target: get_profit_target(table)?,
+        cooldown_candles: get_count(table, "cooldown_candles")?,
+        max_round_trips_per_day: get_count(table, "max_round_trips_per_day")?,
+        expiry_guard_days: get_count(table, "expiry_guard_days")?,
+        roll_on_expiry: get_bool(table, "roll_on_expiry")?,
+        confirmation_timeframe_minutes: get_confirmation_timeframe(table)?,
+        confirmation_ema_pair: get_str(table, "confirmation_ema_pair")?.map(|value| EmaPair::parse(&value)).transpose()?,
+        execution: get_execution(table)?,
+        account: get_str(table, "account")?,
+    })
+}
+
+
+fn get_str(table: &dyn TableLike, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item
+            .as_str()
+            .map(|value| Some(value.to_string()))
+            .ok_or_else(|| format!("'{}' must be a string", key).into()),
+    }
+}
+
+
+fn get_bool(table: &dyn TableLike, key: &str) -> Result<Option<bool>, Box<dyn std::error::Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| format!("'{}' must be a boolean", key).into()),
+    }
+}
+
+
+fn get_float(table: &dyn TableLike, key: &str) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item
+            .as_float()
+            .or_else(|| item.as_integer().map(|value| value as f64))
+            .map(Some)
+            .ok_or_else(|| format!("'{}' must be a number", key).into()),
+    }
+}
+
+
+fn get_int(table: &dyn TableLike, key: &str) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item
+            .as_integer()
+            .map(Some)
+            .ok_or_else(|| format!("'{}' must be an integer", key).into()),
+    }
+}
+
+
+fn get_str_array(table: &dyn TableLike, key: &str) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+    let Some(item) = table.get(key) else {
+        return Ok(None);
+    };
+    let array = item
+        .as_array()
+        .ok_or_else(|| format!("'{}' must be an array of strings", key))?;
+
+    array
+        .iter()
+        .map(|value| {
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("'{}' must be an array of strings", key).into())
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()
+        .map(Some)
+}
+
+
+fn get_windows(table: &dyn TableLike, key: &str) -> Result<Option<Vec<TradingWindow>>, Box<dyn std::error::Error>> {
+    match get_str_array(table, key)? {
+        None => Ok(None),
+        Some(values) => values
+            .iter()
+            .map(|value| TradingWindow::parse(value))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+    }
+}
+
+
+fn get_existing_positions(table: &dyn TableLike) -> Result<Option<ExistingPositions>, Box<dyn std::error::Error>> {
+    get_str(table, "existing_positions")?
+        .map(|value| ExistingPositions::parse(&value))
+        .transpose()
+}
+
+
+fn get_max_spread(table: &dyn TableLike) -> Result<Option<MaxSpread>, Box<dyn std::error::Error>> {
+    get_str(table, "max_spread")?
+        .map(|value| MaxSpread::parse(&value))
+        .transpose()
+}
+
+
+fn get_min_imbalance(table: &dyn TableLike) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match get_float(table, "min_imbalance")? {
+        Some(imbalance) if imbalance <= 0.0 || imbalance > 1.0 => Err("'min_imbalance' must be in (0, 1]".into()),
+        imbalance => Ok(imbalance),
+    }
+}
+
+
+fn get_execution(table: &dyn TableLike) -> Result<Option<ExecutionPolicy>, Box<dyn std::error::Error>> {
+    get_str(table, "execution")?
+        .map(|value| ExecutionPolicy::parse(&value))
+        .transpose()
+}
+
+
+fn get_timeframe(table: &dyn TableLike) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    match get_int(table, "timeframe_minutes")? {
+        Some(minutes) if minutes <= 0 => Err("'timeframe_minutes' must be positive".into()),
+        minutes => Ok(minutes),
+    }
+}
+
+
+fn get_position_age(table: &dyn TableLike) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match get_float(table, "max_position_age_hours")? {
+        Some(hours) if hours <= 0.0 => Err("'max_position_age_hours' must be positive".into()),
+        hours => Ok(hours),
+    }
+}
+
+
+fn get_count(table: &dyn TableLike, key: &str) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+    get_int(table, key)?
+        .map(|value| u32::try_from(value).ok().filter(|value| *value > 0).ok_or(format!("'{}' must be positive", key)))
+        .transpose()
+        .map_err(Into::into)
+}
+
+
+fn get_profit_target(table: &dyn TableLike) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match get_float(table, "daily_profit_target")? {
+        Some(target) if target <= 0.0 => Err("'daily_profit_target' must be positive".into()),
+        target => Ok(target),
+    }
+}
+
+
+fn get_confirmation_timeframe(table: &dyn TableLike) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    match get_int(table, "confirmation_timeframe_minutes")? {
+        Some(minutes) if minutes <= 0 => Err("'confirmation_timeframe_minutes' must be positive".into()),
+        minutes => Ok(minutes),
+    }
+}
+
+
+fn get_hysteresis(table: &dyn TableLike) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match get_float(table, "ema_hysteresis")? {
+        Some(percent) if percent < 0.0 => Err("'ema_hysteresis' must not be negative".into()),
+        percent => Ok(percent),
+    }
+}
+
+
+fn get_ema_pairs(table: &dyn TableLike, key: &str) -> Result<Option<Vec<EmaPair>>, Box<dyn std::error::Error>> {
+    let Some(values) = get_str_array(table, key)? else {
+        return Ok(None);
+    };
+    if values.is_empty() {
+        return Err(format!("'{}' must contain at least one pair", key).into());
+    }
+
+    values
+        .iter()
+        .map(|value| EmaPair::parse(value))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::fs;
+use chrono::{DateTime, NaiveDate, Utc};
+use tracing::error;
+use crate::psql::DataForEma;
+
+
+/// Kind of a corporate action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CorporateActionKind {
+    /// Dividend per share; `date` is the ex-dividend date.
+    Dividend { amount: f64 },
+    /// Split of one share into `ratio` shares; a reverse split has a ratio below one.
+    Split { ratio: f64 },
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorporateAction {
+    pub date: NaiveDate,
+    pub kind: CorporateActionKind,
+}
+
+
+/// Calendar of dividends and splits by instrument code.
+///
+/// The calendar is loaded from a CSV file with the columns `sec_code,date,kind,value`:
+/// ```ignore
+/// SBER,2024-07-11,dividend,33.3
+/// GMKN,2024-04-04,split,100
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CorporateActionCalendar {
+    actions: HashMap<String, Vec<CorporateAction>>,
+}
+
+
+impl CorporateActionCalendar {
+    /// Reads the calendar from a CSV file.
+    pub fn load_csv(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            error!("Error reading corporate actions file {}: {}", path, e);
+            e
+        })?;
+
+        Self::parse_csv(&content).map_err(|e| {
+            error!("Error parsing corporate actions file {}: {}", path, e);
+            e
+        })
+    }
+
+
+    /// Parses the calendar from CSV. Empty lines, comments starting with `#`
+    /// and the header line are skipped.
+    pub fn parse_csv(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut calendar = CorporateActionCalendar::default();
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("sec_code") {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [sec_code, date, kind, value] = fields[..] else {
+                return Err(format!("line {}: expected 4 fields, got {}", number + 1, fields.len()).into());
+            };
+
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")?;
+            let value: f64 = value.parse()?;
+            let kind = match kind {
+                "dividend" => CorporateActionKind::Dividend { amount: value },
+                "split" if value > 0.0 => CorporateActionKind::Split { ratio: value },
+                _ => return Err(format!("line {}: unknown corporate action '{}' with value {}", number + 1, kind, value).into()),
+            };
+
+            calendar.add(sec_code, CorporateAction { date, kind });
+        }
+
+        Ok(calendar)
+    }
+
+
+    pub fn add(&mut self, sec_code: &str, action: CorporateAction) {
+        let actions = self.actions.entry(sec_code.to_string()).or_default();
+        actions.push(action);
+        actions.sort_by_key(|action| action.date);
+    }
+
+
+    /// Returns the nearest ex-dividend date of the instrument on or after `from`.
+    pub fn next_ex_dividend(&self, sec_code: &str, from: NaiveDate) -> Option<NaiveDate> {
+        self.actions
+            .get(sec_code)?
+            .iter()
+            .filter(|action| matches!(action.kind, CorporateActionKind::Dividend { .. }))
+            .map(|action| action.date)
+            .find(|date| *date >= from)
+    }
+
+
+    /// Checks whether a position opened on `today` would be held over an ex-dividend date
+    /// within the next `days` days. A position held on the day before the ex-dividend date
+    /// receives the dividend, while its price drops by the dividend amount.
+    pub fn is_ex_dividend_near(&self, sec_code: &str, today: NaiveDate, days: i64) -> bool {
+        self.next_ex_dividend(sec_code, today)
+            .is_some_and(|date| (date - today).num_days() <= days)
+    }
+
+
+    /// Adjusts the candles of the instrument for splits, so the prices before a split are
+    /// comparable with the prices after it. The candles must be ordered by time.
+    pub fn adjust_for_splits(&self, sec_code: &str, candles: &mut [DataForEma]) {
+        let Some(actions) = self.actions.get(sec_code) else {
+            return;
+        };
+
+        for action in actions {
+            let CorporateActionKind::Split { ratio } = action.kind else {
+                continue;
+            };
+
+            for candle in candles.iter_mut().filter(|candle| is_before(candle.period_start, action.date)) {
+                candle.open /= ratio;
+                candle.high /= ratio;
+                candle.low /= ratio;
+                candle.close /= ratio;
+                candle.volume *= ratio;
+            }
+        }
+    }
+}
+
+
+fn is_before(time: DateTime<Utc>, date: NaiveDate) -> bool {
+    time.date_naive() < date
+}
@@ -0,0 +1,75 @@
+//! Conversion between UTF-8 and Windows-1251, the encoding of all strings
+//! of the library Trans2QUIK.dll: result and error messages, replies and
+//! the comments of transactions.
+
+
+/// Characters of the bytes 0x80..=0xBF. The bytes 0xC0..=0xFF are the Cyrillic letters `А`..=`я`.
+const UPPER_HALF: [char; 64] = [
+    '\u{0402}', '\u{0403}', '\u{201A}', '\u{0453}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{20AC}', '\u{2030}', '\u{0409}', '\u{2039}', '\u{040A}', '\u{040C}', '\u{040B}', '\u{040F}',
+    '\u{0452}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{0098}', '\u{2122}', '\u{0459}', '\u{203A}', '\u{045A}', '\u{045C}', '\u{045B}', '\u{045F}',
+    '\u{00A0}', '\u{040E}', '\u{045E}', '\u{0408}', '\u{00A4}', '\u{0490}', '\u{00A6}', '\u{00A7}',
+    '\u{0401}', '\u{00A9}', '\u{0404}', '\u{00AB}', '\u{00AC}', '\u{00AD}', '\u{00AE}', '\u{0407}',
+    '\u{00B0}', '\u{00B1}', '\u{0406}', '\u{0456}', '\u{0491}', '\u{00B5}', '\u{00B6}', '\u{00B7}',
+    '\u{0451}', '\u{2116}', '\u{0454}', '\u{00BB}', '\u{0458}', '\u{0405}', '\u{0455}', '\u{0457}',
+];
+
+
+/// Decodes a Windows-1251 string. Every byte has a character, so decoding never fails.
+pub fn decode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            0x00..=0x7F => byte as char,
+            0x80..=0xBF => UPPER_HALF[(byte - 0x80) as usize],
+            0xC0..=0xFF => char::from_u32(0x0410 + (byte - 0xC0) as u32).unwrap_or(char::REPLACEMENT_CHARACTER),
+        })
+        .collect()
+}
+
+
+/// Encodes a string in Windows-1251. Fails on characters which have no Windows-1251 code.
+pub fn encode(value: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    value
+        .chars()
+        .map(|c| match c {
+            '\u{0000}'..='\u{007F}' => Ok(c as u8),
+            '\u{0410}'..='\u{044F}' => Ok((c as u32 - 0x0410 + 0xC0) as u8),
+            _ => UPPER_HALF
+                .iter()
+                .position(|&upper| upper == c)
+                .map(|position| 0x80 + position as u8)
+                .ok_or_else(|| format!("character '{}' cannot be encoded in Windows-1251: {}", c, value).into()),
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn cyrillic_is_encoded_and_decoded_back() {
+        let message = "Заявка №42 снята, ЁЖ ёж";
+        let bytes = encode(message).unwrap();
+        assert_eq!(&bytes[..6], &[0xC7, 0xE0, 0xFF, 0xE2, 0xEA, 0xE0]);
+        assert!(bytes.contains(&0xB9));
+        assert_eq!(decode(&bytes), message);
+    }
+
+
+    #[test]
+    fn every_byte_is_decoded_to_a_character_encoded_back_to_it() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(encode(&decode(&bytes)).unwrap(), bytes);
+    }
+
+
+    #[test]
+    fn character_without_a_code_is_not_encoded() {
+        assert!(encode("SBER \u{00E9}").is_err());
+    }
+}
@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use serde_json::{json, Value};
+use ta::indicators::ExponentialMovingAverage;
+use ta::{Next, Reset};
+use crate::config::InstrumentSettings;
+use crate::latency::{LatencyStats, Stage};
+use crate::psql::DataForEma;
+use crate::strategy::{Action, Decision, Strategy};
+
+
+/// Periods of the fast and the slow EMA of a crossover, written as `9/21`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EmaPair {
+    pub fast: usize,
+    pub slow: usize,
+}
+
+
+impl EmaPair {
+    /// Parses a pair from the `FAST/SLOW` format, e.g. `50/200`.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (fast, slow) = value
+            .split_once('/')
+            .ok_or_else(|| format!("invalid EMA pair '{}', expected FAST/SLOW", value))?;
+        let fast: usize = fast.trim().parse().map_err(|_| format!("invalid fast period in '{}'", value))?;
+        let slow: usize = slow.trim().parse().map_err(|_| format!("invalid slow period in '{}'", value))?;
+
+        if fast == 0 || fast >= slow {
+            return Err(format!("EMA pair '{}' must have 0 < fast < slow", value).into());
+        }
+
+        Ok(EmaPair { fast, slow })
+    }
+}
+
+
+impl fmt::Display for EmaPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.fast, self.slow)
+    }
+}
+
+
+/// Direction in which the fast EMA crossed the slow EMA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossover {
+    /// The fast EMA crossed above the slow EMA.
+    Bullish,
+    /// The fast EMA crossed below the slow EMA.
+    Bearish,
+}
+
+
+impl Crossover {
+    pub fn action(&self) -> Action {
+        match self {
+            Crossover::Bullish => Action::Buy,
+            Crossover::Bearish => Action::Sell,
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Crossover::Bullish => "bullish",
+            Crossover::Bearish => "bearish",
+        }
+    }
+
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "bullish" => Some(Crossover::Bullish),
+            "bearish" => Some(Crossover::Bearish),
+            _ => None,
+        }
+    }
+}
+
+
+/// Additional conditions a crossover must meet before it is signalled. A cross which doesn't
+/// meet them yet stays pending while the fast EMA keeps its new side, and is signalled on the
+/// first candle which meets them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrossoverFilters {
+    /// The close must be above both EMAs for a bullish cross and below both for a bearish one.
+    pub price_beyond_emas: bool,
+    /// Minimum change of the slow EMA on the candle in percent of it, in the direction of the
+    /// cross. 0 accepts a flat slow EMA, `None` disables the check.
+    pub min_slope: Option<f64>,
+    /// Closed candles the fast EMA must stay on its new side, the candle of the cross included.
+    pub persist_candles: u32,
+}
+
+
+impl Default for CrossoverFilters {
+    fn default() -> Self {
+        CrossoverFilters {
+            price_beyond_emas: false,
+            min_slope: None,
+            persist_candles: 1,
+        }
+    }
+}
+
+
+/// State of a crossover: remembers on which side of the slow EMA the fast EMA was,
+/// so a signal fires once per cross instead of on every candle.
+#[derive(Debug, Clone, Default)]
+pub struct CrossoverSignal {
+    /// `Some(true)` if the fast EMA was above the slow EMA on the previous candle.
+    above: Option<bool>,
+    /// Band around the slow EMA in percent of it, inside which the side doesn't change.
+    hysteresis: f64,
+    filters: CrossoverFilters,
+    /// Candles the fast EMA has stayed on its side, the current one included.
+    candles_in_state: u32,
+    /// Cross which hasn't met the filters yet.
+    pending: Option<Crossover>,
+    /// Slow EMA of the previous candle, the slope is measured from it.
+    slow: Option<f64>,
+    last_signal: Option<Crossover>,
+}
+
+
+impl CrossoverSignal {
+    pub fn with_hysteresis(mut self, percent: f64) -> Self {
+        self.hysteresis = percent.max(0.0);
+        self
+    }
+
+
+    pub fn with_filters(mut self, filters: CrossoverFilters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+
+    /// Returns the crossover signalled on the candle with the close price `close`, if any.
+    /// Equal values and values within the hysteresis band keep the previous side. Without
+    /// the filters a cross is signalled on its own candle.
+    pub fn next(&mut self, fast: f64, slow: f64, close: f64) -> Option<Crossover> {
+        let band = slow.abs() * self.hysteresis / 100.0;
+        let previous_slow = self.slow.replace(slow);
+        let above = if fast == slow || (fast - slow).abs() <= band && self.above.is_some() {
+            self.above?
+        } else {
+            fast > slow
+        };
+
+        let previous = self.above.replace(above);
+        self.candles_in_state = if previous == Some(above) { self.candles_in_state.saturating_add(1) } else { 1 };
+        match previous {
+            Some(false) if above => self.pending = Some(Crossover::Bullish),
+            Some(true) if !above => self.pending = Some(Crossover::Bearish),
+            _ => {}
+        }
+
+        let crossover = self.pending.filter(|crossover| self.accepts(*crossover, fast, slow, previous_slow, close))?;
+        self.pending = None;
+        self.last_signal = Some(crossover);
+        Some(crossover)
+    }
+
+
+    fn accepts(&self, crossover: Crossover, fast: f64, slow: f64, previous_slow: Option<f64>, close: f64) -> bool {
+        if self.candles_in_state < self.filters.persist_candles {
+            return false;
+        }
+        let bullish = crossover == Crossover::Bullish;
+        if self.filters.price_beyond_emas {
+            let beyond = if bullish { close > fast.max(slow) } else { close < fast.min(slow) };
+            if !beyond {
+                return false;
+            }
+        }
+        if let Some(min_slope) = self.filters.min_slope {
+            // The slope of the first candle is unknown, the cross waits for the next one
+            let Some(previous_slow) = previous_slow.filter(|previous| *previous != 0.0) else {
+                return false;
+            };
+            let slope = (slow - previous_slow) / previous_slow.abs() * 100.0;
+            if (if bullish { slope } else { -slope }) < min_slope {
+                return false;
+            }
+        }
+        true
+    }
+
+
+    /// Direction of the trend: the side of the slow EMA the fast EMA is on.
+    pub fn trend(&self) -> Option<Crossover> {
+        self.above.map(|above| if above { Crossover::Bullish } else { Crossover::Bearish })
+    }
+
+
+    /// State of the crossover for `bot_state`, without the hysteresis and the filters which
+    /// come from the settings.
+    pub fn state(&self) -> Value {
+        json!({
+            "above": self.above,
+            "candles_in_state": self.candles_in_state,
+            "pending": self.pending.map(|signal| signal.as_str()),
+            "slow": self.slow,
+            "last_signal": self.last_signal.map(|signal| signal.as_str()),
+        })
+    }
+
+
+    /// Restores the state saved by `state`, keeping the hysteresis and the filters.
+    /// A state saved before the filters restores without a pending cross.
+    pub fn restore(&mut self, state: &Value) -> Option<()> {
+        self.above = state.get("above")?.as_bool();
+        self.candles_in_state = state.get("candles_in_state")?.as_u64()? as u32;
+        self.pending = state.get("pending").and_then(Value::as_str).and_then(Crossover::parse);
+        self.slow = state.get("slow").and_then(Value::as_f64);
+        self.last_signal = state.get("last_signal")?.as_str().and_then(Crossover::parse);
+        Some(())
+    }
+}
+
+
+/// State of an EMA pair with the last values of its EMAs for `bot_state`.
+fn ema_state(pair: EmaPair, values: Option<(f64, f64)>, signal: &CrossoverSignal) -> Value {
+    json!({
+        "pair": pair.to_string(),
+        "fast": values.map(|(fast, _)| fast),
+        "slow": values.map(|(_, slow)| slow),
+        "signal": signal.state(),
+    })
+}
+
+
+/// Restores the EMAs and the signal of a pair from `ema_state`. A fresh EMA takes its first
+/// input as its value, so feeding it the saved value continues the series.
+fn restore_ema(
+    state: &Value,
+    fast: &mut ExponentialMovingAverage,
+    slow: &mut ExponentialMovingAverage,
+    values: &mut Option<(f64, f64)>,
+    signal: &mut CrossoverSignal,
+) -> Option<()> {
+    if let (Some(saved_fast), Some(saved_slow)) = (state.get("fast")?.as_f64(), state.get("slow")?.as_f64()) {
+        fast.reset();
+        slow.reset();
+        *values = Some((fast.next(saved_fast), slow.next(saved_slow)));
+    }
+    signal.restore(state.get("signal")?)
+}
+
+
+/// Crossover of an EMA pair tagged with the pair that produced it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairSignal {
+    pub pair: EmaPair,
+    pub crossover: Crossover,
+}
+
+
+impl PairSignal {
+    /// Reason code of the signal in the evaluation audit, e.g. `ema_cross_9/21`.
+    pub fn reason_code(&self) -> String {
+        format!("ema_cross_{}", self.pair)
+    }
+}
+
+
+struct PairState {
+    pair: EmaPair,
+    fast: ExponentialMovingAverage,
+    slow: ExponentialMovingAverage,
+    /// Last values of the fast and the slow EMA.
+    values: Option<(f64, f64)>,
+    signal: CrossoverSignal,
+}
+
+
+/// Several EMA crossovers of one instrument evaluated on the same candles, e.g. 9/21 and 50/200,
+/// each with its own `CrossoverSignal`.
+///
+/// With the trend filter the signals of the faster pairs are only emitted in the direction
+/// of the trend of the slowest pair; the slowest pair itself is never filtered.
+///
+/// # Example of use
+/// ```ignore
+/// let mut signals = MultiPairSignal::new(&settings.ema_pairs, settings.ema_trend_filter)?;
+/// for signal in signals.next(candle.close) {
+///     info!("{}: {:?} by {}", settings.sec_code, signal.crossover, signal.pair);
+/// }
+/// ```
+pub struct MultiPairSignal {
+    pairs: Vec<PairState>,
+    trend_filter: bool,
+    /// Histograms the EMA calculation and the signal update are timed into.
+    latency: LatencyStats,
+}
+
+
+impl MultiPairSignal {
+    pub fn new(pairs: &[EmaPair], trend_filter: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        if pairs.is_empty() {
+            return Err("at least one EMA pair is required".into());
+        }
+
+        let mut pairs = pairs
+            .iter()
+            .map(|pair| {
+                Ok(PairState {
+                    pair: *pair,
+                    fast: ExponentialMovingAverage::new(pair.fast)?,
+                    slow: ExponentialMovingAverage::new(pair.slow)?,
+                    values: None,
+                    signal: CrossoverSignal::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        // The slowest pair goes last, it defines the trend
+        pairs.sort_by_key(|state| (state.pair.slow, state.pair.fast));
+
+        Ok(MultiPairSignal { pairs, trend_filter, latency: LatencyStats::default() })
+    }
+
+
+    /// Period of the slowest EMA, the candles the signals need to warm up.
+    pub fn warm_up_candles(&self) -> usize {
+        self.pairs.iter().map(|state| state.pair.slow).max().unwrap_or_default()
+    }
+
+
+    /// Times the EMA calculation and the signal update into the shared histograms.
+    pub fn with_latency(mut self, latency: LatencyStats) -> Self {
+        self.latency = latency;
+        self
+    }
+
+
+    /// Sets the hysteresis of the crossovers of every pair, in percent of the slow EMA.
+    pub fn with_hysteresis(mut self, percent: f64) -> Self {
+        for state in &mut self.pairs {
+            state.signal = state.signal.clone().with_hysteresis(percent);
+        }
+        self
+    }
+
+
+    /// Sets the conditions the crossovers of every pair must meet to be signalled.
+    pub fn with_filters(mut self, filters: CrossoverFilters) -> Self {
+        for state in &mut self.pairs {
+            state.signal = state.signal.clone().with_filters(filters);
+        }
+        self
+    }
+
+
+    /// Trend of the slowest pair.
+    pub fn trend(&self) -> Option<Crossover> {
+        self.pairs.last().and_then(|state| state.signal.trend())
+    }
+
+
+    /// Feeds the close price of a candle to every pair and returns the crossovers on it.
+    pub fn next(&mut self, close: f64) -> Vec<PairSignal> {
+        self.latency.time(Stage::EmaCalc, || {
+            for state in &mut self.pairs {
+                state.values = Some((state.fast.next(close), state.slow.next(close)));
+            }
+        });
+        let mut signals: Vec<PairSignal> = self.latency.time(Stage::SignalUpdate, || {
+            self.pairs
+                .iter_mut()
+                .filter_map(|state| {
+                    let (fast, slow) = state.values?;
+                    state
+                        .signal
+                        .next(fast, slow, close)
+                        .map(|crossover| PairSignal { pair: state.pair, crossover })
+                })
+                .collect()
+        });
+
+        if self.trend_filter && self.pairs.len() > 1 {
+            let trend_pair = self.pairs[self.pairs.len() - 1].pair;
+            let trend = self.trend();
+            signals.retain(|signal| signal.pair == trend_pair || Some(signal.crossover) == trend);
+        }
+
+        signals
+    }
+
+
+    /// State of every pair for `bot_state`.
+    pub fn state(&self) -> Value {
+        Value::Array(self.pairs.iter().map(|state| ema_state(state.pair, state.values, &state.signal)).collect())
+    }
+
+
+    /// Restores the pairs saved by `state`. The pairs not in the saved state, e.g. added
+    /// to the settings since, start fresh.
+    pub fn restore(&mut self, saved: &Value) -> Option<()> {
+        for saved in saved.as_array()? {
+            let pair = EmaPair::parse(saved.get("pair")?.as_str()?).ok()?;
+            if let Some(state) = self.pairs.iter_mut().find(|state| state.pair == pair) {
+                restore_ema(saved, &mut state.fast, &mut state.slow, &mut state.values, &mut state.signal)?;
+            }
+        }
+        Some(())
+    }
+
+
+    /// Like `next`, but only the crossovers accepted by the confirmation predicate are returned,
+    /// e.g. the ones in the direction of the trend of a higher timeframe.
+    pub fn next_confirmed(&mut self, close: f64, confirm: impl Fn(Crossover) -> bool) -> Vec<PairSignal> {
+        let mut signals = self.next(close);
+        signals.retain(|signal| confirm(signal.crossover));
+        signals
+    }
+}
+
+
+/// Trend of an EMA pair on a higher timeframe confirming the crossovers of a lower one:
+/// e.g. a 5-minute 9/21 cross up is only taken while the 1-hour 9/21 fast EMA is above the slow one.
+///
+/// # Example of use
+/// ```ignore
+/// let mut confirmation = TimeframeConfirmation::new(Duration::from_secs(3600), EmaPair { fast: 9, slow: 21 })?;
+/// confirmation.next(hourly_candle.close);
+/// let signals = signals.next_confirmed(candle.close, |crossover| confirmation.confirms(crossover));
+/// ```
+pub struct TimeframeConfirmation {
+    timeframe: Duration,
+    pair: EmaPair,
+    fast: ExponentialMovingAverage,
+    slow: ExponentialMovingAverage,
+    values: Option<(f64, f64)>,
+    signal: CrossoverSignal,
+    latency: LatencyStats,
+}
+
+
+impl TimeframeConfirmation {
+    pub fn new(timeframe: Duration, pair: EmaPair) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(TimeframeConfirmation {
+            timeframe,
+            pair,
+            fast: ExponentialMovingAverage::new(pair.fast)?,
+            slow: ExponentialMovingAverage::new(pair.slow)?,
+            values: None,
+            signal: CrossoverSignal::default(),
+            latency: LatencyStats::default(),
+        })
+    }
+
+
+    /// Times the EMA calculation and the signal update into the shared histograms.
+    pub fn with_latency(mut self, latency: LatencyStats) -> Self {
+        self.latency = latency;
+        self
+    }
+
+
+    pub fn timeframe(&self) -> Duration {
+        self.timeframe
+    }
+
+
+    /// Feeds the close price of a candle of the higher timeframe.
+    pub fn next(&mut self, close: f64) {
+        let (fast, slow) = self.latency.time(Stage::EmaCalc, || (self.fast.next(close), self.slow.next(close)));
+        self.values = Some((fast, slow));
+        self.latency.time(Stage::SignalUpdate, || self.signal.next(fast, slow, close));
+    }
+
+
+    /// The trend of the higher timeframe is in the direction of the crossover.
+    /// Nothing is confirmed before the first candle of the higher timeframe.
+    pub fn confirms(&self, crossover: Crossover) -> bool {
+        self.signal.trend() == Some(crossover)
+    }
+
+
+    pub fn state(&self) -> Value {
+        ema_state(self.pair, self.values, &self.signal)
+    }
+
+
+    /// Restores the state saved by `state` if it is of the same pair.
+    pub fn restore(&mut self, saved: &Value) -> Option<()> {
+        if EmaPair::parse(saved.get("pair")?.as_str()?).ok()? != self.pair {
+            return Some(());
+        }
+        restore_ema(saved, &mut self.fast, &mut self.slow, &mut self.values, &mut self.signal)
+    }
+}
+
+
+/// EMA crossover strategy: the `MultiPairSignal` of every enabled instrument of the watchlist
+/// with its `ema_pairs`, `ema_trend_filter`, `ema_hysteresis` and crossover filters, evaluated
+/// on the close of the candles of its `timeframe_minutes`. With `confirmation_timeframe_minutes` the crossovers
+/// are only taken in the direction of the trend of the higher timeframe.
+pub struct EmaCross {
+    signals: HashMap<String, MultiPairSignal>,
+    timeframes: HashMap<String, Duration>,
+    confirmations: HashMap<String, TimeframeConfirmation>,
+    latency: LatencyStats,
+}
+
+
+impl EmaCross {
+    pub const NAME: &'static str = "ema_cross";
+
+
+    /// Builds the signals of the instruments, timed into `latency`.
+    pub fn new(settings: &[InstrumentSettings], latency: LatencyStats) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut strategy = EmaCross {
+            signals: HashMap::new(),
+            timeframes: HashMap::new(),
+            confirmations: HashMap::new(),
+            latency,
+        };
+        for settings in settings {
+            strategy.insert(settings)?;
+        }
+
+        Ok(strategy)
+    }
+
+
+    /// Starts the signals of an enabled instrument from scratch.
+    fn insert(&mut self, settings: &InstrumentSettings) -> Result<(), Box<dyn std::error::Error>> {
+        if !settings.enabled {
+            return Ok(());
+        }
+        let sec_code = &settings.sec_code;
+        let signal = MultiPairSignal::new(&settings.ema_pairs, settings.ema_trend_filter)
+            .map_err(|e| format!("{}: {}", sec_code, e))?
+            .with_hysteresis(settings.ema_hysteresis)
+            .with_filters(settings.crossover_filters())
+            .with_latency(self.latency.clone());
+        let confirmation = settings
+            .confirmation()
+            .map(|(timeframe, pair)| TimeframeConfirmation::new(timeframe, pair).map(|confirmation| confirmation.with_latency(self.latency.clone())))
+            .transpose()?;
+
+        self.signals.insert(sec_code.clone(), signal);
+        self.timeframes.insert(sec_code.clone(), settings.timeframe());
+        match confirmation {
+            Some(confirmation) => self.confirmations.insert(sec_code.clone(), confirmation),
+            None => self.confirmations.remove(sec_code),
+        };
+
+        Ok(())
+    }
+}
+
+
+impl Strategy for EmaCross {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+
+    fn wants_instruments(&self) -> Vec<String> {
+        let mut instruments: Vec<String> = self.signals.keys().cloned().collect();
+        instruments.sort();
+        instruments
+    }
+
+
+    fn timeframe(&self, sec_code: &str) -> Option<Duration> {
+        self.timeframes.get(sec_code).copied()
+    }
+
+
+    fn warm_up_candles(&self, sec_code: &str) -> usize {
+        self.signals.get(sec_code).map_or(0, MultiPairSignal::warm_up_candles)
+    }
+
+
+    fn confirmation_timeframes(&self, sec_code: &str) -> Vec<Duration> {
+        self.confirmations.get(sec_code).map(TimeframeConfirmation::timeframe).into_iter().collect()
+    }
+
+
+    fn on_confirmation_candle(&mut self, sec_code: &str, timeframe: Duration, candle: &DataForEma) {
+        if let Some(confirmation) = self.confirmations.get_mut(sec_code).filter(|confirmation| confirmation.timeframe() == timeframe) {
+            confirmation.next(candle.close);
+        }
+    }
+
+
+    fn on_candle(&mut self, sec_code: &str, candle: &DataForEma) -> Vec<Decision> {
+        let Some(signals) = self.signals.get_mut(sec_code) else {
+            return Vec::new();
+        };
+
+        let signals = match self.confirmations.get(sec_code) {
+            Some(confirmation) => signals.next_confirmed(candle.close, |crossover| confirmation.confirms(crossover)),
+            None => signals.next(candle.close),
+        };
+
+        signals
+            .into_iter()
+            .map(|signal| Decision {
+                sec_code: sec_code.to_string(),
+                action: signal.crossover.action(),
+                reason_code: signal.reason_code(),
+            })
+            .collect()
+    }
+
+
+    fn add_instrument(&mut self, settings: &InstrumentSettings) -> Result<(), Box<dyn std::error::Error>> {
+        self.insert(settings)
+    }
+
+
+    fn remove_instrument(&mut self, sec_code: &str) {
+        self.signals.remove(sec_code);
+        self.timeframes.remove(sec_code);
+        self.confirmations.remove(sec_code);
+    }
+
+
+    fn state(&self, sec_code: &str) -> Option<Value> {
+        Some(json!({
+            "pairs": self.signals.get(sec_code)?.state(),
+            "confirmation": self.confirmations.get(sec_code).map(TimeframeConfirmation::state),
+        }))
+    }
+
+
+    fn restore(&mut self, sec_code: &str, state: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let malformed = || format!("{}: malformed state of the EMA crossovers", sec_code);
+        if let Some(signals) = self.signals.get_mut(sec_code) {
+            signals.restore(state.get("pairs").ok_or_else(malformed)?).ok_or_else(malformed)?;
+        }
+        if let (Some(confirmation), Some(saved)) = (self.confirmations.get_mut(sec_code), state.get("confirmation").filter(|saved| !saved.is_null())) {
+            confirmation.restore(saved).ok_or_else(malformed)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn cross_is_signalled_once_on_its_candle() {
+        let mut signal = CrossoverSignal::default();
+
+        assert_eq!(signal.next(99.0, 100.0, 99.0), None);
+        assert_eq!(signal.next(101.0, 100.0, 101.0), Some(Crossover::Bullish));
+        assert_eq!(signal.next(102.0, 100.0, 102.0), None);
+        assert_eq!(signal.next(98.0, 100.0, 98.0), Some(Crossover::Bearish));
+    }
+
+
+    #[test]
+    fn cross_within_the_hysteresis_keeps_the_side() {
+        let mut signal = CrossoverSignal::default().with_hysteresis(1.0);
+
+        assert_eq!(signal.next(98.0, 100.0, 98.0), None);
+        assert_eq!(signal.next(100.5, 100.0, 100.5), None);
+        assert_eq!(signal.next(101.5, 100.0, 101.5), Some(Crossover::Bullish));
+    }
+
+
+    #[test]
+    fn cross_waits_for_the_persist_candles() {
+        let filters = CrossoverFilters { persist_candles: 2, ..Default::default() };
+        let mut signal = CrossoverSignal::default().with_filters(filters);
+
+        assert_eq!(signal.next(99.0, 100.0, 99.0), None);
+        assert_eq!(signal.next(101.0, 100.0, 101.0), None);
+        assert_eq!(signal.next(101.0, 100.0, 101.0), Some(Crossover::Bullish));
+        // A cross back before the persist candles cancels the pending cross
+        assert_eq!(signal.next(99.0, 100.0, 99.0), None);
+        assert_eq!(signal.next(101.0, 100.0, 101.0), None);
+        assert_eq!(signal.next(101.0, 100.0, 101.0), Some(Crossover::Bullish));
+    }
+
+
+    #[test]
+    fn cross_waits_for_the_price_beyond_the_emas() {
+        let filters = CrossoverFilters { price_beyond_emas: true, ..Default::default() };
+        let mut signal = CrossoverSignal::default().with_filters(filters);
+
+        assert_eq!(signal.next(99.0, 100.0, 99.0), None);
+        // The close is between the EMAs
+        assert_eq!(signal.next(101.0, 100.0, 100.5), None);
+        assert_eq!(signal.next(101.0, 100.0, 102.0), Some(Crossover::Bullish));
+        assert_eq!(signal.next(99.0, 100.0, 99.5), None);
+        assert_eq!(signal.next(99.0, 100.0, 98.0), Some(Crossover::Bearish));
+    }
+
+
+    #[test]
+    fn cross_waits_for_the_slope_of_the_slow_ema() {
+        let filters = CrossoverFilters { min_slope: Some(0.1), ..Default::default() };
+        let mut signal = CrossoverSignal::default().with_filters(filters);
+
+        // The slope of the first candle is unknown
+        assert_eq!(signal.next(99.0, 100.0, 99.0), None);
+        // The slow EMA rises by 0.05%
+        assert_eq!(signal.next(101.0, 100.05, 101.0), None);
+        // And then by 0.2%
+        assert_eq!(signal.next(101.0, 100.25, 101.0), Some(Crossover::Bullish));
+    }
+
+
+    #[test]
+    fn state_is_restored_with_the_pending_cross() {
+        let filters = CrossoverFilters { persist_candles: 2, ..Default::default() };
+        let mut signal = CrossoverSignal::default().with_filters(filters);
+        signal.next(99.0, 100.0, 99.0);
+        signal.next(101.0, 100.0, 101.0);
+
+        let mut restored = CrossoverSignal::default().with_filters(filters);
+        restored.restore(&signal.state()).unwrap();
+        assert_eq!(restored.trend(), Some(Crossover::Bullish));
+        assert_eq!(restored.next(101.0, 100.0, 101.0), Some(Crossover::Bullish));
+    }
+}
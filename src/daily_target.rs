@@ -0,0 +1,169 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use crate::clock::{Clock, SystemClock};
+use crate::config::InstrumentSettings;
+use crate::portfolio::InstrumentPosition;
+use crate::psql::Evaluation;
+use crate::strategy::Action;
+
+
+/// Instrument which reached its `daily_profit_target` in the current session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetReached {
+    pub sec_code: String,
+    /// Profit of the day net of the commissions.
+    pub pnl: f64,
+    pub target: f64,
+}
+
+
+impl TargetReached {
+    pub const REASON_CODE: &'static str = "daily_profit_target";
+
+
+    /// Record of a skipped entry in the evaluation audit.
+    pub fn evaluation(&self, candle_time: DateTime<Utc>) -> Evaluation {
+        Evaluation {
+            instrument_code: self.sec_code.clone(),
+            candle_time,
+            indicators: vec![
+                ("daily_pnl".to_string(), self.pnl),
+                ("daily_profit_target".to_string(), self.target),
+            ],
+            decision: "skip".to_string(),
+            reason_code: Self::REASON_CODE.to_string(),
+        }
+    }
+}
+
+
+impl fmt::Display for TargetReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: profit of the day {:.2} reached the target {:.2}, no new entries until the next session",
+            self.sec_code, self.pnl, self.target
+        )
+    }
+}
+
+
+/// Daily profit target of the instruments: once the realized profit of an instrument net of
+/// the commissions reaches its `daily_profit_target` in a session, new entries in it are
+/// skipped until the next exchange day, so a name which already performed doesn't give the
+/// profit back. Exits and the protective orders of the open position are not affected.
+///
+/// The profit of the day is counted from the positions seen at the start of the exchange day.
+///
+/// # Example of use
+/// ```ignore
+/// let mut target = DailyProfitTarget::new(&config.instrument_settings()?, config.exchange_timezone, &portfolio.open_positions());
+/// if let Some(reached) = portfolio.position(&decision.sec_code).and_then(|position| target.on_position(position)) {
+///     warn!("{}", reached);
+/// }
+///
+/// if let Err(reached) = target.check(&decision.sec_code, decision.action) {
+///     info!("Entry is skipped, {}", reached);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DailyProfitTarget {
+    targets: HashMap<String, f64>,
+    timezone: FixedOffset,
+    /// Exchange day the profit is counted for.
+    day: Option<NaiveDate>,
+    /// Net realized profit of the instruments at the start of the day.
+    baseline: HashMap<String, f64>,
+    /// Net realized profit of the instruments after their last fill.
+    net: HashMap<String, f64>,
+    reached: HashSet<String>,
+    clock: Arc<dyn Clock>,
+}
+
+
+impl DailyProfitTarget {
+    pub fn new(settings: &[InstrumentSettings], timezone: FixedOffset, positions: &[InstrumentPosition]) -> Self {
+        let net: HashMap<String, f64> = positions
+            .iter()
+            .map(|position| (position.sec_code.clone(), position.realized_pnl - position.commission))
+            .collect();
+
+        DailyProfitTarget {
+            targets: settings
+                .iter()
+                .filter_map(|settings| settings.daily_profit_target.map(|target| (settings.sec_code.clone(), target)))
+                .collect(),
+            timezone,
+            day: None,
+            baseline: net.clone(),
+            net,
+            reached: HashSet::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+
+    /// Starts counting from zero at the start of a new exchange day.
+    fn roll(&mut self) {
+        let today = self.clock.now().with_timezone(&self.timezone).date_naive();
+        if self.day == Some(today) {
+            return;
+        }
+
+        if self.day.is_some() {
+            self.baseline = self.net.clone();
+            self.reached.clear();
+        }
+        self.day = Some(today);
+    }
+
+
+    /// Net realized profit of the instrument in the current exchange day.
+    pub fn day_pnl(&mut self, sec_code: &str) -> f64 {
+        self.roll();
+        self.net.get(sec_code).copied().unwrap_or(0.0) - self.baseline.get(sec_code).copied().unwrap_or(0.0)
+    }
+
+
+    /// Updates the profit of the day with the position after a fill. Returns the target
+    /// once when the instrument reaches it.
+    pub fn on_position(&mut self, position: &InstrumentPosition) -> Option<TargetReached> {
+        self.roll();
+        self.net.insert(position.sec_code.clone(), position.realized_pnl - position.commission);
+
+        let target = self.targets.get(&position.sec_code).copied()?;
+        let pnl = self.day_pnl(&position.sec_code);
+        if pnl < target || !self.reached.insert(position.sec_code.clone()) {
+            return None;
+        }
+
+        Some(TargetReached {
+            sec_code: position.sec_code.clone(),
+            pnl,
+            target,
+        })
+    }
+
+
+    /// Allows the action or returns the reached target when it is a new entry.
+    pub fn check(&mut self, sec_code: &str, action: Action) -> Result<(), TargetReached> {
+        self.roll();
+        if !matches!(action, Action::Buy | Action::Sell) || !self.reached.contains(sec_code) {
+            return Ok(());
+        }
+
+        Err(TargetReached {
+            sec_code: sec_code.to_string(),
+            pnl: self.day_pnl(sec_code),
+            target: self.targets.get(sec_code).copied().unwrap_or_default(),
+        })
+    }
+}
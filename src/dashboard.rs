@@ -0,0 +1,806 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+use crate::clock::{Clock, SystemClock};
+use crate::config::RunMode;
+use crate::domain::{Position, Signal, ToJson};
+use crate::portfolio::MoneyFlows;
+use crate::heartbeat;
+use crate::instrument::Readiness;
+use crate::psql::{Db, Heartbeat, Incident, Severity};
+use crate::latency::{LatencyHistogram, Stage};
+use crate::metrics::Metrics;
+use crate::retry::RetryStats;
+use crate::margin::MarginForecast;
+use crate::risk::RiskLimit;
+use crate::task_health::TaskHealth;
+use crate::throttle::ThrottleStats;
+use quik_rs::quik::{ConnectionHealth, FunctionStats};
+
+
+/// Number of recent signals shown on the page.
+const MAX_SIGNALS: usize = 20;
+
+/// Number of equity points kept for the sparkline.
+const MAX_EQUITY_POINTS: usize = 500;
+
+/// Maximum size of a request head, larger requests are refused.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Time to receive a request head, a slower client is disconnected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept, e.g. when the descriptors of the process run out.
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(100);
+
+/// Incidents of this period before the refresh are shown on the page.
+const INCIDENT_WINDOW: chrono::Duration = chrono::Duration::hours(24);
+
+/// Heartbeats of this period before the refresh are charted on the page.
+const HEARTBEAT_WINDOW: chrono::Duration = chrono::Duration::hours(24);
+
+
+/// State of the bot shown by the dashboard.
+#[derive(Debug, Clone)]
+pub struct DashboardSnapshot {
+    pub mode: RunMode,
+    pub connection: ConnectionHealth,
+    pub positions: Vec<Position>,
+    /// Recent signals, the newest first.
+    pub signals: Vec<Signal>,
+    pub equity: Vec<(DateTime<Utc>, f64)>,
+    /// Gross profit, commissions and slippage since the start of the trading.
+    pub money_flows: MoneyFlows,
+    /// Calls of the Trans2QUIK.dll functions in the last statistics period.
+    pub calls: BTreeMap<&'static str, FunctionStats>,
+    /// Retries of the database connection, the reconnection to QUIK and the notifications.
+    pub retries: BTreeMap<&'static str, RetryStats>,
+    /// Latency of the stages of the trading loop since the start.
+    pub latency: BTreeMap<Stage, LatencyHistogram>,
+    /// Configured risk limits with their current utilization.
+    pub risk_limits: Vec<RiskLimit>,
+    /// End-of-day margin forecast by account.
+    pub margin: Vec<(String, MarginForecast)>,
+    /// Health of the evaluation of every instrument by the trading loop.
+    pub tasks: BTreeMap<String, TaskHealth>,
+    /// Ready/warm-up matrix of the instruments of the watchlist.
+    pub readiness: Vec<Readiness>,
+    /// Queue of the transaction throttle, read when the state is shown.
+    pub throttle: Option<Arc<ThrottleStats>>,
+    /// Incidents of the last day, the newest first.
+    pub incidents: Vec<Incident>,
+    /// Heartbeats of the last day, the oldest first.
+    pub heartbeats: Vec<Heartbeat>,
+    pub updated_at: DateTime<Utc>,
+}
+
+
+/// Read-only web dashboard with the live status, the positions, the recent signals,
+/// an equity sparkline, the utilization of the risk limits, the incidents and the uptime of the
+/// last day, for checking the bot from a phone browser.
+///
+/// The trading loop updates the state, the embedded HTTP server only reads it:
+/// `/` returns the page, `/api/status` returns the same data as JSON.
+///
+/// # Example of use
+/// ```ignore
+/// let dashboard = Dashboard::new(config.mode)
+///     .with_clock(clock.clone())
+///     .with_throttle(gateway.throttle_stats())
+///     .with_metrics(metrics.clone());
+/// // Only on the local host, `0.0.0.0:8080` serves the other hosts of the network as well
+/// tokio::spawn(dashboard.clone().serve("127.0.0.1:8080".to_string()));
+/// dashboard.set_connection(health.borrow().clone());
+/// dashboard.push_signal(Signal::from_decision(&decision, clock.now()));
+/// dashboard.push_equity(clock.now(), equity);
+/// dashboard.set_money_flows(position.money_flows());
+/// dashboard.set_risk_limits(vec![risk::order_rate_limit(&throttle.stats(), config.max_transactions_per_second)]);
+/// ```
+#[derive(Clone)]
+pub struct Dashboard {
+    snapshot: Arc<RwLock<DashboardSnapshot>>,
+    /// Statistics read when the state is shown.
+    metrics: Metrics,
+    /// Time of the updates and of the readiness of the instruments.
+    clock: Arc<dyn Clock>,
+}
+
+
+impl Dashboard {
+    pub fn new(mode: RunMode) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        Dashboard {
+            snapshot: Arc::new(RwLock::new(DashboardSnapshot {
+                mode,
+                connection: ConnectionHealth::default(),
+                positions: Vec::new(),
+                signals: Vec::new(),
+                equity: Vec::new(),
+                money_flows: MoneyFlows::default(),
+                calls: BTreeMap::new(),
+                retries: BTreeMap::new(),
+                latency: BTreeMap::new(),
+                risk_limits: Vec::new(),
+                margin: Vec::new(),
+                tasks: BTreeMap::new(),
+                readiness: Vec::new(),
+                throttle: None,
+                incidents: Vec::new(),
+                heartbeats: Vec::new(),
+                updated_at: clock.now(),
+            })),
+            metrics: Metrics::default(),
+            clock,
+        }
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self.update(|_| ());
+        self
+    }
+
+
+    /// Shows the queue depth and the counters of the transactions of the throttle.
+    pub fn with_throttle(self, throttle: Arc<ThrottleStats>) -> Self {
+        self.update(|snapshot| snapshot.throttle = Some(throttle));
+        self
+    }
+
+
+    /// Shows the retries, the latency, the task health and the readiness of the instruments
+    /// recorded in the metrics.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+
+    /// State of the bot with the current statistics of the metrics.
+    pub fn snapshot(&self) -> DashboardSnapshot {
+        let mut snapshot = self.snapshot.read().unwrap_or_else(|e| e.into_inner()).clone();
+        snapshot.retries = self.metrics.retries.stats();
+        snapshot.latency = self.metrics.latency.stats();
+        snapshot.tasks = self.metrics.tasks.stats();
+        snapshot.readiness = self.metrics.instruments.readiness(self.clock.now());
+        snapshot
+    }
+
+
+    fn update(&self, f: impl FnOnce(&mut DashboardSnapshot)) {
+        let mut snapshot = self.snapshot.write().unwrap_or_else(|e| e.into_inner());
+        f(&mut snapshot);
+        snapshot.updated_at = self.clock.now();
+    }
+
+
+    pub fn set_connection(&self, connection: ConnectionHealth) {
+        self.update(|snapshot| snapshot.connection = connection);
+    }
+
+
+    pub fn set_positions(&self, positions: Vec<Position>) {
+        self.update(|snapshot| snapshot.positions = positions);
+    }
+
+
+    pub fn push_signal(&self, signal: Signal) {
+        self.update(|snapshot| {
+            snapshot.signals.insert(0, signal);
+            snapshot.signals.truncate(MAX_SIGNALS);
+        });
+    }
+
+
+    pub fn set_money_flows(&self, money_flows: MoneyFlows) {
+        self.update(|snapshot| snapshot.money_flows = money_flows);
+    }
+
+
+    pub fn push_equity(&self, time: DateTime<Utc>, equity: f64) {
+        self.update(|snapshot| {
+            snapshot.equity.push((time, equity));
+            if snapshot.equity.len() > MAX_EQUITY_POINTS {
+                let excess = snapshot.equity.len() - MAX_EQUITY_POINTS;
+                snapshot.equity.drain(..excess);
+            }
+        });
+    }
+
+
+    pub fn set_call_stats(&self, calls: BTreeMap<&'static str, FunctionStats>) {
+        self.update(|snapshot| snapshot.calls = calls);
+    }
+
+
+    pub fn set_risk_limits(&self, risk_limits: Vec<RiskLimit>) {
+        self.update(|snapshot| snapshot.risk_limits = risk_limits);
+    }
+
+
+    pub fn set_margin(&self, margin: Vec<(String, MarginForecast)>) {
+        self.update(|snapshot| snapshot.margin = margin);
+    }
+
+
+    pub fn set_incidents(&self, incidents: Vec<Incident>) {
+        self.update(|snapshot| snapshot.incidents = incidents);
+    }
+
+
+    pub fn set_heartbeats(&self, heartbeats: Vec<Heartbeat>) {
+        self.update(|snapshot| snapshot.heartbeats = heartbeats);
+    }
+
+
+    /// Serves the dashboard on `addr` until the task is cancelled, e.g. `127.0.0.1:8080`.
+    /// A bare port, e.g. `8080`, is served on `127.0.0.1` only.
+    pub async fn serve(self, addr: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let addr = listen_addr(&addr);
+        let listener = TcpListener::bind(&addr).await?;
+        info!("Dashboard is served on http://{}", addr);
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Error accepting a dashboard connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+                    continue;
+                }
+            };
+            let dashboard = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = dashboard.handle(stream, REQUEST_TIMEOUT).await {
+                    warn!("Dashboard request from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+
+    /// Answers a request whose head arrives within `timeout`.
+    async fn handle(&self, mut stream: TcpStream, timeout: Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Read the request head, the body of a GET request is ignored
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let Ok(read) = tokio::time::timeout_at(deadline, stream.read(&mut buffer)).await else {
+                return respond(&mut stream, "408 Request Timeout", "text/plain", "").await;
+            };
+            let read = read?;
+            if read == 0 {
+                return Ok(());
+            }
+            request.extend_from_slice(&buffer[..read]);
+            if request.len() > MAX_REQUEST_SIZE {
+                return respond(&mut stream, "431 Request Header Fields Too Large", "text/plain", "").await;
+            }
+        }
+
+        let head = String::from_utf8_lossy(&request);
+        let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+        let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+        if method != "GET" {
+            return respond(&mut stream, "405 Method Not Allowed", "text/plain", "read-only").await;
+        }
+
+        match path {
+            "/" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", &render_page(&self.snapshot())).await,
+            "/api/status" => {
+                let body = status_json(&self.snapshot()).to_string();
+                respond(&mut stream, "200 OK", "application/json", &body).await
+            }
+            _ => respond(&mut stream, "404 Not Found", "text/plain", "not found").await,
+        }
+    }
+}
+
+
+async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+
+/// Address to listen on, `127.0.0.1` for a bare port.
+fn listen_addr(addr: &str) -> String {
+    let port = addr.strip_prefix(':').unwrap_or(addr);
+    if port.parse::<u16>().is_ok() {
+        format!("127.0.0.1:{}", port)
+    } else {
+        addr.to_string()
+    }
+}
+
+
+fn status_json(snapshot: &DashboardSnapshot) -> serde_json::Value {
+    let connection = &snapshot.connection;
+
+    json!({
+        "mode": snapshot.mode.message_prefix(),
+        "updated_at": snapshot.updated_at.to_rfc3339(),
+        "connection": {
+            "connected": connection.is_connected(),
+            "dll_connected": connection.dll_connected,
+            "quik_connected": connection.quik_connected,
+            "latency_ms": connection.latency.map(|latency| latency.as_secs_f64() * 1000.0),
+            "disconnects": connection.disconnects,
+            "last_error": connection.last_error,
+        },
+        "positions": snapshot.positions.to_json(),
+        "risk_limits": snapshot.risk_limits.to_json(),
+        "margin": snapshot.margin.iter().map(|(account, forecast)| (account.clone(), forecast.to_json())).collect::<BTreeMap<_, _>>(),
+        "signals": snapshot.signals.to_json(),
+        "equity": snapshot.equity.iter().map(|(time, equity)| json!([time.to_rfc3339(), equity])).collect::<Vec<_>>(),
+        "money_flows": {
+            "gross_pnl": snapshot.money_flows.gross_pnl,
+            "commission": snapshot.money_flows.commission,
+            "slippage": snapshot.money_flows.slippage,
+            "net_pnl": snapshot.money_flows.net_pnl(),
+            "expected_pnl": snapshot.money_flows.expected_pnl(),
+        },
+        "calls": snapshot.calls.iter().map(|(function, stats)| json!({
+            "function": function,
+            "calls": stats.calls,
+            "failures": stats.failures(),
+            "results": stats.results.iter().map(|(result, count)| (format!("{:?}", result), *count)).collect::<BTreeMap<_, _>>(),
+            "avg_latency_ms": stats.average_latency().as_secs_f64() * 1000.0,
+            "max_latency_ms": stats.max_latency.as_secs_f64() * 1000.0,
+        })).collect::<Vec<_>>(),
+        "readiness": snapshot.readiness.iter().map(|row| (row.sec_code.clone(), row.to_json())).collect::<BTreeMap<_, _>>(),
+        "tasks": snapshot.tasks.iter().map(|(sec_code, health)| (sec_code.clone(), health.to_json())).collect::<BTreeMap<_, _>>(),
+        "retries": snapshot.retries.iter().map(|(operation, stats)| json!({
+            "operation": operation,
+            "calls": stats.calls,
+            "retries": stats.retries,
+            "failures": stats.failures,
+            "last_error": stats.last_error,
+        })).collect::<Vec<_>>(),
+        "latency": snapshot.latency.iter().map(|(stage, histogram)| (stage.as_str(), histogram.to_json())).collect::<BTreeMap<_, _>>(),
+        "transactions": snapshot.throttle.as_ref().map(|throttle| json!({
+            "queue_depth": throttle.queue_depth(),
+            "peak_queue_depth": throttle.peak_queue_depth(),
+            "sent": throttle.sent(),
+            "failed": throttle.failed(),
+            "rate": throttle.rate(),
+        })),
+        "incidents": snapshot.incidents.iter().map(|incident| json!({
+            "id": incident.id,
+            "created_at": incident.created_at.to_rfc3339(),
+            "severity": incident.severity.as_str(),
+            "kind": incident.kind,
+            "sec_code": incident.sec_code,
+            "message": incident.message,
+            "resolved": incident.resolved,
+            "resolved_at": incident.resolved_at.map(|time| time.to_rfc3339()),
+            "resolution": incident.resolution,
+        })).collect::<Vec<_>>(),
+        "uptime": {
+            "percent": heartbeat::uptime(&snapshot.heartbeats, HEARTBEAT_WINDOW),
+            "heartbeats": snapshot.heartbeats.iter().map(|heartbeat| json!([
+                heartbeat.recorded_at.to_rfc3339(),
+                heartbeat.state,
+                heartbeat.instruments_active,
+                heartbeat.loop_latency_ms,
+            ])).collect::<Vec<_>>(),
+        },
+    })
+}
+
+
+fn render_page(snapshot: &DashboardSnapshot) -> String {
+    let connection = &snapshot.connection;
+    let (status, color) = if connection.is_connected() {
+        ("connected", "#2e7d32")
+    } else {
+        ("disconnected", "#c62828")
+    };
+
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <meta http-equiv=\"refresh\" content=\"10\"><title>quik-rs</title>\
+         <style>body{{font-family:sans-serif;margin:1em}}table{{border-collapse:collapse;width:100%}}\
+         td,th{{border-bottom:1px solid #ddd;padding:4px;text-align:left}}</style></head><body>\
+         <h2>quik-rs {}</h2><p><b style=\"color:{}\">{}</b>, latency {}, disconnects {}</p>",
+        snapshot.mode.message_prefix(),
+        color,
+        status,
+        connection.latency.map_or("-".to_string(), |latency| format!("{:.1} ms", latency.as_secs_f64() * 1000.0)),
+        connection.disconnects,
+    );
+    if let Some(error) = &connection.last_error {
+        let _ = write!(page, "<p>{}</p>", escape(error));
+    }
+
+    let _ = write!(page, "<h3>Equity</h3>{}", sparkline(&snapshot.equity));
+    let flows = &snapshot.money_flows;
+    let _ = write!(
+        page,
+        "<p>Gross {:.2}, commission {:.2}, net {:.2}; slippage {:.2}</p>",
+        flows.gross_pnl,
+        flows.commission,
+        flows.net_pnl(),
+        flows.slippage
+    );
+
+    page.push_str("<h3>Risk limits</h3><table><tr><th>Limit</th><th>Used</th><th>Limit</th><th>Utilization</th></tr>");
+    for limit in &snapshot.risk_limits {
+        let utilization = limit.utilization();
+        let color = match utilization {
+            u if u >= 0.9 => "#c62828",
+            u if u >= 0.7 => "#ef6c00",
+            _ => "#2e7d32",
+        };
+        let _ = write!(
+            page,
+            "<tr><td>{}{}</td><td>{:.2}</td><td>{:.2}</td><td><div style=\"background:#eee;width:100%\">\
+             <div style=\"background:{};width:{:.0}%;color:#fff;white-space:nowrap\">{:.0}%</div></div></td></tr>",
+            limit.name,
+            [&limit.account, &limit.sec_code]
+                .into_iter()
+                .flatten()
+                .map(|value| format!(" {}", escape(value)))
+                .collect::<String>(),
+            limit.used,
+            limit.limit,
+            color,
+            (utilization * 100.0).min(100.0),
+            utilization * 100.0
+        );
+    }
+    page.push_str("</table>");
+
+    if !snapshot.margin.is_empty() {
+        page.push_str("<h3>Margin</h3><table><tr><th>Account</th><th>Security</th><th>Current</th><th>End of day</th></tr>");
+        for (account, forecast) in &snapshot.margin {
+            for instrument in &forecast.instruments {
+                let _ = write!(
+                    page,
+                    "<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{:.2}</td></tr>",
+                    escape(account),
+                    escape(&instrument.sec_code),
+                    instrument.current,
+                    instrument.projected
+                );
+            }
+            let color = if forecast.shortfall().is_some() { "#c62828" } else { "#2e7d32" };
+            let _ = write!(
+                page,
+                "<tr><td>{}</td><td><b>available {:.2}</b></td><td>{:.2}</td><td><b style=\"color:{}\">{:.2}</b></td></tr>",
+                escape(account),
+                forecast.available,
+                forecast.current(),
+                color,
+                forecast.projected()
+            );
+        }
+        page.push_str("</table>");
+    }
+    if let Some(throttle) = &snapshot.throttle {
+        let _ = write!(
+            page,
+            "<p>Transactions: sent {}, failed {}, queued {} (peak {})</p>",
+            throttle.sent(),
+            throttle.failed(),
+            throttle.queue_depth(),
+            throttle.peak_queue_depth()
+        );
+    }
+
+    page.push_str("<h3>Incidents</h3><table><tr><th>Time</th><th>Severity</th><th>Kind</th><th>Security</th><th>Message</th><th>Resolution</th></tr>");
+    for incident in &snapshot.incidents {
+        let color = match (incident.resolved, incident.severity) {
+            (true, _) => "#2e7d32",
+            (false, Severity::Critical) => "#c62828",
+            (false, _) => "#ef6c00",
+        };
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td><b style=\"color:{}\">{}</b></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            incident.created_at.format("%d.%m %H:%M:%S"),
+            color,
+            incident.severity.as_str(),
+            escape(&incident.kind),
+            escape(incident.sec_code.as_deref().unwrap_or("-")),
+            escape(&incident.message),
+            escape(incident.resolution.as_deref().unwrap_or(if incident.resolved { "resolved" } else { "-" }))
+        );
+    }
+    page.push_str("</table>");
+
+    page.push_str("<h3>Positions</h3><table><tr><th>Security</th><th>Lots</th><th>Average</th><th>Last</th><th>P&amp;L</th></tr>");
+    for position in &snapshot.positions {
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}</td></tr>",
+            escape(&position.sec_code),
+            position.lots,
+            position.average_price,
+            position.last_price,
+            position.pnl()
+        );
+    }
+    page.push_str("</table>");
+
+    page.push_str("<h3>Recent signals</h3><table><tr><th>Time</th><th>Security</th><th>Decision</th><th>Reason</th></tr>");
+    for signal in &snapshot.signals {
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            signal.time.format("%d.%m %H:%M:%S"),
+            escape(&signal.sec_code),
+            escape(&signal.decision),
+            escape(&signal.reason)
+        );
+    }
+    page.push_str("</table>");
+
+    page.push_str("<h3>Readiness</h3><table><tr><th>Security</th><th>Phase</th><th>Warm-up</th><th>Data</th><th>Subscribed</th><th>Trading</th></tr>");
+    for row in &snapshot.readiness {
+        let _ = write!(page, "<tr><td>{}</td><td>{}</td>", escape(&row.sec_code), row.phase.as_str());
+        for light in [row.warmed_up, row.data_fresh, row.subscribed, row.trading_enabled] {
+            let _ = write!(page, "<td><b style=\"color:{}\">&#9679;</b></td>", light.color());
+        }
+        page.push_str("</tr>");
+    }
+    page.push_str("</table>");
+
+    page.push_str("<h3>Instrument tasks</h3><table><tr><th>Security</th><th>Status</th><th>Last candle</th><th>Failures</th><th>Last error</th></tr>");
+    for (sec_code, health) in &snapshot.tasks {
+        let color = match health.status() {
+            "ok" => "#2e7d32",
+            "failing" => "#ef6c00",
+            _ => "#c62828",
+        };
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td><b style=\"color:{}\">{}</b></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(sec_code),
+            color,
+            health.status(),
+            health.last_ok.map_or("-".to_string(), |time| time.format("%d.%m %H:%M").to_string()),
+            health.failures,
+            escape(health.last_error.as_deref().unwrap_or("-"))
+        );
+    }
+    page.push_str("</table>");
+
+    page.push_str("<h3>Trans2QUIK calls</h3><table><tr><th>Function</th><th>Calls</th><th>Failures</th><th>Results</th><th>Average</th><th>Max</th></tr>");
+    for (function, stats) in &snapshot.calls {
+        let mut results: Vec<String> = stats.results.iter().map(|(result, count)| format!("{:?} {}", result, count)).collect();
+        results.sort();
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1} ms</td><td>{:.1} ms</td></tr>",
+            function,
+            stats.calls,
+            stats.failures(),
+            results.join(", "),
+            stats.average_latency().as_secs_f64() * 1000.0,
+            stats.max_latency.as_secs_f64() * 1000.0
+        );
+    }
+    page.push_str("</table>");
+
+    page.push_str("<h3>Retries</h3><table><tr><th>Operation</th><th>Calls</th><th>Retries</th><th>Failures</th><th>Last error</th></tr>");
+    for (operation, stats) in &snapshot.retries {
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            operation,
+            stats.calls,
+            stats.retries,
+            stats.failures,
+            escape(stats.last_error.as_deref().unwrap_or("-"))
+        );
+    }
+    page.push_str("</table>");
+
+    let latency: Vec<(DateTime<Utc>, f64)> = snapshot.heartbeats.iter().map(|heartbeat| (heartbeat.recorded_at, heartbeat.loop_latency_ms)).collect();
+    let _ = write!(
+        page,
+        "<h3>Uptime</h3><p>Connected {:.1}% of the last {} hours, loop latency of the heartbeats in ms:</p>{}",
+        heartbeat::uptime(&snapshot.heartbeats, HEARTBEAT_WINDOW),
+        HEARTBEAT_WINDOW.num_hours(),
+        sparkline(&latency)
+    );
+
+    page.push_str("<h3>Loop latency</h3><table><tr><th>Stage</th><th>Count</th><th>Average</th><th>p50</th><th>p99</th><th>Max</th></tr>");
+    for (stage, histogram) in &snapshot.latency {
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td></tr>",
+            stage,
+            histogram.count,
+            histogram.average().as_secs_f64() * 1000.0,
+            histogram.quantile(0.5).as_secs_f64() * 1000.0,
+            histogram.quantile(0.99).as_secs_f64() * 1000.0,
+            histogram.max.as_secs_f64() * 1000.0
+        );
+    }
+    let _ = write!(page, "</table><p><small>Updated {}</small></p></body></html>", snapshot.updated_at.format("%Y-%m-%d %H:%M:%S UTC"));
+
+    page
+}
+
+
+/// Renders the equity curve as an inline SVG polyline.
+fn sparkline(equity: &[(DateTime<Utc>, f64)]) -> String {
+    const WIDTH: f64 = 300.0;
+    const HEIGHT: f64 = 60.0;
+
+    if equity.len() < 2 {
+        return "<p>No data</p>".to_string();
+    }
+
+    let (min, max) = equity
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), (_, value)| (min.min(*value), max.max(*value)));
+    let range = if max > min { max - min } else { 1.0 };
+    let step = WIDTH / (equity.len() - 1) as f64;
+
+    let points: Vec<String> = equity
+        .iter()
+        .enumerate()
+        .map(|(index, (_, value))| format!("{:.1},{:.1}", index as f64 * step, HEIGHT - (value - min) / range * HEIGHT))
+        .collect();
+    let last = equity[equity.len() - 1].1;
+
+    format!(
+        "<svg viewBox=\"0 0 {} {}\" width=\"100%\" height=\"{}\" preserveAspectRatio=\"none\">\
+         <polyline fill=\"none\" stroke=\"#1565c0\" stroke-width=\"2\" points=\"{}\"/></svg><p>{:.2} (min {:.2}, max {:.2})</p>",
+        WIDTH,
+        HEIGHT,
+        HEIGHT,
+        points.join(" "),
+        last,
+        min,
+        max
+    )
+}
+
+
+/// Incidents task: every `period` reads the incidents of the last day, resolved or not, and
+/// publishes them to the dashboard.
+pub async fn publish_incidents(db: Arc<Db>, dashboard: Dashboard, clock: Arc<dyn Clock>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+        match db.get_incidents(clock.now() - INCIDENT_WINDOW, false).await {
+            Ok(mut incidents) => {
+                incidents.reverse();
+                dashboard.set_incidents(incidents);
+            }
+            Err(e) => error!("Error reading the incidents for the dashboard: {}", e),
+        }
+    }
+}
+
+
+/// Heartbeats task: every `period` reads the heartbeats of the last day and publishes them to the
+/// dashboard for the uptime and the loop latency trend.
+pub async fn publish_heartbeats(db: Arc<Db>, dashboard: Dashboard, clock: Arc<dyn Clock>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+        match db.get_heartbeats(clock.now() - HEARTBEAT_WINDOW).await {
+            Ok(heartbeats) => dashboard.set_heartbeats(heartbeats),
+            Err(e) => error!("Error reading the heartbeats for the dashboard: {}", e),
+        }
+    }
+}
+
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::config::Config;
+    use crate::instrument::Phase;
+
+
+    #[test]
+    fn updates_and_readiness_are_timed_by_the_clock() {
+        let settings = Config::parse("[[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"SBER\"\n")
+            .unwrap()
+            .instrument_settings()
+            .unwrap()
+            .remove(0);
+        let now: DateTime<Utc> = "2026-06-03T07:30:00Z".parse().unwrap();
+        let clock = Arc::new(TestClock::new(now));
+        let metrics = Metrics::default();
+        metrics.instruments.register(&settings, 0, Some(Duration::from_secs(900)));
+        metrics.instruments.record_subscribed("SBER", true);
+        metrics.instruments.record_candle("SBER", now);
+        let dashboard = Dashboard::new(RunMode::Demo).with_clock(clock.clone()).with_metrics(metrics);
+
+        let snapshot = dashboard.snapshot();
+        assert_eq!((snapshot.updated_at, snapshot.readiness[0].phase), (now, Phase::Ready));
+
+        // An hour later the candle of 15 minutes is stale
+        clock.advance(chrono::Duration::hours(1));
+        dashboard.push_equity(now, 100.0);
+        let snapshot = dashboard.snapshot();
+        assert_eq!(snapshot.updated_at, now + chrono::Duration::hours(1));
+        assert_eq!(snapshot.readiness[0].phase, Phase::Stale);
+    }
+
+
+    #[test]
+    fn bare_port_is_served_on_the_local_host_only() {
+        assert_eq!(listen_addr("8080"), "127.0.0.1:8080");
+        assert_eq!(listen_addr(":8080"), "127.0.0.1:8080");
+        assert_eq!(listen_addr("0.0.0.0:8080"), "0.0.0.0:8080");
+    }
+
+
+    async fn connect(dashboard: &Dashboard, timeout: Duration) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let dashboard = dashboard.clone();
+        tokio::spawn(async move { dashboard.handle(stream, timeout).await });
+        client
+    }
+
+
+    #[tokio::test]
+    async fn status_is_returned_as_json() {
+        let dashboard = Dashboard::new(RunMode::Demo);
+        dashboard.push_equity("2026-06-03T07:30:00Z".parse().unwrap(), 100.0);
+        let mut client = connect(&dashboard, REQUEST_TIMEOUT).await;
+
+        client.write_all(b"GET /api/status HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["equity"][0][1], 100.0);
+    }
+
+
+    #[tokio::test]
+    async fn client_not_sending_its_request_is_disconnected() {
+        let dashboard = Dashboard::new(RunMode::Demo);
+        let mut client = connect(&dashboard, Duration::from_millis(50)).await;
+
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+}
@@ -0,0 +1,315 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use libc::{c_long, c_ulong};
+use serde_json::{json, Value};
+use quik_rs::transaction::Side;
+use quik_rs::OrderInfo;
+use crate::portfolio::InstrumentPosition;
+use crate::psql::{DataForEma, InstrumentRow};
+use crate::strategy::Decision;
+
+
+/// Offset of the Moscow exchange time from UTC, the time of the orders and the trades is in exchange time.
+const MOSCOW_OFFSET_HOURS: i64 = 3;
+
+
+/// Time in UTC from the date `YYYYMMDD` and the time `HHMMSS` of the terminal in exchange time.
+pub fn exchange_time(date: c_long, time: c_long) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::from_ymd_opt((date / 10000) as i32, (date / 100 % 100) as u32, (date % 100) as u32)?;
+    let time = NaiveTime::from_hms_opt((time / 10000) as u32, (time / 100 % 100) as u32, (time % 100) as u32)?;
+
+    Some(date.and_time(time).and_utc() - chrono::Duration::hours(MOSCOW_OFFSET_HOURS))
+}
+
+
+/// Serialization of the domain types to JSON for the APIs and the exports.
+///
+/// The keys are part of the interface: a field is added, never renamed.
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+
+fn side_str(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "buy",
+        Side::Sell => "sell",
+    }
+}
+
+
+/// Candle of an instrument.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    /// Start of the period of the candle.
+    pub time: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+
+impl From<&DataForEma> for Candle {
+    fn from(candle: &DataForEma) -> Self {
+        Candle {
+            time: candle.period_start,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+        }
+    }
+}
+
+
+impl ToJson for Candle {
+    fn to_json(&self) -> Value {
+        json!({
+            "time": self.time.to_rfc3339(),
+            "open": self.open,
+            "high": self.high,
+            "low": self.low,
+            "close": self.close,
+            "volume": self.volume,
+        })
+    }
+}
+
+
+/// Decision of a strategy on an instrument at a time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub time: DateTime<Utc>,
+    pub sec_code: String,
+    /// Name of the action, see `Action::as_str`.
+    pub decision: String,
+    pub reason: String,
+}
+
+
+impl Signal {
+    pub fn from_decision(decision: &Decision, time: DateTime<Utc>) -> Self {
+        Signal {
+            time,
+            sec_code: decision.sec_code.clone(),
+            decision: decision.action.as_str().to_string(),
+            reason: decision.reason_code.clone(),
+        }
+    }
+}
+
+
+impl ToJson for Signal {
+    fn to_json(&self) -> Value {
+        json!({
+            "time": self.time.to_rfc3339(),
+            "sec_code": self.sec_code,
+            "decision": self.decision,
+            "reason": self.reason,
+        })
+    }
+}
+
+
+/// State of an order in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    Active,
+    Withdrawn,
+    Executed,
+}
+
+
+impl OrderStatus {
+    /// Status of the terminal: 1 - active, 2 - withdrawn, any other value - executed.
+    pub fn from_quik(status: c_long) -> Self {
+        match status {
+            1 => OrderStatus::Active,
+            2 => OrderStatus::Withdrawn,
+            _ => OrderStatus::Executed,
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Active => "active",
+            OrderStatus::Withdrawn => "withdrawn",
+            OrderStatus::Executed => "executed",
+        }
+    }
+}
+
+
+/// Order of the terminal with the fields the bot uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    pub trans_id: c_ulong,
+    pub order_num: u64,
+    pub class_code: String,
+    pub sec_code: String,
+    pub side: Side,
+    pub price: f64,
+    /// Quantity in lots.
+    pub quantity: i64,
+    /// Lots not filled yet.
+    pub balance: i64,
+    pub status: OrderStatus,
+    pub placed_at: Option<DateTime<Utc>>,
+}
+
+
+impl Order {
+    pub fn filled(&self) -> i64 {
+        self.quantity - self.balance
+    }
+}
+
+
+impl From<&OrderInfo> for Order {
+    fn from(order: &OrderInfo) -> Self {
+        Order {
+            trans_id: order.trans_id,
+            order_num: order.order_num,
+            class_code: order.class_code.clone(),
+            sec_code: order.sec_code.clone(),
+            side: if order.is_sell { Side::Sell } else { Side::Buy },
+            price: order.price,
+            quantity: order.qty,
+            balance: order.balance,
+            status: OrderStatus::from_quik(order.status),
+            placed_at: exchange_time(order.date, order.time),
+        }
+    }
+}
+
+
+impl ToJson for Order {
+    fn to_json(&self) -> Value {
+        json!({
+            "trans_id": self.trans_id,
+            "order_num": self.order_num,
+            "class_code": self.class_code,
+            "sec_code": self.sec_code,
+            "side": side_str(self.side),
+            "price": self.price,
+            "quantity": self.quantity,
+            "balance": self.balance,
+            "status": self.status.as_str(),
+            "placed_at": self.placed_at.map(|time| time.to_rfc3339()),
+        })
+    }
+}
+
+
+/// Position of an instrument.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Position {
+    pub class_code: String,
+    pub sec_code: String,
+    /// Position in lots, positive for long, negative for short.
+    pub lots: i64,
+    pub average_price: f64,
+    pub last_price: f64,
+    /// Realized profit before the commissions.
+    pub realized_pnl: f64,
+    pub commission: f64,
+}
+
+
+impl Position {
+    /// Unrealized profit in price units per lot unit.
+    pub fn pnl(&self) -> f64 {
+        (self.last_price - self.average_price) * self.lots as f64
+    }
+}
+
+
+impl From<&InstrumentPosition> for Position {
+    fn from(position: &InstrumentPosition) -> Self {
+        Position {
+            class_code: position.class_code.clone(),
+            sec_code: position.sec_code.clone(),
+            lots: position.quantity,
+            average_price: position.average_price,
+            last_price: position.last_price,
+            realized_pnl: position.realized_pnl,
+            commission: position.commission,
+        }
+    }
+}
+
+
+impl ToJson for Position {
+    fn to_json(&self) -> Value {
+        json!({
+            "class_code": self.class_code,
+            "sec_code": self.sec_code,
+            "lots": self.lots,
+            "average_price": self.average_price,
+            "last_price": self.last_price,
+            "pnl": self.pnl(),
+            "realized_pnl": self.realized_pnl,
+            "commission": self.commission,
+        })
+    }
+}
+
+
+/// Instrument with its trading parameters from `current_trades`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    pub class_code: String,
+    pub sec_code: String,
+    pub lot: Option<i32>,
+    pub lot_multiplier: Option<i32>,
+    pub price_step: Option<f64>,
+    pub price_decimals: Option<i32>,
+    pub session_status: Option<String>,
+    pub instrument_status: Option<String>,
+    /// Expiry date of a futures contract or a bond.
+    pub expiry: Option<NaiveDate>,
+}
+
+
+impl From<&InstrumentRow> for Instrument {
+    fn from(row: &InstrumentRow) -> Self {
+        Instrument {
+            class_code: row.class_code.clone(),
+            sec_code: row.instrument_code.clone(),
+            lot: row.lot,
+            lot_multiplier: row.lot_multiplier,
+            price_step: row.price_step,
+            price_decimals: row.price_decimals,
+            session_status: row.session_status.clone(),
+            instrument_status: row.instrument_status.clone(),
+            expiry: row.expiry,
+        }
+    }
+}
+
+
+impl ToJson for Instrument {
+    fn to_json(&self) -> Value {
+        json!({
+            "class_code": self.class_code,
+            "sec_code": self.sec_code,
+            "lot": self.lot,
+            "lot_multiplier": self.lot_multiplier,
+            "price_step": self.price_step,
+            "price_decimals": self.price_decimals,
+            "session_status": self.session_status,
+            "instrument_status": self.instrument_status,
+            "expiry": self.expiry.map(|date| date.to_string()),
+        })
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+use crate::account::{self, AccountSettings};
+use crate::clock::Clock;
+use crate::config::{ClassSession, Config, InstrumentSettings};
+use crate::existing_positions;
+use crate::expiry;
+use crate::gateway::OrderGateway;
+use crate::instrument_info::InstrumentInfo;
+use crate::margin::Position;
+use crate::notifier::Notifier;
+use crate::psql::{Db, IncidentKind, Severity};
+use crate::session_report::SessionReport;
+use crate::tearsheet::TearSheet;
+
+
+/// Built-in step of the end-of-day pipeline, run in the order of `EodStep::ALL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EodStep {
+    /// Closes the open positions with limit orders at the price limits.
+    Flatten,
+    /// Checks that no working orders are left and the positions match the limits of the terminal.
+    Reconcile,
+    /// Builds the daily candles of the day from the trades.
+    AggregateCandles,
+    /// Computes the trade statistics of the day by strategy.
+    ComputeStats,
+    /// Stores the profit and loss of the session in `daily_reports` and sends its summary.
+    SessionReport,
+    /// Archives the positions of the expired contracts and deletes the old evaluations.
+    Archive,
+    /// Sends the report of the pipeline to the notification channels.
+    SendReport,
+}
+
+
+impl EodStep {
+    pub const ALL: [EodStep; 7] = [
+        EodStep::Flatten,
+        EodStep::Reconcile,
+        EodStep::AggregateCandles,
+        EodStep::ComputeStats,
+        EodStep::SessionReport,
+        EodStep::Archive,
+        EodStep::SendReport,
+    ];
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EodStep::Flatten => "flatten",
+            EodStep::Reconcile => "reconcile",
+            EodStep::AggregateCandles => "aggregate_candles",
+            EodStep::ComputeStats => "compute_stats",
+            EodStep::SessionReport => "session_report",
+            EodStep::Archive => "archive",
+            EodStep::SendReport => "send_report",
+        }
+    }
+}
+
+
+/// What the pipeline does after a failed step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+    /// The next steps run as usual.
+    Continue,
+    /// The next steps are skipped, only the report is sent.
+    Abort,
+}
+
+
+impl OnFailure {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value {
+            "continue" => Ok(OnFailure::Continue),
+            "abort" => Ok(OnFailure::Abort),
+            _ => Err(format!("unknown on_failure '{}', expected 'continue' or 'abort'", value).into()),
+        }
+    }
+}
+
+
+/// Settings of a step, the table `[eod.<step>]` of the configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepSettings {
+    pub enabled: bool,
+    pub on_failure: OnFailure,
+}
+
+
+/// End-of-day pipeline, the `[eod]` table of the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EodSettings {
+    /// The pipeline runs by itself after the close of the sessions.
+    pub run_after_close: bool,
+    /// Wait after the close of the last session, so the last trades are exported.
+    pub delay_minutes: u32,
+    /// Exchange time of the automatic run, overriding the close of the sessions.
+    pub run_at: Option<NaiveTime>,
+    /// Names of the notification channels receiving the report.
+    pub notification_channels: Vec<String>,
+    pub steps: HashMap<EodStep, StepSettings>,
+}
+
+
+impl Default for EodSettings {
+    fn default() -> Self {
+        EodSettings {
+            run_after_close: false,
+            delay_minutes: 15,
+            run_at: None,
+            notification_channels: Vec::new(),
+            steps: EodStep::ALL
+                .into_iter()
+                .map(|step| {
+                    // Flattening sends orders, so it is only run when asked for
+                    let settings = match step {
+                        EodStep::Flatten => StepSettings { enabled: false, on_failure: OnFailure::Abort },
+                        _ => StepSettings { enabled: true, on_failure: OnFailure::Continue },
+                    };
+                    (step, settings)
+                })
+                .collect(),
+        }
+    }
+}
+
+
+impl EodSettings {
+    pub fn step(&self, step: EodStep) -> StepSettings {
+        self.steps.get(&step).copied().unwrap_or(StepSettings { enabled: false, on_failure: OnFailure::Continue })
+    }
+}
+
+
+/// Result of a step of the pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    Done(String),
+    Failed(String),
+    /// Disabled in the configuration or skipped after an aborting failure.
+    Skipped(&'static str),
+}
+
+
+/// Outcome of a run of the end-of-day pipeline.
+#[derive(Debug, Clone)]
+pub struct EodReport {
+    pub date: NaiveDate,
+    pub steps: Vec<(EodStep, StepResult)>,
+    /// A failed step stopped the pipeline.
+    pub aborted: bool,
+}
+
+
+impl EodReport {
+    pub fn is_ok(&self) -> bool {
+        !self.steps.iter().any(|(_, result)| matches!(result, StepResult::Failed(_)))
+    }
+}
+
+
+impl fmt::Display for EodReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |f: fn(&StepResult) -> bool| self.steps.iter().filter(|(_, result)| f(result)).count();
+        write!(
+            f,
+            "end of day {}: {} steps done, {} failed, {} skipped",
+            self.date,
+            count(|result| matches!(result, StepResult::Done(_))),
+            count(|result| matches!(result, StepResult::Failed(_))),
+            count(|result| matches!(result, StepResult::Skipped(_)))
+        )?;
+        if self.aborted {
+            write!(f, ", aborted")?;
+        }
+
+        for (step, result) in &self.steps {
+            match result {
+                StepResult::Done(summary) => write!(f, "\n  - {}: {}", step.as_str(), summary)?,
+                StepResult::Failed(e) => write!(f, "\n  - {}: failed, {}", step.as_str(), e)?,
+                StepResult::Skipped(reason) => write!(f, "\n  - {}: {}", step.as_str(), reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Close of the trading on the day: the latest end of the windows of the sessions.
+/// `None` if no session trades on the day.
+pub fn session_close(sessions: &HashMap<String, ClassSession>, date: NaiveDate) -> Option<NaiveTime> {
+    sessions
+        .values()
+        .flat_map(|session| match date.weekday() {
+            Weekday::Sat | Weekday::Sun => &session.weekends,
+            _ => &session.weekdays,
+        })
+        .map(|window| window.end)
+        .max()
+}
+
+
+/// Bounds of the exchange day in UTC.
+fn day_bounds(date: NaiveDate, timezone: FixedOffset) -> (DateTime<Utc>, DateTime<Utc>) {
+    let bound = |date: NaiveDate| {
+        timezone
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .single()
+            .map_or_else(|| date.and_time(NaiveTime::MIN).and_utc(), |time| time.with_timezone(&Utc))
+    };
+    (bound(date), bound(date + chrono::Duration::days(1)))
+}
+
+
+/// End-of-day maintenance pipeline: a sequence of the built-in steps of `EodStep::ALL`,
+/// each enabled and given its failure handling in `[eod.<step>]`. A failed step with
+/// `on_failure = "abort"` skips the rest of the pipeline, except the report, so the operator
+/// learns about the failure.
+///
+/// The pipeline runs by itself after the close of the sessions, see `run_after_close`, or
+/// by hand with `quik-rs eod [YYYY-MM-DD]`. Every step can be run again for the same day.
+///
+/// # Example of use
+/// ```ignore
+/// let pipeline = Pipeline::new(db.clone(), &config)?.with_gateway(gateway.clone());
+/// let report = pipeline.run(today).await;
+/// info!("{}", report);
+/// ```
+#[derive(Clone)]
+pub struct Pipeline {
+    db: Arc<Db>,
+    settings: EodSettings,
+    instruments: Vec<InstrumentSettings>,
+    strategies: Vec<String>,
+    accounts: Vec<AccountSettings>,
+    /// Capital of the trade statistics.
+    capital: f64,
+    audit_retention_days: i64,
+    timezone: FixedOffset,
+    notifier: Notifier,
+    gateway: Option<Arc<OrderGateway>>,
+}
+
+
+impl Pipeline {
+    pub fn new(db: Arc<Db>, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Pipeline {
+            db,
+            settings: config.eod.clone(),
+            instruments: config.instrument_settings()?,
+            strategies: config.strategies.clone(),
+            accounts: config.accounts.clone(),
+            capital: config.backtest.capital,
+            audit_retention_days: config.audit_retention_days,
+            timezone: config.exchange_timezone,
+            notifier: Notifier::from_config(config),
+            gateway: None,
+        })
+    }
+
+
+    /// Gateway the flatten step sends the closing orders through, without it the step fails.
+    pub fn with_gateway(mut self, gateway: Arc<OrderGateway>) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+
+    /// Runs the enabled steps for the exchange day `date`.
+    pub async fn run(&self, date: NaiveDate) -> EodReport {
+        let mut report = EodReport { date, steps: Vec::new(), aborted: false };
+        info!("End of day {} is started", date);
+
+        for step in EodStep::ALL {
+            let settings = self.settings.step(step);
+            if !settings.enabled {
+                report.steps.push((step, StepResult::Skipped("disabled")));
+                continue;
+            }
+            if report.aborted && step != EodStep::SendReport {
+                report.steps.push((step, StepResult::Skipped("skipped after the abort")));
+                continue;
+            }
+
+            let result = match step {
+                EodStep::Flatten => self.flatten().await,
+                EodStep::Reconcile => self.reconcile().await,
+                EodStep::AggregateCandles => self.aggregate_candles(date).await,
+                EodStep::ComputeStats => self.compute_stats(date).await,
+                EodStep::SessionReport => self.session_report(date).await,
+                EodStep::Archive => self.archive(date).await,
+                EodStep::SendReport => self.send_report(&report).await,
+            };
+            match result {
+                Ok(summary) => {
+                    info!("End of day {}: {}", step.as_str(), summary);
+                    report.steps.push((step, StepResult::Done(summary)));
+                }
+                Err(e) => {
+                    error!("End of day {} failed: {}", step.as_str(), e);
+                    report.steps.push((step, StepResult::Failed(e.to_string())));
+                    report.aborted |= settings.on_failure == OnFailure::Abort;
+                }
+            }
+        }
+
+        if report.is_ok() {
+            info!("{}", report);
+        } else {
+            warn!("{}", report);
+        }
+        report
+    }
+
+
+    async fn flatten(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let Some(gateway) = &self.gateway else {
+            return Err("no terminal to send the closing orders to".into());
+        };
+        let positions = self.db.get_positions().await?;
+        let codes: Vec<String> = positions.iter().map(|position| position.sec_code.clone()).collect();
+        let infos: Vec<InstrumentInfo> = self.db.get_instrument_rows(&codes).await?.into_iter().map(InstrumentInfo::from).collect();
+
+        let mut closed = 0;
+        let mut failed = Vec::new();
+        for record in positions.iter().filter(|record| record.quantity != 0) {
+            let Some(settings) = self.instruments.iter().find(|settings| settings.sec_code == record.sec_code && settings.allows_orders()) else {
+                failed.push(format!("{} doesn't send orders", record.sec_code));
+                continue;
+            };
+            let position = Position {
+                sec_code: record.sec_code.clone(),
+                lots: record.quantity,
+                price: record.last_price,
+            };
+            let info = infos.iter().find(|info| info.class_code == settings.class_code && info.sec_code == settings.sec_code);
+            match existing_positions::close(gateway, settings, info, &position) {
+                Ok(()) => closed += 1,
+                Err(e) => failed.push(format!("{} {} lots: {}", record.sec_code, record.quantity, e)),
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(format!("closing orders of {} positions sent, not sent: {}", closed, failed.join("; ")).into());
+        }
+        Ok(format!("closing orders of {} positions sent", closed))
+    }
+
+
+    async fn reconcile(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut mismatches = Vec::new();
+        for order in self.db.get_working_orders().await? {
+            mismatches.push((order.sec_code.clone(), format!("order {} of {} lots is still working after the close", order.order_num, order.balance)));
+        }
+
+        // The positions in lots are compared with the securities limits of their accounts in units
+        let positions = self.db.get_positions().await?;
+        if !self.accounts.is_empty() {
+            let codes: Vec<String> = positions.iter().map(|position| position.sec_code.clone()).collect();
+            let rows = self.db.get_instrument_rows(&codes).await?;
+            for position in &positions {
+                let Some(account) = account::of_position(&self.accounts, &self.instruments, &position.class_code, &position.sec_code) else {
+                    continue;
+                };
+                let Some(holding) = self.db.get_depo_position(&account.client_code, &position.sec_code).await? else {
+                    continue;
+                };
+                let lot_size = rows
+                    .iter()
+                    .find(|row| row.instrument_code == position.sec_code)
+                    .and_then(|row| row.lot)
+                    .map_or(1, |lot| lot as i64)
+                    .max(1);
+                if position.quantity * lot_size != holding.current_balance {
+                    mismatches.push((
+                        position.sec_code.clone(),
+                        format!("position of {} lots of {} units doesn't match the balance of {} units", position.quantity, lot_size, holding.current_balance),
+                    ));
+                }
+            }
+        }
+
+        for (sec_code, message) in &mismatches {
+            self.db
+                .insert_incident(Severity::Warning, IncidentKind::ReconciliationMismatch, Some(sec_code), message)
+                .await?;
+        }
+        if !mismatches.is_empty() {
+            let messages: Vec<String> = mismatches.iter().map(|(sec_code, message)| format!("{}: {}", sec_code, message)).collect();
+            return Err(messages.join("; ").into());
+        }
+        match self.accounts.len() {
+            0 => Ok("no working orders, the positions are not compared without [accounts]".to_string()),
+            accounts => Ok(format!("no working orders, {} positions match {} accounts", positions.len(), accounts)),
+        }
+    }
+
+
+    async fn aggregate_candles(&self, date: NaiveDate) -> Result<String, Box<dyn std::error::Error>> {
+        let (from, to) = day_bounds(date, self.timezone);
+        let instruments = self.db.aggregate_daily_candles(date, from, to).await?;
+        Ok(format!("daily candles of {} instruments", instruments))
+    }
+
+
+    async fn compute_stats(&self, date: NaiveDate) -> Result<String, Box<dyn std::error::Error>> {
+        let (from, to) = day_bounds(date, self.timezone);
+        let mut lines = Vec::new();
+        for strategy in &self.strategies {
+            let fills = self.db.get_fills(strategy, from, to).await?;
+            let stats = TearSheet::build(strategy, date, date, self.capital, &fills).stats;
+            lines.push(format!(
+                "{} {} fills, {} closed trades, net {:.2}, commission {:.2}",
+                strategy, stats.fills, stats.closed_trades, stats.net_pnl, stats.commission
+            ));
+        }
+        Ok(lines.join("; "))
+    }
+
+
+    async fn session_report(&self, date: NaiveDate) -> Result<String, Box<dyn std::error::Error>> {
+        let (from, to) = day_bounds(date, self.timezone);
+        let fills = self.db.get_fills_before(to).await?;
+        let incidents = self.db.get_incidents(from, false).await?.into_iter().filter(|incident| incident.created_at < to).collect();
+        let report = SessionReport::build(date, from, &fills).with_incidents(incidents);
+        self.db.upsert_daily_reports(&report.records()).await?;
+
+        let sent = if self.settings.notification_channels.is_empty() {
+            0
+        } else {
+            self.notifier.notify(None, &self.settings.notification_channels, &report.to_string()).await
+        };
+        Ok(format!(
+            "net {:.2} over {} trades of {} instruments, {} incidents, sent to {} channels",
+            report.total.net_pnl(),
+            report.total.trades,
+            report.instruments.len(),
+            report.incidents.len(),
+            sent
+        ))
+    }
+
+
+    async fn archive(&self, date: NaiveDate) -> Result<String, Box<dyn std::error::Error>> {
+        let positions = self.db.get_positions().await?;
+        let codes: Vec<String> = positions.iter().map(|position| position.sec_code.clone()).collect();
+        let rows = self.db.get_instrument_rows(&codes).await?;
+        // The contracts expiring today are archived the next day, like the expiry task does
+        let archived = expiry::archive_expired(&self.db, &rows, &positions, date + chrono::Duration::days(1)).await?;
+
+        let before = day_bounds(date, self.timezone).1 - chrono::Duration::days(self.audit_retention_days);
+        let deleted = self.db.delete_evaluations_before(before).await?;
+        Ok(format!("{} expired positions archived, {} evaluations before {} deleted", archived, deleted, before.date_naive()))
+    }
+
+
+    async fn send_report(&self, report: &EodReport) -> Result<String, Box<dyn std::error::Error>> {
+        if self.settings.notification_channels.is_empty() {
+            return Ok("no notification channels".to_string());
+        }
+
+        let sent = self.notifier.notify(None, &self.settings.notification_channels, &report.to_string()).await;
+        if sent == 0 {
+            return Err("the report reached no channel".into());
+        }
+        Ok(format!("sent to {} channels", sent))
+    }
+}
+
+
+/// Runs the pipeline every exchange day after the close of the sessions plus `delay_minutes`,
+/// or at `run_at`. Days without a session are skipped, and the pipeline for a close passed
+/// before the start is not run, use `quik-rs eod` for it.
+///
+/// # Example of use
+/// ```ignore
+/// if config.eod.run_after_close {
+///     tokio::spawn(eod::run_after_close(Pipeline::new(db.clone(), &config)?, config.sessions.clone(), clock.clone()));
+/// }
+/// ```
+pub async fn run_after_close(pipeline: Pipeline, sessions: HashMap<String, ClassSession>, clock: Arc<dyn Clock>) {
+    let (settings, timezone) = (pipeline.settings.clone(), pipeline.timezone);
+    if settings.run_at.is_none() && sessions.values().all(|session| session.weekdays.is_empty() && session.weekends.is_empty()) {
+        warn!("End of day is not scheduled: no [sessions] to close and no 'run_at' in [eod]");
+        return;
+    }
+    let mut date = clock.now().with_timezone(&timezone).date_naive();
+
+    loop {
+        // The delay may move the run past the midnight
+        let time = match settings.run_at {
+            Some(time) => Some(date.and_time(time)),
+            None => session_close(&sessions, date).map(|close| date.and_time(close) + chrono::Duration::minutes(settings.delay_minutes as i64)),
+        };
+        let Some(start) = time.and_then(|time| timezone.from_local_datetime(&time).single()) else {
+            date += chrono::Duration::days(1);
+            continue;
+        };
+
+        let now = clock.now();
+        if start.with_timezone(&Utc) <= now {
+            date += chrono::Duration::days(1);
+            continue;
+        }
+        info!("End of day {} is scheduled at {}", date, start);
+        sleep((start.with_timezone(&Utc) - now).to_std().unwrap_or_default()).await;
+
+        pipeline.run(date).await;
+        date += chrono::Duration::days(1);
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, info};
+use quik_rs::transaction::{Side, Transaction};
+use crate::config::InstrumentSettings;
+use crate::instrument_info::InstrumentInfo;
+use crate::orderbook::OrderBook;
+use crate::orders::OrderTracker;
+
+
+/// Distance of a limit price beyond the touch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceOffset {
+    /// Number of price steps of the instrument.
+    Ticks(f64),
+    /// Percent of the touch price.
+    Percent(f64),
+}
+
+
+impl PriceOffset {
+    /// Parses an offset like `2ticks` or `0.1%`.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let value = value.trim().to_lowercase();
+
+        let (number, offset): (&str, fn(f64) -> PriceOffset) = if let Some(number) = value.strip_suffix('%') {
+            (number, PriceOffset::Percent)
+        } else if let Some(number) = value.strip_suffix("ticks").or_else(|| value.strip_suffix("tick")) {
+            (number, PriceOffset::Ticks)
+        } else {
+            return Err(format!("offset '{}' must end with 'ticks' or '%'", value).into());
+        };
+
+        let number: f64 = number.trim().parse().map_err(|e| format!("offset '{}': {}", value, e))?;
+        if number <= 0.0 {
+            return Err(format!("offset '{}' must be positive", value).into());
+        }
+
+        Ok(offset(number))
+    }
+
+
+    /// Distance in price units, `None` for an offset in ticks without the price step.
+    pub fn distance(&self, price: f64, price_step: Option<f64>) -> Option<f64> {
+        match self {
+            PriceOffset::Ticks(ticks) => price_step.map(|step| step * ticks),
+            PriceOffset::Percent(percent) => Some(price * percent / 100.0),
+        }
+    }
+}
+
+
+impl fmt::Display for PriceOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceOffset::Ticks(ticks) => write!(f, "{}ticks", ticks),
+            PriceOffset::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+
+/// How a signal is turned into orders, the `execution` setting of a group or an instrument.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExecutionPolicy {
+    /// A single market order for the whole quantity.
+    #[default]
+    Market,
+    /// A limit order at the best price of the opposite side, the ask for a buy and the bid
+    /// for a sell, so the order takes the top of the book and never walks it.
+    LimitAtTouch,
+    /// A limit order the offset beyond the touch, trading the slippage for the chance of a fill.
+    LimitWithOffset(PriceOffset),
+    /// Market orders of equal slices spread evenly over the period, one slice a minute.
+    Twap { minutes: u32 },
+}
+
+
+impl ExecutionPolicy {
+    /// Parses a policy like `market`, `limit-at-touch`, `limit-with-offset 2ticks` or `twap 30m`.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let value = value.trim().to_lowercase();
+        let (name, argument) = match value.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (value.as_str(), None),
+        };
+
+        match (name, argument) {
+            ("market", None) => Ok(ExecutionPolicy::Market),
+            ("limit-at-touch", None) => Ok(ExecutionPolicy::LimitAtTouch),
+            ("limit-with-offset", Some(offset)) => Ok(ExecutionPolicy::LimitWithOffset(PriceOffset::parse(offset)?)),
+            ("twap", Some(period)) => {
+                let minutes: u32 = period
+                    .strip_suffix('m')
+                    .unwrap_or(period)
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("twap period '{}': {}", period, e))?;
+                if minutes == 0 {
+                    return Err(format!("twap period '{}' must be positive", period).into());
+                }
+                Ok(ExecutionPolicy::Twap { minutes })
+            }
+            ("limit-with-offset", None) => Err("'limit-with-offset' requires an offset, e.g. 'limit-with-offset 2ticks'".into()),
+            ("twap", None) => Err("'twap' requires a period, e.g. 'twap 30m'".into()),
+            _ => Err(format!(
+                "unknown execution '{}', expected 'market', 'limit-at-touch', 'limit-with-offset <offset>' or 'twap <minutes>m'",
+                value
+            )
+            .into()),
+        }
+    }
+
+
+    /// Orders of a signal for `quantity` lots, sent through the account of the instrument with
+    /// the prices on the grid of `info`. The limit policies need the order book for the touch,
+    /// and a limit with an offset needs the price step of the instrument: the price beyond
+    /// the touch is snapped to the grid away from the touch, up for a buy and down for a sell.
+    ///
+    /// # Example of use
+    /// ```ignore
+    /// let slices = settings.execution.plan(&settings, Side::Buy, 10, Some(&book), cache.get("QJSIM", "SBER").as_ref())?;
+    /// tokio::spawn(execution::execute(orders.clone(), slices));
+    /// ```
+    pub fn plan(
+        &self,
+        instrument: &InstrumentSettings,
+        side: Side,
+        quantity: i64,
+        book: Option<&OrderBook>,
+        info: Option<&InstrumentInfo>,
+    ) -> Result<Vec<Slice>, Box<dyn std::error::Error>> {
+        let sec_code = instrument.sec_code.as_str();
+        if quantity <= 0 {
+            return Err(format!("quantity {} of {} must be positive", quantity, sec_code).into());
+        }
+        let order = |quantity: i64, price: Option<f64>| {
+            instrument.route(Transaction::new_order(&instrument.class_code, sec_code, side, quantity, price), info)
+        };
+
+        match self {
+            ExecutionPolicy::Market => Ok(vec![Slice { delay: Duration::ZERO, transaction: order(quantity, None) }]),
+            ExecutionPolicy::LimitAtTouch => {
+                let touch = touch(book, side).ok_or_else(|| format!("no quote of {} for a limit at the touch", sec_code))?;
+                Ok(vec![Slice { delay: Duration::ZERO, transaction: order(quantity, Some(touch)) }])
+            }
+            ExecutionPolicy::LimitWithOffset(offset) => {
+                let touch = touch(book, side).ok_or_else(|| format!("no quote of {} for a limit with an offset", sec_code))?;
+                let price_format = info
+                    .map(InstrumentInfo::price_format)
+                    .filter(|price_format| price_format.step > 0.0)
+                    .ok_or_else(|| format!("the offset {} of {} requires the price step", offset, sec_code))?;
+                let distance = offset.distance(touch, Some(price_format.step)).unwrap_or_default();
+                // A percent of the touch is off the grid, it is snapped beyond the touch
+                let price = price_format.round_for(
+                    match side {
+                        Side::Buy => touch + distance,
+                        Side::Sell => touch - distance,
+                    },
+                    side,
+                );
+                Ok(vec![Slice { delay: Duration::ZERO, transaction: order(quantity, Some(price)) }])
+            }
+            ExecutionPolicy::Twap { minutes } => {
+                // Fewer lots than minutes are sent one lot a slice over the whole period
+                let slices = quantity.min(*minutes as i64);
+                let interval = Duration::from_secs(*minutes as u64 * 60) / slices as u32;
+                Ok((0..slices)
+                    .map(|slice| {
+                        // The remainder goes to the first slices
+                        let lots = quantity / slices + i64::from(slice < quantity % slices);
+                        Slice { delay: interval * slice as u32, transaction: order(lots, None) }
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+
+impl fmt::Display for ExecutionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionPolicy::Market => f.write_str("market"),
+            ExecutionPolicy::LimitAtTouch => f.write_str("limit-at-touch"),
+            ExecutionPolicy::LimitWithOffset(offset) => write!(f, "limit-with-offset {}", offset),
+            ExecutionPolicy::Twap { minutes } => write!(f, "twap {}m", minutes),
+        }
+    }
+}
+
+
+/// Order of an execution plan, sent `delay` after the signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slice {
+    pub delay: Duration,
+    pub transaction: Transaction,
+}
+
+
+/// Best price of the side the order trades against.
+fn touch(book: Option<&OrderBook>, side: Side) -> Option<f64> {
+    let book = book?;
+    let level = match side {
+        Side::Buy => book.asks.first(),
+        Side::Sell => book.bids.first(),
+    };
+    level.map(|level| level.price)
+}
+
+
+/// Submits the slices of a plan through the order tracker at their delays. A slice which
+/// fails to be sent is logged and the rest of the plan continues.
+pub async fn execute(orders: Arc<Mutex<OrderTracker>>, slices: Vec<Slice>) {
+    let total = slices.len();
+    let mut elapsed = Duration::ZERO;
+
+    for (number, slice) in slices.into_iter().enumerate() {
+        if slice.delay > elapsed {
+            sleep(slice.delay - elapsed).await;
+            elapsed = slice.delay;
+        }
+
+        let sec_code = slice.transaction.sec_code.clone();
+        let submitted = orders.lock().unwrap_or_else(|e| e.into_inner()).submit(slice.transaction);
+        match submitted {
+            Ok(trans_id) => info!("Slice {} of {} of {} is submitted as {}", number + 1, total, sec_code, trans_id),
+            Err(e) => error!("Error submitting the slice {} of {} of {}: {}", number + 1, total, sec_code, e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::orderbook::Level;
+
+
+    fn info(price_step: f64, price_decimals: i32) -> InstrumentInfo {
+        InstrumentInfo {
+            class_code: "QJSIM".to_string(),
+            sec_code: "SBER".to_string(),
+            lot: 1,
+            lot_multiplier: None,
+            price_step: Some(price_step),
+            price_decimals: Some(price_decimals),
+            session_status: None,
+            instrument_status: None,
+            price_max: None,
+            price_min: None,
+        }
+    }
+
+
+    #[test]
+    fn limit_at_touch_is_written_on_the_price_grid() {
+        let config = Config::parse("[[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"SBER\"\n").unwrap();
+        let settings = config.instrument_settings().unwrap().remove(0);
+        let book = OrderBook { bids: vec![], asks: vec![Level { price: 92.50251, volume: 10.0 }] };
+
+        let slices = ExecutionPolicy::LimitAtTouch
+            .plan(&settings, Side::Buy, 1, Some(&book), Some(&info(0.0025, 4)))
+            .unwrap();
+
+        assert!(slices[0].transaction.to_string().contains("PRICE=92.5025;"));
+    }
+
+
+    #[test]
+    fn percent_offset_is_snapped_beyond_the_touch() {
+        let config = Config::parse("[[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"SBER\"\n").unwrap();
+        let settings = config.instrument_settings().unwrap().remove(0);
+        let book = OrderBook {
+            bids: vec![Level { price: 92.5025, volume: 10.0 }],
+            asks: vec![Level { price: 92.5025, volume: 10.0 }],
+        };
+        let policy = ExecutionPolicy::parse("limit-with-offset 0.01%").unwrap();
+
+        let buy = policy.plan(&settings, Side::Buy, 1, Some(&book), Some(&info(0.0025, 4))).unwrap();
+        let sell = policy.plan(&settings, Side::Sell, 1, Some(&book), Some(&info(0.0025, 4))).unwrap();
+
+        // 92.5025 ± 0.00925
+        assert!(buy[0].transaction.to_string().contains("PRICE=92.5125;"));
+        assert!(sell[0].transaction.to_string().contains("PRICE=92.4925;"));
+        assert!(policy.plan(&settings, Side::Buy, 1, Some(&book), None).is_err());
+    }
+}
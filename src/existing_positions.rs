@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+use tracing::{error, info, warn};
+use quik_rs::transaction::{Side, Transaction};
+use crate::config::{ExistingPositions, InstrumentSettings};
+use crate::gateway::OrderGateway;
+use crate::instrument_info::{InstrumentCache, InstrumentInfo};
+use crate::margin::Position;
+
+
+/// Positions found at startup sorted by the policy of their instruments.
+#[derive(Debug, Clone, Default)]
+pub struct StartupPositions {
+    /// Positions managed by the strategies, by security code.
+    pub adopted: HashMap<String, Position>,
+
+    /// Positions closed with a limit order at the price limit.
+    pub closed: Vec<Position>,
+
+    /// Positions left to the user: the instrument is not traded while they are open.
+    pub ignored: Vec<Position>,
+
+    /// Positions whose closing order failed, they are left to the user as well.
+    pub failed: Vec<(Position, String)>,
+}
+
+
+impl StartupPositions {
+    /// Instruments with a position the bot must not touch while it is open.
+    pub fn blocked(&self) -> HashSet<String> {
+        self.ignored
+            .iter()
+            .chain(self.failed.iter().map(|(position, _)| position))
+            .map(|position| position.sec_code.clone())
+            .collect()
+    }
+}
+
+
+/// Applies the `existing_positions` policy of every instrument to the positions found
+/// at startup, so the behavior of the bot after a restart is explicit: the position is
+/// adopted by the strategy, closed at the price limit or left to the user.
+///
+/// Positions of instruments outside the watchlist are always ignored, and positions of
+/// instruments which can't send orders (disabled or watch-only) are ignored instead of closed.
+///
+/// # Example of use
+/// ```ignore
+/// let startup = existing_positions::apply(&gateway, &cache, &config.instrument_settings()?, positions);
+/// let trader = Trader::new(db.clone(), &config, cache.clone(), portfolio.clone(), orders.clone())?.with_blocked(startup.blocked());
+/// ```
+pub fn apply(
+    gateway: &OrderGateway,
+    instruments: &InstrumentCache,
+    settings: &[InstrumentSettings],
+    positions: Vec<Position>,
+) -> StartupPositions {
+    let mut startup = StartupPositions::default();
+
+    for position in positions.into_iter().filter(|position| position.lots != 0) {
+        let Some(instrument) = settings.iter().find(|settings| settings.sec_code == position.sec_code) else {
+            warn!("Position {} {} is not in the watchlist and is ignored", position.sec_code, position.lots);
+            startup.ignored.push(position);
+            continue;
+        };
+
+        match instrument.existing_positions {
+            ExistingPositions::Adopt => {
+                info!("Position {} {} lots at {} is adopted", position.sec_code, position.lots, position.price);
+                startup.adopted.insert(position.sec_code.clone(), position);
+            }
+            ExistingPositions::Close if instrument.allows_orders() => match close(gateway, instrument, instruments.get(&instrument.class_code, &instrument.sec_code).as_ref(), &position) {
+                Ok(()) => {
+                    info!("Closing order of the position {} {} lots is sent", position.sec_code, position.lots);
+                    startup.closed.push(position);
+                }
+                Err(e) => {
+                    error!("Error closing the position {} {} lots: {}", position.sec_code, position.lots, e);
+                    startup.failed.push((position, e));
+                }
+            },
+            ExistingPositions::Close => {
+                warn!("Position {} {} lots can't be closed, the instrument doesn't send orders", position.sec_code, position.lots);
+                startup.ignored.push(position);
+            }
+            ExistingPositions::Ignore => {
+                info!("Position {} {} lots is left to the user, the instrument is not traded", position.sec_code, position.lots);
+                startup.ignored.push(position);
+            }
+        }
+    }
+
+    startup
+}
+
+
+/// Sends the order closing the position, its outcome arrives as the reply and the order callback.
+///
+/// The order is a limit at the price limit of the session, PRICEMIN for a sell and PRICEMAX
+/// for a buy, which executes at once against the book like a market order: SPBFUT rejects
+/// market orders. Without the price limits of the instrument the order is not sent.
+pub fn close(gateway: &OrderGateway, instrument: &InstrumentSettings, info: Option<&InstrumentInfo>, position: &Position) -> Result<(), String> {
+    let side = if position.lots > 0 { Side::Sell } else { Side::Buy };
+    let price = info
+        .and_then(|info| info.marketable_price(side))
+        .ok_or_else(|| format!("no price limits of {} to close the position at", position.sec_code))?;
+    let order = instrument.route(Transaction::new_order(&instrument.class_code, &position.sec_code, side, position.lots.abs(), Some(price)), info);
+    gateway.send(order).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
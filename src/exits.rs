@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use quik_rs::transaction::{Side, Transaction};
+use crate::bracket::{Bracket, BracketTemplate};
+use crate::clock::Clock;
+use crate::config::InstrumentSettings;
+use crate::gateway::OrderGateway;
+use crate::instrument_info::{InstrumentCache, InstrumentInfo};
+use crate::portfolio::{InstrumentPosition, Portfolio};
+use crate::psql::{Db, ExitRecord};
+use crate::trading_loop;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    StopLoss,
+    TakeProfit,
+}
+
+
+impl ExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::StopLoss => "stop_loss",
+            ExitReason::TakeProfit => "take_profit",
+        }
+    }
+}
+
+
+/// Exit triggered by the last price of a position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExitSignal {
+    pub reason: ExitReason,
+    pub sec_code: String,
+    pub quantity: i64,
+    pub bracket: Bracket,
+    pub last_price: f64,
+}
+
+
+impl ExitSignal {
+    /// Level reached by the price.
+    pub fn trigger_price(&self) -> f64 {
+        match self.reason {
+            ExitReason::StopLoss => self.bracket.stop,
+            ExitReason::TakeProfit => self.bracket.target,
+        }
+    }
+}
+
+
+/// Protective exits of the open positions of a strategy: the stop and the target are calculated
+/// by the bracket template of the strategy from the average price of every position and the
+/// position is closed at once when the last price reaches either of them.
+///
+/// The levels are recalculated when the average price changes, e.g. after an addition to the
+/// position. An ATR template uses the last ATR set for the instrument; the position is not
+/// protected until it is known.
+///
+/// # Example of use
+/// ```ignore
+/// let template = config.brackets.get("ema_cross").copied().ok_or("no bracket template")?;
+/// let mut exits = ExitManager::new("ema_cross", template);
+/// exits.set_atr("SBER", atr);
+///
+/// if let Some(signal) = exits.check(&position) {
+///     close(&signal)?;
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExitManager {
+    strategy: String,
+    template: BracketTemplate,
+    brackets: HashMap<String, Bracket>,
+    atr: HashMap<String, f64>,
+    /// Quantity of the positions whose exit is sent: the exit is not repeated until it changes.
+    pending: HashMap<String, i64>,
+    /// Instruments with a position left to the user at the start, see `existing_positions::apply`.
+    blocked: HashSet<String>,
+}
+
+
+impl ExitManager {
+    pub fn new(strategy: &str, template: BracketTemplate) -> Self {
+        ExitManager {
+            strategy: strategy.to_string(),
+            template,
+            brackets: HashMap::new(),
+            atr: HashMap::new(),
+            pending: HashMap::new(),
+            blocked: HashSet::new(),
+        }
+    }
+
+
+    /// Leaves the positions of the instruments alone until they are closed.
+    pub fn with_blocked(mut self, blocked: HashSet<String>) -> Self {
+        self.blocked = blocked;
+        self
+    }
+
+
+    pub fn strategy(&self) -> &str {
+        &self.strategy
+    }
+
+
+    /// Sets the ATR of the instrument for the ATR offsets of the template.
+    pub fn set_atr(&mut self, sec_code: &str, atr: f64) {
+        self.atr.insert(sec_code.to_string(), atr);
+    }
+
+
+    /// The template needs the ATR of the position: it has ATR offsets and the position has no
+    /// bracket at its average price yet, so every new entry is protected by the current ATR.
+    pub fn needs_atr(&self, position: &InstrumentPosition) -> bool {
+        self.template.uses_atr()
+            && self
+                .brackets
+                .get(&position.sec_code)
+                .is_none_or(|bracket| bracket.entry != position.average_price)
+    }
+
+
+    /// Keeps the instruments blocked only while they have an open position, so the next
+    /// positions of the strategy are protected.
+    pub fn unblock_closed(&mut self, positions: &[InstrumentPosition]) {
+        self.blocked.retain(|sec_code| positions.iter().any(|position| &position.sec_code == sec_code));
+    }
+
+
+    /// Checks the last price of the position against its stop and target.
+    pub fn check(&mut self, position: &InstrumentPosition) -> Option<ExitSignal> {
+        let sec_code = &position.sec_code;
+        if self.blocked.contains(sec_code) {
+            return None;
+        }
+        if position.quantity == 0 || position.last_price <= 0.0 {
+            self.brackets.remove(sec_code);
+            self.pending.remove(sec_code);
+            return None;
+        }
+        if self.pending.get(sec_code).is_some_and(|quantity| *quantity == position.quantity) {
+            return None;
+        }
+        self.pending.remove(sec_code);
+
+        let side = if position.quantity > 0 { Side::Buy } else { Side::Sell };
+        let bracket = match self.brackets.get(sec_code) {
+            Some(bracket) if bracket.side == side && bracket.entry == position.average_price => *bracket,
+            _ => match self.template.apply(side, position.average_price, self.atr.get(sec_code).copied()) {
+                Ok(bracket) => {
+                    info!("{}: {} stop {} target {}", self.strategy, sec_code, bracket.stop, bracket.target);
+                    self.brackets.insert(sec_code.clone(), bracket);
+                    bracket
+                }
+                Err(e) => {
+                    warn!("{}: {} is not protected: {}", self.strategy, sec_code, e);
+                    self.brackets.remove(sec_code);
+                    return None;
+                }
+            },
+        };
+
+        let price = position.last_price;
+        let reason = match side {
+            Side::Buy if price <= bracket.stop => ExitReason::StopLoss,
+            Side::Buy if price >= bracket.target => ExitReason::TakeProfit,
+            Side::Sell if price >= bracket.stop => ExitReason::StopLoss,
+            Side::Sell if price <= bracket.target => ExitReason::TakeProfit,
+            _ => return None,
+        };
+
+        Some(ExitSignal {
+            reason,
+            sec_code: sec_code.clone(),
+            quantity: position.quantity,
+            bracket,
+            last_price: price,
+        })
+    }
+
+
+    /// Marks the exit as sent, so it isn't repeated while the position is unchanged.
+    pub fn sent(&mut self, signal: &ExitSignal) {
+        self.pending.insert(signal.sec_code.clone(), signal.quantity);
+    }
+}
+
+
+/// Exit manager task: every `period` reads the last prices of the open positions from
+/// `current_trades`, closes the positions which reached their stop or target and records the
+/// exits with their reason in the `exits` table. The ATR of an ATR template is of `atr_period`
+/// candles of the timeframe of the instrument, taken at every new entry.
+///
+/// Instruments which can't send orders (disabled or watch-only) are not closed, and neither are
+/// the positions left to the user at the start.
+///
+/// # Example of use
+/// ```ignore
+/// let exits = ExitManager::new("ema_cross", template).with_blocked(startup.blocked());
+/// tokio::spawn(exits::run(db.clone(), gateway.clone(), cache.clone(), portfolio.clone(), exits, settings, 14, clock.clone(), Duration::from_secs(1)));
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    db: Arc<Db>,
+    gateway: Arc<OrderGateway>,
+    instruments: InstrumentCache,
+    portfolio: Arc<RwLock<Portfolio>>,
+    mut manager: ExitManager,
+    settings: Vec<InstrumentSettings>,
+    atr_period: usize,
+    clock: Arc<dyn Clock>,
+    period: Duration,
+) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let positions = portfolio.read().unwrap_or_else(|e| e.into_inner()).open_positions();
+        manager.unblock_closed(&positions);
+        if positions.is_empty() {
+            continue;
+        }
+
+        let codes: Vec<String> = positions.iter().map(|position| position.sec_code.clone()).collect();
+        let prices = match db.get_last_prices(&codes).await {
+            Ok(prices) => prices,
+            Err(e) => {
+                error!("Error reading the last prices: {}", e);
+                continue;
+            }
+        };
+        let positions = {
+            let mut portfolio = portfolio.write().unwrap_or_else(|e| e.into_inner());
+            for (sec_code, price) in prices {
+                portfolio.on_price(&sec_code, price);
+            }
+            portfolio.open_positions()
+        };
+
+        for position in positions {
+            let Some(instrument) = settings.iter().find(|settings| settings.sec_code == position.sec_code) else {
+                continue;
+            };
+            if !instrument.allows_orders() {
+                continue;
+            }
+            if manager.needs_atr(&position) {
+                let timeframe = chrono::Duration::minutes(instrument.timeframe_minutes);
+                match trading_loop::last_atr(&db, &position.sec_code, timeframe, atr_period, clock.now()).await {
+                    Ok(Some(atr)) => manager.set_atr(&position.sec_code, atr),
+                    Ok(None) => {}
+                    Err(e) => error!("Error calculating the ATR of {}: {}", position.sec_code, e),
+                }
+            }
+            let Some(signal) = manager.check(&position) else {
+                continue;
+            };
+
+            warn!(
+                "{}: {} {} at {} reached {}, closing {} lots",
+                manager.strategy(),
+                signal.sec_code,
+                signal.reason.as_str(),
+                signal.last_price,
+                signal.trigger_price(),
+                signal.quantity
+            );
+            let trans_id = match close(&gateway, instrument, instruments.get(&instrument.class_code, &instrument.sec_code).as_ref(), &signal) {
+                Ok(trans_id) => {
+                    manager.sent(&signal);
+                    Some(trans_id)
+                }
+                Err(e) => {
+                    error!("Error closing the position {}: {}", signal.sec_code, e);
+                    continue;
+                }
+            };
+
+            let record = ExitRecord {
+                strategy: manager.strategy().to_string(),
+                sec_code: signal.sec_code.clone(),
+                reason: signal.reason.as_str().to_string(),
+                quantity: signal.quantity,
+                entry_price: signal.bracket.entry,
+                trigger_price: signal.trigger_price(),
+                last_price: signal.last_price,
+                trans_id,
+                created_at: clock.now(),
+            };
+            if let Err(e) = db.insert_exit(&record).await {
+                error!("Error saving the exit {}: {}", record.sec_code, e);
+            }
+        }
+    }
+}
+
+
+/// Sends the order closing the position: a limit at the price limit of the session where it
+/// is known, which executes at once like a market order and is accepted by SPBFUT, otherwise
+/// a market order.
+fn close(gateway: &OrderGateway, instrument: &InstrumentSettings, info: Option<&InstrumentInfo>, signal: &ExitSignal) -> Result<i64, String> {
+    let side = signal.bracket.side.opposite();
+    let price = info.and_then(|info| info.marketable_price(side));
+    let order = instrument.route(Transaction::new_order(&instrument.class_code, &signal.sec_code, side, signal.quantity.abs(), price), info);
+    let trans_id = gateway.send(order).map_err(|e| e.to_string())?;
+
+    Ok(trans_id as i64)
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use tracing::{error, info, warn};
+use quik_rs::transaction::{Side, Transaction};
+use crate::account::AccountSettings;
+use crate::clock::Clock;
+use crate::config::InstrumentSettings;
+use crate::gateway::OrderGateway;
+use crate::instrument_info::InstrumentInfo;
+use crate::psql::{Db, IncidentKind, InstrumentRow, PositionRecord, Severity};
+use crate::strategy::{Action, Decision};
+
+
+/// Month codes of the futures series of the Moscow Exchange, January to December.
+const MONTH_CODES: &str = "FGHJKMNQUVXZ";
+
+
+/// Base of the short code of a futures series, e.g. `Si` of `SiZ5`: the code without
+/// the month letter and the last digit of the year. `None` for a code of another form.
+pub fn series_base(sec_code: &str) -> Option<&str> {
+    let mut chars = sec_code.char_indices().rev();
+    let (_, year) = chars.next()?;
+    let (month_at, month) = chars.next()?;
+    if !year.is_ascii_digit() || !MONTH_CODES.contains(month) || month_at == 0 {
+        return None;
+    }
+
+    Some(&sec_code[..month_at])
+}
+
+
+/// Next series of the contract among `rows`: the same class and base with the nearest later expiry.
+pub fn next_series<'a>(contract: &InstrumentRow, rows: &'a [InstrumentRow]) -> Option<&'a InstrumentRow> {
+    let base = series_base(&contract.instrument_code)?;
+    let expiry = contract.expiry?;
+
+    rows.iter()
+        .filter(|row| row.class_code == contract.class_code && series_base(&row.instrument_code) == Some(base))
+        .filter(|row| row.expiry.is_some_and(|next| next > expiry))
+        .min_by_key(|row| row.expiry)
+}
+
+
+/// New position suppressed close to the expiry of the contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpiryBlock {
+    pub sec_code: String,
+    pub expiry: NaiveDate,
+    pub days_left: i64,
+}
+
+
+impl ExpiryBlock {
+    pub const REASON_CODE: &'static str = "expiry";
+}
+
+
+impl fmt::Display for ExpiryBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} days to the expiry on {}, no new positions", self.sec_code, self.days_left, self.expiry)
+    }
+}
+
+
+/// Expiry guard of the futures contracts: within `expiry_guard_days` of the expiry date
+/// (`MAT_DATE` of `current_trades`) no new position is opened in the contract, so the bot
+/// doesn't enter a series with thin liquidity which is about to be settled. Exits are never
+/// suppressed. The instruments without an expiry date or without `expiry_guard_days` are not affected.
+///
+/// # Example of use
+/// ```ignore
+/// let settings = config.instrument_settings()?;
+/// let rows = db.get_instrument_rows(&codes).await?;
+/// let guard = ExpiryGuard::new(&settings, &rows, config.exchange_timezone);
+/// let decision = guard.filter(decision, Utc::now());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExpiryGuard {
+    /// Guard days and the expiry date by security code.
+    contracts: HashMap<String, (u32, NaiveDate)>,
+    timezone: FixedOffset,
+}
+
+
+impl ExpiryGuard {
+    pub fn new(settings: &[InstrumentSettings], rows: &[InstrumentRow], timezone: FixedOffset) -> Self {
+        let mut guard = ExpiryGuard {
+            contracts: HashMap::new(),
+            timezone,
+        };
+        for settings in settings {
+            guard.add_instrument(settings, expiry_of(rows, settings));
+        }
+        guard
+    }
+
+
+    /// Starts following an instrument added to the universe.
+    pub fn add_instrument(&mut self, settings: &InstrumentSettings, expiry: Option<NaiveDate>) {
+        if let (Some(days), Some(expiry)) = (settings.expiry_guard_days, expiry) {
+            self.contracts.insert(settings.sec_code.clone(), (days, expiry));
+        }
+    }
+
+
+    pub fn remove_instrument(&mut self, sec_code: &str) {
+        self.contracts.remove(sec_code);
+    }
+
+
+    /// Days from the exchange day of `now` to the expiry of the contract.
+    pub fn days_left(&self, sec_code: &str, now: DateTime<Utc>) -> Option<i64> {
+        let (_, expiry) = self.contracts.get(sec_code)?;
+        Some((*expiry - now.with_timezone(&self.timezone).date_naive()).num_days())
+    }
+
+
+    /// Allows the action or returns the block of a new position close to the expiry.
+    pub fn check(&self, sec_code: &str, action: Action, now: DateTime<Utc>) -> Result<(), ExpiryBlock> {
+        if !matches!(action, Action::Buy | Action::Sell) {
+            return Ok(());
+        }
+        let (Some((days, expiry)), Some(days_left)) = (self.contracts.get(sec_code), self.days_left(sec_code, now)) else {
+            return Ok(());
+        };
+
+        if days_left <= *days as i64 {
+            return Err(ExpiryBlock {
+                sec_code: sec_code.to_string(),
+                expiry: *expiry,
+                days_left,
+            });
+        }
+        Ok(())
+    }
+
+
+    /// Replaces a blocked decision with `DoNothingExplicit` with the reason code `expiry`,
+    /// so the block is recorded in the evaluation audit.
+    pub fn filter(&self, decision: Decision, now: DateTime<Utc>) -> Decision {
+        match self.check(&decision.sec_code, decision.action, now) {
+            Ok(()) => decision,
+            Err(_) => Decision {
+                action: Action::DoNothingExplicit,
+                reason_code: ExpiryBlock::REASON_CODE.to_string(),
+                ..decision
+            },
+        }
+    }
+}
+
+
+fn expiry_of(rows: &[InstrumentRow], settings: &InstrumentSettings) -> Option<NaiveDate> {
+    rows.iter()
+        .find(|row| row.class_code == settings.class_code && row.instrument_code == settings.sec_code)
+        .and_then(|row| row.expiry)
+}
+
+
+/// Roll of a position from an expiring contract into the next series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Roll {
+    pub class_code: String,
+    pub from: String,
+    pub to: String,
+    /// Position in lots, positive for long.
+    pub lots: i64,
+    /// Account of the contract the orders are sent through.
+    pub account: Option<AccountSettings>,
+}
+
+
+impl Roll {
+    /// Orders of the roll: the close of the expiring contract and the same position in the next
+    /// series. Each is a limit at the price limit of its contract in the direction of the order,
+    /// executed at once like a market order, which SPBFUT rejects. An error without the price
+    /// limits of either contract among `rows`.
+    pub fn transactions(&self, rows: &[InstrumentRow]) -> Result<[Transaction; 2], String> {
+        let (close, open) = if self.lots > 0 { (Side::Sell, Side::Buy) } else { (Side::Buy, Side::Sell) };
+        let order = |sec_code: &str, side: Side| {
+            let info = rows
+                .iter()
+                .find(|row| row.class_code == self.class_code && row.instrument_code == sec_code)
+                .map(|row| InstrumentInfo::from(row.clone()));
+            let price = info
+                .as_ref()
+                .and_then(|info| info.marketable_price(side))
+                .ok_or_else(|| format!("no price limits of {} to roll at", sec_code))?;
+            let order = Transaction::new_order(&self.class_code, sec_code, side, self.lots.abs(), Some(price)).with_comment("roll");
+            let order = match info {
+                Some(info) => order.with_price_format(info.price_format()),
+                None => order,
+            };
+            Ok::<_, String>(match &self.account {
+                Some(account) => account.apply(order),
+                None => order,
+            })
+        };
+
+        Ok([order(&self.from, close)?, order(&self.to, open)?])
+    }
+}
+
+
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "roll {} lots of {}.{} into {}", self.lots, self.class_code, self.from, self.to)
+    }
+}
+
+
+/// Rolls of the open positions of the instruments with `roll_on_expiry` inside their
+/// `expiry_guard_days`. A contract without a later series among `rows` is not rolled and
+/// is reported as an error.
+pub fn rolls(settings: &[InstrumentSettings], rows: &[InstrumentRow], positions: &[PositionRecord], today: NaiveDate) -> Vec<Roll> {
+    let mut rolls = Vec::new();
+    for settings in settings.iter().filter(|settings| settings.roll_on_expiry) {
+        let Some(days) = settings.expiry_guard_days else {
+            continue;
+        };
+        let Some(contract) = rows.iter().find(|row| row.class_code == settings.class_code && row.instrument_code == settings.sec_code) else {
+            continue;
+        };
+        let Some(position) = positions.iter().find(|position| position.sec_code == settings.sec_code && position.quantity != 0) else {
+            continue;
+        };
+        if contract.expiry.is_none_or(|expiry| (expiry - today).num_days() > days as i64) {
+            continue;
+        }
+
+        match next_series(contract, rows) {
+            Some(next) => rolls.push(Roll {
+                class_code: settings.class_code.clone(),
+                from: settings.sec_code.clone(),
+                to: next.instrument_code.clone(),
+                lots: position.quantity,
+                account: settings.account.clone(),
+            }),
+            None => error!("{}: no next series to roll {} lots into before the expiry", settings.sec_code, position.quantity),
+        }
+    }
+    rolls
+}
+
+
+/// Moves the positions of the contracts expired before `today` to `positions_archive`.
+/// A contract expired with an open position was settled by the exchange, which the journal
+/// doesn't see, so it is recorded as an incident for a manual check of the settlement.
+pub async fn archive_expired(db: &Db, rows: &[InstrumentRow], positions: &[PositionRecord], today: NaiveDate) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut archived = 0;
+    for position in positions {
+        let Some(expiry) = rows.iter().find(|row| row.instrument_code == position.sec_code).and_then(|row| row.expiry) else {
+            continue;
+        };
+        if expiry >= today {
+            continue;
+        }
+
+        if position.quantity != 0 {
+            let message = format!("{} expired on {} with an open position of {} lots", position.sec_code, expiry, position.quantity);
+            warn!("{}", message);
+            db.insert_incident(Severity::Warning, IncidentKind::InstrumentChange, Some(&position.sec_code), &message).await?;
+        }
+        if db.archive_position(&position.sec_code, Some(expiry)).await? {
+            info!("Position of the expired {} is archived", position.sec_code);
+            archived += 1;
+        }
+    }
+    Ok(archived)
+}
+
+
+/// Expiry task: every `period` archives the positions of the expired contracts and rolls
+/// the expiring ones through the gateway. The next series must be in the watchlist to be rolled
+/// into. A roll is sent once a day, a roll which failed to be sent is retried on the next check.
+///
+/// # Example of use
+/// ```ignore
+/// tokio::spawn(expiry::run(db.clone(), gateway.clone(), config.instrument_settings()?, config.exchange_timezone, clock.clone(), Duration::from_secs(3600)));
+/// ```
+pub async fn run(
+    db: Arc<Db>,
+    gateway: Arc<OrderGateway>,
+    settings: Vec<InstrumentSettings>,
+    timezone: FixedOffset,
+    clock: Arc<dyn Clock>,
+    period: Duration,
+) {
+    let codes: Vec<String> = settings.iter().map(|settings| settings.sec_code.clone()).collect();
+    let mut interval = tokio::time::interval(period);
+    let mut rolled: HashSet<(NaiveDate, String)> = HashSet::new();
+
+    loop {
+        interval.tick().await;
+        let today = clock.now().with_timezone(&timezone).date_naive();
+        let (rows, positions) = match (db.get_instrument_rows(&codes).await, db.get_positions().await) {
+            (Ok(rows), Ok(positions)) => (rows, positions),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Error reading the instruments and the positions for the expiry check: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = archive_expired(&db, &rows, &positions, today).await {
+            error!("Error archiving the expired positions: {}", e);
+        }
+        rolled.retain(|(date, _)| *date == today);
+        for roll in rolls(&settings, &rows, &positions, today) {
+            if rolled.contains(&(today, roll.from.clone())) {
+                continue;
+            }
+            match send(&gateway, &roll, &rows) {
+                Ok(()) => {
+                    info!("{} is sent", roll);
+                    rolled.insert((today, roll.from.clone()));
+                }
+                Err(e) => error!("Error sending the {}: {}", roll, e),
+            }
+        }
+    }
+}
+
+
+/// Sends the close of the expiring contract, then the open of the next series. The open is not
+/// sent when the close isn't.
+fn send(gateway: &OrderGateway, roll: &Roll, rows: &[InstrumentRow]) -> Result<(), Box<dyn std::error::Error>> {
+    let [close, open] = roll.transactions(rows)?;
+    gateway.send(close)?;
+    gateway.send(open)?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn row(sec_code: &str, price_min: f64, price_max: f64) -> InstrumentRow {
+        InstrumentRow {
+            class_code: "SPBFUT".to_string(),
+            instrument_code: sec_code.to_string(),
+            lot: Some(1),
+            lot_multiplier: None,
+            price_step: Some(1.0),
+            price_decimals: Some(0),
+            session_status: None,
+            instrument_status: None,
+            expiry: None,
+            price_max: Some(price_max),
+            price_min: Some(price_min),
+        }
+    }
+
+
+    #[test]
+    fn roll_is_sent_as_limits_at_the_price_limits() {
+        let roll = Roll {
+            class_code: "SPBFUT".to_string(),
+            from: "SiZ6".to_string(),
+            to: "SiH7".to_string(),
+            lots: 2,
+            account: None,
+        };
+        let rows = [row("SiZ6", 87000.0, 95000.0), row("SiH7", 88000.0, 96000.0)];
+
+        let [close, open] = roll.transactions(&rows).unwrap();
+
+        let (close, open) = (close.to_string(), open.to_string());
+        assert!(close.contains("OPERATION=S;") && close.contains("PRICE=87000;"), "{}", close);
+        assert!(open.contains("OPERATION=B;") && open.contains("PRICE=96000;"), "{}", open);
+        assert!(roll.transactions(&rows[..1]).is_err());
+    }
+}
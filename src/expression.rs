@@ -0,0 +1,355 @@
+use std::collections::{HashMap, VecDeque};
+use ta::indicators::{AverageTrueRange, ExponentialMovingAverage, RateOfChange, SimpleMovingAverage};
+use ta::{Close, DataItem, High, Low, Next, Open, Volume};
+
+
+/// Arithmetic expression over named values, e.g. `(ema9 - ema21) / atr14`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+}
+
+
+impl Expr {
+    /// Parses an expression. Supported are numbers, identifiers, `+ - * /`, unary minus and parentheses.
+    pub fn parse(source: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        let expr = parser.expression()?;
+
+        if parser.position != tokens.len() {
+            return Err(format!("unexpected token {:?} in '{}'", tokens[parser.position], source).into());
+        }
+
+        Ok(expr)
+    }
+
+
+    /// Names of all variables used in the expression.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+
+    fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Expr::Neg(expr) => expr.collect_variables(names),
+            Expr::Add(left, right) | Expr::Sub(left, right) | Expr::Mul(left, right) | Expr::Div(left, right) => {
+                left.collect_variables(names);
+                right.collect_variables(names);
+            }
+        }
+    }
+
+
+    /// Evaluates the expression. Returns `None` when a variable is missing or the result is not finite,
+    /// e.g. after a division by zero.
+    pub fn eval(&self, variables: &HashMap<String, f64>) -> Option<f64> {
+        let value = match self {
+            Expr::Number(value) => *value,
+            Expr::Variable(name) => *variables.get(name)?,
+            Expr::Neg(expr) => -expr.eval(variables)?,
+            Expr::Add(left, right) => left.eval(variables)? + right.eval(variables)?,
+            Expr::Sub(left, right) => left.eval(variables)? - right.eval(variables)?,
+            Expr::Mul(left, right) => left.eval(variables)? * right.eval(variables)?,
+            Expr::Div(left, right) => left.eval(variables)? / right.eval(variables)?,
+        };
+
+        value.is_finite().then_some(value)
+    }
+}
+
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Box<dyn std::error::Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' | '(' | ')' => {
+                chars.next();
+                tokens.push(match c {
+                    '+' => Token::Plus,
+                    '-' => Token::Minus,
+                    '*' => Token::Star,
+                    '/' => Token::Slash,
+                    '(' => Token::LParen,
+                    _ => Token::RParen,
+                });
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    number.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number.parse()?));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            _ => return Err(format!("unexpected character '{}' in '{}'", c, source).into()),
+        }
+    }
+
+    Ok(tokens)
+}
+
+
+/// Recursive descent parser:
+/// ```ignore
+/// expression = term (("+" | "-") term)*
+/// term       = factor (("*" | "/") factor)*
+/// factor     = "-" factor | number | ident | "(" expression ")"
+/// ```
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+
+    fn expression(&mut self) -> Result<Expr, Box<dyn std::error::Error>> {
+        let mut left = self.term()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.position += 1;
+                    left = Expr::Add(Box::new(left), Box::new(self.term()?));
+                }
+                Some(Token::Minus) => {
+                    self.position += 1;
+                    left = Expr::Sub(Box::new(left), Box::new(self.term()?));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+
+    fn term(&mut self) -> Result<Expr, Box<dyn std::error::Error>> {
+        let mut left = self.factor()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.position += 1;
+                    left = Expr::Mul(Box::new(left), Box::new(self.factor()?));
+                }
+                Some(Token::Slash) => {
+                    self.position += 1;
+                    left = Expr::Div(Box::new(left), Box::new(self.factor()?));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+
+    fn factor(&mut self) -> Result<Expr, Box<dyn std::error::Error>> {
+        match self.next().cloned() {
+            Some(Token::Minus) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Ident(name)) => Ok(Expr::Variable(name)),
+            Some(Token::LParen) => {
+                let expr = self.expression()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("missing closing parenthesis".into()),
+                }
+            }
+            Some(token) => Err(format!("unexpected token {:?}", token).into()),
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+}
+
+
+/// Momentum: the change of the close over `period` candles, updated incrementally.
+/// Until `period` candles are seen the change is taken from the first close.
+pub struct Momentum {
+    period: usize,
+    closes: VecDeque<f64>,
+}
+
+
+impl Momentum {
+    pub fn new(period: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        if period == 0 {
+            return Err("momentum period must be positive".into());
+        }
+
+        Ok(Momentum {
+            period,
+            closes: VecDeque::with_capacity(period + 1),
+        })
+    }
+
+
+    pub fn next(&mut self, close: f64) -> f64 {
+        self.closes.push_back(close);
+        if self.closes.len() > self.period + 1 {
+            self.closes.pop_front();
+        }
+
+        close - self.closes.front().copied().unwrap_or(close)
+    }
+}
+
+
+/// A built-in value available in custom indicator expressions.
+enum Builtin {
+    Ema(ExponentialMovingAverage),
+    Sma(SimpleMovingAverage),
+    Atr(AverageTrueRange),
+    Roc(RateOfChange),
+    Momentum(Momentum),
+    Open,
+    High,
+    Low,
+    Close,
+    Volume,
+}
+
+
+impl Builtin {
+    /// Creates a built-in by its name: `open`, `high`, `low`, `close`, `volume`,
+    /// or an indicator with the period in the name, e.g. `ema9`, `sma50`, `atr14`, `roc12`, `mom10`.
+    fn from_name(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let builtin = match name {
+            "open" => Builtin::Open,
+            "high" => Builtin::High,
+            "low" => Builtin::Low,
+            "close" => Builtin::Close,
+            "volume" => Builtin::Volume,
+            _ => {
+                let split = name
+                    .find(|c: char| c.is_ascii_digit())
+                    .ok_or_else(|| format!("unknown built-in '{}'", name))?;
+                let period: usize = name[split..].parse()?;
+
+                match &name[..split] {
+                    "ema" => Builtin::Ema(ExponentialMovingAverage::new(period)?),
+                    "sma" => Builtin::Sma(SimpleMovingAverage::new(period)?),
+                    "atr" => Builtin::Atr(AverageTrueRange::new(period)?),
+                    "roc" => Builtin::Roc(RateOfChange::new(period)?),
+                    "mom" => Builtin::Momentum(Momentum::new(period)?),
+                    _ => return Err(format!("unknown built-in '{}'", name).into()),
+                }
+            }
+        };
+
+        Ok(builtin)
+    }
+
+
+    fn next(&mut self, item: &DataItem) -> f64 {
+        match self {
+            Builtin::Ema(ema) => ema.next(item),
+            Builtin::Sma(sma) => sma.next(item),
+            Builtin::Atr(atr) => atr.next(item),
+            Builtin::Roc(roc) => roc.next(item),
+            Builtin::Momentum(momentum) => momentum.next(item.close()),
+            Builtin::Open => item.open(),
+            Builtin::High => item.high(),
+            Builtin::Low => item.low(),
+            Builtin::Close => item.close(),
+            Builtin::Volume => item.volume(),
+        }
+    }
+}
+
+
+/// Custom indicator defined in the configuration as an expression over built-ins.
+///
+/// # Example of use
+/// ```ignore
+/// let mut indicator = CustomIndicator::new("trend_strength", "(ema9 - ema21) / atr14")?;
+/// for item in candles {
+///     if let Some(value) = indicator.next(&item) {
+///         database.insert_indicator_value(sec_code, "trend_strength", time, value).await?;
+///     }
+/// }
+/// ```
+pub struct CustomIndicator {
+    expr: Expr,
+    builtins: Vec<(String, Builtin)>,
+    values: HashMap<String, f64>,
+}
+
+
+impl CustomIndicator {
+    pub fn new(name: &str, source: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let expr = Expr::parse(source)?;
+        let builtins = expr
+            .variables()
+            .into_iter()
+            .map(|variable| Builtin::from_name(&variable).map(|builtin| (variable, builtin)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("indicator '{}': {}", name, e))?;
+
+        Ok(CustomIndicator {
+            expr,
+            builtins,
+            values: HashMap::new(),
+        })
+    }
+
+
+    /// Updates the built-ins with the next candle and evaluates the expression.
+    pub fn next(&mut self, item: &DataItem) -> Option<f64> {
+        for (variable, builtin) in self.builtins.iter_mut() {
+            self.values.insert(variable.clone(), builtin.next(item));
+        }
+
+        self.expr.eval(&self.values)
+    }
+}
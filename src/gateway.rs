@@ -0,0 +1,164 @@
+use std::os::raw::c_ulong;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Instant};
+use tracing::{error, warn};
+use quik_rs::quik::{QuikEvent, TransIdAllocator};
+use quik_rs::transaction::Transaction;
+use crate::intents::IntentLog;
+use crate::supervisor::KnownOrders;
+use crate::throttle::{ThrottleStats, TransactionThrottle};
+
+
+/// Interval between the checks of the queue and the intents while settling.
+const SETTLE_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// The single path of the transactions out of the bot. Every transaction, an order of
+/// the trading loop, a cancellation of the janitor or the dead-man switch, or a closing order
+/// of the end of day, takes its TRANS_ID here, is recorded in the intent log, registered in
+/// `KnownOrders` and queued in the throttle. A transaction whose intent is not recorded is not sent.
+///
+/// The replies and the order callbacks acknowledge the intents, see `run`.
+///
+/// # Example of use
+/// ```ignore
+/// let throttle = TransactionThrottle::start(terminal.clone(), config.max_transactions_per_second, config.transaction_burst, metrics.latency.clone())?;
+/// let gateway = Arc::new(OrderGateway::new(TransIdAllocator::open(&config.trans_id_file)?, intent_log, throttle));
+/// tokio::spawn(gateway::run(gateway.clone(), terminal.events()));
+/// let trans_id = gateway.send(Transaction::kill_order("QJSIM", "SBER", order_num))?;
+/// ```
+pub struct OrderGateway {
+    trans_ids: Mutex<TransIdAllocator>,
+    /// Shared with the throttle, which settles the intents of the transactions it fails to send.
+    intents: Arc<Mutex<IntentLog>>,
+    known_orders: Arc<KnownOrders>,
+    throttle: TransactionThrottle,
+}
+
+
+impl OrderGateway {
+    pub fn new(trans_ids: TransIdAllocator, intents: IntentLog, throttle: TransactionThrottle) -> Self {
+        OrderGateway {
+            trans_ids: Mutex::new(trans_ids),
+            intents: Arc::new(Mutex::new(intents)),
+            known_orders: Arc::new(KnownOrders::default()),
+            throttle,
+        }
+    }
+
+
+    /// Sends the transaction with a new TRANS_ID, returned once the transaction is queued.
+    /// The outcome arrives as `QuikEvent::TransactionReply`. The intent of a transaction
+    /// which is not queued or which the terminal refuses to send is settled as not sent.
+    pub fn send(&self, transaction: Transaction) -> Result<c_ulong, Box<dyn std::error::Error>> {
+        let trans_id = self.trans_ids.lock().unwrap_or_else(|e| e.into_inner()).next_id()?;
+        let transaction = transaction.with_trans_id(trans_id);
+
+        self.intents.lock().unwrap_or_else(|e| e.into_inner()).record(&transaction)?;
+        self.known_orders.register(trans_id);
+        let intents = self.intents.clone();
+        let not_sent = move |error: String| {
+            if let Err(e) = intents.lock().unwrap_or_else(|e| e.into_inner()).not_sent(trans_id, &error) {
+                error!("Error settling the intent {} which is not sent: {}", trans_id, e);
+            }
+        };
+        if let Err(e) = self.throttle.send(transaction.to_string(), Box::new(not_sent.clone())) {
+            not_sent(e.to_string());
+            return Err(e);
+        }
+
+        Ok(trans_id)
+    }
+
+
+    /// Settles the intent of a transaction given up and sent again as `by`, see `IntentLog::supersede`.
+    pub fn supersede(&self, trans_id: c_ulong, by: c_ulong) {
+        if let Err(e) = self.intents.lock().unwrap_or_else(|e| e.into_inner()).supersede(trans_id, by) {
+            error!("Error superseding the intent {} by {}: {}", trans_id, by, e);
+        }
+    }
+
+
+    /// Orders sent by the bot, for the dead-man switch and the janitor.
+    pub fn known_orders(&self) -> Arc<KnownOrders> {
+        self.known_orders.clone()
+    }
+
+
+    pub fn throttle_stats(&self) -> Arc<ThrottleStats> {
+        self.throttle.stats()
+    }
+
+
+    /// Acknowledges the intent of the transaction the event reports.
+    pub fn on_event(&self, event: &QuikEvent) {
+        if let Err(e) = self.intents.lock().unwrap_or_else(|e| e.into_inner()).on_event(event) {
+            error!("Error acknowledging the intent: {}", e);
+        }
+    }
+
+
+    /// Number of the transactions queued or sent without an acknowledgement yet.
+    pub fn unsettled(&self) -> usize {
+        let unconfirmed = self.intents.lock().unwrap_or_else(|e| e.into_inner()).unconfirmed().len();
+        unconfirmed.max(self.throttle.stats().queue_depth())
+    }
+
+
+    /// Waits up to `timeout` until every transaction is sent and acknowledged, e.g. before
+    /// the terminal is shut down. Returns the number of the transactions left unsettled.
+    pub async fn settle(&self, timeout: Duration) -> usize {
+        let started = Instant::now();
+        loop {
+            let unsettled = self.unsettled();
+            if unsettled == 0 || started.elapsed() >= timeout {
+                return unsettled;
+            }
+            sleep(SETTLE_INTERVAL).await;
+        }
+    }
+}
+
+
+/// Acknowledges the intents of the gateway by the events of the terminal until they are closed.
+pub async fn run(gateway: Arc<OrderGateway>, mut events: mpsc::UnboundedReceiver<QuikEvent>) {
+    while let Some(event) = events.recv().await {
+        gateway.on_event(&event);
+    }
+    warn!("Events are closed, the intents are not acknowledged anymore");
+}
+
+
+/// Gateway over `terminal` with the TRANS_IDs and the intents in the temporary directory, named
+/// after the test. Starts the throttle, so it is created inside the runtime of the test.
+#[cfg(test)]
+pub fn for_tests(terminal: Arc<dyn quik_rs::quik::QuikApi>, name: &str) -> Arc<OrderGateway> {
+    let path = |kind: &str| {
+        let path = std::env::temp_dir().join(format!("quik-rs-{}-{}-{}", name, std::process::id(), kind));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    };
+    let throttle = TransactionThrottle::start(terminal, 1000.0, 1000, crate::latency::LatencyStats::default()).unwrap();
+    let trans_ids = TransIdAllocator::open(&path("trans_id")).unwrap();
+    Arc::new(OrderGateway::new(trans_ids, IntentLog::open(&path("intents.jsonl")).unwrap(), throttle))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quik_rs::mock::MockTerminal;
+
+
+    #[tokio::test]
+    async fn intent_of_a_transaction_the_terminal_refuses_is_settled() {
+        // Not connected, the terminal refuses every transaction
+        let gateway = for_tests(Arc::new(MockTerminal::new()), "gateway_not_sent");
+
+        gateway.send(Transaction::new_order("QJSIM", "SBER", quik_rs::transaction::Side::Buy, 1, Some(250.0))).unwrap();
+        assert_eq!(gateway.settle(Duration::from_secs(5)).await, 0);
+        assert_eq!(gateway.throttle_stats().failed(), 1);
+    }
+}
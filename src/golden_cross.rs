@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use ta::indicators::SimpleMovingAverage;
+use ta::Next;
+use crate::config::InstrumentSettings;
+use crate::crossover::{Crossover, CrossoverSignal};
+use crate::psql::DataForEma;
+use crate::strategy::{Decision, Strategy};
+
+
+/// Period of the fast SMA.
+const FAST_PERIOD: usize = 50;
+
+/// Period of the slow SMA.
+const SLOW_PERIOD: usize = 200;
+
+/// Length of the evaluated candles, the exchange day.
+const TIMEFRAME: Duration = Duration::from_secs(24 * 3600);
+
+
+struct SmaState {
+    fast: SimpleMovingAverage,
+    slow: SimpleMovingAverage,
+    signal: CrossoverSignal,
+    /// Candles seen, the signals wait for the full period of the slow SMA.
+    candles: usize,
+}
+
+
+impl SmaState {
+    fn new() -> Self {
+        SmaState {
+            fast: SimpleMovingAverage::new(FAST_PERIOD).expect("valid period"),
+            slow: SimpleMovingAverage::new(SLOW_PERIOD).expect("valid period"),
+            signal: CrossoverSignal::default(),
+            candles: 0,
+        }
+    }
+
+
+    fn next(&mut self, close: f64) -> Option<Crossover> {
+        let fast = self.fast.next(close);
+        let slow = self.slow.next(close);
+        self.candles += 1;
+        if self.candles < SLOW_PERIOD {
+            return None;
+        }
+
+        self.signal.next(fast, slow, close)
+    }
+}
+
+
+/// Classic golden/death cross strategy on the daily candles: buys when the 50-day SMA crosses
+/// above the 200-day SMA and sells when it crosses below. A low-frequency built-in for
+/// conservative use, evaluated on the daily candles of every enabled instrument of the
+/// watchlist regardless of its `timeframe_minutes`.
+///
+/// No signal is emitted until 200 daily candles are seen.
+pub struct GoldenCross {
+    states: HashMap<String, SmaState>,
+}
+
+
+impl GoldenCross {
+    pub const NAME: &'static str = "sma_golden_cross";
+
+
+    pub fn new(settings: &[InstrumentSettings]) -> Self {
+        GoldenCross {
+            states: settings
+                .iter()
+                .filter(|settings| settings.enabled)
+                .map(|settings| (settings.sec_code.clone(), SmaState::new()))
+                .collect(),
+        }
+    }
+}
+
+
+impl Strategy for GoldenCross {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+
+    fn wants_instruments(&self) -> Vec<String> {
+        let mut instruments: Vec<String> = self.states.keys().cloned().collect();
+        instruments.sort();
+        instruments
+    }
+
+
+    fn timeframe(&self, sec_code: &str) -> Option<Duration> {
+        self.states.contains_key(sec_code).then_some(TIMEFRAME)
+    }
+
+
+    fn warm_up_candles(&self, sec_code: &str) -> usize {
+        if self.states.contains_key(sec_code) {
+            SLOW_PERIOD
+        } else {
+            0
+        }
+    }
+
+
+    fn on_candle(&mut self, sec_code: &str, candle: &DataForEma) -> Vec<Decision> {
+        let Some(state) = self.states.get_mut(sec_code) else {
+            return Vec::new();
+        };
+
+        state
+            .next(candle.close)
+            .map(|crossover| Decision {
+                sec_code: sec_code.to_string(),
+                action: crossover.action(),
+                reason_code: match crossover {
+                    Crossover::Bullish => "golden_cross".to_string(),
+                    Crossover::Bearish => "death_cross".to_string(),
+                },
+            })
+            .into_iter()
+            .collect()
+    }
+
+
+    fn add_instrument(&mut self, settings: &InstrumentSettings) -> Result<(), Box<dyn std::error::Error>> {
+        if settings.enabled {
+            self.states.insert(settings.sec_code.clone(), SmaState::new());
+        }
+        Ok(())
+    }
+
+
+    fn remove_instrument(&mut self, sec_code: &str) {
+        self.states.remove(sec_code);
+    }
+}
@@ -0,0 +1,559 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+use quik_rs::quik::{self, QuikApi};
+use crate::call_stats;
+use crate::churn::ChurnGuard;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::dashboard::{self, Dashboard};
+use crate::eod::{self, Pipeline};
+use crate::exits::{self, ExitManager};
+use crate::existing_positions;
+use crate::expiry::{self, ExpiryGuard};
+use crate::gateway::{self, OrderGateway};
+use crate::heartbeat::{self, LoopStats};
+use crate::holding::{self, HoldingTracker};
+use crate::instrument_info::{self, InstrumentCache};
+use crate::intents::IntentLog;
+use crate::janitor::OrderJanitor;
+use crate::journal;
+use crate::margin::Position;
+use crate::metrics::Metrics;
+use crate::notifier::Notifier;
+use crate::order_recovery;
+use crate::orders::{self, OrderTracker};
+use crate::portfolio::{self, Portfolio};
+use crate::position_age::{self, PositionAgeMonitor};
+use crate::psql::Db;
+use crate::risk::{self, RiskPanel};
+use crate::scheduler;
+use crate::shutdown;
+use crate::sizing::DEFAULT_ATR_PERIOD;
+use crate::strategy::StrategySet;
+use crate::supervisor::{self, ConnectionState, ConnectionSupervisor};
+use crate::throttle::TransactionThrottle;
+use crate::tick_feed::{self, TICK_POLL_PERIOD};
+use crate::tick_filter::CandleFilter;
+use crate::trading_loop::{self, Trader};
+use crate::universe::{self, Universe, UniverseChanges};
+use crate::watchdog::Watchdog;
+
+
+/// Time the initial snapshot of the orders is awaited after the start.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// Time the connection of the terminal is awaited before the positions found at the start are handled.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// Period of the check of the submit and the chase timeouts of the orders.
+const ORDER_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+
+/// Period of the check of the stops and the targets of the open positions.
+const EXIT_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+
+/// Period of the check of the holding periods of the open positions.
+const HOLDING_CHECK_PERIOD: Duration = Duration::from_secs(10);
+
+
+/// Period of the check of the age of the open positions.
+const POSITION_AGE_PERIOD: Duration = Duration::from_secs(60);
+
+
+/// Period of the expiry check of the futures contracts.
+const EXPIRY_CHECK_PERIOD: Duration = Duration::from_secs(3600);
+
+
+/// Period of the refresh of the parameters of the instruments: lots, price steps and statuses.
+const INSTRUMENT_REFRESH_PERIOD: Duration = Duration::from_secs(300);
+
+
+/// Period of the aggregates of the Trans2QUIK calls, see `call_stats::run`.
+const CALL_STATS_PERIOD: Duration = Duration::from_secs(60);
+
+
+/// Period of the refresh of the risk panel of the dashboard.
+const RISK_PANEL_PERIOD: Duration = Duration::from_secs(5);
+
+/// Period of the refresh of the incidents of the dashboard.
+const INCIDENTS_PERIOD: Duration = Duration::from_secs(30);
+
+/// Period of the refresh of the uptime of the dashboard, the heartbeats are written every minute.
+const HEARTBEATS_PERIOD: Duration = Duration::from_secs(60);
+
+
+/// Waits for a request to stop the bot: Ctrl-C, SIGTERM on Unix, or closing the console
+/// and the system shutdown on Windows, which is how a service manager stops a console process.
+pub async fn shutdown_signal() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => info!("SIGTERM is received"),
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+
+        let mut close = ctrl_close()?;
+        let mut shutdown = ctrl_shutdown()?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = close.recv() => info!("Console close is received"),
+            _ = shutdown.recv() => info!("System shutdown is received"),
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+
+/// Subscriptions of the watchlist: the securities codes joined with `|` by class code.
+fn subscriptions(config: &Config) -> Vec<(String, String)> {
+    let mut classes: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for instrument in &config.instruments {
+        classes.entry(&instrument.class_code).or_default().push(&instrument.sec_code);
+    }
+
+    classes
+        .into_iter()
+        .map(|(class_code, sec_codes)| (class_code.to_string(), sec_codes.join("|")))
+        .collect()
+}
+
+
+/// Forwards SIGHUP to `force`, the conventional way to make a daemon reload its configuration.
+fn forward_hangups(force: mpsc::UnboundedSender<()>) -> Result<tokio::task::JoinHandle<()>, Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        Ok(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if force.send(()).is_err() {
+                    return;
+                }
+            }
+        }))
+    }
+
+    #[cfg(not(unix))]
+    {
+        drop(force);
+        Ok(tokio::spawn(async {}))
+    }
+}
+
+
+/// Runs the bot without a display until a shutdown signal, e.g. on a server or as a Windows
+/// service: the connection supervisor keeps the terminal connected and subscribed, and the web
+/// dashboard is served if `dashboard_addr` is set. The caller shuts the terminal down afterwards.
+///
+/// The candle scheduler evaluates the strategies at every candle close, see `scheduler::run`,
+/// and the trading loop executes their decisions, see `Trader`. The statistics of the
+/// Trans2QUIK calls are recorded every minute, see `call_stats::run`. A heartbeat with the connection
+/// state and the last candle cycle is written every minute, see `heartbeat::run`.
+/// With `universe_refresh_minutes` the subscriptions and the strategies follow the instrument
+/// universe, refreshed periodically and on SIGHUP.
+///
+/// Every transaction of the bot goes through the `OrderGateway`: it is recorded in `intents`,
+/// the intent log reconciled at the start, and sent through the throttle of
+/// `max_transactions_per_second`.
+///
+/// Every callback event of the terminal is written to the event journal from the start,
+/// see `journal::run`, for the order recovery and the resubmission checks after a restart.
+///
+/// The positions are loaded at the start and kept up to date by the trades, see `portfolio::run`.
+/// The open ones are adopted, closed or left to the user by `existing_positions` of their
+/// instruments once the terminal is connected, see `existing_positions::apply`; the bot doesn't
+/// trade nor protect an instrument while its position left to the user is open.
+/// A position open longer than `max_position_age_hours` of its instrument is reported, see
+/// `position_age::run`.
+/// The positions of the instruments of a strategy with `[brackets.<strategy>]` are closed at
+/// its stop or target, see `exits::run`, and with `[holding.<strategy>]` after the maximum
+/// holding period, see `holding::run`.
+///
+/// The orders of the bot are followed by the `OrderTracker`, which chases them with `[chase]`
+/// on the price grids of the instruments, refreshed every few minutes. The working orders of
+/// the bot at the last stop are reconciled with the initial snapshot of the orders, see
+/// `order_recovery::reconcile`, and the matched ones are adopted by the tracker. With
+/// `stale_order_minutes` the `OrderJanitor` cancels the old working orders the bot didn't send,
+/// and with `dead_man_switch` the supervisor cancels them after every reconnection. The positions
+/// of the expired contracts are archived and the expiring ones are rolled through the gateway,
+/// see `expiry::run`. With `run_after_close` of `[eod]` the end-of-day pipeline runs after the close
+/// of the sessions.
+///
+/// The connection supervisor, the candle scheduler, the trading loop, the expiry task, the
+/// end-of-day pipeline and the risk panel run under the `watchdog`: a task which panics or exits
+/// is restarted with the delays of `[retry]` and reported, and a task failing `max_restarts` times
+/// within `window_minutes` stops the bot with an error, so the service manager sees it instead of
+/// a bot running without the task. A restarted scheduler restores the signal states from
+/// `bot_state`, and a restarted trading loop reads the positions from the portfolio.
+///
+/// At a shutdown signal, or when a task trips the circuit breaker, the tasks sending orders,
+/// the execution plans in flight and the janitor are stopped first, and the bot stops gracefully
+/// with `[shutdown]`, see `shutdown::run`:
+/// the working orders are cancelled if configured, the replies are awaited and the final state
+/// of the orders is reported before the terminal is disconnected.
+///
+/// # Example of use
+/// ```ignore
+/// if std::env::args().any(|arg| arg == "--headless") {
+///     headless::run(terminal.clone(), &config, config_path, intent_log).await?;
+/// }
+/// terminal.shutdown()?;
+/// ```
+pub async fn run(terminal: Arc<dyn QuikApi>, config: &Config, config_path: &str, intents: IntentLog) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tasks = Vec::new();
+    // The tasks sending orders, stopped first at the shutdown
+    let mut trading = Vec::new();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    // Shared by the tasks and shown by the dashboard, the retries of every subsystem built
+    // from the configuration are counted in them
+    let metrics = Metrics::default();
+    let mut config = config.clone();
+    config.retry = config.retry.with_metrics(metrics.retries.clone());
+    let config = &config;
+    let db = Arc::new(Db::connect(&config.connection_str, &config.retry).await?);
+    let intents = intents.with_clock(clock.clone());
+    // Subscribed before any callback is set, so the journal has every event of the session
+    tasks.push(tokio::spawn(journal::run(db.clone(), terminal.events(), clock.clone())));
+
+    let throttle = TransactionThrottle::start(terminal.clone(), config.max_transactions_per_second, config.transaction_burst, metrics.latency.clone())?;
+    let gateway = Arc::new(OrderGateway::new(quik::TransIdAllocator::open(&config.trans_id_file)?, intents, throttle));
+    tasks.push(tokio::spawn(gateway::run(gateway.clone(), terminal.events())));
+    // The lots and the price grids of the orders
+    let instruments = InstrumentCache::new(config.instruments.iter().map(|i| (i.class_code.clone(), i.sec_code.clone())).collect());
+    let mut tracker = OrderTracker::new(gateway.clone());
+    if let Some(chase) = &config.chase {
+        tracker = tracker.with_chase(chase.clone(), instruments.clone());
+    }
+    let orders = Arc::new(Mutex::new(tracker));
+    tasks.push(tokio::spawn(orders::run(orders.clone(), db.clone(), terminal.events(), ORDER_CHECK_PERIOD)));
+    let portfolio = Arc::new(RwLock::new(Portfolio::load(&db).await?));
+    tasks.push(tokio::spawn(portfolio::run(db.clone(), portfolio.clone(), terminal.events(), clock.clone())));
+    if let Some(minutes) = config.stale_order_minutes {
+        // Sees the initial snapshot, so the leftovers of the last run are cancelled as well
+        let janitor = OrderJanitor::new(gateway.clone(), db.clone(), Duration::from_secs(minutes as u64 * 60)).with_clock(clock.clone());
+        trading.push(tokio::spawn(janitor.run(terminal.events())));
+    }
+
+    let events = terminal.events();
+    // The initial snapshot of the orders arrives once the supervisor starts them
+    let mut order_events = terminal.events();
+    terminal.set_connection_status_callback()?;
+    terminal.set_transactions_reply_callback()?;
+    let (tripped, mut breakers) = mpsc::unbounded_channel();
+    let watchdog = Watchdog::new(config.watchdog.clone(), config.retry.clone(), db.clone(), Notifier::from_config(config), tripped);
+    let (refreshed_db, refreshed) = (db.clone(), instruments.clone());
+    tasks.push(tokio::spawn(watchdog.clone().supervise("instrument_info", move || {
+        instrument_info::run(refreshed_db.clone(), refreshed.clone(), INSTRUMENT_REFRESH_PERIOD)
+    })));
+
+    let (changes, universe_changes) = mpsc::unbounded_channel();
+    let updates = match config.universe_refresh {
+        Some(period) => {
+            let universe = Universe::new(universe::eligible(config_path, &db, clock.as_ref()).await?);
+            info!("Instrument universe: {} instruments", universe.instruments().len());
+
+            let (subscriptions, updates) = watch::channel(universe.subscriptions());
+            let (force, requests) = mpsc::unbounded_channel();
+            tasks.push(forward_hangups(force)?);
+            tasks.push(tokio::spawn(universe::run(config_path.to_string(), db.clone(), universe, subscriptions, changes, requests, clock.clone(), period)));
+            Some(updates)
+        }
+        None => None,
+    };
+    // The readiness of the instruments is tracked before the supervisor subscribes to them
+    let strategies = StrategySet::from_config(config, &metrics.latency)?;
+    for settings in &config.instrument_settings()? {
+        metrics.instruments.register(settings, strategies.warm_up_candles(&settings.sec_code), strategies.timeframes(&settings.sec_code).first().copied());
+    }
+    // A restarted supervisor subscribes to the events again and starts with the current subscriptions
+    let first_events = Mutex::new(Some(events));
+    let (state, connection) = watch::channel(ConnectionState::Disconnected);
+    let stats = Arc::new(LoopStats::default());
+    tasks.push(tokio::spawn(heartbeat::run(db.clone(), connection.clone(), stats.clone())));
+    tasks.push(tokio::spawn(supervisor::record_disconnects(db.clone(), connection.clone())));
+    let (supervised, retry, initial, readiness) = (terminal.clone(), config.retry.clone(), subscriptions(config), metrics.instruments.clone());
+    let dead_man_switch = config.dead_man_switch.then(|| gateway.clone());
+    tasks.push(tokio::spawn(watchdog.clone().supervise("connection_supervisor", move || {
+        let events = first_events.lock().unwrap_or_else(|e| e.into_inner()).take().unwrap_or_else(|| supervised.events());
+        let supervisor = match &updates {
+            Some(updates) => ConnectionSupervisor::new(supervised.clone(), updates.borrow().clone()).with_subscription_updates(updates.clone()),
+            None => ConnectionSupervisor::new(supervised.clone(), initial.clone()),
+        };
+        let supervisor = supervisor.with_retry_policy(retry.clone()).with_state(state.clone()).with_readiness(readiness.clone());
+        match &dead_man_switch {
+            Some(gateway) => supervisor.with_dead_man_switch(gateway.clone()),
+            None => supervisor,
+        }
+        .run(events)
+    })));
+
+    // The positions found at the start are closed once the terminal is connected, with the price
+    // limits of the closing orders known before the first refresh of the instrument task
+    let mut connected = connection;
+    if tokio::time::timeout(CONNECT_TIMEOUT, connected.wait_for(|state| *state == ConnectionState::Connected)).await.is_err() {
+        warn!("The terminal is not connected after {:?}, the positions found at the start may fail to close", CONNECT_TIMEOUT);
+    }
+    let settings = config.instrument_settings()?;
+    instruments.refresh(&db).await?;
+    let found = portfolio
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .open_positions()
+        .into_iter()
+        .map(|position| Position {
+            price: if position.average_price > 0.0 { position.average_price } else { position.last_price },
+            sec_code: position.sec_code,
+            lots: position.quantity,
+        })
+        .collect();
+    let blocked = existing_positions::apply(&gateway, &instruments, &settings, found).blocked();
+
+    // The dashboard is kept up to date even when it isn't served
+    let dashboard = Dashboard::new(config.mode)
+        .with_clock(clock.clone())
+        .with_throttle(gateway.throttle_stats())
+        .with_metrics(metrics.clone());
+    tasks.push(tokio::spawn(call_stats::run(db.clone(), terminal.clone(), dashboard.clone(), CALL_STATS_PERIOD, clock.clone())));
+
+    let codes: Vec<String> = settings.iter().map(|settings| settings.sec_code.clone()).collect();
+    let churn = ChurnGuard::new(&settings, config.exchange_timezone);
+    let expiry_guard = ExpiryGuard::new(&settings, &db.get_instrument_rows(&codes).await?, config.exchange_timezone);
+    let monitor = PositionAgeMonitor::new(&settings).with_clock(clock.clone());
+    tasks.push(tokio::spawn(position_age::run(portfolio.clone(), monitor, Notifier::from_config(config), settings.clone(), POSITION_AGE_PERIOD)));
+    let atr_period = config.sizing.map_or(DEFAULT_ATR_PERIOD, |sizer| sizer.atr_period);
+    for (strategy, template) in &config.brackets {
+        let wanted = strategies.instruments_of(strategy);
+        if wanted.is_empty() {
+            continue;
+        }
+        let protected = settings.iter().filter(|settings| wanted.contains(&settings.sec_code)).cloned().collect();
+        trading.push(tokio::spawn(exits::run(
+            db.clone(),
+            gateway.clone(),
+            instruments.clone(),
+            portfolio.clone(),
+            ExitManager::new(strategy, *template).with_blocked(blocked.clone()),
+            protected,
+            atr_period,
+            clock.clone(),
+            EXIT_CHECK_PERIOD,
+        )));
+    }
+    let mut holding = Vec::new();
+    for (strategy, rules) in &config.holding {
+        let wanted = strategies.instruments_of(strategy);
+        if wanted.is_empty() {
+            continue;
+        }
+        let held = settings.iter().filter(|settings| wanted.contains(&settings.sec_code)).cloned().collect();
+        let tracker = Arc::new(Mutex::new(HoldingTracker::new(*rules)));
+        trading.push(tokio::spawn(holding::run(
+            db.clone(),
+            gateway.clone(),
+            instruments.clone(),
+            portfolio.clone(),
+            tracker.clone(),
+            held,
+            blocked.clone(),
+            clock.clone(),
+            HOLDING_CHECK_PERIOD,
+        )));
+        holding.push((strategy.clone(), tracker));
+    }
+    // A restarted scheduler starts again from the configuration: it builds the strategies anew,
+    // restores their signal states from `bot_state` and catches up with the changes of the universe
+    let (decisions, received) = mpsc::unbounded_channel();
+    let universe = UniverseChanges::default();
+    tasks.push(tokio::spawn(universe.clone().forward(universe_changes)));
+    let wants_ticks = strategies.wants_ticks();
+    let first_strategies = Mutex::new(Some(strategies));
+    let scheduler_config = Arc::new(config.clone());
+    let (scheduled_db, scheduled, timezone, scheduler_clock) = (db.clone(), terminal.clone(), config.exchange_timezone, clock.clone());
+    let (scheduler_metrics, filter) = (metrics.clone(), CandleFilter::new(config.tick_filter, instruments.clone()));
+    trading.push(tokio::spawn(watchdog.clone().supervise("scheduler", move || {
+        let strategies = first_strategies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .map_or_else(|| StrategySet::from_config(&scheduler_config, &scheduler_metrics.latency).map_err(|e| e.to_string()), Ok);
+        let (tick_sender, ticks) = mpsc::unbounded_channel();
+        let feed = tick_feed::run(scheduled_db.clone(), scheduler_clock.clone(), TICK_POLL_PERIOD, tick_sender);
+        let run = strategies.map(|strategies| {
+            scheduler::run(
+                scheduled_db.clone(),
+                strategies,
+                churn.clone(),
+                expiry_guard.clone(),
+                scheduled.events(),
+                universe.subscribe(),
+                ticks,
+                timezone,
+                scheduler_clock.clone(),
+                stats.clone(),
+                scheduler_metrics.clone(),
+                filter.clone(),
+                decisions.clone(),
+            )
+        });
+        async move {
+            let run = match run {
+                Ok(run) => run,
+                Err(e) => {
+                    error!("Strategies are not built, the candle scheduler is not started: {}", e);
+                    return;
+                }
+            };
+            // Without a strategy evaluating the ticks the feed is dropped, so it doesn't read them
+            if wants_ticks {
+                tokio::select! {
+                    _ = run => {}
+                    _ = feed => {}
+                }
+            } else {
+                run.await
+            }
+        }
+    })));
+
+    // A restarted trader reads the positions from the portfolio kept up to date by `portfolio::run`
+    // and continues with the profit of the day and the blocked instruments of the last one
+    let build_trader = {
+        let (db, config, instruments, portfolio, orders) = (db.clone(), Arc::new(config.clone()), instruments.clone(), portfolio.clone(), orders.clone());
+        let (clock, dashboard, calendar) = (clock.clone(), dashboard.clone(), config.corporate_action_calendar()?);
+        move || -> Result<Trader, String> {
+            let trader = Trader::new(db.clone(), &config, instruments.clone(), portfolio.clone(), orders.clone())
+                .map_err(|e| e.to_string())?
+                .with_clock(clock.clone())
+                .with_dashboard(dashboard.clone())
+                .with_corporate_actions(calendar.clone());
+            Ok(holding.iter().fold(trader, |trader, (strategy, tracker)| trader.with_holding(strategy, tracker.clone())))
+        }
+    };
+    let trader = build_trader()?.with_blocked(blocked);
+    let state = trader.state();
+    let executions = state.clone();
+    let first_trader = Mutex::new(Some(trader));
+    let received = Arc::new(tokio::sync::Mutex::new(received));
+    trading.push(tokio::spawn(watchdog.clone().supervise("trading_loop", move || {
+        let trader = first_trader
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .map_or_else(|| build_trader().map(|trader| trader.with_state(state.clone())), Ok);
+        let received = received.clone();
+        async move {
+            match trader {
+                Ok(trader) => trading_loop::run(trader, &mut *received.lock().await).await,
+                Err(e) => error!("Trader is not built, the trading loop is not started: {}", e),
+            }
+        }
+    })));
+    if settings.iter().any(|settings| settings.expiry_guard_days.is_some()) {
+        let (db, gateway, settings, timezone, clock) = (db.clone(), gateway.clone(), settings.clone(), config.exchange_timezone, clock.clone());
+        trading.push(tokio::spawn(watchdog.clone().supervise("expiry", move || {
+            expiry::run(db.clone(), gateway.clone(), settings.clone(), timezone, clock.clone(), EXPIRY_CHECK_PERIOD)
+        })));
+    }
+    if config.eod.run_after_close {
+        let pipeline = Pipeline::new(db.clone(), config)?.with_gateway(gateway.clone());
+        let (sessions, clock) = (config.sessions.clone(), clock.clone());
+        trading.push(tokio::spawn(watchdog.clone().supervise("eod", move || {
+            eod::run_after_close(pipeline.clone(), sessions.clone(), clock.clone())
+        })));
+    }
+    let (recovery_db, recovered, known_orders) = (db.clone(), orders.clone(), gateway.known_orders());
+    tasks.push(tokio::spawn(async move {
+        match order_recovery::reconcile(&recovery_db, &mut order_events, SNAPSHOT_TIMEOUT).await {
+            Ok(reconciliation) => {
+                let mut recovered = recovered.lock().unwrap_or_else(|e| e.into_inner());
+                for order in &reconciliation.matched {
+                    known_orders.register(order.trans_id);
+                    recovered.adopt(order);
+                }
+            }
+            Err(e) => error!("Error reconciling the order book: {}", e),
+        }
+    }));
+
+    if let Some(addr) = &config.dashboard_addr {
+        let mut health = quik::monitor_health(terminal.clone(), Duration::from_secs(5), clock.clone());
+
+        let panel = RiskPanel {
+            settings,
+            accounts: config.accounts.clone(),
+            max_transactions_per_second: config.max_transactions_per_second,
+            throttle: Some(gateway.throttle_stats()),
+            timezone: config.exchange_timezone,
+            clock: clock.clone(),
+        };
+        tasks.push(tokio::spawn(dashboard::publish_incidents(db.clone(), dashboard.clone(), clock.clone(), INCIDENTS_PERIOD)));
+        tasks.push(tokio::spawn(dashboard::publish_heartbeats(db.clone(), dashboard.clone(), clock.clone(), HEARTBEATS_PERIOD)));
+
+        let (db, publisher) = (db.clone(), dashboard.clone());
+        tasks.push(tokio::spawn(watchdog.clone().supervise("risk_panel", move || {
+            risk::publish(db.clone(), publisher.clone(), panel.clone(), RISK_PANEL_PERIOD)
+        })));
+
+        let publisher = dashboard.clone();
+        tasks.push(tokio::spawn(async move {
+            while health.changed().await.is_ok() {
+                let connection = health.borrow().clone();
+                publisher.set_connection(connection);
+            }
+        }));
+
+        let addr = addr.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = dashboard.serve(addr).await {
+                error!("Dashboard error: {}", e);
+            }
+        }));
+    }
+
+    info!("Running headless, press Ctrl-C to stop");
+    let broken = tokio::select! {
+        signal = shutdown_signal() => {
+            signal?;
+            None
+        }
+        task = breakers.recv() => task,
+    };
+    info!("Shutting down");
+
+    // The orders in flight are settled with the tracker, the gateway and the journal still running
+    for task in trading {
+        task.abort();
+    }
+    // The slices of the plans in flight would race the cancellations of the shutdown
+    executions.stop().await;
+    if let Err(e) = shutdown::run(terminal.clone(), &orders, &Notifier::from_config(config), &config.shutdown).await {
+        error!("Error shutting the terminal down: {}", e);
+    }
+    for task in tasks {
+        task.abort();
+    }
+
+    match broken {
+        Some(task) => Err(format!("task {} keeps failing, the bot is stopped", task).into()),
+        None => Ok(()),
+    }
+}
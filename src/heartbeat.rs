@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::interval;
+use tracing::warn;
+use crate::psql::{Db, Heartbeat};
+use crate::supervisor::ConnectionState;
+
+
+/// Statistics of the last candle cycle of the scheduler, updated by `scheduler::run` and read
+/// by the heartbeat writer.
+#[derive(Debug, Default)]
+pub struct LoopStats {
+    instruments_active: AtomicUsize,
+    latency_micros: AtomicU64,
+}
+
+
+impl LoopStats {
+    /// Records one candle cycle.
+    pub fn record(&self, instruments_active: usize, latency: Duration) {
+        self.instruments_active.store(instruments_active, Ordering::Relaxed);
+        self.latency_micros.store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+
+    pub fn instruments_active(&self) -> usize {
+        self.instruments_active.load(Ordering::Relaxed)
+    }
+
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(self.latency_micros.load(Ordering::Relaxed))
+    }
+}
+
+
+/// Writes a heartbeat row every minute, so uptime, outage windows and the latency
+/// of the candle cycles can be analyzed historically. A gap between the rows is an outage.
+///
+/// # Example of use
+/// ```ignore
+/// let stats = Arc::new(LoopStats::default());
+/// let (state, connection) = watch::channel(ConnectionState::Disconnected);
+/// let supervisor = ConnectionSupervisor::new(terminal.clone(), subscriptions).with_state(state);
+/// tokio::spawn(heartbeat::run(db.clone(), connection, stats.clone()));
+/// ```
+pub async fn run(db: Arc<Db>, state: watch::Receiver<ConnectionState>, stats: Arc<LoopStats>) {
+    let mut ticker = interval(Duration::from_secs(60));
+
+    loop {
+        ticker.tick().await;
+
+        let state = *state.borrow();
+        let latency_ms = stats.latency().as_secs_f64() * 1000.0;
+
+        if let Err(e) = db.insert_heartbeat(state.as_str(), stats.instruments_active() as i32, latency_ms).await {
+            warn!("Error writing heartbeat: {:?}", e);
+        }
+    }
+}
+
+
+/// Share of the minutes of the last `window` with a heartbeat of the connected bot, in percent.
+/// The minutes without a row are outages of the bot itself.
+pub fn uptime(heartbeats: &[Heartbeat], window: chrono::Duration) -> f64 {
+    let connected = heartbeats.iter().filter(|heartbeat| heartbeat.state == ConnectionState::Connected.as_str()).count();
+    (connected as f64 / window.num_minutes().max(1) as f64 * 100.0).min(100.0)
+}
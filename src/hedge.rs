@@ -0,0 +1,442 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use crate::config::InstrumentSettings;
+use crate::execution::Slice;
+use crate::instrument_info::InstrumentInfo;
+use crate::orderbook::OrderBook;
+use crate::psql::DataForEma;
+use crate::strategy::{Action, Decision, Strategy};
+
+
+/// Spot instrument hedged with its futures, a table `[[pairs]]` of the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairSettings {
+    /// Security code of the spot leg, e.g. `SBER`.
+    pub spot: String,
+    /// Security code of the futures leg, e.g. `SRZ6`.
+    pub futures: String,
+    /// Units of the underlying in a futures contract, 100 shares for the SBER futures.
+    pub futures_units: i64,
+    /// Lots of the spot leg of an entry, the futures leg matches its notional.
+    pub spot_lots: i64,
+    pub timeframe_minutes: i64,
+    /// Candles of the spread the z-score is computed over.
+    pub lookback: usize,
+    /// Distance of the spread from its mean in standard deviations which opens the pair.
+    pub entry_z: f64,
+    /// Distance within which the open pair is closed.
+    pub exit_z: f64,
+    /// Distance between `exit_z` and `entry_z` within which half of the open pair is closed,
+    /// `None` closes the whole pair at `exit_z`.
+    pub reduce_z: Option<f64>,
+}
+
+
+impl PairSettings {
+    /// Lots of the futures leg matching the notional of `spot_lots` lots of `spot_lot_size` units,
+    /// at least one contract.
+    pub fn futures_lots(&self, spot_lots: i64, spot_lot_size: i64) -> i64 {
+        let units = spot_lots * spot_lot_size.max(1);
+        ((units as f64 / self.futures_units.max(1) as f64).round() as i64).max(1)
+    }
+
+
+    pub fn timeframe(&self) -> Duration {
+        Duration::from_secs(self.timeframe_minutes as u64 * 60)
+    }
+}
+
+
+/// Position of a pair in the spread `ln(futures) - ln(spot)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpreadPosition {
+    Flat,
+    /// Long futures and short spot, opened on a spread below its mean.
+    Long,
+    /// Short futures and long spot, opened on a spread above its mean.
+    Short,
+}
+
+
+impl SpreadPosition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpreadPosition::Flat => "flat",
+            SpreadPosition::Long => "long",
+            SpreadPosition::Short => "short",
+        }
+    }
+
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "flat" => Some(SpreadPosition::Flat),
+            "long" => Some(SpreadPosition::Long),
+            "short" => Some(SpreadPosition::Short),
+            _ => None,
+        }
+    }
+}
+
+
+/// Fraction of the legs closed once the spread of an open pair is back within `reduce_z`.
+const REDUCE_FRACTION: f64 = 0.5;
+
+
+/// Periods of the closes of a single leg kept while waiting for the other leg, e.g. when
+/// the futures had no trades in a period.
+const PENDING_PERIODS: usize = 3;
+
+
+struct PairState {
+    settings: PairSettings,
+    /// Closes of the spot and the futures legs by the start of the candle period, the boundary
+    /// of the scheduler. A spread is taken once both legs have closed the same period.
+    closes: BTreeMap<DateTime<Utc>, (Option<f64>, Option<f64>)>,
+    spreads: VecDeque<f64>,
+    position: SpreadPosition,
+    /// Whether the open pair has been reduced at `reduce_z`.
+    reduced: bool,
+}
+
+
+impl PairState {
+    fn new(settings: PairSettings) -> Self {
+        PairState {
+            spreads: VecDeque::with_capacity(settings.lookback),
+            settings,
+            closes: BTreeMap::new(),
+            position: SpreadPosition::Flat,
+            reduced: false,
+        }
+    }
+
+
+    /// Records the close of a leg in the period starting at `period_start` and evaluates
+    /// the period once both legs have closed it.
+    fn on_close(&mut self, sec_code: &str, period_start: DateTime<Utc>, close: f64) -> Vec<Decision> {
+        let closes = self.closes.entry(period_start).or_default();
+        if self.settings.spot == sec_code {
+            closes.0 = Some(close);
+        } else {
+            closes.1 = Some(close);
+        }
+        let (Some(spot), Some(futures)) = *closes else {
+            // A period the other leg never closes is dropped with the older ones
+            while self.closes.len() > PENDING_PERIODS {
+                self.closes.pop_first();
+            }
+            return Vec::new();
+        };
+
+        // The period is evaluated once, the earlier periods are not evaluated anymore
+        self.closes = self.closes.split_off(&period_start);
+        self.closes.remove(&period_start);
+        self.next(spot, futures)
+    }
+
+
+    /// Z-score of the latest spread, `None` until the lookback is filled or on a flat spread.
+    fn z_score(&self) -> Option<f64> {
+        if self.spreads.len() < self.settings.lookback {
+            return None;
+        }
+        let count = self.spreads.len() as f64;
+        let mean = self.spreads.iter().sum::<f64>() / count;
+        let deviation = (self.spreads.iter().map(|spread| (spread - mean).powi(2)).sum::<f64>() / count).sqrt();
+        let last = *self.spreads.back()?;
+        (deviation > 0.0).then(|| (last - mean) / deviation)
+    }
+
+
+    fn next(&mut self, spot: f64, futures: f64) -> Vec<Decision> {
+        if spot <= 0.0 || futures <= 0.0 {
+            return Vec::new();
+        }
+
+        if self.spreads.len() == self.settings.lookback {
+            self.spreads.pop_front();
+        }
+        self.spreads.push_back(futures.ln() - spot.ln());
+        let Some(z) = self.z_score() else {
+            return Vec::new();
+        };
+
+        let (position, futures_action, spot_action, reason) = match self.position {
+            SpreadPosition::Flat if z >= self.settings.entry_z => (SpreadPosition::Short, Action::Sell, Action::Buy, "spread_above_mean"),
+            SpreadPosition::Flat if z <= -self.settings.entry_z => (SpreadPosition::Long, Action::Buy, Action::Sell, "spread_below_mean"),
+            SpreadPosition::Long | SpreadPosition::Short if z.abs() <= self.settings.exit_z => {
+                (SpreadPosition::Flat, Action::ClosePosition, Action::ClosePosition, "spread_reverted")
+            }
+            SpreadPosition::Long | SpreadPosition::Short
+                if !self.reduced && self.settings.reduce_z.is_some_and(|reduce_z| z.abs() <= reduce_z) =>
+            {
+                let reduce = Action::ReducePosition(REDUCE_FRACTION);
+                (self.position, reduce, reduce, "spread_reverting")
+            }
+            _ => return Vec::new(),
+        };
+        self.reduced = matches!(futures_action, Action::ReducePosition(_));
+        self.position = position;
+
+        vec![
+            Decision { sec_code: self.settings.futures.clone(), action: futures_action, reason_code: reason.to_string() },
+            Decision { sec_code: self.settings.spot.clone(), action: spot_action, reason_code: reason.to_string() },
+        ]
+    }
+}
+
+
+/// Hedge of a spot instrument with its futures traded on the spread between them: the spread
+/// `ln(futures) - ln(spot)` of the candles closed by both legs is compared with its mean over
+/// `lookback` candles. A spread `entry_z` standard deviations above the mean sells the futures
+/// and buys the spot, one below buys the futures and sells the spot; the pair is closed once
+/// the spread is back within `exit_z`. With `reduce_z` half of the legs are closed earlier,
+/// once the spread is back within it.
+///
+/// The decisions come in pairs, one per leg. The trading loop turns them into the orders of
+/// `orders`, which match the notional of the legs and send each one by the execution policy
+/// of its instrument.
+///
+/// # Example of use
+/// ```ignore
+/// let mut hedge = SpreadHedge::new(&config.pairs);
+/// let decisions = hedge.on_candle("SRZ6", &candle);
+/// // In the trading loop, see `Trader`
+/// let slices = hedge::orders(&config.pairs[0], &decisions[0], &futures, &spot)?;
+/// ```
+pub struct SpreadHedge {
+    pairs: Vec<PairState>,
+    /// Pair of every leg by security code.
+    legs: HashMap<String, usize>,
+}
+
+
+impl SpreadHedge {
+    pub const NAME: &'static str = "spread_hedge";
+
+
+    pub fn new(pairs: &[PairSettings]) -> Self {
+        let legs = pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, pair)| [(pair.spot.clone(), index), (pair.futures.clone(), index)])
+            .collect();
+        SpreadHedge {
+            pairs: pairs.iter().cloned().map(PairState::new).collect(),
+            legs,
+        }
+    }
+}
+
+
+impl Strategy for SpreadHedge {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+
+    fn wants_instruments(&self) -> Vec<String> {
+        let mut instruments: Vec<String> = self.legs.keys().cloned().collect();
+        instruments.sort();
+        instruments
+    }
+
+
+    fn timeframe(&self, sec_code: &str) -> Option<Duration> {
+        self.legs.get(sec_code).map(|index| self.pairs[*index].settings.timeframe())
+    }
+
+
+    /// The z-score waits for `lookback` spreads.
+    fn warm_up_candles(&self, sec_code: &str) -> usize {
+        self.legs.get(sec_code).map_or(0, |index| self.pairs[*index].settings.lookback)
+    }
+
+
+    fn on_candle(&mut self, sec_code: &str, candle: &DataForEma) -> Vec<Decision> {
+        let Some(index) = self.legs.get(sec_code) else {
+            return Vec::new();
+        };
+        self.pairs[*index].on_close(sec_code, candle.period_start, candle.close)
+    }
+
+
+    /// A pair stops with either of its legs, e.g. the expired futures.
+    fn remove_instrument(&mut self, sec_code: &str) {
+        if let Some(index) = self.legs.get(sec_code).copied() {
+            self.legs.retain(|_, pair| *pair != index);
+        }
+    }
+
+
+    /// The state of a pair is kept under its spot leg.
+    fn state(&self, sec_code: &str) -> Option<Value> {
+        let pair = self.pairs.iter().find(|pair| pair.settings.spot == sec_code)?;
+        Some(json!({
+            "futures": pair.settings.futures,
+            "position": pair.position.as_str(),
+            "reduced": pair.reduced,
+            "spreads": pair.spreads.iter().collect::<Vec<_>>(),
+        }))
+    }
+
+
+    fn restore(&mut self, sec_code: &str, state: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(pair) = self.pairs.iter_mut().find(|pair| pair.settings.spot == sec_code) else {
+            return Ok(());
+        };
+        // The state of another futures, e.g. before a roll, starts the pair over
+        if state.get("futures").and_then(Value::as_str) != Some(pair.settings.futures.as_str()) {
+            return Ok(());
+        }
+
+        let position = state
+            .get("position")
+            .and_then(Value::as_str)
+            .and_then(SpreadPosition::parse)
+            .ok_or("malformed spread hedge state")?;
+        let spreads: VecDeque<f64> = state
+            .get("spreads")
+            .and_then(Value::as_array)
+            .ok_or("malformed spread hedge state")?
+            .iter()
+            .filter_map(Value::as_f64)
+            .collect();
+
+        let skip = spreads.len().saturating_sub(pair.settings.lookback);
+        pair.spreads = spreads.into_iter().skip(skip).collect();
+        pair.position = position;
+        pair.reduced = state.get("reduced").and_then(Value::as_bool).unwrap_or(false);
+        Ok(())
+    }
+}
+
+
+/// Leg of a pair as seen by the trading loop.
+pub struct Leg<'a> {
+    pub settings: &'a InstrumentSettings,
+    /// Position in lots, positive for long.
+    pub position: i64,
+    /// Units of the underlying in a lot.
+    pub lot_size: i64,
+    pub book: Option<&'a OrderBook>,
+    /// Parameters of the instrument, the price grid of the limit orders.
+    pub info: Option<&'a InstrumentInfo>,
+}
+
+
+/// Orders of a decision of `SpreadHedge` for its leg. An entry of the spot leg is of `spot_lots`,
+/// an entry of the futures leg of the contracts matching the notional of `spot_lots`, and a close
+/// closes the position of the leg. The orders follow the execution policy of the instrument.
+pub fn orders(pair: &PairSettings, decision: &Decision, futures: &Leg, spot: &Leg) -> Result<Vec<Slice>, Box<dyn std::error::Error>> {
+    let leg = if decision.sec_code == pair.futures { futures } else { spot };
+    let entry_lots = if decision.sec_code == pair.futures {
+        pair.futures_lots(pair.spot_lots, spot.lot_size)
+    } else {
+        pair.spot_lots
+    };
+
+    let Some((side, lots)) = decision.action.to_order(leg.position, entry_lots) else {
+        return Ok(Vec::new());
+    };
+    leg.settings.execution.plan(leg.settings, side, lots, leg.book, leg.info)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn pair() -> PairSettings {
+        PairSettings {
+            spot: "SBER".to_string(),
+            futures: "SRZ6".to_string(),
+            futures_units: 100,
+            spot_lots: 10,
+            timeframe_minutes: 15,
+            lookback: 3,
+            entry_z: 1.0,
+            exit_z: 0.5,
+            reduce_z: None,
+        }
+    }
+
+
+    fn period(minutes: i64) -> DateTime<Utc> {
+        "2026-06-03T07:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+
+    #[test]
+    fn closes_of_the_legs_are_paired_by_the_period() {
+        let mut state = PairState::new(pair());
+
+        state.on_close("SBER", period(0), 300.0);
+        // The futures had no trades at 07:00 and closes the next period first
+        state.on_close("SRZ6", period(15), 30300.0);
+        assert!(state.spreads.is_empty());
+
+        state.on_close("SBER", period(15), 300.0);
+        assert_eq!(state.spreads.len(), 1);
+        assert!((state.spreads[0] - (30300.0f64.ln() - 300.0f64.ln())).abs() < 1e-12);
+        // The unpaired close of 07:00 is dropped with the evaluated period
+        assert!(state.closes.is_empty());
+    }
+
+
+    #[test]
+    fn unpaired_closes_are_not_kept_forever() {
+        let mut state = PairState::new(pair());
+        for minutes in (0..10).map(|n| n * 15) {
+            state.on_close("SBER", period(minutes), 300.0);
+        }
+        assert_eq!(state.closes.len(), PENDING_PERIODS);
+    }
+
+
+    #[test]
+    fn z_score_is_known_once_the_lookback_is_filled() {
+        let mut state = PairState::new(pair());
+        state.spreads.extend([1.0, 2.0]);
+        assert_eq!(state.z_score(), None);
+
+        state.spreads.push_back(3.0);
+        // Mean 2, deviation sqrt(2/3)
+        let z = state.z_score().unwrap();
+        assert!((z - 1.5f64.sqrt()).abs() < 1e-12);
+    }
+
+
+    #[test]
+    fn flat_spread_has_no_z_score() {
+        let mut state = PairState::new(pair());
+        state.spreads.extend([0.5, 0.5, 0.5]);
+        assert_eq!(state.z_score(), None);
+    }
+
+
+    #[test]
+    fn open_pair_is_reduced_before_it_is_closed() {
+        let mut state = PairState::new(PairSettings { reduce_z: Some(0.8), ..pair() });
+        // With the spot at 1 the spread is the log of the futures
+        let mut next = |spread: f64| -> Vec<Action> {
+            state.next(1.0, spread.exp()).into_iter().map(|decision| decision.action).collect()
+        };
+
+        assert!(next(0.0).is_empty());
+        assert!(next(0.0).is_empty());
+        // z = 1.41 above the mean: sell the futures, buy the spot
+        assert_eq!(next(1.0), vec![Action::Sell, Action::Buy]);
+        // z = -0.59, within reduce_z
+        let reduce = Action::ReducePosition(REDUCE_FRACTION);
+        assert_eq!(next(0.1), vec![reduce, reduce]);
+        // z = -0.58, the pair is reduced once
+        assert!(next(0.2).is_empty());
+        // Back at the mean
+        assert_eq!(next(0.15), vec![Action::ClosePosition, Action::ClosePosition]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tracing::{error, warn};
+use crate::clock::Clock;
+use crate::config::InstrumentSettings;
+use crate::existing_positions;
+use crate::gateway::OrderGateway;
+use crate::instrument_info::InstrumentCache;
+use crate::margin::Position;
+use crate::portfolio::{InstrumentPosition, Portfolio};
+use crate::psql::Db;
+use crate::strategy::Action;
+
+
+/// Time-based exit rules of a strategy. Every limit is optional: a position is closed when
+/// any maximum is reached regardless of the price, and the exits requested by the strategy
+/// are held back until all minimums are reached.
+///
+/// # Example of use
+/// ```ignore
+/// let rules = config.holding.get("ema_cross").copied().unwrap_or_default();
+/// let mut positions = HoldingTracker::new(rules);
+/// positions.open("SBER", Utc::now(), true);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HoldingRules {
+    pub max_candles: Option<u32>,
+    pub max_duration: Option<Duration>,
+    pub min_candles: Option<u32>,
+    pub min_duration: Option<Duration>,
+}
+
+
+impl HoldingRules {
+    /// A minimum exceeding the maximum would forbid every exit but the forced one.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let (Some(min), Some(max)) = (self.min_candles, self.max_candles) {
+            if min > max {
+                return Err(format!("min_candles {} exceeds max_candles {}", min, max).into());
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_duration, self.max_duration) {
+            if min > max {
+                return Err(format!("min_minutes {:?} exceeds max_hours {:?}", min, max).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Open position followed by the holding rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldPosition {
+    pub opened_at: DateTime<Utc>,
+    /// Candles closed since the position was opened.
+    pub candles: u32,
+    pub long: bool,
+}
+
+
+impl HeldPosition {
+    fn age(&self, now: DateTime<Utc>) -> Duration {
+        (now - self.opened_at).to_std().unwrap_or_default()
+    }
+}
+
+
+/// Holding periods of the open positions of a strategy by security code.
+///
+/// The positions of the portfolio and the candles closed since their opening are synced into
+/// the tracker, `expired` is called on the scheduled checks to close the positions held too
+/// long and the actions of the strategy are passed through `filter`, so a position isn't
+/// closed too early.
+///
+/// # Example of use
+/// ```ignore
+/// positions.sync(&position);
+/// positions.set_candles("SBER", 3);
+/// let action = positions.filter("SBER", action, Utc::now());
+///
+/// // Scheduled check
+/// for sec_code in positions.expired(Utc::now()) {
+///     close_position(&sec_code)?;
+///     positions.close(&sec_code);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HoldingTracker {
+    rules: HoldingRules,
+    positions: HashMap<String, HeldPosition>,
+}
+
+
+impl HoldingTracker {
+    pub fn new(rules: HoldingRules) -> Self {
+        HoldingTracker {
+            rules,
+            positions: HashMap::new(),
+        }
+    }
+
+
+    /// Records a new position. Increasing an open position keeps its holding period.
+    pub fn open(&mut self, sec_code: &str, opened_at: DateTime<Utc>, long: bool) {
+        match self.positions.get_mut(sec_code) {
+            Some(position) if position.long == long => {}
+            _ => {
+                self.positions.insert(sec_code.to_string(), HeldPosition { opened_at, candles: 0, long });
+            }
+        }
+    }
+
+
+    pub fn close(&mut self, sec_code: &str) {
+        self.positions.remove(sec_code);
+    }
+
+
+    /// Follows the position of the portfolio: opened at its `opened_at`, or closed when flat.
+    pub fn sync(&mut self, position: &InstrumentPosition) {
+        match position.opened_at.filter(|_| position.quantity != 0) {
+            Some(opened_at) => self.open(&position.sec_code, opened_at, position.quantity > 0),
+            None => self.close(&position.sec_code),
+        }
+    }
+
+
+    /// Sets the number of the candles of the instrument closed since the position was opened.
+    pub fn set_candles(&mut self, sec_code: &str, candles: u32) {
+        if let Some(position) = self.positions.get_mut(sec_code) {
+            position.candles = candles;
+        }
+    }
+
+
+    /// Positions which reached the maximum holding period and have to be closed.
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut expired: Vec<String> = self
+            .positions
+            .iter()
+            .filter(|(_, position)| {
+                self.rules.max_candles.is_some_and(|max| position.candles >= max)
+                    || self.rules.max_duration.is_some_and(|max| position.age(now) >= max)
+            })
+            .map(|(sec_code, _)| sec_code.clone())
+            .collect();
+        expired.sort();
+        expired
+    }
+
+
+    /// The position is held at least the minimum holding period.
+    pub fn may_exit(&self, sec_code: &str, now: DateTime<Utc>) -> bool {
+        let Some(position) = self.positions.get(sec_code) else {
+            return true;
+        };
+
+        self.rules.min_candles.is_none_or(|min| position.candles >= min)
+            && self.rules.min_duration.is_none_or(|min| position.age(now) >= min)
+    }
+
+
+    /// Replaces an exit or a reversal of the position before the minimum holding period
+    /// with `DoNothingExplicit`, the other actions are passed through.
+    pub fn filter(&self, sec_code: &str, action: Action, now: DateTime<Utc>) -> Action {
+        let Some(position) = self.positions.get(sec_code) else {
+            return action;
+        };
+
+        let exits = match action {
+            Action::ReducePosition(_) | Action::ClosePosition => true,
+            Action::Buy => !position.long,
+            Action::Sell => position.long,
+            Action::DoNothingExplicit => false,
+        };
+
+        if exits && !self.may_exit(sec_code, now) {
+            Action::DoNothingExplicit
+        } else {
+            action
+        }
+    }
+}
+
+
+/// Holding task of a strategy: every `period` syncs the tracker with the open positions of its
+/// instruments and the candles of their timeframes closed since the opening, and closes the
+/// positions which reached the maximum holding period at the price limit of the session,
+/// see `existing_positions::close`. The minimums are applied by the trading loop.
+///
+/// Instruments which can't send orders (disabled or watch-only) are not closed, and neither are
+/// the `blocked` positions left to the user at the start while they are open.
+///
+/// # Example of use
+/// ```ignore
+/// let tracker = Arc::new(Mutex::new(HoldingTracker::new(rules)));
+/// tokio::spawn(holding::run(db.clone(), gateway.clone(), cache.clone(), portfolio.clone(), tracker, settings, startup.blocked(), clock.clone(), Duration::from_secs(10)));
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    db: Arc<Db>,
+    gateway: Arc<OrderGateway>,
+    instruments: InstrumentCache,
+    portfolio: Arc<RwLock<Portfolio>>,
+    tracker: Arc<Mutex<HoldingTracker>>,
+    settings: Vec<InstrumentSettings>,
+    mut blocked: HashSet<String>,
+    clock: Arc<dyn Clock>,
+    period: Duration,
+) {
+    // Quantity of the positions whose closing order is sent: it is not repeated until it changes
+    let mut sent: HashMap<String, i64> = HashMap::new();
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let now = clock.now();
+        let positions: Vec<InstrumentPosition> = portfolio
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .open_positions()
+            .into_iter()
+            .filter(|position| settings.iter().any(|settings| settings.sec_code == position.sec_code))
+            .collect();
+        blocked.retain(|sec_code| positions.iter().any(|position| &position.sec_code == sec_code));
+        sent.retain(|sec_code, quantity| positions.iter().any(|position| &position.sec_code == sec_code && position.quantity == *quantity));
+
+        let mut candles = Vec::new();
+        for position in &positions {
+            let (Some(opened_at), Some(instrument)) = (position.opened_at, settings.iter().find(|settings| settings.sec_code == position.sec_code)) else {
+                continue;
+            };
+            let timeframe = chrono::Duration::minutes(instrument.timeframe_minutes);
+            match db.get_candles(&position.sec_code, opened_at, now, timeframe.num_seconds() as f64).await {
+                Ok(closed) => candles.push((position.sec_code.clone(), closed.iter().filter(|candle| candle.period_start + timeframe <= now).count() as u32)),
+                Err(e) => error!("Error reading the candles of {}: {}", position.sec_code, e),
+            }
+        }
+
+        let expired = {
+            let mut tracker = tracker.lock().unwrap_or_else(|e| e.into_inner());
+            tracker.positions.retain(|sec_code, _| positions.iter().any(|position| &position.sec_code == sec_code));
+            for position in &positions {
+                tracker.sync(position);
+            }
+            for (sec_code, closed) in &candles {
+                tracker.set_candles(sec_code, *closed);
+            }
+            tracker.expired(now)
+        };
+
+        for sec_code in expired {
+            if blocked.contains(&sec_code) || sent.contains_key(&sec_code) {
+                continue;
+            }
+            let (Some(position), Some(instrument)) = (
+                positions.iter().find(|position| position.sec_code == sec_code),
+                settings.iter().find(|settings| settings.sec_code == sec_code),
+            ) else {
+                continue;
+            };
+            if !instrument.allows_orders() {
+                continue;
+            }
+
+            warn!("Position {} {} lots reached the maximum holding period, closing it", sec_code, position.quantity);
+            let closing = Position {
+                sec_code: sec_code.clone(),
+                lots: position.quantity,
+                price: position.last_price,
+            };
+            match existing_positions::close(&gateway, instrument, instruments.get(&instrument.class_code, &sec_code).as_ref(), &closing) {
+                Ok(()) => {
+                    sent.insert(sec_code, position.quantity);
+                }
+                Err(e) => error!("Error closing the position {}: {}", sec_code, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt::Write as _;
+use crate::psql::{AnomalousTick, CallStatsRecord, DataForEma, ExitRecord, InstrumentRow, ParameterChange, QuikEventRecord};
+
+
+/// Formats the candles as a text table for `quik-rs inspect candles`.
+///
+/// # Example of use
+/// ```ignore
+/// let candles = db.get_data_for_ema("SBER", 3600.0, 300.0).await?;
+/// print!("{}", inspect::candles_table(&candles));
+/// ```
+pub fn candles_table(candles: &[DataForEma]) -> String {
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<30} | {:>15} | {:>15} | {:>15} | {:>15} | {:>15}",
+        "period_start", "open_price", "close_price", "min_price", "max_price", "period_volume"
+    );
+    let _ = writeln!(table, "{:-<30}-+-{:-<15}-+-{:-<15}-+-{:-<15}-+-{:-<15}-+-{:-<15}-", "", "", "", "", "", "");
+
+    for candle in candles {
+        let _ = writeln!(
+            table,
+            "{:<30} | {:>15.6} | {:>15.6} | {:>15.6} | {:>15.6} | {:>15.6}",
+            candle.period_start.to_string(),
+            candle.open,
+            candle.close,
+            candle.low,
+            candle.high,
+            candle.volume
+        );
+    }
+
+    table
+}
+
+
+/// Formats the instruments of `current_trades` as a text table for `quik-rs inspect instruments`.
+///
+/// # Example of use
+/// ```ignore
+/// let rows = db.get_instrument_rows(&["SBER".to_string()]).await?;
+/// print!("{}", inspect::instruments_table(&rows));
+/// ```
+pub fn instruments_table(rows: &[InstrumentRow]) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<12} | {:<12} | {:>6} | {:>10} | {:>12} | {:>8} | {:<20} | {:<20}",
+        "class_code", "sec_code", "lot", "multiplier", "price_step", "decimals", "session_status", "instrument_status"
+    );
+    let _ = writeln!(
+        table,
+        "{:-<12}-+-{:-<12}-+-{:-<6}-+-{:-<10}-+-{:-<12}-+-{:-<8}-+-{:-<20}-+-{:-<20}-",
+        "", "", "", "", "", "", "", ""
+    );
+
+    for row in rows {
+        let _ = writeln!(
+            table,
+            "{:<12} | {:<12} | {:>6} | {:>10} | {:>12} | {:>8} | {:<20} | {:<20}",
+            row.class_code,
+            row.instrument_code,
+            optional(row.lot.map(|lot| lot.to_string())),
+            optional(row.lot_multiplier.map(|multiplier| multiplier.to_string())),
+            optional(row.price_step.map(|step| step.to_string())),
+            optional(row.price_decimals.map(|decimals| decimals.to_string())),
+            optional(row.session_status.clone()),
+            optional(row.instrument_status.clone()),
+        );
+    }
+
+    table
+}
+
+
+/// Formats the ticks dropped or flagged by the tick filter as a text table for
+/// `quik-rs inspect anomalies`.
+///
+/// # Example of use
+/// ```ignore
+/// let ticks = db.get_anomalous_ticks(Utc::now() - chrono::Duration::hours(24)).await?;
+/// print!("{}", inspect::anomalies_table(&ticks));
+/// ```
+pub fn anomalies_table(ticks: &[AnomalousTick]) -> String {
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<30} | {:<12} | {:>15} | {:>15} | {:<7} | {:<40}",
+        "recorded_at", "sec_code", "price", "previous_price", "action", "reason"
+    );
+    let _ = writeln!(table, "{:-<30}-+-{:-<12}-+-{:-<15}-+-{:-<15}-+-{:-<7}-+-{:-<40}-", "", "", "", "", "", "");
+
+    for tick in ticks {
+        let _ = writeln!(
+            table,
+            "{:<30} | {:<12} | {:>15.6} | {:>15} | {:<7} | {:<40}",
+            tick.recorded_at.to_string(),
+            tick.instrument_code,
+            tick.price,
+            tick.previous_price.map_or("-".to_string(), |price| format!("{:.6}", price)),
+            tick.action,
+            tick.reason
+        );
+    }
+
+    table
+}
+
+
+/// Formats the callbacks of the event journal as a text table for `quik-rs inspect events`,
+/// the raw fields last.
+///
+/// # Example of use
+/// ```ignore
+/// let events = db.get_quik_events(Utc::now() - chrono::Duration::hours(1)).await?;
+/// print!("{}", inspect::events_table(&events));
+/// ```
+pub fn events_table(events: &[QuikEventRecord]) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<30} | {:<10} | {:>12} | {:>12} | {:<12} | payload",
+        "received_at", "kind", "trans_id", "order_num", "sec_code"
+    );
+    let _ = writeln!(table, "{:-<30}-+-{:-<10}-+-{:-<12}-+-{:-<12}-+-{:-<12}-+-{:-<7}", "", "", "", "", "", "");
+
+    for event in events {
+        let _ = writeln!(
+            table,
+            "{:<30} | {:<10} | {:>12} | {:>12} | {:<12} | {}",
+            event.received_at.to_string(),
+            event.kind,
+            optional(event.trans_id.map(|trans_id| trans_id.to_string())),
+            optional(event.order_num.map(|order_num| order_num.to_string())),
+            optional(event.sec_code.clone()),
+            event.payload
+        );
+    }
+
+    table
+}
+
+
+/// Formats the persisted aggregates of the Trans2QUIK calls as a text table for
+/// `quik-rs inspect calls`, to follow the degradation of the terminal over the periods.
+///
+/// # Example of use
+/// ```ignore
+/// let stats = db.get_call_stats(Utc::now() - chrono::Duration::hours(24)).await?;
+/// print!("{}", inspect::call_stats_table(&stats));
+/// ```
+pub fn call_stats_table(stats: &[CallStatsRecord]) -> String {
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<30} | {:<40} | {:>8} | {:>8} | {:>10} | {:>10} | results",
+        "period_start", "function", "calls", "failures", "avg_ms", "max_ms"
+    );
+    let _ = writeln!(table, "{:-<30}-+-{:-<40}-+-{:-<8}-+-{:-<8}-+-{:-<10}-+-{:-<10}-+-{:-<7}", "", "", "", "", "", "", "");
+
+    for record in stats {
+        let _ = writeln!(
+            table,
+            "{:<30} | {:<40} | {:>8} | {:>8} | {:>10.1} | {:>10.1} | {}",
+            record.period_start.to_string(),
+            record.function,
+            record.calls,
+            record.failures,
+            record.avg_latency_ms,
+            record.max_latency_ms,
+            record.results
+        );
+    }
+
+    table
+}
+
+
+/// Formats the exits of the exit manager as a text table for `quik-rs inspect exits`.
+///
+/// # Example of use
+/// ```ignore
+/// let exits = db.get_exits(Utc::now() - chrono::Duration::hours(24)).await?;
+/// print!("{}", inspect::exits_table(&exits));
+/// ```
+pub fn exits_table(exits: &[ExitRecord]) -> String {
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<30} | {:<16} | {:<12} | {:<11} | {:>8} | {:>15} | {:>15} | {:>15} | {:>12}",
+        "created_at", "strategy", "sec_code", "reason", "quantity", "entry_price", "trigger_price", "last_price", "trans_id"
+    );
+    let _ = writeln!(
+        table,
+        "{:-<30}-+-{:-<16}-+-{:-<12}-+-{:-<11}-+-{:-<8}-+-{:-<15}-+-{:-<15}-+-{:-<15}-+-{:-<12}-",
+        "", "", "", "", "", "", "", "", ""
+    );
+
+    for exit in exits {
+        let _ = writeln!(
+            table,
+            "{:<30} | {:<16} | {:<12} | {:<11} | {:>8} | {:>15.6} | {:>15.6} | {:>15.6} | {:>12}",
+            exit.created_at.to_string(),
+            exit.strategy,
+            exit.sec_code,
+            exit.reason,
+            exit.quantity,
+            exit.entry_price,
+            exit.trigger_price,
+            exit.last_price,
+            exit.trans_id.map_or("-".to_string(), |trans_id| trans_id.to_string())
+        );
+    }
+
+    table
+}
+
+
+/// Formats the log of the parameter rollouts of a strategy as a text table for `quik-rs tune`.
+///
+/// # Example of use
+/// ```ignore
+/// let changes = db.get_parameter_changes("ema_cross").await?;
+/// print!("{}", inspect::parameter_changes_table(&changes));
+/// ```
+pub fn parameter_changes_table(changes: &[ParameterChange]) -> String {
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<30} | {:<16} | {:<30} | {:>10} | {:>10} | {:<9} | details",
+        "recorded_at", "strategy", "parameter", "live", "candidate", "event"
+    );
+    let _ = writeln!(table, "{:-<30}-+-{:-<16}-+-{:-<30}-+-{:-<10}-+-{:-<10}-+-{:-<9}-+-{:-<7}", "", "", "", "", "", "", "");
+
+    for change in changes {
+        let _ = writeln!(
+            table,
+            "{:<30} | {:<16} | {:<30} | {:>10} | {:>10} | {:<9} | {}",
+            change.recorded_at.to_string(),
+            change.strategy,
+            change.parameter,
+            change.live_value,
+            change.candidate_value,
+            change.event,
+            change.details
+        );
+    }
+
+    table
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use crate::config::InstrumentSettings;
+use crate::domain::ToJson;
+
+
+/// Candles of its timeframe after which the last data of an instrument is stale.
+const STALE_CANDLES: i32 = 2;
+
+
+/// Age of the data of an instrument without a timeframe, e.g. one no strategy evaluates,
+/// after which it is stale.
+const DEFAULT_MAX_AGE: Duration = Duration::minutes(2);
+
+
+/// Lifecycle phase of a watchlist instrument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Not enough candles have been received to warm up the indicators.
+    WarmingUp,
+    /// Indicators are warmed up, the data is fresh and the instrument is subscribed.
+    Ready,
+    /// The last data is older than the allowed age or the subscription is lost.
+    Stale,
+    /// Trading is switched off in the configuration.
+    Disabled,
+}
+
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::WarmingUp => "warming up",
+            Phase::Ready => "ready",
+            Phase::Stale => "stale",
+            Phase::Disabled => "disabled",
+        }
+    }
+}
+
+
+/// A traffic-light value of a single readiness check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Light {
+    Green,
+    Yellow,
+    Red,
+}
+
+
+impl Light {
+    /// Color of the light on the dashboard.
+    pub fn color(&self) -> &'static str {
+        match self {
+            Light::Green => "#2e7d32",
+            Light::Yellow => "#ef6c00",
+            Light::Red => "#c62828",
+        }
+    }
+}
+
+
+impl fmt::Display for Light {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Light::Green => write!(f, "green"),
+            Light::Yellow => write!(f, "yellow"),
+            Light::Red => write!(f, "red"),
+        }
+    }
+}
+
+
+/// A row of the readiness matrix: one light per check for a single instrument.
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    pub sec_code: String,
+    pub phase: Phase,
+    pub warmed_up: Light,
+    pub data_fresh: Light,
+    pub subscribed: Light,
+    pub trading_enabled: Light,
+}
+
+
+impl ToJson for Readiness {
+    fn to_json(&self) -> Value {
+        json!({
+            "phase": self.phase.as_str(),
+            "warmed_up": self.warmed_up.to_string(),
+            "data_fresh": self.data_fresh.to_string(),
+            "subscribed": self.subscribed.to_string(),
+            "trading_enabled": self.trading_enabled.to_string(),
+        })
+    }
+}
+
+
+/// State machine of a watchlist instrument, updated by the trading loop and
+/// read by the user interface.
+#[derive(Debug, Clone)]
+pub struct InstrumentState {
+    pub sec_code: String,
+
+    /// Number of candles required before the indicators produce reliable values.
+    warm_up_candles: usize,
+
+    /// Age of the last data after which it is stale.
+    max_age: Duration,
+
+    /// Number of candles received since startup.
+    candles_received: usize,
+
+    /// Time of the last received data.
+    last_data: Option<DateTime<Utc>>,
+
+    /// Orders and trades of the instrument are subscribed to.
+    subscribed: bool,
+
+    /// Trading of the instrument is enabled.
+    trading_enabled: bool,
+
+    /// Signals of the instrument are produced, but no orders are sent.
+    watch_only: bool,
+}
+
+
+impl InstrumentState {
+    pub fn new(settings: &InstrumentSettings, warm_up_candles: usize, max_age: Duration) -> Self {
+        InstrumentState {
+            sec_code: settings.sec_code.clone(),
+            warm_up_candles,
+            max_age,
+            candles_received: 0,
+            last_data: None,
+            subscribed: false,
+            trading_enabled: settings.enabled,
+            watch_only: settings.watch_only,
+        }
+    }
+
+
+    /// Registers a new candle received at the given time.
+    pub fn on_candle(&mut self, time: DateTime<Utc>) {
+        self.candles_received += 1;
+        self.last_data = Some(time);
+    }
+
+
+    /// Registers the result of the subscription to orders and trades.
+    pub fn set_subscribed(&mut self, subscribed: bool) {
+        self.subscribed = subscribed;
+    }
+
+
+    /// Registers the signal state of the strategies restored from `bot_state`, which is as
+    /// warmed up as before the restart.
+    pub fn set_restored(&mut self) {
+        self.candles_received = self.candles_received.max(self.warm_up_candles);
+    }
+
+
+    pub fn is_warmed_up(&self) -> bool {
+        self.candles_received >= self.warm_up_candles
+    }
+
+
+    pub fn is_data_fresh(&self, now: DateTime<Utc>) -> bool {
+        self.last_data.is_some_and(|time| now - time <= self.max_age)
+    }
+
+
+    /// Computes the current phase of the instrument.
+    pub fn phase(&self, now: DateTime<Utc>) -> Phase {
+        if !self.trading_enabled {
+            Phase::Disabled
+        } else if !self.is_warmed_up() {
+            Phase::WarmingUp
+        } else if !self.subscribed || !self.is_data_fresh(now) {
+            Phase::Stale
+        } else {
+            Phase::Ready
+        }
+    }
+
+
+    /// Builds the readiness row of the instrument.
+    pub fn readiness(&self, now: DateTime<Utc>) -> Readiness {
+        let warmed_up = if self.is_warmed_up() {
+            Light::Green
+        } else if self.candles_received > 0 {
+            Light::Yellow
+        } else {
+            Light::Red
+        };
+
+        let data_fresh = match self.last_data {
+            Some(_) if self.is_data_fresh(now) => Light::Green,
+            // The data arrived but is older than allowed
+            Some(time) if now - time <= self.max_age * 2 => Light::Yellow,
+            _ => Light::Red,
+        };
+
+        Readiness {
+            sec_code: self.sec_code.clone(),
+            phase: self.phase(now),
+            warmed_up,
+            data_fresh,
+            subscribed: if self.subscribed { Light::Green } else { Light::Red },
+            // A watch-only instrument is evaluated, but not traded
+            trading_enabled: match (self.trading_enabled, self.watch_only) {
+                (false, _) => Light::Red,
+                (true, true) => Light::Yellow,
+                (true, false) => Light::Green,
+            },
+        }
+    }
+}
+
+
+/// States of the watchlist instruments by security code, updated by the trading loop and
+/// the connection supervisor and read by the dashboard. The clones share the states.
+///
+/// # Example of use
+/// ```ignore
+/// let instruments = InstrumentStates::default();
+/// instruments.register(&settings, strategies.warm_up_candles(&settings.sec_code), Some(Duration::from_secs(900)));
+/// instruments.record_subscribed(&settings.sec_code, true);
+/// instruments.record_candle(&settings.sec_code, closed_at);
+/// let matrix = instruments.readiness(clock.now());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentStates {
+    states: Arc<Mutex<BTreeMap<String, InstrumentState>>>,
+}
+
+
+impl InstrumentStates {
+    /// Starts tracking an instrument of the watchlist, anew if it was tracked: `warm_up_candles`
+    /// of its `timeframe` warm up the strategies, the data older than `STALE_CANDLES` of them is stale.
+    pub fn register(&self, settings: &InstrumentSettings, warm_up_candles: usize, timeframe: Option<std::time::Duration>) {
+        let max_age = timeframe
+            .and_then(|timeframe| Duration::from_std(timeframe).ok())
+            .map_or(DEFAULT_MAX_AGE, |timeframe| timeframe * STALE_CANDLES);
+        let state = InstrumentState::new(settings, warm_up_candles, max_age);
+        self.states.lock().unwrap_or_else(|e| e.into_inner()).insert(settings.sec_code.clone(), state);
+    }
+
+
+    /// Forgets an instrument removed from the universe.
+    pub fn remove(&self, sec_code: &str) {
+        self.states.lock().unwrap_or_else(|e| e.into_inner()).remove(sec_code);
+    }
+
+
+    fn update(&self, sec_code: &str, f: impl FnOnce(&mut InstrumentState)) {
+        if let Some(state) = self.states.lock().unwrap_or_else(|e| e.into_inner()).get_mut(sec_code) {
+            f(state);
+        }
+    }
+
+
+    /// Records a candle of the instrument evaluated by the strategies, closed at `time`.
+    pub fn record_candle(&self, sec_code: &str, time: DateTime<Utc>) {
+        self.update(sec_code, |state| state.on_candle(time));
+    }
+
+
+    /// Records the signal state of the instrument restored from `bot_state`.
+    pub fn record_restored(&self, sec_code: &str) {
+        self.update(sec_code, InstrumentState::set_restored);
+    }
+
+
+    /// Records whether the orders and the trades of the instrument are subscribed to.
+    pub fn record_subscribed(&self, sec_code: &str, subscribed: bool) {
+        self.update(sec_code, |state| state.set_subscribed(subscribed));
+    }
+
+
+    /// Readiness matrix of the tracked instruments by security code, e.g. for the dashboard.
+    pub fn readiness(&self, now: DateTime<Utc>) -> Vec<Readiness> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner()).values().map(|state| state.readiness(now)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+
+    #[test]
+    fn instrument_is_ready_once_warmed_up_subscribed_and_fresh() {
+        let config = Config::parse("[[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"SBER\"\n").unwrap();
+        let settings = config.instrument_settings().unwrap().remove(0);
+        let now: DateTime<Utc> = "2026-06-03T07:30:00Z".parse().unwrap();
+        let instruments = InstrumentStates::default();
+        // The clones share the states, a separate registry doesn't
+        let shared = instruments.clone();
+        let separate = InstrumentStates::default();
+
+        instruments.register(&settings, 2, Some(std::time::Duration::from_secs(900)));
+        assert_eq!(shared.readiness(now)[0].phase, Phase::WarmingUp);
+        shared.record_candle("SBER", now - Duration::minutes(15));
+        shared.record_candle("SBER", now);
+        assert_eq!(instruments.readiness(now)[0].phase, Phase::Stale);
+        shared.record_subscribed("SBER", true);
+
+        let readiness = instruments.readiness(now);
+        assert_eq!((readiness[0].phase, readiness[0].warmed_up, readiness[0].subscribed), (Phase::Ready, Light::Green, Light::Green));
+        // Two candles of 15 minutes later the data is stale
+        assert_eq!(instruments.readiness(now + Duration::minutes(31))[0].data_fresh, Light::Yellow);
+        assert!(separate.readiness(now).is_empty());
+    }
+}
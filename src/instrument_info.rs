@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{info, warn};
+use quik_rs::transaction::{PriceFormat, Side};
+use crate::psql::{Db, IncidentKind, InstrumentRow, Severity};
+
+
+/// Trading parameters of an instrument used to size and price orders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentInfo {
+    pub class_code: String,
+    pub sec_code: String,
+    /// Number of securities in a lot.
+    pub lot: i32,
+    pub lot_multiplier: Option<i32>,
+    /// Minimum price step.
+    pub price_step: Option<f64>,
+    /// Number of decimals of the price.
+    pub price_decimals: Option<i32>,
+    pub session_status: Option<String>,
+    pub instrument_status: Option<String>,
+    /// Maximum and minimum price of the session.
+    pub price_max: Option<f64>,
+    pub price_min: Option<f64>,
+}
+
+
+impl InstrumentInfo {
+    /// Price grid for the prices of the transactions of the instrument.
+    pub fn price_format(&self) -> PriceFormat {
+        PriceFormat::new(self.price_step, self.price_decimals.and_then(|decimals| usize::try_from(decimals).ok()))
+    }
+
+
+    /// Price limit in the direction of the order, the price of a limit order executed at once
+    /// like a market one: SPBFUT rejects market orders. `None` without the limits.
+    pub fn marketable_price(&self, side: Side) -> Option<f64> {
+        match side {
+            Side::Buy => self.price_max,
+            Side::Sell => self.price_min,
+        }
+        .filter(|price| *price > 0.0)
+    }
+}
+
+
+impl From<InstrumentRow> for InstrumentInfo {
+    fn from(row: InstrumentRow) -> Self {
+        InstrumentInfo {
+            class_code: row.class_code,
+            sec_code: row.instrument_code,
+            lot: row.lot.unwrap_or(1),
+            lot_multiplier: row.lot_multiplier,
+            price_step: row.price_step,
+            price_decimals: row.price_decimals,
+            session_status: row.session_status,
+            instrument_status: row.instrument_status,
+            price_max: row.price_max,
+            price_min: row.price_min,
+        }
+    }
+}
+
+
+/// Change of a parameter of an instrument detected by a refresh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentChange {
+    pub class_code: String,
+    pub sec_code: String,
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+
+impl InstrumentChange {
+    /// A changed lot or price step makes the orders sized with the old values wrong.
+    pub fn affects_orders(&self) -> bool {
+        matches!(self.field, "lot" | "lot_multiplier" | "price_step" | "price_decimals")
+    }
+}
+
+
+impl fmt::Display for InstrumentChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}: {} changed from {} to {}", self.class_code, self.sec_code, self.field, self.old, self.new)
+    }
+}
+
+
+/// Cache of the parameters of the watchlist instruments, shared between the refresh job
+/// and the code sizing the orders.
+///
+/// # Example of use
+/// ```ignore
+/// let cache = InstrumentCache::new(config.instruments.iter().map(|i| (i.class_code.clone(), i.sec_code.clone())).collect());
+/// tokio::spawn(instrument_info::run(db.clone(), cache.clone(), Duration::from_secs(300)));
+/// let lot = cache.get("QJSIM", "SBER").map_or(1, |info| info.lot);
+/// let price_format = cache.get("CETS", "USD000UTSTOM").map(|info| info.price_format());
+/// ```
+#[derive(Debug, Clone)]
+pub struct InstrumentCache {
+    /// Pairs of the class code and the securities code of the watchlist.
+    instruments: Vec<(String, String)>,
+    infos: Arc<RwLock<HashMap<(String, String), InstrumentInfo>>>,
+}
+
+
+impl InstrumentCache {
+    pub fn new(instruments: Vec<(String, String)>) -> Self {
+        InstrumentCache {
+            instruments,
+            infos: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+
+    /// Cache of the given parameters, for the tests.
+    #[cfg(test)]
+    pub fn with_infos(infos: Vec<InstrumentInfo>) -> Self {
+        let infos: HashMap<(String, String), InstrumentInfo> =
+            infos.into_iter().map(|info| ((info.class_code.clone(), info.sec_code.clone()), info)).collect();
+        InstrumentCache {
+            instruments: infos.keys().cloned().collect(),
+            infos: Arc::new(RwLock::new(infos)),
+        }
+    }
+
+
+    pub fn get(&self, class_code: &str, sec_code: &str) -> Option<InstrumentInfo> {
+        self.infos
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(class_code.to_string(), sec_code.to_string()))
+            .cloned()
+    }
+
+
+    /// Parameters of the instrument of any class of the watchlist.
+    pub fn find(&self, sec_code: &str) -> Option<InstrumentInfo> {
+        self.infos
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .find(|info| info.sec_code == sec_code)
+            .cloned()
+    }
+
+
+    /// Reloads the parameters from `current_trades` and returns the changes
+    /// against the cached values. The first load reports no changes.
+    pub async fn refresh(&self, db: &Db) -> Result<Vec<InstrumentChange>, Box<dyn std::error::Error>> {
+        let sec_codes: Vec<String> = self.instruments.iter().map(|(_, sec_code)| sec_code.clone()).collect();
+        let rows = db.get_instrument_rows(&sec_codes).await?;
+
+        let mut changes = Vec::new();
+        let mut infos = self.infos.write().unwrap_or_else(|e| e.into_inner());
+
+        for row in rows {
+            let key = (row.class_code.clone(), row.instrument_code.clone());
+            if !self.instruments.contains(&key) {
+                continue;
+            }
+
+            let info = InstrumentInfo::from(row);
+            if let Some(old) = infos.get(&key) {
+                changes.extend(diff(old, &info));
+            }
+            infos.insert(key, info);
+        }
+
+        Ok(changes)
+    }
+}
+
+
+fn diff(old: &InstrumentInfo, new: &InstrumentInfo) -> Vec<InstrumentChange> {
+    let show = |value: &dyn fmt::Debug| format!("{:?}", value);
+    let fields: [(&'static str, String, String); 6] = [
+        ("lot", old.lot.to_string(), new.lot.to_string()),
+        ("lot_multiplier", show(&old.lot_multiplier), show(&new.lot_multiplier)),
+        ("price_step", show(&old.price_step), show(&new.price_step)),
+        ("price_decimals", show(&old.price_decimals), show(&new.price_decimals)),
+        ("session_status", show(&old.session_status), show(&new.session_status)),
+        ("instrument_status", show(&old.instrument_status), show(&new.instrument_status)),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old_value, new_value)| InstrumentChange {
+            class_code: new.class_code.clone(),
+            sec_code: new.sec_code.clone(),
+            field,
+            old: old_value,
+            new: new_value,
+        })
+        .collect()
+}
+
+
+/// Refreshes the cache every `period`. Changes of the lot or the price step, e.g. after
+/// a split, are recorded as warning incidents, status changes are logged.
+pub async fn run(db: Arc<Db>, cache: InstrumentCache, period: Duration) {
+    let mut ticker = interval(period);
+
+    loop {
+        ticker.tick().await;
+
+        let changes = match cache.refresh(&db).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!("Error refreshing the instrument parameters: {}", e);
+                continue;
+            }
+        };
+
+        for change in changes {
+            if !change.affects_orders() {
+                info!("{}", change);
+                continue;
+            }
+
+            warn!("{}, orders are sized with the new value", change);
+            if let Err(e) = db
+                .insert_incident(Severity::Warning, IncidentKind::InstrumentChange, Some(&change.sec_code), &change.to_string())
+                .await
+            {
+                warn!("Error recording the instrument change: {}", e);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn marketable_price_is_the_limit_in_the_direction_of_the_order() {
+        let mut info = InstrumentInfo {
+            class_code: "SPBFUT".to_string(),
+            sec_code: "SiZ6".to_string(),
+            lot: 1,
+            lot_multiplier: None,
+            price_step: Some(1.0),
+            price_decimals: Some(0),
+            session_status: None,
+            instrument_status: None,
+            price_max: Some(95000.0),
+            price_min: Some(87000.0),
+        };
+
+        assert_eq!(info.marketable_price(Side::Buy), Some(95000.0));
+        assert_eq!(info.marketable_price(Side::Sell), Some(87000.0));
+
+        // The limits are not exported yet
+        info.price_min = Some(0.0);
+        assert_eq!(info.marketable_price(Side::Sell), None);
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::psql::{self, Db};
+
+
+/// Tables of the schema with the columns and their types as reported by `information_schema`.
+const TABLES: &[(&str, &[(&str, &str)])] = &[
+    ("current_trades", &[
+        ("class_code", "character varying"),
+        ("instrument_code", "character varying"),
+        ("lot", "integer"),
+        ("last_price", "numeric"),
+        ("price_step", "numeric"),
+        ("price_decimals", "integer"),
+        ("buy_deposit", "numeric"),
+        ("sell_deposit", "numeric"),
+        ("mat_date", "date"),
+    ]),
+    ("historical_trades", &[
+        ("id", "integer"),
+        ("instrument_code", "character varying"),
+        ("last_price", "numeric"),
+        ("last_volume", "numeric"),
+        ("update_timestamptz", "timestamp with time zone"),
+    ]),
+    ("incidents", &[
+        ("id", "integer"),
+        ("created_at", "timestamp with time zone"),
+        ("severity", "character varying"),
+        ("kind", "character varying"),
+        ("resolved", "boolean"),
+    ]),
+    ("indicator_values", &[
+        ("instrument_code", "character varying"),
+        ("indicator", "character varying"),
+        ("candle_time", "timestamp with time zone"),
+        ("value", "double precision"),
+    ]),
+    ("heartbeats", &[
+        ("recorded_at", "timestamp with time zone"),
+        ("state", "character varying"),
+    ]),
+    ("evaluations", &[
+        ("instrument_code", "character varying"),
+        ("candle_time", "timestamp with time zone"),
+        ("indicator_names", "ARRAY"),
+        ("indicator_values", "ARRAY"),
+        ("decision", "character varying"),
+        ("reason_code", "character varying"),
+    ]),
+    ("schema_version", &[("version", "integer")]),
+    ("fills", &[
+        ("trade_num", "bigint"),
+        ("strategy", "character varying"),
+        ("instrument_code", "character varying"),
+        ("is_sell", "boolean"),
+        ("quantity", "bigint"),
+        ("price", "double precision"),
+        ("commission", "double precision"),
+        ("expected_price", "double precision"),
+        ("executed_at", "timestamp with time zone"),
+    ]),
+    ("quik_events", &[
+        ("received_at", "timestamp with time zone"),
+        ("kind", "character varying"),
+        ("trans_id", "bigint"),
+        ("order_num", "bigint"),
+        ("payload", "jsonb"),
+    ]),
+    ("positions", &[
+        ("sec_code", "character varying"),
+        ("quantity", "bigint"),
+        ("average_price", "double precision"),
+        ("realized_pnl", "double precision"),
+        ("commission", "double precision"),
+        ("slippage", "double precision"),
+        ("last_trade_num", "bigint"),
+        ("opened_at", "timestamp with time zone"),
+        ("account", "character varying"),
+    ]),
+    ("bot_state", &[
+        ("strategy", "character varying"),
+        ("sec_code", "character varying"),
+        ("state", "jsonb"),
+        ("updated_at", "timestamp with time zone"),
+    ]),
+    ("daily_candles", &[
+        ("trade_date", "date"),
+        ("instrument_code", "character varying"),
+        ("close_price", "double precision"),
+        ("volume", "double precision"),
+        ("trades", "bigint"),
+    ]),
+    ("daily_reports", &[
+        ("report_date", "date"),
+        ("instrument_code", "character varying"),
+        ("net_pnl", "double precision"),
+        ("trades", "bigint"),
+        ("max_drawdown", "double precision"),
+    ]),
+    ("positions_archive", &[
+        ("sec_code", "character varying"),
+        ("quantity", "bigint"),
+        ("realized_pnl", "double precision"),
+        ("expiry", "date"),
+        ("archived_at", "timestamp with time zone"),
+    ]),
+    ("exits", &[
+        ("sec_code", "character varying"),
+        ("reason", "character varying"),
+        ("quantity", "bigint"),
+        ("created_at", "timestamp with time zone"),
+    ]),
+    ("money_limits", &[
+        ("client_code", "character varying"),
+        ("currency", "character varying"),
+        ("limit_kind", "integer"),
+        ("current_balance", "numeric"),
+        ("locked", "numeric"),
+    ]),
+    ("depo_limits", &[
+        ("client_code", "character varying"),
+        ("sec_code", "character varying"),
+        ("limit_kind", "integer"),
+        ("current_balance", "bigint"),
+        ("locked_sell", "bigint"),
+    ]),
+    ("backtests", &[
+        ("id", "integer"),
+        ("strategy", "character varying"),
+        ("parameters", "jsonb"),
+        ("net_pnl", "double precision"),
+        ("max_drawdown", "double precision"),
+        ("created_at", "timestamp with time zone"),
+    ]),
+];
+
+/// Indexes the queries of the bot rely on.
+const INDEXES: &[(&str, &str)] = &[
+    ("fills", "fills_pkey"),
+    ("fills", "fills_strategy_executed_at"),
+    ("quik_events", "quik_events_trans_id"),
+    ("quik_events", "quik_events_order_num"),
+    ("quik_events", "quik_events_received_at"),
+    ("positions", "positions_pkey"),
+    ("bot_state", "bot_state_pkey"),
+    ("daily_candles", "daily_candles_pkey"),
+    ("daily_reports", "daily_reports_pkey"),
+    ("backtests", "backtests_strategy_created_at"),
+    ("money_limits", "money_limits_pkey"),
+    ("depo_limits", "depo_limits_pkey"),
+    ("evaluations", "evaluations_pkey"),
+    ("indicator_values", "indicator_values_instrument_code_indicator_candle_time_key"),
+];
+
+
+/// Problem found by the integrity check.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityProblem {
+    MissingTable(&'static str),
+    MissingColumn { table: &'static str, column: &'static str },
+    WrongType { table: &'static str, column: &'static str, expected: &'static str, found: String },
+    MissingIndex { table: &'static str, index: &'static str },
+    /// The schema is older or newer than the version of the bot.
+    SchemaVersion { found: i32, expected: i32 },
+    /// Trades of the event journal whose orders are unknown.
+    TradesWithoutOrders(i64),
+    /// Open positions whose last trade is not recorded.
+    PositionsWithoutTrades(Vec<String>),
+}
+
+
+impl fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityProblem::MissingTable(table) => write!(f, "table {} is missing", table),
+            IntegrityProblem::MissingColumn { table, column } => write!(f, "column {}.{} is missing", table, column),
+            IntegrityProblem::WrongType { table, column, expected, found } => {
+                write!(f, "column {}.{} has type {}, expected {}", table, column, found, expected)
+            }
+            IntegrityProblem::MissingIndex { table, index } => write!(f, "index {} of {} is missing", index, table),
+            IntegrityProblem::SchemaVersion { found, expected } if found < expected => {
+                write!(f, "schema version {} is older than {}, run `quik-rs migrate`", found, expected)
+            }
+            IntegrityProblem::SchemaVersion { found, expected } => {
+                write!(f, "schema version {} is newer than {} supported by this build", found, expected)
+            }
+            IntegrityProblem::TradesWithoutOrders(count) => write!(f, "{} trades in quik_events have no order", count),
+            IntegrityProblem::PositionsWithoutTrades(sec_codes) => {
+                write!(f, "open positions without a recorded trade: {}", sec_codes.join(", "))
+            }
+        }
+    }
+}
+
+
+/// Result of the integrity check.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub problems: Vec<IntegrityProblem>,
+}
+
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "database integrity check passed");
+        }
+
+        write!(f, "database integrity check found {} problems:", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Checks at startup that the tables, the columns with their types and the indexes of the
+/// schema exist, that the schema version matches the bot, and that the data is consistent:
+/// every trade of the event journal has its order and every open position has its last trade.
+///
+/// The bot refuses to trade with problems unless started with `--ignore-integrity`.
+///
+/// # Example of use
+/// ```ignore
+/// let report = integrity::check(&db).await?;
+/// if !report.is_ok() {
+///     return Err(report.to_string().into());
+/// }
+/// ```
+pub async fn check(db: &Db) -> Result<IntegrityReport, Box<dyn std::error::Error>> {
+    let mut report = IntegrityReport::default();
+
+    let mut columns: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (table, column, data_type) in db.get_columns().await? {
+        columns.entry(table).or_default().insert(column, data_type);
+    }
+    let indexes: HashSet<(String, String)> = db.get_indexes().await?.into_iter().collect();
+
+    for (table, expected) in TABLES {
+        let Some(found) = columns.get(*table) else {
+            report.problems.push(IntegrityProblem::MissingTable(table));
+            continue;
+        };
+        for (column, data_type) in expected.iter() {
+            match found.get(*column) {
+                None => report.problems.push(IntegrityProblem::MissingColumn { table, column }),
+                Some(found) if found != data_type => report.problems.push(IntegrityProblem::WrongType {
+                    table,
+                    column,
+                    expected: data_type,
+                    found: found.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+    }
+    for (table, index) in INDEXES {
+        if columns.contains_key(*table) && !indexes.contains(&(table.to_string(), index.to_string())) {
+            report.problems.push(IntegrityProblem::MissingIndex { table, index });
+        }
+    }
+
+    // The data checks need the tables, a broken schema is reported alone
+    if !report.is_ok() {
+        return Ok(report);
+    }
+
+    let version = db.get_schema_version().await?.max(1);
+    if version != psql::SCHEMA_VERSION {
+        report.problems.push(IntegrityProblem::SchemaVersion { found: version, expected: psql::SCHEMA_VERSION });
+    }
+
+    let trades = db.count_trades_without_orders().await?;
+    if trades > 0 {
+        report.problems.push(IntegrityProblem::TradesWithoutOrders(trades));
+    }
+
+    let positions = db.get_positions_without_trades().await?;
+    if !positions.is_empty() {
+        report.problems.push(IntegrityProblem::PositionsWithoutTrades(positions));
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::raw::c_ulong;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+use quik_rs::quik::QuikEvent;
+use quik_rs::transaction::{Transaction, TransactionKind};
+use crate::clock::{Clock, SystemClock};
+use crate::psql::Db;
+
+
+/// Transaction about to be sent, recorded before it leaves the bot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Intent {
+    pub trans_id: c_ulong,
+    pub class_code: String,
+    pub sec_code: String,
+    /// OPERATION of an order, `B` or `S`, empty for the cancellations.
+    pub side: String,
+    /// Quantity in lots, 0 for the cancellations.
+    pub quantity: i64,
+    pub created_at: DateTime<Utc>,
+    /// Text of the transaction as sent to the terminal.
+    pub transaction: String,
+}
+
+
+impl Intent {
+    pub fn new(transaction: &Transaction, created_at: DateTime<Utc>) -> Self {
+        let (side, quantity) = match &transaction.kind {
+            TransactionKind::Order { side, quantity, .. } | TransactionKind::StopOrder { side, quantity, .. } => {
+                (side.operation().to_string(), *quantity)
+            }
+            TransactionKind::Kill { .. } | TransactionKind::KillStop { .. } => (String::new(), 0),
+        };
+
+        Intent {
+            trans_id: transaction.trans_id,
+            class_code: transaction.class_code.clone(),
+            sec_code: transaction.sec_code.clone(),
+            side,
+            quantity,
+            created_at,
+            transaction: transaction.to_string(),
+        }
+    }
+
+
+    fn to_json(&self) -> Value {
+        json!({
+            "intent": {
+                "trans_id": self.trans_id,
+                "class_code": self.class_code,
+                "sec_code": self.sec_code,
+                "side": self.side,
+                "quantity": self.quantity,
+                "created_at": self.created_at.to_rfc3339(),
+                "transaction": self.transaction,
+            }
+        })
+    }
+
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let str = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+
+        Some(Intent {
+            trans_id: value.get("trans_id")?.as_u64()? as c_ulong,
+            class_code: str("class_code")?,
+            sec_code: str("sec_code")?,
+            side: str("side")?,
+            quantity: value.get("quantity")?.as_i64()?,
+            created_at: DateTime::parse_from_rfc3339(&str("created_at")?).ok()?.with_timezone(&Utc),
+            transaction: str("transaction")?,
+        })
+    }
+}
+
+
+/// Write-ahead log of the transactions of the bot: an intent is appended and flushed to disk
+/// before the transaction is sent, and acknowledged once the terminal replies or reports its
+/// order. An intent is settled as well when the terminal refuses to send the transaction,
+/// see `not_sent`, or when it is sent again under a new TRANS_ID, see `supersede`. After
+/// a crash the unacknowledged intents are the transactions which may have been sent without
+/// the bot knowing their outcome.
+///
+/// The log is a file of JSON lines; a damaged line is skipped, and a last line torn by
+/// the crash is cut off, so the next record starts on a line of its own.
+///
+/// # Example of use
+/// ```ignore
+/// // The gateway records the intent of every transaction before it is queued
+/// let intents = IntentLog::open(&config.intent_log_file)?;
+/// let gateway = Arc::new(OrderGateway::new(TransIdAllocator::open(&config.trans_id_file)?, intents, throttle));
+/// tokio::spawn(gateway::run(gateway.clone(), terminal.events()));
+/// let trans_id = gateway.send(order)?;
+/// ```
+pub struct IntentLog {
+    path: String,
+    file: File,
+    pending: BTreeMap<c_ulong, Intent>,
+    clock: Arc<dyn Clock>,
+}
+
+
+impl IntentLog {
+    /// Opens the log in `path`, creating it if missing, and replays it.
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut pending = BTreeMap::new();
+        let mut torn = None;
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                for (number, line) in content.lines().enumerate() {
+                    let Ok(value) = serde_json::from_str::<Value>(line) else {
+                        warn!("Intent log {}: line {} is damaged and skipped", path, number + 1);
+                        continue;
+                    };
+                    if let Some(intent) = value.get("intent").and_then(Intent::from_json) {
+                        pending.insert(intent.trans_id, intent);
+                    } else if let Some(trans_id) = ["ack", "not_sent", "superseded"].iter().find_map(|key| value.get(*key).and_then(Value::as_u64)) {
+                        pending.remove(&(trans_id as c_ulong));
+                    }
+                }
+                if !content.is_empty() && !content.ends_with('\n') {
+                    torn = Some(content.rfind('\n').map_or(0, |end| end + 1) as u64);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                error!("Error reading the intent log {}: {}", path, e);
+                return Err(e.into());
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| {
+            error!("Error opening the intent log {}: {}", path, e);
+            e
+        })?;
+        if let Some(length) = torn {
+            warn!("Intent log {}: the last line is torn and cut off", path);
+            file.set_len(length)?;
+        }
+
+        Ok(IntentLog {
+            path: path.to_string(),
+            file,
+            pending,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+
+    fn append(&mut self, value: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        writeln!(self.file, "{}", value)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| {
+                error!("Error writing the intent log {}: {}", self.path, e);
+                e
+            })?;
+        Ok(())
+    }
+
+
+    /// Records the transaction before it is sent. The transaction must not be sent if this fails.
+    pub fn record(&mut self, transaction: &Transaction) -> Result<(), Box<dyn std::error::Error>> {
+        let intent = Intent::new(transaction, self.clock.now());
+        self.append(&intent.to_json())?;
+        self.pending.insert(intent.trans_id, intent);
+        Ok(())
+    }
+
+
+    /// Acknowledges the intent: the terminal knows the transaction.
+    pub fn confirm(&mut self, trans_id: c_ulong) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.pending.contains_key(&trans_id) {
+            return Ok(());
+        }
+        self.append(&json!({ "ack": trans_id }))?;
+        self.pending.remove(&trans_id);
+        Ok(())
+    }
+
+
+    /// Settles the intent of a transaction the terminal refused to send, e.g. while disconnected:
+    /// the transaction never left the bot.
+    pub fn not_sent(&mut self, trans_id: c_ulong, error: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.pending.contains_key(&trans_id) {
+            return Ok(());
+        }
+        self.append(&json!({ "not_sent": trans_id, "error": error }))?;
+        self.pending.remove(&trans_id);
+        Ok(())
+    }
+
+
+    /// Settles the intent of a transaction given up and sent again as `by`, whose intent
+    /// is the one to follow.
+    pub fn supersede(&mut self, trans_id: c_ulong, by: c_ulong) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.pending.contains_key(&trans_id) {
+            return Ok(());
+        }
+        self.append(&json!({ "superseded": trans_id, "by": by }))?;
+        self.pending.remove(&trans_id);
+        Ok(())
+    }
+
+
+    /// Acknowledges the intents by the transaction replies and the order callbacks.
+    pub fn on_event(&mut self, event: &QuikEvent) -> Result<(), Box<dyn std::error::Error>> {
+        match event {
+            QuikEvent::TransactionReply(reply) => self.confirm(reply.trans_id),
+            QuikEvent::OrderUpdate(order) if order.trans_id != 0 => self.confirm(order.trans_id),
+            _ => Ok(()),
+        }
+    }
+
+
+    /// Intents without an acknowledgement, in the order of the TRANS_IDs.
+    pub fn unconfirmed(&self) -> Vec<&Intent> {
+        self.pending.values().collect()
+    }
+
+
+    /// Rewrites the log with the unacknowledged intents only.
+    pub fn compact(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let temporary = format!("{}.tmp", self.path);
+        let mut content = String::new();
+        for intent in self.pending.values() {
+            content.push_str(&intent.to_json().to_string());
+            content.push('\n');
+        }
+
+        let mut file = File::create(&temporary)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+
+        Ok(())
+    }
+}
+
+
+/// Outcome of the startup reconciliation of the intent log.
+#[derive(Debug, Clone, Default)]
+pub struct Reconciliation {
+    /// Intents found in the event journal with the order number, if any.
+    pub confirmed: Vec<(Intent, Option<i64>)>,
+    /// Intents possibly sent but never confirmed: their orders must be checked in the terminal.
+    pub unknown: Vec<Intent>,
+}
+
+
+impl Reconciliation {
+    pub fn is_ok(&self) -> bool {
+        self.unknown.is_empty()
+    }
+}
+
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "intent log: {} confirmed by the journal, {} unknown", self.confirmed.len(), self.unknown.len())?;
+        for intent in &self.unknown {
+            write!(
+                f,
+                "\n  - TRANS_ID {} {}.{} {} {} at {}",
+                intent.trans_id,
+                intent.class_code,
+                intent.sec_code,
+                intent.side,
+                intent.quantity,
+                intent.created_at.to_rfc3339()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Compares the unacknowledged intents left by a crash with the replies and the orders of the
+/// event journal. The intents found in the journal are acknowledged; the rest may have been sent
+/// without a trace and are reported, so the trading doesn't resume until they are checked.
+///
+/// # Example of use
+/// ```ignore
+/// let mut intents = IntentLog::open(&config.intent_log_file)?;
+/// let reconciliation = intents::reconcile(&mut intents, &db).await?;
+/// if !reconciliation.is_ok() {
+///     return Err(reconciliation.to_string().into());
+/// }
+/// ```
+pub async fn reconcile(log: &mut IntentLog, db: &Db) -> Result<Reconciliation, Box<dyn std::error::Error>> {
+    let trans_ids: Vec<i64> = log.unconfirmed().iter().map(|intent| intent.trans_id as i64).collect();
+    if trans_ids.is_empty() {
+        return Ok(Reconciliation::default());
+    }
+
+    let journal: BTreeMap<i64, Option<i64>> = db.get_acknowledged_trans_ids(&trans_ids).await?.into_iter().collect();
+    let reconciliation = settle(log, &journal)?;
+
+    info!("{}", reconciliation);
+    Ok(reconciliation)
+}
+
+
+/// Acknowledges the unacknowledged intents found in `journal`, the TRANS_IDs of the journal
+/// with their order numbers, and compacts the log.
+fn settle(log: &mut IntentLog, journal: &BTreeMap<i64, Option<i64>>) -> Result<Reconciliation, Box<dyn std::error::Error>> {
+    let mut reconciliation = Reconciliation::default();
+    let intents: Vec<Intent> = log.unconfirmed().into_iter().cloned().collect();
+    for intent in intents {
+        match journal.get(&(intent.trans_id as i64)) {
+            Some(order_num) => {
+                log.confirm(intent.trans_id)?;
+                reconciliation.confirmed.push((intent, *order_num));
+            }
+            None => reconciliation.unknown.push(intent),
+        }
+    }
+    log.compact()?;
+
+    Ok(reconciliation)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quik_rs::transaction::Side;
+    use crate::clock::TestClock;
+
+
+    /// Empty log in the temporary directory named after the test.
+    fn log_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("quik-rs-intent-{}-{}.jsonl", name, std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+
+    fn order(trans_id: c_ulong) -> Transaction {
+        Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, Some(250.0)).with_trans_id(trans_id)
+    }
+
+
+    fn pending(log: &IntentLog) -> Vec<c_ulong> {
+        log.unconfirmed().iter().map(|intent| intent.trans_id).collect()
+    }
+
+
+    #[test]
+    fn intent_is_recorded_at_the_time_of_the_clock() {
+        let path = log_path("clock");
+        let now = "2026-06-03T07:00:00Z".parse().unwrap();
+
+        let mut intents = IntentLog::open(&path).unwrap().with_clock(Arc::new(TestClock::new(now)));
+        intents.record(&Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, None).with_trans_id(7)).unwrap();
+        assert_eq!(intents.unconfirmed()[0].created_at, now);
+
+        // The time survives the replay of the log
+        let replayed = IntentLog::open(&path).unwrap();
+        assert_eq!(replayed.unconfirmed()[0].created_at, now);
+        let _ = std::fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn replay_keeps_only_the_unsettled_intents() {
+        let path = log_path("replay");
+        let mut log = IntentLog::open(&path).unwrap();
+        for trans_id in 1..=5 {
+            log.record(&order(trans_id)).unwrap();
+        }
+        log.confirm(1).unwrap();
+        log.not_sent(2, "not connected").unwrap();
+        log.supersede(3, 5).unwrap();
+
+        assert_eq!(pending(&log), vec![4, 5]);
+        let replayed = IntentLog::open(&path).unwrap();
+        assert_eq!(pending(&replayed), vec![4, 5]);
+        assert_eq!(replayed.unconfirmed()[0], log.unconfirmed()[0]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn torn_last_line_is_cut_off_and_the_log_continues() {
+        let path = log_path("torn");
+        let mut log = IntentLog::open(&path).unwrap();
+        log.record(&order(1)).unwrap();
+        log.record(&order(2)).unwrap();
+        drop(log);
+        // The crash tore the acknowledgement of 1
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"ack\":").unwrap();
+
+        let mut log = IntentLog::open(&path).unwrap();
+        assert_eq!(pending(&log), vec![1, 2]);
+        log.confirm(2).unwrap();
+
+        let replayed = IntentLog::open(&path).unwrap();
+        assert_eq!(pending(&replayed), vec![1]);
+        assert!(std::fs::read_to_string(&path).unwrap().lines().all(|line| serde_json::from_str::<Value>(line).is_ok()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn compaction_leaves_the_unsettled_intents_only() {
+        let path = log_path("compact");
+        let mut log = IntentLog::open(&path).unwrap();
+        for trans_id in 1..=4 {
+            log.record(&order(trans_id)).unwrap();
+        }
+        log.confirm(1).unwrap();
+        log.confirm(3).unwrap();
+
+        log.compact().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        // The log is appended to after the compaction
+        log.confirm(2).unwrap();
+        assert_eq!(pending(&IntentLog::open(&path).unwrap()), vec![4]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+
+    #[test]
+    fn reconciliation_acknowledges_the_intents_found_in_the_journal() {
+        let path = log_path("reconcile");
+        let mut log = IntentLog::open(&path).unwrap();
+        for trans_id in 1..=3 {
+            log.record(&order(trans_id)).unwrap();
+        }
+        let journal = BTreeMap::from([(1, Some(101)), (3, None), (9, Some(109))]);
+
+        let reconciliation = settle(&mut log, &journal).unwrap();
+        assert!(!reconciliation.is_ok());
+        let confirmed: Vec<(c_ulong, Option<i64>)> = reconciliation.confirmed.iter().map(|(intent, order_num)| (intent.trans_id, *order_num)).collect();
+        assert_eq!(confirmed, vec![(1, Some(101)), (3, None)]);
+        assert_eq!(reconciliation.unknown.iter().map(|intent| intent.trans_id).collect::<Vec<_>>(), vec![2]);
+        assert!(reconciliation.to_string().contains("TRANS_ID 2 QJSIM.SBER B 1"));
+        assert_eq!(pending(&IntentLog::open(&path).unwrap()), vec![2]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
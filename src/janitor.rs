@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tokio::time::interval;
+use tracing::{error, warn};
+use quik_rs::quik::{OrderInfo, QuikEvent};
+use quik_rs::transaction::Transaction;
+use crate::clock::{Clock, SystemClock};
+use crate::domain::exchange_time;
+use crate::gateway::OrderGateway;
+use crate::psql::{Db, IncidentKind, Severity};
+use crate::supervisor::KnownOrders;
+
+
+/// Interval between the checks of the working orders.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+
+/// Active order observed through the order callbacks.
+#[derive(Debug, Clone)]
+struct WorkingOrder {
+    order: OrderInfo,
+    /// Time the order was placed, or first seen if the terminal didn't report it.
+    placed_at: DateTime<Utc>,
+    /// The stale order was already reported, a failed cancellation is retried silently.
+    reported: bool,
+}
+
+
+impl WorkingOrder {
+    fn new(order: OrderInfo, now: DateTime<Utc>) -> Self {
+        let placed_at = exchange_time(order.date, order.time).unwrap_or(now);
+        WorkingOrder { order, placed_at, reported: false }
+    }
+
+
+    /// An order without fills that the bot didn't send, e.g. a leftover of a crash.
+    fn is_stale(&self, known_orders: &KnownOrders, now: DateTime<Utc>, max_age: Duration) -> bool {
+        let age = (now - self.placed_at).to_std().unwrap_or_default();
+        let unfilled = self.order.balance == self.order.qty;
+
+        age > max_age && unfilled && !known_orders.is_known(self.order.trans_id)
+    }
+}
+
+
+/// Janitor of the working orders: cancels the active orders older than `max_age` which have
+/// no fills and were not sent by the bot, and records an incident for each of them, so
+/// the order book of the terminal stays consistent with the state of the bot.
+///
+/// # Example of use
+/// ```ignore
+/// let janitor = OrderJanitor::new(gateway.clone(), db.clone(), Duration::from_secs(30 * 60));
+/// tokio::spawn(janitor.run(terminal.events()));
+/// ```
+pub struct OrderJanitor {
+    /// Sends the cancellations, the orders it sent are not stale.
+    gateway: Arc<OrderGateway>,
+    db: Arc<Db>,
+    max_age: Duration,
+
+    /// Active orders by order number.
+    orders: HashMap<u64, WorkingOrder>,
+
+    clock: Arc<dyn Clock>,
+}
+
+
+impl OrderJanitor {
+    pub fn new(gateway: Arc<OrderGateway>, db: Arc<Db>, max_age: Duration) -> Self {
+        OrderJanitor {
+            gateway,
+            db,
+            max_age,
+            orders: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+
+    /// Runs the janitor until the task is cancelled. Only the order events are taken into account.
+    pub async fn run(mut self, mut events: mpsc::UnboundedReceiver<QuikEvent>) {
+        let mut ticker = interval(CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(QuikEvent::OrderUpdate(order)) => self.track(order),
+                    Some(_) => {}
+                    None => {
+                        warn!("Order events are closed, the janitor is stopped");
+                        return;
+                    }
+                },
+                _ = ticker.tick() => self.sweep().await,
+            }
+        }
+    }
+
+
+    fn track(&mut self, order: OrderInfo) {
+        match order.mode {
+            // The end of the initial snapshot
+            2 => {}
+            // 1 - active, the other statuses are final
+            _ if order.status == 1 => {
+                let order_num = order.order_num;
+                match self.orders.get_mut(&order_num) {
+                    Some(working) => working.order = order,
+                    None => {
+                        self.orders.insert(order_num, WorkingOrder::new(order, self.clock.now()));
+                    }
+                }
+            }
+            _ => {
+                self.orders.remove(&order.order_num);
+            }
+        }
+    }
+
+
+    /// Cancels the stale orders. An order stays tracked until its cancellation is reported
+    /// by the order callback, so a failed cancellation is retried on the next check.
+    async fn sweep(&mut self) {
+        let now = self.clock.now();
+        let known_orders = self.gateway.known_orders();
+        let stale: Vec<u64> = self
+            .orders
+            .iter()
+            .filter(|(_, working)| working.is_stale(&known_orders, now, self.max_age))
+            .map(|(order_num, _)| *order_num)
+            .collect();
+
+        for order_num in stale {
+            let Some(working) = self.orders.get_mut(&order_num) else {
+                continue;
+            };
+            let order = working.order.clone();
+            let placed_at = working.placed_at;
+            let first_attempt = !working.reported;
+            working.reported = true;
+
+            // The error is not Send, keep only its message across the awaits
+            let result = self.cancel(&order).map_err(|e| e.to_string());
+            if !first_attempt {
+                if let Err(e) = result {
+                    warn!("Stale order {} is still not cancelled: {}", order_num, e);
+                }
+                continue;
+            }
+
+            let age = (now - placed_at).num_minutes();
+            let message = match &result {
+                Ok(()) => format!("stale order {} {} placed {} min ago without fills is being cancelled", order_num, order.sec_code, age),
+                Err(e) => format!("stale order {} {} placed {} min ago without fills is not cancelled: {}", order_num, order.sec_code, age, e),
+            };
+            warn!("{}", message);
+
+            let severity = if result.is_ok() { Severity::Warning } else { Severity::Critical };
+            if let Err(e) = self
+                .db
+                .insert_incident(severity, IncidentKind::StaleOrder, Some(&order.sec_code), &message)
+                .await
+            {
+                error!("Error recording the stale order {}: {}", order_num, e);
+            }
+        }
+    }
+
+
+    /// Sends the cancellation, its outcome arrives as the reply and the order callback.
+    fn cancel(&self, order: &OrderInfo) -> Result<(), Box<dyn std::error::Error>> {
+        self.gateway.send(Transaction::kill_order(&order.class_code, &order.sec_code, order.order_num))?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+
+
+    #[test]
+    fn old_unfilled_order_of_another_sender_is_stale() {
+        let clock = TestClock::new("2026-06-03T07:00:00Z".parse().unwrap());
+        let max_age = Duration::from_secs(30 * 60);
+        let known_orders = KnownOrders::default();
+        // 10:00 in Moscow, placed with the clock
+        let order = OrderInfo { trans_id: 7, order_num: 100, sec_code: "SBER".to_string(), qty: 2, balance: 2, status: 1, date: 20260603, time: 100000, ..Default::default() };
+        let working = WorkingOrder::new(order, clock.now());
+
+        clock.advance(chrono::Duration::minutes(30));
+        assert!(!working.is_stale(&known_orders, clock.now(), max_age));
+        clock.advance(chrono::Duration::minutes(1));
+        assert!(working.is_stale(&known_orders, clock.now(), max_age));
+
+        let mut filled = working.clone();
+        filled.order.balance = 1;
+        assert!(!filled.is_stale(&known_orders, clock.now(), max_age));
+
+        known_orders.register(7);
+        assert!(!working.is_stale(&known_orders, clock.now(), max_age));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use quik_rs::quik::QuikEvent;
+use crate::clock::Clock;
+use crate::psql::{Db, QuikEventRecord};
+
+
+/// Converts an event to a journal record with all of its fields as JSON.
+pub fn record(event: &QuikEvent, received_at: DateTime<Utc>) -> QuikEventRecord {
+    match event {
+        QuikEvent::ConnectionStatus(event) => QuikEventRecord {
+            received_at,
+            kind: "connection".to_string(),
+            trans_id: None,
+            order_num: None,
+            sec_code: None,
+            payload: json!({
+                "status": format!("{:?}", event.status),
+                "error_code": event.error_code,
+                "info_message": event.info_message,
+            })
+            .to_string(),
+        },
+        QuikEvent::TransactionReply(reply) => QuikEventRecord {
+            received_at,
+            kind: "reply".to_string(),
+            trans_id: Some(reply.trans_id as i64),
+            order_num: Some(reply.order_num as i64),
+            sec_code: Some(reply.sec_code.clone()),
+            payload: json!({
+                "trans2quik_result": format!("{:?}", reply.trans2quik_result),
+                "error_code": reply.error_code,
+                "reply_code": reply.reply_code,
+                "status": format!("{:?}", reply.status),
+                "trans_id": reply.trans_id,
+                "order_num": reply.order_num,
+                "reply_message": reply.reply_message,
+                "class_code": reply.class_code,
+                "sec_code": reply.sec_code,
+                "price": reply.price,
+                "quantity": reply.quantity,
+                "balance": reply.balance,
+                "firm_id": reply.firm_id,
+                "account": reply.account,
+                "client_code": reply.client_code,
+                "broker_ref": reply.broker_ref,
+                "exchange_code": reply.exchange_code,
+            })
+            .to_string(),
+        },
+        QuikEvent::OrderUpdate(order) => QuikEventRecord {
+            received_at,
+            kind: "order".to_string(),
+            trans_id: Some(order.trans_id as i64),
+            order_num: Some(order.order_num as i64),
+            sec_code: Some(order.sec_code.clone()),
+            payload: json!({
+                "mode": order.mode,
+                "trans_id": order.trans_id,
+                "order_num": order.order_num,
+                "class_code": order.class_code,
+                "sec_code": order.sec_code,
+                "price": order.price,
+                "balance": order.balance,
+                "value": order.value,
+                "is_sell": order.is_sell,
+                "status": order.status,
+                "qty": order.qty,
+                "date": order.date,
+                "time": order.time,
+                "activation_time": order.activation_time,
+                "withdraw_time": order.withdraw_time,
+                "expiry": order.expiry,
+                "accrued_int": order.accrued_int,
+                "yield": order.yield_value,
+                "uid": order.uid,
+                "visible_qty": order.visible_qty,
+                "period": order.period,
+                "awg_price": order.awg_price,
+                "user_id": order.user_id,
+                "account": order.account,
+                "broker_ref": order.broker_ref,
+                "client_code": order.client_code,
+                "firm_id": order.firm_id,
+                "reject_reason": order.reject_reason,
+            })
+            .to_string(),
+        },
+        QuikEvent::TradeUpdate(trade) => QuikEventRecord {
+            received_at,
+            kind: "trade".to_string(),
+            trans_id: None,
+            order_num: Some(trade.order_num as i64),
+            sec_code: Some(trade.sec_code.clone()),
+            payload: json!({
+                "mode": trade.mode,
+                "trade_num": trade.trade_num,
+                "order_num": trade.order_num,
+                "class_code": trade.class_code,
+                "sec_code": trade.sec_code,
+                "price": trade.price,
+                "qty": trade.qty,
+                "value": trade.value,
+                "is_sell": trade.is_sell,
+                "date": trade.date,
+                "settle_date": trade.settle_date,
+                "time": trade.time,
+                "is_marginal": trade.is_marginal,
+                "accrued_int": trade.accrued_int,
+                "yield": trade.yield_value,
+                "ts_commission": trade.ts_commission,
+                "clearing_center_commission": trade.clearing_center_commission,
+                "exchange_commission": trade.exchange_commission,
+                "trading_system_commission": trade.trading_system_commission,
+                "broker_commission": trade.broker_commission,
+                "kind": trade.kind,
+                "currency": trade.currency,
+                "settle_currency": trade.settle_currency,
+                "settle_code": trade.settle_code,
+                "account": trade.account,
+                "broker_ref": trade.broker_ref,
+                "client_code": trade.client_code,
+                "user_id": trade.user_id,
+                "firm_id": trade.firm_id,
+                "exchange_code": trade.exchange_code,
+            })
+            .to_string(),
+        },
+    }
+}
+
+
+/// Writes every callback event of the terminal to the `quik_events` table, in the order
+/// of arrival, until the channel is closed. The time of arrival is taken when the event
+/// is received from the channel, so it is not delayed by the writes.
+///
+/// # Example of use
+/// ```ignore
+/// tokio::spawn(journal::run(db.clone(), terminal.events(), clock.clone()));
+/// ```
+pub async fn run(db: Arc<Db>, mut events: mpsc::UnboundedReceiver<QuikEvent>, clock: Arc<dyn Clock>) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    // Stamp the events as soon as they arrive, the writer may lag behind the callbacks
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if sender.send(record(&event, clock.now())).is_err() {
+                return;
+            }
+        }
+    });
+
+    while let Some(record) = receiver.recv().await {
+        if let Err(e) = db.insert_quik_event(&record).await {
+            error!("Error journaling the QUIK event {}: {}", record.payload, e);
+        }
+    }
+
+    info!("QUIK events are closed, the journal is stopped");
+}
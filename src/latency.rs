@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::domain::ToJson;
+
+
+/// Upper bounds of the buckets of the latency histograms, the last bucket is unbounded.
+pub const BUCKETS: [Duration; 8] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+];
+
+
+/// Stage of a cycle of the trading loop after a candle close.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// Reading the closed candle from the database.
+    CandleQuery,
+    /// Feeding the close price to the EMAs.
+    EmaCalc,
+    /// Updating the crossover signals with the new EMA values.
+    SignalUpdate,
+    /// From queueing a transaction to sending it to the terminal, including the throttling.
+    TransactionSend,
+    /// Saving the signal states to the database.
+    DbInsert,
+    /// The whole cycle of a candle close.
+    Cycle,
+}
+
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::CandleQuery => "candle_query",
+            Stage::EmaCalc => "ema_calc",
+            Stage::SignalUpdate => "signal_update",
+            Stage::TransactionSend => "transaction_send",
+            Stage::DbInsert => "db_insert",
+            Stage::Cycle => "cycle",
+        }
+    }
+}
+
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+
+/// Histogram of the latency of a stage since the start of the bot.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyHistogram {
+    /// Number of the samples by bucket of `BUCKETS`, the last one counts the slower samples.
+    pub buckets: [u64; BUCKETS.len() + 1],
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = BUCKETS.iter().position(|bound| latency <= *bound).unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.count as u32
+    }
+
+
+    /// Upper bound of the bucket of the quantile, e.g. 0.99, the maximum for the last bucket.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let rank = (self.count as f64 * quantile.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKETS.get(bucket).map_or(self.max, |bound| (*bound).min(self.max));
+            }
+        }
+        self.max
+    }
+}
+
+
+impl ToJson for LatencyHistogram {
+    fn to_json(&self) -> Value {
+        json!({
+            "count": self.count,
+            "avg_ms": self.average().as_secs_f64() * 1000.0,
+            "p50_ms": self.quantile(0.5).as_secs_f64() * 1000.0,
+            "p99_ms": self.quantile(0.99).as_secs_f64() * 1000.0,
+            "max_ms": self.max.as_secs_f64() * 1000.0,
+            "buckets": self.buckets.to_vec(),
+        })
+    }
+}
+
+
+/// Latency histograms of the stages of the trading loop, shared by the loop, the strategies and
+/// the transaction queue. The clones share the histograms.
+///
+/// # Example of use
+/// ```ignore
+/// let latency = LatencyStats::default();
+/// let signals = latency.time(Stage::SignalUpdate, || signal.next(fast, slow, close));
+/// latency.record(Stage::CandleQuery, started.elapsed());
+/// let p99 = latency.stats().get(&Stage::Cycle).map(|histogram| histogram.quantile(0.99));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    histograms: Arc<Mutex<BTreeMap<Stage, LatencyHistogram>>>,
+}
+
+
+impl LatencyStats {
+    /// Records a sample of the latency of the stage.
+    pub fn record(&self, stage: Stage, latency: Duration) {
+        let mut histograms = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        histograms.entry(stage).or_default().record(latency);
+    }
+
+
+    /// Runs the closure and records its latency under the stage.
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let value = f();
+        self.record(stage, started.elapsed());
+        value
+    }
+
+
+    /// Latency histograms of the stages, e.g. for the dashboard.
+    pub fn stats(&self) -> BTreeMap<Stage, LatencyHistogram> {
+        self.histograms.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
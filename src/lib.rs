@@ -0,0 +1,39 @@
+//! Safe wrapper over the library Trans2QUIK.dll of the QUIK terminal: loading
+//! the library, connecting to the terminal, sending transactions and receiving
+//! orders, trades and transaction replies as `QuikEvent`.
+//!
+//! The trading bot in `main.rs` is built on top of this library, other programs
+//! can use it without the bot.
+//!
+//! # Example of use
+//! ```no_run
+//! # use quik_rs::transaction::Side;
+//! # use tracing::info;
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let terminal = quik_rs::Terminal::new(r"c:\QUIK Junior\trans2quik.dll", r"c:\QUIK Junior")?;
+//! terminal.connect()?;
+//! let mut events = terminal.events();
+//! let _subscription = terminal.subscribe("QJSIM", "SBER")?;
+//! let order = quik_rs::Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, None).with_trans_id(1);
+//! terminal.send_sync_transaction(&order.to_string())?;
+//! while let Some(event) = events.recv().await {
+//!     info!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+
+pub mod clock;
+pub mod cp1251;
+pub mod mock;
+pub mod quik;
+pub mod transaction;
+
+
+pub use mock::MockTerminal;
+pub use quik::{
+    Batch, BatchOutcome, BatchReport, CallStats, Capabilities, ConnectionHealth, FunctionStats, OrderInfo, QuikApi, QuikEvent, ReplyStatus, Subscription, Terminal, TradeInfo,
+    Trans2QuikError, Trans2quikResult, TransactionReply,
+};
+pub use transaction::Transaction;
@@ -1,35 +1,432 @@
-use tracing_subscriber::{self, fmt::TestWriter};
-use tracing::{info, error};
-mod quik;
-mod trader;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use quik_rs::{clock, mock, quik};
+use quik_rs::quik::QuikApi;
+use domain::ToJson;
+
 mod psql;
-mod ema;
+mod config;
+mod instrument;
+mod orderbook;
+mod supervisor;
+mod expression;
+mod corporate_actions;
+mod summary;
+mod diagnostics;
+mod margin;
+mod bracket;
+mod heartbeat;
+mod strategy;
+mod secrets;
+mod migrate;
+mod throttle;
+mod tuning;
+mod benchmark;
+mod tick_filter;
+mod instrument_info;
+mod dashboard;
+mod tearsheet;
+mod crossover;
+mod janitor;
+mod journal;
+mod call_stats;
+mod existing_positions;
+mod holding;
+mod headless;
+mod portfolio;
+mod signal_export;
+mod spread;
+mod exits;
+mod orders;
+mod integrity;
+mod scheduler;
+mod notifier;
+mod golden_cross;
+mod intents;
+mod shutdown;
+mod backtest;
+mod chart;
+mod position_age;
+mod universe;
+mod daily_target;
+mod inspect;
+mod sizing;
+mod account;
+mod churn;
+mod domain;
+mod retry;
+mod order_recovery;
+mod expiry;
+mod task_health;
+mod risk;
+mod bot_state;
+mod latency;
+mod eod;
+mod session_report;
+mod execution;
+mod watchdog;
+mod hedge;
+mod gateway;
+mod trading_loop;
+mod metrics;
+mod tick_feed;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
+    let _log_guard = diagnostics::init_logging();
+
+    let config_path = "config.toml";
+    match std::env::args().nth(1).as_deref() {
+        Some("collect-diagnostics") => {
+            diagnostics::collect(config_path)?;
+            return Ok(());
+        }
+        Some("encrypt-secret") => {
+            // The secret is read from stdin, so it does not end up in the shell history
+            let passphrase = std::env::var(secrets::PASSPHRASE_VAR)
+                .map_err(|_| format!("{} must be set", secrets::PASSPHRASE_VAR))?;
+            let mut secret = String::new();
+            std::io::stdin().read_line(&mut secret)?;
+            println!("{}", secrets::encrypt(secret.trim_end_matches(['\r', '\n']), &passphrase)?);
+            return Ok(());
+        }
+        Some("migrate") => {
+            let config = config::Config::load(config_path)?;
+            let database = psql::Db::new(&config.connection_str).await?;
+            migrate::run(&database, &config).await?;
+            return Ok(());
+        }
+        Some("db-benchmark") => {
+            // quik-rs db-benchmark [ticks per second of an instrument] [seconds]
+            let mut args = std::env::args().skip(2);
+            let ticks_per_second = args.next().map(|value| value.parse()).transpose()?.unwrap_or(1.0);
+            let seconds = args.next().map(|value| value.parse()).transpose()?.unwrap_or(60);
+
+            let config = config::Config::load(config_path)?;
+            let database = Arc::new(psql::Db::new(&config.connection_str).await?);
+            let profile = benchmark::LoadProfile {
+                instruments: config.instruments.len(),
+                ticks_per_second,
+                duration: std::time::Duration::from_secs(seconds),
+            };
+            println!("{}", benchmark::run(database, profile).await?);
+            return Ok(());
+        }
+        Some("tear-sheet") => {
+            // quik-rs tear-sheet <strategy> <from YYYY-MM-DD> <to YYYY-MM-DD> [capital] [file]
+            let mut args = std::env::args().skip(2);
+            let usage = "usage: quik-rs tear-sheet <strategy> <from YYYY-MM-DD> <to YYYY-MM-DD> [capital] [file]";
+            let strategy = args.next().ok_or(usage)?;
+            let from: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let to: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let capital = args.next().map(|value| value.parse()).transpose()?.unwrap_or(1_000_000.0);
+            let file = args.next().unwrap_or_else(|| format!("tear-sheet-{}-{}-{}.html", strategy, from, to));
+
+            let config = config::Config::load(config_path)?;
+            let database = psql::Db::new(&config.connection_str).await?;
+            let sheet = tearsheet::TearSheet::load(&database, &strategy, from, to, capital).await?;
+            std::fs::write(&file, sheet.render_html(clock::Clock::now(&clock::SystemClock)))?;
+            println!("{}: {} fills, net profit {:.2}, tear sheet is written to {}", strategy, sheet.stats.fills, sheet.stats.net_pnl, file);
+            return Ok(());
+        }
+        Some("chart") => {
+            // quik-rs chart <sec_code> <from YYYY-MM-DD> <to YYYY-MM-DD> [file]
+            let mut args = std::env::args().skip(2);
+            let usage = "usage: quik-rs chart <sec_code> <from YYYY-MM-DD> <to YYYY-MM-DD> [file]";
+            let sec_code = args.next().ok_or(usage)?;
+            let from: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let to: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let file = args.next().unwrap_or_else(|| format!("chart-{}-{}-{}.html", sec_code, from, to));
+
+            let config = config::Config::load(config_path)?;
+            let timeframe = config
+                .instrument_settings()?
+                .into_iter()
+                .find(|settings| settings.sec_code == sec_code)
+                .map_or(std::time::Duration::from_secs(15 * 60), |settings| settings.timeframe());
+            let database = psql::Db::new(&config.connection_str).await?;
+            let chart = chart::Chart::load(&database, &sec_code, timeframe, from, to).await?;
+            std::fs::write(&file, chart.render_html(clock::Clock::now(&clock::SystemClock)))?;
+            println!("{}: {} candles, chart is written to {}", sec_code, chart.candles.len(), file);
+            return Ok(());
+        }
+        Some("backtest") => {
+            // quik-rs backtest <strategy> <from YYYY-MM-DD> <to YYYY-MM-DD> [capital] [file]
+            let mut args = std::env::args().skip(2);
+            let usage = "usage: quik-rs backtest <strategy> <from YYYY-MM-DD> <to YYYY-MM-DD> [capital] [file]";
+            let strategy = args.next().ok_or(usage)?;
+            let from: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let to: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+
+            let config = config::Config::load(config_path)?;
+            let mut settings = config.backtest;
+            if let Some(capital) = args.next() {
+                settings.capital = capital.parse()?;
+            }
+            let file = args.next().unwrap_or_else(|| format!("backtest-{}-{}-{}.html", strategy, from, to));
+
+            let database = psql::Db::new(&config.connection_str).await?;
+            database.init().await?;
+            let (result, id) = backtest::run(&database, &config, &strategy, from, to, settings, &clock::SystemClock).await?;
+            std::fs::write(&file, result.sheet.render_html(clock::Clock::now(&clock::SystemClock)))?;
+            println!("{}\nbacktest {} is stored, tear sheet is written to {}", result, id, file);
+            return Ok(());
+        }
+        Some("inspect") => {
+            // quik-rs inspect instruments | quik-rs inspect candles <sec_code> [hours], --json prints JSON
+            // quik-rs inspect anomalies [hours] | quik-rs inspect events [hours] | quik-rs inspect calls [hours]
+            // quik-rs inspect exits [hours]
+            let json = std::env::args().any(|arg| arg == "--json");
+            let mut args = std::env::args().skip(2).filter(|arg| arg != "--json");
+            let usage = "usage: quik-rs inspect instruments | quik-rs inspect candles <sec_code> [hours] [--json] | quik-rs inspect anomalies [hours] | quik-rs inspect events [hours] | quik-rs inspect calls [hours] | quik-rs inspect exits [hours]";
+            let config = config::Config::load(config_path)?;
+            let database = psql::Db::new(&config.connection_str).await?;
+
+            match args.next().as_deref() {
+                Some("instruments") => {
+                    let sec_codes: Vec<String> = config.instruments.iter().map(|instrument| instrument.sec_code.clone()).collect();
+                    let rows = database.get_instrument_rows(&sec_codes).await?;
+                    if json {
+                        let instruments: Vec<domain::Instrument> = rows.iter().map(domain::Instrument::from).collect();
+                        println!("{}", instruments.to_json());
+                    } else {
+                        print!("{}", inspect::instruments_table(&rows));
+                    }
+                }
+                Some("candles") => {
+                    let sec_code = args.next().ok_or(usage)?;
+                    let hours: f64 = args.next().map(|value| value.parse()).transpose()?.unwrap_or(24.0);
+                    let timeframe = config
+                        .instrument_settings()?
+                        .into_iter()
+                        .find(|settings| settings.sec_code == sec_code)
+                        .map_or(std::time::Duration::from_secs(15 * 60), |settings| settings.timeframe());
+                    let candles = database.get_data_for_ema(&sec_code, hours * 3600.0, timeframe.as_secs_f64()).await?;
+                    if json {
+                        let candles: Vec<domain::Candle> = candles.iter().map(domain::Candle::from).collect();
+                        println!("{}", candles.to_json());
+                    } else {
+                        print!("{}", inspect::candles_table(&candles));
+                    }
+                }
+                Some("anomalies") => {
+                    let hours: f64 = args.next().map(|value| value.parse()).transpose()?.unwrap_or(24.0);
+                    let since = clock::Clock::now(&clock::SystemClock) - chrono::Duration::seconds((hours * 3600.0) as i64);
+                    let ticks = database.get_anomalous_ticks(since).await?;
+                    print!("{}", inspect::anomalies_table(&ticks));
+                }
+                Some("events") => {
+                    let hours: f64 = args.next().map(|value| value.parse()).transpose()?.unwrap_or(1.0);
+                    let since = clock::Clock::now(&clock::SystemClock) - chrono::Duration::seconds((hours * 3600.0) as i64);
+                    let events = database.get_quik_events(since).await?;
+                    print!("{}", inspect::events_table(&events));
+                }
+                Some("calls") => {
+                    let hours: f64 = args.next().map(|value| value.parse()).transpose()?.unwrap_or(24.0);
+                    let since = clock::Clock::now(&clock::SystemClock) - chrono::Duration::seconds((hours * 3600.0) as i64);
+                    let stats = database.get_call_stats(since).await?;
+                    print!("{}", inspect::call_stats_table(&stats));
+                }
+                Some("exits") => {
+                    let hours: f64 = args.next().map(|value| value.parse()).transpose()?.unwrap_or(24.0);
+                    let since = clock::Clock::now(&clock::SystemClock) - chrono::Duration::seconds((hours * 3600.0) as i64);
+                    let exits = database.get_exits(since).await?;
+                    print!("{}", inspect::exits_table(&exits));
+                }
+                _ => return Err(usage.into()),
+            }
+            return Ok(());
+        }
+        Some("export-signals") => {
+            // quik-rs export-signals <from YYYY-MM-DD> <to YYYY-MM-DD> [horizons] [file]
+            let mut args = std::env::args().skip(2);
+            let usage = "usage: quik-rs export-signals <from YYYY-MM-DD> <to YYYY-MM-DD> [horizons, e.g. 15m,1h,1d] [file]";
+            let from: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let to: chrono::NaiveDate = args.next().ok_or(usage)?.parse()?;
+            let horizons = signal_export::Horizon::parse_list(&args.next().unwrap_or_else(|| "15m,1h,1d".to_string()))?;
+            let file = args.next().unwrap_or_else(|| format!("signals-{}-{}.csv", from, to));
+
+            let config = config::Config::load(config_path)?;
+            let database = psql::Db::new(&config.connection_str).await?;
+            let instrument_codes: Vec<String> = config.instruments.iter().map(|instrument| instrument.sec_code.clone()).collect();
+            let start = from.and_time(Default::default()).and_utc();
+            let end = (to + chrono::Duration::days(1)).and_time(Default::default()).and_utc();
+            let dataset = signal_export::SignalDataset::load(&database, &instrument_codes, start, end, horizons).await?;
+            std::fs::write(&file, dataset.to_csv())?;
+            println!("{} signals with {} indicators are written to {}", dataset.signals.len(), dataset.indicator_names.len(), file);
+            return Ok(());
+        }
+        Some("tune") => {
+            // quik-rs tune <strategy> prints the parameter changes of the strategy,
+            // quik-rs tune <strategy> <sec_code> <parameter> <value> [candles] runs a guarded rollout
+            let mut args = std::env::args().skip(2);
+            let usage = "usage: quik-rs tune <strategy> [<sec_code> <parameter> <value> [candles]]";
+            let strategy = args.next().ok_or(usage)?;
+            let config = config::Config::load(config_path)?;
+            let database = psql::Db::new(&config.connection_str).await?;
+            let Some(sec_code) = args.next() else {
+                print!("{}", inspect::parameter_changes_table(&database.get_parameter_changes(&strategy).await?));
+                return Ok(());
+            };
+            let parameter = args.next().ok_or(usage)?;
+            let value: f64 = args.next().ok_or(usage)?.parse()?;
+            let candles = args.next().map(|value| value.parse()).transpose()?.unwrap_or(96);
+
+            let settings = config
+                .instrument_settings()?
+                .into_iter()
+                .find(|settings| settings.sec_code == sec_code)
+                .ok_or_else(|| format!("{} is not in the watchlist", sec_code))?;
+            let live_value = tuning::parameter_value(&settings, &parameter)?;
+            let candidate = config::Config::parse(&tuning::with_parameter(&std::fs::read_to_string(config_path)?, &sec_code, &parameter, value)?)?;
+            let name = format!("{}.{}", sec_code, parameter);
+            let mut rollout = tuning::ParameterRollout::start(&database, &strategy, &name, live_value, value, candles).await?;
+            println!("{} of {}: {} runs in paper mode alongside {} for {} candles, Ctrl+C rejects it", name, strategy, value, live_value, candles);
 
-    let path = r"c:\QUIK Junior\trans2quik.dll";
-    let terminal = quik::Terminal::new(path)?;
+            let interrupted = tokio::select! {
+                result = tuning::paper_run(&database, &mut rollout, &sec_code, &config, &candidate, &clock::SystemClock) => {
+                    result?;
+                    false
+                }
+                _ = tokio::signal::ctrl_c() => true,
+            };
+            if interrupted {
+                rollout.reject(&database).await?;
+                return Err("the rollout is interrupted and rejected".into());
+            }
+
+            println!("{}", rollout.comparison());
+            print!("Promote {} of {} from {} to {}? [y/N] ", name, strategy, live_value, value);
+            std::io::Write::flush(&mut std::io::stdout())?;
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            if answer.trim().eq_ignore_ascii_case("y") {
+                let value = rollout.promote(&database).await?;
+                // The file is read again, it may have been edited during the paper run
+                std::fs::write(config_path, tuning::with_parameter(&std::fs::read_to_string(config_path)?, &sec_code, &parameter, value)?)?;
+                println!("{} = {} is written to {}, restart the bot to trade it", name, value, config_path);
+            } else {
+                rollout.reject(&database).await?;
+                println!("{} stays {}", name, live_value);
+            }
+            return Ok(());
+        }
+        Some("eod") => {
+            // quik-rs eod [YYYY-MM-DD], the current exchange day by default
+            let config = config::Config::load(config_path)?;
+            let date = match std::env::args().nth(2) {
+                Some(date) => date.parse()?,
+                None => clock::Clock::now(&clock::SystemClock).with_timezone(&config.exchange_timezone).date_naive(),
+            };
+            let database = Arc::new(psql::Db::new(&config.connection_str).await?);
+            let mut pipeline = eod::Pipeline::new(database, &config)?;
+
+            // The terminal is only needed to send the closing orders
+            // The closing orders are recorded in the intent log and acknowledged before the exit
+            let gateway = if config.eod.step(eod::EodStep::Flatten).enabled {
+                let terminal = open_terminal(&config)?;
+                let events = terminal.events();
+                terminal.connect()?;
+                terminal.set_transactions_reply_callback()?;
+                let throttle = throttle::TransactionThrottle::start(terminal.clone(), config.max_transactions_per_second, config.transaction_burst, latency::LatencyStats::default())?;
+                let intent_log = intents::IntentLog::open(&config.intent_log_file)?;
+                let gateway = Arc::new(gateway::OrderGateway::new(quik::TransIdAllocator::open(&config.trans_id_file)?, intent_log, throttle));
+                tokio::spawn(gateway::run(gateway.clone(), events));
+                pipeline = pipeline.with_gateway(gateway.clone());
+                Some((terminal, gateway))
+            } else {
+                None
+            };
+            let report = pipeline.run(date).await;
+            if let Some((terminal, gateway)) = gateway {
+                let unsettled = gateway.settle(config.shutdown.reply_timeout).await;
+                if unsettled > 0 {
+                    warn!("{} closing orders are not acknowledged by the terminal, check them in the terminal", unsettled);
+                }
+                terminal.shutdown()?;
+            }
+
+            println!("{}", report);
+            if !report.is_ok() {
+                return Err("the end of day pipeline has failed steps".into());
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let config = config::Config::load(config_path)?;
+    match config.mode {
+        config::RunMode::Demo => info!("{}", config.mode.banner()),
+        config::RunMode::Live => warn!("{}", config.mode.banner()),
+    }
+    info!("{}", summary::StartupSummary::new(&config)?);
+
+    // A corrupted schema or inconsistent data must not be traded on
+    let database = psql::Db::connect(&config.connection_str, &config.retry).await?;
+    let report = integrity::check(&database).await?;
+    if report.is_ok() {
+        info!("{}", report);
+    } else if std::env::args().any(|arg| arg == "--ignore-integrity") {
+        warn!("Trading despite the problems, --ignore-integrity is set: {}", report);
+    } else {
+        error!("{}", report);
+        return Err("the database integrity check failed, start with --ignore-integrity to trade anyway".into());
+    }
+
+    // Transactions possibly sent before a crash must be checked before trading resumes
+    let mut intent_log = intents::IntentLog::open(&config.intent_log_file)?;
+    let reconciliation = intents::reconcile(&mut intent_log, &database).await?;
+    if !reconciliation.is_ok() {
+        if std::env::args().any(|arg| arg == "--ignore-intents") {
+            warn!("Trading despite the unknown intents, --ignore-intents is set: {}", reconciliation);
+            for intent in &reconciliation.unknown {
+                intent_log.confirm(intent.trans_id)?;
+            }
+            intent_log.compact()?;
+        } else {
+            error!("{}", reconciliation);
+            return Err("transactions of the intent log may have been sent, check their orders in the terminal and start with --ignore-intents".into());
+        }
+    }
+
+    // The orders and their sizes are checked against the limits exported from the terminal
+    let instruments = config.instrument_settings()?;
+    for settings in &config.accounts {
+        let sec_codes: Vec<String> = instruments
+            .iter()
+            .filter(|instrument| instrument.account.as_ref().is_some_and(|account| account.name == settings.name))
+            .map(|instrument| instrument.sec_code.clone())
+            .collect();
+        let account = account::Account::load(&database, settings, &sec_codes).await?;
+        match account.money {
+            Some(_) => info!("{} ({})", account, settings.name),
+            None => warn!("{} ({})", account, settings.name),
+        }
+    }
+
+    let terminal = open_terminal(&config)?;
     terminal.connect()?;
     terminal.is_quik_connected()?;
-    terminal.disconnect()?;
-    
-    // let connection_str = "host=localhost user=postgres dbname=postgres password=password";
-    // let database = psql::Db::new(connection_str).await?;
-    // database.init().await?;
-
-    // Параметры запроса
-    // let instrument_code = "AAPL";
-    // let lookback_interval_seconds: f64 = (5 * 3600 + 15 * 60) as f64; // 5 часов 15 минут в секундах
-    // let period_length_seconds: f64 = (15 * 60) as f64; // 15 минут в секундах
-
-    // let lookback_interval_seconds: f64 = 600 as f64;
-    // let period_length_seconds: f64 = 10 as f64;
-    // let data = database.get_data_for_ema(instrument_code, lookback_interval_seconds, period_length_seconds).await?;
-    // println!("{:?}", d);
-    // let e = ema::Ema::calculate_ema(data);
-    // info!("ema value = {}", e);
+    if std::env::args().any(|arg| arg == "--headless") {
+        headless::run(terminal.clone(), &config, config_path, intent_log).await?;
+    }
+    terminal.shutdown()?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+
+/// Terminal of the configured backend, not connected yet.
+fn open_terminal(config: &config::Config) -> Result<Arc<dyn QuikApi>, Box<dyn std::error::Error>> {
+    Ok(match config.backend {
+        config::Backend::Trans2quik => Arc::new(quik::Terminal::new(&config.path_to_lib, &config.path_to_quik)?),
+        config::Backend::Mock => Arc::new(
+            mock::MockTerminal::new()
+                .with_ack_latency(config.paper.ack_latency)
+                .with_fill_latency(config.paper.fill_latency)
+                .with_jitter(config.paper.jitter)
+                .with_slippage(config.paper.slippage),
+        ),
+    })
+}
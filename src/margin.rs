@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde_json::{json, Value};
+use quik_rs::transaction::Side;
+use crate::domain::{Order, OrderStatus, ToJson};
+use crate::psql::MarginRequirement;
+
+
+/// Open position of an instrument in lots: positive for a long position, negative for a short one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub sec_code: String,
+    pub lots: i64,
+    pub price: f64,
+}
+
+
+/// Margin parameters of an instrument. The rates are the share of the position value
+/// which must be covered by own funds: 1.0 without leverage, e.g. 0.25 with leverage of four.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarginParams {
+    pub lot_size: f64,
+    pub long_rate: f64,
+    pub short_rate: f64,
+}
+
+
+impl MarginParams {
+    /// Parameters of an instrument with `lot_size` units in a lot priced at `price`: the rates
+    /// are the deposits of a lot exported by QUIK to `current_trades` against the value of
+    /// the lot, the full value without them.
+    pub fn of_instrument(lot_size: i64, price: f64, requirement: Option<&MarginRequirement>) -> Self {
+        let value = lot_size.max(1) as f64 * price;
+        let rate = |deposit: Option<f64>| match deposit {
+            Some(deposit) if deposit > 0.0 && value > 0.0 => deposit / value,
+            _ => 1.0,
+        };
+        MarginParams {
+            lot_size: lot_size.max(1) as f64,
+            long_rate: rate(requirement.and_then(|requirement| requirement.buy_deposit)),
+            short_rate: rate(requirement.and_then(|requirement| requirement.sell_deposit)),
+        }
+    }
+}
+
+
+impl Default for MarginParams {
+    fn default() -> Self {
+        MarginParams {
+            lot_size: 1.0,
+            long_rate: 1.0,
+            short_rate: 1.0,
+        }
+    }
+}
+
+
+/// Projected requirement of one instrument.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentRequirement {
+    pub sec_code: String,
+    /// Requirement of the current position.
+    pub current: f64,
+    /// Requirement at the end of the day, if the resting orders of the worst side are executed.
+    pub projected: f64,
+}
+
+
+/// End-of-day forecast of the funds required by the positions and the resting orders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginForecast {
+    pub available: f64,
+    pub instruments: Vec<InstrumentRequirement>,
+}
+
+
+impl MarginForecast {
+    /// Funds required by the current positions.
+    pub fn current(&self) -> f64 {
+        self.instruments.iter().map(|instrument| instrument.current).sum()
+    }
+
+
+    /// Funds required at the end of the day.
+    pub fn projected(&self) -> f64 {
+        self.instruments.iter().map(|instrument| instrument.projected).sum()
+    }
+
+
+    /// Amount by which the projected requirement exceeds the available funds.
+    pub fn shortfall(&self) -> Option<f64> {
+        let shortfall = self.projected() - self.available;
+        (shortfall > 0.0).then_some(shortfall)
+    }
+}
+
+
+impl ToJson for MarginForecast {
+    fn to_json(&self) -> Value {
+        json!({
+            "available": self.available,
+            "current": self.current(),
+            "projected": self.projected(),
+            "shortfall": self.shortfall(),
+            "instruments": self.instruments.iter().map(|instrument| json!({
+                "sec_code": instrument.sec_code,
+                "current": instrument.current,
+                "projected": instrument.projected,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+
+impl fmt::Display for MarginForecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Margin: current {:.2}, projected {:.2}, available {:.2}",
+            self.current(),
+            self.projected(),
+            self.available
+        )?;
+
+        for instrument in &self.instruments {
+            writeln!(f, "  {}: current {:.2}, projected {:.2}", instrument.sec_code, instrument.current, instrument.projected)?;
+        }
+
+        if let Some(shortfall) = self.shortfall() {
+            writeln!(f, "  shortfall {:.2}", shortfall)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Projects the cash and margin requirements from the current positions and the resting orders.
+///
+/// # Example of use
+/// ```ignore
+/// let projector = MarginProjector::default().with_params("SBER", MarginParams { lot_size: 10.0, long_rate: 0.25, short_rate: 0.3 });
+/// let forecast = projector.forecast(&positions, &db.get_working_orders().await?, available_funds);
+/// if let Some(shortfall) = forecast.shortfall() {
+///     warn!("Projected margin requirement exceeds the available funds by {:.2}", shortfall);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MarginProjector {
+    params: HashMap<String, MarginParams>,
+    default_params: MarginParams,
+}
+
+
+impl MarginProjector {
+    pub fn with_params(mut self, sec_code: &str, params: MarginParams) -> Self {
+        self.params.insert(sec_code.to_string(), params);
+        self
+    }
+
+
+    /// Calculates the forecast. Only the active orders are taken into account, with their unexecuted
+    /// balance. As it is unknown which orders are executed by the end of the day, the projection
+    /// of every instrument is the worst of the cases when all buy or all sell orders are executed.
+    pub fn forecast(&self, positions: &[Position], orders: &[Order], available: f64) -> MarginForecast {
+        let mut sec_codes: Vec<&str> = positions.iter().map(|position| position.sec_code.as_str()).collect();
+        sec_codes.extend(orders.iter().filter(|order| is_active(order)).map(|order| order.sec_code.as_str()));
+        sec_codes.sort_unstable();
+        sec_codes.dedup();
+
+        let instruments = sec_codes
+            .into_iter()
+            .map(|sec_code| {
+                let params = self.params.get(sec_code).copied().unwrap_or(self.default_params);
+                let position = positions.iter().find(|position| position.sec_code == sec_code);
+                let lots = position.map_or(0, |position| position.lots);
+                let price = position.map_or(0.0, |position| position.price);
+
+                let mut long_case = requirement(lots, price, &params);
+                let mut short_case = long_case;
+                let mut buy_lots = lots;
+                let mut sell_lots = lots;
+                let mut buy_value = lots as f64 * price;
+                let mut sell_value = buy_value;
+
+                for order in orders.iter().filter(|order| is_active(order) && order.sec_code == sec_code) {
+                    if order.side == Side::Sell {
+                        sell_lots -= order.balance;
+                        sell_value -= order.balance as f64 * order.price;
+                        short_case = requirement_of_value(sell_lots, sell_value, &params);
+                    } else {
+                        buy_lots += order.balance;
+                        buy_value += order.balance as f64 * order.price;
+                        long_case = requirement_of_value(buy_lots, buy_value, &params);
+                    }
+                }
+
+                InstrumentRequirement {
+                    sec_code: sec_code.to_string(),
+                    current: requirement(lots, price, &params),
+                    projected: long_case.max(short_case),
+                }
+            })
+            .collect();
+
+        MarginForecast { available, instruments }
+    }
+}
+
+
+fn is_active(order: &Order) -> bool {
+    order.status == OrderStatus::Active && order.balance > 0
+}
+
+
+fn requirement(lots: i64, price: f64, params: &MarginParams) -> f64 {
+    requirement_of_value(lots, lots as f64 * price, params)
+}
+
+
+/// Requirement of a position of `lots` lots with the total price `value` of all lots.
+fn requirement_of_value(lots: i64, value: f64, params: &MarginParams) -> f64 {
+    let rate = if lots >= 0 { params.long_rate } else { params.short_rate };
+    value.abs() * params.lot_size * rate
+}
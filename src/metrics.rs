@@ -0,0 +1,25 @@
+use crate::instrument::InstrumentStates;
+use crate::latency::LatencyStats;
+use crate::retry::RetryMetrics;
+use crate::task_health::TaskHealthStats;
+
+
+/// Live statistics of the headless bot, recorded by its tasks and read by the dashboard:
+/// the readiness of the instruments, the health of their evaluation, the latency of the
+/// stages of the trading loop and the retries of the subsystems. The clones share the
+/// statistics; every task is given the part it records by the caller.
+///
+/// # Example of use
+/// ```ignore
+/// let metrics = Metrics::default();
+/// let retry = config.retry.clone().with_metrics(metrics.retries.clone());
+/// let throttle = TransactionThrottle::start(terminal.clone(), config.max_transactions_per_second, config.transaction_burst, metrics.latency.clone())?;
+/// let dashboard = Dashboard::new(config.mode).with_metrics(metrics.clone());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    pub instruments: InstrumentStates,
+    pub tasks: TaskHealthStats,
+    pub latency: LatencyStats,
+    pub retries: RetryMetrics,
+}
@@ -0,0 +1,107 @@
+use ta::DataItem;
+use tracing::{info, warn};
+use crate::config::Config;
+use crate::expression::CustomIndicator;
+use crate::psql::{self, Db};
+
+
+/// Indicators of the EMA crossover backfilled in addition to the custom indicators.
+const BACKFILL_INDICATORS: [&str; 2] = ["ema9", "ema21"];
+
+/// History backfilled by the migration to the version 2.
+const BACKFILL_DAYS: i64 = 365;
+
+
+/// Brings the database schema to `psql::SCHEMA_VERSION`, applying the missing migrations
+/// in order. Run by the `quik-rs migrate` command.
+///
+/// Version 1 is the schema created by `Db::init`. Version 2 backfills `indicator_values`
+/// from the candles of `historical_trades`, so the history of the indicators is kept
+/// when the bot switches to the generic indicator table.
+pub async fn run(db: &Db, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    db.init().await?;
+
+    // A database without the schema_version table has the tables of the version 1
+    let mut version = db.get_schema_version().await?.max(1);
+    info!("Schema version {}, target version {}", version, psql::SCHEMA_VERSION);
+
+    while version < psql::SCHEMA_VERSION {
+        version += 1;
+        match version {
+            2 => backfill_indicator_values(db, config).await?,
+            _ => return Err(format!("no migration to the schema version {}", version).into()),
+        }
+
+        db.set_schema_version(version).await?;
+        info!("Schema is migrated to the version {}", version);
+    }
+
+    Ok(())
+}
+
+
+/// Calculates the indicators on the stored candles of every instrument and writes them to
+/// `indicator_values`, reporting the progress and verifying the number of stored values.
+async fn backfill_indicator_values(db: &Db, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut indicators: Vec<(String, String)> = BACKFILL_INDICATORS
+        .iter()
+        .map(|name| (name.to_string(), name.to_string()))
+        .collect();
+    indicators.extend(config.custom_indicators.iter().cloned());
+
+    let instruments = config.instrument_settings()?;
+    let total = instruments.len();
+
+    for (number, instrument) in instruments.iter().enumerate() {
+        let sec_code = &instrument.sec_code;
+        // The candles of the timeframe of the instrument, as the strategies see them
+        let mut candles = db
+            .get_data_for_ema(sec_code, (BACKFILL_DAYS * 24 * 3600) as f64, instrument.timeframe().as_secs_f64())
+            .await?;
+        // The indicators see the same session as the trading, without auctions and breaks
+        if let Some(session) = &instrument.session {
+            candles = session.in_session(candles, config.exchange_timezone);
+        }
+        let Some(since) = candles.first().map(|candle| candle.period_start) else {
+            info!("[{}/{}] {}: no candles to backfill", number + 1, total, sec_code);
+            continue;
+        };
+
+        for (name, source) in &indicators {
+            let mut indicator = CustomIndicator::new(name, source)?;
+            let mut written = 0;
+
+            for candle in &candles {
+                let item = match DataItem::builder()
+                    .open(candle.open)
+                    .high(candle.high)
+                    .low(candle.low)
+                    .close(candle.close)
+                    .volume(candle.volume)
+                    .build()
+                {
+                    Ok(item) => item,
+                    Err(e) => {
+                        warn!("{} {}: skipping invalid candle: {:?}", sec_code, candle.period_start, e);
+                        continue;
+                    }
+                };
+
+                if let Some(value) = indicator.next(&item) {
+                    db.insert_indicator_value(sec_code, name, candle.period_start, value).await?;
+                    written += 1;
+                }
+            }
+
+            // Verify that every calculated value is stored
+            let stored = db.count_indicator_values(sec_code, name, since).await?;
+            if stored < written {
+                return Err(format!("{} {}: {} values written, but only {} stored", sec_code, name, written, stored).into());
+            }
+
+            info!("[{}/{}] {} {}: {} values backfilled", number + 1, total, sec_code, name, written);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use libc::c_long;
+use tokio::sync::mpsc;
+use tracing::info;
+use crate::quik::{
+    CallStats, Capabilities, ConnectionEvent, OrderInfo, QuikApi, QuikEvent, ReplyStatus, SyncTransactionResult, TradeInfo, TransactionReply, Trans2QuikError,
+    Trans2quikResult,
+};
+
+
+/// Reply code of an executed transaction.
+const REPLY_EXECUTED: c_long = 3;
+
+
+/// Simulated QUIK terminal for running the bot without Trans2QUIK.dll.
+///
+/// Connection events are produced by `connect`, `disconnect`, `simulate_disconnect` and
+/// `simulate_reconnect`. Transactions are acknowledged after `ack_latency`, and new orders sent
+/// with `send_sync_transaction` or `send_async_transaction` are filled completely `fill_latency`
+/// later at the order price shifted by `slippage` against the order; both latencies get a random
+/// `jitter`. The order and trade callbacks are emitted as events once orders and trades are started.
+///
+/// # Example of use
+/// ```
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # use quik_rs::{MockTerminal, QuikApi};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let terminal: Arc<dyn QuikApi> = Arc::new(
+///     MockTerminal::new()
+///         .with_ack_latency(Duration::from_millis(20))
+///         .with_fill_latency(Duration::from_millis(50))
+///         .with_jitter(Duration::from_millis(30))
+///         .with_slippage(0.01),
+/// );
+/// let mut events = terminal.events();
+/// terminal.connect()?;
+/// terminal.start_trades()?;
+/// terminal.send_sync_transaction("ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=QJSIM; SECCODE=SBER; OPERATION=B; PRICE=250; QUANTITY=1;")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockTerminal {
+    ack_latency: Duration,
+    fill_latency: Duration,
+    jitter: Duration,
+    slippage: f64,
+    dll_connected: AtomicBool,
+    quik_connected: AtomicBool,
+    connection_callback: AtomicBool,
+    orders_started: Arc<AtomicBool>,
+    trades_started: Arc<AtomicBool>,
+    next_order_num: AtomicU64,
+    next_trade_num: Arc<AtomicU64>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<QuikEvent>>>>,
+
+    /// The library is not called, the statistics stay empty.
+    call_stats: CallStats,
+}
+
+
+impl Default for MockTerminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl MockTerminal {
+    pub fn new() -> Self {
+        MockTerminal {
+            ack_latency: Duration::ZERO,
+            fill_latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            slippage: 0.0,
+            dll_connected: AtomicBool::new(false),
+            quik_connected: AtomicBool::new(false),
+            connection_callback: AtomicBool::new(false),
+            orders_started: Arc::new(AtomicBool::new(false)),
+            trades_started: Arc::new(AtomicBool::new(false)),
+            next_order_num: AtomicU64::new(1),
+            next_trade_num: Arc::new(AtomicU64::new(1)),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            call_stats: CallStats::default(),
+        }
+    }
+
+
+    /// Delay between sending a transaction and its acknowledgement: the synchronous send
+    /// returns and the reply of the asynchronous send arrives after it.
+    pub fn with_ack_latency(mut self, ack_latency: Duration) -> Self {
+        self.ack_latency = ack_latency;
+        self
+    }
+
+
+    /// Delay between the acknowledgement of an order and its fill.
+    pub fn with_fill_latency(mut self, fill_latency: Duration) -> Self {
+        self.fill_latency = fill_latency;
+        self
+    }
+
+
+    /// Maximum random delay added to every acknowledgement and fill latency, simulating
+    /// the jitter of the network and the exchange.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+
+    /// Price shift of the fills against the order: buys are filled higher, sells lower.
+    pub fn with_slippage(mut self, slippage: f64) -> Self {
+        self.slippage = slippage;
+        self
+    }
+
+
+    /// Simulates a loss of the connection between the terminal and the server.
+    pub fn simulate_disconnect(&self) {
+        self.quik_connected.store(false, Ordering::SeqCst);
+        self.connection_event(Trans2quikResult::QuikDisconnected, "simulated disconnect");
+    }
+
+
+    /// Simulates the recovery of the connection between the terminal and the server.
+    pub fn simulate_reconnect(&self) {
+        if self.dll_connected.load(Ordering::SeqCst) {
+            self.quik_connected.store(true, Ordering::SeqCst);
+            self.connection_event(Trans2quikResult::QuikConnected, "simulated reconnect");
+        }
+    }
+
+
+    fn connection_event(&self, status: Trans2quikResult, info_message: &str) {
+        if self.connection_callback.load(Ordering::SeqCst) {
+            dispatch(
+                &self.subscribers,
+                QuikEvent::ConnectionStatus(ConnectionEvent {
+                    status,
+                    error_code: 0,
+                    info_message: info_message.to_string(),
+                }),
+            );
+        }
+    }
+
+
+    /// Fails like the library does when the terminal is not connected.
+    fn check_connected(&self, function: &'static str) -> Result<(), Trans2QuikError> {
+        let result = if !self.dll_connected.load(Ordering::SeqCst) {
+            Trans2quikResult::DllNotConnected
+        } else if !self.quik_connected.load(Ordering::SeqCst) {
+            Trans2quikResult::QuikNotConnected
+        } else {
+            return Ok(());
+        };
+
+        Err(Trans2QuikError::Failed {
+            function,
+            result,
+            error_code: 0,
+            error_message: String::new(),
+        })
+    }
+
+
+    /// The latency with a random jitter added.
+    fn jittered(&self, latency: Duration) -> Duration {
+        if self.jitter.is_zero() {
+            return latency;
+        }
+
+        let mut bytes = [0u8; 8];
+        let fraction = match getrandom::getrandom(&mut bytes) {
+            Ok(()) => u64::from_le_bytes(bytes) as f64 / u64::MAX as f64,
+            Err(_) => 0.5,
+        };
+        latency + self.jitter.mul_f64(fraction)
+    }
+
+
+    /// Executes a transaction: new orders are registered and filled in the background after
+    /// `ack_latency` and the fill latency, other transactions are accepted as executed.
+    fn execute(&self, function: &'static str, transaction: &str, ack_latency: Duration) -> Result<SyncTransactionResult, Trans2QuikError> {
+        self.check_connected(function)?;
+
+        let fields = parse_transaction(transaction);
+        let field = |name: &str| fields.get(name).map(String::as_str).unwrap_or_default();
+        let trans_id = field("TRANS_ID").parse().unwrap_or(0);
+
+        let mut result = SyncTransactionResult {
+            result: Trans2quikResult::Success,
+            reply_code: REPLY_EXECUTED,
+            status: ReplyStatus::Executed,
+            trans_id,
+            order_num: 0,
+            result_message: String::new(),
+            error_code: 0,
+            error_message: String::new(),
+        };
+
+        if field("ACTION") == "NEW_ORDER" {
+            let (Ok(price), Ok(qty)) = (field("PRICE").parse::<f64>(), field("QUANTITY").parse::<i64>()) else {
+                return Err(Trans2QuikError::Failed {
+                    function,
+                    result: Trans2quikResult::WrongSyntax,
+                    error_code: 0,
+                    error_message: format!("invalid PRICE or QUANTITY: {}", transaction),
+                });
+            };
+
+            result.order_num = self.next_order_num.fetch_add(1, Ordering::SeqCst);
+            result.result_message = format!("Order {} is registered", result.order_num);
+
+            self.fill(ack_latency, OrderInfo {
+                trans_id,
+                order_num: result.order_num,
+                class_code: field("CLASSCODE").to_string(),
+                sec_code: field("SECCODE").to_string(),
+                price,
+                balance: qty,
+                qty,
+                value: price * qty as f64,
+                is_sell: field("OPERATION") == "S",
+                broker_ref: field("COMMENT").to_string(),
+                ..OrderInfo::default()
+            });
+        }
+
+        info!("MockTerminal transaction {} -> order num {}", trans_id, result.order_num);
+        Ok(result)
+    }
+
+
+    fn fill(&self, ack_latency: Duration, order: OrderInfo) {
+        let fill_latency = ack_latency + self.jittered(self.fill_latency);
+        let price = if order.is_sell { order.price - self.slippage } else { order.price + self.slippage };
+        let orders_started = self.orders_started.clone();
+        let trades_started = self.trades_started.clone();
+        let next_trade_num = self.next_trade_num.clone();
+        let subscribers = self.subscribers.clone();
+
+        thread::spawn(move || {
+            thread::sleep(fill_latency);
+
+            let trade = TradeInfo {
+                trade_num: next_trade_num.fetch_add(1, Ordering::SeqCst),
+                order_num: order.order_num,
+                class_code: order.class_code.clone(),
+                sec_code: order.sec_code.clone(),
+                price,
+                qty: order.qty,
+                value: price * order.qty as f64,
+                is_sell: order.is_sell,
+                broker_ref: order.broker_ref.clone(),
+                ..TradeInfo::default()
+            };
+
+            if orders_started.load(Ordering::SeqCst) {
+                let executed = OrderInfo {
+                    balance: 0,
+                    // Any status except 1 (active) and 2 (withdrawn) means executed
+                    status: 3,
+                    ..order
+                };
+                dispatch(&subscribers, QuikEvent::OrderUpdate(executed));
+            }
+            if trades_started.load(Ordering::SeqCst) {
+                dispatch(&subscribers, QuikEvent::TradeUpdate(trade));
+            }
+        });
+    }
+}
+
+
+impl QuikApi for MockTerminal {
+    fn connect(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        if self.dll_connected.swap(true, Ordering::SeqCst) {
+            return Ok(Trans2quikResult::AlreadyConnectedToQuik);
+        }
+        self.quik_connected.store(true, Ordering::SeqCst);
+
+        info!("MockTerminal connected");
+        self.connection_event(Trans2quikResult::DllConnected, "");
+        self.connection_event(Trans2quikResult::QuikConnected, "");
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn disconnect(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.check_connected("TRANS2QUIK_DISCONNECT").or_else(|e| match e.result() {
+            Some(Trans2quikResult::QuikNotConnected) => Ok(()),
+            _ => Err(e),
+        })?;
+        self.dll_connected.store(false, Ordering::SeqCst);
+        self.quik_connected.store(false, Ordering::SeqCst);
+
+        info!("MockTerminal disconnected");
+        self.connection_event(Trans2quikResult::DllDisconnected, "");
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn is_quik_connected(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.check_connected("TRANS2QUIK_IS_QUIK_CONNECTED")?;
+        Ok(Trans2quikResult::QuikConnected)
+    }
+
+
+    fn is_dll_connected(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        if self.dll_connected.load(Ordering::SeqCst) {
+            Ok(Trans2quikResult::DllConnected)
+        } else {
+            Err(Trans2QuikError::Failed {
+                function: "TRANS2QUIK_IS_DLL_CONNECTED",
+                result: Trans2quikResult::DllNotConnected,
+                error_code: 0,
+                error_message: String::new(),
+            })
+        }
+    }
+
+
+    fn events(&self) -> mpsc::UnboundedReceiver<QuikEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        lock(&self.subscribers).push(sender);
+        receiver
+    }
+
+
+    fn set_connection_status_callback(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.connection_callback.store(true, Ordering::SeqCst);
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn set_transactions_reply_callback(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn subscribe_orders(&self, _class_code: &str, _sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn unsubscribe_orders(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.orders_started.store(false, Ordering::SeqCst);
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn start_orders(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.orders_started.store(true, Ordering::SeqCst);
+        // No orders survive a restart of the simulated terminal, the initial snapshot is only its end
+        dispatch(&self.subscribers, QuikEvent::OrderUpdate(OrderInfo { mode: 2, ..OrderInfo::default() }));
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn subscribe_trades(&self, _class_code: &str, _sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn unsubscribe_trades(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.trades_started.store(false, Ordering::SeqCst);
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn start_trades(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.trades_started.store(true, Ordering::SeqCst);
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn send_sync_transaction(&self, transaction: &str) -> Result<SyncTransactionResult, Trans2QuikError> {
+        let ack_latency = self.jittered(self.ack_latency);
+        let result = self.execute("TRANS2QUIK_SEND_SYNC_TRANSACTION", transaction, ack_latency)?;
+        thread::sleep(ack_latency);
+        Ok(result)
+    }
+
+
+    fn send_async_transaction(&self, transaction: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        let ack_latency = self.jittered(self.ack_latency);
+        let result = self.execute("TRANS2QUIK_SEND_ASYNC_TRANSACTION", transaction, ack_latency)?;
+        let fields = parse_transaction(transaction);
+        let field = |name: &str| fields.get(name).cloned().unwrap_or_default();
+
+        // The reply callback of the real terminal is called from its own thread
+        let subscribers = self.subscribers.clone();
+        let reply = TransactionReply {
+            trans2quik_result: result.result,
+            error_code: result.error_code,
+            reply_code: result.reply_code,
+            status: result.status,
+            trans_id: result.trans_id,
+            order_num: result.order_num,
+            reply_message: result.result_message,
+            class_code: field("CLASSCODE"),
+            sec_code: field("SECCODE"),
+            price: field("PRICE").parse().unwrap_or(0.0),
+            quantity: field("QUANTITY").parse().unwrap_or(0),
+            balance: 0,
+            firm_id: String::new(),
+            account: field("ACCOUNT"),
+            client_code: field("CLIENT_CODE"),
+            broker_ref: field("COMMENT"),
+            exchange_code: String::new(),
+        };
+        thread::spawn(move || {
+            thread::sleep(ack_latency);
+            dispatch(&subscribers, QuikEvent::TransactionReply(reply));
+        });
+
+        Ok(Trans2quikResult::Success)
+    }
+
+
+    fn shutdown(&self) -> Result<(), Trans2QuikError> {
+        lock(&self.subscribers).clear();
+        self.orders_started.store(false, Ordering::SeqCst);
+        self.trades_started.store(false, Ordering::SeqCst);
+        self.dll_connected.store(false, Ordering::SeqCst);
+        self.quik_connected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+
+    fn call_stats(&self) -> &CallStats {
+        &self.call_stats
+    }
+
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all()
+    }
+}
+
+
+fn lock(subscribers: &Mutex<Vec<mpsc::UnboundedSender<QuikEvent>>>) -> MutexGuard<'_, Vec<mpsc::UnboundedSender<QuikEvent>>> {
+    subscribers.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+
+/// Sends the event to every subscriber. Subscribers whose receivers were dropped are removed.
+fn dispatch(subscribers: &Mutex<Vec<mpsc::UnboundedSender<QuikEvent>>>, event: QuikEvent) {
+    lock(subscribers).retain(|sender| sender.send(event.clone()).is_ok());
+}
+
+
+/// Splits a transaction string `KEY=VALUE; KEY=VALUE;` into its fields.
+fn parse_transaction(transaction: &str) -> HashMap<String, String> {
+    transaction
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.trim().to_uppercase(), value.trim().to_string()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn order_is_acknowledged_and_filled_with_the_slippage() {
+        let terminal = MockTerminal::new().with_fill_latency(Duration::from_millis(50)).with_slippage(0.05);
+        terminal.connect().unwrap();
+        terminal.start_orders().unwrap();
+        terminal.start_trades().unwrap();
+        let mut events = terminal.events();
+
+        let transaction = "ACTION=NEW_ORDER; TRANS_ID=7; CLASSCODE=QJSIM; SECCODE=SBER; OPERATION=B; PRICE=250; QUANTITY=2; COMMENT=bot/ema_cross;";
+        assert_eq!(terminal.send_async_transaction(transaction).unwrap(), Trans2quikResult::Success);
+
+        let Some(QuikEvent::TransactionReply(reply)) = events.blocking_recv() else {
+            panic!("the reply is expected first");
+        };
+        assert_eq!((reply.trans_id, reply.order_num, reply.status), (7, 1, ReplyStatus::Executed));
+        let Some(QuikEvent::OrderUpdate(order)) = events.blocking_recv() else {
+            panic!("the executed order is expected after the reply");
+        };
+        assert_eq!((order.order_num, order.balance, order.status), (1, 0, 3));
+        let Some(QuikEvent::TradeUpdate(trade)) = events.blocking_recv() else {
+            panic!("the trade is expected after the order");
+        };
+        assert_eq!((trade.order_num, trade.qty, trade.is_sell), (1, 2, false));
+        assert!((trade.price - 250.05).abs() < 1e-9);
+        assert_eq!(trade.broker_ref, "bot/ema_cross");
+    }
+
+
+    #[test]
+    fn transaction_fails_while_the_terminal_is_disconnected() {
+        let terminal = MockTerminal::new();
+        terminal.connect().unwrap();
+        terminal.simulate_disconnect();
+
+        let error = terminal.send_sync_transaction("ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=QJSIM; SECCODE=SBER; OPERATION=S; PRICE=250; QUANTITY=1;");
+        assert!(matches!(error, Err(Trans2QuikError::Failed { result: Trans2quikResult::QuikNotConnected, .. })));
+
+        terminal.simulate_reconnect();
+        let result = terminal.send_sync_transaction("ACTION=NEW_ORDER; TRANS_ID=2; CLASSCODE=QJSIM; SECCODE=SBER; OPERATION=S; PRICE=250; QUANTITY=1;");
+        assert_eq!(result.unwrap().order_num, 1);
+    }
+}
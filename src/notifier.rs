@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use serde_json::json;
+use tracing::{info, warn};
+use crate::config::{Config, RunMode};
+use crate::retry::RetryPolicy;
+use crate::secrets::Secret;
+
+
+/// Time a channel is given to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// Time a channel is given to answer the whole request, so a stuck channel doesn't hold
+/// the retries of the notification forever.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+
+/// Destination of the notifications, defined in `[channels.<name>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotificationChannel {
+    /// Chat of a Telegram bot, the message is sent by `sendMessage` of the Bot API at `api_url`.
+    Telegram { bot_token: Secret, chat_id: String, api_url: String },
+    /// The message is posted as JSON `{"text": ...}` to the URL.
+    Webhook { url: Secret },
+}
+
+
+impl NotificationChannel {
+    /// Address of the Bot API, `api_url` replaces it with a proxy or a local Bot API server.
+    pub const TELEGRAM_API_URL: &'static str = "https://api.telegram.org";
+
+
+    /// URL and JSON body of the request delivering the message.
+    fn request(&self, text: &str) -> (String, String) {
+        match self {
+            NotificationChannel::Telegram { bot_token, chat_id, api_url } => (
+                format!("{}/bot{}/sendMessage", api_url.trim_end_matches('/'), &**bot_token),
+                json!({ "chat_id": chat_id, "text": text }).to_string(),
+            ),
+            NotificationChannel::Webhook { url } => (url.to_string(), json!({ "text": text }).to_string()),
+        }
+    }
+
+
+    /// URL of the channel safe to log: the bot token of Telegram is in the path and a webhook
+    /// may carry its key in the path or the query, so only the scheme and the host are shown.
+    pub fn display_url(&self) -> String {
+        let url = match self {
+            NotificationChannel::Telegram { api_url, .. } => api_url.as_str(),
+            NotificationChannel::Webhook { url } => &**url,
+        };
+        let (scheme, address) = url.split_once("://").unwrap_or(("", url));
+        let host = address.split(['/', '?', '#']).next().unwrap_or_default();
+        // Credentials in the authority are dropped as well
+        let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+        format!("{}://{}/***", scheme, host)
+    }
+
+
+    pub async fn send(&self, client: &reqwest::Client, text: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (url, body) = self.request(text);
+        let response = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            // The error of reqwest shows the URL with the token, only the safe form is kept
+            .map_err(|e| DeliveryError { url: self.display_url(), source: e.without_url() })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Box::new(StatusError {
+                url: self.display_url(),
+                status: status.to_string(),
+                code: Some(status.as_u16()),
+            }));
+        }
+        Ok(())
+    }
+}
+
+
+/// Request which didn't reach the channel or got no answer in time.
+#[derive(Debug)]
+struct DeliveryError {
+    url: String,
+    source: reqwest::Error,
+}
+
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.url, self.source)
+    }
+}
+
+
+impl std::error::Error for DeliveryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+
+/// Response of the channel with a status other than 2xx.
+#[derive(Debug)]
+struct StatusError {
+    url: String,
+    status: String,
+    code: Option<u16>,
+}
+
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.url, self.status)
+    }
+}
+
+
+impl std::error::Error for StatusError {}
+
+
+/// The delivery is worth retrying: a network error, a rate limit or an error of the server,
+/// but not a malformed URL or a request the channel refused.
+pub fn is_retryable(e: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(e) = e.downcast_ref::<DeliveryError>() {
+        return e.source.is_timeout() || e.source.is_connect() || e.source.is_request();
+    }
+    e.downcast_ref::<StatusError>()
+        .and_then(|e| e.code)
+        .is_some_and(|code| code == 429 || code >= 500)
+}
+
+
+/// Notification layer: routes the messages of a strategy to the channels of its
+/// `[notifications.<strategy>]` profile, e.g. the scalping alerts to one chat and the swing
+/// signals to another. A strategy without a profile and the messages without a strategy go
+/// to the `notification_channels` of the instrument.
+///
+/// Every message starts with the prefix of the run mode, so demo and live messages can't be confused.
+///
+/// # Example of use
+/// ```ignore
+/// let notifier = Notifier::from_config(&config);
+/// notifier.notify(Some("ema_cross"), &settings.notification_channels, "SBER: buy by ema_cross_9/21").await;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    prefix: &'static str,
+    channels: HashMap<String, NotificationChannel>,
+    routes: HashMap<String, Vec<String>>,
+    retry: RetryPolicy,
+    /// HTTPS client with the timeouts, shared by the channels.
+    client: reqwest::Client,
+}
+
+
+impl Notifier {
+    pub fn new(mode: RunMode, channels: HashMap<String, NotificationChannel>, routes: HashMap<String, Vec<String>>) -> Self {
+        Notifier {
+            prefix: mode.message_prefix(),
+            channels,
+            routes,
+            retry: RetryPolicy::default(),
+            client: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.mode, config.channels.clone(), config.notifications.clone()).with_retry_policy(config.retry.clone())
+    }
+
+
+    /// Retries of the undelivered messages, a channel down for longer than the policy allows is skipped.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+
+    /// Names of the channels of a message of the strategy about an instrument.
+    pub fn channels_for<'a>(&'a self, strategy: Option<&str>, instrument_channels: &'a [String]) -> &'a [String] {
+        strategy
+            .and_then(|strategy| self.routes.get(strategy))
+            .map_or(instrument_channels, Vec::as_slice)
+    }
+
+
+    /// Sends the message to every channel of the route. Undeliverable messages are logged,
+    /// a notification never stops the trading. Returns the number of channels reached.
+    pub async fn notify(&self, strategy: Option<&str>, instrument_channels: &[String], text: &str) -> usize {
+        let text = format!("{} {}", self.prefix, text);
+        let mut sent = 0;
+
+        for name in self.channels_for(strategy, instrument_channels) {
+            let Some(channel) = self.channels.get(name) else {
+                warn!("Notification channel '{}' is not defined in [channels]", name);
+                continue;
+            };
+            match self.retry.run("notification", || channel.send(&self.client, &text), |e| is_retryable(e.as_ref())).await {
+                Ok(()) => sent += 1,
+                Err(e) => warn!("Error sending the notification to '{}': {}", name, e),
+            }
+        }
+
+        if sent > 0 {
+            info!("Notification is sent to {} channels: {}", sent, text);
+        }
+        sent
+    }
+}
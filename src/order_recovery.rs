@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{timeout_at, Instant};
+use tracing::{error, info, warn};
+use quik_rs::quik::QuikEvent;
+use crate::domain::{Order, OrderStatus};
+use crate::psql::{Db, IncidentKind, Severity};
+
+
+/// Outcome of the startup reconciliation of the order book.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBookReconciliation {
+    /// Working orders of the bot still active in the terminal, with their current fills.
+    pub matched: Vec<Order>,
+    /// Active orders of the terminal the journal doesn't know, e.g. placed by hand or by another program.
+    pub unknown: Vec<Order>,
+    /// Working orders of the bot which are not active anymore, with the status of the terminal
+    /// if the snapshot has the order: filled or withdrawn while the bot was stopped.
+    pub orphaned: Vec<(Order, Option<OrderStatus>)>,
+    /// The terminal reported the end of the snapshot. Without it the orders missing from the
+    /// snapshot are not reported as orphaned.
+    pub snapshot_complete: bool,
+}
+
+
+impl OrderBookReconciliation {
+    pub fn is_ok(&self) -> bool {
+        self.unknown.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+
+impl fmt::Display for OrderBookReconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "order book: {} working orders matched, {} unknown, {} orphaned",
+            self.matched.len(),
+            self.unknown.len(),
+            self.orphaned.len()
+        )?;
+        if !self.snapshot_complete {
+            write!(f, ", the snapshot of the terminal is incomplete")?;
+        }
+
+        for order in &self.matched {
+            if order.filled() > 0 {
+                write!(f, "\n  - order {} {} is filled {} of {} lots", order.order_num, order.sec_code, order.filled(), order.quantity)?;
+            }
+        }
+        for order in &self.unknown {
+            write!(
+                f,
+                "\n  - unknown order {} {}.{} {:?} {} lots at {}",
+                order.order_num, order.class_code, order.sec_code, order.side, order.balance, order.price
+            )?;
+        }
+        for (order, status) in &self.orphaned {
+            write!(
+                f,
+                "\n  - orphaned order {} {} {:?} {} lots: {}",
+                order.order_num,
+                order.sec_code,
+                order.side,
+                order.balance,
+                status.map_or("not in the terminal", |status| status.as_str())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Compares the working orders of the journal with the orders of the terminal by order number.
+pub fn compare(local: &[Order], snapshot: &[Order], snapshot_complete: bool) -> OrderBookReconciliation {
+    let remote: BTreeMap<u64, &Order> = snapshot.iter().map(|order| (order.order_num, order)).collect();
+    let local_nums: Vec<u64> = local.iter().map(|order| order.order_num).collect();
+    let mut reconciliation = OrderBookReconciliation {
+        snapshot_complete,
+        ..OrderBookReconciliation::default()
+    };
+
+    for order in local {
+        match remote.get(&order.order_num) {
+            Some(current) if current.status == OrderStatus::Active => reconciliation.matched.push((*current).clone()),
+            Some(current) => reconciliation.orphaned.push((order.clone(), Some(current.status))),
+            None if snapshot_complete => reconciliation.orphaned.push((order.clone(), None)),
+            None => {}
+        }
+    }
+    reconciliation.unknown = snapshot
+        .iter()
+        .filter(|order| order.status == OrderStatus::Active && order.balance > 0 && !local_nums.contains(&order.order_num))
+        .cloned()
+        .collect();
+
+    reconciliation
+}
+
+
+/// Collects the orders of the initial snapshot which the terminal replays after `start_orders`,
+/// until its end or `timeout`. Returns the orders and whether the end was received.
+pub async fn initial_snapshot(events: &mut mpsc::UnboundedReceiver<QuikEvent>, timeout: Duration) -> (Vec<Order>, bool) {
+    let deadline = Instant::now() + timeout;
+    let mut orders: BTreeMap<u64, Order> = BTreeMap::new();
+
+    loop {
+        match timeout_at(deadline, events.recv()).await {
+            Ok(Some(QuikEvent::OrderUpdate(order))) => match order.mode {
+                // The end of the initial snapshot
+                2 => return (orders.into_values().collect(), true),
+                _ => {
+                    orders.insert(order.order_num, Order::from(&order));
+                }
+            },
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => return (orders.into_values().collect(), false),
+        }
+    }
+}
+
+
+/// Reconciles the order book after a restart: the working orders of the bot at the stop,
+/// taken from the last order events of the journal, are compared with the initial snapshot of
+/// the terminal. The unknown and the orphaned orders are recorded as incidents, so a restart
+/// doesn't silently forget the orders in flight; the matched ones can be adopted by
+/// `OrderTracker::adopt` and registered in `KnownOrders`.
+///
+/// The events must be taken before the orders are started, so the snapshot is not missed.
+///
+/// # Example of use
+/// ```ignore
+/// let mut events = terminal.events();
+/// tokio::spawn(supervisor.run(terminal.events()));
+/// let reconciliation = order_recovery::reconcile(&db, &mut events, Duration::from_secs(30)).await?;
+/// for order in &reconciliation.matched {
+///     known_orders.register(order.trans_id);
+///     tracker.lock().unwrap().adopt(order);
+/// }
+/// ```
+pub async fn reconcile(
+    db: &Db,
+    events: &mut mpsc::UnboundedReceiver<QuikEvent>,
+    timeout: Duration,
+) -> Result<OrderBookReconciliation, Box<dyn std::error::Error>> {
+    let (snapshot, complete) = initial_snapshot(events, timeout).await;
+    let local = db.get_working_orders().await?;
+    let reconciliation = compare(&local, &snapshot, complete);
+
+    if reconciliation.is_ok() && reconciliation.snapshot_complete {
+        info!("{}", reconciliation);
+        return Ok(reconciliation);
+    }
+    warn!("{}", reconciliation);
+
+    let mismatches = reconciliation
+        .unknown
+        .iter()
+        .map(|order| (order, format!("unknown order {} {:?} {} lots at {} is active in the terminal", order.order_num, order.side, order.balance, order.price)))
+        .chain(reconciliation.orphaned.iter().map(|(order, status)| {
+            let status = status.map_or("not in the terminal", |status| status.as_str());
+            (order, format!("working order {} of the bot is {} after the restart", order.order_num, status))
+        }));
+    for (order, message) in mismatches {
+        if let Err(e) = db
+            .insert_incident(Severity::Warning, IncidentKind::ReconciliationMismatch, Some(&order.sec_code), &message)
+            .await
+        {
+            error!("Error recording the order mismatch {}: {}", order.order_num, e);
+        }
+    }
+
+    Ok(reconciliation)
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use chrono::{DateTime, Utc};
+use crate::psql::Evaluation;
+use crate::strategy::Action;
+
+
+/// Levels of each side the imbalance confirming the entries is taken over. The quotes exported
+/// to `current_trades` hold the best level of each side only.
+pub const ENTRY_DEPTH: usize = 1;
+
+
+/// A price level of the order book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    pub volume: f64,
+}
+
+
+/// Snapshot of the order book (Level 2) of an instrument.
+/// Bids are sorted by price descending, asks by price ascending.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBook {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+
+/// Order book imbalance indicator, the confirmation of the entries by `min_imbalance`.
+///
+/// The value is `(bid_volume - ask_volume) / (bid_volume + ask_volume)` over the top `depth`
+/// levels of each side and lies in the range `[-1, 1]`: positive values mean buying pressure,
+/// negative values mean selling pressure.
+///
+/// # Example of use
+/// ```ignore
+/// let imbalance = orderbook::Imbalance::new(orderbook::ENTRY_DEPTH, 0.2);
+/// if let Err(skip) = imbalance.check(Action::Buy, &book) {
+///     info!("SBER: buy signal is skipped, {}", skip);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Imbalance {
+    /// Number of top levels of each side taken into account.
+    depth: usize,
+
+    /// Minimum absolute imbalance required to confirm an entry.
+    threshold: f64,
+}
+
+
+impl Imbalance {
+    pub fn new(depth: usize, threshold: f64) -> Self {
+        Imbalance { depth, threshold }
+    }
+
+
+    /// Calculates the imbalance of the order book. An empty book gives zero.
+    pub fn value(&self, book: &OrderBook) -> f64 {
+        let bid_volume: f64 = book.bids.iter().take(self.depth).map(|level| level.volume).sum();
+        let ask_volume: f64 = book.asks.iter().take(self.depth).map(|level| level.volume).sum();
+        let total = bid_volume + ask_volume;
+
+        if total > 0.0 {
+            (bid_volume - ask_volume) / total
+        } else {
+            0.0
+        }
+    }
+
+
+    /// Confirms a long entry when bids outweigh asks by at least the threshold.
+    pub fn confirms_buy(&self, book: &OrderBook) -> bool {
+        self.value(book) >= self.threshold
+    }
+
+
+    /// Confirms a short entry when asks outweigh bids by at least the threshold.
+    pub fn confirms_sell(&self, book: &OrderBook) -> bool {
+        self.value(book) <= -self.threshold
+    }
+
+
+    /// Checks that the book confirms an entry; the exits are not checked.
+    pub fn check(&self, action: Action, book: &OrderBook) -> Result<(), ImbalanceSkip> {
+        let confirmed = match action {
+            Action::Buy => self.confirms_buy(book),
+            Action::Sell => self.confirms_sell(book),
+            _ => true,
+        };
+        if confirmed {
+            Ok(())
+        } else {
+            Err(ImbalanceSkip { value: self.value(book), threshold: self.threshold })
+        }
+    }
+}
+
+
+/// Entry skipped for the order book not confirming it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImbalanceSkip {
+    pub value: f64,
+    pub threshold: f64,
+}
+
+
+impl ImbalanceSkip {
+    pub const REASON_CODE: &'static str = "min_imbalance";
+
+
+    /// Record of the skip in the evaluation audit.
+    pub fn evaluation(&self, instrument_code: &str, candle_time: DateTime<Utc>) -> Evaluation {
+        Evaluation {
+            instrument_code: instrument_code.to_string(),
+            candle_time,
+            indicators: vec![
+                ("imbalance".to_string(), self.value),
+                ("min_imbalance".to_string(), self.threshold),
+            ],
+            decision: "skip".to_string(),
+            reason_code: Self::REASON_CODE.to_string(),
+        }
+    }
+}
+
+
+impl fmt::Display for ImbalanceSkip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "order book imbalance {:.2} is within {}", self.value, self.threshold)
+    }
+}
@@ -0,0 +1,759 @@
+use std::collections::HashMap;
+use std::os::raw::c_ulong;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::interval;
+use tracing::{error, info, warn};
+use quik_rs::quik::{OrderInfo, QuikEvent, TransactionReply};
+use quik_rs::transaction::{Side, Transaction, TransactionKind};
+use crate::domain::Order;
+use crate::gateway::OrderGateway;
+use crate::instrument_info::InstrumentCache;
+use crate::psql::{Db, IncidentKind, Severity};
+
+
+/// Time a reply to a submission is waited for before the journal is searched for it.
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Submissions of an order without a reply, the first one included, before it is given up.
+const MAX_ATTEMPTS: u32 = 3;
+
+
+/// Chasing of the limit orders which are not filled in time, the `[chase]` table of the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaseSettings {
+    /// Time without fills after which a working limit order is cancelled and its remaining
+    /// lots are placed again at a new price.
+    pub timeout: Duration,
+    /// Price steps of the instrument by which every chase moves the price towards the market.
+    pub ticks: u32,
+    /// Chases of an order after which it is left working at its last price.
+    pub max_chases: u32,
+}
+
+
+impl Default for ChaseSettings {
+    fn default() -> Self {
+        ChaseSettings {
+            timeout: Duration::from_secs(30),
+            ticks: 1,
+            max_chases: 3,
+        }
+    }
+}
+
+
+/// State of an order sent by the bot.
+///
+/// ```text
+/// PendingSubmit → Accepted → PartiallyFilled → Filled
+///       │            │              │
+///       └→ Rejected  └──────────────┴→ Cancelled
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderState {
+    /// The transaction is sent, the reply is not received yet.
+    PendingSubmit,
+    /// The order is placed in the trading system.
+    Accepted,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+
+impl OrderState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderState::PendingSubmit => "pending_submit",
+            OrderState::Accepted => "accepted",
+            OrderState::PartiallyFilled => "partially_filled",
+            OrderState::Filled => "filled",
+            OrderState::Cancelled => "cancelled",
+            OrderState::Rejected => "rejected",
+        }
+    }
+
+
+    /// The state won't change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, OrderState::Filled | OrderState::Cancelled | OrderState::Rejected)
+    }
+
+
+    /// Order of the states along the lifecycle: the replies and the order callbacks arrive
+    /// in any order, so an order never goes back to an earlier state.
+    fn rank(&self) -> u8 {
+        match self {
+            OrderState::PendingSubmit => 0,
+            OrderState::Accepted => 1,
+            OrderState::PartiallyFilled => 2,
+            OrderState::Filled | OrderState::Cancelled | OrderState::Rejected => 3,
+        }
+    }
+}
+
+
+/// Order sent by the bot with its current state.
+#[derive(Debug, Clone)]
+pub struct TrackedOrder {
+    pub transaction: Transaction,
+    pub state: OrderState,
+    pub order_num: Option<u64>,
+    /// Filled lots.
+    pub filled: i64,
+    /// Lots not filled yet, the balance of the order callbacks.
+    pub balance: i64,
+    /// Number of submissions, the first one included.
+    pub attempts: u32,
+    /// Number of the chases which placed the remaining lots again, see `ChaseSettings`.
+    pub chases: u32,
+    /// Reason of the rejection or the cancellation.
+    pub reason: Option<String>,
+    submitted_at: Instant,
+    /// Placement or the last fill of the order, the start of the chase timeout.
+    working_since: Instant,
+    /// The order was given up after the submit timeout: a late acceptance is cancelled.
+    abandoned: bool,
+    /// The order is cancelled to be chased: its cancellation places the remaining lots again.
+    chasing: bool,
+    /// The submit timeout passed and the journal is searched for the submission, see `confirm`.
+    unconfirmed: bool,
+}
+
+
+impl TrackedOrder {
+    pub fn trans_id(&self) -> c_ulong {
+        self.transaction.trans_id
+    }
+}
+
+
+/// State machine of the orders of the bot driven by the transaction replies and the order
+/// callbacks, so the bot knows which orders are in flight.
+///
+/// An order without a reply within `SUBMIT_TIMEOUT` is not sent again blindly: the reply may be
+/// lost while the order is placed. The journal of the events is searched for its TRANS_ID
+/// first, and only a submission the journal doesn't know is given up and sent again with a new
+/// TRANS_ID, up to `MAX_ATTEMPTS` submissions in total. If the given up submission is accepted
+/// after all, it is cancelled; if it is filled before the cancellation, the fill is reported.
+///
+/// The order callbacks track the partial fills by the balance of the order. With a chase
+/// configured, a limit order without fills for the chase timeout is cancelled, and once
+/// the cancellation is confirmed its remaining lots are placed again as a new order,
+/// repriced towards the market by `ticks` price steps of the instrument.
+///
+/// # Example of use
+/// ```ignore
+/// let orders = Arc::new(Mutex::new(
+///     OrderTracker::new(gateway.clone()).with_chase(ChaseSettings::default(), instruments.clone()),
+/// ));
+/// tokio::spawn(orders::run(orders.clone(), db.clone(), terminal.events(), Duration::from_secs(1)));
+///
+/// let trans_id = orders.lock().unwrap().submit(Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, None))?;
+/// ```
+pub struct OrderTracker {
+    /// Allocates the TRANS_IDs, records the intents and sends the transactions.
+    gateway: Arc<OrderGateway>,
+    orders: HashMap<c_ulong, TrackedOrder>,
+    chase: Option<ChaseSettings>,
+    /// Price grids of the instruments, the orders of the instruments without a price step are not chased.
+    instruments: InstrumentCache,
+}
+
+
+impl OrderTracker {
+    pub fn new(gateway: Arc<OrderGateway>) -> Self {
+        OrderTracker {
+            gateway,
+            orders: HashMap::new(),
+            chase: None,
+            instruments: InstrumentCache::new(Vec::new()),
+        }
+    }
+
+
+    /// Chases the limit orders not filled within the timeout, repriced on the price grids of `instruments`.
+    pub fn with_chase(mut self, chase: ChaseSettings, instruments: InstrumentCache) -> Self {
+        self.chase = Some(chase);
+        self.instruments = instruments;
+        self
+    }
+
+
+    /// Sends the transaction with a new TRANS_ID and tracks it in `PendingSubmit`.
+    pub fn submit(&mut self, transaction: Transaction) -> Result<c_ulong, Box<dyn std::error::Error>> {
+        self.send(transaction, 1)
+    }
+
+
+    fn send(&mut self, transaction: Transaction, attempts: u32) -> Result<c_ulong, Box<dyn std::error::Error>> {
+        let trans_id = self.gateway.send(transaction.clone())?;
+        let transaction = transaction.with_trans_id(trans_id);
+
+        info!("Order {} {} is submitted, attempt {}", trans_id, transaction.sec_code, attempts);
+        let now = Instant::now();
+        let balance = match transaction.kind {
+            TransactionKind::Order { quantity, .. } | TransactionKind::StopOrder { quantity, .. } => quantity,
+            _ => 0,
+        };
+        self.orders.insert(
+            trans_id,
+            TrackedOrder {
+                transaction,
+                state: OrderState::PendingSubmit,
+                order_num: None,
+                filled: 0,
+                balance,
+                attempts,
+                chases: 0,
+                reason: None,
+                submitted_at: now,
+                working_since: now,
+                abandoned: false,
+                chasing: false,
+                unconfirmed: false,
+            },
+        );
+
+        Ok(trans_id)
+    }
+
+
+    /// Tracks a working order placed before a restart, found by the startup reconciliation.
+    pub fn adopt(&mut self, order: &Order) {
+        let transaction = Transaction::new_order(&order.class_code, &order.sec_code, order.side, order.quantity, Some(order.price))
+            .with_trans_id(order.trans_id);
+        let filled = order.filled();
+
+        info!("Order {} {} is adopted after the restart, {} of {} lots filled", order.trans_id, order.sec_code, filled, order.quantity);
+        self.orders.insert(
+            order.trans_id,
+            TrackedOrder {
+                transaction,
+                state: if filled > 0 { OrderState::PartiallyFilled } else { OrderState::Accepted },
+                order_num: Some(order.order_num),
+                filled,
+                balance: order.balance,
+                attempts: 1,
+                chases: 0,
+                reason: None,
+                submitted_at: Instant::now(),
+                working_since: Instant::now(),
+                abandoned: false,
+                chasing: false,
+                unconfirmed: false,
+            },
+        );
+    }
+
+
+    /// Orders which are not filled, cancelled or rejected yet.
+    pub fn in_flight(&self) -> Vec<&TrackedOrder> {
+        let mut orders: Vec<&TrackedOrder> = self.orders.values().filter(|order| !order.state.is_final()).collect();
+        orders.sort_by_key(|order| order.trans_id());
+        orders
+    }
+
+
+    /// Forgets the orders in final states. The abandoned orders are kept, so their late
+    /// acceptance is still cancelled.
+    pub fn prune(&mut self) {
+        self.orders.retain(|_, order| !order.state.is_final() || order.abandoned);
+    }
+
+
+    /// Applies an event of the terminal, returns the order rejected by it.
+    pub fn on_event(&mut self, event: &QuikEvent) -> Option<TrackedOrder> {
+        match event {
+            QuikEvent::TransactionReply(reply) => return self.on_reply(reply),
+            QuikEvent::OrderUpdate(order) => self.on_order(order),
+            _ => {}
+        }
+        None
+    }
+
+
+    /// Applies a transaction reply, returns the order if the reply rejected it.
+    pub fn on_reply(&mut self, reply: &TransactionReply) -> Option<TrackedOrder> {
+        let order = self.orders.get_mut(&reply.trans_id)?;
+        if reply.order_num != 0 {
+            order.order_num = Some(reply.order_num);
+        }
+
+        if reply.status.is_executed() {
+            transition(order, OrderState::Accepted, None);
+        } else if reply.status.is_rejected() && order.state != OrderState::Rejected {
+            transition(order, OrderState::Rejected, Some(reply.reply_message.clone()));
+            return (order.state == OrderState::Rejected).then(|| order.clone());
+        }
+        // Transient statuses leave the order pending until the submit timeout resubmits it
+        None
+    }
+
+
+    pub fn on_order(&mut self, info: &OrderInfo) {
+        // The end of the initial snapshot
+        if info.mode == 2 {
+            return;
+        }
+        let Some(order) = self.orders.get_mut(&info.trans_id) else {
+            return;
+        };
+        order.order_num = Some(info.order_num);
+        // A callback without the quantity doesn't tell the filled lots
+        let filled = if info.qty > 0 { (info.qty - info.balance).max(0) } else { order.filled };
+        let new_fill = filled > order.filled;
+        if new_fill {
+            order.working_since = Instant::now();
+        }
+        order.filled = filled;
+        order.balance = info.balance;
+
+        let state = match info.status {
+            1 if order.filled > 0 => OrderState::PartiallyFilled,
+            1 => OrderState::Accepted,
+            2 => OrderState::Cancelled,
+            _ => OrderState::Filled,
+        };
+
+        if order.abandoned {
+            match state {
+                OrderState::Accepted | OrderState::PartiallyFilled => {
+                    warn!("Abandoned order {} {} is accepted late and is cancelled", info.trans_id, info.order_num);
+                    let kill = Transaction::kill_order(&info.class_code, &info.sec_code, info.order_num);
+                    if let Err(e) = self.send_kill(kill) {
+                        error!("Error cancelling the abandoned order {}: {}", info.order_num, e);
+                    }
+                }
+                OrderState::Filled => {
+                    error!("Abandoned order {} {} is filled, the order was resubmitted and may be doubled", info.trans_id, info.order_num);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        let completed = !order.state.is_final() && state.is_final();
+        transition(order, state, None);
+        if new_fill && state == OrderState::PartiallyFilled {
+            info!("Order {} {}: {} of {} lots filled, {} remaining", order.trans_id(), order.transaction.sec_code, order.filled, info.qty, order.balance);
+        }
+        if !completed {
+            return;
+        }
+
+        match state {
+            OrderState::Filled => info!("Order {} {} is completed: {} of {} lots filled", order.trans_id(), order.transaction.sec_code, order.filled, info.qty),
+            OrderState::Cancelled if order.filled > 0 => {
+                info!("Order {} {} is cancelled with {} of {} lots filled, {} remaining", order.trans_id(), order.transaction.sec_code, order.filled, info.qty, order.balance)
+            }
+            _ => {}
+        }
+        if state == OrderState::Cancelled && order.chasing && order.balance > 0 {
+            let trans_id = order.trans_id();
+            self.chase_remaining(trans_id);
+        }
+    }
+
+
+    /// Sends a cancellation of every accepted order of the bot which is not filled yet.
+    /// The orders become `Cancelled` by their callbacks. Returns the number of cancellations sent.
+    /// The cancelled orders are not chased.
+    pub fn cancel_working(&mut self) -> usize {
+        let working: Vec<(String, String, u64)> = self
+            .orders
+            .values_mut()
+            .filter(|order| matches!(order.state, OrderState::Accepted | OrderState::PartiallyFilled))
+            .filter_map(|order| {
+                order.chasing = false;
+                Some((order.transaction.class_code.clone(), order.transaction.sec_code.clone(), order.order_num?))
+            })
+            .collect();
+
+        let mut sent = 0;
+        for (class_code, sec_code, order_num) in working {
+            match self.send_kill(Transaction::kill_order(&class_code, &sec_code, order_num)) {
+                Ok(()) => sent += 1,
+                Err(e) => error!("Error cancelling the order {}: {}", order_num, e),
+            }
+        }
+        sent
+    }
+
+
+    fn send_kill(&self, kill: Transaction) -> Result<(), Box<dyn std::error::Error>> {
+        self.gateway.send(kill)?;
+        Ok(())
+    }
+
+
+    /// Orders pending longer than the submit timeout, to be settled by `confirm` once the journal
+    /// is searched for them. Nothing expires while transactions are queued in the throttle, since
+    /// the queued ones are not sent yet.
+    pub fn expire(&mut self, now: Instant) -> Vec<c_ulong> {
+        if self.gateway.throttle_stats().queue_depth() > 0 {
+            return Vec::new();
+        }
+        let mut expired: Vec<c_ulong> = self
+            .orders
+            .values_mut()
+            .filter(|order| order.state == OrderState::PendingSubmit && !order.unconfirmed)
+            .filter(|order| now.duration_since(order.submitted_at) >= SUBMIT_TIMEOUT)
+            .map(|order| {
+                order.unconfirmed = true;
+                order.trans_id()
+            })
+            .collect();
+        expired.sort();
+        expired
+    }
+
+
+    /// Settles an expired order by the journal: `found` if the journal has a reply or an order
+    /// of its TRANS_ID. A found submission reached the terminal and its order keeps waiting for
+    /// the events. The one the journal doesn't know is given up and sent again, or rejected after
+    /// the last attempt. Returns the new TRANS_ID.
+    pub fn confirm(&mut self, trans_id: c_ulong, found: bool, now: Instant) -> Option<c_ulong> {
+        let order = self.orders.get_mut(&trans_id)?;
+        if order.state != OrderState::PendingSubmit || !order.unconfirmed {
+            return None;
+        }
+        order.unconfirmed = false;
+
+        if found {
+            warn!("Order {} has no reply within {:?}, but the journal has it, it is not resubmitted", trans_id, SUBMIT_TIMEOUT);
+            order.submitted_at = now;
+            return None;
+        }
+
+        order.abandoned = true;
+        let reason = format!("no reply within {:?} and not in the journal", SUBMIT_TIMEOUT);
+        if order.attempts >= MAX_ATTEMPTS {
+            transition(order, OrderState::Rejected, Some(format!("{} after {} attempts", reason, order.attempts)));
+            return None;
+        }
+        transition(order, OrderState::Cancelled, Some(reason));
+
+        let transaction = order.transaction.clone();
+        let attempts = order.attempts + 1;
+        match self.send(transaction, attempts) {
+            Ok(new_trans_id) => {
+                warn!("Order {} is resubmitted as {}", trans_id, new_trans_id);
+                self.gateway.supersede(trans_id, new_trans_id);
+                Some(new_trans_id)
+            }
+            Err(e) => {
+                error!("Error resubmitting the order {}: {}", trans_id, e);
+                None
+            }
+        }
+    }
+
+
+    /// Cancels the working limit orders without fills for the chase timeout, their remaining
+    /// lots are placed again when the cancellation is confirmed. Returns the number of the
+    /// cancellations sent.
+    pub fn chase(&mut self, now: Instant) -> usize {
+        let Some(chase) = &self.chase else {
+            return 0;
+        };
+        let stale: Vec<(c_ulong, Transaction)> = self
+            .orders
+            .values()
+            .filter(|order| matches!(order.state, OrderState::Accepted | OrderState::PartiallyFilled))
+            .filter(|order| !order.abandoned && !order.chasing && order.chases < chase.max_chases)
+            .filter(|order| matches!(order.transaction.kind, TransactionKind::Order { price: Some(_), .. }))
+            .filter(|order| self.price_step(&order.transaction).is_some())
+            .filter(|order| now.duration_since(order.working_since) >= chase.timeout)
+            .filter_map(|order| {
+                let kill = Transaction::kill_order(&order.transaction.class_code, &order.transaction.sec_code, order.order_num?);
+                Some((order.trans_id(), kill))
+            })
+            .collect();
+
+        let mut sent = 0;
+        for (trans_id, kill) in stale {
+            match self.send_kill(kill) {
+                Ok(()) => {
+                    info!("Order {} is not filled within {:?} and is cancelled to be chased", trans_id, chase.timeout);
+                    if let Some(order) = self.orders.get_mut(&trans_id) {
+                        order.chasing = true;
+                    }
+                    sent += 1;
+                }
+                Err(e) => error!("Error cancelling the order {} to chase it: {}", trans_id, e),
+            }
+        }
+        sent
+    }
+
+
+    fn price_step(&self, transaction: &Transaction) -> Option<f64> {
+        self.instruments.get(&transaction.class_code, &transaction.sec_code)?.price_step
+    }
+
+
+    /// Places the remaining lots of the cancelled order again, with the price moved towards
+    /// the market by the ticks of the chase.
+    fn chase_remaining(&mut self, trans_id: c_ulong) {
+        let (Some(chase), Some(order)) = (&self.chase, self.orders.get(&trans_id)) else {
+            return;
+        };
+        let TransactionKind::Order { side, price: Some(price), .. } = order.transaction.kind else {
+            return;
+        };
+        let Some(info) = self.instruments.get(&order.transaction.class_code, &order.transaction.sec_code) else {
+            return;
+        };
+        let Some(step) = info.price_step else {
+            return;
+        };
+
+        // On the grid, so the trading system doesn't reject the new price
+        let shift = step * chase.ticks as f64;
+        let price_format = info.price_format();
+        let price = price_format.round(match side {
+            Side::Buy => price + shift,
+            Side::Sell => price - shift,
+        });
+        let mut transaction = order.transaction.clone().with_price_format(price_format);
+        transaction.kind = TransactionKind::Order { side, quantity: order.balance, price: Some(price) };
+        let (remaining, chases) = (order.balance, order.chases + 1);
+
+        match self.send(transaction, 1) {
+            Ok(new_trans_id) => {
+                info!("Order {} is chased as {}: {} lots at {}, chase {}", trans_id, new_trans_id, remaining, price_format.format(price), chases);
+                if let Some(order) = self.orders.get_mut(&new_trans_id) {
+                    order.chases = chases;
+                }
+            }
+            Err(e) => error!("Error chasing the order {}: {}", trans_id, e),
+        }
+    }
+}
+
+
+fn transition(order: &mut TrackedOrder, state: OrderState, reason: Option<String>) {
+    if order.state == state || order.state.rank() >= state.rank() {
+        return;
+    }
+
+    info!("Order {} {}: {} → {}", order.trans_id(), order.transaction.sec_code, order.state.as_str(), state.as_str());
+    order.state = state;
+    if reason.is_some() {
+        order.reason = reason;
+    }
+}
+
+
+/// Drives the order tracker by the events of the terminal and checks the submit and the chase
+/// timeouts every `check_interval` until the events are closed, forgetting the orders in final
+/// states. The expired orders are searched in the journal of `db` by TRANS_ID; while the journal
+/// can't be read they are not resubmitted. Orders rejected by the terminal are recorded as
+/// `rejection` incidents.
+pub async fn run(tracker: Arc<Mutex<OrderTracker>>, db: Arc<Db>, mut events: mpsc::UnboundedReceiver<QuikEvent>, check_interval: Duration) {
+    let mut ticker = interval(check_interval);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => {
+                    let rejected = tracker.lock().unwrap_or_else(|e| e.into_inner()).on_event(&event);
+                    if let Some(order) = rejected {
+                        let message = format!("Order {} is rejected: {}", order.trans_id(), order.reason.as_deref().unwrap_or_default());
+                        if let Err(e) = db.insert_incident(Severity::Warning, IncidentKind::Rejection, Some(&order.transaction.sec_code), &message).await {
+                            error!("Error recording the rejection of the order {}: {}", order.trans_id(), e);
+                        }
+                    }
+                }
+                None => {
+                    warn!("Order events are closed, the order tracker is stopped");
+                    return;
+                }
+            },
+            _ = ticker.tick() => {
+                let expired = {
+                    let mut tracker = tracker.lock().unwrap_or_else(|e| e.into_inner());
+                    tracker.prune();
+                    tracker.chase(Instant::now());
+                    tracker.expire(Instant::now())
+                };
+                if expired.is_empty() {
+                    continue;
+                }
+
+                let trans_ids: Vec<i64> = expired.iter().map(|trans_id| *trans_id as i64).collect();
+                let found: Vec<c_ulong> = match db.get_acknowledged_trans_ids(&trans_ids).await {
+                    Ok(rows) => rows.into_iter().map(|(trans_id, _)| trans_id as c_ulong).collect(),
+                    Err(e) => {
+                        error!("Error searching the journal for the orders without a reply, they are not resubmitted: {}", e);
+                        expired.clone()
+                    }
+                };
+                let mut tracker = tracker.lock().unwrap_or_else(|e| e.into_inner());
+                for trans_id in expired {
+                    tracker.confirm(trans_id, found.contains(&trans_id), Instant::now());
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quik_rs::mock::MockTerminal;
+    use quik_rs::quik::QuikApi;
+    use crate::gateway;
+    use crate::instrument_info::InstrumentInfo;
+
+
+    /// Tracker over a terminal which is not connected: the transactions are lost without a reply.
+    async fn lost_replies(name: &str) -> OrderTracker {
+        let terminal: Arc<dyn QuikApi> = Arc::new(MockTerminal::new());
+        OrderTracker::new(gateway::for_tests(terminal, name))
+    }
+
+
+    async fn expired(tracker: &mut OrderTracker, now: Instant) -> Vec<c_ulong> {
+        // The throttle sends the queued transactions first
+        while tracker.gateway.throttle_stats().queue_depth() > 0 {
+            tokio::task::yield_now().await;
+        }
+        tracker.expire(now)
+    }
+
+
+    #[tokio::test]
+    async fn order_found_in_the_journal_is_not_resubmitted() {
+        let mut tracker = lost_replies("found_in_journal").await;
+        let trans_id = tracker.submit(Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, Some(250.0))).unwrap();
+        let now = Instant::now();
+
+        assert!(expired(&mut tracker, now).await.is_empty());
+        let later = now + Duration::from_secs(11);
+        assert_eq!(expired(&mut tracker, later).await, vec![trans_id]);
+        // Waits for the journal, not expired twice
+        assert!(tracker.expire(later).is_empty());
+
+        assert_eq!(tracker.confirm(trans_id, true, later), None);
+        assert_eq!(tracker.in_flight().len(), 1);
+        assert_eq!(tracker.orders.get(&trans_id).unwrap().state, OrderState::PendingSubmit);
+    }
+
+
+    #[tokio::test]
+    async fn order_missing_from_the_journal_is_resubmitted_up_to_max_attempts() {
+        let mut tracker = lost_replies("missing_from_journal").await;
+        let mut trans_id = tracker.submit(Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, Some(250.0))).unwrap();
+        let mut now = Instant::now();
+
+        for attempt in 2..=MAX_ATTEMPTS {
+            now += Duration::from_secs(11);
+            assert_eq!(expired(&mut tracker, now).await, vec![trans_id]);
+            let next = tracker.confirm(trans_id, false, now).unwrap();
+            assert_eq!(tracker.orders.get(&trans_id).unwrap().state, OrderState::Cancelled);
+            assert_eq!(tracker.orders.get(&next).unwrap().attempts, attempt);
+            trans_id = next;
+        }
+
+        now += Duration::from_secs(11);
+        assert_eq!(expired(&mut tracker, now).await, vec![trans_id]);
+        assert_eq!(tracker.confirm(trans_id, false, now), None);
+        assert_eq!(tracker.orders.get(&trans_id).unwrap().state, OrderState::Rejected);
+        assert!(tracker.in_flight().is_empty());
+    }
+
+
+    #[tokio::test]
+    async fn order_is_filled_through_the_mock_terminal() {
+        let terminal: Arc<dyn QuikApi> = Arc::new(MockTerminal::new().with_fill_latency(Duration::from_millis(20)));
+        terminal.connect().unwrap();
+        terminal.start_orders().unwrap();
+        let mut events = terminal.events();
+        let mut tracker = OrderTracker::new(gateway::for_tests(terminal.clone(), "mock_fill"));
+
+        let trans_id = tracker.submit(Transaction::new_order("QJSIM", "SBER", Side::Sell, 2, Some(250.0))).unwrap();
+        let mut states = Vec::new();
+        while states.last() != Some(&OrderState::Filled) {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+            assert!(tracker.on_event(&event).is_none());
+            states.push(tracker.orders.get(&trans_id).unwrap().state);
+        }
+
+        assert_eq!(states, vec![OrderState::Accepted, OrderState::Filled]);
+        let order = tracker.orders.get(&trans_id).unwrap();
+        assert_eq!((order.order_num, order.filled, order.balance), (Some(1), 2, 0));
+        assert!(tracker.in_flight().is_empty());
+    }
+
+
+    #[tokio::test]
+    async fn chased_price_stays_on_the_price_grid() {
+        let info = InstrumentInfo {
+            class_code: "CETS".to_string(),
+            sec_code: "USD000UTSTOM".to_string(),
+            lot: 1,
+            lot_multiplier: None,
+            price_step: Some(0.0025),
+            price_decimals: Some(4),
+            session_status: None,
+            instrument_status: None,
+            price_max: None,
+            price_min: None,
+        };
+        let chase = ChaseSettings { timeout: Duration::from_secs(30), ticks: 3, max_chases: 3 };
+        let mut tracker = lost_replies("chase_grid").await.with_chase(chase, InstrumentCache::with_infos(vec![info]));
+        let trans_id = tracker.submit(Transaction::new_order("CETS", "USD000UTSTOM", Side::Buy, 3, Some(92.5025))).unwrap();
+
+        let order = |status, balance| OrderInfo {
+            trans_id,
+            order_num: 7,
+            class_code: "CETS".to_string(),
+            sec_code: "USD000UTSTOM".to_string(),
+            qty: 3,
+            balance,
+            status,
+            ..OrderInfo::default()
+        };
+        tracker.on_order(&order(1, 2));
+        assert_eq!(tracker.chase(Instant::now() + Duration::from_secs(31)), 1);
+        tracker.on_order(&order(2, 2));
+
+        let chased = tracker.in_flight().into_iter().find(|order| order.trans_id() != trans_id).unwrap();
+        assert_eq!(chased.chases, 1);
+        assert!(chased.transaction.to_string().contains("PRICE=92.5100;"));
+        assert!(chased.transaction.to_string().contains("QUANTITY=2;"));
+    }
+
+
+    #[tokio::test]
+    async fn callback_without_the_quantity_keeps_the_filled_lots() {
+        let mut tracker = lost_replies("unknown_quantity").await;
+        let trans_id = tracker.submit(Transaction::new_order("QJSIM", "SBER", Side::Buy, 3, Some(250.0))).unwrap();
+        let order = |qty, balance| OrderInfo {
+            trans_id,
+            order_num: 7,
+            class_code: "QJSIM".to_string(),
+            sec_code: "SBER".to_string(),
+            qty,
+            balance,
+            status: 1,
+            ..OrderInfo::default()
+        };
+
+        tracker.on_order(&order(3, 2));
+        tracker.on_order(&order(0, 2));
+        let tracked = tracker.orders.get(&trans_id).unwrap();
+        assert_eq!((tracked.filled, tracked.state), (1, OrderState::PartiallyFilled));
+
+        // A balance above the quantity is not a negative fill
+        tracker.on_order(&order(3, 5));
+        assert_eq!(tracker.orders.get(&trans_id).unwrap().filled, 0);
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use quik_rs::quik::{QuikEvent, TradeInfo};
+use crate::clock::Clock;
+use crate::domain;
+use crate::psql::{Db, Fill, PositionRecord};
+use crate::strategy::Action;
+
+
+/// Money flows of the trading split by where the returns go: the gross profit realized by the
+/// prices of the trades, the commissions, and the slippage against the expected prices.
+///
+/// The slippage is already in the prices of the trades and so in the gross profit; it is
+/// an estimate of the part of the gross profit lost to the execution, the net profit is
+/// the gross profit minus the commissions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MoneyFlows {
+    pub gross_pnl: f64,
+    pub commission: f64,
+    pub slippage: f64,
+}
+
+
+impl MoneyFlows {
+    pub fn net_pnl(&self) -> f64 {
+        self.gross_pnl - self.commission
+    }
+
+
+    /// Gross profit the trades would have realized at the expected prices.
+    pub fn expected_pnl(&self) -> f64 {
+        self.gross_pnl + self.slippage
+    }
+}
+
+
+impl std::ops::AddAssign for MoneyFlows {
+    fn add_assign(&mut self, other: Self) {
+        self.gross_pnl += other.gross_pnl;
+        self.commission += other.commission;
+        self.slippage += other.slippage;
+    }
+}
+
+
+/// Slippage of a fill against its expected price in money: positive when the price is worse,
+/// i.e. higher for a buy and lower for a sell, 0 without the expected price.
+pub fn slippage(is_sell: bool, quantity: i64, price: f64, expected_price: Option<f64>, point_value: f64) -> f64 {
+    let Some(expected_price) = expected_price else {
+        return 0.0;
+    };
+    let worse = if is_sell { expected_price - price } else { price - expected_price };
+    worse * quantity as f64 * point_value
+}
+
+
+/// Money value of a move of the price by one for one lot of the trade: the value of the trade
+/// per price and lot, e.g. the lot size of a share or the step price per step of a futures.
+/// 1 if the trade has no value.
+pub fn point_value(trade: &TradeInfo) -> f64 {
+    let notional = trade.price * trade.qty as f64;
+    if trade.value > 0.0 && notional > 0.0 {
+        trade.value / notional
+    } else {
+        1.0
+    }
+}
+
+
+/// Position of an instrument: quantity in lots, positive for long and negative for short,
+/// with the average entry price and the profit in money.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstrumentPosition {
+    pub class_code: String,
+    pub sec_code: String,
+    /// Trade account of the trades of the position, empty before the first trade.
+    pub account: String,
+    pub quantity: i64,
+    pub average_price: f64,
+    /// Realized profit before the commissions.
+    pub realized_pnl: f64,
+    pub commission: f64,
+    /// Estimated slippage of the fills, see `MoneyFlows`.
+    pub slippage: f64,
+    pub last_price: f64,
+    /// Number of the last applied trade, older trades are already in the position.
+    pub last_trade_num: u64,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Time the position was opened or reversed, `None` while it is flat.
+    pub opened_at: Option<DateTime<Utc>>,
+    /// Money value of a move of the price by one for one lot, see `point_value`.
+    pub point_value: f64,
+}
+
+
+impl InstrumentPosition {
+    /// Applies a fill of `quantity` units worth `point_value` per price unit each and returns
+    /// the realized profit of the reduced part of the position in money.
+    pub fn apply(&mut self, quantity: i64, price: f64, point_value: f64) -> Option<f64> {
+        let closed = if self.quantity != 0 && self.quantity.signum() != quantity.signum() {
+            quantity.abs().min(self.quantity.abs())
+        } else {
+            0
+        };
+        let realized = (closed > 0).then(|| (price - self.average_price) * (closed * self.quantity.signum()) as f64 * point_value);
+
+        let opened = quantity.abs() - closed;
+        self.quantity += quantity;
+        if self.quantity == 0 {
+            self.average_price = 0.0;
+        } else if opened > 0 {
+            // The position is increased or reversed, the opened part averages the price
+            let kept = self.quantity.abs() - opened;
+            self.average_price = (self.average_price * kept as f64 + price * opened as f64) / self.quantity.abs() as f64;
+        }
+
+        self.realized_pnl += realized.unwrap_or(0.0);
+        self.last_price = price;
+        self.point_value = point_value;
+        realized
+    }
+
+
+    /// Profit of the open position at the last price in money.
+    pub fn unrealized_pnl(&self) -> f64 {
+        (self.last_price - self.average_price) * self.quantity as f64 * self.point_value
+    }
+
+
+    pub fn money_flows(&self) -> MoneyFlows {
+        MoneyFlows {
+            gross_pnl: self.realized_pnl,
+            commission: self.commission,
+            slippage: self.slippage,
+        }
+    }
+}
+
+
+impl From<PositionRecord> for InstrumentPosition {
+    fn from(record: PositionRecord) -> Self {
+        InstrumentPosition {
+            class_code: record.class_code,
+            sec_code: record.sec_code,
+            account: record.account,
+            quantity: record.quantity,
+            average_price: record.average_price,
+            realized_pnl: record.realized_pnl,
+            commission: record.commission,
+            slippage: record.slippage,
+            last_price: record.last_price,
+            last_trade_num: record.last_trade_num as u64,
+            updated_at: Some(record.updated_at),
+            opened_at: record.opened_at,
+            point_value: record.point_value,
+        }
+    }
+}
+
+
+impl From<&InstrumentPosition> for PositionRecord {
+    fn from(position: &InstrumentPosition) -> Self {
+        PositionRecord {
+            class_code: position.class_code.clone(),
+            sec_code: position.sec_code.clone(),
+            account: position.account.clone(),
+            quantity: position.quantity,
+            average_price: position.average_price,
+            realized_pnl: position.realized_pnl,
+            commission: position.commission,
+            slippage: position.slippage,
+            last_price: position.last_price,
+            last_trade_num: position.last_trade_num as i64,
+            updated_at: position.updated_at.unwrap_or_else(Utc::now),
+            opened_at: position.opened_at,
+            point_value: position.point_value,
+        }
+    }
+}
+
+
+/// Positions of all instruments built from the trades of the terminal.
+///
+/// A trade is applied once: trades with a number not greater than the last applied trade
+/// of the instrument are skipped, so the initial snapshot of trades after a restart doesn't
+/// double the positions loaded from the database.
+///
+/// # Example of use
+/// ```ignore
+/// let portfolio = Arc::new(RwLock::new(Portfolio::load(&db).await?));
+/// tokio::spawn(portfolio::run(db.clone(), portfolio.clone(), terminal.events(), clock.clone()));
+///
+/// if portfolio.read().unwrap().is_positioned("SBER", action) {
+///     return Ok(());
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Portfolio {
+    positions: HashMap<String, InstrumentPosition>,
+    /// Expected prices of the last orders of the instruments with their direction, a sell or
+    /// a buy, for the slippage of their fills.
+    expected_prices: HashMap<String, (bool, f64)>,
+}
+
+
+impl Portfolio {
+    pub fn new(positions: Vec<InstrumentPosition>) -> Self {
+        Portfolio {
+            positions: positions
+                .into_iter()
+                .map(|position| (position.sec_code.clone(), position))
+                .collect(),
+            expected_prices: HashMap::new(),
+        }
+    }
+
+
+    /// Loads the positions persisted in the `positions` table.
+    pub async fn load(db: &Db) -> Result<Self, Box<dyn std::error::Error>> {
+        let positions = db.get_positions().await?;
+        Ok(Self::new(positions.into_iter().map(InstrumentPosition::from).collect()))
+    }
+
+
+    /// Applies a trade received at `now`, returns the updated position or `None` if the trade
+    /// was already applied.
+    pub fn on_trade(&mut self, trade: &TradeInfo, now: DateTime<Utc>) -> Option<&InstrumentPosition> {
+        let expected_price = self.expected_price(trade);
+        let position = self.positions.entry(trade.sec_code.clone()).or_insert_with(|| InstrumentPosition {
+            class_code: trade.class_code.clone(),
+            sec_code: trade.sec_code.clone(),
+            ..Default::default()
+        });
+        if trade.trade_num <= position.last_trade_num {
+            return None;
+        }
+
+        let quantity = if trade.is_sell { -trade.qty } else { trade.qty };
+        let previous = position.quantity;
+        let point_value = point_value(trade);
+        position.apply(quantity, trade.price, point_value);
+        position.commission += trade.commission();
+        position.slippage += slippage(trade.is_sell, trade.qty, trade.price, expected_price, point_value);
+        position.last_trade_num = trade.trade_num;
+        position.account = trade.account.clone();
+        position.updated_at = Some(now);
+        if position.quantity == 0 {
+            position.opened_at = None;
+        } else if previous.signum() != position.quantity.signum() {
+            position.opened_at = position.updated_at;
+        }
+
+        Some(position)
+    }
+
+
+    /// Price expected for the trade by the sender of its order, see `expect_price`.
+    pub fn expected_price(&self, trade: &TradeInfo) -> Option<f64> {
+        self.expected_prices
+            .get(&trade.sec_code)
+            .filter(|(is_sell, _)| *is_sell == trade.is_sell)
+            .map(|(_, price)| *price)
+    }
+
+
+    /// Sets the price expected by the sender of the orders of the instrument, e.g. the touch
+    /// when the orders were planned: the fills in the direction of the orders add their
+    /// slippage against it, until the next orders of the instrument.
+    pub fn expect_price(&mut self, sec_code: &str, is_sell: bool, price: f64) {
+        self.expected_prices.insert(sec_code.to_string(), (is_sell, price));
+    }
+
+
+    /// Updates the last price of an open position, for the unrealized profit.
+    pub fn on_price(&mut self, sec_code: &str, price: f64) {
+        if let Some(position) = self.positions.get_mut(sec_code) {
+            position.last_price = price;
+        }
+    }
+
+
+    pub fn position(&self, sec_code: &str) -> Option<&InstrumentPosition> {
+        self.positions.get(sec_code)
+    }
+
+
+    /// Position of the instrument in lots, 0 if there is none.
+    pub fn lots(&self, sec_code: &str) -> i64 {
+        self.positions.get(sec_code).map_or(0, |position| position.quantity)
+    }
+
+
+    /// Open positions sorted by security code.
+    pub fn open_positions(&self) -> Vec<InstrumentPosition> {
+        let mut positions: Vec<InstrumentPosition> = self
+            .positions
+            .values()
+            .filter(|position| position.quantity != 0)
+            .cloned()
+            .collect();
+        positions.sort_by(|a, b| a.sec_code.cmp(&b.sec_code));
+        positions
+    }
+
+
+    /// The instrument is already positioned in the direction of the action, e.g. a buy signal
+    /// with a long position, so the signal can be skipped.
+    pub fn is_positioned(&self, sec_code: &str, action: Action) -> bool {
+        let lots = self.lots(sec_code);
+        match action {
+            Action::Buy => lots > 0,
+            Action::Sell => lots < 0,
+            _ => false,
+        }
+    }
+}
+
+
+/// Applies the trades of the terminal to the portfolio and writes the updated positions
+/// to the `positions` table until the channel is closed.
+pub async fn run(db: Arc<Db>, portfolio: Arc<RwLock<Portfolio>>, mut events: mpsc::UnboundedReceiver<QuikEvent>, clock: Arc<dyn Clock>) {
+    while let Some(event) = events.recv().await {
+        let QuikEvent::TradeUpdate(trade) = event else {
+            continue;
+        };
+        // The end of the initial snapshot
+        if trade.mode == 2 {
+            continue;
+        }
+
+        let (position, expected_price) = {
+            let mut portfolio = portfolio.write().unwrap_or_else(|e| e.into_inner());
+            let expected_price = portfolio.expected_price(&trade);
+            (portfolio.on_trade(&trade, clock.now()).cloned(), expected_price)
+        };
+        let Some(position) = position else {
+            continue;
+        };
+        info!(
+            "Position {}: {} lots at {}, realized {:.2}, commission {:.2}",
+            position.sec_code, position.quantity, position.average_price, position.realized_pnl, position.commission
+        );
+
+        if let Err(e) = db.upsert_position(&PositionRecord::from(&position)).await {
+            error!("Error saving the position {}: {}", position.sec_code, e);
+        }
+        if let Some(fill) = fill(&trade, expected_price, clock.now()) {
+            if let Err(e) = db.insert_fill(&fill).await {
+                error!("Error saving the fill {} of {}: {}", fill.trade_num, fill.strategy, e);
+            }
+        }
+    }
+
+    warn!("Trade events are closed, the portfolio is not updated anymore");
+}
+
+
+/// Fill of a strategy for the tear sheet, `None` for a trade without the strategy in the broker
+/// reference (orders sent from the terminal by hand).
+fn fill(trade: &TradeInfo, expected_price: Option<f64>, now: DateTime<Utc>) -> Option<Fill> {
+    let strategy = trade.broker_ref.rsplit('/').next().unwrap_or_default().trim();
+    if strategy.is_empty() {
+        return None;
+    }
+
+    Some(Fill {
+        strategy: strategy.to_string(),
+        instrument_code: trade.sec_code.clone(),
+        trade_num: trade.trade_num as i64,
+        is_sell: trade.is_sell,
+        quantity: trade.qty,
+        price: trade.price,
+        commission: trade.commission(),
+        expected_price,
+        executed_at: domain::exchange_time(trade.date, trade.time).unwrap_or(now),
+        point_value: point_value(trade),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+
+
+    fn trade(trade_num: u64, qty: i64, is_sell: bool) -> TradeInfo {
+        TradeInfo {
+            trade_num,
+            class_code: "QJSIM".to_string(),
+            sec_code: "SBER".to_string(),
+            price: 250.0,
+            qty,
+            is_sell,
+            ..Default::default()
+        }
+    }
+
+
+    #[test]
+    fn position_is_stamped_with_the_time_of_the_clock() {
+        let clock = TestClock::new("2026-06-03T07:00:00Z".parse().unwrap());
+        let mut portfolio = Portfolio::default();
+
+        let opened = portfolio.on_trade(&trade(1, 2, false), clock.now()).cloned().unwrap();
+        assert_eq!(opened.updated_at, Some(clock.now()));
+        assert_eq!(opened.opened_at, Some(clock.now()));
+
+        clock.advance(chrono::Duration::minutes(15));
+        let added = portfolio.on_trade(&trade(2, 1, false), clock.now()).cloned().unwrap();
+        assert_eq!(added.updated_at, Some(clock.now()));
+        assert_eq!(added.opened_at, opened.opened_at);
+
+        // A trade already applied doesn't move the time
+        assert!(portfolio.on_trade(&trade(2, 1, false), clock.now()).is_none());
+    }
+
+
+    #[test]
+    fn fills_add_their_slippage_against_the_expected_price() {
+        let now = "2026-06-03T07:00:00Z".parse().unwrap();
+        let mut portfolio = Portfolio::default();
+        portfolio.expect_price("SBER", false, 249.5);
+
+        // A buy at 250.0 is 0.5 worse than expected on each of 2 lots
+        let position = portfolio.on_trade(&trade(1, 2, false), now).cloned().unwrap();
+        assert_eq!(position.slippage, 1.0);
+        assert_eq!(position.money_flows().expected_pnl(), position.realized_pnl + 1.0);
+
+        // A sell isn't in the direction of the expected price
+        let position = portfolio.on_trade(&trade(2, 1, true), now).cloned().unwrap();
+        assert_eq!(position.slippage, 1.0);
+    }
+
+
+    #[test]
+    fn profit_is_in_money_for_a_lot_of_ten_shares() {
+        let now = "2026-06-03T07:00:00Z".parse().unwrap();
+        let mut portfolio = Portfolio::default();
+        // The value of a trade of a lot of 10 shares is 10 times its price per lot
+        let lot = |trade_num, qty, is_sell, price: f64| TradeInfo { price, value: price * qty as f64 * 10.0, ..trade(trade_num, qty, is_sell) };
+
+        let opened = portfolio.on_trade(&lot(1, 2, false, 250.0), now).cloned().unwrap();
+        assert_eq!(opened.point_value, 10.0);
+        portfolio.on_price("SBER", 255.0);
+        assert_eq!(portfolio.position("SBER").unwrap().unrealized_pnl(), 100.0);
+
+        // 2 lots of 10 shares sold 10 higher
+        let closed = portfolio.on_trade(&lot(2, 2, true, 260.0), now).cloned().unwrap();
+        assert_eq!(closed.quantity, 0);
+        assert_eq!(closed.realized_pnl, 200.0);
+        assert_eq!(closed.money_flows().gross_pnl, 200.0);
+        assert_eq!(fill(&TradeInfo { broker_ref: "/ema_cross".to_string(), ..lot(2, 2, true, 260.0) }, None, now).unwrap().point_value, 10.0);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tracing::warn;
+use crate::clock::{Clock, SystemClock};
+use crate::config::InstrumentSettings;
+use crate::notifier::Notifier;
+use crate::portfolio::{InstrumentPosition, Portfolio};
+
+
+/// Position open longer than the `max_position_age_hours` of its instrument.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgedPosition {
+    pub sec_code: String,
+    /// Position in lots, positive for long, negative for short.
+    pub lots: i64,
+    pub opened_at: DateTime<Utc>,
+    pub age: Duration,
+    pub max_age: Duration,
+}
+
+
+impl fmt::Display for AgedPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: position of {} lots is open for {:.1} h since {}, longer than {:.1} h without reaching \
+             its target or stop, review it manually",
+            self.sec_code,
+            self.lots,
+            self.age.as_secs_f64() / 3600.0,
+            self.opened_at.format("%Y-%m-%d %H:%M UTC"),
+            self.max_age.as_secs_f64() / 3600.0,
+        )
+    }
+}
+
+
+/// Guardrail for the positions held by the bot: a position still open after the
+/// `max_position_age_hours` of its instrument has reached neither its target nor its stop
+/// and is reported once for a manual review. A position reopened later is reported again.
+///
+/// The positions restored without the opening time are aged from their last trade.
+///
+/// # Example of use
+/// ```ignore
+/// let mut monitor = PositionAgeMonitor::new(&config.instrument_settings()?);
+/// for position in monitor.check(&portfolio.open_positions()) {
+///     warn!("{}", position);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PositionAgeMonitor {
+    limits: HashMap<String, Duration>,
+    /// Opening time of the positions already reported.
+    reported: HashMap<String, DateTime<Utc>>,
+    clock: Arc<dyn Clock>,
+}
+
+
+impl PositionAgeMonitor {
+    pub fn new(settings: &[InstrumentSettings]) -> Self {
+        PositionAgeMonitor {
+            limits: settings
+                .iter()
+                .filter_map(|settings| settings.max_position_age().map(|age| (settings.sec_code.clone(), age)))
+                .collect(),
+            reported: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+
+    /// The alert is configured for at least one instrument.
+    pub fn is_enabled(&self) -> bool {
+        !self.limits.is_empty()
+    }
+
+
+    /// Open positions over their age limit which are not reported yet.
+    pub fn check(&mut self, positions: &[InstrumentPosition]) -> Vec<AgedPosition> {
+        let now = self.clock.now();
+        self.reported.retain(|sec_code, _| positions.iter().any(|position| &position.sec_code == sec_code));
+
+        let mut aged = Vec::new();
+        for position in positions.iter().filter(|position| position.quantity != 0) {
+            let Some(max_age) = self.limits.get(&position.sec_code).copied() else {
+                continue;
+            };
+            let Some(opened_at) = position.opened_at.or(position.updated_at) else {
+                continue;
+            };
+            if self.reported.get(&position.sec_code) == Some(&opened_at) {
+                continue;
+            }
+            let age = (now - opened_at).to_std().unwrap_or_default();
+            if age < max_age {
+                continue;
+            }
+
+            self.reported.insert(position.sec_code.clone(), opened_at);
+            aged.push(AgedPosition {
+                sec_code: position.sec_code.clone(),
+                lots: position.quantity,
+                opened_at,
+                age,
+                max_age,
+            });
+        }
+
+        aged
+    }
+}
+
+
+/// Position age task: every `period` checks the open positions of the portfolio and sends
+/// the positions over their age limit to the notification channels of the instrument.
+///
+/// # Example of use
+/// ```ignore
+/// let monitor = PositionAgeMonitor::new(&settings);
+/// tokio::spawn(position_age::run(portfolio.clone(), monitor, notifier.clone(), settings, Duration::from_secs(60)));
+/// ```
+pub async fn run(
+    portfolio: Arc<RwLock<Portfolio>>,
+    mut monitor: PositionAgeMonitor,
+    notifier: Notifier,
+    settings: Vec<InstrumentSettings>,
+    period: Duration,
+) {
+    if !monitor.is_enabled() {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let positions = portfolio.read().unwrap_or_else(|e| e.into_inner()).open_positions();
+        for position in monitor.check(&positions) {
+            warn!("{}", position);
+            let channels = settings
+                .iter()
+                .find(|settings| settings.sec_code == position.sec_code)
+                .map(|settings| settings.notification_channels.as_slice())
+                .unwrap_or_default();
+            notifier.notify(None, channels, &position.to_string()).await;
+        }
+    }
+}
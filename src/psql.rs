@@ -1,22 +1,29 @@
-use chrono::{DateTime, Utc};
-use std::str::FromStr;
-use std::convert::TryInto;
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
 
-
-use tokio;
-use tracing::{error, info};
+use tracing::{debug, error};
+use libc::{c_long, c_ulong};
+use quik_rs::transaction::Side;
+use crate::domain::{self, Order, OrderStatus};
+use crate::orderbook::{Level, OrderBook};
+use crate::retry::RetryPolicy;
 use bb8::RunError;
 use bb8_postgres::{
     bb8::Pool,
     PostgresConnectionManager,
-    tokio_postgres::{NoTls, Error},
+    tokio_postgres::NoTls,
+    tokio_postgres::types::ToSql,
 };
 
 
-#[derive(Debug)]
+/// Версия схемы базы данных, увеличивается при каждом изменении таблиц
+pub const SCHEMA_VERSION: i32 = 2;
+
+
+#[derive(Debug, Clone)]
 pub struct DataForEma {
+    pub period_start: DateTime<Utc>,
     pub open: f64,
     pub high: f64,
     pub low: f64,
@@ -25,6 +32,341 @@ pub struct DataForEma {
 }
 
 
+/// Важность инцидента
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "critical" => Severity::Critical,
+            "warning" => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+}
+
+
+/// Вид инцидента
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncidentKind {
+    Disconnect,
+    Rejection,
+    RiskBreach,
+    ReconciliationMismatch,
+    InstrumentChange,
+    StaleOrder,
+    TaskRestart,
+}
+
+
+impl IncidentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IncidentKind::Disconnect => "disconnect",
+            IncidentKind::Rejection => "rejection",
+            IncidentKind::RiskBreach => "risk_breach",
+            IncidentKind::ReconciliationMismatch => "reconciliation_mismatch",
+            IncidentKind::InstrumentChange => "instrument_change",
+            IncidentKind::StaleOrder => "stale_order",
+            IncidentKind::TaskRestart => "task_restart",
+        }
+    }
+}
+
+
+/// Параметры инструмента из таблицы current_trades
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentRow {
+    pub class_code: String,
+    pub instrument_code: String,
+    pub lot: Option<i32>,
+    pub lot_multiplier: Option<i32>,
+    pub price_step: Option<f64>,
+    pub price_decimals: Option<i32>,
+    pub session_status: Option<String>,
+    pub instrument_status: Option<String>,
+    /// Дата погашения (MAT_DATE), `None` для инструментов без срока обращения
+    pub expiry: Option<NaiveDate>,
+    /// Максимально возможная цена (PRICEMAX)
+    pub price_max: Option<f64>,
+    /// Минимально возможная цена (PRICEMIN)
+    pub price_min: Option<f64>,
+}
+
+
+/// Запись таблицы incidents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incident {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub severity: Severity,
+    pub kind: String,
+    pub sec_code: Option<String>,
+    pub message: String,
+    pub resolved: bool,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolution: Option<String>,
+}
+
+
+/// Запись таблицы heartbeats, пишется раз в минуту
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub recorded_at: DateTime<Utc>,
+    /// Состояние соединения, например "connected" или "disconnected"
+    pub state: String,
+    pub instruments_active: i32,
+    pub loop_latency_ms: f64,
+}
+
+
+/// Запись таблицы evaluations: значения индикаторов, решение и код причины
+/// для одной оценки инструмента на свече
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub instrument_code: String,
+    pub candle_time: DateTime<Utc>,
+    pub indicators: Vec<(String, f64)>,
+    pub decision: String,
+    pub reason_code: String,
+}
+
+
+/// Запись журнала изменений параметров стратегий: запуск, завершение, продвижение или отклонение.
+#[derive(Debug, Clone)]
+pub struct ParameterChange {
+    pub strategy: String,
+    pub parameter: String,
+    pub live_value: f64,
+    pub candidate_value: f64,
+    pub event: String,
+    pub details: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+
+/// Сделка бота, записанная по событию TradeUpdate, с именем стратегии, выставившей заявку
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub strategy: String,
+    pub instrument_code: String,
+    pub trade_num: i64,
+    pub is_sell: bool,
+    pub quantity: i64,
+    pub price: f64,
+    pub commission: f64,
+    /// Ожидаемая цена исполнения (цена сигнала), по ней оценивается проскальзывание
+    pub expected_price: Option<f64>,
+    pub executed_at: DateTime<Utc>,
+    /// Стоимость изменения цены на единицу для единицы `quantity` в деньгах, например размер лота
+    /// акции или стоимость шага цены фьючерса, деленная на шаг
+    pub point_value: f64,
+}
+
+
+/// Запись журнала обратных вызовов QUIK: событие с сырыми полями в JSON и временем получения
+#[derive(Debug, Clone)]
+pub struct QuikEventRecord {
+    pub received_at: DateTime<Utc>,
+    /// connection, reply, order или trade
+    pub kind: String,
+    pub trans_id: Option<i64>,
+    pub order_num: Option<i64>,
+    pub sec_code: Option<String>,
+    pub payload: String,
+}
+
+
+/// Денежный лимит клиента из таблицы money_limits, выгружаемой из QUIK
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoneyLimit {
+    pub firm_id: String,
+    pub client_code: String,
+    pub currency: String,
+    /// Срок расчетов: 0 - T0, 1 - T1, 2 - T2
+    pub limit_kind: i32,
+    pub open_balance: f64,
+    pub current_balance: f64,
+    /// Средства, заблокированные под активные заявки
+    pub locked: f64,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+
+impl MoneyLimit {
+    /// Средства, доступные для новых заявок
+    pub fn available(&self) -> f64 {
+        self.current_balance - self.locked
+    }
+}
+
+
+/// Позиция по бумаге из таблицы depo_limits, выгружаемой из QUIK. Количество в штуках
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepoPosition {
+    pub firm_id: String,
+    pub client_code: String,
+    pub sec_code: String,
+    pub limit_kind: i32,
+    pub open_balance: i64,
+    pub current_balance: i64,
+    /// Бумаги, заблокированные под заявки на продажу
+    pub locked_sell: i64,
+    pub average_price: Option<f64>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+
+impl DepoPosition {
+    /// Бумаги, доступные для продажи
+    pub fn available(&self) -> i64 {
+        self.current_balance - self.locked_sell
+    }
+}
+
+
+/// Гарантийное обеспечение инструмента на один лот из таблицы current_trades
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginRequirement {
+    pub instrument_code: String,
+    pub buy_deposit: Option<f64>,
+    pub sell_deposit: Option<f64>,
+}
+
+
+/// Состояние сигналов стратегии по инструменту, восстанавливаемое после перезапуска
+#[derive(Debug, Clone)]
+pub struct BotStateRecord {
+    pub strategy: String,
+    pub sec_code: String,
+    /// Состояние в JSON, формат определяет стратегия
+    pub state: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+
+/// Итоги торговой сессии по инструменту, пустой код инструмента — итог по всем инструментам
+#[derive(Debug, Clone)]
+pub struct DailyReportRecord {
+    pub report_date: NaiveDate,
+    pub instrument_code: String,
+    pub realized_pnl: f64,
+    pub commission: f64,
+    pub net_pnl: f64,
+    pub trades: i64,
+    pub closed_trades: i64,
+    /// Доля прибыльных закрытых сделок в процентах, нет без закрытых сделок
+    pub hit_rate: Option<f64>,
+    pub max_drawdown: f64,
+}
+
+
+/// Позиция инструмента, восстанавливаемая после перезапуска
+#[derive(Debug, Clone)]
+pub struct PositionRecord {
+    pub class_code: String,
+    pub sec_code: String,
+    /// Торговый счет сделок позиции
+    pub account: String,
+    /// Лоты, положительные для длинной позиции и отрицательные для короткой
+    pub quantity: i64,
+    pub average_price: f64,
+    /// Реализованная прибыль до вычета комиссий
+    pub realized_pnl: f64,
+    pub commission: f64,
+    /// Оценка потерь на проскальзывании, уже учтенных в ценах сделок
+    pub slippage: f64,
+    pub last_price: f64,
+    /// Номер последней учтенной сделки
+    pub last_trade_num: i64,
+    pub updated_at: DateTime<Utc>,
+    /// Время открытия позиции, `None` для закрытой позиции
+    pub opened_at: Option<DateTime<Utc>>,
+    /// Стоимость изменения цены на единицу для одного лота в деньгах
+    pub point_value: f64,
+}
+
+
+/// Результат прогона бэктеста стратегии
+#[derive(Debug, Clone)]
+pub struct BacktestRecord {
+    pub strategy: String,
+    /// Параметры стратегии по инструментам в JSON
+    pub parameters: String,
+    pub period_from: DateTime<Utc>,
+    pub period_to: DateTime<Utc>,
+    pub capital: f64,
+    pub fills: i64,
+    pub closed_trades: i64,
+    pub net_pnl: f64,
+    pub max_drawdown: f64,
+    pub sharpe: Option<f64>,
+    /// Доля прибыльных сделок в процентах
+    pub win_rate: Option<f64>,
+    pub created_at: DateTime<Utc>,
+}
+
+
+/// Защитный выход из позиции по стоп-лоссу или тейк-профиту
+#[derive(Debug, Clone)]
+pub struct ExitRecord {
+    pub strategy: String,
+    pub sec_code: String,
+    /// stop_loss или take_profit
+    pub reason: String,
+    /// Лоты закрываемой позиции, положительные для длинной и отрицательные для короткой
+    pub quantity: i64,
+    pub entry_price: f64,
+    /// Уровень стопа или цели, достигнутый ценой
+    pub trigger_price: f64,
+    pub last_price: f64,
+    pub trans_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+
+/// Агрегат вызовов функции Trans2QUIK.dll за период
+#[derive(Debug, Clone)]
+pub struct CallStatsRecord {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub function: String,
+    pub calls: i64,
+    pub failures: i64,
+    /// Количество вызовов по кодам результата в JSON, например {"Success": 10}
+    pub results: String,
+    pub avg_latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+
+/// Тик, отброшенный или помеченный фильтром аномальных цен
+#[derive(Debug, Clone)]
+pub struct AnomalousTick {
+    pub instrument_code: String,
+    pub price: f64,
+    pub previous_price: Option<f64>,
+    pub action: String,
+    pub reason: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+
 pub struct Db {
     pool: Pool<PostgresConnectionManager<NoTls>>,
 }
@@ -55,6 +397,36 @@ impl Db {
     }
 
 
+    // Пул без сервера для тестов: соединение к закрытому порту, запросы быстро завершаются ошибкой
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        let manager = PostgresConnectionManager::new_from_stringlike("host=127.0.0.1 port=1 user=test connect_timeout=1", NoTls)
+            .expect("valid connection string");
+        let pool = Pool::builder()
+            .max_size(1)
+            .connection_timeout(std::time::Duration::from_millis(100))
+            .build_unchecked(manager);
+
+        Db { pool }
+    }
+
+
+    // Инициализация пула с повторными попытками по политике, например пока сервер БД запускается
+    pub async fn connect(connection_str: &str, retry: &RetryPolicy) -> Result<Self, RunError<bb8_postgres::tokio_postgres::Error>> {
+        retry.run("db_connect", || Db::new(connection_str), Db::is_retryable).await
+    }
+
+
+    // Ошибку стоит повторить, если это сбой сети или тайм-аут, а не отказ сервера с кодом SQLSTATE,
+    // например неверный пароль или несуществующая база
+    pub fn is_retryable(e: &RunError<bb8_postgres::tokio_postgres::Error>) -> bool {
+        match e {
+            RunError::User(e) => e.code().is_none(),
+            RunError::TimedOut => true,
+        }
+    }
+
+
     // Создание таблицы текущих торгов
     pub async fn create_current_trades(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
         // Получаем соединение из пула
@@ -88,6 +460,71 @@ impl Db {
             e
         })?;
 
+        // Шаг цены добавлен в существующие таблицы после их создания
+        conn.execute("ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS price_step DECIMAL(15,6);", &[])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса добавления столбца price_step: {:?}", e);
+                e
+            })?;
+
+        // Точность цены (SEC_SCALE) нужна для записи цены в транзакции
+        conn.execute("ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS price_decimals INTEGER;", &[])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса добавления столбца price_decimals: {:?}", e);
+                e
+            })?;
+
+        // Гарантийное обеспечение покупателя и продавца (BUYDEPO, SELLDEPO) для срочного рынка
+        conn.batch_execute(
+            "
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS buy_deposit DECIMAL(15,6);
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS sell_deposit DECIMAL(15,6);
+            ",
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса добавления столбцов гарантийного обеспечения: {:?}", e);
+            e
+        })?;
+
+        // Дата погашения (MAT_DATE) фьючерсов и облигаций
+        conn.execute("ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS mat_date DATE;", &[])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса добавления столбца mat_date: {:?}", e);
+                e
+            })?;
+
+        // Лучшие котировки (BID, OFFER) и их объемы (BIDDEPTH, OFFERDEPTH) для цены лимитных заявок
+        conn.batch_execute(
+            "
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS bid DECIMAL(15,6);
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS offer DECIMAL(15,6);
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS bid_depth DECIMAL(15,6);
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS offer_depth DECIMAL(15,6);
+            ",
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса добавления столбцов котировок: {:?}", e);
+            e
+        })?;
+
+        // Лимиты цены (PRICEMAX, PRICEMIN) для закрывающих лимитных заявок по рынку
+        conn.batch_execute(
+            "
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS price_max DECIMAL(15,6);
+            ALTER TABLE current_trades ADD COLUMN IF NOT EXISTS price_min DECIMAL(15,6);
+            ",
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса добавления столбцов лимитов цены: {:?}", e);
+            e
+        })?;
+
         Ok(())
     }
 
@@ -222,125 +659,2586 @@ impl Db {
         Ok(())
     }
 
-    // Инициализация базы данных
-    pub async fn init(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
-        self.create_current_trades().await?;
-        self.create_historical_trades().await?;
-        self.insert_into_historical().await?;
-        self.before_update_current_trades().await?;
-        
+    // Создание таблицы инцидентов
+    pub async fn create_incidents(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу
+        let query = "
+            CREATE TABLE IF NOT EXISTS incidents (
+                id SERIAL PRIMARY KEY,
+                created_at TIMESTAMPTZ DEFAULT NOW(),
+                severity VARCHAR(16) NOT NULL,
+                kind VARCHAR(32) NOT NULL,
+                sec_code VARCHAR(12),
+                message TEXT NOT NULL,
+                resolved BOOLEAN NOT NULL DEFAULT FALSE,
+                resolved_at TIMESTAMPTZ,
+                resolution TEXT
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы incidents: {:?}", e);
+            e
+        })?;
+
         Ok(())
     }
 
 
-    // Получение данных торгов для расчета EMA
-    pub async fn get_data_for_ema(&self, instrument_code: &str, lookback_interval_seconds: f64, period_length_seconds: f64) -> Result<Vec<DataForEma>, RunError<bb8_postgres::tokio_postgres::Error>> {
+    // Создание таблицы значений пользовательских индикаторов
+    pub async fn create_indicator_values(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
         // Получаем соединение из пула
         let conn = self.pool.get().await.map_err(|e| {
             error!("Ошибка получения соединения из пула: {:?}", e);
             e
         })?;
 
-        // SQL-запрос с использованием приведения типов
+        // Создаем таблицу
         let query = "
-            WITH period_data AS (
-                SELECT
-                    ht.*,
-                    NOW() - FLOOR(
-                        EXTRACT(EPOCH FROM NOW() - ht.update_timestamptz) / $1::double precision
-                    ) * $1::double precision * INTERVAL '1 second' AS period_start
-                FROM
-                    historical_trades ht
-                WHERE
-                    ht.instrument_code = $3
-                    AND ht.update_timestamptz >= NOW() - $2::double precision * INTERVAL '1 second'
-            )
-            SELECT
-                period_start,
-                (ARRAY_AGG(last_price ORDER BY update_timestamptz ASC))[1] AS open_price,
-                (ARRAY_AGG(last_price ORDER BY update_timestamptz DESC))[1] AS close_price,
-                MIN(last_price) AS min_price,
-                MAX(last_price) AS max_price,
-                SUM(last_volume) AS period_volume
-            FROM
-                period_data
-            GROUP BY
-                period_start
-            ORDER BY
-                period_start;
+            CREATE TABLE IF NOT EXISTS indicator_values (
+                id SERIAL PRIMARY KEY,
+                instrument_code VARCHAR(12) NOT NULL,
+                indicator VARCHAR(64) NOT NULL,
+                candle_time TIMESTAMPTZ NOT NULL,
+                value DOUBLE PRECISION NOT NULL,
+                UNIQUE (instrument_code, indicator, candle_time)
+            );
         ";
 
-        // Выполняем запрос с параметрами
-        let rows = conn
-            .query(query, &[&period_length_seconds, &lookback_interval_seconds, &instrument_code])
-            .await.map_err(|e| {
-                error!("Ошибка выполнения запроса получения данных для расчета EMA: {:?}", e);
-                e
-            })?;
-        
-
-        // Печатаем названия столбцов
-        println!(
-            "{:<30} | {:>15} | {:>15} | {:>15} | {:>15} | {:>15}",
-            "period_start", "open_price", "close_price", "min_price", "max_price", "period_volume"
-        );
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы indicator_values: {:?}", e);
+            e
+        })?;
 
-        // Печатаем разделительную линию
-        println!("{:-<30}-+-{:-<15}-+-{:-<15}-+-{:-<15}-+-{:-<15}-+-{:-<15}-", "", "", "", "", "", "");
+        Ok(())
+    }
 
-        // Создаем вектор для DataItem
-        let mut data_item: Vec<DataForEma> = Vec::new();
 
-        // Обрабатываем результаты
-        for row in rows {
-            let period_start: DateTime<Utc> = row.get("period_start");
+    // Инициализация базы данных
+    pub async fn create_heartbeats(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
 
-            let open_price: f64 = row.try_get::<_, Decimal>("open_price")
-                .ok()
-                .and_then(|dec| dec.to_f64())
-                .unwrap_or_default();
+        // Создаем таблицу
+        let query = "
+            CREATE TABLE IF NOT EXISTS heartbeats (
+                recorded_at TIMESTAMPTZ PRIMARY KEY DEFAULT NOW(),
+                state VARCHAR(16) NOT NULL,
+                instruments_active INTEGER NOT NULL,
+                loop_latency_ms DOUBLE PRECISION NOT NULL
+            );
+        ";
 
-            let close_price: f64 = row.try_get::<_, Decimal>("close_price")
-                .ok()
-                .and_then(|dec| dec.to_f64())
-                .unwrap_or_default();
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы heartbeats: {:?}", e);
+            e
+        })?;
 
-            let min_price: f64 = row.try_get::<_, Decimal>("min_price")
-                .ok()
-                .and_then(|dec| dec.to_f64())
-                .unwrap_or_default();
+        Ok(())
+    }
 
-            let max_price: f64 = row.try_get::<_, Decimal>("max_price")
-                .ok()
-                .and_then(|dec| dec.to_f64())
-                .unwrap_or_default();
 
-            let period_volume: f64 = row.try_get::<_, Decimal>("period_volume")
-                .ok()
-                .and_then(|dec| dec.to_f64())
-                .unwrap_or_default();
+    pub async fn create_evaluations(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
 
-            println!(
-                "{:<30} | {:>15.6} | {:>15.6} | {:>15.6} | {:>15.6} | {:>15.6}",
-                period_start,
-                open_price,
-                close_price,
-                min_price,
-                max_price,
-                period_volume
+        // Создаем таблицу, имена и значения индикаторов хранятся в двух массивах
+        let query = "
+            CREATE TABLE IF NOT EXISTS evaluations (
+                instrument_code VARCHAR(12) NOT NULL,
+                candle_time TIMESTAMPTZ NOT NULL,
+                indicator_names TEXT[] NOT NULL,
+                indicator_values DOUBLE PRECISION[] NOT NULL,
+                decision VARCHAR(16) NOT NULL,
+                reason_code VARCHAR(32) NOT NULL,
+                recorded_at TIMESTAMPTZ DEFAULT NOW(),
+                PRIMARY KEY (instrument_code, candle_time)
             );
+        ";
 
-            let item = DataForEma {
-                open: open_price,
-                high: max_price,
-                low: min_price,
-                close: close_price,
-                volume: period_volume,
-            };
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы evaluations: {:?}", e);
+            e
+        })?;
 
-            data_item.push(item);
-        }
-    
-        Ok(data_item)
+        Ok(())
     }
-}
\ No newline at end of file
+
+
+    pub async fn create_schema_version(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу
+        let query = "
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                applied_at TIMESTAMPTZ DEFAULT NOW()
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы schema_version: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_parameter_changes(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу
+        let query = "
+            CREATE TABLE IF NOT EXISTS parameter_changes (
+                id SERIAL PRIMARY KEY,
+                strategy VARCHAR(64) NOT NULL,
+                parameter VARCHAR(64) NOT NULL,
+                live_value DOUBLE PRECISION NOT NULL,
+                candidate_value DOUBLE PRECISION NOT NULL,
+                event VARCHAR(16) NOT NULL,
+                details TEXT NOT NULL,
+                recorded_at TIMESTAMPTZ DEFAULT NOW()
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы parameter_changes: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_anomalous_ticks(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу
+        let query = "
+            CREATE TABLE IF NOT EXISTS anomalous_ticks (
+                id SERIAL PRIMARY KEY,
+                instrument_code VARCHAR(12) NOT NULL,
+                price DOUBLE PRECISION NOT NULL,
+                previous_price DOUBLE PRECISION,
+                action VARCHAR(8) NOT NULL,
+                reason TEXT NOT NULL,
+                recorded_at TIMESTAMPTZ DEFAULT NOW()
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы anomalous_ticks: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_fills(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу, номер сделки биржи уникален, повторная запись сделки из снимка игнорируется
+        let query = "
+            CREATE TABLE IF NOT EXISTS fills (
+                trade_num BIGINT PRIMARY KEY,
+                strategy VARCHAR(32) NOT NULL,
+                instrument_code VARCHAR(12) NOT NULL,
+                is_sell BOOLEAN NOT NULL,
+                quantity BIGINT NOT NULL,
+                price DOUBLE PRECISION NOT NULL,
+                commission DOUBLE PRECISION NOT NULL DEFAULT 0,
+                executed_at TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS fills_strategy_executed_at ON fills (strategy, executed_at);
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.batch_execute(query).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы fills: {:?}", e);
+            e
+        })?;
+
+        // Ожидаемая цена добавлена для оценки проскальзывания
+        conn.execute("ALTER TABLE fills ADD COLUMN IF NOT EXISTS expected_price DOUBLE PRECISION;", &[])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса добавления столбца expected_price: {:?}", e);
+                e
+            })?;
+
+        // Стоимость пункта цены добавлена для прибыли в деньгах, старые сделки считаются по 1
+        conn.execute("ALTER TABLE fills ADD COLUMN IF NOT EXISTS point_value DOUBLE PRECISION NOT NULL DEFAULT 1;", &[])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса добавления столбца point_value: {:?}", e);
+                e
+            })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_quik_events(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу, индексы нужны для поиска жизненного цикла заявки
+        let query = "
+            CREATE TABLE IF NOT EXISTS quik_events (
+                id BIGSERIAL PRIMARY KEY,
+                received_at TIMESTAMPTZ NOT NULL,
+                kind VARCHAR(16) NOT NULL,
+                trans_id BIGINT,
+                order_num BIGINT,
+                sec_code VARCHAR(12),
+                payload JSONB NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS quik_events_trans_id ON quik_events (trans_id);
+            CREATE INDEX IF NOT EXISTS quik_events_order_num ON quik_events (order_num);
+            CREATE INDEX IF NOT EXISTS quik_events_received_at ON quik_events (received_at);
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.batch_execute(query).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы quik_events: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_call_stats(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу агрегатов вызовов Trans2QUIK.dll
+        let query = "
+            CREATE TABLE IF NOT EXISTS trans2quik_call_stats (
+                id BIGSERIAL PRIMARY KEY,
+                period_start TIMESTAMPTZ NOT NULL,
+                period_end TIMESTAMPTZ NOT NULL,
+                function VARCHAR(64) NOT NULL,
+                calls BIGINT NOT NULL,
+                failures BIGINT NOT NULL,
+                results JSONB NOT NULL,
+                avg_latency_ms DOUBLE PRECISION NOT NULL,
+                max_latency_ms DOUBLE PRECISION NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS trans2quik_call_stats_period_end ON trans2quik_call_stats (period_end);
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.batch_execute(query).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы trans2quik_call_stats: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_positions(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу позиций, одна строка на инструмент
+        let query = "
+            CREATE TABLE IF NOT EXISTS positions (
+                sec_code VARCHAR(12) PRIMARY KEY,
+                class_code VARCHAR(12) NOT NULL,
+                quantity BIGINT NOT NULL,
+                average_price DOUBLE PRECISION NOT NULL,
+                realized_pnl DOUBLE PRECISION NOT NULL,
+                last_price DOUBLE PRECISION NOT NULL,
+                last_trade_num BIGINT NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы positions: {:?}", e);
+            e
+        })?;
+
+        // Комиссии и проскальзывание учитываются отдельно от реализованной прибыли
+        conn.batch_execute(
+            "
+            ALTER TABLE positions ADD COLUMN IF NOT EXISTS commission DOUBLE PRECISION NOT NULL DEFAULT 0;
+            ALTER TABLE positions ADD COLUMN IF NOT EXISTS slippage DOUBLE PRECISION NOT NULL DEFAULT 0;
+            ALTER TABLE positions ADD COLUMN IF NOT EXISTS opened_at TIMESTAMPTZ;
+            ALTER TABLE positions ADD COLUMN IF NOT EXISTS point_value DOUBLE PRECISION NOT NULL DEFAULT 1;
+            ",
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса добавления столбцов commission, slippage, opened_at и point_value: {:?}", e);
+            e
+        })?;
+
+        // Торговый счет позиции при нескольких счетах
+        conn.execute("ALTER TABLE positions ADD COLUMN IF NOT EXISTS account VARCHAR(32) NOT NULL DEFAULT '';", &[])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса добавления столбца account: {:?}", e);
+                e
+            })?;
+
+        // Архив позиций погашенных инструментов
+        let query = "
+            CREATE TABLE IF NOT EXISTS positions_archive (
+                id SERIAL PRIMARY KEY,
+                sec_code VARCHAR(12) NOT NULL,
+                class_code VARCHAR(12) NOT NULL,
+                quantity BIGINT NOT NULL,
+                average_price DOUBLE PRECISION NOT NULL,
+                realized_pnl DOUBLE PRECISION NOT NULL,
+                commission DOUBLE PRECISION NOT NULL,
+                slippage DOUBLE PRECISION NOT NULL,
+                last_price DOUBLE PRECISION NOT NULL,
+                last_trade_num BIGINT NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL,
+                expiry DATE,
+                archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы positions_archive: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_exits(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу выходов с причиной закрытия позиции
+        let query = "
+            CREATE TABLE IF NOT EXISTS exits (
+                id BIGSERIAL PRIMARY KEY,
+                strategy VARCHAR(32) NOT NULL,
+                sec_code VARCHAR(12) NOT NULL,
+                reason VARCHAR(16) NOT NULL,
+                quantity BIGINT NOT NULL,
+                entry_price DOUBLE PRECISION NOT NULL,
+                trigger_price DOUBLE PRECISION NOT NULL,
+                last_price DOUBLE PRECISION NOT NULL,
+                trans_id BIGINT,
+                created_at TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS exits_created_at ON exits (created_at);
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.batch_execute(query).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы exits: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn create_backtests(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу результатов бэктестов
+        let query = "
+            CREATE TABLE IF NOT EXISTS backtests (
+                id SERIAL PRIMARY KEY,
+                strategy VARCHAR(32) NOT NULL,
+                parameters JSONB NOT NULL,
+                period_from TIMESTAMPTZ NOT NULL,
+                period_to TIMESTAMPTZ NOT NULL,
+                capital DOUBLE PRECISION NOT NULL,
+                fills BIGINT NOT NULL,
+                closed_trades BIGINT NOT NULL,
+                net_pnl DOUBLE PRECISION NOT NULL,
+                max_drawdown DOUBLE PRECISION NOT NULL,
+                sharpe DOUBLE PRECISION,
+                win_rate DOUBLE PRECISION,
+                created_at TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS backtests_strategy_created_at ON backtests (strategy, created_at);
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.batch_execute(query).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы backtests: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn init(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        self.create_current_trades().await?;
+        self.create_historical_trades().await?;
+        self.insert_into_historical().await?;
+        self.before_update_current_trades().await?;
+        self.create_incidents().await?;
+        self.create_indicator_values().await?;
+        self.create_heartbeats().await?;
+        self.create_evaluations().await?;
+        self.create_schema_version().await?;
+        self.create_parameter_changes().await?;
+        self.create_anomalous_ticks().await?;
+        self.create_fills().await?;
+        self.create_quik_events().await?;
+        self.create_call_stats().await?;
+        self.create_positions().await?;
+        self.create_exits().await?;
+        self.create_backtests().await?;
+        self.create_account_limits().await?;
+        self.create_bot_state().await?;
+        self.create_daily_candles().await?;
+        self.create_daily_reports().await?;
+        
+        Ok(())
+    }
+
+
+    // Получение данных торгов для расчета EMA
+    pub async fn get_data_for_ema(&self, instrument_code: &str, lookback_interval_seconds: f64, period_length_seconds: f64) -> Result<Vec<DataForEma>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // SQL-запрос с использованием приведения типов
+        let query = "
+            WITH period_data AS (
+                SELECT
+                    ht.*,
+                    NOW() - FLOOR(
+                        EXTRACT(EPOCH FROM NOW() - ht.update_timestamptz) / $1::double precision
+                    ) * $1::double precision * INTERVAL '1 second' AS period_start
+                FROM
+                    historical_trades ht
+                WHERE
+                    ht.instrument_code = $3
+                    AND ht.update_timestamptz >= NOW() - $2::double precision * INTERVAL '1 second'
+            )
+            SELECT
+                period_start,
+                (ARRAY_AGG(last_price ORDER BY update_timestamptz ASC))[1] AS open_price,
+                (ARRAY_AGG(last_price ORDER BY update_timestamptz DESC))[1] AS close_price,
+                MIN(last_price) AS min_price,
+                MAX(last_price) AS max_price,
+                SUM(last_volume) AS period_volume
+            FROM
+                period_data
+            GROUP BY
+                period_start
+            ORDER BY
+                period_start;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn
+            .query(query, &[&period_length_seconds, &lookback_interval_seconds, &instrument_code])
+            .await.map_err(|e| {
+                error!("Ошибка выполнения запроса получения данных для расчета EMA: {:?}", e);
+                e
+            })?;
+
+
+        // Создаем вектор для DataItem
+        let mut data_item: Vec<DataForEma> = Vec::new();
+
+        // Обрабатываем результаты
+        for row in rows {
+            let period_start: DateTime<Utc> = row.get("period_start");
+
+            let open_price: f64 = row.try_get::<_, Decimal>("open_price")
+                .ok()
+                .and_then(|dec| dec.to_f64())
+                .unwrap_or_default();
+
+            let close_price: f64 = row.try_get::<_, Decimal>("close_price")
+                .ok()
+                .and_then(|dec| dec.to_f64())
+                .unwrap_or_default();
+
+            let min_price: f64 = row.try_get::<_, Decimal>("min_price")
+                .ok()
+                .and_then(|dec| dec.to_f64())
+                .unwrap_or_default();
+
+            let max_price: f64 = row.try_get::<_, Decimal>("max_price")
+                .ok()
+                .and_then(|dec| dec.to_f64())
+                .unwrap_or_default();
+
+            let period_volume: f64 = row.try_get::<_, Decimal>("period_volume")
+                .ok()
+                .and_then(|dec| dec.to_f64())
+                .unwrap_or_default();
+
+            let item = DataForEma {
+                period_start,
+                open: open_price,
+                high: max_price,
+                low: min_price,
+                close: close_price,
+                volume: period_volume,
+            };
+
+            data_item.push(item);
+        }
+
+        // Таблицу свечей печатает `quik-rs inspect candles`
+        debug!("{}: {} свечей по {} с", instrument_code, data_item.len(), period_length_seconds);
+    
+        Ok(data_item)
+    }
+
+    // Запись инцидента, возвращает id записи
+    pub async fn insert_incident(
+        &self,
+        severity: Severity,
+        kind: IncidentKind,
+        sec_code: Option<&str>,
+        message: &str,
+    ) -> Result<i32, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO incidents (severity, kind, sec_code, message)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn
+            .query_one(query, &[&severity.as_str(), &kind.as_str(), &sec_code, &message])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса записи инцидента: {:?}", e);
+                e
+            })?;
+
+        Ok(row.get("id"))
+    }
+
+
+    // Отметка инцидента как решенного
+    pub async fn resolve_incident(&self, id: i32, resolution: &str) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            UPDATE incidents
+            SET resolved = TRUE, resolved_at = NOW(), resolution = $2
+            WHERE id = $1;
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(query, &[&id, &resolution]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса решения инцидента {}: {:?}", id, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    // Получение инцидентов начиная с момента since, для просмотра и ежедневных отчетов
+    pub async fn get_incidents(&self, since: DateTime<Utc>, only_unresolved: bool) -> Result<Vec<Incident>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT id, created_at, severity, kind, sec_code, message, resolved, resolved_at, resolution
+            FROM incidents
+            WHERE created_at >= $1
+                AND (NOT $2 OR NOT resolved)
+            ORDER BY created_at;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&since, &only_unresolved]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения инцидентов: {:?}", e);
+            e
+        })?;
+
+        let incidents = rows
+            .iter()
+            .map(|row| Incident {
+                id: row.get("id"),
+                created_at: row.get("created_at"),
+                severity: Severity::parse(row.get("severity")),
+                kind: row.get("kind"),
+                sec_code: row.get("sec_code"),
+                message: row.get("message"),
+                resolved: row.get("resolved"),
+                resolved_at: row.get("resolved_at"),
+                resolution: row.get("resolution"),
+            })
+            .collect();
+
+        Ok(incidents)
+    }
+
+    // Запись значения пользовательского индикатора для свечи
+    pub async fn insert_indicator_value(
+        &self,
+        instrument_code: &str,
+        indicator: &str,
+        candle_time: DateTime<Utc>,
+        value: f64,
+    ) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO indicator_values (instrument_code, indicator, candle_time, value)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (instrument_code, indicator, candle_time) DO UPDATE SET value = EXCLUDED.value;
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(query, &[&instrument_code, &indicator, &candle_time, &value]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса записи значения индикатора {}: {:?}", indicator, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Пишет строку heartbeat. Пропуски между строками дольше минуты означают простой бота.
+    pub async fn insert_heartbeat(
+        &self,
+        state: &str,
+        instruments_active: i32,
+        loop_latency_ms: f64,
+    ) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO heartbeats (state, instruments_active, loop_latency_ms)
+            VALUES ($1, $2, $3);
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(query, &[&state, &instruments_active, &loop_latency_ms]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса записи heartbeat: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_heartbeats(&self, since: DateTime<Utc>) -> Result<Vec<Heartbeat>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT recorded_at, state, instruments_active, loop_latency_ms
+            FROM heartbeats
+            WHERE recorded_at >= $1
+            ORDER BY recorded_at;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения heartbeats: {:?}", e);
+            e
+        })?;
+
+        let heartbeats = rows
+            .iter()
+            .map(|row| Heartbeat {
+                recorded_at: row.get("recorded_at"),
+                state: row.get("state"),
+                instruments_active: row.get("instruments_active"),
+                loop_latency_ms: row.get("loop_latency_ms"),
+            })
+            .collect();
+
+        Ok(heartbeats)
+    }
+
+
+    /// Пишет запись аудита оценки свечи. Повторная оценка той же свечи заменяет запись.
+    pub async fn insert_evaluation(&self, evaluation: &Evaluation) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO evaluations (instrument_code, candle_time, indicator_names, indicator_values, decision, reason_code)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (instrument_code, candle_time) DO UPDATE SET
+                indicator_names = EXCLUDED.indicator_names,
+                indicator_values = EXCLUDED.indicator_values,
+                decision = EXCLUDED.decision,
+                reason_code = EXCLUDED.reason_code,
+                recorded_at = NOW();
+        ";
+
+        let (names, values): (Vec<&str>, Vec<f64>) = evaluation
+            .indicators
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .unzip();
+
+        // Выполняем запрос с параметрами
+        conn.execute(
+            query,
+            &[&evaluation.instrument_code, &evaluation.candle_time, &names, &values, &evaluation.decision, &evaluation.reason_code],
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса записи оценки {}: {:?}", evaluation.instrument_code, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_evaluations(
+        &self,
+        instrument_code: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<Evaluation>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT instrument_code, candle_time, indicator_names, indicator_values, decision, reason_code
+            FROM evaluations
+            WHERE instrument_code = $1
+                AND candle_time >= $2
+            ORDER BY candle_time;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&instrument_code, &since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения оценок {}: {:?}", instrument_code, e);
+            e
+        })?;
+
+        let evaluations = rows
+            .iter()
+            .map(|row| {
+                let names: Vec<String> = row.get("indicator_names");
+                let values: Vec<f64> = row.get("indicator_values");
+
+                Evaluation {
+                    instrument_code: row.get("instrument_code"),
+                    candle_time: row.get("candle_time"),
+                    indicators: names.into_iter().zip(values).collect(),
+                    decision: row.get("decision"),
+                    reason_code: row.get("reason_code"),
+                }
+            })
+            .collect();
+
+        Ok(evaluations)
+    }
+
+
+    /// Удаляет записи аудита старше `before`. Возвращает количество удаленных записей.
+    pub async fn delete_evaluations_before(&self, before: DateTime<Utc>) -> Result<u64, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "DELETE FROM evaluations WHERE candle_time < $1;";
+
+        // Выполняем запрос с параметрами
+        let deleted = conn.execute(query, &[&before]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса удаления старых оценок: {:?}", e);
+            e
+        })?;
+
+        Ok(deleted)
+    }
+
+
+    /// Пишет событие изменения параметра стратегии в журнал.
+    pub async fn insert_parameter_change(
+        &self,
+        strategy: &str,
+        parameter: &str,
+        live_value: f64,
+        candidate_value: f64,
+        event: &str,
+        details: &str,
+    ) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO parameter_changes (strategy, parameter, live_value, candidate_value, event, details)
+            VALUES ($1, $2, $3, $4, $5, $6);
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(query, &[&strategy, &parameter, &live_value, &candidate_value, &event, &details])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса записи изменения параметра {}.{}: {:?}", strategy, parameter, e);
+                e
+            })?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_parameter_changes(&self, strategy: &str) -> Result<Vec<ParameterChange>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT strategy, parameter, live_value, candidate_value, event, details, recorded_at
+            FROM parameter_changes
+            WHERE strategy = $1
+            ORDER BY id;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&strategy]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения изменений параметров {}: {:?}", strategy, e);
+            e
+        })?;
+
+        let changes = rows
+            .iter()
+            .map(|row| ParameterChange {
+                strategy: row.get("strategy"),
+                parameter: row.get("parameter"),
+                live_value: row.get("live_value"),
+                candidate_value: row.get("candidate_value"),
+                event: row.get("event"),
+                details: row.get("details"),
+                recorded_at: row.get("recorded_at"),
+            })
+            .collect();
+
+        Ok(changes)
+    }
+
+
+    /// Текущая версия схемы, 0 если миграции еще не применялись
+    pub async fn get_schema_version(&self) -> Result<i32, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "SELECT COALESCE(MAX(version), 0) AS version FROM schema_version;";
+
+        // Выполняем запрос
+        let row = conn.query_one(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения версии схемы: {:?}", e);
+            e
+        })?;
+
+        Ok(row.get("version"))
+    }
+
+
+    pub async fn set_schema_version(&self, version: i32) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "INSERT INTO schema_version (version) VALUES ($1) ON CONFLICT (version) DO NOTHING;";
+
+        // Выполняем запрос с параметрами
+        conn.execute(query, &[&version]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса записи версии схемы {}: {:?}", version, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Количество значений индикатора инструмента начиная с `since`, для проверки миграций
+    pub async fn count_indicator_values(
+        &self,
+        instrument_code: &str,
+        indicator: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i64, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT COUNT(*) AS count
+            FROM indicator_values
+            WHERE instrument_code = $1
+                AND indicator = $2
+                AND candle_time >= $3;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn.query_one(query, &[&instrument_code, &indicator, &since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса подсчета значений индикатора {}: {:?}", indicator, e);
+            e
+        })?;
+
+        Ok(row.get("count"))
+    }
+
+
+    /// Записывает тик инструмента в current_trades. Обновление существующей строки копируется
+    /// триггером before_update_current_trades в historical_trades, новая строка только вставляется.
+    pub async fn write_tick(
+        &self,
+        class_code: &str,
+        instrument_code: &str,
+        last_price: f64,
+        last_volume: f64,
+    ) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let update = "
+            UPDATE current_trades
+            SET last_price = $3::float8, last_volume = $4::float8, last_price_time = LOCALTIME, trade_date = CURRENT_DATE
+            WHERE class_code = $1 AND instrument_code = $2;
+        ";
+        let insert = "
+            INSERT INTO current_trades (class_code, instrument_code, last_price, last_volume, last_price_time, trade_date)
+            VALUES ($1, $2, $3::float8, $4::float8, LOCALTIME, CURRENT_DATE);
+        ";
+
+        // Выполняем запрос с параметрами, при отсутствии строки вставляем ее
+        let params: [&(dyn ToSql + Sync); 4] = [&class_code, &instrument_code, &last_price, &last_volume];
+        let updated = conn.execute(update, &params).await.map_err(|e| {
+            error!("Ошибка выполнения запроса обновления тика {}: {:?}", instrument_code, e);
+            e
+        })?;
+        if updated == 0 {
+            conn.execute(insert, &params).await.map_err(|e| {
+                error!("Ошибка выполнения запроса вставки тика {}: {:?}", instrument_code, e);
+                e
+            })?;
+        }
+
+        Ok(())
+    }
+
+
+    /// Количество тиков инструментов с кодом, начинающимся с `prefix`, начиная с `since`.
+    pub async fn count_ticks(&self, prefix: &str, since: DateTime<Utc>) -> Result<i64, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT COUNT(*) AS count
+            FROM historical_trades
+            WHERE instrument_code LIKE $1 || '%'
+                AND update_timestamptz >= $2;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn.query_one(query, &[&prefix, &since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса подсчета тиков {}: {:?}", prefix, e);
+            e
+        })?;
+
+        Ok(row.get("count"))
+    }
+
+
+    /// Удаляет текущие и исторические данные инструментов с кодом, начинающимся с `prefix`.
+    /// Возвращает количество удаленных строк.
+    pub async fn delete_instrument_data(&self, prefix: &str) -> Result<u64, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let mut deleted = 0;
+        for query in [
+            "DELETE FROM current_trades WHERE instrument_code LIKE $1 || '%';",
+            "DELETE FROM historical_trades WHERE instrument_code LIKE $1 || '%';",
+        ] {
+            // Выполняем запрос с параметрами
+            deleted += conn.execute(query, &[&prefix]).await.map_err(|e| {
+                error!("Ошибка выполнения запроса удаления данных {}: {:?}", prefix, e);
+                e
+            })?;
+        }
+
+        Ok(deleted)
+    }
+
+
+    /// Записывает тик, отброшенный или помеченный фильтром аномальных цен, для последующего разбора.
+    pub async fn insert_anomalous_tick(
+        &self,
+        instrument_code: &str,
+        price: f64,
+        previous_price: Option<f64>,
+        action: &str,
+        reason: &str,
+    ) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO anomalous_ticks (instrument_code, price, previous_price, action, reason)
+            VALUES ($1, $2, $3, $4, $5);
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(query, &[&instrument_code, &price, &previous_price, &action, &reason])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса записи аномального тика {}: {:?}", instrument_code, e);
+                e
+            })?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_anomalous_ticks(&self, since: DateTime<Utc>) -> Result<Vec<AnomalousTick>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT instrument_code, price, previous_price, action, reason, recorded_at
+            FROM anomalous_ticks
+            WHERE recorded_at >= $1
+            ORDER BY id;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения аномальных тиков: {:?}", e);
+            e
+        })?;
+
+        let ticks = rows
+            .iter()
+            .map(|row| AnomalousTick {
+                instrument_code: row.get("instrument_code"),
+                price: row.get("price"),
+                previous_price: row.get("previous_price"),
+                action: row.get("action"),
+                reason: row.get("reason"),
+                recorded_at: row.get("recorded_at"),
+            })
+            .collect();
+
+        Ok(ticks)
+    }
+
+
+    pub async fn create_bot_state(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу состояния стратегий, одна строка на стратегию и инструмент
+        let query = "
+            CREATE TABLE IF NOT EXISTS bot_state (
+                strategy VARCHAR(32) NOT NULL,
+                sec_code VARCHAR(12) NOT NULL,
+                state JSONB NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL,
+                PRIMARY KEY (strategy, sec_code)
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы bot_state: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Записывает состояние стратегий, заменяя прежнее состояние тех же стратегий и инструментов.
+    pub async fn upsert_bot_state(&self, records: &[BotStateRecord]) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO bot_state (strategy, sec_code, state, updated_at)
+            VALUES ($1, $2, $3::text::jsonb, $4)
+            ON CONFLICT (strategy, sec_code) DO UPDATE SET
+                state = EXCLUDED.state,
+                updated_at = EXCLUDED.updated_at;
+        ";
+
+        for record in records {
+            // Выполняем запрос с параметрами
+            conn.execute(query, &[&record.strategy, &record.sec_code, &record.state, &record.updated_at])
+                .await
+                .map_err(|e| {
+                    error!("Ошибка выполнения запроса записи состояния {} {}: {:?}", record.strategy, record.sec_code, e);
+                    e
+                })?;
+        }
+
+        Ok(())
+    }
+
+
+    pub async fn get_bot_state(&self) -> Result<Vec<BotStateRecord>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT strategy, sec_code, state::text AS state, updated_at
+            FROM bot_state
+            ORDER BY strategy, sec_code;
+        ";
+
+        // Выполняем запрос
+        let rows = conn.query(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения состояния стратегий: {:?}", e);
+            e
+        })?;
+
+        Ok(rows
+            .iter()
+            .map(|row| BotStateRecord {
+                strategy: row.get("strategy"),
+                sec_code: row.get("sec_code"),
+                state: row.get("state"),
+                updated_at: row.get("updated_at"),
+            })
+            .collect())
+    }
+
+
+    // Создание таблицы дневных свечей, заполняется конвейером конца дня
+    pub async fn create_daily_candles(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу
+        let query = "
+            CREATE TABLE IF NOT EXISTS daily_candles (
+                trade_date DATE NOT NULL,
+                instrument_code VARCHAR(12) NOT NULL,
+                open_price DOUBLE PRECISION NOT NULL,
+                high_price DOUBLE PRECISION NOT NULL,
+                low_price DOUBLE PRECISION NOT NULL,
+                close_price DOUBLE PRECISION NOT NULL,
+                volume DOUBLE PRECISION NOT NULL,
+                trades BIGINT NOT NULL,
+                PRIMARY KEY (trade_date, instrument_code)
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы daily_candles: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Собирает дневные свечи всех инструментов за торговый день из historical_trades,
+    /// повторный запуск за тот же день заменяет свечи. Возвращает число инструментов.
+    pub async fn aggregate_daily_candles(
+        &self,
+        trade_date: NaiveDate,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<u64, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO daily_candles (trade_date, instrument_code, open_price, high_price, low_price, close_price, volume, trades)
+            SELECT
+                $1,
+                instrument_code,
+                (ARRAY_AGG(last_price ORDER BY update_timestamptz ASC))[1]::double precision,
+                MAX(last_price)::double precision,
+                MIN(last_price)::double precision,
+                (ARRAY_AGG(last_price ORDER BY update_timestamptz DESC))[1]::double precision,
+                COALESCE(SUM(last_volume), 0)::double precision,
+                COUNT(*)
+            FROM historical_trades
+            WHERE update_timestamptz >= $2
+                AND update_timestamptz < $3
+                AND last_price IS NOT NULL
+                AND instrument_code IS NOT NULL
+            GROUP BY instrument_code
+            ON CONFLICT (trade_date, instrument_code) DO UPDATE SET
+                open_price = EXCLUDED.open_price,
+                high_price = EXCLUDED.high_price,
+                low_price = EXCLUDED.low_price,
+                close_price = EXCLUDED.close_price,
+                volume = EXCLUDED.volume,
+                trades = EXCLUDED.trades;
+        ";
+
+        // Выполняем запрос с параметрами
+        let aggregated = conn.execute(query, &[&trade_date, &from, &to]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса сборки дневных свечей за {}: {:?}", trade_date, e);
+            e
+        })?;
+
+        Ok(aggregated)
+    }
+
+
+    pub async fn create_daily_reports(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        // Создаем таблицу, итог дня хранится с пустым кодом инструмента
+        let query = "
+            CREATE TABLE IF NOT EXISTS daily_reports (
+                report_date DATE NOT NULL,
+                instrument_code VARCHAR(12) NOT NULL,
+                realized_pnl DOUBLE PRECISION NOT NULL,
+                commission DOUBLE PRECISION NOT NULL,
+                net_pnl DOUBLE PRECISION NOT NULL,
+                trades BIGINT NOT NULL,
+                closed_trades BIGINT NOT NULL,
+                hit_rate DOUBLE PRECISION,
+                max_drawdown DOUBLE PRECISION NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                PRIMARY KEY (report_date, instrument_code)
+            );
+        ";
+
+        // Выполняем команду создания таблицы
+        conn.execute(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблицы daily_reports: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Записывает итоги сессии, повторный отчет за тот же день заменяет строки дня.
+    pub async fn upsert_daily_reports(&self, records: &[DailyReportRecord]) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let mut conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let transaction = conn.transaction().await.map_err(|e| {
+            error!("Ошибка начала транзакции записи отчета сессии: {:?}", e);
+            e
+        })?;
+
+        // Инструменты без сделок при повторном отчете не остаются от прошлого запуска
+        for date in records.iter().map(|record| record.report_date).collect::<std::collections::BTreeSet<_>>() {
+            transaction.execute("DELETE FROM daily_reports WHERE report_date = $1;", &[&date]).await.map_err(|e| {
+                error!("Ошибка выполнения запроса удаления отчета сессии за {}: {:?}", date, e);
+                e
+            })?;
+        }
+
+        let query = "
+            INSERT INTO daily_reports (
+                report_date, instrument_code, realized_pnl, commission, net_pnl, trades, closed_trades, hit_rate, max_drawdown
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+        ";
+
+        for record in records {
+            // Выполняем запрос с параметрами
+            transaction
+                .execute(
+                    query,
+                    &[
+                        &record.report_date,
+                        &record.instrument_code,
+                        &record.realized_pnl,
+                        &record.commission,
+                        &record.net_pnl,
+                        &record.trades,
+                        &record.closed_trades,
+                        &record.hit_rate,
+                        &record.max_drawdown,
+                    ],
+                )
+                .await
+                .map_err(|e| {
+                    error!("Ошибка выполнения запроса записи отчета сессии {} {}: {:?}", record.report_date, record.instrument_code, e);
+                    e
+                })?;
+        }
+
+        transaction.commit().await.map_err(|e| {
+            error!("Ошибка фиксации транзакции записи отчета сессии: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    // Создание таблиц лимитов клиента: money_limits и depo_limits заполняются выгрузкой
+    // таблиц "Денежные лимиты" и "Лимиты по бумагам" из QUIK (DDE или Lua), как current_trades
+    pub async fn create_account_limits(&self) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            CREATE TABLE IF NOT EXISTS money_limits (
+                firm_id VARCHAR(12) NOT NULL,
+                client_code VARCHAR(12) NOT NULL,
+                currency VARCHAR(4) NOT NULL,
+                tag VARCHAR(12) NOT NULL DEFAULT '',
+                limit_kind INTEGER NOT NULL,
+                open_balance DECIMAL(18,6),
+                current_balance DECIMAL(18,6),
+                current_limit DECIMAL(18,6),
+                locked DECIMAL(18,6),
+                updated_at TIMESTAMPTZ,
+                PRIMARY KEY (firm_id, client_code, currency, tag, limit_kind)
+            );
+            CREATE TABLE IF NOT EXISTS depo_limits (
+                firm_id VARCHAR(12) NOT NULL,
+                client_code VARCHAR(12) NOT NULL,
+                sec_code VARCHAR(12) NOT NULL,
+                trd_acc_id VARCHAR(12) NOT NULL DEFAULT '',
+                limit_kind INTEGER NOT NULL,
+                open_balance BIGINT,
+                current_balance BIGINT,
+                locked_buy BIGINT,
+                locked_sell BIGINT,
+                awg_position_price DECIMAL(18,6),
+                updated_at TIMESTAMPTZ,
+                PRIMARY KEY (firm_id, client_code, sec_code, trd_acc_id, limit_kind)
+            );
+        ";
+
+        // Выполняем команду создания таблиц
+        conn.batch_execute(query).await.map_err(|e| {
+            error!("Ошибка выполнения запроса создания таблиц лимитов: {:?}", e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Денежный лимит клиента в валюте с наибольшим сроком расчетов, по которому QUIK
+    /// проверяет заявки (T2 для фондового рынка).
+    pub async fn get_money_limit(&self, client_code: &str, currency: &str) -> Result<Option<MoneyLimit>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT firm_id, client_code, currency, limit_kind, open_balance::float8 AS open_balance,
+                current_balance::float8 AS current_balance, locked::float8 AS locked, updated_at
+            FROM money_limits
+            WHERE client_code = $1 AND currency = $2
+            ORDER BY limit_kind DESC
+            LIMIT 1;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn.query_opt(query, &[&client_code, &currency]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения денежного лимита {}: {:?}", client_code, e);
+            e
+        })?;
+
+        Ok(row.map(|row| MoneyLimit {
+            firm_id: row.get("firm_id"),
+            client_code: row.get("client_code"),
+            currency: row.get("currency"),
+            limit_kind: row.get("limit_kind"),
+            open_balance: row.get::<_, Option<f64>>("open_balance").unwrap_or_default(),
+            current_balance: row.get::<_, Option<f64>>("current_balance").unwrap_or_default(),
+            locked: row.get::<_, Option<f64>>("locked").unwrap_or_default(),
+            updated_at: row.get("updated_at"),
+        }))
+    }
+
+
+    /// Позиция клиента по бумаге с наибольшим сроком расчетов.
+    pub async fn get_depo_position(&self, client_code: &str, sec_code: &str) -> Result<Option<DepoPosition>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT firm_id, client_code, sec_code, limit_kind, open_balance, current_balance, locked_sell,
+                awg_position_price::float8 AS average_price, updated_at
+            FROM depo_limits
+            WHERE client_code = $1 AND sec_code = $2
+            ORDER BY limit_kind DESC
+            LIMIT 1;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn.query_opt(query, &[&client_code, &sec_code]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения позиции {} по {}: {:?}", client_code, sec_code, e);
+            e
+        })?;
+
+        Ok(row.map(|row| DepoPosition {
+            firm_id: row.get("firm_id"),
+            client_code: row.get("client_code"),
+            sec_code: row.get("sec_code"),
+            limit_kind: row.get("limit_kind"),
+            open_balance: row.get::<_, Option<i64>>("open_balance").unwrap_or_default(),
+            current_balance: row.get::<_, Option<i64>>("current_balance").unwrap_or_default(),
+            locked_sell: row.get::<_, Option<i64>>("locked_sell").unwrap_or_default(),
+            average_price: row.get("average_price"),
+            updated_at: row.get("updated_at"),
+        }))
+    }
+
+
+    /// Гарантийное обеспечение инструментов из таблицы current_trades.
+    pub async fn get_margin_requirements(&self, instrument_codes: &[String]) -> Result<Vec<MarginRequirement>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT instrument_code, buy_deposit::float8 AS buy_deposit, sell_deposit::float8 AS sell_deposit
+            FROM current_trades
+            WHERE instrument_code = ANY($1);
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&instrument_codes]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения гарантийного обеспечения: {:?}", e);
+            e
+        })?;
+
+        Ok(rows
+            .iter()
+            .map(|row| MarginRequirement {
+                instrument_code: row.get::<_, Option<String>>("instrument_code").unwrap_or_default(),
+                buy_deposit: row.get("buy_deposit"),
+                sell_deposit: row.get("sell_deposit"),
+            })
+            .collect())
+    }
+
+
+    /// Параметры инструментов из таблицы current_trades: лот, шаг цены и статусы.
+    pub async fn get_instrument_rows(&self, instrument_codes: &[String]) -> Result<Vec<InstrumentRow>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT class_code, instrument_code, lot, lot_multiplier, price_step::float8 AS price_step,
+                price_decimals, session_status, instrument_status, mat_date,
+                price_max::float8 AS price_max, price_min::float8 AS price_min
+            FROM current_trades
+            WHERE instrument_code = ANY($1);
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&instrument_codes]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения параметров инструментов: {:?}", e);
+            e
+        })?;
+
+        let instruments = rows
+            .iter()
+            .map(|row| InstrumentRow {
+                class_code: row.get::<_, Option<String>>("class_code").unwrap_or_default(),
+                instrument_code: row.get::<_, Option<String>>("instrument_code").unwrap_or_default(),
+                lot: row.get("lot"),
+                lot_multiplier: row.get("lot_multiplier"),
+                price_step: row.get("price_step"),
+                price_decimals: row.get("price_decimals"),
+                session_status: row.get("session_status"),
+                instrument_status: row.get("instrument_status"),
+                expiry: row.get("mat_date"),
+                price_max: row.get("price_max"),
+                price_min: row.get("price_min"),
+            })
+            .collect();
+
+        Ok(instruments)
+    }
+
+
+    /// Лучшие котировки инструмента из current_trades, `None` без строки инструмента.
+    /// Сторона без котировки остается пустой.
+    pub async fn get_top_of_book(&self, class_code: &str, instrument_code: &str) -> Result<Option<OrderBook>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT bid::float8 AS bid, offer::float8 AS offer, bid_depth::float8 AS bid_depth, offer_depth::float8 AS offer_depth
+            FROM current_trades
+            WHERE class_code = $1 AND instrument_code = $2;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn.query_opt(query, &[&class_code, &instrument_code]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения котировок {}: {:?}", instrument_code, e);
+            e
+        })?;
+
+        let level = |price: Option<f64>, volume: Option<f64>| {
+            price
+                .filter(|price| *price > 0.0)
+                .map(|price| Level { price, volume: volume.unwrap_or_default() })
+        };
+        Ok(row.map(|row| OrderBook {
+            bids: level(row.get("bid"), row.get("bid_depth")).into_iter().collect(),
+            asks: level(row.get("offer"), row.get("offer_depth")).into_iter().collect(),
+        }))
+    }
+
+
+    /// Записывает сделку бота. Сделка с уже записанным номером игнорируется.
+    pub async fn insert_fill(&self, fill: &Fill) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO fills (trade_num, strategy, instrument_code, is_sell, quantity, price, commission, expected_price, executed_at, point_value)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (trade_num) DO NOTHING;
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(
+            query,
+            &[
+                &fill.trade_num,
+                &fill.strategy,
+                &fill.instrument_code,
+                &fill.is_sell,
+                &fill.quantity,
+                &fill.price,
+                &fill.commission,
+                &fill.expected_price,
+                &fill.executed_at,
+                &fill.point_value,
+            ],
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса записи сделки {}: {:?}", fill.trade_num, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Сделки стратегии за период [from, to) в порядке исполнения.
+    pub async fn get_fills(
+        &self,
+        strategy: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Fill>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT trade_num, strategy, instrument_code, is_sell, quantity, price, commission, expected_price, executed_at, point_value
+            FROM fills
+            WHERE strategy = $1
+                AND executed_at >= $2
+                AND executed_at < $3
+            ORDER BY executed_at, trade_num;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&strategy, &from, &to]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения сделок стратегии {}: {:?}", strategy, e);
+            e
+        })?;
+
+        let fills = rows
+            .iter()
+            .map(|row| Fill {
+                trade_num: row.get("trade_num"),
+                strategy: row.get("strategy"),
+                instrument_code: row.get("instrument_code"),
+                is_sell: row.get("is_sell"),
+                quantity: row.get("quantity"),
+                price: row.get("price"),
+                commission: row.get("commission"),
+                expected_price: row.get("expected_price"),
+                executed_at: row.get("executed_at"),
+                point_value: row.get("point_value"),
+            })
+            .collect();
+
+        Ok(fills)
+    }
+
+
+    /// Сделки всех стратегий до момента `to` в порядке исполнения.
+    pub async fn get_fills_before(&self, to: DateTime<Utc>) -> Result<Vec<Fill>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT trade_num, strategy, instrument_code, is_sell, quantity, price, commission, expected_price, executed_at, point_value
+            FROM fills
+            WHERE executed_at < $1
+            ORDER BY executed_at, trade_num;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&to]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения сделок до {}: {:?}", to, e);
+            e
+        })?;
+
+        let fills = rows
+            .iter()
+            .map(|row| Fill {
+                trade_num: row.get("trade_num"),
+                strategy: row.get("strategy"),
+                instrument_code: row.get("instrument_code"),
+                is_sell: row.get("is_sell"),
+                quantity: row.get("quantity"),
+                price: row.get("price"),
+                commission: row.get("commission"),
+                expected_price: row.get("expected_price"),
+                executed_at: row.get("executed_at"),
+                point_value: row.get("point_value"),
+            })
+            .collect();
+
+        Ok(fills)
+    }
+
+
+    /// Записывает событие обратного вызова QUIK в журнал.
+    pub async fn insert_quik_event(&self, record: &QuikEventRecord) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO quik_events (received_at, kind, trans_id, order_num, sec_code, payload)
+            VALUES ($1, $2, $3, $4, $5, $6::text::jsonb);
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(
+            query,
+            &[&record.received_at, &record.kind, &record.trans_id, &record.order_num, &record.sec_code, &record.payload],
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса записи события QUIK {}: {:?}", record.kind, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// События журнала начиная с `since` в порядке получения, для разбора и воспроизведения.
+    pub async fn get_quik_events(&self, since: DateTime<Utc>) -> Result<Vec<QuikEventRecord>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT received_at, kind, trans_id, order_num, sec_code, payload::text AS payload
+            FROM quik_events
+            WHERE received_at >= $1
+            ORDER BY id;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения событий QUIK: {:?}", e);
+            e
+        })?;
+
+        let records = rows
+            .iter()
+            .map(|row| QuikEventRecord {
+                received_at: row.get("received_at"),
+                kind: row.get("kind"),
+                trans_id: row.get("trans_id"),
+                order_num: row.get("order_num"),
+                sec_code: row.get("sec_code"),
+                payload: row.get("payload"),
+            })
+            .collect();
+
+        Ok(records)
+    }
+
+
+    pub async fn insert_call_stats(&self, record: &CallStatsRecord) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO trans2quik_call_stats (period_start, period_end, function, calls, failures, results, avg_latency_ms, max_latency_ms)
+            VALUES ($1, $2, $3, $4, $5, $6::text::jsonb, $7, $8);
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(
+            query,
+            &[
+                &record.period_start,
+                &record.period_end,
+                &record.function,
+                &record.calls,
+                &record.failures,
+                &record.results,
+                &record.avg_latency_ms,
+                &record.max_latency_ms,
+            ],
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса записи статистики вызовов {}: {:?}", record.function, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    /// Агрегаты вызовов Trans2QUIK.dll за периоды, закончившиеся начиная с `since`.
+    pub async fn get_call_stats(&self, since: DateTime<Utc>) -> Result<Vec<CallStatsRecord>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT period_start, period_end, function, calls, failures, results::text AS results, avg_latency_ms, max_latency_ms
+            FROM trans2quik_call_stats
+            WHERE period_end >= $1
+            ORDER BY period_end, function;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения статистики вызовов: {:?}", e);
+            e
+        })?;
+
+        let records = rows
+            .iter()
+            .map(|row| CallStatsRecord {
+                period_start: row.get("period_start"),
+                period_end: row.get("period_end"),
+                function: row.get("function"),
+                calls: row.get("calls"),
+                failures: row.get("failures"),
+                results: row.get("results"),
+                avg_latency_ms: row.get("avg_latency_ms"),
+                max_latency_ms: row.get("max_latency_ms"),
+            })
+            .collect();
+
+        Ok(records)
+    }
+
+
+    pub async fn upsert_position(&self, record: &PositionRecord) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO positions (sec_code, class_code, quantity, average_price, realized_pnl, commission, slippage, last_price, last_trade_num, updated_at, opened_at, account, point_value)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            ON CONFLICT (sec_code) DO UPDATE SET
+                class_code = EXCLUDED.class_code,
+                account = EXCLUDED.account,
+                quantity = EXCLUDED.quantity,
+                average_price = EXCLUDED.average_price,
+                realized_pnl = EXCLUDED.realized_pnl,
+                commission = EXCLUDED.commission,
+                slippage = EXCLUDED.slippage,
+                last_price = EXCLUDED.last_price,
+                last_trade_num = EXCLUDED.last_trade_num,
+                updated_at = EXCLUDED.updated_at,
+                opened_at = EXCLUDED.opened_at,
+                point_value = EXCLUDED.point_value;
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(
+            query,
+            &[
+                &record.sec_code,
+                &record.class_code,
+                &record.quantity,
+                &record.average_price,
+                &record.realized_pnl,
+                &record.commission,
+                &record.slippage,
+                &record.last_price,
+                &record.last_trade_num,
+                &record.updated_at,
+                &record.opened_at,
+                &record.account,
+                &record.point_value,
+            ],
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса записи позиции {}: {:?}", record.sec_code, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_positions(&self) -> Result<Vec<PositionRecord>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT sec_code, class_code, account, quantity, average_price, realized_pnl, commission, slippage, last_price, last_trade_num, updated_at, opened_at, point_value
+            FROM positions
+            ORDER BY sec_code;
+        ";
+
+        // Выполняем запрос
+        let rows = conn.query(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения позиций: {:?}", e);
+            e
+        })?;
+
+        let records = rows
+            .iter()
+            .map(|row| PositionRecord {
+                sec_code: row.get("sec_code"),
+                class_code: row.get("class_code"),
+                account: row.get("account"),
+                quantity: row.get("quantity"),
+                average_price: row.get("average_price"),
+                realized_pnl: row.get("realized_pnl"),
+                commission: row.get("commission"),
+                slippage: row.get("slippage"),
+                last_price: row.get("last_price"),
+                last_trade_num: row.get("last_trade_num"),
+                updated_at: row.get("updated_at"),
+                opened_at: row.get("opened_at"),
+                point_value: row.get("point_value"),
+            })
+            .collect();
+
+        Ok(records)
+    }
+
+
+    /// Переносит позицию погашенного инструмента в positions_archive одним запросом,
+    /// возвращает true, если позиция была.
+    pub async fn archive_position(&self, sec_code: &str, expiry: Option<NaiveDate>) -> Result<bool, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            WITH archived AS (
+                DELETE FROM positions WHERE sec_code = $1
+                RETURNING sec_code, class_code, quantity, average_price, realized_pnl, commission, slippage, last_price, last_trade_num, updated_at
+            )
+            INSERT INTO positions_archive (sec_code, class_code, quantity, average_price, realized_pnl, commission, slippage, last_price, last_trade_num, updated_at, expiry)
+            SELECT sec_code, class_code, quantity, average_price, realized_pnl, commission, slippage, last_price, last_trade_num, updated_at, $2
+            FROM archived;
+        ";
+
+        // Выполняем запрос с параметрами
+        let archived = conn.execute(query, &[&sec_code, &expiry]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса архивирования позиции {}: {:?}", sec_code, e);
+            e
+        })?;
+
+        Ok(archived > 0)
+    }
+
+
+    /// Цены сделок инструмента за период в порядке времени.
+    pub async fn get_prices(
+        &self,
+        instrument_code: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, f64)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT update_timestamptz, last_price::double precision AS last_price
+            FROM historical_trades
+            WHERE instrument_code = $1
+                AND update_timestamptz >= $2
+                AND update_timestamptz <= $3
+                AND last_price IS NOT NULL
+            ORDER BY update_timestamptz;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&instrument_code, &from, &to]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения цен {}: {:?}", instrument_code, e);
+            e
+        })?;
+
+        Ok(rows.iter().map(|row| (row.get("update_timestamptz"), row.get("last_price"))).collect())
+    }
+
+
+    pub async fn insert_exit(&self, record: &ExitRecord) -> Result<(), RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO exits (strategy, sec_code, reason, quantity, entry_price, trigger_price, last_price, trans_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+        ";
+
+        // Выполняем запрос с параметрами
+        conn.execute(
+            query,
+            &[
+                &record.strategy,
+                &record.sec_code,
+                &record.reason,
+                &record.quantity,
+                &record.entry_price,
+                &record.trigger_price,
+                &record.last_price,
+                &record.trans_id,
+                &record.created_at,
+            ],
+        )
+        .await
+        .map_err(|e| {
+            error!("Ошибка выполнения запроса записи выхода {}: {:?}", record.sec_code, e);
+            e
+        })?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_exits(&self, since: DateTime<Utc>) -> Result<Vec<ExitRecord>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT strategy, sec_code, reason, quantity, entry_price, trigger_price, last_price, trans_id, created_at
+            FROM exits
+            WHERE created_at >= $1
+            ORDER BY created_at;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&since]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения выходов: {:?}", e);
+            e
+        })?;
+
+        let records = rows
+            .iter()
+            .map(|row| ExitRecord {
+                strategy: row.get("strategy"),
+                sec_code: row.get("sec_code"),
+                reason: row.get("reason"),
+                quantity: row.get("quantity"),
+                entry_price: row.get("entry_price"),
+                trigger_price: row.get("trigger_price"),
+                last_price: row.get("last_price"),
+                trans_id: row.get("trans_id"),
+                created_at: row.get("created_at"),
+            })
+            .collect();
+
+        Ok(records)
+    }
+
+
+    /// Последние цены инструментов из таблицы текущих торгов.
+    pub async fn get_last_prices(&self, instrument_codes: &[String]) -> Result<Vec<(String, f64)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT instrument_code, last_price::double precision AS last_price
+            FROM current_trades
+            WHERE instrument_code = ANY($1)
+                AND last_price IS NOT NULL;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&instrument_codes]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения последних цен: {:?}", e);
+            e
+        })?;
+
+        Ok(rows.iter().map(|row| (row.get("instrument_code"), row.get("last_price"))).collect())
+    }
+
+
+    /// Столбцы таблиц схемы public: таблица, столбец и тип из information_schema.
+    pub async fn get_columns(&self) -> Result<Vec<(String, String, String)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT table_name::text, column_name::text, data_type::text
+            FROM information_schema.columns
+            WHERE table_schema = 'public'
+            ORDER BY table_name, ordinal_position;
+        ";
+
+        // Выполняем запрос
+        let rows = conn.query(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения столбцов: {:?}", e);
+            e
+        })?;
+
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+    }
+
+
+    /// Индексы таблиц схемы public: таблица и имя индекса.
+    pub async fn get_indexes(&self) -> Result<Vec<(String, String)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT tablename::text, indexname::text
+            FROM pg_indexes
+            WHERE schemaname = 'public';
+        ";
+
+        // Выполняем запрос
+        let rows = conn.query(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения индексов: {:?}", e);
+            e
+        })?;
+
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+
+    /// Сделки журнала событий, для заявок которых нет ни событий заявки, ни ответов на транзакции.
+    pub async fn count_trades_without_orders(&self) -> Result<i64, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT COUNT(*)
+            FROM quik_events trade
+            WHERE trade.kind = 'trade'
+                AND NOT EXISTS (
+                    SELECT 1 FROM quik_events orders
+                    WHERE orders.kind IN ('order', 'reply') AND orders.order_num = trade.order_num
+                );
+        ";
+
+        // Выполняем запрос
+        let row = conn.query_one(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса проверки сделок без заявок: {:?}", e);
+            e
+        })?;
+
+        Ok(row.get(0))
+    }
+
+
+    /// Открытые позиции, последняя сделка которых не найдена ни в fills, ни в журнале событий.
+    pub async fn get_positions_without_trades(&self) -> Result<Vec<String>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT sec_code
+            FROM positions
+            WHERE quantity <> 0
+                AND NOT EXISTS (SELECT 1 FROM fills WHERE fills.trade_num = positions.last_trade_num)
+                AND NOT EXISTS (
+                    SELECT 1 FROM quik_events
+                    WHERE kind = 'trade' AND (payload->>'trade_num')::bigint = positions.last_trade_num
+                )
+            ORDER BY sec_code;
+        ";
+
+        // Выполняем запрос
+        let rows = conn.query(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса проверки позиций без сделок: {:?}", e);
+            e
+        })?;
+
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+
+    /// Заявки, активные по последнему событию журнала: книга заявок бота на момент остановки.
+    pub async fn get_working_orders(&self) -> Result<Vec<Order>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT trans_id, order_num, class_code, sec_code, is_sell, price, qty, balance, date, time
+            FROM (
+                SELECT DISTINCT ON (order_num)
+                    trans_id,
+                    order_num,
+                    payload->>'class_code' AS class_code,
+                    sec_code,
+                    (payload->>'is_sell')::boolean AS is_sell,
+                    (payload->>'price')::float8 AS price,
+                    (payload->>'qty')::bigint AS qty,
+                    (payload->>'balance')::bigint AS balance,
+                    (payload->>'status')::bigint AS status,
+                    (payload->>'date')::bigint AS date,
+                    (payload->>'time')::bigint AS time
+                FROM quik_events
+                WHERE kind = 'order' AND order_num > 0
+                ORDER BY order_num, id DESC
+            ) last_events
+            WHERE status = 1 AND balance > 0
+            ORDER BY order_num;
+        ";
+
+        // Выполняем запрос
+        let rows = conn.query(query, &[]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения активных заявок журнала: {:?}", e);
+            e
+        })?;
+
+        let orders = rows
+            .iter()
+            .map(|row| {
+                let date: i64 = row.get("date");
+                let time: i64 = row.get("time");
+                Order {
+                    trans_id: row.get::<_, Option<i64>>("trans_id").unwrap_or_default() as c_ulong,
+                    order_num: row.get::<_, i64>("order_num") as u64,
+                    class_code: row.get::<_, Option<String>>("class_code").unwrap_or_default(),
+                    sec_code: row.get::<_, Option<String>>("sec_code").unwrap_or_default(),
+                    side: if row.get("is_sell") { Side::Sell } else { Side::Buy },
+                    price: row.get("price"),
+                    quantity: row.get("qty"),
+                    balance: row.get("balance"),
+                    status: OrderStatus::Active,
+                    placed_at: domain::exchange_time(date as c_long, time as c_long),
+                }
+            })
+            .collect();
+
+        Ok(orders)
+    }
+
+
+    /// TRANS_ID из списка, на которые в журнале событий есть ответ или заявка, с номером заявки.
+    pub async fn get_acknowledged_trans_ids(&self, trans_ids: &[i64]) -> Result<Vec<(i64, Option<i64>)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT trans_id, MAX(NULLIF(order_num, 0)) AS order_num
+            FROM quik_events
+            WHERE kind IN ('reply', 'order') AND trans_id = ANY($1)
+            GROUP BY trans_id;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&trans_ids]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса поиска подтвержденных транзакций: {:?}", e);
+            e
+        })?;
+
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+
+    /// Свечи инструмента за период из historical_trades, выровненные по началу эпохи.
+    pub async fn get_candles(
+        &self,
+        instrument_code: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        period_length_seconds: f64,
+    ) -> Result<Vec<DataForEma>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT
+                TO_TIMESTAMP(FLOOR(EXTRACT(EPOCH FROM update_timestamptz) / $4::double precision) * $4::double precision) AS period_start,
+                (ARRAY_AGG(last_price ORDER BY update_timestamptz ASC))[1]::double precision AS open_price,
+                (ARRAY_AGG(last_price ORDER BY update_timestamptz DESC))[1]::double precision AS close_price,
+                MIN(last_price)::double precision AS min_price,
+                MAX(last_price)::double precision AS max_price,
+                COALESCE(SUM(last_volume), 0)::double precision AS period_volume
+            FROM historical_trades
+            WHERE instrument_code = $1
+                AND update_timestamptz >= $2
+                AND update_timestamptz < $3
+                AND last_price IS NOT NULL
+            GROUP BY 1
+            ORDER BY 1;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn
+            .query(query, &[&instrument_code, &from, &to, &period_length_seconds])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса получения свечей {}: {:?}", instrument_code, e);
+                e
+            })?;
+
+        Ok(rows
+            .iter()
+            .map(|row| DataForEma {
+                period_start: row.get("period_start"),
+                open: row.get("open_price"),
+                high: row.get("max_price"),
+                low: row.get("min_price"),
+                close: row.get("close_price"),
+                volume: row.get("period_volume"),
+            })
+            .collect())
+    }
+
+
+    /// Тики инструмента (цена и объем) из historical_trades в интервале [start, end) в порядке поступления.
+    /// Границы задает вызывающий, поэтому свеча из тиков не зависит от времени выполнения запроса.
+    pub async fn get_ticks(
+        &self,
+        instrument_code: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<(f64, f64)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT
+                last_price::double precision AS price,
+                COALESCE(last_volume, 0)::double precision AS volume
+            FROM historical_trades
+            WHERE instrument_code = $1
+                AND update_timestamptz >= $2
+                AND update_timestamptz < $3
+                AND last_price IS NOT NULL
+            ORDER BY update_timestamptz ASC;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn
+            .query(query, &[&instrument_code, &start, &end])
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса получения тиков {} [{}, {}): {:?}", instrument_code, start, end, e);
+                e
+            })?;
+
+        Ok(rows.iter().map(|row| (row.get("price"), row.get("volume"))).collect())
+    }
+
+
+    /// Тики всех инструментов из historical_trades после `after` (код инструмента, цена и время)
+    /// в порядке поступления, не более `limit`.
+    pub async fn get_ticks_after(
+        &self,
+        after: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<(String, f64, DateTime<Utc>)>, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            SELECT
+                instrument_code,
+                last_price::double precision AS price,
+                update_timestamptz
+            FROM historical_trades
+            WHERE update_timestamptz > $1
+                AND last_price IS NOT NULL
+            ORDER BY update_timestamptz ASC
+            LIMIT $2;
+        ";
+
+        // Выполняем запрос с параметрами
+        let rows = conn.query(query, &[&after, &limit]).await.map_err(|e| {
+            error!("Ошибка выполнения запроса получения тиков после {}: {:?}", after, e);
+            e
+        })?;
+
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("instrument_code"), row.get("price"), row.get("update_timestamptz")))
+            .collect())
+    }
+
+
+    /// Сохраняет результат бэктеста, возвращает id записи.
+    pub async fn insert_backtest(&self, record: &BacktestRecord) -> Result<i32, RunError<bb8_postgres::tokio_postgres::Error>> {
+        // Получаем соединение из пула
+        let conn = self.pool.get().await.map_err(|e| {
+            error!("Ошибка получения соединения из пула: {:?}", e);
+            e
+        })?;
+
+        let query = "
+            INSERT INTO backtests (
+                strategy, parameters, period_from, period_to, capital, fills, closed_trades,
+                net_pnl, max_drawdown, sharpe, win_rate, created_at
+            )
+            VALUES ($1, $2::text::jsonb, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            RETURNING id;
+        ";
+
+        // Выполняем запрос с параметрами
+        let row = conn
+            .query_one(
+                query,
+                &[
+                    &record.strategy,
+                    &record.parameters,
+                    &record.period_from,
+                    &record.period_to,
+                    &record.capital,
+                    &record.fills,
+                    &record.closed_trades,
+                    &record.net_pnl,
+                    &record.max_drawdown,
+                    &record.sharpe,
+                    &record.win_rate,
+                    &record.created_at,
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Ошибка выполнения запроса записи бэктеста: {:?}", e);
+                e
+            })?;
+
+        Ok(row.get(0))
+    }
+}
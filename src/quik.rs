@@ -1,22 +1,96 @@
-use std::error::Error;
-use libloading;
-use libc;
-use std::ptr;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ffi::CStr;
 use std::ffi::CString;
-use libloading::{Library, Symbol};
-use libc::{c_char, c_long, c_ulong};
-use tracing::{info, error};
-use tracing_subscriber;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use libloading::{Library, Symbol};
+use libc::{c_char, c_double, c_long, c_ulong};
+use tokio::sync::{mpsc, watch};
+use tracing::{info, error, warn};
+use crate::clock::Clock;
+use crate::cp1251;
+use crate::transaction::Transaction;
+
+
+/// Prototype of the callback function TRANS2QUIK_CONNECTION_STATUS_CALLBACK.
+type ConnectionStatusCallback = extern "C" fn(c_long, c_long, *const c_char);
+
+/// Prototype of the callback function TRANS2QUIK_TRANSACTION_REPLY_CALLBACK.
+type TransactionReplyCallback = extern "C" fn(c_long, c_long, c_long, c_ulong, u64, *const c_char, isize);
+
+/// Prototype of the callback function TRANS2QUIK_ORDER_STATUS_CALLBACK:
+/// mode, transaction ID, order number, class code, securities code, price, balance,
+/// volume, sell flag, status and order descriptor.
+type OrderStatusCallback = extern "C" fn(c_long, c_ulong, u64, *const c_char, *const c_char, c_double, i64, c_double, c_long, c_long, isize);
+
+/// Prototype of the callback function TRANS2QUIK_TRADE_STATUS_CALLBACK:
+/// mode, trade number, order number, class code, securities code, price, quantity,
+/// volume, sell flag and trade descriptor.
+type TradeStatusCallback = extern "C" fn(c_long, u64, u64, *const c_char, *const c_char, c_double, i64, c_double, c_long, isize);
+
+/// Prototype of a getter function returning a string field of a descriptor.
+type DescriptorStr = unsafe extern "C" fn(isize) -> *const c_char;
+
+/// Prototype of a getter function returning a floating point field of a descriptor.
+type DescriptorDouble = unsafe extern "C" fn(isize) -> c_double;
+
+/// Prototype of a getter function returning an integer field of a descriptor.
+type DescriptorInt64 = unsafe extern "C" fn(isize) -> i64;
+
+/// Prototype of a getter function returning a `long` field of a descriptor, e.g. a date or a time.
+type DescriptorLong = unsafe extern "C" fn(isize) -> c_long;
+
+
+/// Maximum number of terminals loaded in one process.
+pub const MAX_TERMINALS: usize = 4;
+
+/// Contexts of the loaded terminals by slot.
+///
+/// The callback functions of Trans2QUIK.dll carry no context, so every slot has its own
+/// instances of the callback functions (e.g. `connection_status_callback::<0>`), which
+/// look up the context of their slot. This way the events of two terminals loaded
+/// in the same process are never interleaved.
+static CALLBACK_CONTEXTS: [RwLock<Option<Arc<CallbackContext>>>; MAX_TERMINALS] = [const { RwLock::new(None) }; MAX_TERMINALS];
+
+/// Connection status callback functions by slot.
+const CONNECTION_STATUS_CALLBACKS: [ConnectionStatusCallback; MAX_TERMINALS] = [
+    connection_status_callback::<0>,
+    connection_status_callback::<1>,
+    connection_status_callback::<2>,
+    connection_status_callback::<3>,
+];
 
+/// Transaction reply callback functions by slot.
+const TRANSACTION_REPLY_CALLBACKS: [TransactionReplyCallback; MAX_TERMINALS] = [
+    transaction_reply_callback::<0>,
+    transaction_reply_callback::<1>,
+    transaction_reply_callback::<2>,
+    transaction_reply_callback::<3>,
+];
 
-type TRANS2QUIK_CONNECTION_STATUS_CALLBACK = ();
+/// Order status callback functions by slot.
+const ORDER_STATUS_CALLBACKS: [OrderStatusCallback; MAX_TERMINALS] = [
+    order_status_callback::<0>,
+    order_status_callback::<1>,
+    order_status_callback::<2>,
+    order_status_callback::<3>,
+];
+
+/// Trade status callback functions by slot.
+const TRADE_STATUS_CALLBACKS: [TradeStatusCallback; MAX_TERMINALS] = [
+    trade_status_callback::<0>,
+    trade_status_callback::<1>,
+    trade_status_callback::<2>,
+    trade_status_callback::<3>,
+];
 
 
 /// Corresponds to the description of constants whose values are returned when exiting functions
 /// and procedures in the library `Trans2QUIK.dll `:
-/// ```
+/// ```text
 /// TRANS2QUIK_SUCCESS 0
 /// TRANS2QUIK_FAILED 1
 /// TRANS2QUIK_QUIK_TERMINAL_NOT_FOUND 2
@@ -33,7 +107,7 @@ type TRANS2QUIK_CONNECTION_STATUS_CALLBACK = ();
 /// TRANS2QUIK_WRONG_CONNECTION_HANDLE 13
 /// TRANS2QUIK_WRONG_INPUT_PARAMS 14
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(i32)]
 pub enum Trans2quikResult {
     Success = 0,
@@ -80,21 +154,698 @@ impl From<c_long> for Trans2quikResult {
     }
 }
 
+
+impl Trans2quikResult {
+    /// Checks whether the code reports a failure of the function. The codes of the connection
+    /// state, e.g. `QuikDisconnected`, are not failures.
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            self,
+            Trans2quikResult::Success
+                | Trans2quikResult::AlreadyConnectedToQuik
+                | Trans2quikResult::QuikConnected
+                | Trans2quikResult::QuikDisconnected
+                | Trans2quikResult::DllConnected
+                | Trans2quikResult::DllDisconnected
+        )
+    }
+
+
+    /// Converts a failure code of the function `function` to `Trans2QuikError`.
+    fn check(self, function: &'static str, error_code: c_long, error_message: String) -> Result<Trans2quikResult, Trans2QuikError> {
+        if self.is_failure() {
+            error!("{} failed -> {:?}, error code: {}, error message: {}", function, self, error_code, error_message);
+            Err(Trans2QuikError::Failed { function, result: self, error_code, error_message })
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+
+/// Error of a function of the library Trans2QUIK.dll.
+///
+/// # Example of use
+/// ```ignore
+/// match terminal.subscribe_orders("QJSIM", "SBER") {
+///     Ok(_) => {}
+///     Err(e) if e.result() == Some(Trans2quikResult::DllNotConnected) => supervisor_reconnect(),
+///     Err(e) => return Err(e.into()),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trans2QuikError {
+    /// The function returned a failure code, e.g. `Failed`, `QuikNotConnected` or `DllNotConnected`.
+    Failed {
+        function: &'static str,
+        result: Trans2quikResult,
+        /// Extended error code returned by the function.
+        error_code: c_long,
+        /// Error message decoded from Windows-1251.
+        error_message: String,
+    },
+    /// A parameter cannot be passed to the library, e.g. it contains a null byte
+    /// or a character missing in Windows-1251.
+    InvalidParameter(String),
+    /// The function is missing in the loaded version of the library, see `Capabilities`.
+    Unsupported(&'static str),
+}
+
+
+impl Trans2QuikError {
+    /// Code returned by the function, if the function was called.
+    pub fn result(&self) -> Option<Trans2quikResult> {
+        match self {
+            Trans2QuikError::Failed { result, .. } => Some(*result),
+            Trans2QuikError::InvalidParameter(_) | Trans2QuikError::Unsupported(_) => None,
+        }
+    }
+
+
+    /// Checks whether the error is caused by a lost connection to the terminal or the server.
+    pub fn is_disconnected(&self) -> bool {
+        matches!(
+            self.result(),
+            Some(Trans2quikResult::QuikNotConnected) | Some(Trans2quikResult::DllNotConnected)
+        )
+    }
+}
+
+
+impl fmt::Display for Trans2QuikError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trans2QuikError::Failed { function, result, error_code, error_message } => {
+                write!(f, "{} -> {:?}, error code: {}", function, result, error_code)?;
+                if !error_message.is_empty() {
+                    write!(f, ", error message: {}", error_message)?;
+                }
+                Ok(())
+            }
+            Trans2QuikError::InvalidParameter(message) => write!(f, "invalid parameter: {}", message),
+            Trans2QuikError::Unsupported(function) => {
+                write!(f, "{} is not supported by this version of Trans2QUIK.dll", function)
+            }
+        }
+    }
+}
+
+
+impl std::error::Error for Trans2QuikError {}
+
+/// Status of a transaction on the QUIK server, the reply code of a transaction reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyStatus {
+    /// 0 - the transaction is sent to the server.
+    Sent,
+    /// 1 - the transaction is received by the QUIK server.
+    Received,
+    /// 2 - the transaction could not be sent to the trading system, e.g. the gateway is disconnected.
+    GatewayError,
+    /// 3 - the transaction is executed.
+    Executed,
+    /// 4 - the transaction is rejected by the trading system, the reason is in the message.
+    RejectedByExchange,
+    /// 5 - the transaction failed the checks of the QUIK server, e.g. the trading permissions.
+    RejectedByServer,
+    /// 6 - the transaction failed the limit check, e.g. insufficient funds or margin.
+    RejectedByLimits,
+    /// 10 - the transaction is not supported by the trading system.
+    NotSupported,
+    /// 11 - the digital signature of the transaction is invalid.
+    SignatureInvalid,
+    /// 12 - no reply within the timeout, e.g. the transaction was sent while the connection was lost.
+    Timeout,
+    /// 13 - the transaction is rejected because it could cause a cross trade.
+    CrossTrade,
+    Unknown(c_long),
+}
+
+
+impl From<c_long> for ReplyStatus {
+    fn from(code: c_long) -> Self {
+        match code {
+            0 => ReplyStatus::Sent,
+            1 => ReplyStatus::Received,
+            2 => ReplyStatus::GatewayError,
+            3 => ReplyStatus::Executed,
+            4 => ReplyStatus::RejectedByExchange,
+            5 => ReplyStatus::RejectedByServer,
+            6 => ReplyStatus::RejectedByLimits,
+            10 => ReplyStatus::NotSupported,
+            11 => ReplyStatus::SignatureInvalid,
+            12 => ReplyStatus::Timeout,
+            13 => ReplyStatus::CrossTrade,
+            code => ReplyStatus::Unknown(code),
+        }
+    }
+}
+
+
+impl ReplyStatus {
+    pub fn is_executed(&self) -> bool {
+        *self == ReplyStatus::Executed
+    }
+
+
+    /// The transaction is refused and won't be executed.
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            ReplyStatus::RejectedByExchange
+                | ReplyStatus::RejectedByServer
+                | ReplyStatus::RejectedByLimits
+                | ReplyStatus::NotSupported
+                | ReplyStatus::SignatureInvalid
+                | ReplyStatus::CrossTrade
+        )
+    }
+
+
+    /// The transaction is rejected for insufficient funds or margin.
+    pub fn is_insufficient_funds(&self) -> bool {
+        *self == ReplyStatus::RejectedByLimits
+    }
+
+
+    /// The outcome is unknown and the transaction may be retried after the reconnection.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ReplyStatus::GatewayError | ReplyStatus::Timeout)
+    }
+
+
+    /// The status won't change anymore.
+    pub fn is_final(&self) -> bool {
+        !matches!(self, ReplyStatus::Sent | ReplyStatus::Received)
+    }
+}
+
+
+/// Event received from the QUIK terminal by one of the callback functions.
+#[derive(Debug, Clone)]
+pub enum QuikEvent {
+    ConnectionStatus(ConnectionEvent),
+    TransactionReply(TransactionReply),
+    OrderUpdate(OrderInfo),
+    TradeUpdate(TradeInfo),
+}
+
+
+/// Connection event received by the callback function TRANS2QUIK_CONNECTION_STATUS_CALLBACK.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    /// One of `QuikConnected`, `QuikDisconnected`, `DllConnected` or `DllDisconnected`.
+    pub status: Trans2quikResult,
+    pub error_code: c_long,
+    pub info_message: String,
+}
+
+
+/// Reply to a transaction received by the callback function TRANS2QUIK_TRANSACTION_REPLY_CALLBACK.
+///
+/// The fields after `reply_message` are read from the transaction reply descriptor
+/// with the TRANS2QUIK_TRANSREPLY_* functions.
+#[derive(Debug, Clone)]
+pub struct TransactionReply {
+    pub trans2quik_result: Trans2quikResult,
+    pub error_code: c_long,
+    pub reply_code: c_long,
+    /// Classification of `reply_code`.
+    pub status: ReplyStatus,
+    pub trans_id: c_ulong,
+    pub order_num: u64,
+    pub reply_message: String,
+    pub class_code: String,
+    pub sec_code: String,
+    pub price: f64,
+    pub quantity: i64,
+    pub balance: i64,
+    pub firm_id: String,
+    pub account: String,
+    pub client_code: String,
+    pub broker_ref: String,
+    pub exchange_code: String,
+}
+
+
+/// Result of a transaction sent by `Terminal::send_sync_transaction`.
+#[derive(Debug, Clone)]
+pub struct SyncTransactionResult {
+    pub result: Trans2quikResult,
+    /// Status of the transaction on the server, e.g. 3 - executed.
+    pub reply_code: c_long,
+    /// Classification of `reply_code`.
+    pub status: ReplyStatus,
+    pub trans_id: c_ulong,
+    /// Number of the order assigned by the trading system, 0 if no order was created.
+    pub order_num: u64,
+    pub result_message: String,
+    pub error_code: c_long,
+    pub error_message: String,
+}
+
+
+/// Functions of the library Trans2QUIK.dll for reading the transaction reply descriptor.
+struct TransReplyGetters {
+    class_code: DescriptorStr,
+    sec_code: DescriptorStr,
+    price: DescriptorDouble,
+    quantity: DescriptorInt64,
+    balance: DescriptorInt64,
+    firm_id: DescriptorStr,
+    account: DescriptorStr,
+    client_code: DescriptorStr,
+    broker_ref: DescriptorStr,
+    exchange_code: DescriptorStr,
+}
+
+
+impl TransReplyGetters {
+    /// Loads the functions, `None` if the library has no transaction reply descriptor functions.
+    fn load(library: &Library) -> Option<Self> {
+        Some(TransReplyGetters {
+            class_code: load_optional(library, b"TRANS2QUIK_TRANSREPLY_CLASSCODE\0")?,
+            sec_code: load_optional(library, b"TRANS2QUIK_TRANSREPLY_SECCODE\0")?,
+            price: load_optional(library, b"TRANS2QUIK_TRANSREPLY_PRICE\0")?,
+            quantity: load_optional(library, b"TRANS2QUIK_TRANSREPLY_QUANTITY\0")?,
+            balance: load_optional(library, b"TRANS2QUIK_TRANSREPLY_BALANCE\0")?,
+            firm_id: load_optional(library, b"TRANS2QUIK_TRANSREPLY_FIRMID\0")?,
+            account: load_optional(library, b"TRANS2QUIK_TRANSREPLY_ACCOUNT\0")?,
+            client_code: load_optional(library, b"TRANS2QUIK_TRANSREPLY_CLIENTCODE\0")?,
+            broker_ref: load_optional(library, b"TRANS2QUIK_TRANSREPLY_BROKERREF\0")?,
+            exchange_code: load_optional(library, b"TRANS2QUIK_TRANSREPLY_EXCHANGECODE\0")?,
+        })
+    }
+}
+
+
+/// Order received by the callback function TRANS2QUIK_ORDER_STATUS_CALLBACK.
+///
+/// The fields after `status` are read from the order descriptor with the TRANS2QUIK_ORDER_* functions.
+#[derive(Debug, Clone, Default)]
+pub struct OrderInfo {
+    /// 0 - a new order, 1 - an order from the initial snapshot, 2 - the end of the initial snapshot.
+    pub mode: c_long,
+    pub trans_id: c_ulong,
+    pub order_num: u64,
+    pub class_code: String,
+    pub sec_code: String,
+    pub price: f64,
+    pub balance: i64,
+    pub value: f64,
+    pub is_sell: bool,
+    /// 1 - active, 2 - withdrawn, any other value - executed.
+    pub status: c_long,
+    pub qty: i64,
+    /// Date in the `YYYYMMDD` format.
+    pub date: c_long,
+    /// Time in the `HHMMSS` format.
+    pub time: c_long,
+    pub activation_time: c_long,
+    pub withdraw_time: c_long,
+    /// Expiry date in the `YYYYMMDD` format.
+    pub expiry: c_long,
+    pub accrued_int: f64,
+    pub yield_value: f64,
+    pub uid: c_long,
+    pub visible_qty: i64,
+    pub period: c_long,
+    pub awg_price: f64,
+    pub user_id: String,
+    pub account: String,
+    pub broker_ref: String,
+    pub client_code: String,
+    pub firm_id: String,
+    pub reject_reason: String,
+}
+
+
+/// Functions of the library Trans2QUIK.dll for reading the order descriptor.
+struct OrderGetters {
+    qty: DescriptorInt64,
+    date: DescriptorLong,
+    time: DescriptorLong,
+    activation_time: DescriptorLong,
+    withdraw_time: DescriptorLong,
+    expiry: DescriptorLong,
+    accrued_int: DescriptorDouble,
+    yield_value: DescriptorDouble,
+    uid: DescriptorLong,
+    visible_qty: DescriptorInt64,
+    period: DescriptorLong,
+    awg_price: DescriptorDouble,
+    user_id: DescriptorStr,
+    account: DescriptorStr,
+    broker_ref: DescriptorStr,
+    client_code: DescriptorStr,
+    firm_id: DescriptorStr,
+    reject_reason: DescriptorStr,
+}
+
+
+impl OrderGetters {
+    /// Loads the functions, `None` if the library has no order descriptor functions.
+    fn load(library: &Library) -> Option<Self> {
+        Some(OrderGetters {
+            qty: load_optional(library, b"TRANS2QUIK_ORDER_QTY\0")?,
+            date: load_optional(library, b"TRANS2QUIK_ORDER_DATE\0")?,
+            time: load_optional(library, b"TRANS2QUIK_ORDER_TIME\0")?,
+            activation_time: load_optional(library, b"TRANS2QUIK_ORDER_ACTIVATION_TIME\0")?,
+            withdraw_time: load_optional(library, b"TRANS2QUIK_ORDER_WITHDRAW_TIME\0")?,
+            expiry: load_optional(library, b"TRANS2QUIK_ORDER_EXPIRY\0")?,
+            accrued_int: load_optional(library, b"TRANS2QUIK_ORDER_ACCRUED_INT\0")?,
+            yield_value: load_optional(library, b"TRANS2QUIK_ORDER_YIELD\0")?,
+            uid: load_optional(library, b"TRANS2QUIK_ORDER_UID\0")?,
+            visible_qty: load_optional(library, b"TRANS2QUIK_ORDER_VISIBLE_QTY\0")?,
+            period: load_optional(library, b"TRANS2QUIK_ORDER_PERIOD\0")?,
+            awg_price: load_optional(library, b"TRANS2QUIK_ORDER_AWG_PRICE\0")?,
+            user_id: load_optional(library, b"TRANS2QUIK_ORDER_USERID\0")?,
+            account: load_optional(library, b"TRANS2QUIK_ORDER_ACCOUNT\0")?,
+            broker_ref: load_optional(library, b"TRANS2QUIK_ORDER_BROKERREF\0")?,
+            client_code: load_optional(library, b"TRANS2QUIK_ORDER_CLIENT_CODE\0")?,
+            firm_id: load_optional(library, b"TRANS2QUIK_ORDER_FIRMID\0")?,
+            reject_reason: load_optional(library, b"TRANS2QUIK_ORDER_REJECT_REASON\0")?,
+        })
+    }
+}
+
+
+/// Trade received by the callback function TRANS2QUIK_TRADE_STATUS_CALLBACK.
+///
+/// The fields after `is_sell` are read from the trade descriptor with the TRANS2QUIK_TRADE_* functions.
+#[derive(Debug, Clone, Default)]
+pub struct TradeInfo {
+    /// 0 - a new trade, 1 - a trade from the initial snapshot, 2 - the end of the initial snapshot.
+    pub mode: c_long,
+    pub trade_num: u64,
+    pub order_num: u64,
+    pub class_code: String,
+    pub sec_code: String,
+    pub price: f64,
+    pub qty: i64,
+    pub value: f64,
+    pub is_sell: bool,
+    /// Date in the `YYYYMMDD` format.
+    pub date: c_long,
+    /// Settlement date in the `YYYYMMDD` format.
+    pub settle_date: c_long,
+    /// Time in the `HHMMSS` format.
+    pub time: c_long,
+    pub is_marginal: bool,
+    pub accrued_int: f64,
+    pub yield_value: f64,
+    pub ts_commission: f64,
+    pub clearing_center_commission: f64,
+    pub exchange_commission: f64,
+    pub trading_system_commission: f64,
+    pub broker_commission: f64,
+    pub kind: c_long,
+    pub currency: String,
+    pub settle_currency: String,
+    pub settle_code: String,
+    pub account: String,
+    pub broker_ref: String,
+    pub client_code: String,
+    pub user_id: String,
+    pub firm_id: String,
+    pub exchange_code: String,
+}
+
+
+impl TradeInfo {
+    /// Total commission of the trade, used to calculate the realized profit and loss.
+    pub fn commission(&self) -> f64 {
+        self.ts_commission
+            + self.clearing_center_commission
+            + self.exchange_commission
+            + self.trading_system_commission
+            + self.broker_commission
+    }
+}
+
+
+/// Functions of the library Trans2QUIK.dll for reading the trade descriptor.
+struct TradeGetters {
+    date: DescriptorLong,
+    settle_date: DescriptorLong,
+    time: DescriptorLong,
+    is_marginal: DescriptorLong,
+    accrued_int: DescriptorDouble,
+    yield_value: DescriptorDouble,
+    ts_commission: DescriptorDouble,
+    clearing_center_commission: DescriptorDouble,
+    exchange_commission: DescriptorDouble,
+    trading_system_commission: DescriptorDouble,
+    broker_commission: DescriptorDouble,
+    kind: DescriptorLong,
+    currency: DescriptorStr,
+    settle_currency: DescriptorStr,
+    settle_code: DescriptorStr,
+    account: DescriptorStr,
+    broker_ref: DescriptorStr,
+    client_code: DescriptorStr,
+    user_id: DescriptorStr,
+    firm_id: DescriptorStr,
+    exchange_code: DescriptorStr,
+}
+
+
+impl TradeGetters {
+    /// Loads the functions, `None` if the library has no trade descriptor functions.
+    fn load(library: &Library) -> Option<Self> {
+        Some(TradeGetters {
+            date: load_optional(library, b"TRANS2QUIK_TRADE_DATE\0")?,
+            settle_date: load_optional(library, b"TRANS2QUIK_TRADE_SETTLE_DATE\0")?,
+            time: load_optional(library, b"TRANS2QUIK_TRADE_TIME\0")?,
+            is_marginal: load_optional(library, b"TRANS2QUIK_TRADE_IS_MARGINAL\0")?,
+            accrued_int: load_optional(library, b"TRANS2QUIK_TRADE_ACCRUED_INT\0")?,
+            yield_value: load_optional(library, b"TRANS2QUIK_TRADE_YIELD\0")?,
+            ts_commission: load_optional(library, b"TRANS2QUIK_TRADE_TS_COMMISSION\0")?,
+            clearing_center_commission: load_optional(library, b"TRANS2QUIK_TRADE_CLEARING_CENTER_COMMISSION\0")?,
+            exchange_commission: load_optional(library, b"TRANS2QUIK_TRADE_EXCHANGE_COMMISSION\0")?,
+            trading_system_commission: load_optional(library, b"TRANS2QUIK_TRADE_TRADING_SYSTEM_COMMISSION\0")?,
+            broker_commission: load_optional(library, b"TRANS2QUIK_TRADE_BROKER_COMMISSION\0")?,
+            kind: load_optional(library, b"TRANS2QUIK_TRADE_KIND\0")?,
+            currency: load_optional(library, b"TRANS2QUIK_TRADE_CURRENCY\0")?,
+            settle_currency: load_optional(library, b"TRANS2QUIK_TRADE_SETTLE_CURRENCY\0")?,
+            settle_code: load_optional(library, b"TRANS2QUIK_TRADE_SETTLE_CODE\0")?,
+            account: load_optional(library, b"TRANS2QUIK_TRADE_ACCOUNT\0")?,
+            broker_ref: load_optional(library, b"TRANS2QUIK_TRADE_BROKERREF\0")?,
+            client_code: load_optional(library, b"TRANS2QUIK_TRADE_CLIENT_CODE\0")?,
+            user_id: load_optional(library, b"TRANS2QUIK_TRADE_USERID\0")?,
+            firm_id: load_optional(library, b"TRANS2QUIK_TRADE_FIRMID\0")?,
+            exchange_code: load_optional(library, b"TRANS2QUIK_TRADE_EXCHANGE_CODE\0")?,
+        })
+    }
+}
+
+
+/// Everything the callback functions of a terminal need: the subscribers to its events
+/// and the descriptor getters of its library.
+struct CallbackContext {
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<QuikEvent>>>,
+    transreply_getters: Option<TransReplyGetters>,
+    order_getters: Option<OrderGetters>,
+    trade_getters: Option<TradeGetters>,
+}
+
+
+/// Optional features of the loaded version of the library Trans2QUIK.dll. The connection,
+/// the transactions and their replies are supported by every version, the other functions
+/// appeared later: their calls fail with `Trans2QuikError::Unsupported` and the missing
+/// fields of the events are left empty.
+///
+/// # Example of use
+/// ```no_run
+/// # use tracing::warn;
+/// # fn run(terminal: &quik_rs::Terminal) -> Result<(), Box<dyn std::error::Error>> {
+/// let capabilities = terminal.capabilities();
+/// if capabilities.orders && capabilities.trades {
+///     let subscription = terminal.subscribe("QJSIM", "SBER")?;
+/// } else {
+///     warn!("Trans2QUIK.dll doesn't deliver orders and trades, missing: {:?}", capabilities.missing());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// TRANS2QUIK_SUBSCRIBE_ORDERS, TRANS2QUIK_UNSUBSCRIBE_ORDERS and TRANS2QUIK_START_ORDERS.
+    pub orders: bool,
+
+    /// TRANS2QUIK_SUBSCRIBE_TRADES, TRANS2QUIK_UNSUBSCRIBE_TRADES and TRANS2QUIK_START_TRADES.
+    pub trades: bool,
+
+    /// TRANS2QUIK_TRANSREPLY_* functions reading the extended fields of a transaction reply.
+    pub transreply_descriptor: bool,
+
+    /// TRANS2QUIK_ORDER_* functions reading the extended fields of an order.
+    pub order_descriptor: bool,
+
+    /// TRANS2QUIK_TRADE_* functions reading the extended fields of a trade.
+    pub trade_descriptor: bool,
+}
+
+
+impl Capabilities {
+    /// Every feature is supported, e.g. by `MockTerminal`.
+    pub fn all() -> Self {
+        Capabilities {
+            orders: true,
+            trades: true,
+            transreply_descriptor: true,
+            order_descriptor: true,
+            trade_descriptor: true,
+        }
+    }
+
+
+    /// Names of the unsupported features.
+    pub fn missing(&self) -> Vec<&'static str> {
+        [
+            (self.orders, "orders"),
+            (self.trades, "trades"),
+            (self.transreply_descriptor, "transreply_descriptor"),
+            (self.order_descriptor, "order_descriptor"),
+            (self.trade_descriptor, "trade_descriptor"),
+        ]
+        .iter()
+        .filter(|(supported, _)| !supported)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+
+/// Statistics of the calls of one function of the library Trans2QUIK.dll.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionStats {
+    pub calls: u64,
+
+    /// Number of calls by the returned result code.
+    pub results: HashMap<Trans2quikResult, u64>,
+
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+
+impl FunctionStats {
+    pub fn average_latency(&self) -> Duration {
+        if self.calls == 0 {
+            return Duration::ZERO;
+        }
+        self.total_latency / self.calls as u32
+    }
+
+
+    /// Number of calls which returned a failure code.
+    pub fn failures(&self) -> u64 {
+        self.results
+            .iter()
+            .filter(|(result, _)| result.is_failure())
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+
+    fn record(&mut self, result: Trans2quikResult, latency: Duration) {
+        self.calls += 1;
+        *self.results.entry(result).or_default() += 1;
+        self.total_latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+}
+
+
+/// Counters of the calls of the library functions by function name: the number of calls,
+/// the result codes and the latency, so a degradation on the terminal side (e.g. a rising
+/// number of `WrongConnectionHandle` or slow synchronous transactions) can be spotted.
+///
+/// # Example of use
+/// ```no_run
+/// # use quik_rs::QuikApi;
+/// # use tracing::info;
+/// # fn run(terminal: &quik_rs::Terminal) {
+/// // Statistics since the previous call, the counters are reset
+/// for (function, stats) in terminal.call_stats().take() {
+///     info!("{}: {} calls, {} failures, average {:?}", function, stats.calls, stats.failures(), stats.average_latency());
+/// }
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct CallStats {
+    functions: Mutex<HashMap<&'static str, FunctionStats>>,
+}
+
+
+impl CallStats {
+    pub fn record(&self, function: &'static str, result: Trans2quikResult, latency: Duration) {
+        self.lock().entry(function).or_default().record(result, latency);
+    }
+
+
+    /// Statistics since the start or the last `take`.
+    pub fn snapshot(&self) -> BTreeMap<&'static str, FunctionStats> {
+        self.lock().iter().map(|(function, stats)| (*function, stats.clone())).collect()
+    }
+
+
+    /// Statistics since the start or the last `take`, the counters are reset.
+    pub fn take(&self) -> BTreeMap<&'static str, FunctionStats> {
+        self.lock().drain().collect()
+    }
+
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<&'static str, FunctionStats>> {
+        self.functions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+
 /// The `Terminal` structure is used to interact with the QUIK trading terminal through the library `Trans2QUIK.dll`.
 ///
 /// This structure provides loading of the DLL library `Trans2QUIK.dll `, establishing a connection to the QUIK terminal
 /// and calling functions from the library to control the terminal and perform trading operations.
 ///
 /// # Example of use
-/// ```
-/// let path = r"c:\QUIK Junior\trans2quik.dll";
-/// let terminal = quik::Terminal::new(path)?;
+/// ```no_run
+/// # use quik_rs::{quik, QuikApi};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let path_to_lib = r"c:\QUIK Junior\trans2quik.dll";
+/// let path_to_quik = r"c:\QUIK Junior";
+/// let terminal = quik::Terminal::new(path_to_lib, path_to_quik)?;
 /// terminal.connect()?;
+/// # Ok(())
+/// # }
 /// ```
+///
+/// Up to `MAX_TERMINALS` terminals can be driven from one process. Each of them has to load
+/// its own copy of the library, since the library keeps a single connection per loaded module.
 pub struct Terminal {
     /// Loading a dynamic library `Trans2QUIK.dll `, which provides an API for interacting with QUIK.
+    /// Only held to keep the loaded functions valid.
+    #[allow(dead_code)]
     library: Library,
 
+    /// Path to the directory of the QUIK terminal used by `connect`.
+    path_to_quik: String,
+
+    /// Slot of the terminal in `CALLBACK_CONTEXTS`.
+    slot: usize,
+
+    /// Context of the callback functions of the terminal.
+    context: Arc<CallbackContext>,
+
+    /// Set by `shutdown`, so the terminal is shut down only once.
+    shut_down: AtomicBool,
+
+    /// Optional features of the loaded version of the library.
+    capabilities: Capabilities,
+
+    /// Statistics of the calls of the library functions.
+    call_stats: CallStats,
+
     /// Calling a function from the library Trans2QUIK.dll for establishing communication with the QUIK terminal.
     trans2quik_connect: unsafe extern "C" fn(*const c_char, *mut c_long, *mut c_char, c_ulong) -> c_long,
 
@@ -106,12 +857,43 @@ pub struct Terminal {
 
     /// Calling a function from the library Trans2QUIK.dll to check if there is a connection between the library Trans2QUIK.dll and the QUIK terminal.
     trans2quik_is_dll_connected: unsafe extern "C" fn(*mut c_long, *mut c_char, c_ulong) -> c_long,
+
+    /// Calling a function from the library Trans2QUIK.dll to set the callback function for connection events.
+    trans2quik_set_connection_status_callback: unsafe extern "C" fn(ConnectionStatusCallback, *mut c_long, *mut c_char, c_ulong) -> c_long,
+
+    /// Calling a function from the library Trans2QUIK.dll to set the callback function for transaction replies.
+    trans2quik_set_transactions_reply_callback: unsafe extern "C" fn(TransactionReplyCallback, *mut c_long, *mut c_char, c_ulong) -> c_long,
+
+    /// Calling a function from the library Trans2QUIK.dll to subscribe to orders by class and securities codes.
+    trans2quik_subscribe_orders: Option<unsafe extern "C" fn(*const c_char, *const c_char) -> c_long>,
+
+    /// Calling a function from the library Trans2QUIK.dll to cancel the subscription to orders.
+    trans2quik_unsubscribe_orders: Option<unsafe extern "C" fn() -> c_long>,
+
+    /// Calling a function from the library Trans2QUIK.dll to start receiving orders by the callback function.
+    trans2quik_start_orders: Option<unsafe extern "C" fn(OrderStatusCallback) -> c_long>,
+
+    /// Calling a function from the library Trans2QUIK.dll to subscribe to trades by class and securities codes.
+    trans2quik_subscribe_trades: Option<unsafe extern "C" fn(*const c_char, *const c_char) -> c_long>,
+
+    /// Calling a function from the library Trans2QUIK.dll to cancel the subscription to trades.
+    trans2quik_unsubscribe_trades: Option<unsafe extern "C" fn() -> c_long>,
+
+    /// Calling a function from the library Trans2QUIK.dll to start receiving trades by the callback function.
+    trans2quik_start_trades: Option<unsafe extern "C" fn(TradeStatusCallback) -> c_long>,
+
+    /// Calling a function from the library Trans2QUIK.dll to send a transaction synchronously.
+    trans2quik_send_sync_transaction: unsafe extern "C" fn(*const c_char, *mut c_long, *mut c_ulong, *mut u64, *mut c_char, c_ulong, *mut c_long, *mut c_char, c_ulong) -> c_long,
+
+    /// Calling a function from the library Trans2QUIK.dll to send a transaction asynchronously.
+    trans2quik_send_async_transaction: unsafe extern "C" fn(*const c_char, *mut c_long, *mut c_char, c_ulong) -> c_long,
 }
 
 
 impl Terminal {
-    /// The function is used to load the library Trans2QUIK.dll.
-    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    /// The function is used to load the library Trans2QUIK.dll. `path_to_quik` is the directory
+    /// of the QUIK terminal which `connect` connects to.
+    pub fn new(path: &str, path_to_quik: &str) -> Result<Self, Box<dyn std::error::Error>> {
         // Loading a dynamic library `Trans2QUIK.dll `, which provides an API for interacting with QUIK.
         let library = unsafe {
             Library::new(path).map_err(|e| { error!("Trans2QUIK.dll loading error: {:?}", e); e})?
@@ -141,25 +923,102 @@ impl Terminal {
             *symbol
         };
         
+        // Calling a function from the library Trans2QUIK.dll to set the callback function for connection events.
+        let trans2quik_set_connection_status_callback = load_symbol(&library, b"TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK\0")?;
+
+        // Calling a function from the library Trans2QUIK.dll to set the callback function for transaction replies.
+        let trans2quik_set_transactions_reply_callback = load_symbol(&library, b"TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK\0")?;
+
+        // Functions for reading the transaction reply descriptor, missing in old versions of the library.
+        let transreply_getters = TransReplyGetters::load(&library);
+
+        // Calling functions from the library Trans2QUIK.dll to subscribe to orders and receive them, missing in old versions of the library.
+        let trans2quik_subscribe_orders = load_optional(&library, b"TRANS2QUIK_SUBSCRIBE_ORDERS\0");
+        let trans2quik_unsubscribe_orders = load_optional(&library, b"TRANS2QUIK_UNSUBSCRIBE_ORDERS\0");
+        let trans2quik_start_orders = load_optional(&library, b"TRANS2QUIK_START_ORDERS\0");
+
+        // Functions for reading the order descriptor.
+        let order_getters = OrderGetters::load(&library);
+
+        // Calling functions from the library Trans2QUIK.dll to subscribe to trades and receive them, missing in old versions of the library.
+        let trans2quik_subscribe_trades = load_optional(&library, b"TRANS2QUIK_SUBSCRIBE_TRADES\0");
+        let trans2quik_unsubscribe_trades = load_optional(&library, b"TRANS2QUIK_UNSUBSCRIBE_TRADES\0");
+        let trans2quik_start_trades = load_optional(&library, b"TRANS2QUIK_START_TRADES\0");
+
+        // Calling a function from the library Trans2QUIK.dll to send a transaction synchronously.
+        let trans2quik_send_sync_transaction = load_symbol(&library, b"TRANS2QUIK_SEND_SYNC_TRANSACTION\0")?;
+
+        // Calling a function from the library Trans2QUIK.dll to send a transaction asynchronously.
+        let trans2quik_send_async_transaction = load_symbol(&library, b"TRANS2QUIK_SEND_ASYNC_TRANSACTION\0")?;
+
+        // Functions for reading the trade descriptor.
+        let trade_getters = TradeGetters::load(&library);
+
+        let capabilities = Capabilities {
+            orders: trans2quik_subscribe_orders.is_some() && trans2quik_unsubscribe_orders.is_some() && trans2quik_start_orders.is_some(),
+            trades: trans2quik_subscribe_trades.is_some() && trans2quik_unsubscribe_trades.is_some() && trans2quik_start_trades.is_some(),
+            transreply_descriptor: transreply_getters.is_some(),
+            order_descriptor: order_getters.is_some(),
+            trade_descriptor: trade_getters.is_some(),
+        };
+        if capabilities.missing().is_empty() {
+            info!("Trans2QUIK.dll supports all features");
+        } else {
+            warn!("Trans2QUIK.dll doesn't support: {}", capabilities.missing().join(", "));
+        }
+
+        // Register the context of the callback functions in a free slot
+        let context = Arc::new(CallbackContext {
+            subscribers: Mutex::new(Vec::new()),
+            transreply_getters,
+            order_getters,
+            trade_getters,
+        });
+        let slot = acquire_slot(context.clone())?;
+
         Ok(Terminal {
             library,
+            path_to_quik: path_to_quik.to_string(),
+            slot,
+            context,
+            shut_down: AtomicBool::new(false),
+            capabilities,
+            call_stats: CallStats::default(),
             trans2quik_connect,
             trans2quik_disconnect,
             trans2quik_is_quik_connected,
             trans2quik_is_dll_connected,
+            trans2quik_set_connection_status_callback,
+            trans2quik_set_transactions_reply_callback,
+            trans2quik_subscribe_orders,
+            trans2quik_unsubscribe_orders,
+            trans2quik_start_orders,
+            trans2quik_subscribe_trades,
+            trans2quik_unsubscribe_trades,
+            trans2quik_start_trades,
+            trans2quik_send_sync_transaction,
+            trans2quik_send_async_transaction,
         })
     }
 
 
-    /// The function is used to establish communication with the QUIK terminal.
-    pub fn connect(&self) -> Result<Trans2quikResult, Box<dyn std::error::Error>> {
+    /// The function is used to establish communication with the QUIK terminal
+    /// in the directory passed to `new`.
+    pub fn connect(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        self.connect_with_path(&self.path_to_quik)
+    }
+
+
+    /// The function is used to establish communication with the QUIK terminal in the directory `path_to_quik`.
+    pub fn connect_with_path(&self, path_to_quik: &str) -> Result<Trans2quikResult, Trans2QuikError> {
         // Prepare the parameters
-        let connection_string = CString::new(r"c:\QUIK Junior").expect("CString::new failed");
+        let connection_string = cstring(path_to_quik)?;
         let mut result_code: c_long = 0;
         let mut result_message = vec![0 as c_char; 256];
-        let mut result_message_len = result_message.len();
+        let result_message_len = result_message.len();
     
         // Call the function
+        let started = Instant::now();
         let function_result = unsafe {
             (self.trans2quik_connect)(
                 connection_string.as_ptr(),
@@ -170,29 +1029,29 @@ impl Terminal {
         };
     
         // Convert the result message
-        let result_message = unsafe {
-            CStr::from_ptr(result_message.as_ptr()).to_string_lossy().into_owned()
-        };
+        let result_message = unsafe { string_from_ptr(result_message.as_ptr()) };
     
         // Map the result_code to Trans2quikResult
         let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_CONNECT", trans2quik_result, started.elapsed());
     
         // Log the result
         info!("TRANS2QUIK_CONNECT -> {:?}: {}", trans2quik_result, result_message);
     
         // Return the result
-        Ok(trans2quik_result)
+        trans2quik_result.check("TRANS2QUIK_CONNECT", result_code, result_message)
     }
 
 
     /// The function is used to disconnect from the QUIK terminal.
-    pub fn disconnect(&self) -> Result<Trans2quikResult, Box<dyn std::error::Error>> {
+    pub fn disconnect(&self) -> Result<Trans2quikResult, Trans2QuikError> {
         // Prepare the parameters
         let mut result_code: c_long = 0;
         let mut result_message = vec![0 as c_char; 256];
-        let mut result_message_len = result_message.len();
+        let result_message_len = result_message.len();
     
         // Call the function
+        let started = Instant::now();
         let function_result = unsafe {
             (self.trans2quik_disconnect)(
                 &mut result_code as *mut c_long,
@@ -202,29 +1061,29 @@ impl Terminal {
         };
     
         // Convert the result message
-        let result_message = unsafe {
-            CStr::from_ptr(result_message.as_ptr()).to_string_lossy().into_owned()
-        };
+        let result_message = unsafe { string_from_ptr(result_message.as_ptr()) };
     
         // Map the result_code to Trans2quikResult
         let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_DISCONNECT", trans2quik_result, started.elapsed());
     
         // Log the result
         info!("TRANS2QUIK_DISCONNECT -> {:?}: {}", trans2quik_result, result_message);
     
         // Return the result
-        Ok(trans2quik_result)
+        trans2quik_result.check("TRANS2QUIK_DISCONNECT", result_code, result_message)
     }
 
 
     /// The function is used to check if there is a connection between the QUIK terminal and the server.
-    pub fn is_quik_connected(&self) -> Result<Trans2quikResult, Box<dyn std::error::Error>> {
+    pub fn is_quik_connected(&self) -> Result<Trans2quikResult, Trans2QuikError> {
         // Prepare the parameters
         let mut result_code: c_long = 0;
         let mut result_message = vec![0 as c_char; 256];
         let result_message_len = result_message.len();
     
         // Call the function
+        let started = Instant::now();
         let function_result = unsafe {
             (self.trans2quik_is_quik_connected)(
                 &mut result_code as *mut c_long,
@@ -234,29 +1093,29 @@ impl Terminal {
         };
     
         // Convert the result message
-        let result_message = unsafe {
-            CStr::from_ptr(result_message.as_ptr()).to_string_lossy().into_owned()
-        };
+        let result_message = unsafe { string_from_ptr(result_message.as_ptr()) };
     
         // Map the result_code to Trans2quikResult
         let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_IS_QUIK_CONNECTED", trans2quik_result, started.elapsed());
     
         // Log the result
         info!("TRANS2QUIK_IS_QUIK_CONNECTED -> {:?}: {}", trans2quik_result, result_message);
     
         // Return the result
-        Ok(trans2quik_result)
+        trans2quik_result.check("TRANS2QUIK_IS_QUIK_CONNECTED", result_code, result_message)
     }
 
 
     /// Checking for a connection between the library Trans2QUIK.dll and the QUIK terminal.
-    pub fn is_dll_connected(&self) -> Result<Trans2quikResult, Box<dyn std::error::Error>> {
+    pub fn is_dll_connected(&self) -> Result<Trans2quikResult, Trans2QuikError> {
         // Prepare the parameters
         let mut result_code: c_long = 0;
         let mut result_message = vec![0 as c_char; 256];
         let result_message_len = result_message.len();
     
         // Call the function
+        let started = Instant::now();
         let function_result = unsafe {
             (self.trans2quik_is_dll_connected)(
                 &mut result_code as *mut c_long,
@@ -266,95 +1125,1351 @@ impl Terminal {
         };
     
         // Convert the result message
-        let result_message = unsafe {
-            CStr::from_ptr(result_message.as_ptr()).to_string_lossy().into_owned()
-        };
+        let result_message = unsafe { string_from_ptr(result_message.as_ptr()) };
     
         // Map the result_code to Trans2quikResult
         let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_IS_DLL_CONNECTED", trans2quik_result, started.elapsed());
     
         // Log the result
-        info!("TRANS2QUIK_IS_QUIK_CONNECTED -> {:?}: {}", trans2quik_result, result_message);
+        info!("TRANS2QUIK_IS_DLL_CONNECTED -> {:?}: {}", trans2quik_result, result_message);
     
         // Return the result
-        Ok(trans2quik_result)
+        trans2quik_result.check("TRANS2QUIK_IS_DLL_CONNECTED", result_code, result_message)
     }
-}
 
 
-/// Описание прототипа Функции обратного вызова для контроля за состоянием соединения 
-/// между библиотекой Trans2QUIK.dll и используемым терминалом QUIK и между 
-/// используемым терминалом QUIK и сервером.
-pub fn connection_status_callback(lib: &Library) {
-    // Определяем тип функции
-    unsafe {
-        // Найдем функцию TRANS2QUIK_CONNECTION_STATUS_CALLBACK в библиотеке
-        let connection_status_callback: Symbol<unsafe extern "C" fn(*mut c_long, *mut c_char) -> c_long> = lib.get(b"TRANS2QUIK_CONNECTION_STATUS_CALLBACK\0").expect("Could not find function");
-        
-        // Вызываем функцию
+    /// The function is used to send a transaction synchronously. The function returns after the
+    /// transaction is executed by the server or the connection between the terminal and the server is lost.
+    pub fn send_sync_transaction(&self, transaction: &str) -> Result<SyncTransactionResult, Trans2QuikError> {
+        // Prepare the parameters
+        let transaction_string = cstring(transaction)?;
+        let mut reply_code: c_long = 0;
+        let mut trans_id: c_ulong = 0;
+        let mut order_num: u64 = 0;
+        let mut result_message = vec![0 as c_char; 256];
+        let result_message_len = result_message.len();
         let mut error_code: c_long = 0;
         let mut error_message = vec![0 as c_char; 256];
+        let error_message_len = error_message.len();
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe {
+            (self.trans2quik_send_sync_transaction)(
+                transaction_string.as_ptr(),
+                &mut reply_code as *mut c_long,
+                &mut trans_id as *mut c_ulong,
+                &mut order_num as *mut u64,
+                result_message.as_mut_ptr(),
+                result_message_len as c_ulong,
+                &mut error_code as *mut c_long,
+                error_message.as_mut_ptr(),
+                error_message_len as c_ulong,
+            )
+        };
+
+        // Convert the messages
+        let result_message = unsafe { string_from_ptr(result_message.as_ptr()) };
+        let error_message = unsafe { string_from_ptr(error_message.as_ptr()) };
 
-        let result = connection_status_callback(
-            &mut error_code as *mut c_long,
-            error_message.as_mut_ptr()
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_SEND_SYNC_TRANSACTION", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!(
+            "TRANS2QUIK_SEND_SYNC_TRANSACTION -> {:?}, reply code: {}, trans id: {}, order num: {}, result message: {}",
+            trans2quik_result, reply_code, trans_id, order_num, result_message
         );
 
-        let error_message = CStr::from_ptr(error_message.as_ptr()).to_string_lossy().into_owned();
-
-        match result {
-            8 => info!("TRANS2QUIK_QUIK_CONNECTED - соединение между терминалом QUIK и сервером установлено"),
-            9 => {
-                info!("TRANS2QUIK_QUIK_DISCONNECTED - соединение между терминалом QUIK и сервером разорвано");
-                info!("Error code: {}, error message: {}", error_code, error_message);
-            },
-            10 => info!(" TRANS2QUIK_DLL_CONNECTED - соединение между DLL и используемым терминалом QUIK установлено"),
-            11 => {
-                info!(" TRANS2QUIK_DLL_DISCONNECTED - соединение между DLL и используемым терминалом QUIK разорвано");
-                info!("Error code: {}, error message: {}", error_code, error_message);
-            }
-            _ => info!("Unknown result code"),
-        }
+        // Return the result
+        let result = trans2quik_result.check("TRANS2QUIK_SEND_SYNC_TRANSACTION", error_code, error_message.clone())?;
+        Ok(SyncTransactionResult {
+            result,
+            reply_code,
+            status: ReplyStatus::from(reply_code),
+            trans_id,
+            order_num,
+            result_message,
+            error_code,
+            error_message,
+        })
     }
-}
 
 
-/// Описание прототипа функции обратного вызова для обработки полученной информации о соединении
-pub fn set_connection_status_callback(lib: &Library) -> bool {
-    // Определяем тип функции
-    unsafe {
-        // Найдем функцию  TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK в библиотеке
-        let set_connection_status_callback: Symbol<unsafe extern "C" fn(Option<TRANS2QUIK_CONNECTION_STATUS_CALLBACK>, *mut c_long, *mut c_char, c_ulong) -> c_long> = lib.get(b"TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK\0").expect("Could not find function");
-        
-        // Вызываем функцию
+    /// The function is used to send a transaction asynchronously. The function returns as soon as
+    /// the transaction is passed to the terminal, the reply is delivered as `QuikEvent::TransactionReply`
+    /// after `set_transactions_reply_callback`.
+    pub fn send_async_transaction(&self, transaction: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Prepare the parameters
+        let transaction_string = cstring(transaction)?;
         let mut error_code: c_long = 0;
         let mut error_message = vec![0 as c_char; 256];
+        let error_message_len = error_message.len();
 
-        let result = set_connection_status_callback(
-            Some(connection_status_callback(&lib)),
-            &mut error_code as *mut c_long,
-            error_message.as_mut_ptr(),
-            error_message.len() as c_ulong
-        );
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe {
+            (self.trans2quik_send_async_transaction)(
+                transaction_string.as_ptr(),
+                &mut error_code as *mut c_long,
+                error_message.as_mut_ptr(),
+                error_message_len as c_ulong,
+            )
+        };
 
-        let error_message = CStr::from_ptr(error_message.as_ptr()).to_string_lossy().into_owned();
+        // Convert the error message
+        let error_message = unsafe { string_from_ptr(error_message.as_ptr()) };
 
-        match result {
-            0 => info!("TRANS2QUIK_SUCCESS - функция обратного вызова установлена"),
-            1 => {
-                info!("TRANS2QUIK_FAILED - функцию обратного вызова установить не удалось");
-                info!("Error code: {}, error message: {}", error_code, error_message);
-            },
-            _ => info!("Unknown result code"),
-        }
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_SEND_ASYNC_TRANSACTION", trans2quik_result, started.elapsed());
 
-        if result == 10
-        {
-            return true
+        // Log the result
+        info!("TRANS2QUIK_SEND_ASYNC_TRANSACTION -> {:?}, error code: {}, error message: {}", trans2quik_result, error_code, error_message);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_SEND_ASYNC_TRANSACTION", error_code, error_message)
+    }
+
+
+    /// Shuts the terminal down: closes the receivers of `events`, cancels the subscriptions
+    /// to orders and trades and disconnects from the QUIK terminal. All steps are attempted
+    /// even if one of them fails. Repeated calls do nothing, so the method can be called
+    /// on a shared `Arc<Terminal>` from the shutdown path, and `Drop` calls it as a fallback.
+    pub fn shutdown(&self) -> Result<(), Trans2QuikError> {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
         }
-        else
-        {
-            return false
+
+        // Stop delivering events, the receivers get `None`
+        self.context.lock_subscribers().clear();
+
+        // Nothing to release without a connection
+        if !matches!(self.is_dll_connected(), Ok(Trans2quikResult::DllConnected)) {
+            return Ok(());
         }
+
+        let unsubscribed = self.unsubscribe_orders().and(self.unsubscribe_trades());
+        self.disconnect()?;
+        // Nothing is subscribed to without the subscription functions
+        if !matches!(unsubscribed, Err(Trans2QuikError::Unsupported(_))) {
+            unsubscribed?;
+        }
+
+        info!("Terminal is shut down");
+        Ok(())
+    }
+
+
+    /// Subscribes to the orders and the trades of `sec_codes` of the class `class_code` and starts
+    /// receiving them. The subscriptions are cancelled when the returned guard is dropped.
+    pub fn subscribe(&self, class_code: &str, sec_codes: &str) -> Result<Subscription<'_>, Trans2QuikError> {
+        Subscription::new(self, class_code, sec_codes)
+    }
+
+
+    /// Optional features of the loaded version of the library.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+
+    /// Sends a set of transactions with distinct TRANS_IDs asynchronously and returns their
+    /// outcomes as the replies arrive, e.g. for rebalancing a basket of instruments.
+    pub fn send_batch(&self, transactions: Vec<Transaction>) -> Result<Batch, Trans2QuikError> {
+        Batch::send(self, transactions)
+    }
+
+
+    /// Returns a new receiver of the events of the terminal. Every receiver gets all events
+    /// which arrive after it was created, so the bot and the connection supervisor can
+    /// listen to the same terminal independently.
+    pub fn events(&self) -> mpsc::UnboundedReceiver<QuikEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.context.lock_subscribers().push(sender);
+        receiver
+    }
+
+
+    /// The function is used to set the callback function that receives the connection events.
+    /// The events are delivered as `QuikEvent::ConnectionStatus`.
+    pub fn set_connection_status_callback(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Prepare the parameters
+        let mut error_code: c_long = 0;
+        let mut error_message = vec![0 as c_char; 256];
+        let error_message_len = error_message.len();
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe {
+            (self.trans2quik_set_connection_status_callback)(
+                CONNECTION_STATUS_CALLBACKS[self.slot],
+                &mut error_code as *mut c_long,
+                error_message.as_mut_ptr(),
+                error_message_len as c_ulong,
+            )
+        };
+
+        // Convert the error message
+        let error_message = unsafe { string_from_ptr(error_message.as_ptr()) };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK -> {:?}, error code: {}, error message: {}", trans2quik_result, error_code, error_message);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK", error_code, error_message)
+    }
+
+
+    /// The function is used to set the callback function that receives replies to asynchronous transactions.
+    /// The replies are delivered as `QuikEvent::TransactionReply`.
+    pub fn set_transactions_reply_callback(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Prepare the parameters
+        let mut error_code: c_long = 0;
+        let mut error_message = vec![0 as c_char; 256];
+        let error_message_len = error_message.len();
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe {
+            (self.trans2quik_set_transactions_reply_callback)(
+                TRANSACTION_REPLY_CALLBACKS[self.slot],
+                &mut error_code as *mut c_long,
+                error_message.as_mut_ptr(),
+                error_message_len as c_ulong,
+            )
+        };
+
+        // Convert the error message
+        let error_message = unsafe { string_from_ptr(error_message.as_ptr()) };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK -> {:?}, error code: {}, error message: {}", trans2quik_result, error_code, error_message);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK", error_code, error_message)
+    }
+
+
+    /// The function is used to subscribe to orders. Empty `class_code` subscribes to all classes,
+    /// empty `sec_codes` subscribes to all securities of the class. Several securities codes
+    /// are separated with the `|` symbol, e.g. `SBER|GAZP`.
+    pub fn subscribe_orders(&self, class_code: &str, sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Prepare the parameters
+        let class_code = cstring(class_code)?;
+        let sec_codes = cstring(sec_codes)?;
+
+        // Old versions of the library have no such function
+        let function = supported(self.trans2quik_subscribe_orders, "TRANS2QUIK_SUBSCRIBE_ORDERS")?;
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe {
+            (function)(class_code.as_ptr(), sec_codes.as_ptr())
+        };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_SUBSCRIBE_ORDERS", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_SUBSCRIBE_ORDERS -> {:?}", trans2quik_result);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_SUBSCRIBE_ORDERS", 0, String::new())
+    }
+
+
+    /// The function is used to cancel the subscription to orders.
+    pub fn unsubscribe_orders(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Old versions of the library have no such function
+        let function = supported(self.trans2quik_unsubscribe_orders, "TRANS2QUIK_UNSUBSCRIBE_ORDERS")?;
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe { (function)() };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_UNSUBSCRIBE_ORDERS", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_UNSUBSCRIBE_ORDERS -> {:?}", trans2quik_result);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_UNSUBSCRIBE_ORDERS", 0, String::new())
     }
-}
\ No newline at end of file
+
+
+    /// The function is used to start receiving the subscribed orders. The orders are delivered
+    /// as `QuikEvent::OrderUpdate`. After a reconnection the orders have to be subscribed to
+    /// and started again.
+    pub fn start_orders(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Old versions of the library have no such function
+        let function = supported(self.trans2quik_start_orders, "TRANS2QUIK_START_ORDERS")?;
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe { (function)(ORDER_STATUS_CALLBACKS[self.slot]) };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_START_ORDERS", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_START_ORDERS -> {:?}", trans2quik_result);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_START_ORDERS", 0, String::new())
+    }
+
+
+    /// The function is used to subscribe to trades. The parameters are the same as in `subscribe_orders`.
+    pub fn subscribe_trades(&self, class_code: &str, sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Prepare the parameters
+        let class_code = cstring(class_code)?;
+        let sec_codes = cstring(sec_codes)?;
+
+        // Old versions of the library have no such function
+        let function = supported(self.trans2quik_subscribe_trades, "TRANS2QUIK_SUBSCRIBE_TRADES")?;
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe {
+            (function)(class_code.as_ptr(), sec_codes.as_ptr())
+        };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_SUBSCRIBE_TRADES", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_SUBSCRIBE_TRADES -> {:?}", trans2quik_result);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_SUBSCRIBE_TRADES", 0, String::new())
+    }
+
+
+    /// The function is used to cancel the subscription to trades.
+    pub fn unsubscribe_trades(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Old versions of the library have no such function
+        let function = supported(self.trans2quik_unsubscribe_trades, "TRANS2QUIK_UNSUBSCRIBE_TRADES")?;
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe { (function)() };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_UNSUBSCRIBE_TRADES", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_UNSUBSCRIBE_TRADES -> {:?}", trans2quik_result);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_UNSUBSCRIBE_TRADES", 0, String::new())
+    }
+
+
+    /// The function is used to start receiving the subscribed trades. The trades are delivered
+    /// as `QuikEvent::TradeUpdate`. After a reconnection the trades have to be subscribed to
+    /// and started again.
+    pub fn start_trades(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        // Old versions of the library have no such function
+        let function = supported(self.trans2quik_start_trades, "TRANS2QUIK_START_TRADES")?;
+
+        // Call the function
+        let started = Instant::now();
+        let function_result = unsafe { (function)(TRADE_STATUS_CALLBACKS[self.slot]) };
+
+        // Map the result_code to Trans2quikResult
+        let trans2quik_result = Trans2quikResult::from(function_result);
+        self.call_stats.record("TRANS2QUIK_START_TRADES", trans2quik_result, started.elapsed());
+
+        // Log the result
+        info!("TRANS2QUIK_START_TRADES -> {:?}", trans2quik_result);
+
+        // Return the result
+        trans2quik_result.check("TRANS2QUIK_START_TRADES", 0, String::new())
+    }
+}
+
+
+/// Operations of the QUIK terminal used by the bot. Implemented by `Terminal` and by
+/// `mock::MockTerminal`, which runs without Trans2QUIK.dll, e.g. on Linux, in backtests and in CI.
+pub trait QuikApi: Send + Sync {
+    fn connect(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn disconnect(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn is_quik_connected(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn is_dll_connected(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn events(&self) -> mpsc::UnboundedReceiver<QuikEvent>;
+    fn set_connection_status_callback(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn set_transactions_reply_callback(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn subscribe_orders(&self, class_code: &str, sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn unsubscribe_orders(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn start_orders(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn subscribe_trades(&self, class_code: &str, sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn unsubscribe_trades(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn start_trades(&self) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn send_sync_transaction(&self, transaction: &str) -> Result<SyncTransactionResult, Trans2QuikError>;
+    fn send_async_transaction(&self, transaction: &str) -> Result<Trans2quikResult, Trans2QuikError>;
+    fn shutdown(&self) -> Result<(), Trans2QuikError>;
+    fn call_stats(&self) -> &CallStats;
+    fn capabilities(&self) -> Capabilities;
+}
+
+
+impl dyn QuikApi {
+    /// Subscribes to orders and trades, see `Terminal::subscribe`.
+    pub fn subscribe(&self, class_code: &str, sec_codes: &str) -> Result<Subscription<'_>, Trans2QuikError> {
+        Subscription::new(self, class_code, sec_codes)
+    }
+
+
+    /// Sends a set of transactions, see `Terminal::send_batch`.
+    pub fn send_batch(&self, transactions: Vec<Transaction>) -> Result<Batch, Trans2QuikError> {
+        Batch::send(self, transactions)
+    }
+}
+
+
+/// Guard of the subscription to orders and trades returned by `Terminal::subscribe`.
+/// Dropping the guard cancels the subscriptions, so an early return can't leave
+/// orphaned subscriptions behind.
+///
+/// Trans2QUIK.dll cancels all subscriptions to orders and trades at once, so only one
+/// guard should be alive per terminal at a time.
+///
+/// # Example of use
+/// ```ignore
+/// let events = terminal.events();
+/// let subscription = terminal.subscribe("QJSIM", "SBER|GAZP")?;
+/// trade(&terminal, events)?;
+/// subscription.unsubscribe()?;
+/// ```
+pub struct Subscription<'a> {
+    terminal: &'a dyn QuikApi,
+    class_code: String,
+    sec_codes: String,
+    active: bool,
+}
+
+
+impl<'a> Subscription<'a> {
+    fn new(terminal: &'a dyn QuikApi, class_code: &str, sec_codes: &str) -> Result<Self, Trans2QuikError> {
+        // The guard exists before the first call, so a failed step cancels the previous ones
+        let subscription = Subscription {
+            terminal,
+            class_code: class_code.to_string(),
+            sec_codes: sec_codes.to_string(),
+            active: true,
+        };
+
+        terminal.subscribe_orders(class_code, sec_codes)?;
+        terminal.subscribe_trades(class_code, sec_codes)?;
+        terminal.start_orders()?;
+        terminal.start_trades()?;
+
+        Ok(subscription)
+    }
+
+
+    pub fn class_code(&self) -> &str {
+        &self.class_code
+    }
+
+
+    pub fn sec_codes(&self) -> &str {
+        &self.sec_codes
+    }
+
+
+    /// Cancels the subscriptions and returns the error, which `Drop` can only log.
+    pub fn unsubscribe(mut self) -> Result<(), Trans2QuikError> {
+        self.cancel()
+    }
+
+
+    fn cancel(&mut self) -> Result<(), Trans2QuikError> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+
+        let orders = self.terminal.unsubscribe_orders();
+        self.terminal.unsubscribe_trades()?;
+        orders?;
+
+        info!("Subscription to {} {} is cancelled", self.class_code, self.sec_codes);
+        Ok(())
+    }
+}
+
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.cancel() {
+            warn!("Error cancelling the subscription to {} {}: {}", self.class_code, self.sec_codes, e);
+        }
+    }
+}
+
+
+/// Outcome of a transaction of a batch.
+#[derive(Debug, Clone)]
+pub enum BatchOutcome {
+    /// The final reply of the transaction, executed or rejected.
+    Replied(Box<TransactionReply>),
+    /// The transaction could not be sent.
+    Failed { trans_id: c_ulong, error: Trans2QuikError },
+}
+
+
+impl BatchOutcome {
+    pub fn trans_id(&self) -> c_ulong {
+        match self {
+            BatchOutcome::Replied(reply) => reply.trans_id,
+            BatchOutcome::Failed { trans_id, .. } => *trans_id,
+        }
+    }
+
+
+    pub fn is_executed(&self) -> bool {
+        matches!(self, BatchOutcome::Replied(reply) if reply.status.is_executed())
+    }
+}
+
+
+/// Outcomes of all transactions of a batch returned by `Batch::wait`.
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    pub executed: Vec<TransactionReply>,
+    /// Transactions replied with any status except executed.
+    pub rejected: Vec<TransactionReply>,
+    pub failed: Vec<(c_ulong, Trans2QuikError)>,
+    /// Transactions without a final reply when the events of the terminal were closed.
+    pub unanswered: Vec<c_ulong>,
+}
+
+
+impl BatchReport {
+    pub fn is_success(&self) -> bool {
+        self.rejected.is_empty() && self.failed.is_empty() && self.unanswered.is_empty()
+    }
+}
+
+
+/// Transactions sent by `Terminal::send_batch`, yields the outcome of every transaction
+/// in the order of the replies.
+///
+/// # Example of use
+/// ```ignore
+/// let transactions = basket
+///     .iter()
+///     .map(|(sec_code, side, quantity)| Ok(Transaction::new_order("TQBR", sec_code, *side, *quantity, None).with_trans_id(allocator.next_id()?)))
+///     .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+///
+/// let mut batch = terminal.send_batch(transactions)?;
+/// while let Some(outcome) = batch.next().await {
+///     info!("{} executed: {}", outcome.trans_id(), outcome.is_executed());
+/// }
+///
+/// // Or all at once
+/// let report = terminal.send_batch(transactions)?.wait().await;
+/// if !report.is_success() {
+///     warn!("Rebalancing is incomplete: {:?}", report);
+/// }
+/// ```
+pub struct Batch {
+    events: mpsc::UnboundedReceiver<QuikEvent>,
+    /// Outcomes known at the sending, the failed transactions.
+    ready: VecDeque<BatchOutcome>,
+    /// Sent transactions without a final reply.
+    pending: HashSet<c_ulong>,
+}
+
+
+impl Batch {
+    /// Sends the transactions asynchronously. Every transaction must have its own TRANS_ID,
+    /// otherwise nothing is sent.
+    fn send(terminal: &dyn QuikApi, transactions: Vec<Transaction>) -> Result<Self, Trans2QuikError> {
+        let mut trans_ids = HashSet::new();
+        for transaction in &transactions {
+            if transaction.trans_id == 0 {
+                return Err(Trans2QuikError::InvalidParameter("a transaction of the batch has no TRANS_ID".to_string()));
+            }
+            if !trans_ids.insert(transaction.trans_id) {
+                return Err(Trans2QuikError::InvalidParameter(format!("TRANS_ID {} is repeated in the batch", transaction.trans_id)));
+            }
+        }
+
+        // Subscribe before sending, so no reply is missed
+        let mut batch = Batch {
+            events: terminal.events(),
+            ready: VecDeque::new(),
+            pending: HashSet::new(),
+        };
+
+        for transaction in transactions {
+            let trans_id = transaction.trans_id;
+            match terminal.send_async_transaction(&transaction.to_string()) {
+                Ok(_) => {
+                    batch.pending.insert(trans_id);
+                }
+                Err(error) => {
+                    warn!("Transaction {} of the batch is not sent: {}", trans_id, error);
+                    batch.ready.push_back(BatchOutcome::Failed { trans_id, error });
+                }
+            }
+        }
+
+        Ok(batch)
+    }
+
+
+    /// Transactions without an outcome yet.
+    pub fn pending(&self) -> usize {
+        self.ready.len() + self.pending.len()
+    }
+
+
+    /// Waits for the next outcome, `None` when all outcomes are returned or the events
+    /// of the terminal are closed.
+    pub async fn next(&mut self) -> Option<BatchOutcome> {
+        if let Some(outcome) = self.ready.pop_front() {
+            return Some(outcome);
+        }
+
+        while !self.pending.is_empty() {
+            let QuikEvent::TransactionReply(reply) = self.events.recv().await? else {
+                continue;
+            };
+            if reply.status.is_final() && self.pending.remove(&reply.trans_id) {
+                return Some(BatchOutcome::Replied(Box::new(reply)));
+            }
+        }
+
+        None
+    }
+
+
+    /// Waits for the outcomes of all transactions.
+    pub async fn wait(mut self) -> BatchReport {
+        let mut report = BatchReport::default();
+
+        while let Some(outcome) = self.next().await {
+            match outcome {
+                BatchOutcome::Replied(reply) if reply.status.is_executed() => report.executed.push(*reply),
+                BatchOutcome::Replied(reply) => report.rejected.push(*reply),
+                BatchOutcome::Failed { trans_id, error } => report.failed.push((trans_id, error)),
+            }
+        }
+        report.unanswered = self.pending.into_iter().collect();
+        report.unanswered.sort();
+
+        report
+    }
+}
+
+
+impl QuikApi for Terminal {
+    fn connect(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::connect(self)
+    }
+
+
+    fn disconnect(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::disconnect(self)
+    }
+
+
+    fn is_quik_connected(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::is_quik_connected(self)
+    }
+
+
+    fn is_dll_connected(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::is_dll_connected(self)
+    }
+
+
+    fn events(&self) -> mpsc::UnboundedReceiver<QuikEvent> {
+        Terminal::events(self)
+    }
+
+
+    fn set_connection_status_callback(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::set_connection_status_callback(self)
+    }
+
+
+    fn set_transactions_reply_callback(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::set_transactions_reply_callback(self)
+    }
+
+
+    fn subscribe_orders(&self, class_code: &str, sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::subscribe_orders(self, class_code, sec_codes)
+    }
+
+
+    fn unsubscribe_orders(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::unsubscribe_orders(self)
+    }
+
+
+    fn start_orders(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::start_orders(self)
+    }
+
+
+    fn subscribe_trades(&self, class_code: &str, sec_codes: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::subscribe_trades(self, class_code, sec_codes)
+    }
+
+
+    fn unsubscribe_trades(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::unsubscribe_trades(self)
+    }
+
+
+    fn start_trades(&self) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::start_trades(self)
+    }
+
+
+    fn send_sync_transaction(&self, transaction: &str) -> Result<SyncTransactionResult, Trans2QuikError> {
+        Terminal::send_sync_transaction(self, transaction)
+    }
+
+
+    fn send_async_transaction(&self, transaction: &str) -> Result<Trans2quikResult, Trans2QuikError> {
+        Terminal::send_async_transaction(self, transaction)
+    }
+
+
+    fn shutdown(&self) -> Result<(), Trans2QuikError> {
+        Terminal::shutdown(self)
+    }
+
+
+    fn call_stats(&self) -> &CallStats {
+        &self.call_stats
+    }
+
+
+    fn capabilities(&self) -> Capabilities {
+        Terminal::capabilities(self)
+    }
+}
+
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Release the connection before the library is unloaded, also when unwinding after a panic
+        if let Err(e) = self.shutdown() {
+            error!("Error shutting down the terminal: {}", e);
+        }
+
+        // Free the slot, so the callback functions of the slot stop delivering events
+        let mut context = CALLBACK_CONTEXTS[self.slot].write().unwrap_or_else(|e| e.into_inner());
+        *context = None;
+    }
+}
+
+
+/// Allocator of unique TRANS_ID values which survives restarts.
+///
+/// The IDs are reserved in blocks of `TRANS_ID_BLOCK`, and the end of the reserved block is
+/// persisted to a file before any ID of the block is issued. After a restart the allocation
+/// continues after the persisted block, so an ID is never reused, at the cost of skipping
+/// the unused rest of the block.
+///
+/// # Example of use
+/// ```ignore
+/// let mut allocator = TransIdAllocator::open("trans_id.txt")?;
+/// let trans_id = allocator.next_id()?;
+/// tracker.register(trans_id, signal);
+/// terminal.send_sync_transaction(&format!("TRANS_ID={}; ...", trans_id))?;
+/// ```
+#[derive(Debug)]
+pub struct TransIdAllocator {
+    path: String,
+    last: c_ulong,
+    reserved: c_ulong,
+}
+
+
+/// Number of IDs reserved by one write of the allocator file.
+const TRANS_ID_BLOCK: c_ulong = 100;
+
+/// Maximum TRANS_ID accepted by QUIK.
+const MAX_TRANS_ID: c_ulong = i32::MAX as c_ulong;
+
+
+impl TransIdAllocator {
+    /// Opens the allocator persisted in `path`. A missing file starts the allocation from 1.
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let last = match std::fs::read_to_string(path) {
+            Ok(content) => content.trim().parse().map_err(|e| format!("invalid TRANS_ID in {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                error!("Error reading TRANS_ID file {}: {}", path, e);
+                return Err(e.into());
+            }
+        };
+
+        Ok(TransIdAllocator {
+            path: path.to_string(),
+            last,
+            reserved: last,
+        })
+    }
+
+
+    /// Returns the next TRANS_ID.
+    pub fn next_id(&mut self) -> Result<c_ulong, Box<dyn std::error::Error>> {
+        if self.last >= MAX_TRANS_ID {
+            return Err("TRANS_ID range is exhausted".into());
+        }
+
+        if self.last == self.reserved {
+            let reserved = (self.reserved + TRANS_ID_BLOCK).min(MAX_TRANS_ID);
+            std::fs::write(&self.path, reserved.to_string()).map_err(|e| {
+                error!("Error writing TRANS_ID file {}: {}", self.path, e);
+                e
+            })?;
+            self.reserved = reserved;
+        }
+
+        self.last += 1;
+        Ok(self.last)
+    }
+}
+
+
+/// Correlation of transactions, orders and trades with the signals which caused them.
+///
+/// A signal is registered with the TRANS_ID of its transaction. The order numbers are
+/// learned from the transaction replies and the order callbacks, so the trades, which
+/// only carry the order number, are attributed to the signal as well.
+#[derive(Debug, Clone)]
+pub struct CorrelationTracker<S> {
+    signals: HashMap<c_ulong, S>,
+    orders: HashMap<u64, c_ulong>,
+}
+
+
+impl<S> Default for CorrelationTracker<S> {
+    fn default() -> Self {
+        CorrelationTracker {
+            signals: HashMap::new(),
+            orders: HashMap::new(),
+        }
+    }
+}
+
+
+impl<S> CorrelationTracker<S> {
+    pub fn register(&mut self, trans_id: c_ulong, signal: S) {
+        self.signals.insert(trans_id, signal);
+    }
+
+
+    /// Links the order numbers of the event to their TRANS_ID.
+    pub fn on_event(&mut self, event: &QuikEvent) {
+        let (trans_id, order_num) = match event {
+            QuikEvent::TransactionReply(reply) => (reply.trans_id, reply.order_num),
+            QuikEvent::OrderUpdate(order) => (order.trans_id, order.order_num),
+            _ => return,
+        };
+
+        if order_num != 0 && self.signals.contains_key(&trans_id) {
+            self.orders.insert(order_num, trans_id);
+        }
+    }
+
+
+    pub fn signal_by_trans_id(&self, trans_id: c_ulong) -> Option<&S> {
+        self.signals.get(&trans_id)
+    }
+
+
+    pub fn signal_by_order(&self, order_num: u64) -> Option<&S> {
+        self.signal_by_trans_id(*self.orders.get(&order_num)?)
+    }
+
+
+    /// Returns the signal which caused the event, if it is known.
+    pub fn attribute(&self, event: &QuikEvent) -> Option<&S> {
+        match event {
+            QuikEvent::TransactionReply(reply) => self.signal_by_trans_id(reply.trans_id),
+            QuikEvent::OrderUpdate(order) => self.signal_by_trans_id(order.trans_id),
+            QuikEvent::TradeUpdate(trade) => self.signal_by_order(trade.order_num),
+            QuikEvent::ConnectionStatus(_) => None,
+        }
+    }
+}
+
+
+/// Health of the connection to the QUIK terminal, published by `monitor_health`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionHealth {
+    /// The library Trans2QUIK.dll is connected to the QUIK terminal.
+    pub dll_connected: bool,
+
+    /// The QUIK terminal is connected to the server.
+    pub quik_connected: bool,
+
+    /// Round-trip time of the last `is_quik_connected` call.
+    pub latency: Option<Duration>,
+
+    /// Number of losses of the connection since the start of the monitor.
+    pub disconnects: u64,
+
+    /// Time of the last check.
+    pub checked_at: Option<DateTime<Utc>>,
+
+    /// Error of the last check, if it failed.
+    pub last_error: Option<String>,
+}
+
+
+impl ConnectionHealth {
+    pub fn is_connected(&self) -> bool {
+        self.dll_connected && self.quik_connected
+    }
+}
+
+
+/// Starts a heartbeat task which calls `is_quik_connected` every `interval`, records its
+/// round-trip latency, counts the disconnect events and publishes the `ConnectionHealth`,
+/// so the user interface can display the live connection state.
+///
+/// # Example of use
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # use quik_rs::clock::SystemClock;
+/// # use quik_rs::{quik, QuikApi};
+/// # use tracing::info;
+/// # async fn run(terminal: Arc<dyn QuikApi>) {
+/// let mut health = quik::monitor_health(terminal.clone(), Duration::from_secs(5), Arc::new(SystemClock));
+/// while health.changed().await.is_ok() {
+///     let health = health.borrow().clone();
+///     info!("QUIK connected: {}, latency: {:?}, disconnects: {}", health.is_connected(), health.latency, health.disconnects);
+/// }
+/// # }
+/// ```
+pub fn monitor_health(terminal: Arc<dyn QuikApi>, interval: Duration, clock: Arc<dyn Clock>) -> watch::Receiver<ConnectionHealth> {
+    let (sender, receiver) = watch::channel(ConnectionHealth::default());
+    let events = terminal.events();
+    tokio::spawn(run_health_monitor(terminal, events, interval, clock, sender));
+    receiver
+}
+
+
+async fn run_health_monitor(
+    terminal: Arc<dyn QuikApi>,
+    mut events: mpsc::UnboundedReceiver<QuikEvent>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    sender: watch::Sender<ConnectionHealth>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        let mut health = sender.borrow().clone();
+        let was_connected = health.is_connected();
+
+        tokio::select! {
+            _ = ticker.tick() => {
+                let started = Instant::now();
+                let result = terminal.is_quik_connected();
+                health.latency = Some(started.elapsed());
+                health.checked_at = Some(clock.now());
+
+                match result {
+                    Ok(result) => {
+                        health.dll_connected = true;
+                        health.quik_connected = result == Trans2quikResult::QuikConnected;
+                        health.last_error = None;
+                    }
+                    Err(e) => {
+                        health.dll_connected = !matches!(e.result(), Some(Trans2quikResult::DllNotConnected));
+                        health.quik_connected = false;
+                        health.last_error = Some(e.to_string());
+                    }
+                }
+            }
+            event = events.recv() => {
+                let Some(event) = event else {
+                    // The terminal is shut down
+                    return;
+                };
+                let QuikEvent::ConnectionStatus(event) = event else {
+                    continue;
+                };
+
+                match event.status {
+                    Trans2quikResult::DllConnected => health.dll_connected = true,
+                    Trans2quikResult::DllDisconnected => {
+                        health.dll_connected = false;
+                        health.quik_connected = false;
+                    }
+                    Trans2quikResult::QuikConnected => health.quik_connected = true,
+                    Trans2quikResult::QuikDisconnected => health.quik_connected = false,
+                    _ => continue,
+                }
+            }
+        }
+
+        if was_connected && !health.is_connected() {
+            health.disconnects += 1;
+            warn!("Connection to QUIK is lost, disconnects: {}", health.disconnects);
+        }
+
+        if sender.send(health).is_err() {
+            // All receivers are dropped
+            return;
+        }
+    }
+}
+
+
+impl CallbackContext {
+    fn lock_subscribers(&self) -> MutexGuard<'_, Vec<mpsc::UnboundedSender<QuikEvent>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+
+    /// Sends the event to every subscriber. Subscribers whose receivers were dropped are removed.
+    fn dispatch(&self, event: QuikEvent) {
+        self.lock_subscribers().retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+
+/// Stores the context in a free slot of `CALLBACK_CONTEXTS` and returns the slot.
+fn acquire_slot(context: Arc<CallbackContext>) -> Result<usize, Box<dyn std::error::Error>> {
+    for (slot, entry) in CALLBACK_CONTEXTS.iter().enumerate() {
+        let mut entry = entry.write().unwrap_or_else(|e| e.into_inner());
+        if entry.is_none() {
+            *entry = Some(context);
+            return Ok(slot);
+        }
+    }
+
+    error!("All {} terminal slots are in use", MAX_TERMINALS);
+    Err(format!("no more than {} terminals can be loaded in one process", MAX_TERMINALS).into())
+}
+
+
+/// Returns the context of the terminal registered in the slot.
+fn callback_context(slot: usize) -> Option<Arc<CallbackContext>> {
+    CALLBACK_CONTEXTS[slot].read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+
+/// Loads a function from the library Trans2QUIK.dll by its null-terminated name.
+fn load_symbol<T: Copy>(library: &Library, name: &[u8]) -> Result<T, libloading::Error> {
+    unsafe {
+        let symbol: Symbol<T> = library.get(name).map_err(|e| {
+            error!("{} error: {}", String::from_utf8_lossy(&name[..name.len() - 1]), e);
+            e
+        })?;
+        Ok(*symbol)
+    }
+}
+
+
+/// Loads a function which is missing in some versions of the library.
+fn load_optional<T: Copy>(library: &Library, name: &[u8]) -> Option<T> {
+    unsafe {
+        let symbol: Symbol<T> = library.get(name).ok()?;
+        Some(*symbol)
+    }
+}
+
+
+/// Returns the function if the loaded version of the library has it.
+fn supported<T>(function: Option<T>, name: &'static str) -> Result<T, Trans2QuikError> {
+    function.ok_or(Trans2QuikError::Unsupported(name))
+}
+
+
+/// Converts a null-terminated Windows-1251 string returned by the library Trans2QUIK.dll.
+/// A null pointer is converted to an empty string.
+unsafe fn string_from_ptr(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        cp1251::decode(CStr::from_ptr(ptr).to_bytes())
+    }
+}
+
+
+/// Converts a string passed to the library Trans2QUIK.dll to a null-terminated Windows-1251 string.
+fn cstring(value: &str) -> Result<CString, Trans2QuikError> {
+    let bytes = cp1251::encode(value).map_err(|e| Trans2QuikError::InvalidParameter(e.to_string()))?;
+    CString::new(bytes).map_err(|e| Trans2QuikError::InvalidParameter(format!("{}: {}", e, value)))
+}
+
+
+/// Callback function TRANS2QUIK_CONNECTION_STATUS_CALLBACK. Sends the connection event
+/// to the subscribers of the terminal.
+extern "C" fn connection_status_callback<const SLOT: usize>(connection_event: c_long, error_code: c_long, info_message: *const c_char) {
+    let Some(context) = callback_context(SLOT) else {
+        error!("TRANS2QUIK_CONNECTION_STATUS_CALLBACK: no terminal in slot {}", SLOT);
+        return;
+    };
+
+    let event = ConnectionEvent {
+        status: Trans2quikResult::from(connection_event),
+        error_code,
+        info_message: unsafe { string_from_ptr(info_message) },
+    };
+
+    info!("TRANS2QUIK_CONNECTION_STATUS_CALLBACK -> {:?}", event);
+
+    context.dispatch(QuikEvent::ConnectionStatus(event));
+}
+
+
+/// Callback function TRANS2QUIK_TRANSACTION_REPLY_CALLBACK. Reads the transaction reply
+/// descriptor and sends the typed reply to the subscribers of the terminal.
+extern "C" fn transaction_reply_callback<const SLOT: usize>(
+    trans2quik_result: c_long,
+    error_code: c_long,
+    reply_code: c_long,
+    trans_id: c_ulong,
+    order_num: u64,
+    reply_message: *const c_char,
+    trans_reply_descriptor: isize,
+) {
+    let Some(context) = callback_context(SLOT) else {
+        error!("TRANS2QUIK_TRANSACTION_REPLY_CALLBACK: no terminal in slot {}", SLOT);
+        return;
+    };
+
+    let mut reply = TransactionReply {
+        trans2quik_result: Trans2quikResult::from(trans2quik_result),
+        error_code,
+        reply_code,
+        status: ReplyStatus::from(reply_code),
+        trans_id,
+        order_num,
+        reply_message: unsafe { string_from_ptr(reply_message) },
+        class_code: String::new(),
+        sec_code: String::new(),
+        price: 0.0,
+        quantity: 0,
+        balance: 0,
+        firm_id: String::new(),
+        account: String::new(),
+        client_code: String::new(),
+        broker_ref: String::new(),
+        exchange_code: String::new(),
+    };
+
+    // The descriptor is only valid during the callback, its functions are missing in old versions of the library
+    if let Some(getters) = context.transreply_getters.as_ref().filter(|_| trans_reply_descriptor != 0) {
+        unsafe {
+            reply.class_code = string_from_ptr((getters.class_code)(trans_reply_descriptor));
+            reply.sec_code = string_from_ptr((getters.sec_code)(trans_reply_descriptor));
+            reply.price = (getters.price)(trans_reply_descriptor);
+            reply.quantity = (getters.quantity)(trans_reply_descriptor);
+            reply.balance = (getters.balance)(trans_reply_descriptor);
+            reply.firm_id = string_from_ptr((getters.firm_id)(trans_reply_descriptor));
+            reply.account = string_from_ptr((getters.account)(trans_reply_descriptor));
+            reply.client_code = string_from_ptr((getters.client_code)(trans_reply_descriptor));
+            reply.broker_ref = string_from_ptr((getters.broker_ref)(trans_reply_descriptor));
+            reply.exchange_code = string_from_ptr((getters.exchange_code)(trans_reply_descriptor));
+        }
+    }
+
+    info!("TRANS2QUIK_TRANSACTION_REPLY_CALLBACK -> {:?}", reply);
+
+    context.dispatch(QuikEvent::TransactionReply(reply));
+}
+
+
+/// Callback function TRANS2QUIK_ORDER_STATUS_CALLBACK. Reads the order descriptor
+/// and sends the order to the subscribers of the terminal.
+#[allow(clippy::too_many_arguments)]
+extern "C" fn order_status_callback<const SLOT: usize>(
+    mode: c_long,
+    trans_id: c_ulong,
+    order_num: u64,
+    class_code: *const c_char,
+    sec_code: *const c_char,
+    price: c_double,
+    balance: i64,
+    value: c_double,
+    is_sell: c_long,
+    status: c_long,
+    order_descriptor: isize,
+) {
+    let Some(context) = callback_context(SLOT) else {
+        error!("TRANS2QUIK_ORDER_STATUS_CALLBACK: no terminal in slot {}", SLOT);
+        return;
+    };
+
+    let mut order = OrderInfo {
+        mode,
+        trans_id,
+        order_num,
+        class_code: unsafe { string_from_ptr(class_code) },
+        sec_code: unsafe { string_from_ptr(sec_code) },
+        price,
+        balance,
+        value,
+        is_sell: is_sell != 0,
+        status,
+        qty: 0,
+        date: 0,
+        time: 0,
+        activation_time: 0,
+        withdraw_time: 0,
+        expiry: 0,
+        accrued_int: 0.0,
+        yield_value: 0.0,
+        uid: 0,
+        visible_qty: 0,
+        period: 0,
+        awg_price: 0.0,
+        user_id: String::new(),
+        account: String::new(),
+        broker_ref: String::new(),
+        client_code: String::new(),
+        firm_id: String::new(),
+        reject_reason: String::new(),
+    };
+
+    // The descriptor is only valid during the callback and is empty at the end of the initial snapshot,
+    // its functions are missing in old versions of the library
+    if let Some(getters) = context.order_getters.as_ref().filter(|_| order_descriptor != 0) {
+        unsafe {
+            order.qty = (getters.qty)(order_descriptor);
+            order.date = (getters.date)(order_descriptor);
+            order.time = (getters.time)(order_descriptor);
+            order.activation_time = (getters.activation_time)(order_descriptor);
+            order.withdraw_time = (getters.withdraw_time)(order_descriptor);
+            order.expiry = (getters.expiry)(order_descriptor);
+            order.accrued_int = (getters.accrued_int)(order_descriptor);
+            order.yield_value = (getters.yield_value)(order_descriptor);
+            order.uid = (getters.uid)(order_descriptor);
+            order.visible_qty = (getters.visible_qty)(order_descriptor);
+            order.period = (getters.period)(order_descriptor);
+            order.awg_price = (getters.awg_price)(order_descriptor);
+            order.user_id = string_from_ptr((getters.user_id)(order_descriptor));
+            order.account = string_from_ptr((getters.account)(order_descriptor));
+            order.broker_ref = string_from_ptr((getters.broker_ref)(order_descriptor));
+            order.client_code = string_from_ptr((getters.client_code)(order_descriptor));
+            order.firm_id = string_from_ptr((getters.firm_id)(order_descriptor));
+            order.reject_reason = string_from_ptr((getters.reject_reason)(order_descriptor));
+        }
+    }
+
+    info!("TRANS2QUIK_ORDER_STATUS_CALLBACK -> {:?}", order);
+
+    context.dispatch(QuikEvent::OrderUpdate(order));
+}
+
+
+/// Callback function TRANS2QUIK_TRADE_STATUS_CALLBACK. Reads the trade descriptor
+/// and sends the trade to the subscribers of the terminal.
+#[allow(clippy::too_many_arguments)]
+extern "C" fn trade_status_callback<const SLOT: usize>(
+    mode: c_long,
+    trade_num: u64,
+    order_num: u64,
+    class_code: *const c_char,
+    sec_code: *const c_char,
+    price: c_double,
+    qty: i64,
+    value: c_double,
+    is_sell: c_long,
+    trade_descriptor: isize,
+) {
+    let Some(context) = callback_context(SLOT) else {
+        error!("TRANS2QUIK_TRADE_STATUS_CALLBACK: no terminal in slot {}", SLOT);
+        return;
+    };
+
+    let mut trade = TradeInfo {
+        mode,
+        trade_num,
+        order_num,
+        class_code: unsafe { string_from_ptr(class_code) },
+        sec_code: unsafe { string_from_ptr(sec_code) },
+        price,
+        qty,
+        value,
+        is_sell: is_sell != 0,
+        date: 0,
+        settle_date: 0,
+        time: 0,
+        is_marginal: false,
+        accrued_int: 0.0,
+        yield_value: 0.0,
+        ts_commission: 0.0,
+        clearing_center_commission: 0.0,
+        exchange_commission: 0.0,
+        trading_system_commission: 0.0,
+        broker_commission: 0.0,
+        kind: 0,
+        currency: String::new(),
+        settle_currency: String::new(),
+        settle_code: String::new(),
+        account: String::new(),
+        broker_ref: String::new(),
+        client_code: String::new(),
+        user_id: String::new(),
+        firm_id: String::new(),
+        exchange_code: String::new(),
+    };
+
+    // The descriptor is only valid during the callback and is empty at the end of the initial snapshot,
+    // its functions are missing in old versions of the library
+    if let Some(getters) = context.trade_getters.as_ref().filter(|_| trade_descriptor != 0) {
+        unsafe {
+            trade.date = (getters.date)(trade_descriptor);
+            trade.settle_date = (getters.settle_date)(trade_descriptor);
+            trade.time = (getters.time)(trade_descriptor);
+            trade.is_marginal = (getters.is_marginal)(trade_descriptor) != 0;
+            trade.accrued_int = (getters.accrued_int)(trade_descriptor);
+            trade.yield_value = (getters.yield_value)(trade_descriptor);
+            trade.ts_commission = (getters.ts_commission)(trade_descriptor);
+            trade.clearing_center_commission = (getters.clearing_center_commission)(trade_descriptor);
+            trade.exchange_commission = (getters.exchange_commission)(trade_descriptor);
+            trade.trading_system_commission = (getters.trading_system_commission)(trade_descriptor);
+            trade.broker_commission = (getters.broker_commission)(trade_descriptor);
+            trade.kind = (getters.kind)(trade_descriptor);
+            trade.currency = string_from_ptr((getters.currency)(trade_descriptor));
+            trade.settle_currency = string_from_ptr((getters.settle_currency)(trade_descriptor));
+            trade.settle_code = string_from_ptr((getters.settle_code)(trade_descriptor));
+            trade.account = string_from_ptr((getters.account)(trade_descriptor));
+            trade.broker_ref = string_from_ptr((getters.broker_ref)(trade_descriptor));
+            trade.client_code = string_from_ptr((getters.client_code)(trade_descriptor));
+            trade.user_id = string_from_ptr((getters.user_id)(trade_descriptor));
+            trade.firm_id = string_from_ptr((getters.firm_id)(trade_descriptor));
+            trade.exchange_code = string_from_ptr((getters.exchange_code)(trade_descriptor));
+        }
+    }
+
+    info!("TRANS2QUIK_TRADE_STATUS_CALLBACK -> {:?}", trade);
+
+    context.dispatch(QuikEvent::TradeUpdate(trade));
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{FixedOffset, NaiveDate};
+use serde_json::{json, Value};
+use tracing::{error, warn};
+use crate::account::{self, Account, AccountSettings};
+use crate::clock::Clock;
+use crate::config::InstrumentSettings;
+use crate::dashboard::Dashboard;
+use crate::domain::{Order, Position, ToJson};
+use crate::margin::{self, MarginForecast, MarginParams, MarginProjector};
+use crate::portfolio::{InstrumentPosition, MoneyFlows};
+use crate::psql::{Db, IncidentKind, Severity};
+use crate::throttle::ThrottleStats;
+
+
+/// Name, account and instrument of a limit.
+type LimitKey = (&'static str, Option<String>, Option<String>);
+
+
+/// Configured limit with its current utilization, a row of the risk panel of the dashboard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskLimit {
+    /// Name of the limit, e.g. `exposure`, `daily_loss` or `order_rate`.
+    pub name: &'static str,
+    /// Account of the limit or of the instrument.
+    pub account: Option<String>,
+    /// Instrument of a per-instrument limit.
+    pub sec_code: Option<String>,
+    pub used: f64,
+    pub limit: f64,
+}
+
+
+impl RiskLimit {
+    /// Used fraction of the limit, above 1 when the limit is exceeded.
+    pub fn utilization(&self) -> f64 {
+        if self.limit > 0.0 {
+            self.used.max(0.0) / self.limit
+        } else {
+            0.0
+        }
+    }
+}
+
+
+impl ToJson for RiskLimit {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "account": self.account,
+            "sec_code": self.sec_code,
+            "used": self.used,
+            "limit": self.limit,
+            "utilization": self.utilization(),
+        })
+    }
+}
+
+
+/// Exposure of the instruments with a `risk_budget`: the value of the position at the last price.
+/// `lot_sizes` are the units in a lot by security code, 1 when unknown.
+pub fn exposure_limits(settings: &[InstrumentSettings], positions: &[Position], lot_sizes: &HashMap<String, i64>) -> Vec<RiskLimit> {
+    settings
+        .iter()
+        .filter_map(|settings| {
+            let budget = settings.risk_budget?;
+            let value = positions
+                .iter()
+                .find(|position| position.sec_code == settings.sec_code)
+                .map_or(0.0, |position| {
+                    let lot_size = lot_sizes.get(&position.sec_code).copied().unwrap_or(1).max(1);
+                    (position.lots * lot_size) as f64 * position.last_price
+                });
+
+            Some(RiskLimit {
+                name: "exposure",
+                account: settings.account.as_ref().map(|account| account.name.clone()),
+                sec_code: Some(settings.sec_code.clone()),
+                used: value.abs(),
+                limit: budget,
+            })
+        })
+        .collect()
+}
+
+
+/// Loss of the exchange day against `max_daily_loss` of the account, `day_pnl` is the net
+/// profit of the day of the positions of the account.
+pub fn daily_loss_limit(account: &str, max_daily_loss: f64, day_pnl: f64) -> RiskLimit {
+    RiskLimit {
+        name: "daily_loss",
+        account: Some(account.to_string()),
+        sec_code: None,
+        used: if day_pnl < 0.0 { -day_pnl } else { 0.0 },
+        limit: max_daily_loss,
+    }
+}
+
+
+/// End-of-day margin requirement of the positions and the resting orders of an account against
+/// its available funds.
+pub fn margin_limit(account: &str, forecast: &MarginForecast) -> RiskLimit {
+    RiskLimit {
+        name: "margin",
+        account: Some(account.to_string()),
+        sec_code: None,
+        used: forecast.projected(),
+        limit: forecast.available,
+    }
+}
+
+
+/// Transactions of the last second against `max_transactions_per_second`.
+pub fn order_rate_limit(stats: &ThrottleStats, max_per_second: f64) -> RiskLimit {
+    RiskLimit {
+        name: "order_rate",
+        account: None,
+        sec_code: None,
+        used: stats.rate() as f64,
+        limit: max_per_second,
+    }
+}
+
+
+/// Inputs of the risk panel which the publishing task reads besides the database.
+#[derive(Clone)]
+pub struct RiskPanel {
+    pub settings: Vec<InstrumentSettings>,
+    /// Accounts, the ones with `max_daily_loss` get the daily loss limit.
+    pub accounts: Vec<AccountSettings>,
+    pub max_transactions_per_second: f64,
+    /// Statistics of the transaction queue, the order rate is not shown without it.
+    pub throttle: Option<Arc<ThrottleStats>>,
+    pub timezone: FixedOffset,
+    pub clock: Arc<dyn Clock>,
+}
+
+
+/// Risk panel task: every `period` reads the positions and the lot sizes of the instruments,
+/// and publishes the configured limits with their utilization, the positions and their money flows
+/// to the dashboard. The equity curve is the net profit of the positions with the unrealized one.
+///
+/// The loss of the day of an account is the change of the realized profit net of the commissions
+/// of its positions since the first refresh of the exchange day, like the daily profit target
+/// counts it. The margin of every account with a money limit is projected to the end of the day
+/// from its positions and the resting orders of the journal, see `MarginProjector`. A limit
+/// exceeded, the projected margin above the available funds too, is recorded as a `risk_breach`
+/// incident, resolved once the utilization is back within the limit.
+///
+/// # Example of use
+/// ```ignore
+/// let panel = RiskPanel {
+///     settings: config.instrument_settings()?,
+///     accounts: config.accounts.clone(),
+///     max_transactions_per_second: config.max_transactions_per_second,
+///     throttle: Some(throttle.stats()),
+///     timezone: config.exchange_timezone,
+///     clock: clock.clone(),
+/// };
+/// tokio::spawn(risk::publish(db.clone(), dashboard.clone(), panel, Duration::from_secs(5)));
+/// ```
+pub async fn publish(db: Arc<Db>, dashboard: Dashboard, panel: RiskPanel, period: Duration) {
+    let codes: Vec<String> = panel.settings.iter().map(|settings| settings.sec_code.clone()).collect();
+    let mut interval = tokio::time::interval(period);
+    // Exchange day and the net realized profit at its start by account
+    let mut baselines: HashMap<String, (NaiveDate, f64)> = HashMap::new();
+    let mut breaches = HashMap::new();
+
+    loop {
+        interval.tick().await;
+        let (records, rows) = match (db.get_positions().await, db.get_instrument_rows(&codes).await) {
+            (Ok(records), Ok(rows)) => (records, rows),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Error reading the positions for the risk panel: {}", e);
+                continue;
+            }
+        };
+
+        let positions: Vec<InstrumentPosition> = records.into_iter().map(InstrumentPosition::from).collect();
+        let mut money_flows = MoneyFlows::default();
+        for position in &positions {
+            money_flows += position.money_flows();
+        }
+        let unrealized: f64 = positions.iter().map(InstrumentPosition::unrealized_pnl).sum();
+        let positions: Vec<Position> = positions.iter().map(Position::from).collect();
+        let lot_sizes: HashMap<String, i64> = rows
+            .iter()
+            .filter_map(|row| Some((row.instrument_code.clone(), row.lot? as i64)))
+            .collect();
+
+        let mut limits = exposure_limits(&panel.settings, &positions, &lot_sizes);
+        let today = panel.clock.now().with_timezone(&panel.timezone).date_naive();
+        for settings in &panel.accounts {
+            let Some(max_daily_loss) = settings.max_daily_loss else {
+                continue;
+            };
+            let net: f64 = positions
+                .iter()
+                .filter(|position| {
+                    account::of_position(&panel.accounts, &panel.settings, &position.class_code, &position.sec_code)
+                        .is_some_and(|account| account.name == settings.name)
+                })
+                .map(|position| position.realized_pnl - position.commission)
+                .sum();
+            let day_pnl = match baselines.get(&settings.name) {
+                Some((day, start)) if *day == today => net - start,
+                _ => {
+                    baselines.insert(settings.name.clone(), (today, net));
+                    0.0
+                }
+            };
+            limits.push(daily_loss_limit(&settings.name, max_daily_loss, day_pnl));
+        }
+        let orders = db.get_working_orders().await.unwrap_or_else(|e| {
+            error!("Error reading the resting orders for the margin forecast: {}", e);
+            Vec::new()
+        });
+        let mut forecasts = Vec::new();
+        for settings in &panel.accounts {
+            match margin_forecast(&db, &panel, settings, &positions, &orders, &lot_sizes).await {
+                Ok(Some(forecast)) => {
+                    limits.push(margin_limit(&settings.name, &forecast));
+                    forecasts.push((settings.name.clone(), forecast));
+                }
+                Ok(None) => {}
+                Err(e) => error!("Error projecting the margin of the account {}: {}", settings.name, e),
+            }
+        }
+        if let Some(throttle) = &panel.throttle {
+            limits.push(order_rate_limit(throttle, panel.max_transactions_per_second));
+        }
+
+        dashboard.set_positions(positions);
+        dashboard.push_equity(panel.clock.now(), money_flows.net_pnl() + unrealized);
+        dashboard.set_money_flows(money_flows);
+        dashboard.set_margin(forecasts);
+        record_breaches(&db, &limits, &mut breaches).await;
+        dashboard.set_risk_limits(limits);
+    }
+}
+
+
+/// End-of-day margin forecast of the positions and the resting orders of the account, priced
+/// at the last prices and the resting prices with the deposits of QUIK. `None` without the money
+/// limit of the account.
+async fn margin_forecast(
+    db: &Db,
+    panel: &RiskPanel,
+    settings: &AccountSettings,
+    positions: &[Position],
+    orders: &[Order],
+    lot_sizes: &HashMap<String, i64>,
+) -> Result<Option<MarginForecast>, Box<dyn std::error::Error>> {
+    let of_account = |class_code: &str, sec_code: &str| {
+        account::of_position(&panel.accounts, &panel.settings, class_code, sec_code).is_some_and(|account| account.name == settings.name)
+    };
+    let positions: Vec<margin::Position> = positions
+        .iter()
+        .filter(|position| position.lots != 0 && of_account(&position.class_code, &position.sec_code))
+        .map(|position| margin::Position { sec_code: position.sec_code.clone(), lots: position.lots, price: position.last_price })
+        .collect();
+    let orders: Vec<Order> = orders.iter().filter(|order| of_account(&order.class_code, &order.sec_code)).cloned().collect();
+
+    let mut codes: Vec<String> = positions.iter().map(|position| position.sec_code.clone()).collect();
+    codes.extend(orders.iter().map(|order| order.sec_code.clone()));
+    codes.sort();
+    codes.dedup();
+    let account = Account::load(db, settings, &codes).await?;
+    let Some(available) = account.available_funds() else {
+        return Ok(None);
+    };
+
+    let mut projector = MarginProjector::default();
+    for sec_code in &codes {
+        let price = positions
+            .iter()
+            .find(|position| &position.sec_code == sec_code)
+            .map(|position| position.price)
+            .or_else(|| orders.iter().find(|order| &order.sec_code == sec_code).map(|order| order.price))
+            .unwrap_or_default();
+        let lot_size = lot_sizes.get(sec_code).copied().unwrap_or(1);
+        projector = projector.with_params(sec_code, MarginParams::of_instrument(lot_size, price, account.margin.get(sec_code)));
+    }
+    Ok(Some(projector.forecast(&positions, &orders, available)))
+}
+
+
+/// Records a limit exceeded since the last refresh as a critical `risk_breach` incident, and
+/// resolves the incident once the limit is within its bounds again. `breaches` are the open
+/// incidents by the name, the account and the instrument of the limit.
+async fn record_breaches(db: &Db, limits: &[RiskLimit], breaches: &mut HashMap<LimitKey, i32>) {
+    for limit in limits {
+        let key = (limit.name, limit.account.clone(), limit.sec_code.clone());
+        let exceeded = limit.utilization() > 1.0;
+        match (exceeded, breaches.get(&key).copied()) {
+            (true, None) => {
+                let message = format!("{} limit is exceeded: {:.2} of {:.2}", limit.name, limit.used, limit.limit);
+                warn!("{}{}", message, limit.account.as_ref().map(|account| format!(" by the account {}", account)).unwrap_or_default());
+                match db.insert_incident(Severity::Critical, IncidentKind::RiskBreach, limit.sec_code.as_deref(), &message).await {
+                    Ok(id) => {
+                        breaches.insert(key, id);
+                    }
+                    Err(e) => error!("Error recording the breach of the {} limit: {}", limit.name, e),
+                }
+            }
+            (false, Some(id)) => {
+                let resolution = format!("{:.2} of {:.2}", limit.used, limit.limit);
+                if let Err(e) = db.resolve_incident(id, &resolution).await {
+                    error!("Error resolving the breach {}: {}", id, e);
+                }
+                breaches.remove(&key);
+            }
+            _ => {}
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::margin::InstrumentRequirement;
+
+
+    fn position(sec_code: &str, lots: i64, last_price: f64) -> Position {
+        Position {
+            class_code: "QJSIM".to_string(),
+            sec_code: sec_code.to_string(),
+            lots,
+            average_price: last_price,
+            last_price,
+            realized_pnl: 0.0,
+            commission: 0.0,
+        }
+    }
+
+
+    #[test]
+    fn exposure_is_the_value_of_the_position_against_the_budget() {
+        let settings = Config::parse(
+            "[[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"SBER\"\nrisk_budget = 100000.0\n\n\
+             [[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"GAZP\"\n",
+        )
+        .unwrap()
+        .instrument_settings()
+        .unwrap();
+        let lot_sizes = HashMap::from([("SBER".to_string(), 10)]);
+
+        // A short position is as exposed as a long one, the instrument without a budget has no limit
+        let limits = exposure_limits(&settings, &[position("SBER", -20, 300.0), position("GAZP", 5, 150.0)], &lot_sizes);
+        assert_eq!(limits.len(), 1);
+        assert_eq!((limits[0].sec_code.as_deref(), limits[0].used, limits[0].limit), (Some("SBER"), 60000.0, 100000.0));
+        assert_eq!(limits[0].utilization(), 0.6);
+
+        // No position uses nothing of the budget
+        assert_eq!(exposure_limits(&settings, &[], &lot_sizes)[0].used, 0.0);
+    }
+
+
+    #[test]
+    fn only_a_loss_of_the_day_uses_the_daily_loss_limit() {
+        let loss = daily_loss_limit("L01", 5000.0, -6000.0);
+        assert_eq!((loss.account.as_deref(), loss.used), (Some("L01"), 6000.0));
+        // Exceeded limit is above 1
+        assert_eq!(loss.utilization(), 1.2);
+
+        assert_eq!(daily_loss_limit("L01", 5000.0, 1000.0).utilization(), 0.0);
+        // A limit of zero is not configured rather than exceeded
+        assert_eq!(daily_loss_limit("L01", 0.0, -1000.0).utilization(), 0.0);
+    }
+
+
+    #[test]
+    fn margin_is_the_projected_requirement_against_the_available_funds() {
+        let forecast = MarginForecast {
+            available: 1000.0,
+            instruments: vec![InstrumentRequirement { sec_code: "SBER".to_string(), current: 400.0, projected: 900.0 }],
+        };
+        let limit = margin_limit("L01", &forecast);
+        assert_eq!((limit.name, limit.used, limit.limit), ("margin", 900.0, 1000.0));
+
+        let stats = ThrottleStats::default();
+        assert_eq!(order_rate_limit(&stats, 5.0).utilization(), 0.0);
+    }
+}
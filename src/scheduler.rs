@@ -0,0 +1,500 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use quik_rs::quik::QuikEvent;
+use crate::bot_state;
+use crate::churn::ChurnGuard;
+use crate::clock::{Clock, SystemClock};
+use crate::expiry::ExpiryGuard;
+use crate::heartbeat::LoopStats;
+use crate::latency::{LatencyStats, Stage};
+use crate::metrics::Metrics;
+use crate::psql::{DataForEma, Db};
+use crate::strategy::{Decision, StrategySet};
+use crate::tick_feed::Tick;
+use crate::tick_filter::CandleFilter;
+use crate::universe::UniverseChange;
+
+
+/// Time the closed candle of an instrument is waited for before its evaluation is skipped.
+const CANDLE_QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Delay of the wakeup after a candle boundary.
+const CLOSE_DELAY: Duration = Duration::from_secs(1);
+
+
+/// End of the candle of `timeframe` containing `now`, i.e. the next candle boundary.
+/// The candles are aligned to the midnight of the exchange time, so e.g. the 15-minute candles
+/// close at :00, :15, :30 and :45 and the daily candles at the exchange midnight.
+pub fn next_boundary(now: DateTime<Utc>, timeframe: Duration, timezone: FixedOffset) -> DateTime<Utc> {
+    let step = (timeframe.as_millis() as i64).max(1);
+    let offset = timezone.local_minus_utc() as i64 * 1000;
+    let local = now.timestamp_millis() + offset;
+    let boundary = (local.div_euclid(step) + 1) * step - offset;
+
+    Utc.timestamp_millis_opt(boundary).single().unwrap_or(now)
+}
+
+
+/// Reason the trading loop is woken up.
+#[derive(Debug, Clone)]
+pub enum Wakeup {
+    /// Candles of the timeframes closed at `closed_at`.
+    CandleClose { closed_at: DateTime<Utc>, timeframes: Vec<Duration> },
+    /// Callback of the terminal, e.g. a trade or an order update, to react to immediately.
+    Event(Box<QuikEvent>),
+    /// The events of the terminal are closed.
+    Closed,
+}
+
+
+/// Candle-close scheduler: instead of polling at a fixed interval the trading loop sleeps until
+/// the next boundary of any of its timeframes and is woken exactly then, or immediately by
+/// a callback of the terminal.
+///
+/// The wakeup is delayed by `CLOSE_DELAY` after the boundary, so the last trades of the closed
+/// candle reach `historical_trades` before the candle is read.
+///
+/// # Example of use
+/// ```ignore
+/// let mut scheduler = CandleScheduler::new(vec![Duration::from_secs(300), Duration::from_secs(900)], config.exchange_timezone);
+/// loop {
+///     match scheduler.next(&mut events).await {
+///         Wakeup::CandleClose { timeframes, .. } => evaluate(&timeframes).await?,
+///         Wakeup::Event(event) => on_event(&event),
+///         Wakeup::Closed => break,
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CandleScheduler {
+    timeframes: Vec<Duration>,
+    timezone: FixedOffset,
+    /// Last boundary reported, so a boundary is not reported twice.
+    last_close: Option<DateTime<Utc>>,
+    clock: Arc<dyn Clock>,
+}
+
+
+impl CandleScheduler {
+    pub fn new(mut timeframes: Vec<Duration>, timezone: FixedOffset) -> Self {
+        timeframes.retain(|timeframe| !timeframe.is_zero());
+        timeframes.sort();
+        timeframes.dedup();
+
+        CandleScheduler {
+            timeframes,
+            timezone,
+            last_close: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+
+    pub fn timeframes(&self) -> &[Duration] {
+        &self.timeframes
+    }
+
+
+    /// Next boundary after `now` which is not reported yet, with the timeframes closing at it.
+    pub fn next_close(&self, now: DateTime<Utc>) -> Option<(DateTime<Utc>, Vec<Duration>)> {
+        // The boundary reported last may still be ahead of `now` by the close delay
+        let now = match self.last_close {
+            Some(last_close) if last_close > now => last_close,
+            _ => now,
+        };
+        let boundaries: Vec<(Duration, DateTime<Utc>)> = self
+            .timeframes
+            .iter()
+            .map(|timeframe| (*timeframe, next_boundary(now, *timeframe, self.timezone)))
+            .collect();
+        let closed_at = boundaries.iter().map(|(_, boundary)| *boundary).min()?;
+
+        let timeframes = boundaries
+            .into_iter()
+            .filter(|(_, boundary)| *boundary == closed_at)
+            .map(|(timeframe, _)| timeframe)
+            .collect();
+
+        Some((closed_at, timeframes))
+    }
+
+
+    /// Waits for the next candle close or event of the terminal, whichever comes first.
+    pub async fn next(&mut self, events: &mut mpsc::UnboundedReceiver<QuikEvent>) -> Wakeup {
+        let Some((closed_at, timeframes)) = self.next_close(self.clock.now()) else {
+            return match events.recv().await {
+                Some(event) => Wakeup::Event(Box::new(event)),
+                None => Wakeup::Closed,
+            };
+        };
+        let wake_at = closed_at + chrono::Duration::from_std(CLOSE_DELAY).unwrap_or_default();
+        let wait = (wake_at - self.clock.now()).to_std().unwrap_or_default();
+
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => Wakeup::Event(Box::new(event)),
+                None => Wakeup::Closed,
+            },
+            _ = tokio::time::sleep(wait) => {
+                self.last_close = Some(closed_at);
+                Wakeup::CandleClose { closed_at, timeframes }
+            }
+        }
+    }
+}
+
+
+/// Candle series to evaluate: the instruments of the strategies by timeframe.
+fn candle_series(strategies: &StrategySet) -> HashMap<Duration, Vec<String>> {
+    let mut instruments: HashMap<Duration, Vec<String>> = HashMap::new();
+    for sec_code in strategies.instruments() {
+        for timeframe in strategies.timeframes(&sec_code) {
+            instruments.entry(timeframe).or_default().push(sec_code.clone());
+        }
+    }
+    instruments
+}
+
+
+/// Rebuilds the candle series after a change of the instruments, and the scheduler if the
+/// timeframes have changed.
+fn reschedule(strategies: &StrategySet, instruments: &mut HashMap<Duration, Vec<String>>, scheduler: &mut CandleScheduler, timezone: FixedOffset) {
+    *instruments = candle_series(strategies);
+    let mut timeframes: Vec<Duration> = instruments.keys().copied().collect();
+    timeframes.sort();
+    if timeframes != scheduler.timeframes() {
+        *scheduler = CandleScheduler::new(timeframes, timezone).with_clock(scheduler.clock.clone());
+        info!("Candle scheduler timeframes are changed to {:?}", scheduler.timeframes());
+    }
+}
+
+
+/// Reads the candle of the instrument closed at `closed_at`, the trades of
+/// `[closed_at - timeframe, closed_at)` passing the tick filter, within `CANDLE_QUERY_TIMEOUT`.
+/// The bounds come from the boundary, so a late query doesn't shift the candle. The anomalous
+/// ticks are recorded in `anomalous_ticks` by the shortest timeframe of the instrument (`record`),
+/// so they are recorded once.
+async fn closed_candle(
+    db: Arc<Db>,
+    filter: CandleFilter,
+    sec_code: String,
+    timeframe: Duration,
+    closed_at: DateTime<Utc>,
+    record: bool,
+    latency: LatencyStats,
+) -> Result<Option<DataForEma>, String> {
+    let start = closed_at - chrono::Duration::from_std(timeframe).unwrap_or_default();
+    let query = db.get_ticks(&sec_code, start, closed_at);
+    let started = Instant::now();
+    let ticks = tokio::time::timeout(CANDLE_QUERY_TIMEOUT, query).await;
+    latency.record(Stage::CandleQuery, started.elapsed());
+    let ticks = match ticks {
+        Ok(Ok(ticks)) => ticks,
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err(format!("no candle within {:?}", CANDLE_QUERY_TIMEOUT)),
+    };
+
+    let (candle, anomalies) = filter.candle(&sec_code, timeframe, start, &ticks);
+    for anomaly in anomalies.iter().filter(|_| record) {
+        warn!("{}: {} tick at {}: {}", sec_code, anomaly.action.as_str(), anomaly.price, anomaly.reason);
+        if let Err(e) = db.insert_anomalous_tick(&sec_code, anomaly.price, anomaly.previous_price, anomaly.action.as_str(), &anomaly.reason).await {
+            error!("Error saving the anomalous tick of {}: {}", sec_code, e);
+        }
+    }
+    Ok(candle)
+}
+
+
+/// Sends the decisions of the strategies on the instrument at `time` through the cooldown,
+/// anti-churn and expiry rules. Returns `false` if the decisions are not received anymore.
+fn send_decisions(
+    churn: &mut ChurnGuard,
+    expiry: &ExpiryGuard,
+    sec_code: &str,
+    signals: Vec<(&'static str, Decision)>,
+    time: DateTime<Utc>,
+    decisions: &mpsc::UnboundedSender<(&'static str, Decision)>,
+) -> bool {
+    for (strategy, decision) in signals {
+        if let Err(skip) = churn.check(sec_code, decision.action, time) {
+            info!("{}: {} signal of {} is suppressed, {}", sec_code, decision.action.as_str(), strategy, skip);
+        }
+        if let Err(block) = expiry.check(sec_code, decision.action, time) {
+            info!(
+                "{}: {} signal of {} is suppressed, {} days to the expiry on {}",
+                sec_code, decision.action.as_str(), strategy, block.days_left, block.expiry
+            );
+        }
+        let decision = expiry.filter(churn.filter(decision, time), time);
+        if decisions.send((strategy, decision)).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+
+/// Message of a panic caught by `catch_unwind`.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+
+/// Event-driven trading loop of the strategies: evaluates the closed candles of every instrument
+/// as soon as its timeframe closes and passes the fills to the strategies as they arrive.
+/// The boundaries and the times of the fills come from `clock`.
+/// The decisions are sent to `decisions` with the name of the strategy.
+///
+/// The instruments added to the universe start with a fresh signal state and the removed ones
+/// are dropped, as the changes arrive from `universe`. The decisions suppressed by the cooldown
+/// and anti-churn rules of `churn` and the new positions close to the expiry of a contract
+/// blocked by `expiry` are sent as `DoNothingExplicit` with the reason code of the rule.
+///
+/// The ticks from `ticks`, see `tick_feed`, are passed to the strategies evaluating them and
+/// their decisions pass the same rules as the decisions on a candle.
+///
+/// The candle of every instrument is read by its own task, and the tasks of a candle close are
+/// awaited until one shared deadline `CANDLE_QUERY_TIMEOUT` after the close: an instrument
+/// whose query hangs or fails is skipped for that bar without delaying the others. The ticks
+/// of the candle pass `filter` first, see `CandleFilter`. A strategy
+/// panicking on an instrument stops the evaluation of that instrument instead of the loop.
+/// The health and the readiness of every instrument are kept in `metrics` for the dashboard.
+///
+/// The signal states are restored from `bot_state` at the start and saved after every
+/// candle close, see `bot_state::save`.
+///
+/// The stages of a candle close are timed into the latency histograms of `metrics`, and a cycle
+/// taking longer than the shortest closed timeframe is reported with a warning: the next
+/// candle closes before the signals of this one are out. The last cycle is recorded in `stats`
+/// for the heartbeats, see `heartbeat::run`.
+///
+/// # Example of use
+/// ```ignore
+/// let (decisions, mut received) = mpsc::unbounded_channel();
+/// let churn = ChurnGuard::new(&config.instrument_settings()?, config.exchange_timezone);
+/// let expiry = ExpiryGuard::new(&config.instrument_settings()?, &db.get_instrument_rows(&codes).await?, config.exchange_timezone);
+/// tokio::spawn(scheduler::run(
+///     db.clone(), StrategySet::from_config(&config, &metrics.latency)?, churn, expiry, terminal.events(), universe_changes, ticks,
+///     config.exchange_timezone, Arc::new(SystemClock), Arc::new(LoopStats::default()), metrics.clone(),
+///     CandleFilter::new(config.tick_filter, cache.clone()), decisions,
+/// ));
+/// while let Some((strategy, decision)) = received.recv().await {
+///     info!("{}: {} {}", strategy, decision.sec_code, decision.action.as_str());
+/// }
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    db: Arc<Db>,
+    mut strategies: StrategySet,
+    mut churn: ChurnGuard,
+    mut expiry: ExpiryGuard,
+    mut events: mpsc::UnboundedReceiver<QuikEvent>,
+    mut universe: mpsc::UnboundedReceiver<UniverseChange>,
+    mut ticks: mpsc::UnboundedReceiver<Tick>,
+    timezone: FixedOffset,
+    clock: Arc<dyn Clock>,
+    stats: Arc<LoopStats>,
+    metrics: Metrics,
+    filter: CandleFilter,
+    decisions: mpsc::UnboundedSender<(&'static str, Decision)>,
+) {
+    let mut instruments = candle_series(&strategies);
+    let mut scheduler = CandleScheduler::new(instruments.keys().copied().collect(), timezone).with_clock(clock.clone());
+    info!("Candle scheduler is started for the strategies {:?} and the timeframes {:?}", strategies.names(), scheduler.timeframes());
+    let mut universe_open = true;
+    let mut ticks_open = true;
+    if let Err(e) = bot_state::restore(&db, &mut strategies, &metrics.instruments, clock.now()).await {
+        error!("Error restoring the signal states, the strategies start fresh: {}", e);
+    }
+
+    loop {
+        let wakeup = tokio::select! {
+            wakeup = scheduler.next(&mut events) => wakeup,
+            change = universe.recv(), if universe_open => {
+                match change {
+                    Some(change) => {
+                        for sec_code in &change.removed {
+                            strategies.remove_instrument(sec_code);
+                            churn.remove_instrument(sec_code);
+                            expiry.remove_instrument(sec_code);
+                            metrics.tasks.remove(sec_code);
+                            metrics.instruments.remove(sec_code);
+                        }
+                        for settings in &change.added {
+                            metrics.tasks.remove(&settings.sec_code);
+                            churn.add_instrument(settings);
+                            if settings.expiry_guard_days.is_some() {
+                                match db.get_instrument_rows(std::slice::from_ref(&settings.sec_code)).await {
+                                    Ok(rows) => expiry.add_instrument(settings, rows.first().and_then(|row| row.expiry)),
+                                    Err(e) => error!("Error reading the expiry of {}: {}", settings.sec_code, e),
+                                }
+                            }
+                            if let Err(e) = strategies.add_instrument(settings) {
+                                error!("Instrument {} is not added to the strategies: {}", settings.sec_code, e);
+                            }
+                            let timeframe = strategies.timeframes(&settings.sec_code).first().copied();
+                            metrics.instruments.register(settings, strategies.warm_up_candles(&settings.sec_code), timeframe);
+                        }
+                        reschedule(&strategies, &mut instruments, &mut scheduler, timezone);
+                    }
+                    None => universe_open = false,
+                }
+                continue;
+            }
+            tick = ticks.recv(), if ticks_open => {
+                match tick {
+                    Some(tick) => {
+                        let signals = panic::catch_unwind(AssertUnwindSafe(|| strategies.on_tick(&tick.sec_code, tick.price, tick.time)));
+                        match signals {
+                            Ok(signals) => {
+                                if !send_decisions(&mut churn, &expiry, &tick.sec_code, signals, tick.time, &decisions) {
+                                    warn!("Decisions are not received anymore, the candle scheduler is stopped");
+                                    return;
+                                }
+                            }
+                            Err(panic) => {
+                                let message = panic_message(panic.as_ref());
+                                error!("Strategies have panicked on a tick of {} at {}, the instrument is stopped: {}", tick.sec_code, tick.time, message);
+                                metrics.tasks.record_failure(&tick.sec_code, &format!("panicked: {}", message), true);
+                                strategies.remove_instrument(&tick.sec_code);
+                                churn.remove_instrument(&tick.sec_code);
+                                expiry.remove_instrument(&tick.sec_code);
+                                reschedule(&strategies, &mut instruments, &mut scheduler, timezone);
+                            }
+                        }
+                    }
+                    None => ticks_open = false,
+                }
+                continue;
+            }
+        };
+
+        match wakeup {
+            Wakeup::CandleClose { closed_at, timeframes } => {
+                let started = Instant::now();
+                let budget = timeframes.iter().min().copied();
+                let mut evaluated: Vec<String> = Vec::new();
+                let mut stopped: Vec<String> = Vec::new();
+                // The longer timeframes first, they confirm the signals of the shorter ones
+                let queries: Vec<_> = timeframes
+                    .into_iter()
+                    .rev()
+                    .flat_map(|timeframe| instruments.get(&timeframe).into_iter().flatten().map(move |sec_code| (timeframe, sec_code.clone())))
+                    .map(|(timeframe, sec_code)| {
+                        let record = strategies.timeframes(&sec_code).first() == Some(&timeframe);
+                        let query = tokio::spawn(closed_candle(db.clone(), filter.clone(), sec_code.clone(), timeframe, closed_at, record, metrics.latency.clone()));
+                        (timeframe, sec_code, query)
+                    })
+                    .collect();
+
+                // The queries run side by side, so one deadline bounds the wait for all of them
+                let deadline = tokio::time::Instant::from_std(started) + CANDLE_QUERY_TIMEOUT;
+                for (timeframe, sec_code, mut query) in queries {
+                    if stopped.contains(&sec_code) {
+                        query.abort();
+                        continue;
+                    }
+                    let candle = match tokio::time::timeout_at(deadline, &mut query).await {
+                        Err(_) => {
+                            query.abort();
+                            let e = format!("no candle within {:?}", CANDLE_QUERY_TIMEOUT);
+                            error!("Candle of {} closed at {} is skipped: {}", sec_code, closed_at, e);
+                            metrics.tasks.record_failure(&sec_code, &e, false);
+                            continue;
+                        }
+                        Ok(Ok(Ok(candle))) => candle,
+                        Ok(Ok(Err(e))) => {
+                            error!("Error reading the candle of {} closed at {}: {}", sec_code, closed_at, e);
+                            metrics.tasks.record_failure(&sec_code, &e, false);
+                            continue;
+                        }
+                        Ok(Err(e)) => {
+                            error!("Candle query of {} closed at {} has failed: {}", sec_code, closed_at, e);
+                            metrics.tasks.record_failure(&sec_code, &e.to_string(), false);
+                            continue;
+                        }
+                    };
+                    let Some(candle) = candle else {
+                        continue;
+                    };
+
+                    churn.on_candle(&sec_code, timeframe);
+                    if !evaluated.contains(&sec_code) {
+                        evaluated.push(sec_code.clone());
+                    }
+                    // The state of the strategies of a panicked instrument is not trusted anymore
+                    let signals = match panic::catch_unwind(AssertUnwindSafe(|| strategies.on_candle(&sec_code, timeframe, &candle))) {
+                        Ok(signals) => signals,
+                        Err(panic) => {
+                            let message = panic_message(panic.as_ref());
+                            error!("Strategies have panicked on {} closed at {}, the instrument is stopped: {}", sec_code, closed_at, message);
+                            metrics.tasks.record_failure(&sec_code, &format!("panicked: {}", message), true);
+                            stopped.push(sec_code);
+                            continue;
+                        }
+                    };
+                    metrics.tasks.record_ok(&sec_code, closed_at);
+                    if strategies.timeframes(&sec_code).first() == Some(&timeframe) {
+                        metrics.instruments.record_candle(&sec_code, closed_at);
+                    }
+
+                    if !send_decisions(&mut churn, &expiry, &sec_code, signals, closed_at, &decisions) {
+                        warn!("Decisions are not received anymore, the candle scheduler is stopped");
+                        return;
+                    }
+                }
+                if !stopped.is_empty() {
+                    for sec_code in &stopped {
+                        strategies.remove_instrument(sec_code);
+                        churn.remove_instrument(sec_code);
+                        expiry.remove_instrument(sec_code);
+                    }
+                    evaluated.retain(|sec_code| !stopped.contains(sec_code));
+                    reschedule(&strategies, &mut instruments, &mut scheduler, timezone);
+                }
+                let save_started = Instant::now();
+                if let Err(e) = bot_state::save(&db, &strategies, &evaluated, closed_at).await {
+                    error!("Error saving the signal states of the candles closed at {}: {}", closed_at, e);
+                }
+                metrics.latency.record(Stage::DbInsert, save_started.elapsed());
+
+                let cycle = started.elapsed();
+                metrics.latency.record(Stage::Cycle, cycle);
+                stats.record(evaluated.len(), cycle);
+                if let Some(budget) = budget.filter(|budget| cycle > *budget) {
+                    warn!(
+                        "Cycle of the candles closed at {} took {:?} for {} instruments, longer than the timeframe of {:?}",
+                        closed_at, cycle, evaluated.len(), budget
+                    );
+                }
+            }
+            Wakeup::Event(event) => {
+                if let QuikEvent::TradeUpdate(trade) = *event {
+                    churn.on_fill(&trade.sec_code, trade.is_sell, trade.qty, clock.now());
+                    strategies.on_fill(&trade);
+                }
+            }
+            Wakeup::Closed => {
+                warn!("Events are closed, the candle scheduler is stopped");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::ops::Deref;
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+
+/// Prefix of an encrypted value in the configuration.
+pub const ENCRYPTED_PREFIX: &str = "enc:v2:";
+
+/// Environment variable with the passphrase of the encrypted values.
+pub const PASSPHRASE_VAR: &str = "QUIK_RS_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+
+/// Secret value of the configuration, e.g. a token or the database connection string. It reads
+/// as a `&str`, but its `Debug` doesn't show it, so a logged configuration doesn't leak it.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Secret(String);
+
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+}
+
+
+impl Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+
+/// Checks whether a configuration value is encrypted.
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+
+/// Encrypts a secret, e.g. a token or the database connection string, with the passphrase.
+///
+/// The key is derived with Argon2id from the passphrase and a random salt, and the value is
+/// encrypted and authenticated with ChaCha20-Poly1305 under a random nonce.
+/// The result is `enc:v2:` followed by base64 of `salt | nonce | ciphertext | tag`.
+pub fn encrypt(value: &str, passphrase: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut random = [0u8; SALT_LEN + NONCE_LEN];
+    getrandom::getrandom(&mut random).map_err(|e| format!("random generator error: {}", e))?;
+    let (salt, nonce) = random.split_at(SALT_LEN);
+
+    let cipher = cipher(passphrase, salt)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(nonce), value.as_bytes())
+        .map_err(|_| "encryption error")?;
+    let mut data = random.to_vec();
+    data.extend(ciphertext);
+
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(data)))
+}
+
+
+/// Decrypts a value produced by `encrypt`. Fails on a wrong passphrase or a modified value.
+pub fn decrypt(value: &str, passphrase: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let encoded = value.strip_prefix(ENCRYPTED_PREFIX).ok_or("value is not encrypted")?;
+    let data = STANDARD.decode(encoded.trim())?;
+    if data.len() < SALT_LEN + NONCE_LEN + TAG_LEN {
+        return Err("encrypted value is too short".into());
+    }
+
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let plaintext = cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "wrong passphrase or corrupted encrypted value")?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+
+/// Decrypts the value if it is encrypted, taking the passphrase from `QUIK_RS_PASSPHRASE`.
+/// Plain values are returned unchanged.
+pub fn reveal(value: &str) -> Result<Secret, Box<dyn std::error::Error>> {
+    if !is_encrypted(value) {
+        return Ok(Secret::new(value));
+    }
+
+    let passphrase = std::env::var(PASSPHRASE_VAR)
+        .map_err(|_| format!("{} must be set to decrypt the encrypted configuration values", PASSPHRASE_VAR))?;
+    decrypt(value, &passphrase).map(Secret::new)
+}
+
+
+/// Cipher with the key derived by Argon2id with its default parameters.
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, Box<dyn std::error::Error>> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("key derivation error: {}", e))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn encrypted_value_round_trips() {
+        let encrypted = encrypt("host=db password=hunter2", "passphrase").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("hunter2"));
+        assert_eq!(decrypt(&encrypted, "passphrase").unwrap(), "host=db password=hunter2");
+        // Every encryption has its own salt and nonce
+        assert_ne!(encrypt("host=db password=hunter2", "passphrase").unwrap(), encrypted);
+    }
+
+
+    #[test]
+    fn wrong_passphrase_and_modified_value_are_rejected() {
+        let encrypted = encrypt("token", "passphrase").unwrap();
+        assert!(decrypt(&encrypted, "other").is_err());
+
+        let mut data = STANDARD.decode(&encrypted[ENCRYPTED_PREFIX.len()..]).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let modified = format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(data));
+        assert!(decrypt(&modified, "passphrase").is_err());
+    }
+
+
+    #[test]
+    fn secret_is_not_shown_by_debug() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(&*secret, "hunter2");
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+    }
+}
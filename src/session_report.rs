@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::portfolio::InstrumentPosition;
+use crate::psql::{DailyReportRecord, Fill, Incident};
+
+
+/// Trading results of an instrument, or of all of them, for the exchange day.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DayPnl {
+    /// Realized profit of the closed trades before the commissions.
+    pub realized_pnl: f64,
+    pub commission: f64,
+    /// Fills of the day.
+    pub trades: usize,
+    pub closed_trades: usize,
+    pub winning_trades: usize,
+    /// Largest fall of the net profit of the day from its peak, zero or negative.
+    pub max_drawdown: f64,
+    /// Net profit after the last fill and its peak, the drawdown is measured against them.
+    net: f64,
+    peak: f64,
+}
+
+
+impl DayPnl {
+    pub fn net_pnl(&self) -> f64 {
+        self.realized_pnl - self.commission
+    }
+
+
+    /// Share of the closed trades in profit in percent.
+    pub fn hit_rate(&self) -> Option<f64> {
+        (self.closed_trades > 0).then(|| self.winning_trades as f64 / self.closed_trades as f64 * 100.0)
+    }
+
+
+    fn add(&mut self, realized: Option<f64>, commission: f64) {
+        self.trades += 1;
+        self.commission += commission;
+        if let Some(realized) = realized {
+            self.closed_trades += 1;
+            if realized > 0.0 {
+                self.winning_trades += 1;
+            }
+            self.realized_pnl += realized;
+        }
+
+        self.net += realized.unwrap_or(0.0) - commission;
+        self.peak = self.peak.max(self.net);
+        self.max_drawdown = self.max_drawdown.min(self.net - self.peak);
+    }
+
+
+    fn record(&self, date: NaiveDate, instrument_code: &str) -> DailyReportRecord {
+        DailyReportRecord {
+            report_date: date,
+            instrument_code: instrument_code.to_string(),
+            realized_pnl: self.realized_pnl,
+            commission: self.commission,
+            net_pnl: self.net_pnl(),
+            trades: self.trades as i64,
+            closed_trades: self.closed_trades as i64,
+            hit_rate: self.hit_rate(),
+            max_drawdown: self.max_drawdown,
+        }
+    }
+}
+
+
+impl fmt::Display for DayPnl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "net {:.2} (realized {:.2}, fees {:.2}), {} trades",
+            self.net_pnl(),
+            self.realized_pnl,
+            self.commission,
+            self.trades
+        )?;
+        if let Some(hit_rate) = self.hit_rate() {
+            write!(f, ", hit rate {:.0}% of {}", hit_rate, self.closed_trades)?;
+        }
+        write!(f, ", max drawdown {:.2}", self.max_drawdown)
+    }
+}
+
+
+/// Profit and loss report of a trading session: the realized profit, the fees, the trades,
+/// the hit rate and the largest drawdown of the day by instrument and in total, with the
+/// incidents of the day. Stored in `daily_reports` and sent as a summary to the notification
+/// channels by the end-of-day pipeline.
+///
+/// # Example of use
+/// ```ignore
+/// let fills = db.get_fills_before(to).await?;
+/// let report = SessionReport::build(date, from, &fills).with_incidents(db.get_incidents(from, false).await?);
+/// db.upsert_daily_reports(&report.records()).await?;
+/// notifier.notify(None, &channels, &report.to_string()).await;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionReport {
+    pub date: NaiveDate,
+    pub instruments: BTreeMap<String, DayPnl>,
+    pub total: DayPnl,
+    pub incidents: Vec<Incident>,
+}
+
+
+impl SessionReport {
+    /// Replays the fills of all the strategies in the order of execution and counts the ones
+    /// executed from `from`, the start of the day. The earlier fills only restore the positions,
+    /// so a position opened on a previous day and closed today is a closed trade of the day.
+    pub fn build(date: NaiveDate, from: DateTime<Utc>, fills: &[Fill]) -> Self {
+        let mut positions: HashMap<(&str, &str), InstrumentPosition> = HashMap::new();
+        let mut instruments: BTreeMap<String, DayPnl> = BTreeMap::new();
+        let mut total = DayPnl::default();
+
+        for fill in fills {
+            let quantity = if fill.is_sell { -fill.quantity } else { fill.quantity };
+            let realized = positions
+                .entry((fill.strategy.as_str(), fill.instrument_code.as_str()))
+                .or_default()
+                .apply(quantity, fill.price, fill.point_value);
+            if fill.executed_at < from {
+                continue;
+            }
+
+            instruments.entry(fill.instrument_code.clone()).or_default().add(realized, fill.commission);
+            total.add(realized, fill.commission);
+        }
+
+        SessionReport { date, instruments, total, incidents: Vec::new() }
+    }
+
+
+    pub fn with_incidents(mut self, incidents: Vec<Incident>) -> Self {
+        self.incidents = incidents;
+        self
+    }
+
+
+    /// Rows of `daily_reports`, the total with an empty instrument code.
+    pub fn records(&self) -> Vec<DailyReportRecord> {
+        self.instruments
+            .iter()
+            .map(|(instrument_code, pnl)| pnl.record(self.date, instrument_code))
+            .chain(std::iter::once(self.total.record(self.date, "")))
+            .collect()
+    }
+}
+
+
+impl fmt::Display for SessionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Session {}: {}", self.date, self.total)?;
+        for (instrument_code, pnl) in &self.instruments {
+            write!(f, "\n  - {}: {}", instrument_code, pnl)?;
+        }
+
+        if !self.incidents.is_empty() {
+            let unresolved = self.incidents.iter().filter(|incident| !incident.resolved).count();
+            write!(f, "\nIncidents: {}, {} unresolved", self.incidents.len(), unresolved)?;
+        }
+        for incident in &self.incidents {
+            write!(f, "\n  - {} {} {}", incident.created_at.format("%H:%M:%S"), incident.severity.as_str(), incident.kind)?;
+            if let Some(sec_code) = &incident.sec_code {
+                write!(f, " {}", sec_code)?;
+            }
+            write!(f, ": {}", incident.message)?;
+            if incident.resolved {
+                write!(f, " (resolved: {})", incident.resolution.as_deref().unwrap_or("-"))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+use quik_rs::quik::QuikApi;
+use crate::config::ShutdownSettings;
+use crate::notifier::Notifier;
+use crate::orders::{OrderState, OrderTracker};
+
+
+/// Interval between the checks of the orders in flight while waiting for the replies.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// State of the orders of the bot when it stopped.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownReport {
+    /// Working orders a cancellation was sent for.
+    pub cancels_sent: usize,
+    /// Orders still in flight at the end of the wait, as `TRANS_ID SEC_CODE state`.
+    pub in_flight: Vec<String>,
+    pub waited: Duration,
+}
+
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
+
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bot stopped after {:.1} s, {} cancellations sent", self.waited.as_secs_f64(), self.cancels_sent)?;
+        if self.is_clean() {
+            return write!(f, ", no orders in flight");
+        }
+        write!(f, ", {} orders still in flight:", self.in_flight.len())?;
+        for order in &self.in_flight {
+            write!(f, "\n  - {}", order)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Orders the shutdown waits for: the submissions without a reply, and the working orders
+/// as well if they are cancelled.
+fn outstanding(tracker: &OrderTracker, cancel_orders: bool) -> Vec<String> {
+    tracker
+        .in_flight()
+        .into_iter()
+        .filter(|order| cancel_orders || order.state == OrderState::PendingSubmit)
+        .map(|order| format!("{} {} {}", order.trans_id(), order.transaction.sec_code, order.state.as_str()))
+        .collect()
+}
+
+
+/// Graceful stop of the trading: optionally cancels the working orders of the bot, waits up to
+/// `reply_timeout` for the replies of the transactions in flight, and only then disconnects the
+/// terminal, reporting the final state of the orders to the notification channels.
+///
+/// The order tracker must keep receiving the events of the terminal during the wait.
+///
+/// # Example of use
+/// ```ignore
+/// headless::shutdown_signal().await?;
+/// let report = shutdown::run(terminal.clone(), &orders, &notifier, &config.shutdown).await?;
+/// info!("{}", report);
+/// ```
+pub async fn run(
+    terminal: Arc<dyn QuikApi>,
+    tracker: &Mutex<OrderTracker>,
+    notifier: &Notifier,
+    settings: &ShutdownSettings,
+) -> Result<ShutdownReport, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let mut report = ShutdownReport::default();
+
+    if settings.cancel_orders {
+        report.cancels_sent = tracker.lock().unwrap_or_else(|e| e.into_inner()).cancel_working();
+        info!("Shutdown: {} cancellations of the working orders are sent", report.cancels_sent);
+    }
+
+    loop {
+        report.in_flight = outstanding(&tracker.lock().unwrap_or_else(|e| e.into_inner()), settings.cancel_orders);
+        if report.in_flight.is_empty() || started.elapsed() >= settings.reply_timeout {
+            break;
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+    report.waited = started.elapsed();
+
+    if report.is_clean() {
+        info!("Shutdown: {}", report);
+    } else {
+        warn!("Shutdown: {}", report);
+    }
+    notifier.notify(None, &settings.notification_channels, &report.to_string()).await;
+
+    terminal.shutdown()?;
+    Ok(report)
+}
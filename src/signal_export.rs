@@ -0,0 +1,187 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use chrono::{DateTime, Duration, Utc};
+use crate::psql::{Db, Evaluation};
+
+
+/// Time after a signal at which its forward return is measured, written as `30s`, `15m`, `1h` or `1d`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Horizon {
+    pub label: String,
+    pub duration: Duration,
+}
+
+
+impl Horizon {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let value = value.trim();
+        let unit = value.chars().last().ok_or("empty horizon")?;
+        let amount: i64 = value[..value.len() - unit.len_utf8()]
+            .parse()
+            .map_err(|_| format!("invalid horizon '{}', expected e.g. 15m, 1h or 1d", value))?;
+        if amount <= 0 {
+            return Err(format!("horizon '{}' must be positive", value).into());
+        }
+
+        let duration = match unit {
+            's' => Duration::seconds(amount),
+            'm' => Duration::minutes(amount),
+            'h' => Duration::hours(amount),
+            'd' => Duration::days(amount),
+            _ => return Err(format!("invalid horizon unit in '{}', expected s, m, h or d", value).into()),
+        };
+
+        Ok(Horizon { label: value.to_string(), duration })
+    }
+
+
+    /// Parses a comma-separated list, e.g. `15m,1h,1d`.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        value.split(',').map(Horizon::parse).collect()
+    }
+}
+
+
+/// Signal with the values of the indicators at its time and the forward returns.
+#[derive(Debug, Clone)]
+pub struct LabeledSignal {
+    pub instrument_code: String,
+    pub candle_time: DateTime<Utc>,
+    pub decision: String,
+    pub reason_code: String,
+    pub indicators: HashMap<String, f64>,
+    /// Last price at the time of the signal.
+    pub price: Option<f64>,
+    /// Forward returns in percent by horizon, `None` if the price after the horizon is not known yet.
+    pub returns: Vec<Option<f64>>,
+}
+
+
+/// Dataset of the historical signals for labeling and training filters or models outside the bot:
+/// the features are the indicator values from the evaluation audit at the signal time, the labels
+/// are the forward returns over the horizons calculated from `historical_trades`.
+///
+/// # Example of use
+/// ```ignore
+/// let horizons = Horizon::parse_list("15m,1h,1d")?;
+/// let dataset = SignalDataset::load(&db, &["SBER".to_string()], from, to, horizons).await?;
+/// std::fs::write("signals.csv", dataset.to_csv())?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct SignalDataset {
+    pub horizons: Vec<Horizon>,
+    /// Union of the indicator names of all signals, the feature columns.
+    pub indicator_names: Vec<String>,
+    pub signals: Vec<LabeledSignal>,
+}
+
+
+impl SignalDataset {
+    /// Loads the signals of the instruments between `from` and `to`.
+    pub async fn load(
+        db: &Db,
+        instrument_codes: &[String],
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        horizons: Vec<Horizon>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let longest = horizons.iter().map(|horizon| horizon.duration).max().unwrap_or_else(Duration::zero);
+
+        let mut evaluations = Vec::new();
+        let mut prices = HashMap::new();
+        for instrument_code in instrument_codes {
+            evaluations.extend(
+                db.get_evaluations(instrument_code, from)
+                    .await?
+                    .into_iter()
+                    .filter(|evaluation| evaluation.candle_time <= to),
+            );
+            prices.insert(instrument_code.clone(), db.get_prices(instrument_code, from - Duration::days(1), to + longest).await?);
+        }
+
+        Ok(Self::build(&evaluations, &prices, horizons))
+    }
+
+
+    /// Labels the signals, the candles with the `hold` decision are skipped.
+    pub fn build(evaluations: &[Evaluation], prices: &HashMap<String, Vec<(DateTime<Utc>, f64)>>, horizons: Vec<Horizon>) -> Self {
+        let mut indicator_names = BTreeSet::new();
+        let mut signals = Vec::new();
+
+        for evaluation in evaluations.iter().filter(|evaluation| evaluation.decision != "hold") {
+            let series = prices.get(&evaluation.instrument_code).map(Vec::as_slice).unwrap_or_default();
+            let price = price_at(series, evaluation.candle_time);
+            let returns = horizons
+                .iter()
+                .map(|horizon| {
+                    let entry = price?;
+                    let exit = price_at(series, evaluation.candle_time + horizon.duration)?;
+                    // Without a price after the horizon the label would leak the missing data
+                    let last = series.last()?.0;
+                    (last >= evaluation.candle_time + horizon.duration && entry != 0.0).then(|| (exit / entry - 1.0) * 100.0)
+                })
+                .collect();
+
+            indicator_names.extend(evaluation.indicators.iter().map(|(name, _)| name.clone()));
+            signals.push(LabeledSignal {
+                instrument_code: evaluation.instrument_code.clone(),
+                candle_time: evaluation.candle_time,
+                decision: evaluation.decision.clone(),
+                reason_code: evaluation.reason_code.clone(),
+                indicators: evaluation.indicators.iter().cloned().collect(),
+                price,
+                returns,
+            });
+        }
+        signals.sort_by(|a, b| (a.candle_time, &a.instrument_code).cmp(&(b.candle_time, &b.instrument_code)));
+
+        SignalDataset {
+            horizons,
+            indicator_names: indicator_names.into_iter().collect(),
+            signals,
+        }
+    }
+
+
+    /// One row per signal: the instrument, the time, the decision, the reason, the indicators
+    /// and the forward returns `return_<horizon>`. Unknown values are left empty.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("instrument_code,candle_time,decision,reason_code,price");
+        for name in &self.indicator_names {
+            let _ = write!(csv, ",{}", name);
+        }
+        for horizon in &self.horizons {
+            let _ = write!(csv, ",return_{}", horizon.label);
+        }
+        csv.push('\n');
+
+        let optional = |value: Option<f64>| value.map_or(String::new(), |value| value.to_string());
+        for signal in &self.signals {
+            let _ = write!(
+                csv,
+                "{},{},{},{},{}",
+                signal.instrument_code,
+                signal.candle_time.to_rfc3339(),
+                signal.decision,
+                signal.reason_code,
+                optional(signal.price)
+            );
+            for name in &self.indicator_names {
+                let _ = write!(csv, ",{}", optional(signal.indicators.get(name).copied()));
+            }
+            for value in &signal.returns {
+                let _ = write!(csv, ",{}", optional(*value));
+            }
+            csv.push('\n');
+        }
+
+        csv
+    }
+}
+
+
+/// Last price at or before `time`.
+fn price_at(series: &[(DateTime<Utc>, f64)], time: DateTime<Utc>) -> Option<f64> {
+    let index = series.partition_point(|(at, _)| *at <= time);
+    (index > 0).then(|| series[index - 1].1)
+}
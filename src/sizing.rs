@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::bracket::Offset;
+
+
+/// Candles of the ATR without `atr_period`.
+pub const DEFAULT_ATR_PERIOD: usize = 14;
+
+
+/// Method of calculating the lots of an entry, the `method` of the `[sizing]` table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizingMethod {
+    /// The same number of lots on every entry.
+    Fixed { lots: i64 },
+    /// Fixed-fractional sizing: a stop at `stop` from the entry loses `risk_percent` of the equity.
+    FixedFraction { risk_percent: f64, stop: Offset },
+    /// Volatility targeting: a move of one ATR changes the equity by `volatility_percent`,
+    /// so the calm instruments get more lots than the volatile ones.
+    VolatilityTarget { volatility_percent: f64 },
+}
+
+
+/// Rounding of the calculated size to whole lots, the `rounding` of the `[sizing]` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Down, the risk never exceeds the budget. The default.
+    Floor,
+    /// To the nearest lot, the risk is on the budget on average.
+    Nearest,
+}
+
+
+impl Rounding {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value {
+            "floor" => Ok(Rounding::Floor),
+            "nearest" => Ok(Rounding::Nearest),
+            _ => Err(format!("unknown rounding '{}', expected 'floor' or 'nearest'", value).into()),
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rounding::Floor => "floor",
+            Rounding::Nearest => "nearest",
+        }
+    }
+
+
+    pub fn apply(&self, lots: f64) -> i64 {
+        match self {
+            Rounding::Floor => lots.floor() as i64,
+            Rounding::Nearest => lots.round() as i64,
+        }
+    }
+}
+
+
+/// Value of the fractions of a lot lost or gained by the rounding of the entries, by security code.
+///
+/// The residual of an entry is carried to the next entry of the instrument, so e.g. 1.6 lots
+/// floored twice gives 1 and then 2 lots instead of 1 and 1: over many entries the allocation
+/// across the watchlist matches the calculated sizes instead of drifting under them with `floor`
+/// or over them with `nearest`. The residual is kept as a value, so it survives price changes.
+///
+/// # Example of use
+/// ```ignore
+/// let mut residuals = LotResiduals::default();
+/// let lots = sizer.lots_tracked("SBER", &input, &mut residuals);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LotResiduals {
+    residuals: HashMap<String, f64>,
+}
+
+
+impl LotResiduals {
+    /// Rounds the calculated lots of the instrument together with its residual and keeps
+    /// the new residual. `lot_value` is the value of a lot the residual is measured in.
+    pub fn round(&mut self, sec_code: &str, lots: f64, lot_value: f64, rounding: Rounding) -> i64 {
+        if lot_value <= 0.0 || !lots.is_finite() {
+            return rounding.apply(lots);
+        }
+
+        let value = lots * lot_value + self.residual(sec_code);
+        let rounded = rounding.apply(value / lot_value).max(0);
+        self.residuals.insert(sec_code.to_string(), value - rounded as f64 * lot_value);
+        rounded
+    }
+
+
+    /// Value of the fractions of a lot not allocated yet, negative when over-allocated.
+    pub fn residual(&self, sec_code: &str) -> f64 {
+        self.residuals.get(sec_code).copied().unwrap_or(0.0)
+    }
+
+
+    /// Forgets the residual, e.g. when a limit rather than the rounding decided the size.
+    pub fn clear(&mut self, sec_code: &str) {
+        self.residuals.remove(sec_code);
+    }
+}
+
+
+/// Market state an entry is sized on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizingInput {
+    /// Account equity, or the capital of a backtest with its profit.
+    pub equity: f64,
+    pub price: f64,
+    /// Units of the instrument in a lot.
+    pub lot_size: i64,
+    /// ATR of the instrument on its timeframe, `None` until enough candles are seen.
+    pub atr: Option<f64>,
+    /// Lots covered by the funds and the holdings of the account, `None` when unknown.
+    pub affordable_lots: Option<i64>,
+}
+
+
+/// Position sizing of the entries: calculates the lots of an entry from the equity, the price
+/// and the lot size of the instrument by the configured method, rounded to whole lots by
+/// the rounding policy and clamped to `min_lots..=max_lots`. An entry is never larger than
+/// the account can afford, even below `min_lots`.
+///
+/// A method which needs the ATR sizes the entry at `min_lots` until the ATR is known.
+/// A method which needs the equity is not meant to size an entry without it, see `needs_equity`.
+///
+/// # Example of use
+/// ```ignore
+/// let sizer = config.sizing.unwrap_or_else(|| PositionSizer::fixed(1));
+/// let mut residuals = LotResiduals::default();
+/// let input = SizingInput { equity: 1_000_000.0, price: 250.0, lot_size: 10, atr: Some(3.5), affordable_lots: None };
+/// let lots = sizer.lots_tracked("SBER", &input, &mut residuals);
+/// if let Some((side, lots)) = decision.action.to_order(position, lots) {
+///     send_order(side, lots)?;
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionSizer {
+    pub method: SizingMethod,
+    /// Candles of the ATR of the methods which need it, `DEFAULT_ATR_PERIOD` by default.
+    pub atr_period: usize,
+    pub min_lots: i64,
+    pub max_lots: Option<i64>,
+    pub rounding: Rounding,
+}
+
+
+impl PositionSizer {
+    pub fn fixed(lots: i64) -> Self {
+        PositionSizer {
+            method: SizingMethod::Fixed { lots },
+            atr_period: DEFAULT_ATR_PERIOD,
+            min_lots: 1,
+            max_lots: None,
+            rounding: Rounding::Floor,
+        }
+    }
+
+
+    /// Checks the limits and the parameters of the method.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.method {
+            SizingMethod::Fixed { lots } if lots <= 0 => return Err("'lots' must be positive".into()),
+            SizingMethod::FixedFraction { risk_percent, .. } if risk_percent <= 0.0 => {
+                return Err("'risk_percent' must be positive".into())
+            }
+            SizingMethod::VolatilityTarget { volatility_percent } if volatility_percent <= 0.0 => {
+                return Err("'volatility_percent' must be positive".into())
+            }
+            _ => {}
+        }
+        if self.atr_period == 0 {
+            return Err("'atr_period' must be positive".into());
+        }
+        if self.min_lots <= 0 {
+            return Err("'min_lots' must be positive".into());
+        }
+        if let Some(max_lots) = self.max_lots {
+            if max_lots < self.min_lots {
+                return Err(format!("max_lots {} is less than min_lots {}", max_lots, self.min_lots).into());
+            }
+        }
+
+        Ok(())
+    }
+
+
+    /// The method needs the ATR of the instrument.
+    pub fn needs_atr(&self) -> bool {
+        match self.method {
+            SizingMethod::Fixed { .. } => false,
+            SizingMethod::FixedFraction { stop, .. } => matches!(stop, Offset::Atr(_)),
+            SizingMethod::VolatilityTarget { .. } => true,
+        }
+    }
+
+
+    /// The method sizes the entry on the equity, which must be known.
+    pub fn needs_equity(&self) -> bool {
+        !matches!(self.method, SizingMethod::Fixed { .. })
+    }
+
+
+    /// Size of an entry in fractional lots before the rounding, `None` while the method
+    /// lacks the ATR.
+    pub fn target(&self, input: &SizingInput) -> Option<f64> {
+        let lot_size = input.lot_size.max(1) as f64;
+        let lots = match self.method {
+            SizingMethod::Fixed { lots } => Some(lots as f64),
+            SizingMethod::FixedFraction { risk_percent, stop } => stop
+                .distance(input.price, input.atr)
+                .ok()
+                .filter(|distance| *distance > 0.0)
+                .map(|distance| input.equity * risk_percent / 100.0 / (distance * lot_size)),
+            SizingMethod::VolatilityTarget { volatility_percent } => input
+                .atr
+                .filter(|atr| *atr > 0.0)
+                .map(|atr| input.equity * volatility_percent / 100.0 / (atr * lot_size)),
+        };
+
+        lots.filter(|lots| lots.is_finite())
+    }
+
+
+    fn clamp(&self, lots: i64, input: &SizingInput) -> i64 {
+        lots.max(self.min_lots)
+            .min(self.max_lots.unwrap_or(i64::MAX))
+            .min(input.affordable_lots.unwrap_or(i64::MAX).max(0))
+    }
+
+
+    /// Lots of an entry of the instrument with the rounding residual of its previous entries.
+    /// A size decided by the limits rather than the rounding resets the residual.
+    pub fn lots_tracked(&self, sec_code: &str, input: &SizingInput, residuals: &mut LotResiduals) -> i64 {
+        let Some(target) = self.target(input) else {
+            return self.clamp(self.min_lots, input);
+        };
+
+        let rounded = residuals.round(sec_code, target, input.price * input.lot_size.max(1) as f64, self.rounding);
+        let lots = self.clamp(rounded, input);
+        if lots != rounded {
+            residuals.clear(sec_code);
+        }
+        lots
+    }
+}
+
+
+impl fmt::Display for PositionSizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.method {
+            SizingMethod::Fixed { lots } => write!(f, "fixed {} lots", lots)?,
+            SizingMethod::FixedFraction { risk_percent, stop } => write!(f, "fixed_fraction {}% at a stop of {}", risk_percent, stop)?,
+            SizingMethod::VolatilityTarget { volatility_percent } => write!(f, "volatility_target {}% per ATR", volatility_percent)?,
+        }
+        if self.needs_atr() {
+            write!(f, ", atr_period={}", self.atr_period)?;
+        }
+        write!(
+            f,
+            ", lots {}..{}, rounding={}",
+            self.min_lots,
+            self.max_lots.map_or("-".to_string(), |lots| lots.to_string()),
+            self.rounding.as_str()
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn input(atr: Option<f64>) -> SizingInput {
+        SizingInput { equity: 1_000_000.0, price: 250.0, lot_size: 10, atr, affordable_lots: None }
+    }
+
+
+    fn sizer(method: SizingMethod) -> PositionSizer {
+        PositionSizer { method, ..PositionSizer::fixed(1) }
+    }
+
+
+    #[test]
+    fn fixed_fraction_loses_the_risk_percent_at_the_stop() {
+        let sizer = sizer(SizingMethod::FixedFraction { risk_percent: 1.0, stop: Offset::Percent(2.0) });
+        assert!(sizer.needs_equity() && !PositionSizer::fixed(1).needs_equity());
+        // 10 000 of risk over a stop of 5 per unit, 50 per lot
+        assert_eq!(sizer.target(&input(None)), Some(200.0));
+    }
+
+
+    #[test]
+    fn volatility_target_waits_for_the_atr() {
+        let sizer = sizer(SizingMethod::VolatilityTarget { volatility_percent: 0.5 });
+        assert!(sizer.needs_atr());
+        assert_eq!(sizer.target(&input(None)), None);
+        assert_eq!(sizer.lots_tracked("SBER", &input(None), &mut LotResiduals::default()), sizer.min_lots);
+        // 5 000 per ATR of 4 per unit, 40 per lot
+        assert_eq!(sizer.target(&input(Some(4.0))), Some(125.0));
+    }
+
+
+    #[test]
+    fn entry_is_clamped_to_the_limits_and_the_affordable_lots() {
+        let sizer = PositionSizer { max_lots: Some(5), ..PositionSizer::fixed(10) };
+        let mut residuals = LotResiduals::default();
+        assert_eq!(sizer.lots_tracked("SBER", &input(None), &mut residuals), 5);
+
+        let input = SizingInput { affordable_lots: Some(3), ..input(None) };
+        assert_eq!(sizer.lots_tracked("SBER", &input, &mut residuals), 3);
+        // Even below min_lots
+        let sizer = PositionSizer { min_lots: 4, ..sizer };
+        assert_eq!(sizer.lots_tracked("SBER", &input, &mut residuals), 3);
+    }
+
+
+    #[test]
+    fn rounding_residual_is_carried_to_the_next_entry() {
+        let mut residuals = LotResiduals::default();
+        assert_eq!(residuals.round("SBER", 1.6, 100.0, Rounding::Floor), 1);
+        assert_eq!(residuals.round("SBER", 1.6, 100.0, Rounding::Floor), 2);
+        assert!((residuals.residual("SBER") - 20.0).abs() < 1e-9);
+        // Per instrument
+        assert_eq!(residuals.round("GAZP", 1.6, 100.0, Rounding::Nearest), 2);
+        assert!((residuals.residual("GAZP") + 40.0).abs() < 1e-9);
+    }
+
+
+    #[test]
+    fn invalid_sizing_is_rejected() {
+        assert!(PositionSizer::fixed(0).validate().is_err());
+        assert!(PositionSizer { max_lots: Some(1), min_lots: 2, ..PositionSizer::fixed(2) }.validate().is_err());
+        assert!(sizer(SizingMethod::VolatilityTarget { volatility_percent: 0.0 }).validate().is_err());
+        assert!(PositionSizer::fixed(1).validate().is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+use chrono::{DateTime, Utc};
+use crate::orderbook::OrderBook;
+use crate::psql::Evaluation;
+
+
+/// Maximum spread allowed for a market entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaxSpread {
+    /// Number of price steps of the instrument.
+    Ticks(f64),
+    /// Percent of the mid price.
+    Percent(f64),
+}
+
+
+impl MaxSpread {
+    /// Parses a limit like `3ticks` or `0.2%`.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let value = value.trim().to_lowercase();
+
+        let (number, limit): (&str, fn(f64) -> MaxSpread) = if let Some(number) = value.strip_suffix('%') {
+            (number, MaxSpread::Percent)
+        } else if let Some(number) = value.strip_suffix("ticks").or_else(|| value.strip_suffix("tick")) {
+            (number, MaxSpread::Ticks)
+        } else {
+            return Err(format!("max_spread '{}' must end with 'ticks' or '%'", value).into());
+        };
+
+        let number: f64 = number.trim().parse().map_err(|e| format!("max_spread '{}': {}", value, e))?;
+        if number <= 0.0 {
+            return Err(format!("max_spread '{}' must be positive", value).into());
+        }
+
+        Ok(limit(number))
+    }
+
+
+    /// Limit in price units, `None` for a limit in ticks without the price step.
+    pub fn distance(&self, mid_price: f64, price_step: Option<f64>) -> Option<f64> {
+        match self {
+            MaxSpread::Ticks(ticks) => price_step.map(|step| step * ticks),
+            MaxSpread::Percent(percent) => Some(mid_price * percent / 100.0),
+        }
+    }
+}
+
+
+impl fmt::Display for MaxSpread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaxSpread::Ticks(ticks) => write!(f, "{}ticks", ticks),
+            MaxSpread::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+
+/// Source of the spread estimate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpreadSource {
+    /// Best ask minus best bid of the order book.
+    OrderBook,
+    /// High minus low of the trades of the last minute, when the order book is not available.
+    RecentRange,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spread {
+    pub value: f64,
+    pub mid_price: f64,
+    pub source: SpreadSource,
+}
+
+
+/// Market entry skipped because of the spread.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpreadSkip {
+    pub spread: Spread,
+    pub limit: MaxSpread,
+    /// Limit in price units.
+    pub max_value: f64,
+}
+
+
+impl SpreadSkip {
+    pub const REASON_CODE: &'static str = "max_spread";
+
+
+    /// Record of the skip in the evaluation audit.
+    pub fn evaluation(&self, instrument_code: &str, candle_time: DateTime<Utc>) -> Evaluation {
+        Evaluation {
+            instrument_code: instrument_code.to_string(),
+            candle_time,
+            indicators: vec![
+                ("spread".to_string(), self.spread.value),
+                ("max_spread".to_string(), self.max_value),
+            ],
+            decision: "skip".to_string(),
+            reason_code: Self::REASON_CODE.to_string(),
+        }
+    }
+}
+
+
+impl fmt::Display for SpreadSkip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "spread {} by {:?} exceeds {} ({})",
+            self.spread.value, self.spread.source, self.max_value, self.limit
+        )
+    }
+}
+
+
+/// Spread guard of an instrument: skips market entries while the spread is wider than
+/// the `max_spread` setting, e.g. in a thin market right after the opening auction.
+///
+/// The spread is taken from the order book; without it the high/low range of the trades
+/// of the last minute is used as an upper estimate. An entry is allowed while the spread
+/// can't be estimated.
+///
+/// # Example of use
+/// ```ignore
+/// let guard = SpreadGuard::new(limit);
+/// if let Err(skip) = guard.check(book.as_ref(), recent_range, info.price_step) {
+///     info!("{}: entry is skipped, {}", settings.sec_code, skip);
+///     db.insert_evaluation(&skip.evaluation(&settings.sec_code, now)).await?;
+///     return Ok(());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct SpreadGuard {
+    limit: MaxSpread,
+}
+
+
+impl SpreadGuard {
+    pub fn new(limit: MaxSpread) -> Self {
+        SpreadGuard { limit }
+    }
+
+
+    /// Current spread from the two-sided book, otherwise from `recent_range`, the high and the
+    /// low of the trades of the last minute. `None` without both.
+    pub fn estimate(book: Option<&OrderBook>, recent_range: Option<(f64, f64)>) -> Option<Spread> {
+        if let Some((bid, ask)) = book.and_then(|book| Some((book.bids.first()?, book.asks.first()?))) {
+            return Some(Spread {
+                value: ask.price - bid.price,
+                mid_price: (ask.price + bid.price) / 2.0,
+                source: SpreadSource::OrderBook,
+            });
+        }
+
+        let (high, low) = recent_range?;
+        Some(Spread {
+            value: high - low,
+            mid_price: (high + low) / 2.0,
+            source: SpreadSource::RecentRange,
+        })
+    }
+
+
+    /// Allows a market entry or returns the reason to skip it.
+    pub fn check(&self, book: Option<&OrderBook>, recent_range: Option<(f64, f64)>, price_step: Option<f64>) -> Result<(), SpreadSkip> {
+        let Some(spread) = Self::estimate(book, recent_range) else {
+            return Ok(());
+        };
+        let Some(max_value) = self.limit.distance(spread.mid_price, price_step) else {
+            return Ok(());
+        };
+
+        // The spread of exactly the limit in ticks must pass despite the rounding of the prices
+        if spread.value - max_value > max_value * 1e-9 {
+            return Err(SpreadSkip {
+                spread,
+                limit: self.limit,
+                max_value,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Level;
+
+
+    #[test]
+    fn spread_of_the_book_is_checked_before_the_recent_range() {
+        let guard = SpreadGuard::new(MaxSpread::Ticks(3.0));
+        let book = OrderBook {
+            bids: vec![Level { price: 100.0, volume: 10.0 }],
+            asks: vec![Level { price: 100.3, volume: 10.0 }],
+        };
+
+        // Exactly 3 ticks of 0.1 passes despite the rounding of 100.3 - 100.0
+        assert!(guard.check(Some(&book), Some((101.0, 99.0)), Some(0.1)).is_ok());
+
+        // Without a two-sided book the range of the last minute is the estimate
+        let one_sided = OrderBook { bids: book.bids.clone(), asks: Vec::new() };
+        let skip = guard.check(Some(&one_sided), Some((101.0, 99.0)), Some(0.1)).unwrap_err();
+        assert_eq!(skip.spread.source, SpreadSource::RecentRange);
+        assert!((skip.max_value - 0.3).abs() < 1e-9);
+
+        // Nothing to estimate the spread by allows the entry
+        assert!(guard.check(None, None, Some(0.1)).is_ok());
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use quik_rs::quik::TradeInfo;
+use quik_rs::transaction::Side;
+use crate::config::{Config, InstrumentSettings};
+use crate::crossover::EmaCross;
+use crate::golden_cross::GoldenCross;
+use crate::hedge::SpreadHedge;
+use crate::latency::LatencyStats;
+use crate::psql::DataForEma;
+
+
+/// Names of the strategies which can be listed in `strategies` of the configuration.
+pub const STRATEGIES: [&str; 3] = [EmaCross::NAME, GoldenCross::NAME, SpreadHedge::NAME];
+
+
+/// Action requested by a strategy on a candle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Open or increase a long position by the entry quantity.
+    Buy,
+    /// Open or increase a short position by the entry quantity.
+    Sell,
+    /// Close the given fraction of the current position, e.g. 0.5 closes half of it.
+    ReducePosition(f64),
+    /// Close the whole current position.
+    ClosePosition,
+    /// The strategy evaluated the candle and decided to keep the position as it is.
+    /// Unlike the absence of an action it is recorded as a decision.
+    DoNothingExplicit,
+}
+
+
+impl Action {
+    /// Name of the decision in the evaluation audit.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Buy => "buy",
+            Action::Sell => "sell",
+            Action::ReducePosition(_) => "reduce",
+            Action::ClosePosition => "close",
+            Action::DoNothingExplicit => "hold",
+        }
+    }
+
+
+    /// Translates the action to the side and the quantity in lots of an order, given the current
+    /// position in lots (positive for long, negative for short) and the entry quantity in lots.
+    /// Returns `None` when no order is needed, e.g. closing a flat position.
+    pub fn to_order(self, position: i64, entry_lots: i64) -> Option<(Side, i64)> {
+        let closing_side = if position > 0 { Side::Sell } else { Side::Buy };
+
+        let (side, lots) = match self {
+            Action::Buy => (Side::Buy, entry_lots),
+            Action::Sell => (Side::Sell, entry_lots),
+            Action::ReducePosition(fraction) => {
+                let fraction = fraction.clamp(0.0, 1.0);
+                let lots = (position.abs() as f64 * fraction).round() as i64;
+                // A positive fraction of a non-empty position closes at least one lot
+                let lots = if fraction > 0.0 { lots.max(1) } else { lots };
+                (closing_side, lots.min(position.abs()))
+            }
+            Action::ClosePosition => (closing_side, position.abs()),
+            Action::DoNothingExplicit => return None,
+        };
+
+        (lots > 0).then_some((side, lots))
+    }
+}
+
+
+/// Action of a strategy for an instrument with the reason code of the evaluation audit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub sec_code: String,
+    pub action: Action,
+    pub reason_code: String,
+}
+
+
+/// Signal logic of a trading strategy, decoupled from the trading loop: the loop feeds
+/// the candles, the ticks and the fills of the instruments the strategy wants and turns
+/// the decisions into orders.
+///
+/// A new strategy implements the trait, is added to `STRATEGIES` and to `build`, and is
+/// enabled by its name in `strategies` of the configuration.
+pub trait Strategy: Send + Sync {
+    /// Name of the strategy, the key of its `[brackets]` and `[holding]` settings.
+    fn name(&self) -> &'static str;
+
+    /// Security codes of the instruments the strategy evaluates.
+    fn wants_instruments(&self) -> Vec<String>;
+
+    /// Length of the candles the strategy evaluates for the instrument, `None` takes
+    /// the candles of every timeframe.
+    fn timeframe(&self, _sec_code: &str) -> Option<Duration> {
+        None
+    }
+
+    /// Higher timeframes of the instrument whose candles only confirm the signals of the main one.
+    fn confirmation_timeframes(&self, _sec_code: &str) -> Vec<Duration> {
+        Vec::new()
+    }
+
+    /// Candles of its timeframe the strategy needs before its signals of the instrument are reliable.
+    fn warm_up_candles(&self, _sec_code: &str) -> usize {
+        0
+    }
+
+    /// Updates the confirmation state with a closed candle of a confirmation timeframe.
+    fn on_confirmation_candle(&mut self, _sec_code: &str, _timeframe: Duration, _candle: &DataForEma) {}
+
+    /// Evaluates a closed candle of an instrument.
+    fn on_candle(&mut self, sec_code: &str, candle: &DataForEma) -> Vec<Decision>;
+
+    /// Whether the strategy evaluates the ticks, so the trading loop reads them, see `tick_feed`.
+    fn wants_ticks(&self) -> bool {
+        false
+    }
+
+    /// Evaluates a tick of an instrument. Candle-based strategies ignore the ticks.
+    fn on_tick(&mut self, _sec_code: &str, _price: f64, _time: DateTime<Utc>) -> Vec<Decision> {
+        Vec::new()
+    }
+
+    /// Reports a fill of an order of the instrument.
+    fn on_fill(&mut self, _trade: &TradeInfo) {}
+
+    /// Starts evaluating an instrument added to the universe, with a fresh signal state.
+    fn add_instrument(&mut self, _settings: &InstrumentSettings) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Stops evaluating an instrument removed from the universe, e.g. a delisted one.
+    fn remove_instrument(&mut self, _sec_code: &str) {}
+
+    /// Signal state of the instrument persisted in `bot_state`, so a restart mid-session
+    /// continues it. `None` if the strategy keeps no state worth saving.
+    fn state(&self, _sec_code: &str) -> Option<Value> {
+        None
+    }
+
+    /// Restores the signal state of the instrument saved by `state`.
+    fn restore(&mut self, _sec_code: &str, _state: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+
+/// Builds the strategy by its name for the instruments of the watchlist, timing its stages
+/// into `latency`.
+pub fn build(name: &str, config: &Config, latency: &LatencyStats) -> Result<Box<dyn Strategy>, Box<dyn std::error::Error>> {
+    match name {
+        EmaCross::NAME => Ok(Box::new(EmaCross::new(&config.instrument_settings()?, latency.clone())?)),
+        GoldenCross::NAME => Ok(Box::new(GoldenCross::new(&config.instrument_settings()?))),
+        SpreadHedge::NAME => Ok(Box::new(SpreadHedge::new(&config.pairs))),
+        _ => Err(format!("unknown strategy '{}', expected one of: {}", name, STRATEGIES.join(", ")).into()),
+    }
+}
+
+
+/// Strategies run by the trading loop. Every event is passed only to the strategies
+/// which want its instrument.
+///
+/// # Example of use
+/// ```ignore
+/// let mut strategies = StrategySet::from_config(&config, &metrics.latency)?;
+/// for (strategy, decision) in strategies.on_candle("SBER", Duration::from_secs(900), &candle) {
+///     info!("{}: {} {} by {}", strategy, decision.sec_code, decision.action.as_str(), decision.reason_code);
+/// }
+/// ```
+pub struct StrategySet {
+    strategies: Vec<(Box<dyn Strategy>, HashSet<String>)>,
+}
+
+
+impl StrategySet {
+    pub fn new(strategies: Vec<Box<dyn Strategy>>) -> Self {
+        StrategySet {
+            strategies: strategies
+                .into_iter()
+                .map(|strategy| {
+                    let instruments = strategy.wants_instruments().into_iter().collect();
+                    (strategy, instruments)
+                })
+                .collect(),
+        }
+    }
+
+
+    /// Builds the strategies listed in `strategies` of the configuration.
+    pub fn from_config(config: &Config, latency: &LatencyStats) -> Result<Self, Box<dyn std::error::Error>> {
+        let strategies = config
+            .strategies
+            .iter()
+            .map(|name| build(name, config, latency))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self::new(strategies))
+    }
+
+
+    /// Names of the strategies in the order of `strategies` of the configuration.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.strategies.iter().map(|(strategy, _)| strategy.name()).collect()
+    }
+
+
+    /// Distinct candle lengths of the instrument wanted by the strategies, shortest first:
+    /// the trading loop builds a candle series of every length.
+    pub fn timeframes(&self, sec_code: &str) -> Vec<Duration> {
+        let mut timeframes: Vec<Duration> = self
+            .strategies
+            .iter()
+            .filter(|(_, instruments)| instruments.contains(sec_code))
+            .flat_map(|(strategy, _)| strategy.timeframe(sec_code).into_iter().chain(strategy.confirmation_timeframes(sec_code)))
+            .collect();
+        timeframes.sort();
+        timeframes.dedup();
+        timeframes
+    }
+
+
+    /// Candles the slowest of the strategies evaluating the instrument needs to warm up.
+    pub fn warm_up_candles(&self, sec_code: &str) -> usize {
+        self.strategies
+            .iter()
+            .filter(|(_, instruments)| instruments.contains(sec_code))
+            .map(|(strategy, _)| strategy.warm_up_candles(sec_code))
+            .max()
+            .unwrap_or_default()
+    }
+
+
+    /// Security codes wanted by any of the strategies, sorted.
+    pub fn instruments(&self) -> Vec<String> {
+        let instruments: HashSet<&String> = self.strategies.iter().flat_map(|(_, instruments)| instruments).collect();
+        let mut instruments: Vec<String> = instruments.into_iter().cloned().collect();
+        instruments.sort();
+        instruments
+    }
+
+
+    /// Security codes wanted by the strategy, sorted; empty for an unknown strategy.
+    pub fn instruments_of(&self, name: &str) -> Vec<String> {
+        let mut instruments: Vec<String> = self
+            .strategies
+            .iter()
+            .filter(|(strategy, _)| strategy.name() == name)
+            .flat_map(|(_, instruments)| instruments.iter().cloned())
+            .collect();
+        instruments.sort();
+        instruments
+    }
+
+
+    /// Passes the candle of the timeframe to the strategies which evaluate it or confirm
+    /// their signals with it. When several timeframes close at once the longer ones must be
+    /// passed first, so the confirmations are up to date.
+    pub fn on_candle(&mut self, sec_code: &str, timeframe: Duration, candle: &DataForEma) -> Vec<(&'static str, Decision)> {
+        self.dispatch(sec_code, |strategy| {
+            if strategy.timeframe(sec_code).is_none_or(|wanted| wanted == timeframe) {
+                strategy.on_candle(sec_code, candle)
+            } else {
+                if strategy.confirmation_timeframes(sec_code).contains(&timeframe) {
+                    strategy.on_confirmation_candle(sec_code, timeframe, candle);
+                }
+                Vec::new()
+            }
+        })
+    }
+
+
+    /// Adds an instrument of the universe to every strategy.
+    pub fn add_instrument(&mut self, settings: &InstrumentSettings) -> Result<(), Box<dyn std::error::Error>> {
+        for (strategy, instruments) in &mut self.strategies {
+            strategy.add_instrument(settings).map_err(|e| format!("{}: {}: {}", strategy.name(), settings.sec_code, e))?;
+            *instruments = strategy.wants_instruments().into_iter().collect();
+        }
+        Ok(())
+    }
+
+
+    /// Removes an instrument from every strategy.
+    pub fn remove_instrument(&mut self, sec_code: &str) {
+        for (strategy, instruments) in &mut self.strategies {
+            strategy.remove_instrument(sec_code);
+            instruments.remove(sec_code);
+        }
+    }
+
+
+    /// Signal states of the instrument by strategy, for `bot_state`.
+    pub fn states(&self, sec_code: &str) -> Vec<(&'static str, Value)> {
+        self.strategies
+            .iter()
+            .filter(|(_, instruments)| instruments.contains(sec_code))
+            .filter_map(|(strategy, _)| strategy.state(sec_code).map(|state| (strategy.name(), state)))
+            .collect()
+    }
+
+
+    /// Length of the candles the strategy evaluates for the instrument, the shortest timeframe
+    /// of the instrument for a strategy taking the candles of every timeframe. `None` if the
+    /// strategy doesn't run or doesn't evaluate the instrument.
+    pub fn timeframe(&self, strategy: &str, sec_code: &str) -> Option<Duration> {
+        let (running, _) = self
+            .strategies
+            .iter()
+            .find(|(running, instruments)| running.name() == strategy && instruments.contains(sec_code))?;
+        running.timeframe(sec_code).or_else(|| self.timeframes(sec_code).first().copied())
+    }
+
+
+    /// Restores the signal state of the instrument in the strategy, if it runs and evaluates it.
+    /// Returns whether the state was restored.
+    pub fn restore(&mut self, strategy: &str, sec_code: &str, state: &Value) -> Result<bool, Box<dyn std::error::Error>> {
+        for (running, instruments) in &mut self.strategies {
+            if running.name() == strategy && instruments.contains(sec_code) {
+                running.restore(sec_code, state)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+
+    /// Whether any of the strategies evaluates the ticks.
+    pub fn wants_ticks(&self) -> bool {
+        self.strategies.iter().any(|(strategy, _)| strategy.wants_ticks())
+    }
+
+
+    /// Passes the tick to the strategies which evaluate the ticks of the instrument.
+    pub fn on_tick(&mut self, sec_code: &str, price: f64, time: DateTime<Utc>) -> Vec<(&'static str, Decision)> {
+        self.dispatch(sec_code, |strategy| {
+            if strategy.wants_ticks() {
+                strategy.on_tick(sec_code, price, time)
+            } else {
+                Vec::new()
+            }
+        })
+    }
+
+
+    pub fn on_fill(&mut self, trade: &TradeInfo) {
+        for (strategy, instruments) in &mut self.strategies {
+            if instruments.contains(&trade.sec_code) {
+                strategy.on_fill(trade);
+            }
+        }
+    }
+
+
+    fn dispatch(&mut self, sec_code: &str, mut f: impl FnMut(&mut dyn Strategy) -> Vec<Decision>) -> Vec<(&'static str, Decision)> {
+        let mut decisions = Vec::new();
+        for (strategy, instruments) in &mut self.strategies {
+            if instruments.contains(sec_code) {
+                let name = strategy.name();
+                decisions.extend(f(strategy.as_mut()).into_iter().map(|decision| (name, decision)));
+            }
+        }
+        decisions
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// Buys the instrument on a tick at or above the level.
+    struct Breakout {
+        sec_code: String,
+        level: f64,
+    }
+
+
+    impl Strategy for Breakout {
+        fn name(&self) -> &'static str {
+            "breakout"
+        }
+
+        fn wants_instruments(&self) -> Vec<String> {
+            vec![self.sec_code.clone()]
+        }
+
+        fn on_candle(&mut self, _sec_code: &str, _candle: &DataForEma) -> Vec<Decision> {
+            Vec::new()
+        }
+
+        fn wants_ticks(&self) -> bool {
+            true
+        }
+
+        fn on_tick(&mut self, sec_code: &str, price: f64, _time: DateTime<Utc>) -> Vec<Decision> {
+            if price < self.level {
+                return Vec::new();
+            }
+            vec![Decision { sec_code: sec_code.to_string(), action: Action::Buy, reason_code: "breakout".to_string() }]
+        }
+    }
+
+
+    #[test]
+    fn ticks_reach_only_the_strategies_of_the_instrument() {
+        let mut strategies = StrategySet::new(vec![Box::new(Breakout { sec_code: "SBER".to_string(), level: 300.0 })]);
+        let now = Utc::now();
+
+        assert!(strategies.wants_ticks());
+        assert!(strategies.on_tick("SBER", 299.5, now).is_empty());
+        assert!(strategies.on_tick("GAZP", 310.0, now).is_empty());
+
+        let decisions = strategies.on_tick("SBER", 300.5, now);
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions[0].0, "breakout");
+        assert_eq!(decisions[0].1.sec_code, "SBER");
+        assert_eq!(decisions[0].1.action, Action::Buy);
+
+        strategies.remove_instrument("SBER");
+        assert!(strategies.on_tick("SBER", 301.0, now).is_empty());
+    }
+}
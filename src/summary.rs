@@ -0,0 +1,116 @@
+use std::fmt;
+use crate::config::{Backend, Config, InstrumentSettings, RunMode};
+use crate::psql;
+use crate::retry::RetryPolicy;
+use crate::sizing::PositionSizer;
+
+
+/// Structured summary of the session configuration, emitted once at startup,
+/// so every session records unambiguously what it was started with.
+#[derive(Debug, Clone)]
+pub struct StartupSummary {
+    pub mode: RunMode,
+    pub backend: Backend,
+    pub path_to_lib: String,
+    pub path_to_quik: String,
+    pub schema_version: i32,
+    pub max_transactions_per_second: f64,
+    pub transaction_burst: u32,
+    pub strategies: Vec<String>,
+    /// Notification channels of the strategies routed separately.
+    pub notifications: Vec<(String, Vec<String>)>,
+    pub instruments: Vec<InstrumentSettings>,
+    pub custom_indicators: Vec<(String, String)>,
+    pub sizing: Option<PositionSizer>,
+    pub retry: RetryPolicy,
+}
+
+
+impl StartupSummary {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(StartupSummary {
+            mode: config.mode,
+            backend: config.backend,
+            path_to_lib: config.path_to_lib.clone(),
+            path_to_quik: config.path_to_quik.clone(),
+            schema_version: psql::SCHEMA_VERSION,
+            max_transactions_per_second: config.max_transactions_per_second,
+            transaction_burst: config.transaction_burst,
+            strategies: config.strategies.clone(),
+            notifications: {
+                let mut notifications: Vec<(String, Vec<String>)> = config.notifications.clone().into_iter().collect();
+                notifications.sort();
+                notifications
+            },
+            instruments: config.instrument_settings()?,
+            custom_indicators: config.custom_indicators.clone(),
+            sizing: config.sizing,
+            retry: config.retry.clone(),
+        })
+    }
+}
+
+
+impl fmt::Display for StartupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Startup summary")?;
+        writeln!(f, "  mode: {:?}", self.mode)?;
+        writeln!(f, "  backend: {:?}", self.backend)?;
+        writeln!(f, "  library: {}", self.path_to_lib)?;
+        writeln!(f, "  terminal: {}", self.path_to_quik)?;
+        writeln!(f, "  schema version: {}", self.schema_version)?;
+        writeln!(f, "  transaction limit: {}/s, burst {}", self.max_transactions_per_second, self.transaction_burst)?;
+        writeln!(f, "  strategies: {}", self.strategies.join(", "))?;
+        for (strategy, channels) in &self.notifications {
+            writeln!(f, "    {} notifies [{}]", strategy, channels.join(", "))?;
+        }
+
+        writeln!(f, "  sizing: {}", self.sizing.map_or("-".to_string(), |sizer| sizer.to_string()))?;
+        writeln!(f, "  retry: {}", self.retry)?;
+        writeln!(f, "  instruments: {}", self.instruments.len())?;
+        for instrument in &self.instruments {
+            let windows: Vec<String> = instrument
+                .trading_windows
+                .iter()
+                .map(|window| format!("{}-{}", window.start.format("%H:%M"), window.end.format("%H:%M")))
+                .collect();
+
+            writeln!(
+                f,
+                "    {}.{} group={} enabled={} watch_only={} windows=[{}] risk_budget={} timeframe={}m ema_pairs=[{}]{} hysteresis={}%{} min_slope={} confirm_candles={} confirmation={} existing_positions={} max_spread={} max_position_age={} daily_profit_target={} cooldown={} max_round_trips={} expiry_guard={}{}",
+                instrument.class_code,
+                instrument.sec_code,
+                instrument.group.as_deref().unwrap_or("-"),
+                instrument.enabled,
+                instrument.watch_only,
+                windows.join(", "),
+                instrument.risk_budget.map_or("-".to_string(), |budget| budget.to_string()),
+                instrument.timeframe_minutes,
+                instrument.ema_pairs.iter().map(|pair| pair.to_string()).collect::<Vec<_>>().join(", "),
+                if instrument.ema_trend_filter { " trend_filter" } else { "" },
+                instrument.ema_hysteresis,
+                if instrument.ema_price_filter { " price_filter" } else { "" },
+                instrument.ema_min_slope.map_or("-".to_string(), |slope| format!("{}%", slope)),
+                instrument.ema_confirm_candles,
+                instrument
+                    .confirmation()
+                    .map_or("-".to_string(), |(timeframe, pair)| format!("{}m {}", timeframe.as_secs() / 60, pair)),
+                instrument.existing_positions.as_str(),
+                instrument.max_spread.map_or("-".to_string(), |limit| limit.to_string()),
+                instrument.max_position_age_hours.map_or("-".to_string(), |hours| format!("{}h", hours)),
+                instrument.daily_profit_target.map_or("-".to_string(), |target| target.to_string()),
+                instrument.cooldown_candles.map_or("-".to_string(), |candles| format!("{}candles", candles)),
+                instrument.max_round_trips_per_day.map_or("-".to_string(), |trips| format!("{}/day", trips)),
+                instrument.expiry_guard_days.map_or("-".to_string(), |days| format!("{}days", days)),
+                if instrument.roll_on_expiry { " roll" } else { "" },
+            )?;
+        }
+
+        writeln!(f, "  custom indicators: {}", self.custom_indicators.len())?;
+        for (name, expression) in &self.custom_indicators {
+            writeln!(f, "    {} = {}", name, expression)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use libc::c_ulong;
+use tokio::sync::{mpsc, watch};
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+use quik_rs::quik::{OrderInfo, QuikApi, QuikEvent, Trans2quikResult};
+use quik_rs::transaction::Transaction;
+use crate::gateway::OrderGateway;
+use crate::instrument::InstrumentStates;
+use crate::psql::{Db, IncidentKind, Severity};
+use crate::retry::RetryPolicy;
+
+
+/// Interval between the connection checks.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+
+/// State of the connection between the bot, the QUIK terminal and the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connected,
+}
+
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Connected => "connected",
+        }
+    }
+}
+
+
+/// Orders sent by the bot, recognized by their TRANS_ID. The book is marked dirty when
+/// the connection is lost and clean again after the dead-man switch reconciled the orders.
+#[derive(Debug, Default)]
+pub struct KnownOrders {
+    trans_ids: Mutex<HashSet<c_ulong>>,
+    dirty: AtomicBool,
+}
+
+
+impl KnownOrders {
+    pub fn register(&self, trans_id: c_ulong) {
+        self.trans_ids.lock().unwrap_or_else(|e| e.into_inner()).insert(trans_id);
+    }
+
+
+    pub fn is_known(&self, trans_id: c_ulong) -> bool {
+        self.trans_ids.lock().unwrap_or_else(|e| e.into_inner()).contains(&trans_id)
+    }
+
+
+    /// The state of the orders is unknown since the connection was lost.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::SeqCst)
+    }
+}
+
+
+/// Dead-man switch: after a reconnection the active orders of the snapshot which the bot
+/// doesn't recognize are cancelled.
+struct DeadManSwitch {
+    known_orders: Arc<KnownOrders>,
+    gateway: Arc<OrderGateway>,
+}
+
+
+/// The `ConnectionSupervisor` keeps the connection to the QUIK terminal alive.
+///
+/// It listens to the connection status callbacks, periodically polls `is_dll_connected`
+/// and `is_quik_connected`, reconnects with exponential backoff when the DLL or the server
+/// link drops and subscribes to orders and trades again after every reconnection.
+///
+/// # Example of use
+/// ```ignore
+/// let events = terminal.events();
+/// terminal.set_connection_status_callback()?;
+/// let (state, mut connection) = watch::channel(ConnectionState::Disconnected);
+/// let supervisor = ConnectionSupervisor::new(terminal.clone(), vec![("TQBR".into(), "SBER|GAZP".into())]).with_state(state);
+/// tokio::spawn(supervisor.run(events));
+/// connection.wait_for(|state| *state == ConnectionState::Connected).await?;
+/// ```
+///
+/// With `with_dead_man_switch` the book is marked dirty when the connection is lost, and after
+/// the reconnection the active orders of the initial snapshot which are not in `KnownOrders`
+/// are cancelled.
+///
+/// With `with_subscription_updates` the subscriptions follow the instrument universe: on every
+/// update the terminal is unsubscribed and subscribed to the new list.
+pub struct ConnectionSupervisor {
+    terminal: Arc<dyn QuikApi>,
+
+    /// Pairs of the class code and the securities codes to subscribe to after connecting.
+    subscriptions: Vec<(String, String)>,
+
+    /// Delays between the reconnection attempts, retried until the connection is established.
+    retry: RetryPolicy,
+
+    state: watch::Sender<ConnectionState>,
+
+    dead_man_switch: Option<DeadManSwitch>,
+
+    /// New lists of subscriptions, e.g. from the refresh of the instrument universe.
+    subscription_updates: Option<watch::Receiver<Vec<(String, String)>>>,
+
+    /// Readiness of the instruments, whose subscriptions are recorded in it.
+    instruments: InstrumentStates,
+}
+
+
+impl ConnectionSupervisor {
+    pub fn new(terminal: Arc<dyn QuikApi>, subscriptions: Vec<(String, String)>) -> Self {
+        let (state, _) = watch::channel(ConnectionState::Disconnected);
+
+        ConnectionSupervisor {
+            terminal,
+            subscriptions,
+            retry: RetryPolicy::default().until_success(),
+            state,
+            dead_man_switch: None,
+            subscription_updates: None,
+            instruments: InstrumentStates::default(),
+        }
+    }
+
+
+    /// Delays of the reconnection from the policy, the attempts are never limited.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry.until_success();
+        self
+    }
+
+
+    /// Cancels the unrecognized active orders after every reconnection. The orders sent
+    /// through the gateway are recognized, the cancellations are sent through it as well.
+    pub fn with_dead_man_switch(mut self, gateway: Arc<OrderGateway>) -> Self {
+        self.dead_man_switch = Some(DeadManSwitch { known_orders: gateway.known_orders(), gateway });
+        self
+    }
+
+
+    /// Replaces the subscriptions with every list received from `updates`.
+    pub fn with_subscription_updates(mut self, updates: watch::Receiver<Vec<(String, String)>>) -> Self {
+        self.subscription_updates = Some(updates);
+        self
+    }
+
+
+    /// Records the subscriptions of the instruments in their readiness.
+    pub fn with_readiness(mut self, instruments: InstrumentStates) -> Self {
+        self.instruments = instruments;
+        self
+    }
+
+
+    /// Reports the connection state through `state`, which outlives a supervisor restarted
+    /// by the watchdog.
+    pub fn with_state(mut self, state: watch::Sender<ConnectionState>) -> Self {
+        self.state = state;
+        self
+    }
+
+
+    /// Runs the supervisor until the task is cancelled. Only the connection events
+    /// of the terminal and, with the dead-man switch, the order events are taken into account.
+    pub async fn run(mut self, mut events: mpsc::UnboundedReceiver<QuikEvent>) {
+        let mut events_open = true;
+        let mut updates = self.subscription_updates.take();
+
+        // A terminal connected before the start is subscribed as after a reconnection
+        if self.is_connected() {
+            self.resubscribe();
+            self.set_state(ConnectionState::Connected);
+        }
+
+        loop {
+            if !self.is_connected() {
+                self.set_disconnected();
+                self.reconnect().await;
+            }
+
+            tokio::select! {
+                event = events.recv(), if events_open => match event {
+                    Some(QuikEvent::ConnectionStatus(event)) => match event.status {
+                        Trans2quikResult::QuikDisconnected | Trans2quikResult::DllDisconnected => {
+                            warn!("Connection lost: {:?}, {}", event.status, event.info_message);
+                            self.set_disconnected();
+                        }
+                        _ => info!("Connection event: {:?}, {}", event.status, event.info_message),
+                    },
+                    Some(QuikEvent::OrderUpdate(order)) => self.reconcile(&order),
+                    Some(_) => {}
+                    // The callback channel is closed, rely on polling only
+                    None => events_open = false,
+                },
+                changed = async { updates.as_mut()?.changed().await.ok() }, if updates.is_some() => match changed {
+                    Some(()) => {
+                        let subscriptions = updates.as_mut().map(|updates| updates.borrow_and_update().clone()).unwrap_or_default();
+                        self.update_subscriptions(subscriptions);
+                    }
+                    // The universe is not refreshed anymore, keep the current subscriptions
+                    None => updates = None,
+                },
+                _ = sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+
+    /// Checks both the link between the DLL and the terminal and the link between the terminal and the server.
+    fn is_connected(&self) -> bool {
+        let dll_connected = matches!(self.terminal.is_dll_connected(), Ok(Trans2quikResult::DllConnected));
+        let quik_connected = matches!(self.terminal.is_quik_connected(), Ok(Trans2quikResult::QuikConnected));
+
+        dll_connected && quik_connected
+    }
+
+
+    /// Reconnects to the terminal with exponential backoff until the connection is established.
+    async fn reconnect(&self) {
+        let _ = self.retry.run("quik_reconnect", || std::future::ready(self.try_connect()), |_| true).await;
+
+        self.resubscribe();
+        self.set_state(ConnectionState::Connected);
+        info!("Connection to QUIK is established");
+    }
+
+
+    fn try_connect(&self) -> Result<(), String> {
+        match self.terminal.connect() {
+            Ok(Trans2quikResult::Success) | Ok(Trans2quikResult::AlreadyConnectedToQuik) if self.is_connected() => Ok(()),
+            Ok(result) => Err(format!("reconnection to QUIK failed: {:?}", result)),
+            Err(e) => Err(format!("reconnection to QUIK failed: {}", e)),
+        }
+    }
+
+
+    /// Subscribes to orders and trades and starts receiving them, if the library supports them.
+    fn resubscribe(&self) {
+        let capabilities = self.terminal.capabilities();
+
+        for (class_code, sec_codes) in &self.subscriptions {
+            let mut subscribed = true;
+            if capabilities.orders {
+                if let Err(e) = self.terminal.subscribe_orders(class_code, sec_codes) {
+                    warn!("Error subscribing to orders of {} {}: {}", class_code, sec_codes, e);
+                    subscribed = false;
+                }
+            }
+            if capabilities.trades {
+                if let Err(e) = self.terminal.subscribe_trades(class_code, sec_codes) {
+                    warn!("Error subscribing to trades of {} {}: {}", class_code, sec_codes, e);
+                    subscribed = false;
+                }
+            }
+            for sec_code in sec_codes.split('|') {
+                self.instruments.record_subscribed(sec_code, subscribed);
+            }
+        }
+
+        if capabilities.orders {
+            if let Err(e) = self.terminal.start_orders() {
+                warn!("Error starting orders: {}", e);
+            }
+        }
+        if capabilities.trades {
+            if let Err(e) = self.terminal.start_trades() {
+                warn!("Error starting trades: {}", e);
+            }
+        }
+    }
+
+
+    /// Replaces the subscriptions and, while connected, resubscribes the terminal to them.
+    fn update_subscriptions(&mut self, subscriptions: Vec<(String, String)>) {
+        if subscriptions == self.subscriptions {
+            return;
+        }
+        info!("Subscriptions are updated: {:?}", subscriptions);
+        self.subscriptions = subscriptions;
+        if *self.state.borrow() != ConnectionState::Connected {
+            return;
+        }
+
+        let capabilities = self.terminal.capabilities();
+        if capabilities.orders {
+            if let Err(e) = self.terminal.unsubscribe_orders() {
+                warn!("Error unsubscribing from orders: {}", e);
+            }
+        }
+        if capabilities.trades {
+            if let Err(e) = self.terminal.unsubscribe_trades() {
+                warn!("Error unsubscribing from trades: {}", e);
+            }
+        }
+        self.resubscribe();
+    }
+
+
+    /// Marks the book dirty if the connection was up, since orders may change unobserved.
+    fn set_disconnected(&self) {
+        if *self.state.borrow() == ConnectionState::Connected {
+            if let Some(switch) = &self.dead_man_switch {
+                switch.known_orders.dirty.store(true, Ordering::SeqCst);
+                warn!("Order book is marked dirty, unknown orders will be cancelled after the reconnection");
+            }
+        }
+        for sec_code in self.subscriptions.iter().flat_map(|(_, sec_codes)| sec_codes.split('|')) {
+            self.instruments.record_subscribed(sec_code, false);
+        }
+        self.set_state(ConnectionState::Disconnected);
+    }
+
+
+    /// Cancels an active order of the initial snapshot unknown to the bot while the book is dirty.
+    /// The end of the snapshot marks the book clean.
+    fn reconcile(&self, order: &OrderInfo) {
+        let Some(switch) = &self.dead_man_switch else {
+            return;
+        };
+        if !switch.known_orders.is_dirty() {
+            return;
+        }
+
+        match order.mode {
+            // An order of the initial snapshot, 1 - active
+            1 if order.status == 1 && !switch.known_orders.is_known(order.trans_id) => {
+                let kill = Transaction::kill_order(&order.class_code, &order.sec_code, order.order_num);
+                match switch.gateway.send(kill) {
+                    Ok(trans_id) => warn!(
+                        "Unknown order {} {} is cancelled by the dead-man switch, TRANS_ID {}",
+                        order.order_num, order.sec_code, trans_id
+                    ),
+                    Err(e) => error!("Unknown order {} is not cancelled: {}", order.order_num, e),
+                }
+            }
+            // The end of the initial snapshot
+            2 => {
+                switch.known_orders.dirty.store(false, Ordering::SeqCst);
+                info!("Order book is reconciled");
+            }
+            _ => {}
+        }
+    }
+
+
+    fn set_state(&self, state: ConnectionState) {
+        self.state.send_if_modified(|current| {
+            let modified = *current != state;
+            *current = state;
+            modified
+        });
+    }
+}
+
+
+/// Records every loss of the connection as a critical `disconnect` incident and resolves it
+/// after the reconnection, until the state is closed.
+pub async fn record_disconnects(db: Arc<Db>, mut state: watch::Receiver<ConnectionState>) {
+    let mut incident = None;
+
+    while state.changed().await.is_ok() {
+        let current = *state.borrow_and_update();
+        match (current, incident) {
+            (ConnectionState::Disconnected, None) => {
+                match db.insert_incident(Severity::Critical, IncidentKind::Disconnect, None, "connection to QUIK is lost").await {
+                    Ok(id) => incident = Some((id, Instant::now())),
+                    Err(e) => error!("Error recording the disconnect: {}", e),
+                }
+            }
+            (ConnectionState::Connected, Some((id, since))) => {
+                let resolution = format!("reconnected after {:.0?}", since.elapsed());
+                if let Err(e) = db.resolve_incident(id, &resolution).await {
+                    error!("Error resolving the disconnect {}: {}", id, e);
+                }
+                incident = None;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use crate::domain::ToJson;
+
+
+/// Health of the evaluation of an instrument: its candle query and its strategies.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskHealth {
+    /// Close of the last candle evaluated successfully.
+    pub last_ok: Option<DateTime<Utc>>,
+    /// Failures since the start of the bot.
+    pub failures: u64,
+    /// Failures since the last success.
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// The strategies panicked on the instrument, which is not evaluated anymore.
+    pub stopped: bool,
+}
+
+
+impl TaskHealth {
+    pub fn status(&self) -> &'static str {
+        if self.stopped {
+            "stopped"
+        } else if self.consecutive_failures > 0 {
+            "failing"
+        } else {
+            "ok"
+        }
+    }
+}
+
+
+impl ToJson for TaskHealth {
+    fn to_json(&self) -> Value {
+        json!({
+            "status": self.status(),
+            "last_ok": self.last_ok.map(|time| time.to_rfc3339()),
+            "failures": self.failures,
+            "consecutive_failures": self.consecutive_failures,
+            "last_error": self.last_error,
+        })
+    }
+}
+
+
+/// Health of the evaluation tasks of the trading loop by instrument, shared by the loop and
+/// the dashboard. The clones share the health.
+#[derive(Debug, Clone, Default)]
+pub struct TaskHealthStats {
+    tasks: Arc<Mutex<BTreeMap<String, TaskHealth>>>,
+}
+
+
+impl TaskHealthStats {
+    /// Records the successful evaluation of the candle of the instrument closed at `closed_at`.
+    pub fn record_ok(&self, sec_code: &str, closed_at: DateTime<Utc>) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let task = tasks.entry(sec_code.to_string()).or_default();
+        task.last_ok = Some(closed_at);
+        task.consecutive_failures = 0;
+    }
+
+
+    /// Records a failed evaluation of the instrument, `stopped` if it is not evaluated anymore.
+    pub fn record_failure(&self, sec_code: &str, error: &str, stopped: bool) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let task = tasks.entry(sec_code.to_string()).or_default();
+        task.failures += 1;
+        task.consecutive_failures = task.consecutive_failures.saturating_add(1);
+        task.last_error = Some(error.to_string());
+        task.stopped |= stopped;
+    }
+
+
+    /// Forgets an instrument removed from the universe.
+    pub fn remove(&self, sec_code: &str) {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner()).remove(sec_code);
+    }
+
+
+    /// Health of the evaluation tasks by instrument, e.g. for the dashboard.
+    pub fn stats(&self) -> BTreeMap<String, TaskHealth> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use crate::portfolio::{self, InstrumentPosition, MoneyFlows};
+use crate::psql::{Db, Fill};
+
+
+/// Trade statistics of the period. A closed trade is a fill reducing a position,
+/// its profit is measured against the average price of the position.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TradeStats {
+    pub fills: usize,
+    pub closed_trades: usize,
+    pub winning_trades: usize,
+    pub gross_profit: f64,
+    pub gross_loss: f64,
+    pub largest_win: f64,
+    pub largest_loss: f64,
+    pub commission: f64,
+    /// Estimated slippage against the expected prices of the fills, already in the gross profit.
+    pub slippage: f64,
+    pub net_pnl: f64,
+    pub max_drawdown: f64,
+}
+
+
+impl TradeStats {
+    /// Gross profit, commissions and slippage of the closed trades.
+    pub fn money_flows(&self) -> MoneyFlows {
+        MoneyFlows {
+            gross_pnl: self.gross_profit + self.gross_loss,
+            commission: self.commission,
+            slippage: self.slippage,
+        }
+    }
+
+
+    pub fn win_rate(&self) -> Option<f64> {
+        (self.closed_trades > 0).then(|| self.winning_trades as f64 / self.closed_trades as f64 * 100.0)
+    }
+
+
+    pub fn profit_factor(&self) -> Option<f64> {
+        (self.gross_loss < 0.0).then(|| self.gross_profit / -self.gross_loss)
+    }
+
+
+    pub fn average_win(&self) -> Option<f64> {
+        (self.winning_trades > 0).then(|| self.gross_profit / self.winning_trades as f64)
+    }
+
+
+    pub fn average_loss(&self) -> Option<f64> {
+        let losing_trades = self.closed_trades - self.winning_trades;
+        (losing_trades > 0).then(|| self.gross_loss / losing_trades as f64)
+    }
+}
+
+
+/// Performance report of a strategy for a period built from the stored fills: equity curve,
+/// drawdown, monthly returns and trade statistics. Rendered as a standalone HTML page
+/// which can be shared as is or printed to PDF from a browser.
+///
+/// # Example of use
+/// ```ignore
+/// let sheet = TearSheet::load(&db, "ema_cross", from, to, 1_000_000.0).await?;
+/// std::fs::write("tear-sheet.html", sheet.render_html(SystemClock.now()))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct TearSheet {
+    pub strategy: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Capital the returns are calculated on.
+    pub capital: f64,
+    /// Realized profit net of commissions after every fill.
+    pub equity: Vec<(DateTime<Utc>, f64)>,
+    /// Distance from the previous equity peak, zero or negative.
+    pub drawdown: Vec<(DateTime<Utc>, f64)>,
+    /// Realized profit of every month in percent of the capital at the start of the month.
+    pub monthly_returns: BTreeMap<(i32, u32), f64>,
+    pub stats: TradeStats,
+}
+
+
+impl TearSheet {
+    /// Loads the fills of `strategy` from `from` until the end of `to`.
+    pub async fn load(db: &Db, strategy: &str, from: NaiveDate, to: NaiveDate, capital: f64) -> Result<Self, Box<dyn std::error::Error>> {
+        if to < from {
+            return Err(format!("the period {} - {} is empty", from, to).into());
+        }
+        if capital <= 0.0 {
+            return Err(format!("capital {} must be positive", capital).into());
+        }
+
+        let start = from.and_time(Default::default()).and_utc();
+        let end = (to + chrono::Duration::days(1)).and_time(Default::default()).and_utc();
+        let fills = db.get_fills(strategy, start, end).await?;
+
+        Ok(Self::build(strategy, from, to, capital, &fills))
+    }
+
+
+    /// Replays the fills in the order of execution.
+    pub fn build(strategy: &str, from: NaiveDate, to: NaiveDate, capital: f64, fills: &[Fill]) -> Self {
+        let mut positions: HashMap<&str, InstrumentPosition> = HashMap::new();
+        let mut stats = TradeStats { fills: fills.len(), ..Default::default() };
+        let mut equity = Vec::with_capacity(fills.len());
+        let mut drawdown = Vec::with_capacity(fills.len());
+        let mut monthly_pnl: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+        let mut month_start_equity: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+        let (mut total, mut peak) = (0.0, 0.0);
+
+        for fill in fills {
+            let month = (fill.executed_at.year(), fill.executed_at.month());
+            month_start_equity.entry(month).or_insert(total);
+
+            let quantity = if fill.is_sell { -fill.quantity } else { fill.quantity };
+            let mut pnl = -fill.commission;
+            if let Some(realized) = positions.entry(fill.instrument_code.as_str()).or_default().apply(quantity, fill.price, fill.point_value) {
+                stats.closed_trades += 1;
+                if realized > 0.0 {
+                    stats.winning_trades += 1;
+                    stats.gross_profit += realized;
+                    stats.largest_win = stats.largest_win.max(realized);
+                } else {
+                    stats.gross_loss += realized;
+                    stats.largest_loss = stats.largest_loss.min(realized);
+                }
+                pnl += realized;
+            }
+            stats.commission += fill.commission;
+            stats.slippage += portfolio::slippage(fill.is_sell, fill.quantity, fill.price, fill.expected_price, fill.point_value);
+
+            total += pnl;
+            peak = f64::max(peak, total);
+            stats.max_drawdown = stats.max_drawdown.min(total - peak);
+            *monthly_pnl.entry(month).or_default() += pnl;
+            equity.push((fill.executed_at, total));
+            drawdown.push((fill.executed_at, total - peak));
+        }
+        stats.net_pnl = total;
+
+        let monthly_returns = monthly_pnl
+            .into_iter()
+            .map(|(month, pnl)| (month, pnl / (capital + month_start_equity[&month]) * 100.0))
+            .collect();
+
+        TearSheet {
+            strategy: strategy.to_string(),
+            from,
+            to,
+            capital,
+            equity,
+            drawdown,
+            monthly_returns,
+            stats,
+        }
+    }
+
+
+    /// Renders the tear sheet generated at `generated_at`, e.g. `SystemClock.now()`.
+    pub fn render_html(&self, generated_at: DateTime<Utc>) -> String {
+        let stats = &self.stats;
+        let show = |value: Option<f64>, suffix: &str| value.map_or("-".to_string(), |value| format!("{:.2}{}", value, suffix));
+
+        let mut page = String::new();
+        let _ = write!(
+            page,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{strategy} {from} - {to}</title>\
+             <style>body{{font-family:sans-serif;margin:2em;max-width:60em}}table{{border-collapse:collapse}}\
+             td,th{{border:1px solid #ddd;padding:4px 8px;text-align:right}}th:first-child,td:first-child{{text-align:left}}\
+             .loss{{color:#c62828}}.profit{{color:#2e7d32}}</style></head><body>\
+             <h1>{strategy}</h1><p>{from} - {to}, capital {capital:.2}</p>",
+            strategy = escape(&self.strategy),
+            from = self.from,
+            to = self.to,
+            capital = self.capital,
+        );
+
+        page.push_str("<h2>Equity</h2>");
+        page.push_str(&chart(&self.equity, "#1565c0"));
+        page.push_str("<h2>Drawdown</h2>");
+        page.push_str(&chart(&self.drawdown, "#c62828"));
+
+        page.push_str("<h2>Monthly returns, %</h2><table><tr><th>Year</th>");
+        for month in 1..=12 {
+            let _ = write!(page, "<th>{:02}</th>", month);
+        }
+        page.push_str("<th>Year</th></tr>");
+        let years: BTreeSet<i32> = self.monthly_returns.keys().map(|(year, _)| *year).collect();
+        for year in years {
+            let _ = write!(page, "<tr><td>{}</td>", year);
+            let mut compound = 1.0;
+            for month in 1..=12 {
+                match self.monthly_returns.get(&(year, month)) {
+                    Some(value) => {
+                        compound *= 1.0 + value / 100.0;
+                        let _ = write!(page, "<td class=\"{}\">{:.2}</td>", class(*value), value);
+                    }
+                    None => page.push_str("<td></td>"),
+                }
+            }
+            let yearly = (compound - 1.0) * 100.0;
+            let _ = write!(page, "<td class=\"{}\">{:.2}</td></tr>", class(yearly), yearly);
+        }
+        page.push_str("</table>");
+
+        let _ = write!(
+            page,
+            "<h2>Trades</h2><table>\
+             <tr><td>Net profit</td><td class=\"{}\">{:.2}</td></tr>\
+             <tr><td>Gross profit</td><td>{:.2}</td></tr>\
+             <tr><td>Commission</td><td>{:.2}</td></tr>\
+             <tr><td>Slippage, estimated</td><td>{:.2}</td></tr>\
+             <tr><td>Max drawdown</td><td>{:.2}</td></tr>\
+             <tr><td>Fills</td><td>{}</td></tr>\
+             <tr><td>Closed trades</td><td>{}</td></tr>\
+             <tr><td>Win rate</td><td>{}</td></tr>\
+             <tr><td>Profit factor</td><td>{}</td></tr>\
+             <tr><td>Average win</td><td>{}</td></tr>\
+             <tr><td>Average loss</td><td>{}</td></tr>\
+             <tr><td>Largest win</td><td>{:.2}</td></tr>\
+             <tr><td>Largest loss</td><td>{:.2}</td></tr>\
+             </table><p><small>Generated {}</small></p></body></html>",
+            class(stats.net_pnl),
+            stats.net_pnl,
+            stats.money_flows().gross_pnl,
+            stats.commission,
+            stats.slippage,
+            stats.max_drawdown,
+            stats.fills,
+            stats.closed_trades,
+            show(stats.win_rate(), "%"),
+            show(stats.profit_factor(), ""),
+            show(stats.average_win(), ""),
+            show(stats.average_loss(), ""),
+            stats.largest_win,
+            stats.largest_loss,
+            generated_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+
+        page
+    }
+}
+
+
+/// Renders a series as an inline SVG line chart with the zero line.
+fn chart(series: &[(DateTime<Utc>, f64)], color: &str) -> String {
+    const WIDTH: f64 = 800.0;
+    const HEIGHT: f64 = 200.0;
+
+    if series.len() < 2 {
+        return "<p>No data</p>".to_string();
+    }
+
+    let (first, last) = (series[0].0.timestamp() as f64, series[series.len() - 1].0.timestamp() as f64);
+    let span = if last > first { last - first } else { 1.0 };
+    let (min, max) = series
+        .iter()
+        .fold((0.0, 0.0), |(min, max): (f64, f64), (_, value)| (min.min(*value), max.max(*value)));
+    let range = if max > min { max - min } else { 1.0 };
+    let y = |value: f64| HEIGHT - (value - min) / range * HEIGHT;
+
+    let points: Vec<String> = series
+        .iter()
+        .map(|(time, value)| format!("{:.1},{:.1}", (time.timestamp() as f64 - first) / span * WIDTH, y(*value)))
+        .collect();
+
+    format!(
+        "<svg viewBox=\"0 0 {w} {h}\" width=\"100%\" height=\"{h}\" preserveAspectRatio=\"none\">\
+         <line x1=\"0\" y1=\"{zero:.1}\" x2=\"{w}\" y2=\"{zero:.1}\" stroke=\"#999\" stroke-dasharray=\"4\"/>\
+         <polyline fill=\"none\" stroke=\"{color}\" stroke-width=\"2\" points=\"{points}\"/></svg>\
+         <p>{start} - {end}, min {min:.2}, max {max:.2}</p>",
+        w = WIDTH,
+        h = HEIGHT,
+        zero = y(0.0),
+        color = color,
+        points = points.join(" "),
+        start = series[0].0.format("%Y-%m-%d"),
+        end = series[series.len() - 1].0.format("%Y-%m-%d"),
+        min = min,
+        max = max,
+    )
+}
+
+
+fn class(value: f64) -> &'static str {
+    if value < 0.0 {
+        "loss"
+    } else {
+        "profit"
+    }
+}
+
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
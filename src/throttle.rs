@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{error, info};
+use quik_rs::quik::QuikApi;
+use crate::latency::{LatencyStats, Stage};
+
+
+/// Token bucket: holds up to `capacity` tokens and gains `rate` tokens per second.
+/// Every transaction takes one token, so bursts up to `capacity` pass at once and
+/// the sustained flow is limited to `rate` transactions per second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        if rate <= 0.0 {
+            return Err(format!("rate {} must be positive", rate).into());
+        }
+        if capacity == 0 {
+            return Err("capacity must be positive".into());
+        }
+
+        Ok(TokenBucket {
+            capacity: capacity as f64,
+            rate,
+            tokens: capacity as f64,
+            refilled_at: Instant::now(),
+        })
+    }
+
+
+    /// Takes a token if one is available, otherwise returns the time until the next token.
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+
+/// Metrics of the transaction queue.
+#[derive(Debug, Default)]
+pub struct ThrottleStats {
+    queue_depth: AtomicUsize,
+    peak_queue_depth: AtomicUsize,
+    sent: AtomicU64,
+    failed: AtomicU64,
+    /// Send times of the last second.
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+
+impl ThrottleStats {
+    /// Transactions waiting to be sent.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+
+    /// Largest queue depth since the start.
+    pub fn peak_queue_depth(&self) -> usize {
+        self.peak_queue_depth.load(Ordering::Relaxed)
+    }
+
+
+    /// Transactions accepted by the terminal.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+
+    /// Transactions rejected by `send_async_transaction`.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+
+    /// Transactions sent in the last second, the current order rate.
+    pub fn rate(&self) -> usize {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        expire(&mut recent, Instant::now());
+        recent.len()
+    }
+
+
+    fn record_sent(&self) {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        expire(&mut recent, now);
+        recent.push_back(now);
+    }
+}
+
+
+fn expire(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent.front().is_some_and(|sent| now.duration_since(*sent) >= Duration::from_secs(1)) {
+        recent.pop_front();
+    }
+}
+
+
+/// Queue in front of `send_async_transaction` limiting the transaction flow with a token bucket,
+/// so bursts of signals across many instruments are spread out instead of being rejected
+/// by the broker. Transactions are sent in the order they were queued, the time from queueing
+/// to sending is recorded as the `transaction_send` latency in `latency`.
+///
+/// # Example of use
+/// ```ignore
+/// let throttle = TransactionThrottle::start(terminal.clone(), config.max_transactions_per_second, config.transaction_burst, metrics.latency.clone())?;
+/// throttle.send(transaction.to_string(), Box::new(|e| warn!("Transaction is not sent: {}", e)))?;
+/// info!("Transaction queue depth: {}", throttle.stats().queue_depth());
+/// ```
+pub struct TransactionThrottle {
+    sender: mpsc::UnboundedSender<Queued>,
+    stats: Arc<ThrottleStats>,
+}
+
+
+/// Called with the error when `send_async_transaction` rejects a transaction, e.g. while
+/// the terminal is disconnected: the transaction never left the bot.
+pub type OnFailure = Box<dyn FnOnce(String) + Send>;
+
+
+/// Transaction waiting in the queue with the time it was queued.
+struct Queued {
+    queued_at: Instant,
+    transaction: String,
+    on_failure: OnFailure,
+}
+
+
+impl TransactionThrottle {
+    /// Starts the sending task. `max_per_second` is the sustained limit, `burst` is the number
+    /// of transactions that may be sent at once after a quiet period.
+    pub fn start(terminal: Arc<dyn QuikApi>, max_per_second: f64, burst: u32, latency: LatencyStats) -> Result<Self, Box<dyn std::error::Error>> {
+        let bucket = TokenBucket::new(max_per_second, burst)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let stats = Arc::new(ThrottleStats::default());
+
+        tokio::spawn(run(terminal, bucket, receiver, stats.clone(), latency));
+
+        Ok(TransactionThrottle { sender, stats })
+    }
+
+
+    /// Queues a transaction. The result of sending it is logged and a transaction rejected
+    /// by the terminal is passed to `on_failure`; the reply arrives as `QuikEvent::TransactionReply`.
+    pub fn send(&self, transaction: String, on_failure: OnFailure) -> Result<(), Box<dyn std::error::Error>> {
+        let depth = self.stats.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.stats.peak_queue_depth.fetch_max(depth, Ordering::Relaxed);
+
+        let queued = Queued { queued_at: Instant::now(), transaction, on_failure };
+        self.sender.send(queued).map_err(|_| {
+            self.stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            "transaction queue is closed"
+        })?;
+
+        Ok(())
+    }
+
+
+    pub fn stats(&self) -> Arc<ThrottleStats> {
+        self.stats.clone()
+    }
+}
+
+
+async fn run(
+    terminal: Arc<dyn QuikApi>,
+    mut bucket: TokenBucket,
+    mut receiver: mpsc::UnboundedReceiver<Queued>,
+    stats: Arc<ThrottleStats>,
+    latency: LatencyStats,
+) {
+    while let Some(Queued { queued_at, transaction, on_failure }) = receiver.recv().await {
+        while let Err(wait) = bucket.try_take() {
+            sleep(wait).await;
+        }
+
+        let depth = stats.queue_depth.fetch_sub(1, Ordering::Relaxed) - 1;
+        let result = terminal.send_async_transaction(&transaction);
+        latency.record(Stage::TransactionSend, queued_at.elapsed());
+        match result {
+            Ok(_) => {
+                stats.sent.fetch_add(1, Ordering::Relaxed);
+                stats.record_sent();
+                if depth > 0 {
+                    info!("Transaction sent, {} queued", depth);
+                }
+            }
+            Err(e) => {
+                stats.failed.fetch_add(1, Ordering::Relaxed);
+                error!("Transaction '{}' is not sent: {}", transaction, e);
+                on_failure(e.to_string());
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quik_rs::mock::MockTerminal;
+
+
+    #[test]
+    fn bucket_passes_the_burst_and_then_waits() {
+        let mut bucket = TokenBucket::new(10.0, 3).unwrap();
+        for _ in 0..3 {
+            assert!(bucket.try_take().is_ok());
+        }
+        let wait = bucket.try_take().unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+    }
+
+
+    #[test]
+    fn invalid_bucket_is_rejected() {
+        assert!(TokenBucket::new(0.0, 1).is_err());
+        assert!(TokenBucket::new(1.0, 0).is_err());
+    }
+
+
+    #[tokio::test]
+    async fn transactions_over_the_burst_are_queued() {
+        let terminal: Arc<dyn QuikApi> = Arc::new(MockTerminal::new());
+        terminal.connect().unwrap();
+        let throttle = TransactionThrottle::start(terminal, 20.0, 2, LatencyStats::default()).unwrap();
+        let stats = throttle.stats();
+
+        for trans_id in 1..=4 {
+            throttle.send(format!("TRANS_ID={};ACTION=KILL_ORDER;ORDER_KEY=1;", trans_id), Box::new(|_| {})).unwrap();
+        }
+        assert_eq!(stats.peak_queue_depth(), 4);
+        // The burst is sent at once, the rest at 20 per second
+        let started = Instant::now();
+        while stats.sent() < 4 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(80));
+        assert_eq!((stats.queue_depth(), stats.failed(), stats.rate()), (0, 0, 4));
+    }
+
+
+    #[tokio::test]
+    async fn transactions_rejected_by_the_terminal_are_counted() {
+        // Not connected
+        let throttle = TransactionThrottle::start(Arc::new(MockTerminal::new()), 100.0, 10, LatencyStats::default()).unwrap();
+        let stats = throttle.stats();
+
+        let (failures, mut failed) = mpsc::unbounded_channel();
+        throttle.send("TRANS_ID=1;ACTION=KILL_ORDER;ORDER_KEY=1;".to_string(), Box::new(move |e| failures.send(e).unwrap())).unwrap();
+        assert!(tokio::time::timeout(Duration::from_secs(5), failed.recv()).await.unwrap().is_some());
+        assert_eq!((stats.sent(), stats.failed(), stats.queue_depth()), (0, 1, 0));
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tokio::time::interval;
+use tracing::{error, info};
+use crate::clock::Clock;
+use crate::psql::Db;
+
+
+/// Interval between the reads of the new ticks.
+pub const TICK_POLL_PERIOD: Duration = Duration::from_secs(1);
+
+/// Ticks read at most at once, the rest are read right after them.
+const TICK_BATCH: i64 = 10_000;
+
+
+/// Trade of the market exported by the terminal to `historical_trades`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tick {
+    pub sec_code: String,
+    pub price: f64,
+    pub time: DateTime<Utc>,
+}
+
+
+/// Tick feed of the strategies evaluating the ticks, see `Strategy::on_tick`: reads the trades
+/// exported to `historical_trades` after the start every `period` and sends them to `ticks`
+/// in the order of their time. Stops when the ticks are not received anymore.
+///
+/// The terminal exports the ticks to the database rather than to the bot, so a tick reaches
+/// the strategies `period` late at most. A tick exported with the time of the last tick read
+/// after that tick was read is missed.
+///
+/// # Example of use
+/// ```ignore
+/// let (sender, ticks) = mpsc::unbounded_channel();
+/// tokio::spawn(tick_feed::run(db.clone(), clock.clone(), TICK_POLL_PERIOD, sender));
+/// while let Some(tick) = ticks.recv().await {
+///     strategies.on_tick(&tick.sec_code, tick.price, tick.time);
+/// }
+/// ```
+pub async fn run(db: Arc<Db>, clock: Arc<dyn Clock>, period: Duration, ticks: mpsc::UnboundedSender<Tick>) {
+    let mut after = clock.now();
+    let mut ticker = interval(period);
+    info!("Tick feed is started from {}", after);
+
+    loop {
+        ticker.tick().await;
+        loop {
+            let read = match db.get_ticks_after(after, TICK_BATCH).await {
+                Ok(read) => read,
+                Err(e) => {
+                    error!("Error reading the ticks after {}: {}", after, e);
+                    break;
+                }
+            };
+            let full = read.len() as i64 == TICK_BATCH;
+            for (sec_code, price, time) in read {
+                after = after.max(time);
+                if ticks.send(Tick { sec_code, price, time }).is_err() {
+                    info!("Ticks are not received anymore, the tick feed is stopped");
+                    return;
+                }
+            }
+            if !full {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use crate::instrument_info::InstrumentCache;
+use crate::psql::DataForEma;
+
+
+/// What is done with an anomalous tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyAction {
+    /// The tick doesn't reach candles and signals.
+    Drop,
+    /// The tick is passed on, but recorded for review.
+    Flag,
+}
+
+
+impl AnomalyAction {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value {
+            "drop" => Ok(AnomalyAction::Drop),
+            "flag" => Ok(AnomalyAction::Flag),
+            _ => Err(format!("unknown anomaly action '{}', expected 'drop' or 'flag'", value).into()),
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyAction::Drop => "drop",
+            AnomalyAction::Flag => "flag",
+        }
+    }
+}
+
+
+/// Settings of the tick filter, the `[tick_filter]` table of the configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TickFilterSettings {
+    /// Maximum change from the previous accepted tick, in percent.
+    pub max_jump_percent: f64,
+
+    pub action: AnomalyAction,
+
+    /// Number of consecutive ticks at a new level after which the level is accepted
+    /// as a real gap rather than bad prints.
+    pub confirm_ticks: usize,
+}
+
+
+impl Default for TickFilterSettings {
+    fn default() -> Self {
+        TickFilterSettings {
+            max_jump_percent: 10.0,
+            action: AnomalyAction::Drop,
+            confirm_ticks: 3,
+        }
+    }
+}
+
+
+/// Price limits of an instrument set by the exchange for the trading session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceBand {
+    pub min: f64,
+    pub max: f64,
+}
+
+
+/// Decision of the filter on a tick.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TickVerdict {
+    Accept,
+    /// The tick is anomalous; `action` tells whether it is dropped or only flagged.
+    Anomaly { action: AnomalyAction, reason: String },
+}
+
+
+/// Filter of erroneous prices in the incoming ticks, applied before the ticks reach
+/// candles and signals, so one bad print can't trigger a trade, see `CandleFilter`.
+///
+/// A tick is anomalous if its price is not positive, lies outside the exchange price band
+/// of the instrument or differs from the previous accepted tick by more than `max_jump_percent`.
+/// A jump confirmed by `confirm_ticks` consecutive ticks is accepted as a new price level.
+///
+/// # Example of use
+/// ```ignore
+/// let mut filter = TickFilter::new(config.tick_filter);
+/// filter.set_price_band("SBER", PriceBand { min: 230.0, max: 280.0 });
+/// match filter.check("SBER", price) {
+///     TickVerdict::Accept => db.write_tick(class_code, "SBER", price, volume).await?,
+///     TickVerdict::Anomaly { action, reason } => {
+///         db.insert_anomalous_tick("SBER", price, filter.last_price("SBER"), action.as_str(), &reason).await?;
+///         if action == AnomalyAction::Flag {
+///             db.write_tick(class_code, "SBER", price, volume).await?;
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TickFilter {
+    settings: TickFilterSettings,
+    bands: HashMap<String, PriceBand>,
+    /// Price of the last accepted tick by instrument.
+    last_prices: HashMap<String, f64>,
+    /// Consecutive anomalous jumps by instrument, candidates for a new price level.
+    pending: HashMap<String, Vec<f64>>,
+}
+
+
+impl TickFilter {
+    pub fn new(settings: TickFilterSettings) -> Self {
+        TickFilter {
+            settings,
+            bands: HashMap::new(),
+            last_prices: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+
+    pub fn set_price_band(&mut self, sec_code: &str, band: PriceBand) {
+        self.bands.insert(sec_code.to_string(), band);
+    }
+
+
+    /// Price of the last accepted tick of the instrument.
+    pub fn last_price(&self, sec_code: &str) -> Option<f64> {
+        self.last_prices.get(sec_code).copied()
+    }
+
+
+    /// Checks a tick and updates the reference price of the instrument.
+    pub fn check(&mut self, sec_code: &str, price: f64) -> TickVerdict {
+        let reason = self.anomaly(sec_code, price);
+
+        match reason {
+            None => {
+                self.pending.remove(sec_code);
+                self.last_prices.insert(sec_code.to_string(), price);
+                TickVerdict::Accept
+            }
+            Some(reason) => {
+                let action = self.settings.action;
+                if action == AnomalyAction::Flag {
+                    // A flagged tick still moves the market, follow it
+                    self.last_prices.insert(sec_code.to_string(), price);
+                } else if self.confirms_new_level(sec_code, price) {
+                    return TickVerdict::Accept;
+                }
+                TickVerdict::Anomaly { action, reason }
+            }
+        }
+    }
+
+
+    fn anomaly(&self, sec_code: &str, price: f64) -> Option<String> {
+        if !price.is_finite() || price <= 0.0 {
+            return Some(format!("non-positive price {}", price));
+        }
+
+        if let Some(band) = self.bands.get(sec_code) {
+            if price < band.min || price > band.max {
+                return Some(format!("price {} outside the band {}-{}", price, band.min, band.max));
+            }
+        }
+
+        let last = self.last_prices.get(sec_code)?;
+        let jump = (price - last).abs() / last * 100.0;
+        (jump > self.settings.max_jump_percent)
+            .then(|| format!("jump of {:.2}% from {} exceeds {}%", jump, last, self.settings.max_jump_percent))
+    }
+
+
+    /// Collects the consecutive jumped ticks. When `confirm_ticks` of them stay within
+    /// `max_jump_percent` of each other, their level becomes the reference price.
+    fn confirms_new_level(&mut self, sec_code: &str, price: f64) -> bool {
+        // Invalid prices and ticks outside the exchange band are never a new level
+        if !price.is_finite() || price <= 0.0 || self.bands.get(sec_code).is_some_and(|band| price < band.min || price > band.max) {
+            return false;
+        }
+
+        let pending = self.pending.entry(sec_code.to_string()).or_default();
+        let consistent = pending
+            .last()
+            .is_none_or(|previous| (price - previous).abs() / previous * 100.0 <= self.settings.max_jump_percent);
+        if !consistent {
+            pending.clear();
+        }
+        pending.push(price);
+
+        if pending.len() < self.settings.confirm_ticks.max(1) {
+            return false;
+        }
+
+        self.pending.remove(sec_code);
+        self.last_prices.insert(sec_code.to_string(), price);
+        true
+    }
+}
+
+
+/// Tick dropped or flagged by the filter, a row of the `anomalous_ticks` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub price: f64,
+    /// Price of the last accepted tick before it.
+    pub previous_price: Option<f64>,
+    pub action: AnomalyAction,
+    pub reason: String,
+}
+
+
+/// Tick filters of the candle series of the trading loop: the ticks of a closed candle pass
+/// the filter of its timeframe in the order they arrived, and the candle is built from the
+/// accepted and the flagged ones. Every timeframe has its own filter, so the ticks of the
+/// overlapping candles of several timeframes are seen once by each of them. The price bands
+/// are the PRICEMIN and PRICEMAX of the instruments of the cache.
+///
+/// # Example of use
+/// ```ignore
+/// let filter = CandleFilter::new(config.tick_filter, cache.clone());
+/// let ticks = db.get_ticks("SBER", start, closed_at).await?;
+/// let (candle, anomalies) = filter.candle("SBER", Duration::from_secs(300), start, &ticks);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CandleFilter {
+    settings: TickFilterSettings,
+    instruments: InstrumentCache,
+    series: Arc<Mutex<HashMap<Duration, TickFilter>>>,
+}
+
+
+impl CandleFilter {
+    pub fn new(settings: TickFilterSettings, instruments: InstrumentCache) -> Self {
+        CandleFilter {
+            settings,
+            instruments,
+            series: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+
+    /// Candle of `timeframe` starting at `start` from the prices and the volumes of its ticks
+    /// which pass the filter, `None` without such ticks, and the anomalous ticks.
+    pub fn candle(&self, sec_code: &str, timeframe: Duration, start: DateTime<Utc>, ticks: &[(f64, f64)]) -> (Option<DataForEma>, Vec<Anomaly>) {
+        let band = self.instruments.find(sec_code).and_then(|info| match (info.price_min, info.price_max) {
+            (Some(min), Some(max)) if min > 0.0 && max > min => Some(PriceBand { min, max }),
+            _ => None,
+        });
+
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let filter = series.entry(timeframe).or_insert_with(|| TickFilter::new(self.settings));
+        if let Some(band) = band {
+            filter.set_price_band(sec_code, band);
+        }
+
+        let mut candle: Option<DataForEma> = None;
+        let mut anomalies = Vec::new();
+        for &(price, volume) in ticks {
+            let previous_price = filter.last_price(sec_code);
+            if let TickVerdict::Anomaly { action, reason } = filter.check(sec_code, price) {
+                anomalies.push(Anomaly { price, previous_price, action, reason });
+                if action == AnomalyAction::Drop {
+                    continue;
+                }
+            }
+
+            match &mut candle {
+                Some(candle) => {
+                    candle.high = candle.high.max(price);
+                    candle.low = candle.low.min(price);
+                    candle.close = price;
+                    candle.volume += volume;
+                }
+                None => {
+                    candle = Some(DataForEma {
+                        period_start: start,
+                        open: price,
+                        high: price,
+                        low: price,
+                        close: price,
+                        volume,
+                    });
+                }
+            }
+        }
+
+        (candle, anomalies)
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, FixedOffset, Utc};
+use ta::indicators::AverageTrueRange;
+use ta::{DataItem, Next};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+use quik_rs::transaction::{Side, TransactionKind};
+use crate::account::Account;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, InstrumentSettings};
+use crate::corporate_actions::CorporateActionCalendar;
+use crate::daily_target::DailyProfitTarget;
+use crate::dashboard::Dashboard;
+use crate::domain::Signal;
+use crate::execution::{self, ExecutionPolicy, Slice};
+use crate::hedge::{self, Leg, PairSettings, SpreadHedge};
+use crate::holding::HoldingTracker;
+use crate::instrument_info::{InstrumentCache, InstrumentInfo};
+use crate::orderbook::{self, Imbalance, OrderBook};
+use crate::orders::OrderTracker;
+use crate::portfolio::{InstrumentPosition, Portfolio};
+use crate::psql::Db;
+use crate::sizing::{LotResiduals, PositionSizer, SizingInput};
+use crate::spread::{MaxSpread, SpreadGuard, SpreadSkip};
+use crate::strategy::{Action, Decision};
+
+
+/// Candles of the lookback of the ATR per candle of the ATR period, so the
+/// nights and the weekends without candles still leave enough of them.
+const ATR_LOOKBACK_FACTOR: u32 = 10;
+
+
+/// Trading loop of the headless bot: turns the decisions of the strategies into orders.
+///
+/// A decision is not traded when:
+/// - the instrument is outside the watchlist, disabled, watch only or outside its trading windows;
+/// - its position left to the user at the start is still open;
+/// - the instrument is already positioned in the direction of an entry;
+/// - an exit comes before the minimum holding period of `[holding.<strategy>]`;
+/// - the instrument reached its `daily_profit_target` of the day;
+/// - an entry falls within the `ex_dividend_blackout_days` of the instrument;
+/// - the spread of a market entry is wider than `max_spread`, or the book imbalance is
+///   below `min_imbalance`.
+///
+/// An entry is sized by `[sizing]` on the account of the instrument, or is of `entry_lots`
+/// of `[backtest]` without the table; a reduction or a close is sized by the current position.
+/// The legs of a pair of `spread_hedge` are sized by `hedge::orders`. The orders are planned
+/// by the execution policy of the instrument against the best quotes of `current_trades` and
+/// submitted through the order tracker; the touch is the expected price of their fills for
+/// the slippage, see `Portfolio::expect_price`.
+///
+/// # Example of use
+/// ```ignore
+/// let (decisions, mut received) = mpsc::unbounded_channel();
+/// tokio::spawn(scheduler::run(db.clone(), strategies, churn, expiry, terminal.events(), universe, ticks, timezone, clock.clone(), stats, metrics, filter, decisions));
+/// let trader = Trader::new(db.clone(), &config, instruments.clone(), portfolio.clone(), orders.clone())?.with_clock(clock.clone());
+/// tokio::spawn(async move { trading_loop::run(trader, &mut received).await });
+/// ```
+pub struct Trader {
+    db: Arc<Db>,
+    /// Settings of the watchlist instruments by security code.
+    settings: HashMap<String, InstrumentSettings>,
+    instruments: InstrumentCache,
+    portfolio: Arc<RwLock<Portfolio>>,
+    orders: Arc<Mutex<OrderTracker>>,
+    sizer: PositionSizer,
+    /// Pairs of `spread_hedge`, whose legs are sized against each other.
+    pairs: Vec<PairSettings>,
+    timezone: FixedOffset,
+    clock: Arc<dyn Clock>,
+    /// Dashboard showing the recent signals.
+    dashboard: Option<Dashboard>,
+    state: TraderState,
+    /// Holding periods of the positions by strategy, see `holding::run`.
+    holding: HashMap<String, Arc<Mutex<HoldingTracker>>>,
+    /// Ex-dividend dates of the `ex_dividend_blackout_days` of the instruments.
+    corporate_actions: CorporateActionCalendar,
+}
+
+
+/// State of a trader which outlives it: a trader restarted by the watchdog continues with
+/// the profit of the day, the instruments left to the user and the lot residuals of the sizing
+/// of the previous one instead of counting them again from the current positions.
+///
+/// The execution plans in flight are kept here as well, so the shutdown cancels them with
+/// `stop` before it settles the orders, see `shutdown::run`.
+#[derive(Clone)]
+pub struct TraderState {
+    target: Arc<Mutex<DailyProfitTarget>>,
+    /// Instruments with a position left to the user at the start, not traded until it is closed.
+    blocked: Arc<Mutex<HashSet<String>>>,
+    residuals: Arc<Mutex<LotResiduals>>,
+    executions: Arc<Mutex<Executions>>,
+}
+
+
+/// Tasks submitting the slices of the execution plans, see `execution::execute`.
+#[derive(Default)]
+struct Executions {
+    tasks: JoinSet<()>,
+    /// The shutdown has started, no plan is executed anymore.
+    stopped: bool,
+}
+
+
+impl TraderState {
+    /// Starts submitting the slices of a plan. Returns `false` if the shutdown has started.
+    fn execute(&self, orders: Arc<Mutex<OrderTracker>>, slices: Vec<Slice>) -> bool {
+        let mut executions = self.executions.lock().unwrap_or_else(|e| e.into_inner());
+        if executions.stopped {
+            return false;
+        }
+        while executions.tasks.try_join_next().is_some() {}
+        executions.tasks.spawn(execution::execute(orders, slices));
+        true
+    }
+
+
+    /// Cancels the execution plans in flight and waits for their tasks, so no slice is
+    /// submitted once it returns; the plans of the decisions after it are not executed.
+    pub async fn stop(&self) {
+        let mut tasks = {
+            let mut executions = self.executions.lock().unwrap_or_else(|e| e.into_inner());
+            executions.stopped = true;
+            std::mem::take(&mut executions.tasks)
+        };
+        if !tasks.is_empty() {
+            info!("{} execution plans in flight are cancelled", tasks.len());
+        }
+        tasks.shutdown().await;
+    }
+}
+
+
+impl Trader {
+    pub fn new(
+        db: Arc<Db>,
+        config: &Config,
+        instruments: InstrumentCache,
+        portfolio: Arc<RwLock<Portfolio>>,
+        orders: Arc<Mutex<OrderTracker>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let settings = config.instrument_settings()?;
+        // The profit of the day is counted from the positions at the start
+        let positions: Vec<InstrumentPosition> = {
+            let portfolio = portfolio.read().unwrap_or_else(|e| e.into_inner());
+            settings.iter().filter_map(|settings| portfolio.position(&settings.sec_code).cloned()).collect()
+        };
+        let target = DailyProfitTarget::new(&settings, config.exchange_timezone, &positions);
+        let settings = settings.into_iter().map(|settings| (settings.sec_code.clone(), settings)).collect();
+
+        Ok(Trader {
+            db,
+            settings,
+            instruments,
+            portfolio,
+            orders,
+            sizer: config.sizing.unwrap_or_else(|| PositionSizer::fixed(config.backtest.entry_lots)),
+            pairs: config.pairs.clone(),
+            timezone: config.exchange_timezone,
+            clock: Arc::new(SystemClock),
+            dashboard: None,
+            state: TraderState {
+                target: Arc::new(Mutex::new(target)),
+                blocked: Arc::new(Mutex::new(HashSet::new())),
+                residuals: Arc::new(Mutex::new(LotResiduals::default())),
+                executions: Arc::new(Mutex::new(Executions::default())),
+            },
+            holding: HashMap::new(),
+            corporate_actions: CorporateActionCalendar::default(),
+        })
+    }
+
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        {
+            let mut target = self.state.target.lock().unwrap_or_else(|e| e.into_inner());
+            *target = target.clone().with_clock(clock.clone());
+        }
+        self.clock = clock;
+        self
+    }
+
+
+    /// Leaves the instruments alone while their positions found at the start are open, see
+    /// `existing_positions::apply`.
+    pub fn with_blocked(self, blocked: HashSet<String>) -> Self {
+        *self.state.blocked.lock().unwrap_or_else(|e| e.into_inner()) = blocked;
+        self
+    }
+
+
+    /// Continues with the state of a previous trader, e.g. one restarted by the watchdog.
+    pub fn with_state(mut self, state: TraderState) -> Self {
+        self.state = state;
+        self
+    }
+
+
+    /// State of the trader to continue with after a restart, see `with_state`.
+    pub fn state(&self) -> TraderState {
+        self.state.clone()
+    }
+
+
+    /// Holds back the exits of the strategy before the minimum holding period of its positions.
+    pub fn with_holding(mut self, strategy: &str, tracker: Arc<Mutex<HoldingTracker>>) -> Self {
+        self.holding.insert(strategy.to_string(), tracker);
+        self
+    }
+
+
+    /// Keeps the entries out of `ex_dividend_blackout_days` before the ex-dividend dates of the calendar.
+    pub fn with_corporate_actions(mut self, calendar: CorporateActionCalendar) -> Self {
+        self.corporate_actions = calendar;
+        self
+    }
+
+
+    /// Shows every decision of the strategies among the recent signals of the dashboard.
+    pub fn with_dashboard(mut self, dashboard: Dashboard) -> Self {
+        self.dashboard = Some(dashboard);
+        self
+    }
+
+
+    /// Plans the orders of the decision and submits them. Returns the number of the orders planned,
+    /// 0 for a decision which is not traded.
+    pub async fn on_decision(&mut self, strategy: &str, decision: &Decision) -> Result<usize, Box<dyn std::error::Error>> {
+        let sec_code = decision.sec_code.as_str();
+        if decision.action == Action::DoNothingExplicit {
+            return Ok(0);
+        }
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.push_signal(Signal::from_decision(decision, self.clock.now()));
+        }
+        let Some(settings) = self.settings.get(sec_code).cloned() else {
+            warn!("{}: {} signal of {} is not traded, the instrument is not in the watchlist", sec_code, decision.action.as_str(), strategy);
+            return Ok(0);
+        };
+        if settings.watch_only {
+            info!("{}: {} signal of {} is not traded, the instrument is watch only", sec_code, decision.action.as_str(), strategy);
+            return Ok(0);
+        }
+        let now = self.clock.now();
+        if !settings.is_trading_time(now.with_timezone(&self.timezone).naive_local()) {
+            info!("{}: {} signal of {} is not traded outside the trading windows", sec_code, decision.action.as_str(), strategy);
+            return Ok(0);
+        }
+
+        let (position, positioned, current) = {
+            let portfolio = self.portfolio.read().unwrap_or_else(|e| e.into_inner());
+            (portfolio.lots(sec_code), portfolio.is_positioned(sec_code, decision.action), portfolio.position(sec_code).cloned())
+        };
+        {
+            let mut blocked = self.state.blocked.lock().unwrap_or_else(|e| e.into_inner());
+            if blocked.contains(sec_code) {
+                if position != 0 {
+                    info!("{}: {} signal of {} is not traded, the position of {} lots is left to the user", sec_code, decision.action.as_str(), strategy, position);
+                    return Ok(0);
+                }
+                blocked.remove(sec_code);
+            }
+        }
+        if let Some(tracker) = self.holding.get(strategy) {
+            let mut tracker = tracker.lock().unwrap_or_else(|e| e.into_inner());
+            match &current {
+                Some(current) => tracker.sync(current),
+                None => tracker.close(sec_code),
+            }
+            if tracker.filter(sec_code, decision.action, now) != decision.action {
+                info!("{}: {} signal of {} is held back, the position is held less than the minimum holding period", sec_code, decision.action.as_str(), strategy);
+                return Ok(0);
+            }
+        }
+        let checked = {
+            let mut target = self.state.target.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(reached) = current.and_then(|current| target.on_position(&current)) {
+                warn!("{}", reached);
+            }
+            target.check(sec_code, decision.action)
+        };
+        if let Err(reached) = checked {
+            info!("{}: {} signal of {} is skipped, {}", sec_code, decision.action.as_str(), strategy, reached);
+            self.db.insert_evaluation(&reached.evaluation(now)).await?;
+            return Ok(0);
+        }
+        if positioned {
+            info!("{}: {} signal of {} is skipped, already positioned with {} lots", sec_code, decision.action.as_str(), strategy, position);
+            return Ok(0);
+        }
+        if let Some(days) = settings.ex_dividend_blackout_days.filter(|_| matches!(decision.action, Action::Buy | Action::Sell)) {
+            let today = now.with_timezone(&self.timezone).date_naive();
+            if let Some(date) = self.corporate_actions.next_ex_dividend(sec_code, today).filter(|_| self.corporate_actions.is_ex_dividend_near(sec_code, today, days)) {
+                info!("{}: {} signal of {} is skipped, the ex-dividend date {} is within {} days", sec_code, decision.action.as_str(), strategy, date, days);
+                return Ok(0);
+            }
+        }
+
+        if strategy == SpreadHedge::NAME {
+            if let Some(pair) = self.pairs.iter().find(|pair| pair.spot == sec_code || pair.futures == sec_code).cloned() {
+                let slices = stamped(self.hedge_slices(&pair, decision).await?, strategy);
+                if matches!(decision.action, Action::Buy | Action::Sell) {
+                    self.check_account(&settings, &slices).await?;
+                }
+                info!(
+                    "{}: {} signal of {} is executed as a leg of {}/{} by {} in {} orders",
+                    sec_code, decision.action.as_str(), strategy, pair.spot, pair.futures, settings.execution, slices.len()
+                );
+                return Ok(self.execute(slices));
+            }
+        }
+
+        let info = self.instruments.get(&settings.class_code, sec_code);
+        let book = self.db.get_top_of_book(&settings.class_code, sec_code).await?;
+        let market_entry = matches!(decision.action, Action::Buy | Action::Sell)
+            && matches!(settings.execution, ExecutionPolicy::Market | ExecutionPolicy::Twap { .. });
+        if let Some(limit) = settings.max_spread.filter(|_| market_entry) {
+            let checked = self.check_spread(limit, sec_code, book.as_ref(), info.as_ref(), now).await?;
+            if let Err(skip) = checked {
+                info!("{}: {} signal of {} is skipped, {}", sec_code, decision.action.as_str(), strategy, skip);
+                self.db.insert_evaluation(&skip.evaluation(sec_code, now)).await?;
+                return Ok(0);
+            }
+        }
+        if let Some(threshold) = settings.min_imbalance {
+            // Without the quotes the entry is not confirmed
+            let book = book.clone().unwrap_or_default();
+            if let Err(skip) = Imbalance::new(orderbook::ENTRY_DEPTH, threshold).check(decision.action, &book) {
+                info!("{}: {} signal of {} is skipped, {}", sec_code, decision.action.as_str(), strategy, skip);
+                self.db.insert_evaluation(&skip.evaluation(sec_code, now)).await?;
+                return Ok(0);
+            }
+        }
+        let entry_lots = match decision.action {
+            Action::Buy | Action::Sell => self.entry_lots(&settings, decision.action, info.as_ref(), book.as_ref()).await?,
+            _ => 0,
+        };
+        let Some((side, lots)) = decision.action.to_order(position, entry_lots) else {
+            return Ok(0);
+        };
+
+        let slices = stamped(settings.execution.plan(&settings, side, lots, book.as_ref(), info.as_ref())?, strategy);
+        // The touch is the price the fills are expected at, their slippage is measured against it
+        let touch = book.as_ref().and_then(|book| match side {
+            Side::Buy => book.asks.first(),
+            Side::Sell => book.bids.first(),
+        });
+        if let Some(level) = touch {
+            self.portfolio.write().unwrap_or_else(|e| e.into_inner()).expect_price(sec_code, side == Side::Sell, level.price);
+        }
+        info!(
+            "{}: {} signal of {} is executed as {:?} {} lots by {} in {} orders",
+            sec_code, decision.action.as_str(), strategy, side, lots, settings.execution, slices.len()
+        );
+        Ok(self.execute(slices))
+    }
+
+
+    /// Starts the execution of the plan, returns the number of the orders planned.
+    fn execute(&self, slices: Vec<Slice>) -> usize {
+        let planned = slices.len();
+        if !self.state.execute(self.orders.clone(), slices) {
+            warn!("Plan of {} orders is not executed, the bot is shutting down", planned);
+            return 0;
+        }
+        planned
+    }
+
+
+    /// Orders of a decision of `SpreadHedge` for a leg of the pair, planned by `hedge::orders`
+    /// against the position and the lot of the other leg.
+    async fn hedge_slices(&self, pair: &PairSettings, decision: &Decision) -> Result<Vec<Slice>, Box<dyn std::error::Error>> {
+        let mut legs = Vec::new();
+        for sec_code in [&pair.futures, &pair.spot] {
+            let settings = self.settings.get(sec_code).ok_or_else(|| format!("the leg {} is not in the watchlist", sec_code))?;
+            let info = self.instruments.get(&settings.class_code, sec_code);
+            let book = self.db.get_top_of_book(&settings.class_code, sec_code).await?;
+            legs.push((settings, info, book));
+        }
+
+        let portfolio = self.portfolio.read().unwrap_or_else(|e| e.into_inner());
+        let legs: Vec<Leg> = legs
+            .iter()
+            .map(|(settings, info, book)| Leg {
+                settings,
+                position: portfolio.lots(&settings.sec_code),
+                lot_size: info.as_ref().map_or(1, |info| info.lot as i64),
+                book: book.as_ref(),
+                info: info.as_ref(),
+            })
+            .collect();
+        hedge::orders(pair, decision, &legs[0], &legs[1])
+    }
+
+
+    /// Checks the spread of a market entry against `max_spread` of the instrument: the spread
+    /// of the book, or the range of the trades of the last minute without a two-sided book.
+    async fn check_spread(
+        &self,
+        limit: MaxSpread,
+        sec_code: &str,
+        book: Option<&OrderBook>,
+        info: Option<&InstrumentInfo>,
+        now: DateTime<Utc>,
+    ) -> Result<Result<(), SpreadSkip>, Box<dyn std::error::Error>> {
+        let two_sided = book.is_some_and(|book| !book.bids.is_empty() && !book.asks.is_empty());
+        let recent_range = if two_sided {
+            None
+        } else {
+            let candles = self.db.get_candles(sec_code, now - chrono::Duration::minutes(1), now, 60.0).await?;
+            candles.iter().fold(None, |range: Option<(f64, f64)>, candle| match range {
+                Some((high, low)) => Some((high.max(candle.high), low.min(candle.low))),
+                None => Some((candle.high, candle.low)),
+            })
+        };
+
+        Ok(SpreadGuard::new(limit).check(book, recent_range, info.and_then(|info| info.price_step)))
+    }
+
+
+    /// Lots of an entry sized on the account of the instrument at the touch, or the last price
+    /// without the quotes.
+    async fn entry_lots(
+        &mut self,
+        settings: &InstrumentSettings,
+        action: Action,
+        info: Option<&InstrumentInfo>,
+        book: Option<&OrderBook>,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let side = if action == Action::Sell { Side::Sell } else { Side::Buy };
+        let quote = book.and_then(|book| match side {
+            Side::Buy => book.asks.first(),
+            Side::Sell => book.bids.first(),
+        });
+        let price = match quote {
+            Some(level) => level.price,
+            None => self
+                .db
+                .get_last_prices(std::slice::from_ref(&settings.sec_code))
+                .await?
+                .first()
+                .map_or(0.0, |(_, price)| *price),
+        };
+        let lot_size = info.map_or(1, |info| info.lot) as i64;
+
+        let account = match &settings.account {
+            Some(account) => Some(Account::load(&self.db, account, std::slice::from_ref(&settings.sec_code)).await?),
+            None => None,
+        };
+        let equity = account.as_ref().and_then(|account| account.money.as_ref()).map(|money| money.current_balance);
+        // Sized on no equity the entry would silently fall back to min_lots
+        if self.sizer.needs_equity() && !equity.is_some_and(|equity| equity > 0.0) {
+            return Err(format!(
+                "equity of the account of {} is unknown, the entry isn't sized by {}",
+                settings.sec_code, self.sizer
+            )
+            .into());
+        }
+        let atr = if self.sizer.needs_atr() { self.atr(settings).await? } else { None };
+        let input = SizingInput {
+            equity: equity.unwrap_or_default(),
+            price,
+            lot_size,
+            atr,
+            affordable_lots: account.as_ref().and_then(|account| account.affordable_lots(&settings.sec_code, side, price, lot_size)),
+        };
+
+        let mut residuals = self.state.residuals.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(self.sizer.lots_tracked(&settings.sec_code, &input, &mut residuals))
+    }
+
+
+    /// Checks the orders of an entry against the account of the instrument: the entries of the
+    /// hedge legs follow the lots of the pair, not the position sizing capped by the account.
+    async fn check_account(&self, settings: &InstrumentSettings, slices: &[Slice]) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(account) = &settings.account else {
+            return Ok(());
+        };
+        let orders: Vec<(Side, i64, Option<f64>)> = slices
+            .iter()
+            .filter_map(|slice| match slice.transaction.kind {
+                TransactionKind::Order { side, quantity, price } => Some((side, quantity, price)),
+                _ => None,
+            })
+            .collect();
+        let Some(&(side, _, price)) = orders.first() else {
+            return Ok(());
+        };
+
+        let price = match price {
+            Some(price) => price,
+            None => self
+                .db
+                .get_last_prices(std::slice::from_ref(&settings.sec_code))
+                .await?
+                .first()
+                .map_or(0.0, |(_, price)| *price),
+        };
+        let lot_size = self.instruments.get(&settings.class_code, &settings.sec_code).map_or(1, |info| info.lot as i64);
+        let lots = orders.iter().map(|(_, quantity, _)| quantity).sum();
+        let account = Account::load(&self.db, account, std::slice::from_ref(&settings.sec_code)).await?;
+        account.check_order(&settings.sec_code, side, lots, price, lot_size)?;
+        Ok(())
+    }
+
+
+    /// ATR of the instrument over the last candles of its timeframe, `None` with fewer candles
+    /// than the ATR period.
+    async fn atr(&self, settings: &InstrumentSettings) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        let timeframe = chrono::Duration::minutes(settings.timeframe_minutes);
+        last_atr(&self.db, &settings.sec_code, timeframe, self.sizer.atr_period, self.clock.now()).await
+    }
+}
+
+
+/// Stamps the orders with the name of the strategy in their comment, the terminal returns it
+/// as the broker reference of the trades and the fills are attributed by it.
+fn stamped(slices: Vec<Slice>, strategy: &str) -> Vec<Slice> {
+    slices
+        .into_iter()
+        .map(|slice| Slice { transaction: slice.transaction.with_comment(strategy), ..slice })
+        .collect()
+}
+
+
+/// ATR of `period` over the last candles of `timeframe` of the instrument until `now`, `None`
+/// with fewer candles than the period.
+pub async fn last_atr(
+    db: &Db,
+    sec_code: &str,
+    timeframe: chrono::Duration,
+    period: usize,
+    now: DateTime<Utc>,
+) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    let from = now - timeframe * (period as i32 * ATR_LOOKBACK_FACTOR as i32);
+    let candles = db.get_candles(sec_code, from, now, timeframe.num_seconds() as f64).await?;
+    if candles.len() < period {
+        return Ok(None);
+    }
+
+    let mut indicator = AverageTrueRange::new(period)?;
+    let mut atr = None;
+    for candle in &candles[candles.len() - period..] {
+        let item = DataItem::builder().open(candle.open).high(candle.high).low(candle.low).close(candle.close).volume(candle.volume).build()?;
+        atr = Some(indicator.next(&item));
+    }
+    Ok(atr)
+}
+
+
+/// Executes the decisions of the strategies until their channel is closed. A decision which
+/// fails, e.g. without the quotes for a limit order, is logged and skipped. The channel is
+/// borrowed, so a trader restarted by the watchdog takes the decisions left in it.
+pub async fn run(mut trader: Trader, decisions: &mut mpsc::UnboundedReceiver<(&'static str, Decision)>) {
+    while let Some((strategy, decision)) = decisions.recv().await {
+        if let Err(e) = trader.on_decision(strategy, &decision).await {
+            error!("{}: {} signal of {} is not executed: {}", decision.sec_code, decision.action.as_str(), strategy, e);
+        }
+    }
+    warn!("Decisions are closed, the trading loop is stopped");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use quik_rs::mock::MockTerminal;
+    use quik_rs::quik::QuikApi;
+    use quik_rs::transaction::Transaction;
+    use crate::gateway;
+
+
+    fn state() -> TraderState {
+        TraderState {
+            target: Arc::new(Mutex::new(DailyProfitTarget::new(&[], FixedOffset::east_opt(3 * 3600).unwrap(), &[]))),
+            blocked: Arc::new(Mutex::new(HashSet::new())),
+            residuals: Arc::new(Mutex::new(LotResiduals::default())),
+            executions: Arc::new(Mutex::new(Executions::default())),
+        }
+    }
+
+
+    fn in_flight(orders: &Arc<Mutex<OrderTracker>>) -> usize {
+        orders.lock().unwrap().in_flight().len()
+    }
+
+
+    #[tokio::test]
+    async fn stop_cancels_the_slices_in_flight_and_refuses_new_plans() {
+        let terminal: Arc<dyn QuikApi> = Arc::new(MockTerminal::new());
+        let orders = Arc::new(Mutex::new(OrderTracker::new(gateway::for_tests(terminal, "stop_executions"))));
+        let slice = |delay| Slice { delay, transaction: Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, Some(250.0)) };
+        let state = state();
+
+        assert!(state.execute(orders.clone(), vec![slice(Duration::ZERO), slice(Duration::from_secs(3600))]));
+        while in_flight(&orders) == 0 {
+            tokio::task::yield_now().await;
+        }
+        state.stop().await;
+
+        // The second slice of the plan is never submitted, nor is a new plan
+        assert!(!state.execute(orders.clone(), vec![slice(Duration::ZERO)]));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(in_flight(&orders), 1);
+        assert!(state.executions.lock().unwrap().tasks.is_empty());
+    }
+}
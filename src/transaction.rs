@@ -0,0 +1,337 @@
+use std::fmt;
+use chrono::NaiveDate;
+use libc::c_ulong;
+
+
+/// Direction of an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+
+impl Side {
+    /// Value of the OPERATION parameter of a transaction.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Side::Buy => "B",
+            Side::Sell => "S",
+        }
+    }
+
+
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+
+/// Kind of a stop order, the STOP_ORDER_KIND parameter of NEW_STOP_ORDER.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopOrderKind {
+    /// SIMPLE_STOP_ORDER: when the price reaches `stop_price`, a limit order at the order price is placed.
+    StopLimit { stop_price: f64 },
+    /// TAKE_PROFIT_STOP_ORDER: activated at `stop_price`, the order is placed after the price
+    /// retreats by `offset` from its extreme, at the price shifted by `spread`.
+    TakeProfit { stop_price: f64, offset: f64, spread: f64 },
+    /// TAKE_PROFIT_AND_STOP_LIMIT_ORDER: a take-profit at `take_profit` and a stop-limit at `stop_price`,
+    /// the execution of one of them cancels the other.
+    TakeProfitAndStopLimit { take_profit: f64, stop_price: f64, offset: f64, spread: f64 },
+}
+
+
+/// Expiry of a stop order, the EXPIRY_DATE parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// Good till cancelled.
+    Gtc,
+    /// Until the end of the current trading session.
+    Today,
+    Date(NaiveDate),
+}
+
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expiry::Gtc => write!(f, "GTC"),
+            Expiry::Today => write!(f, "TODAY"),
+            Expiry::Date(date) => write!(f, "{}", date.format("%Y%m%d")),
+        }
+    }
+}
+
+
+/// Price grid of an instrument. Prices of a transaction are rounded to the price step and written
+/// with the number of decimals of the instrument, e.g. `PRICE=92.5025` for a currency pair with
+/// the step 0.0025, since the trading system rejects prices off the grid or with more decimals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceFormat {
+    /// Minimum price step, zero disables the rounding.
+    pub step: f64,
+    pub decimals: usize,
+}
+
+
+impl PriceFormat {
+    /// Without `decimals` the number of decimals of the step is used.
+    pub fn new(step: Option<f64>, decimals: Option<usize>) -> Self {
+        let step = step.filter(|step| *step > 0.0).unwrap_or(0.0);
+        let decimals = decimals.unwrap_or_else(|| {
+            let step = step.to_string();
+            step.split_once('.').map_or(0, |(_, fraction)| fraction.len())
+        });
+
+        PriceFormat { step, decimals }
+    }
+
+
+    /// Rounds the price to the nearest multiple of the step.
+    pub fn round(&self, price: f64) -> f64 {
+        if self.step > 0.0 {
+            (price / self.step).round() * self.step
+        } else {
+            price
+        }
+    }
+
+
+    /// Rounds the price to the grid in the direction of the order: up for a buy and down for
+    /// a sell, so a limit beyond the touch keeps at least its offset. A price on the grid is kept.
+    pub fn round_for(&self, price: f64, side: Side) -> f64 {
+        if self.step <= 0.0 {
+            return price;
+        }
+        // Tolerance for a price on the grid which is not exact in binary
+        let steps = price / self.step;
+        let steps = match side {
+            Side::Buy => (steps - 1e-9).ceil(),
+            Side::Sell => (steps + 1e-9).floor(),
+        };
+        steps * self.step
+    }
+
+
+    pub fn format(&self, price: f64) -> String {
+        format!("{:.*}", self.decimals, self.round(price))
+    }
+}
+
+
+/// The ACTION of a transaction with its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionKind {
+    /// NEW_ORDER: a limit order, or a market order if `price` is `None`.
+    Order { side: Side, quantity: i64, price: Option<f64> },
+    /// NEW_STOP_ORDER: the order placed by the stop order has the price `price`.
+    StopOrder { side: Side, quantity: i64, price: f64, kind: StopOrderKind, expiry: Expiry },
+    /// KILL_ORDER: cancels the order with the number `order_num`.
+    Kill { order_num: u64 },
+    /// KILL_STOP_ORDER: cancels the stop order with the number `stop_order_num`.
+    KillStop { stop_order_num: u64 },
+}
+
+
+/// Transaction of the Trans2QUIK.dll format, rendered by `Display` to the string
+/// `ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=QJSIM; ...;`.
+///
+/// # Example of use
+/// ```ignore
+/// let entry = Transaction::new_order("QJSIM", "SBER", Side::Buy, 1, Some(250.0))
+///     .with_trans_id(allocator.next_id()?)
+///     .with_price_format(info.price_format())
+///     .with_account("NL0011100043");
+/// terminal.send_sync_transaction(&entry.to_string())?;
+///
+/// let stop = Transaction::new_stop_order("QJSIM", "SBER", Side::Sell, 1, 244.5, StopOrderKind::StopLimit { stop_price: 245.0 }, Expiry::Today)
+///     .with_trans_id(allocator.next_id()?);
+/// terminal.send_sync_transaction(&stop.to_string())?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub trans_id: c_ulong,
+    pub class_code: String,
+    pub sec_code: String,
+    pub account: Option<String>,
+    pub client_code: Option<String>,
+    pub comment: Option<String>,
+    pub kind: TransactionKind,
+    /// Price grid of the instrument, `None` writes the prices as they are.
+    pub price_format: Option<PriceFormat>,
+}
+
+
+impl Transaction {
+    fn new(class_code: &str, sec_code: &str, kind: TransactionKind) -> Self {
+        Transaction {
+            trans_id: 0,
+            class_code: class_code.to_string(),
+            sec_code: sec_code.to_string(),
+            account: None,
+            client_code: None,
+            comment: None,
+            kind,
+            price_format: None,
+        }
+    }
+
+
+    /// Limit order, or market order if `price` is `None`.
+    pub fn new_order(class_code: &str, sec_code: &str, side: Side, quantity: i64, price: Option<f64>) -> Self {
+        Self::new(class_code, sec_code, TransactionKind::Order { side, quantity, price })
+    }
+
+
+    pub fn new_stop_order(
+        class_code: &str,
+        sec_code: &str,
+        side: Side,
+        quantity: i64,
+        price: f64,
+        kind: StopOrderKind,
+        expiry: Expiry,
+    ) -> Self {
+        Self::new(class_code, sec_code, TransactionKind::StopOrder { side, quantity, price, kind, expiry })
+    }
+
+
+    pub fn kill_order(class_code: &str, sec_code: &str, order_num: u64) -> Self {
+        Self::new(class_code, sec_code, TransactionKind::Kill { order_num })
+    }
+
+
+    pub fn kill_stop_order(class_code: &str, sec_code: &str, stop_order_num: u64) -> Self {
+        Self::new(class_code, sec_code, TransactionKind::KillStop { stop_order_num })
+    }
+
+
+    pub fn with_trans_id(mut self, trans_id: c_ulong) -> Self {
+        self.trans_id = trans_id;
+        self
+    }
+
+
+    pub fn with_account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+
+
+    pub fn with_client_code(mut self, client_code: &str) -> Self {
+        self.client_code = Some(client_code.to_string());
+        self
+    }
+
+
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+
+    /// Rounds and formats the prices of the transaction with the price grid of the instrument.
+    pub fn with_price_format(mut self, price_format: PriceFormat) -> Self {
+        self.price_format = Some(price_format);
+        self
+    }
+
+
+    fn price(&self, price: f64) -> String {
+        match &self.price_format {
+            Some(price_format) => price_format.format(price),
+            None => price.to_string(),
+        }
+    }
+
+
+    /// Parameters of the transaction in the order they are rendered.
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let action = match self.kind {
+            TransactionKind::Order { .. } => "NEW_ORDER",
+            TransactionKind::StopOrder { .. } => "NEW_STOP_ORDER",
+            TransactionKind::Kill { .. } => "KILL_ORDER",
+            TransactionKind::KillStop { .. } => "KILL_STOP_ORDER",
+        };
+
+        let mut params = vec![
+            ("ACTION", action.to_string()),
+            ("TRANS_ID", self.trans_id.to_string()),
+            ("CLASSCODE", self.class_code.clone()),
+            ("SECCODE", self.sec_code.clone()),
+        ];
+        if let Some(account) = &self.account {
+            params.push(("ACCOUNT", account.clone()));
+        }
+        if let Some(client_code) = &self.client_code {
+            params.push(("CLIENT_CODE", client_code.clone()));
+        }
+        if let Some(comment) = &self.comment {
+            params.push(("COMMENT", comment.clone()));
+        }
+
+        match self.kind {
+            TransactionKind::Order { side, quantity, price } => {
+                params.push(("OPERATION", side.operation().to_string()));
+                params.push(("TYPE", if price.is_some() { "L" } else { "M" }.to_string()));
+                // A market order has the zero price
+                params.push(("PRICE", price.map_or("0".to_string(), |price| self.price(price))));
+                params.push(("QUANTITY", quantity.to_string()));
+            }
+            TransactionKind::StopOrder { side, quantity, price, kind, expiry } => {
+                params.push(("OPERATION", side.operation().to_string()));
+                params.push(("PRICE", self.price(price)));
+                params.push(("QUANTITY", quantity.to_string()));
+
+                match kind {
+                    StopOrderKind::StopLimit { stop_price } => {
+                        params.push(("STOP_ORDER_KIND", "SIMPLE_STOP_ORDER".to_string()));
+                        params.push(("STOPPRICE", self.price(stop_price)));
+                    }
+                    StopOrderKind::TakeProfit { stop_price, offset, spread } => {
+                        params.push(("STOP_ORDER_KIND", "TAKE_PROFIT_STOP_ORDER".to_string()));
+                        params.push(("STOPPRICE", self.price(stop_price)));
+                        self.push_offset_and_spread(&mut params, offset, spread);
+                    }
+                    StopOrderKind::TakeProfitAndStopLimit { take_profit, stop_price, offset, spread } => {
+                        params.push(("STOP_ORDER_KIND", "TAKE_PROFIT_AND_STOP_LIMIT_ORDER".to_string()));
+                        params.push(("STOPPRICE", self.price(take_profit)));
+                        params.push(("STOPPRICE2", self.price(stop_price)));
+                        self.push_offset_and_spread(&mut params, offset, spread);
+                    }
+                }
+
+                params.push(("EXPIRY_DATE", expiry.to_string()));
+            }
+            TransactionKind::Kill { order_num } => params.push(("ORDER_KEY", order_num.to_string())),
+            TransactionKind::KillStop { stop_order_num } => params.push(("STOP_ORDER_KEY", stop_order_num.to_string())),
+        }
+
+        params
+    }
+
+
+    fn push_offset_and_spread(&self, params: &mut Vec<(&'static str, String)>, offset: f64, spread: f64) {
+        params.push(("OFFSET", self.price(offset)));
+        params.push(("OFFSET_UNITS", "PRICE_UNITS".to_string()));
+        params.push(("SPREAD", self.price(spread)));
+        params.push(("SPREAD_UNITS", "PRICE_UNITS".to_string()));
+    }
+}
+
+
+
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = self
+            .params()
+            .into_iter()
+            .map(|(key, value)| format!("{}={};", key, value))
+            .collect();
+        write!(f, "{}", params.join(" "))
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use toml_edit::{DocumentMut, Item};
+use tracing::info;
+use quik_rs::transaction::Side;
+use crate::clock::Clock;
+use crate::config::{Config, InstrumentSettings};
+use crate::latency::LatencyStats;
+use crate::psql::Db;
+use crate::strategy::{self, Action, StrategySet};
+
+
+/// Parameters of the settings of an instrument which a rollout can change.
+pub const PARAMETERS: [&str; 3] = ["ema_hysteresis", "ema_min_slope", "ema_confirm_candles"];
+
+/// Closed candles of every timeframe fed to the strategies before the paper run, so the
+/// indicators are warmed up.
+const WARM_UP_CANDLES: i32 = 300;
+
+/// Interval between the checks for new closed candles during the paper run.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Lots of the entries of the paper books.
+const PAPER_LOTS: i64 = 1;
+
+
+/// Paper position driven by the actions of a strategy, valued at the close prices.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PaperBook {
+    /// Position in lots, positive for long, negative for short.
+    pub position: i64,
+    pub cash: f64,
+    pub trades: u32,
+    last_price: f64,
+}
+
+
+impl PaperBook {
+    /// Executes the action at `price` without slippage and commissions.
+    pub fn apply(&mut self, action: Action, price: f64, entry_lots: i64) {
+        if let Some((side, lots)) = action.to_order(self.position, entry_lots) {
+            let lots = match side {
+                Side::Buy => lots,
+                Side::Sell => -lots,
+            };
+            self.position += lots;
+            self.cash -= lots as f64 * price;
+            self.trades += 1;
+        }
+        self.last_price = price;
+    }
+
+
+    /// Realized and unrealized profit per lot unit of price.
+    pub fn pnl(&self) -> f64 {
+        self.cash + self.position as f64 * self.last_price
+    }
+}
+
+
+/// Stage of a parameter rollout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RolloutState {
+    /// The candidate value runs in paper mode alongside the live value.
+    Running,
+    /// The comparison is complete, the operator has to promote or reject the candidate.
+    AwaitingConfirmation,
+    /// The candidate value became the live value.
+    Promoted,
+    Rejected,
+}
+
+
+/// Results of the live and the candidate values over the same candles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RolloutComparison {
+    pub candles: usize,
+    pub live_pnl: f64,
+    pub candidate_pnl: f64,
+    pub live_trades: u32,
+    pub candidate_trades: u32,
+}
+
+
+impl fmt::Display for RolloutComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} candles: live pnl {:.2} ({} trades), candidate pnl {:.2} ({} trades)",
+            self.candles, self.live_pnl, self.live_trades, self.candidate_pnl, self.candidate_trades
+        )
+    }
+}
+
+
+/// Guarded rollout of a new value of a strategy parameter changed at runtime.
+///
+/// The candidate value runs in paper mode alongside the live value for a fixed number
+/// of candles, then the results are compared and the operator confirms the promotion.
+/// Every step is recorded in the `parameter_changes` table.
+///
+/// # Example of use
+/// ```ignore
+/// let mut rollout = ParameterRollout::start(&db, "ema_cross", "SBER.ema_hysteresis", 0.05, 0.1, 96).await?;
+/// // On every candle
+/// rollout.on_candle(&db, candle.close, live_action, candidate_action, entry_lots).await?;
+/// if rollout.state() == RolloutState::AwaitingConfirmation {
+///     info!("{}", rollout.comparison());
+///     let hysteresis = rollout.promote(&db).await?;
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParameterRollout {
+    pub strategy: String,
+    pub parameter: String,
+    pub live_value: f64,
+    pub candidate_value: f64,
+
+    /// Number of candles the values are compared on.
+    candles: usize,
+    evaluated: usize,
+    live: PaperBook,
+    candidate: PaperBook,
+    state: RolloutState,
+}
+
+
+impl ParameterRollout {
+    pub async fn start(
+        db: &Db,
+        strategy: &str,
+        parameter: &str,
+        live_value: f64,
+        candidate_value: f64,
+        candles: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if candles == 0 {
+            return Err("the rollout requires at least one candle".into());
+        }
+        if live_value == candidate_value {
+            return Err(format!("{}.{} is already {}", strategy, parameter, live_value).into());
+        }
+
+        let rollout = ParameterRollout {
+            strategy: strategy.to_string(),
+            parameter: parameter.to_string(),
+            live_value,
+            candidate_value,
+            candles,
+            evaluated: 0,
+            live: PaperBook::default(),
+            candidate: PaperBook::default(),
+            state: RolloutState::Running,
+        };
+        rollout.record(db, "started", &format!("paper run for {} candles", candles)).await?;
+
+        Ok(rollout)
+    }
+
+
+    pub fn state(&self) -> RolloutState {
+        self.state
+    }
+
+
+    pub fn comparison(&self) -> RolloutComparison {
+        RolloutComparison {
+            candles: self.evaluated,
+            live_pnl: self.live.pnl(),
+            candidate_pnl: self.candidate.pnl(),
+            live_trades: self.live.trades,
+            candidate_trades: self.candidate.trades,
+        }
+    }
+
+
+    /// Applies the actions of the strategy with the live and the candidate values to the paper books.
+    /// After the last candle the comparison is recorded and the rollout awaits the confirmation.
+    pub async fn on_candle(
+        &mut self,
+        db: &Db,
+        close: f64,
+        live_action: Option<Action>,
+        candidate_action: Option<Action>,
+        entry_lots: i64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.state != RolloutState::Running {
+            return Ok(());
+        }
+
+        self.live.apply(live_action.unwrap_or(Action::DoNothingExplicit), close, entry_lots);
+        self.candidate.apply(candidate_action.unwrap_or(Action::DoNothingExplicit), close, entry_lots);
+        self.evaluated += 1;
+
+        if self.evaluated >= self.candles {
+            self.state = RolloutState::AwaitingConfirmation;
+            let comparison = self.comparison();
+            info!("{}.{} rollout {} -> {} is complete, {}", self.strategy, self.parameter, self.live_value, self.candidate_value, comparison);
+            self.record(db, "completed", &comparison.to_string()).await?;
+        }
+
+        Ok(())
+    }
+
+
+    /// Promotes the candidate value confirmed by the operator. Returns the new live value.
+    pub async fn promote(&mut self, db: &Db) -> Result<f64, Box<dyn std::error::Error>> {
+        if self.state != RolloutState::AwaitingConfirmation {
+            return Err(format!("{}.{} rollout can't be promoted in the state {:?}", self.strategy, self.parameter, self.state).into());
+        }
+
+        self.record(db, "promoted", &self.comparison().to_string()).await?;
+        self.state = RolloutState::Promoted;
+        info!("{}.{} is changed from {} to {}", self.strategy, self.parameter, self.live_value, self.candidate_value);
+
+        Ok(self.candidate_value)
+    }
+
+
+    /// Rejects the candidate value, also while the paper run is in progress.
+    pub async fn reject(&mut self, db: &Db) -> Result<(), Box<dyn std::error::Error>> {
+        if matches!(self.state, RolloutState::Promoted | RolloutState::Rejected) {
+            return Err(format!("{}.{} rollout is already {:?}", self.strategy, self.parameter, self.state).into());
+        }
+
+        self.record(db, "rejected", &self.comparison().to_string()).await?;
+        self.state = RolloutState::Rejected;
+
+        Ok(())
+    }
+
+
+    async fn record(&self, db: &Db, event: &str, details: &str) -> Result<(), Box<dyn std::error::Error>> {
+        db.insert_parameter_change(&self.strategy, &self.parameter, self.live_value, self.candidate_value, event, details)
+            .await?;
+        Ok(())
+    }
+}
+
+
+/// Value of the parameter in the settings of the instrument.
+pub fn parameter_value(settings: &InstrumentSettings, parameter: &str) -> Result<f64, Box<dyn std::error::Error>> {
+    match parameter {
+        "ema_hysteresis" => Ok(settings.ema_hysteresis),
+        "ema_min_slope" => settings
+            .ema_min_slope
+            .ok_or_else(|| format!("ema_min_slope of {} is not set, the check is disabled", settings.sec_code).into()),
+        "ema_confirm_candles" => Ok(settings.ema_confirm_candles as f64),
+        _ => Err(format!("unknown parameter '{}', expected one of: {}", parameter, PARAMETERS.join(", ")).into()),
+    }
+}
+
+
+/// The configuration `content` with the parameter set to `value` in the `[[instruments]]`
+/// entry of the instrument, which overrides the value of its group. The comments and the
+/// formatting are kept.
+pub fn with_parameter(content: &str, sec_code: &str, parameter: &str, value: f64) -> Result<String, Box<dyn std::error::Error>> {
+    let mut document = content.parse::<DocumentMut>()?;
+    let instrument = document
+        .get_mut("instruments")
+        .and_then(Item::as_array_of_tables_mut)
+        .and_then(|instruments| {
+            instruments
+                .iter_mut()
+                .find(|instrument| instrument.get("sec_code").and_then(Item::as_str) == Some(sec_code))
+        })
+        .ok_or_else(|| format!("{} is not in [[instruments]]", sec_code))?;
+
+    instrument[parameter] = match parameter {
+        "ema_confirm_candles" => toml_edit::value(value as i64),
+        _ => toml_edit::value(value),
+    };
+    Ok(document.to_string())
+}
+
+
+/// Paper run of the rollout of a parameter of the instrument: the strategy of the rollout is
+/// built with the `live` and with the `candidate` configurations, both are fed the candles of
+/// the instrument as they close, and their actions are applied to the paper books until the
+/// rollout has compared its candles. The candles before the start only warm the indicators up.
+pub async fn paper_run(
+    db: &Db,
+    rollout: &mut ParameterRollout,
+    sec_code: &str,
+    live: &Config,
+    candidate: &Config,
+    clock: &dyn Clock,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = live
+        .instrument_settings()?
+        .into_iter()
+        .find(|settings| settings.sec_code == sec_code)
+        .ok_or_else(|| format!("{} is not in the watchlist", sec_code))?;
+    // The latency of a paper run is not reported
+    let latency = LatencyStats::default();
+    let built = strategy::build(&rollout.strategy, live, &latency)?;
+    if !built.wants_instruments().iter().any(|wanted| wanted == sec_code) {
+        return Err(format!("{} doesn't evaluate {}", rollout.strategy, sec_code).into());
+    }
+    let main = built.timeframe(sec_code).unwrap_or_else(|| settings.timeframe());
+    let mut strategies = [StrategySet::new(vec![built]), StrategySet::new(vec![strategy::build(&rollout.strategy, candidate, &latency)?])];
+
+    // The longer timeframes first, they confirm the signals of the shorter ones
+    let mut timeframes = strategies[0].timeframes(sec_code);
+    if !timeframes.contains(&main) {
+        timeframes.push(main);
+    }
+    timeframes.sort_by(|a, b| b.cmp(a));
+
+    let mut fed: HashMap<Duration, DateTime<Utc>> = HashMap::new();
+    let mut warmed_up = false;
+    while rollout.state() == RolloutState::Running {
+        let now = clock.now();
+        for timeframe in &timeframes {
+            let seconds = timeframe.as_secs().max(1) as i64;
+            // The end of the last closed candle
+            let to = DateTime::from_timestamp(now.timestamp() - now.timestamp().rem_euclid(seconds), 0).unwrap_or(now);
+            let from = *fed.entry(*timeframe).or_insert(to - chrono::Duration::seconds(seconds) * WARM_UP_CANDLES);
+            if to <= from {
+                continue;
+            }
+
+            let candles = db.get_candles(sec_code, from, to, seconds as f64).await?;
+            fed.insert(*timeframe, to);
+            for candle in &candles {
+                let [live_action, candidate_action] = strategies.each_mut().map(|strategies| {
+                    strategies
+                        .on_candle(sec_code, *timeframe, candle)
+                        .into_iter()
+                        .rev()
+                        .find(|(_, decision)| decision.sec_code == sec_code)
+                        .map(|(_, decision)| decision.action)
+                });
+                if warmed_up && *timeframe == main {
+                    rollout.on_candle(db, candle.close, live_action, candidate_action, PAPER_LOTS).await?;
+                }
+            }
+        }
+
+        if !warmed_up {
+            warmed_up = true;
+            info!("{}.{} paper run is warmed up, {} candles to compare", rollout.strategy, rollout.parameter, rollout.candles);
+        }
+        if rollout.state() == RolloutState::Running {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    Ok(())
+}
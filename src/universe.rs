@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info};
+use crate::clock::Clock;
+use crate::config::{Config, InstrumentSettings};
+use crate::psql::Db;
+
+
+/// Instruments added to and removed from the universe by a refresh.
+#[derive(Debug, Clone, Default)]
+pub struct UniverseChange {
+    pub added: Vec<InstrumentSettings>,
+    /// Security codes of the instruments removed from the watchlist, disabled or delisted.
+    pub removed: Vec<String>,
+}
+
+
+impl UniverseChange {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+
+impl fmt::Display for UniverseChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let added: Vec<&str> = self.added.iter().map(|settings| settings.sec_code.as_str()).collect();
+        write!(f, "universe: added [{}], removed [{}]", added.join(", "), self.removed.join(", "))
+    }
+}
+
+
+/// Instrument universe of the bot: the enabled watchlist instruments listed in `current_trades`.
+/// An instrument missing from `current_trades` is not traded by the exchange anymore, e.g.
+/// delisted or an expired future.
+///
+/// # Example of use
+/// ```ignore
+/// let mut universe = Universe::new(universe::eligible(config_path, &db, &SystemClock).await?);
+/// let change = universe.update(universe::eligible(config_path, &db, &SystemClock).await?);
+/// for settings in &change.added {
+///     strategies.add_instrument(settings)?;
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Universe {
+    instruments: BTreeMap<String, InstrumentSettings>,
+}
+
+
+impl Universe {
+    pub fn new(instruments: Vec<InstrumentSettings>) -> Self {
+        Universe {
+            instruments: instruments.into_iter().map(|settings| (settings.sec_code.clone(), settings)).collect(),
+        }
+    }
+
+
+    /// Instruments of the universe sorted by security code.
+    pub fn instruments(&self) -> Vec<&InstrumentSettings> {
+        self.instruments.values().collect()
+    }
+
+
+    /// Subscriptions of the universe: the securities codes joined with `|` by class code.
+    pub fn subscriptions(&self) -> Vec<(String, String)> {
+        let mut classes: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for settings in self.instruments.values() {
+            classes.entry(&settings.class_code).or_default().push(&settings.sec_code);
+        }
+
+        classes
+            .into_iter()
+            .map(|(class_code, sec_codes)| (class_code.to_string(), sec_codes.join("|")))
+            .collect()
+    }
+
+
+    /// Replaces the universe with the eligible instruments and returns the difference.
+    /// The instruments kept keep their settings, so their strategies are not restarted.
+    pub fn update(&mut self, eligible: Vec<InstrumentSettings>) -> UniverseChange {
+        let codes: HashSet<&str> = eligible.iter().map(|settings| settings.sec_code.as_str()).collect();
+        let removed: Vec<String> = self.instruments.keys().filter(|sec_code| !codes.contains(sec_code.as_str())).cloned().collect();
+        for sec_code in &removed {
+            self.instruments.remove(sec_code);
+        }
+
+        let mut added = Vec::new();
+        for settings in eligible {
+            if !self.instruments.contains_key(&settings.sec_code) {
+                self.instruments.insert(settings.sec_code.clone(), settings.clone());
+                added.push(settings);
+            }
+        }
+
+        UniverseChange { added, removed }
+    }
+}
+
+
+/// Reads the watchlist from the configuration file and keeps the enabled instruments
+/// listed in `current_trades` and not expired by the exchange day of `clock`.
+pub async fn eligible(config_path: &str, db: &Db, clock: &dyn Clock) -> Result<Vec<InstrumentSettings>, Box<dyn std::error::Error>> {
+    let config = Config::load(config_path)?;
+    let settings: Vec<InstrumentSettings> = config.instrument_settings()?.into_iter().filter(|settings| settings.enabled).collect();
+
+    let codes: Vec<String> = settings.iter().map(|settings| settings.sec_code.clone()).collect();
+    let today = clock.now().with_timezone(&config.exchange_timezone).date_naive();
+    let listed: HashSet<(String, String)> = db
+        .get_instrument_rows(&codes)
+        .await?
+        .into_iter()
+        .filter(|row| row.expiry.is_none_or(|expiry| expiry >= today))
+        .map(|row| (row.class_code, row.instrument_code))
+        .collect();
+
+    Ok(settings
+        .into_iter()
+        .filter(|settings| listed.contains(&(settings.class_code.clone(), settings.sec_code.clone())))
+        .collect())
+}
+
+
+/// Universe refresh task: every `period`, or at once when a refresh is requested through `force`,
+/// re-reads the eligible instruments, publishes the new subscriptions for the connection
+/// supervisor and sends the added and removed instruments to `changes`, e.g. to the trading
+/// loop which starts them with a fresh signal state.
+///
+/// # Example of use
+/// ```ignore
+/// let (subscriptions, updates) = watch::channel(universe.subscriptions());
+/// let supervisor = ConnectionSupervisor::new(terminal.clone(), universe.subscriptions()).with_subscription_updates(updates);
+/// let (force, requests) = mpsc::unbounded_channel();
+/// tokio::spawn(universe::run(config_path.to_string(), db.clone(), universe, subscriptions, changes, requests, clock.clone(), Duration::from_secs(3600)));
+/// force.send(())?;
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    config_path: String,
+    db: Arc<Db>,
+    mut universe: Universe,
+    subscriptions: watch::Sender<Vec<(String, String)>>,
+    changes: mpsc::UnboundedSender<UniverseChange>,
+    mut force: mpsc::UnboundedReceiver<()>,
+    clock: Arc<dyn Clock>,
+    period: Duration,
+) {
+    let mut interval = tokio::time::interval(period);
+    // The first tick is immediate, the universe is fresh at the start
+    interval.tick().await;
+    let mut force_open = true;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            request = force.recv(), if force_open => match request {
+                Some(()) => info!("Universe refresh is requested"),
+                None => {
+                    force_open = false;
+                    continue;
+                }
+            },
+        }
+
+        let eligible = match eligible(&config_path, &db, clock.as_ref()).await {
+            Ok(eligible) => eligible,
+            Err(e) => {
+                error!("Error refreshing the instrument universe: {}", e);
+                continue;
+            }
+        };
+        let change = universe.update(eligible);
+        if change.is_empty() {
+            continue;
+        }
+
+        info!("{}", change);
+        subscriptions.send_replace(universe.subscriptions());
+        // Nobody may be trading on the changes, e.g. in the headless mode
+        let _ = changes.send(change);
+    }
+}
+
+
+/// Changes of the universe for a task restarted by the watchdog: a subscriber first gets every
+/// change since the start, so a task starting again from the configuration catches up with
+/// the current universe, and then the new ones. Only the last subscriber gets the changes.
+///
+/// # Example of use
+/// ```ignore
+/// let universe = UniverseChanges::default();
+/// tokio::spawn(universe.clone().forward(changes));
+/// tokio::spawn(watchdog.supervise("scheduler", move || scheduler(universe.subscribe())));
+/// ```
+#[derive(Clone, Default)]
+pub struct UniverseChanges {
+    inner: Arc<Mutex<ChangeLog>>,
+}
+
+
+#[derive(Default)]
+struct ChangeLog {
+    changes: Vec<UniverseChange>,
+    subscriber: Option<mpsc::UnboundedSender<UniverseChange>>,
+}
+
+
+impl UniverseChanges {
+    /// Receiver of the changes since the start and of the new ones, replacing the last one.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<UniverseChange> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut log = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        for change in &log.changes {
+            let _ = sender.send(change.clone());
+        }
+        log.subscriber = Some(sender);
+        receiver
+    }
+
+
+    /// Records the change and passes it to the subscriber.
+    pub fn publish(&self, change: UniverseChange) {
+        let mut log = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(subscriber) = &log.subscriber {
+            let _ = subscriber.send(change.clone());
+        }
+        log.changes.push(change);
+    }
+
+
+    /// Publishes the changes of the universe refresh task until it stops.
+    pub async fn forward(self, mut changes: mpsc::UnboundedReceiver<UniverseChange>) {
+        while let Some(change) = changes.recv().await {
+            self.publish(change);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn change(added: &[&str], removed: &[&str]) -> UniverseChange {
+        let settings = |sec_code: &&str| {
+            let toml = format!("[[instruments]]\nclass_code = \"QJSIM\"\nsec_code = \"{}\"\n", sec_code);
+            Config::parse(&toml).unwrap().instrument_settings().unwrap().remove(0)
+        };
+        UniverseChange {
+            added: added.iter().map(settings).collect(),
+            removed: removed.iter().map(|sec_code| sec_code.to_string()).collect(),
+        }
+    }
+
+
+    fn codes(change: &UniverseChange) -> (Vec<String>, Vec<String>) {
+        (change.added.iter().map(|settings| settings.sec_code.clone()).collect(), change.removed.clone())
+    }
+
+
+    #[test]
+    fn restarted_subscriber_catches_up_with_the_changes_since_the_start() {
+        let universe = UniverseChanges::default();
+        let mut first = universe.subscribe();
+        universe.publish(change(&["GAZP"], &[]));
+        assert_eq!(codes(&first.try_recv().unwrap()), (vec!["GAZP".to_string()], vec![]));
+
+        universe.publish(change(&[], &["SBER"]));
+        let mut restarted = universe.subscribe();
+        assert_eq!(codes(&restarted.try_recv().unwrap()), (vec!["GAZP".to_string()], vec![]));
+        assert_eq!(codes(&restarted.try_recv().unwrap()), (vec![], vec!["SBER".to_string()]));
+        assert!(restarted.try_recv().is_err());
+
+        // Only the last subscriber gets the new changes
+        universe.publish(change(&["LKOH"], &[]));
+        assert_eq!(codes(&restarted.try_recv().unwrap()), (vec!["LKOH".to_string()], vec![]));
+        assert_eq!(codes(&first.try_recv().unwrap()), (vec![], vec!["SBER".to_string()]));
+        assert!(first.try_recv().is_err());
+    }
+}
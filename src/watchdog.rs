@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio::time::{sleep, Instant};
+use tracing::{error, warn};
+use crate::notifier::Notifier;
+use crate::psql::{Db, IncidentKind, Severity};
+use crate::retry::RetryPolicy;
+
+
+/// Restarts of the supervised tasks, the `[watchdog]` table of the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchdogSettings {
+    /// Restarts of a task within `window` after which it is not restarted anymore.
+    pub max_restarts: u32,
+    pub window: Duration,
+    /// Names of the notification channels receiving the restart alerts.
+    pub notification_channels: Vec<String>,
+}
+
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        WatchdogSettings {
+            max_restarts: 5,
+            window: Duration::from_secs(3600),
+            notification_channels: Vec::new(),
+        }
+    }
+}
+
+
+/// Aborts the supervised task when the watchdog is aborted while waiting for it.
+struct AbortOnDrop(AbortHandle);
+
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+
+/// Supervisor of the long-running tasks of the bot: a task which panics or returns is
+/// started again after the delay of the retry policy, so a bug in one task doesn't leave
+/// the bot running without it. Every restart is recorded as an incident and sent to the
+/// notification channels; the tasks restore their state from the database when they start.
+///
+/// A task restarted `max_restarts` times within `window` trips the circuit breaker: it is
+/// left stopped and its name is sent to the `tripped` channel, so the bot can stop instead of
+/// restarting a broken task forever.
+///
+/// # Example of use
+/// ```ignore
+/// let (tripped, mut breakers) = mpsc::unbounded_channel();
+/// let watchdog = Watchdog::new(config.watchdog.clone(), config.retry, db.clone(), Notifier::from_config(&config), tripped);
+/// tokio::spawn(watchdog.supervise("expiry", move || expiry::run(db.clone(), gateway.clone(), settings.clone(), timezone, clock.clone(), period)));
+/// if let Some(task) = breakers.recv().await {
+///     error!("{} is broken, stopping", task);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Watchdog {
+    settings: WatchdogSettings,
+    retry: RetryPolicy,
+    db: Arc<Db>,
+    notifier: Notifier,
+    tripped: mpsc::UnboundedSender<&'static str>,
+}
+
+
+impl Watchdog {
+    pub fn new(
+        settings: WatchdogSettings,
+        retry: RetryPolicy,
+        db: Arc<Db>,
+        notifier: Notifier,
+        tripped: mpsc::UnboundedSender<&'static str>,
+    ) -> Self {
+        Watchdog { settings, retry, db, notifier, tripped }
+    }
+
+
+    /// Runs the task built by `start` until the circuit breaker trips.
+    pub async fn supervise<F, Fut>(self, name: &'static str, start: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+
+        loop {
+            let task = tokio::spawn(start());
+            // Stopping the watchdog stops the task as well
+            let _guard = AbortOnDrop(task.abort_handle());
+            let reason = match task.await {
+                Ok(()) => "the task has exited".to_string(),
+                Err(e) if e.is_panic() => {
+                    let panic = e.into_panic();
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    format!("the task has panicked: {}", message)
+                }
+                Err(e) => format!("the task was cancelled: {}", e),
+            };
+
+            let now = Instant::now();
+            while restarts.front().is_some_and(|restart| now.duration_since(*restart) >= self.settings.window) {
+                restarts.pop_front();
+            }
+            if restarts.len() >= self.settings.max_restarts as usize {
+                let message = format!(
+                    "{}: {}, restarted {} times within {} minutes, not restarted anymore",
+                    name,
+                    reason,
+                    restarts.len(),
+                    self.settings.window.as_secs() / 60
+                );
+                self.alert(&message).await;
+                let _ = self.tripped.send(name);
+                return;
+            }
+
+            // The first restart is the attempt 2 of the retry policy
+            let delay = self.retry.delay(restarts.len() as u32 + 2);
+            restarts.push_back(now);
+            self.alert(&format!("{}: {}, restart {} in {:?}", name, reason, restarts.len(), delay)).await;
+            sleep(delay).await;
+        }
+    }
+
+
+    async fn alert(&self, message: &str) {
+        warn!("Watchdog: {}", message);
+        if let Err(e) = self.db.insert_incident(Severity::Critical, IncidentKind::TaskRestart, None, message).await {
+            error!("Error recording the restart of the task: {}", e);
+        }
+        self.notifier.notify(None, &self.settings.notification_channels, message).await;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::config::RunMode;
+
+
+    fn watchdog(max_restarts: u32) -> (Watchdog, mpsc::UnboundedReceiver<&'static str>) {
+        let settings = WatchdogSettings { max_restarts, ..WatchdogSettings::default() };
+        let mut retry = RetryPolicy::default();
+        retry.base_delay = Duration::from_millis(10);
+        retry.jitter = 0.0;
+        let (tripped, breakers) = mpsc::unbounded_channel();
+        let notifier = Notifier::new(RunMode::Demo, HashMap::new(), HashMap::new());
+        (Watchdog::new(settings, retry, Arc::new(Db::for_tests()), notifier, tripped), breakers)
+    }
+
+
+    #[tokio::test]
+    async fn panicked_loop_is_restarted_and_takes_the_next_messages() {
+        let (watchdog, _breakers) = watchdog(5);
+        let (sender, receiver) = mpsc::unbounded_channel::<u32>();
+        let (processed, mut seen) = mpsc::unbounded_channel();
+        // Shared like the decisions of the trading loop, so a restarted loop continues with them
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let supervised = tokio::spawn(watchdog.supervise("loop", move || {
+            let (receiver, processed) = (receiver.clone(), processed.clone());
+            async move {
+                while let Some(message) = receiver.lock().await.recv().await {
+                    if message == 0 {
+                        panic!("poisoned message");
+                    }
+                    let _ = processed.send(message);
+                }
+            }
+        }));
+
+        for message in [1, 0, 2, 3] {
+            sender.send(message).unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(tokio::time::timeout(Duration::from_secs(5), seen.recv()).await.unwrap().unwrap());
+        }
+        assert_eq!(received, vec![1, 2, 3]);
+        supervised.abort();
+    }
+
+
+    #[tokio::test]
+    async fn task_failing_too_often_trips_the_breaker() {
+        let (watchdog, mut breakers) = watchdog(2);
+        let starts = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counted = starts.clone();
+        let supervised = tokio::spawn(watchdog.supervise("flaky", move || {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { panic!("broken") }
+        }));
+
+        let tripped = tokio::time::timeout(Duration::from_secs(5), breakers.recv()).await.unwrap();
+        assert_eq!(tripped, Some("flaky"));
+        assert_eq!(starts.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert!(tokio::time::timeout(Duration::from_secs(5), supervised).await.unwrap().is_ok());
+    }
+}